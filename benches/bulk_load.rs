@@ -0,0 +1,60 @@
+// Initial ingest through batch() compared to BulkLoader:
+//
+//   cargo bench --bench bulk_load
+
+use criterion::{criterion_group,criterion_main,Criterion,BatchSize,Throughput};
+use eyros::{Setup,DB,Row,BulkLoader};
+use eyros::Error;
+use random::{Source,default as rand};
+use random_access_disk::RandomAccessDisk;
+use tempfile::{Builder as Tmpfile,TempDir};
+
+type P = (f32,f32);
+type V = u32;
+type Storage = Box<dyn Fn(&str) -> Result<RandomAccessDisk,failure::Error>>;
+
+const ROWS: usize = 200_000;
+const BATCH: usize = 10_000;
+
+fn open (dir: &TempDir) -> Result<DB<RandomAccessDisk,Storage,P,V>,Error> {
+  let path = dir.path().to_path_buf();
+  let storage: Storage = Box::new(move |name| {
+    RandomAccessDisk::builder(path.join(name)).auto_sync(false).build()
+  });
+  Setup::new(storage).build()
+}
+
+fn rows () -> Vec<(P,V)> {
+  let mut r = rand().seed([13,37]);
+  (0..ROWS).map(|i| {
+    ((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), i as V)
+  }).collect()
+}
+
+fn benches (c: &mut Criterion) {
+  let rows = rows();
+  let mut group = c.benchmark_group("load");
+  group.throughput(Throughput::Elements(ROWS as u64));
+  group.sample_size(10);
+  let tmp = || Tmpfile::new().prefix("eyros-bench").tempdir().unwrap();
+  group.bench_function("batch", |b| b.iter_batched(tmp, |dir| {
+    let mut db = open(&dir).unwrap();
+    for chunk in rows.chunks(BATCH) {
+      let batch: Vec<Row<P,V>> = chunk.iter()
+        .map(|(p,v)| Row::Insert(*p,*v)).collect();
+      db.batch(&batch).unwrap();
+    }
+    dir
+  }, BatchSize::PerIteration));
+  group.bench_function("bulk", |b| b.iter_batched(tmp, |dir| {
+    let mut db = open(&dir).unwrap();
+    let mut loader = BulkLoader::new(&mut db).unwrap();
+    loader.extend(rows.iter().cloned());
+    loader.finish().unwrap();
+    dir
+  }, BatchSize::PerIteration));
+  group.finish();
+}
+
+criterion_group!(bulk_load, benches);
+criterion_main!(bulk_load);
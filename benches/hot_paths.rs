@@ -0,0 +1,78 @@
+// Benchmarks for the block read, parse, and encode paths.
+//
+// Save a baseline before changing these paths and compare against it after:
+//
+//   cargo bench --bench hot_paths -- --save-baseline before
+//   cargo bench --bench hot_paths -- --baseline before
+//
+// Criterion reports any regression beyond its noise threshold.
+// tests/allocations.rs guards the allocation counts of the same paths.
+
+use criterion::{criterion_group,criterion_main,Criterion,BatchSize,Throughput};
+use eyros::{Setup,DB,Row};
+use eyros::Error;
+use random::{Source,default as rand};
+use random_access_disk::RandomAccessDisk;
+use tempfile::{Builder as Tmpfile,TempDir};
+
+type P = (f32,f32);
+type Storage = Box<dyn Fn(&str) -> Result<RandomAccessDisk,failure::Error>>;
+
+const ROWS: usize = 20_000;
+
+fn open<V> (dir: &TempDir) -> Result<DB<RandomAccessDisk,Storage,P,V>,Error>
+where V: eyros::Value {
+  let path = dir.path().to_path_buf();
+  let storage: Storage = Box::new(move |name| {
+    RandomAccessDisk::builder(path.join(name)).auto_sync(false).build()
+  });
+  Setup::new(storage).build()
+}
+
+fn rows<V,F> (f: F) -> Vec<Row<P,V>> where V: eyros::Value, F: Fn(usize) -> V {
+  let mut r = rand().seed([13,37]);
+  (0..ROWS).map(|i| {
+    Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), f(i))
+  }).collect()
+}
+
+fn scan<V,F> (c: &mut Criterion, name: &str, f: F) where V: eyros::Value, F: Fn(usize) -> V {
+  let dir = Tmpfile::new().prefix("eyros-bench").tempdir().unwrap();
+  let mut db = open::<V>(&dir).unwrap();
+  db.batch(&rows(f)).unwrap();
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let mut group = c.benchmark_group("scan");
+  group.throughput(Throughput::Elements(ROWS as u64));
+  // scan() bypasses the block cache, so every block is read and parsed
+  group.bench_function(name, |b| b.iter(|| {
+    db.scan(&bbox).unwrap().count()
+  }));
+  group.finish();
+}
+
+fn build<V,F> (c: &mut Criterion, name: &str, f: F) where V: eyros::Value, F: Fn(usize) -> V {
+  let batch = rows(f);
+  let mut group = c.benchmark_group("build");
+  group.throughput(Throughput::Elements(ROWS as u64));
+  group.sample_size(20);
+  group.bench_function(name, |b| b.iter_batched(
+    || Tmpfile::new().prefix("eyros-bench").tempdir().unwrap(),
+    |dir| {
+      let mut db = open::<V>(&dir).unwrap();
+      db.batch(&batch).unwrap();
+      dir
+    },
+    BatchSize::PerIteration
+  ));
+  group.finish();
+}
+
+fn benches (c: &mut Criterion) {
+  scan(c, "u32", |i| i as u32);
+  scan(c, "bytes", |i| format!["value {}", i].into_bytes());
+  build(c, "u32", |i| i as u32);
+  build(c, "bytes", |i| format!["value {}", i].into_bytes());
+}
+
+criterion_group!(hot_paths, benches);
+criterion_main!(hot_paths);
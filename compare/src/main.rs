@@ -0,0 +1,182 @@
+use eyros::{Setup,DB,Row};
+use failure::Error;
+use random::{Source,default as rand};
+use random_access_disk::RandomAccessDisk;
+use rstar::{RTree,AABB,primitives::GeomWithData};
+use rusqlite::{Connection,params};
+use tempfile::Builder as Tmpfile;
+use std::path::Path;
+use std::time::{Duration,Instant};
+
+type P = (f32,f32);
+type V = u32;
+type BBox = ((f32,f32),(f32,f32));
+
+struct Report {
+  name: &'static str,
+  build: Duration,
+  latencies: Vec<Duration>,
+  counts: Vec<usize>,
+  disk: Option<u64>
+}
+
+impl Report {
+  fn print (&self, records: usize) {
+    let mut sorted = self.latencies.clone();
+    sorted.sort();
+    let total: Duration = sorted.iter().sum();
+    let pct = |p: f64| -> f64 {
+      let i = ((sorted.len() as f64 - 1.0) * p).round() as usize;
+      ms(sorted[i])
+    };
+    println!["{:<8} {:>10.3} {:>12.0} {:>10.3} {:>10.3} {:>10.3} {:>12}",
+      self.name,
+      self.build.as_secs_f64(),
+      (records as f64) / self.build.as_secs_f64(),
+      ms(total) / (sorted.len() as f64),
+      pct(0.5),
+      pct(0.99),
+      self.disk.map(|b| b.to_string()).unwrap_or("-".into())
+    ];
+  }
+}
+
+fn ms (d: Duration) -> f64 { d.as_secs_f64() * 1000.0 }
+
+fn main() -> Result<(),Error> {
+  let args: Vec<String> = std::env::args().collect();
+  let records: usize = args.get(1).map(|s| s.parse()).transpose()?.unwrap_or(100_000);
+  let queries: usize = args.get(2).map(|s| s.parse()).transpose()?.unwrap_or(1_000);
+  let batch_size: usize = args.get(3).map(|s| s.parse()).transpose()?.unwrap_or(10_000);
+
+  // every engine gets the same rows and query boxes
+  let mut r = rand().seed([13,12]);
+  let rows: Vec<(P,V)> = (0..records).map(|i| {
+    let x: f32 = r.read::<f32>()*2.0-1.0;
+    let y: f32 = r.read::<f32>()*2.0-1.0;
+    ((x,y), i as u32)
+  }).collect();
+  let bboxes: Vec<BBox> = (0..queries).map(|_| {
+    let x: f32 = r.read::<f32>()*2.0-1.0;
+    let y: f32 = r.read::<f32>()*2.0-1.0;
+    let w: f32 = 0.005 + r.read::<f32>()*0.05;
+    let h: f32 = 0.005 + r.read::<f32>()*0.05;
+    ((x,y),(x+w,y+h))
+  }).collect();
+
+  let dir = Tmpfile::new().prefix("eyros-compare").tempdir()?;
+  let reports = vec![
+    run_eyros(&dir.path().join("eyros"), &rows, &bboxes, batch_size)?,
+    run_rstar(&rows, &bboxes),
+    run_sqlite(&dir.path().join("rtree.db"), &rows, &bboxes, batch_size)?
+  ];
+
+  println!["# {} records, {} queries, batches of {}", records, queries, batch_size];
+  println!["{:<8} {:>10} {:>12} {:>10} {:>10} {:>10} {:>12}",
+    "engine", "build (s)", "records/s", "mean (ms)", "p50 (ms)", "p99 (ms)",
+    "disk (bytes)"];
+  for report in reports.iter() {
+    report.print(records);
+  }
+  for report in reports.iter().skip(1) {
+    let differ = report.counts.iter().zip(reports[0].counts.iter())
+      .filter(|(a,b)| a != b)
+      .count();
+    if differ > 0 {
+      println!["# {} returned a different number of rows than {} for {} queries",
+        report.name, reports[0].name, differ];
+    }
+  }
+  Ok(())
+}
+
+fn run_eyros (dir: &Path, rows: &[(P,V)], bboxes: &[BBox], batch_size: usize)
+-> Result<Report,Error> {
+  let dir = dir.to_path_buf();
+  let mut db: DB<_,_,P,V> = Setup::new(move |name: &str| {
+    Ok(RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build()?)
+  }).build()?;
+  let start = Instant::now();
+  for chunk in rows.chunks(batch_size) {
+    let batch: Vec<Row<P,V>> = chunk.iter()
+      .map(|(p,v)| Row::Insert(*p,*v))
+      .collect();
+    db.batch(&batch)?;
+  }
+  let build = start.elapsed();
+  let mut latencies = Vec::with_capacity(bboxes.len());
+  let mut counts = Vec::with_capacity(bboxes.len());
+  for bbox in bboxes.iter() {
+    let start = Instant::now();
+    let mut count = 0;
+    for result in db.query(bbox)? {
+      result?;
+      count += 1;
+    }
+    latencies.push(start.elapsed());
+    counts.push(count);
+  }
+  let disk = Some(db.disk_usage()?.total());
+  Ok(Report { name: "eyros", build, latencies, counts, disk })
+}
+
+// in memory, so there is no disk usage to report
+fn run_rstar (rows: &[(P,V)], bboxes: &[BBox]) -> Report {
+  let start = Instant::now();
+  let tree = RTree::bulk_load(rows.iter()
+    .map(|((x,y),v)| GeomWithData::new([*x,*y], *v))
+    .collect());
+  let build = start.elapsed();
+  let mut latencies = Vec::with_capacity(bboxes.len());
+  let mut counts = Vec::with_capacity(bboxes.len());
+  for ((x0,y0),(x1,y1)) in bboxes.iter() {
+    let start = Instant::now();
+    let envelope = AABB::from_corners([*x0,*y0], [*x1,*y1]);
+    let count = tree.locate_in_envelope_intersecting(&envelope).count();
+    latencies.push(start.elapsed());
+    counts.push(count);
+  }
+  Report { name: "rstar", build, latencies, counts, disk: None }
+}
+
+fn run_sqlite (file: &Path, rows: &[(P,V)], bboxes: &[BBox], batch_size: usize)
+-> Result<Report,Error> {
+  let mut conn = Connection::open(file)?;
+  conn.execute_batch("CREATE VIRTUAL TABLE points USING rtree(
+    id, minx, maxx, miny, maxy
+  );")?;
+  let start = Instant::now();
+  for chunk in rows.chunks(batch_size) {
+    let tx = conn.transaction()?;
+    {
+      let mut insert = tx.prepare_cached(
+        "INSERT INTO points VALUES (?1, ?2, ?2, ?3, ?3)")?;
+      for ((x,y),v) in chunk.iter() {
+        insert.execute(params![v, x, y])?;
+      }
+    }
+    tx.commit()?;
+  }
+  let build = start.elapsed();
+  let mut latencies = Vec::with_capacity(bboxes.len());
+  let mut counts = Vec::with_capacity(bboxes.len());
+  {
+    let mut select = conn.prepare(
+      "SELECT id FROM points WHERE maxx >= ?1 AND minx <= ?2
+        AND maxy >= ?3 AND miny <= ?4")?;
+    for ((x0,y0),(x1,y1)) in bboxes.iter() {
+      let start = Instant::now();
+      let mut results = select.query(params![x0, x1, y0, y1])?;
+      let mut count = 0;
+      while let Some(row) = results.next()? {
+        let _: i64 = row.get(0)?;
+        count += 1;
+      }
+      latencies.push(start.elapsed());
+      counts.push(count);
+    }
+  }
+  drop(conn);
+  let disk = Some(std::fs::metadata(file)?.len());
+  Ok(Report { name: "sqlite", build, latencies, counts, disk })
+}
@@ -1,6 +1,5 @@
-use eyros::{Setup,Row};
+use eyros::{Setup,Row,Error};
 use rand::random;
-use failure::Error;
 use random_access_disk::RandomAccessDisk;
 use std::path::PathBuf;
 use std::time;
@@ -1,5 +1,5 @@
 use eyros::DB;
-use failure::Error;
+use std::error::Error;
 use std::path::PathBuf;
 use random_access_disk::RandomAccessDisk;
 
@@ -10,7 +10,7 @@ type T = f32;
 type R = ((T,T),(T,T));
 type I = (u32,u64);
 
-fn main() -> Result<(),Error> {
+fn main() -> Result<(),Box<dyn Error>> {
   let args: Vec<String> = std::env::args().collect();
   let base = PathBuf::from(args[1].clone());
   let mut db: DB<_,_,R,I> = DB::open(|name| {
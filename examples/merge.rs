@@ -1,5 +1,4 @@
-use eyros::{DB,Row};
-use failure::Error;
+use eyros::{DB,Row,Error};
 use std::path::PathBuf;
 use random_access_disk::RandomAccessDisk;
 
@@ -27,10 +26,16 @@ fn main() -> Result<(),Error> {
       0
     );
     // TODO: incorporate len field and pre-set data offsets into Row enum
-    let batch: Vec<Row<P,V>> = ranges.list()?.iter().map(|(offset,range,_len)| {
-      //Row::Insert(*range,(b_index as u32,b_offset+*offset))
-      Row::Insert(*range,(b_index as u32,*offset))
-    }).collect();
+    let mut batch: Vec<Row<P,V>> = vec![];
+    for result in ranges.list()? {
+      let (offset,range,_len) = result?;
+      //Row::Insert(range,(b_index as u32,b_offset+offset))
+      batch.push(Row::Insert(range,(b_index as u32,offset)));
+      if batch.len() >= 100_000 {
+        db.batch(&batch)?;
+        batch.clear();
+      }
+    }
     db.batch(&batch)?;
     //b_offset += ranges.store.len()? as u64;
   }
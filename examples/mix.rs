@@ -1,6 +1,5 @@
-use eyros::{DB,Row,Mix,Mix2};
+use eyros::{DB,Row,Mix,Mix2,Error};
 use rand::random;
-use failure::Error;
 use random_access_disk::RandomAccessDisk;
 use std::path::PathBuf;
 
@@ -38,7 +37,7 @@ fn main() -> Result<(),Error> {
   Ok(())
 }
 
-fn storage(name:&str) -> Result<RandomAccessDisk,Error> {
+fn storage(name:&str) -> Result<RandomAccessDisk,failure::Error> {
   let mut p = PathBuf::from("/tmp/eyros-mix-db/");
   p.push(name);
   Ok(RandomAccessDisk::builder(p)
@@ -1,6 +1,5 @@
-use eyros::{DB,Row};
+use eyros::{DB,Row,Error};
 use rand::random;
-use failure::Error;
 use random_access_disk::RandomAccessDisk;
 use std::path::PathBuf;
 
@@ -28,7 +27,7 @@ fn main() -> Result<(),Error> {
   Ok(())
 }
 
-fn storage(name:&str) -> Result<RandomAccessDisk,Error> {
+fn storage(name:&str) -> Result<RandomAccessDisk,failure::Error> {
   let mut p = PathBuf::from("/tmp/eyros-polygons-db/");
   p.push(name);
   Ok(RandomAccessDisk::builder(p)
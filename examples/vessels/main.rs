@@ -0,0 +1,83 @@
+// Simulate AIS position reports of a fleet in the North Sea and track them:
+// upsert the latest position of each vessel, watch a geofence around the
+// approach to Rotterdam, replay a track, and draw a map tile.
+//
+//   cargo run --example vessels
+
+mod tracker;
+
+use tracker::{Tracker,Report,Crossing,render,tile_coords};
+use eyros::{Polygon,Error};
+use random::{Source,default as rand};
+use random_access_disk::RandomAccessDisk;
+use tempfile::Builder as Tmpfile;
+
+const VESSELS: u32 = 300;
+const STEPS: u32 = 60;
+const INTERVAL: f32 = 60.0; // seconds between reports
+
+fn main() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros-vessels").tempdir()?;
+  let path = dir.path().to_path_buf();
+  let mut tracker = Tracker::open(move |name: &str| {
+    Ok(RandomAccessDisk::builder(path.join(name)).auto_sync(false).build()?)
+  })?;
+  tracker.add_fence("rotterdam", Polygon::new(vec![vec![
+    (3.6,51.8),(4.2,51.8),(4.2,52.1),(3.6,52.1)
+  ]]))?;
+
+  let mut r = rand().seed([19,84]);
+  // start anywhere in the box, heading anywhere at 5 to 25 knots
+  let mut fleet: Vec<Report> = (0..VESSELS).map(|i| Report {
+    mmsi: 244_000_000 + i,
+    lon: 2.0 + r.read::<f32>()*5.0,
+    lat: 51.5 + r.read::<f32>()*3.5,
+    time: 0.0,
+    speed: 5.0 + r.read::<f32>()*20.0,
+    course: r.read::<f32>()*360.0
+  }).collect();
+
+  let mut crossings = 0;
+  for step in 0..STEPS {
+    let reports: Vec<Report> = fleet.iter_mut().filter_map(|v| {
+      // a nautical mile is a minute of latitude
+      let miles = v.speed * INTERVAL / 3600.0;
+      let course = (v.course as f64).to_radians();
+      v.lat += (miles as f64 * course.cos() / 60.0) as f32;
+      v.lon += (miles as f64 * course.sin() / 60.0
+        / (v.lat as f64).to_radians().cos()) as f32;
+      v.course = (v.course + (r.read::<f32>()-0.5)*10.0).rem_euclid(360.0);
+      v.time = (step as f32 + 1.0) * INTERVAL;
+      // about one report in ten is lost
+      if r.read::<f32>() < 0.1 { None } else { Some(*v) }
+    }).collect();
+    for event in tracker.ingest(&reports)? {
+      crossings += 1;
+      let verb = match event.crossing { Crossing::Enter => "entered", Crossing::Exit => "left" };
+      println!["t={:>5}s {} {} {}", event.time, event.mmsi, verb, event.fence];
+    }
+  }
+  println!["{} geofence crossings, {} vessels inside now",
+    crossings, tracker.inside("rotterdam")?.len()];
+
+  let first = 244_000_000;
+  let track = tracker.track(first, 600.0, 1200.0)?;
+  println!["\ntrack of {} from 600s to 1200s:", first];
+  for (lon,lat,t) in track.iter() {
+    println!["  t={:>5}s {:.4},{:.4}", t, lon, lat];
+  }
+  if let Some(((lon,lat),(_,t,speed,course))) = tracker.position(first)? {
+    println!["last seen at {:.4},{:.4} at {}s, {:.1} knots heading {:.0}",
+      lon, lat, t, speed, course];
+  }
+  let seen = tracker.seen(((3.0,52.0),(5.0,53.0)), 0.0, 1800.0)?;
+  println!["\n{} vessels reported off the dutch coast in the first half hour",
+    seen.len()];
+
+  let z = 6;
+  let (x,y) = tile_coords(4.5, 53.5, z);
+  let (x,y) = (x as u32, y as u32);
+  println!["\nvessels on tile {}/{}/{}:", z, x, y];
+  println!["{}", render(&tracker.tile(z, x, y, 32)?)];
+  Ok(())
+}
@@ -0,0 +1,258 @@
+use eyros::{DB,Row,Setup,Polygon,QueryRegion,Trigger,Error};
+use random_access_storage::RandomAccess;
+use std::collections::{HashMap,HashSet};
+use std::f64::consts::PI;
+use std::sync::{Arc,Mutex};
+
+/// `(longitude,latitude)` of the last known position of a vessel.
+pub type Position = (f32,f32);
+/// `(longitude,latitude,seconds)` of a position report.
+pub type Sample = (f32,f32,f32);
+/// `(mmsi,seconds,speed in knots,course in degrees)`.
+pub type Vessel = (u32,f32,f32,f32);
+
+pub type Open<S> = Box<dyn Fn(&str) -> Result<S,failure::Error>>;
+
+/// Position report as broadcast by a vessel's AIS transponder.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Report {
+  pub mmsi: u32,
+  pub lon: f32,
+  pub lat: f32,
+  pub time: f32,
+  pub speed: f32,
+  pub course: f32
+}
+
+impl Report {
+  fn value (&self) -> Vessel {
+    (self.mmsi, self.time, self.speed, self.course)
+  }
+}
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Crossing { Enter, Exit }
+
+/// Vessel crossing into or out of a geofence.
+#[derive(Clone,Debug,PartialEq)]
+pub struct FenceEvent {
+  pub fence: String,
+  pub mmsi: u32,
+  pub time: f32,
+  pub crossing: Crossing
+}
+
+struct Fence {
+  name: String,
+  polygon: Polygon,
+  inside: HashSet<u32>
+}
+
+/// Moving object tracker with one row per vessel for its latest position and
+/// one row per report for its track.
+///
+/// Latest positions are upserted by MMSI: each ingest deletes the previous
+/// row of a vessel by its location and inserts the new one in the same
+/// batch, and reports older than the stored position are only added to the
+/// track. Geofences are triggers on the latest positions.
+pub struct Tracker<S> where S: RandomAccess<Error=failure::Error> {
+  pub latest: DB<S,Open<S>,Position,Vessel>,
+  pub history: DB<S,Open<S>,Sample,Vessel>,
+  last: HashMap<u32,(Position,f32)>,
+  fences: Vec<Fence>,
+  matched: Arc<Mutex<Vec<(String,u32)>>>
+}
+
+impl<S> Tracker<S> where S: RandomAccess<Error=failure::Error>+'static {
+  /// Open the tracker's databases with store names prefixed by `latest_` and
+  /// `history_`.
+  pub fn open<F> (open_store: F) -> Result<Self,Error>
+  where F: Fn(&str) -> Result<S,failure::Error> + Clone + 'static {
+    let (a,b) = (open_store.clone(), open_store);
+    let latest: Open<S> = Box::new(move |name| a(&format!["latest_{}", name]));
+    let history: Open<S> = Box::new(move |name| b(&format!["history_{}", name]));
+    let mut tracker = Self {
+      latest: Setup::new(latest)
+        .max_data_size(500)
+        .base_size(2_000)
+        .build()?,
+      history: Setup::new(history)
+        .max_data_size(1_000)
+        .base_size(4_000)
+        .build()?,
+      last: HashMap::new(),
+      fences: vec![],
+      matched: Arc::new(Mutex::new(vec![]))
+    };
+    for row in tracker.latest.query(&((-180.0,-90.0),(180.0,90.0)))? {
+      let (position,vessel,_) = row?;
+      tracker.last.insert(vessel.0, (position,vessel.1));
+    }
+    Ok(tracker)
+  }
+
+  /// Write a batch of reports and return the geofence crossings they cause.
+  pub fn ingest (&mut self, reports: &[Report]) -> Result<Vec<FenceEvent>,Error> {
+    let mut newest: HashMap<u32,&Report> = HashMap::new();
+    for r in reports.iter() {
+      let stored = self.last.get(&r.mmsi).map(|(_,t)| *t);
+      if stored.map(|t| r.time < t).unwrap_or(false) { continue }
+      let e = newest.entry(r.mmsi).or_insert(r);
+      if r.time >= e.time { *e = r }
+    }
+    let mut rows = Vec::with_capacity(newest.len()*2);
+    for (mmsi,r) in newest.iter() {
+      if let Some((p,_)) = self.last.get(mmsi) {
+        let found = self.latest.query(&(*p,*p))?
+          .find(|row| row.as_ref().map(|(_,v,_)| v.0 == *mmsi).unwrap_or(true));
+        if let Some(row) = found {
+          rows.push(Row::Delete(row?.2));
+        }
+      }
+      rows.push(Row::Insert((r.lon,r.lat), r.value()));
+    }
+    self.latest.batch(&rows)?;
+    let samples: Vec<Row<Sample,Vessel>> = reports.iter()
+      .map(|r| Row::Insert((r.lon,r.lat,r.time), r.value()))
+      .collect();
+    self.history.batch(&samples)?;
+
+    let matched: HashSet<(String,u32)> = self.matched.lock().unwrap().drain(..).collect();
+    let mut events = vec![];
+    let mut mmsis: Vec<&u32> = newest.keys().collect();
+    mmsis.sort();
+    for mmsi in mmsis {
+      let r = newest[mmsi];
+      self.last.insert(*mmsi, ((r.lon,r.lat),r.time));
+      for fence in self.fences.iter_mut() {
+        let now = matched.contains(&(fence.name.clone(),*mmsi));
+        let crossing = match (fence.inside.contains(mmsi), now) {
+          (false,true) => Crossing::Enter,
+          (true,false) => Crossing::Exit,
+          _ => continue
+        };
+        if now { fence.inside.insert(*mmsi); } else { fence.inside.remove(mmsi); }
+        events.push(FenceEvent {
+          fence: fence.name.clone(), mmsi: *mmsi, time: r.time, crossing
+        });
+      }
+    }
+    Ok(events)
+  }
+
+  /// Report when vessels cross into or out of `polygon` from now on.
+  /// Vessels already inside don't cause an `Enter` event.
+  pub fn add_fence (&mut self, name: &str, polygon: Polygon) -> Result<(),Error> {
+    let bbox = QueryRegion::<Position>::bbox(&polygon);
+    let (p,matched) = (polygon.clone(), Arc::clone(&self.matched));
+    self.latest.add_trigger(Trigger::new(name)
+      .region(bbox)
+      .filter(move |point: &Position,_: &Vessel| p.contains_point(point))
+      .callback(move |name,_,vessel| {
+        matched.lock().unwrap().push((name.to_string(),vessel.0));
+      }))?;
+    let inside = self.latest.query_region(polygon.clone())?
+      .map(|row| row.map(|(_,v,_)| v.0))
+      .collect::<Result<HashSet<u32>,Error>>()?;
+    self.fences.push(Fence { name: name.to_string(), polygon, inside });
+    Ok(())
+  }
+
+  /// MMSIs of the vessels whose latest position is inside the fence `name`.
+  pub fn inside (&mut self, name: &str) -> Result<Vec<u32>,Error> {
+    let polygon = match self.fences.iter().find(|f| f.name == name) {
+      Some(fence) => fence.polygon.clone(),
+      None => return Err(Error::Invalid(format!["no fence named {}", name]))
+    };
+    let mut mmsis = self.latest.query_region(polygon)?
+      .map(|row| row.map(|(_,v,_)| v.0))
+      .collect::<Result<Vec<u32>,Error>>()?;
+    mmsis.sort();
+    Ok(mmsis)
+  }
+
+  /// Latest position and value of vessel `mmsi`.
+  pub fn position (&mut self, mmsi: u32) -> Result<Option<(Position,Vessel)>,Error> {
+    let p = match self.last.get(&mmsi) {
+      Some((p,_)) => *p,
+      None => return Ok(None)
+    };
+    for row in self.latest.query(&(p,p))? {
+      let (p,v,_) = row?;
+      if v.0 == mmsi { return Ok(Some((p,v))) }
+    }
+    Ok(None)
+  }
+
+  /// Reports of vessel `mmsi` from `start` to `end` seconds in time order.
+  pub fn track (&mut self, mmsi: u32, start: f32, end: f32)
+  -> Result<Vec<Sample>,Error> {
+    let bbox = ((-180.0,-90.0,start),(180.0,90.0,end));
+    let mut samples = self.history.query_filter(&bbox, move |v| v.0 == mmsi)?
+      .map(|row| row.map(|(p,_,_)| p))
+      .collect::<Result<Vec<Sample>,Error>>()?;
+    samples.sort_by(|a,b| a.2.partial_cmp(&b.2).unwrap());
+    Ok(samples)
+  }
+
+  /// MMSIs of the vessels that reported from inside `bbox` between `start`
+  /// and `end` seconds.
+  pub fn seen (&mut self, bbox: (Position,Position), start: f32, end: f32)
+  -> Result<Vec<u32>,Error> {
+    let ((x0,y0),(x1,y1)) = bbox;
+    let mut mmsis = HashSet::new();
+    for row in self.history.query(&((x0,y0,start),(x1,y1,end)))? {
+      let (_,vessel,_) = row?;
+      mmsis.insert(vessel.0);
+    }
+    let mut mmsis: Vec<u32> = mmsis.into_iter().collect();
+    mmsis.sort();
+    Ok(mmsis)
+  }
+
+  /// Count the latest positions in each cell of a `size` by `size` grid over
+  /// the web mercator tile `z/x/y`, with rows from north to south.
+  pub fn tile (&mut self, z: u32, x: u32, y: u32, size: usize)
+  -> Result<Vec<Vec<u32>>,Error> {
+    let (west,north) = tile_corner(z, x, y);
+    let (east,south) = tile_corner(z, x+1, y+1);
+    let bbox = ((west as f32, south as f32),(east as f32, north as f32));
+    let mut grid = vec![vec![0;size];size];
+    for row in self.latest.query(&bbox)? {
+      let ((lon,lat),_,_) = row?;
+      let (tx,ty) = tile_coords(lon as f64, lat as f64, z);
+      let col = ((tx - x as f64) * size as f64).floor();
+      let row = ((ty - y as f64) * size as f64).floor();
+      if col < 0.0 || row < 0.0 || col >= size as f64 || row >= size as f64 {
+        continue
+      }
+      grid[row as usize][col as usize] += 1;
+    }
+    Ok(grid)
+  }
+}
+
+/// Fractional web mercator tile coordinates of a position at zoom `z`.
+pub fn tile_coords (lon: f64, lat: f64, z: u32) -> (f64,f64) {
+  let n = (1u64 << z) as f64;
+  let lat = lat.to_radians();
+  let x = (lon + 180.0) / 360.0 * n;
+  let y = (1.0 - (lat.tan() + 1.0/lat.cos()).ln() / PI) / 2.0 * n;
+  (x,y)
+}
+
+/// `(longitude,latitude)` of the north west corner of tile `z/x/y`.
+pub fn tile_corner (z: u32, x: u32, y: u32) -> (f64,f64) {
+  let n = (1u64 << z) as f64;
+  let lon = x as f64 / n * 360.0 - 180.0;
+  let lat = (PI * (1.0 - 2.0 * y as f64 / n)).sinh().atan().to_degrees();
+  (lon,lat)
+}
+
+/// Draw a tile grid with one character per cell, darker for more vessels.
+pub fn render (grid: &[Vec<u32>]) -> String {
+  let shades = [' ','.',':','*','#'];
+  grid.iter().map(|row| {
+    row.iter().map(|n| shades[(*n as usize).min(shades.len()-1)]).collect::<String>()
+  }).collect::<Vec<_>>().join("\n")
+}
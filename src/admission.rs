@@ -0,0 +1,82 @@
+use crate::Overloaded;
+use crate::Error;
+use std::sync::{Arc,Mutex};
+use std::task::{Context,Poll,Waker};
+
+/// What to do with a query that arrives while `Setup::max_queries()` queries
+/// are already in flight.
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Default)]
+pub enum Admission {
+  /// Fail right away with an `Overloaded` error.
+  #[default]
+  Reject,
+  /// Wait for a running query to finish. Only `AsyncDB::query()` can wait:
+  /// a synchronous `query()` fails with `Overloaded` like `Reject`, since
+  /// the query it would wait on may belong to the same thread.
+  Queue
+}
+
+// counts the queries that hold a permit and the async callers waiting for one
+pub(crate) struct Gate {
+  pub limit: usize,
+  pub mode: Admission,
+  state: Mutex<GateState>
+}
+
+#[derive(Default)]
+struct GateState {
+  active: usize,
+  waiters: Vec<Waker>
+}
+
+impl Gate {
+  pub fn new (limit: usize, mode: Admission) -> Arc<Self> {
+    Arc::new(Self { limit, mode, state: Mutex::new(GateState::default()) })
+  }
+  pub fn active (&self) -> usize {
+    self.state.lock().map(|s| s.active).unwrap_or(0)
+  }
+  pub fn try_acquire (gate: &Arc<Self>) -> Result<Permit,Error> {
+    let mut state = gate.state.lock()
+      .map_err(|_| Error::Other("admission state poisoned".into()))?;
+    if state.active >= gate.limit {
+      return Err(Overloaded { limit: gate.limit }.into());
+    }
+    state.active += 1;
+    Ok(Permit { gate: Arc::clone(gate) })
+  }
+  pub fn poll_acquire (gate: &Arc<Self>, cx: &mut Context)
+  -> Poll<Result<Permit,Error>> {
+    let mut state = match gate.state.lock() {
+      Ok(state) => state,
+      Err(_) => return Poll::Ready(Err(Error::Other("admission state poisoned".into())))
+    };
+    if state.active < gate.limit {
+      state.active += 1;
+      return Poll::Ready(Ok(Permit { gate: Arc::clone(gate) }));
+    }
+    state.waiters.push(cx.waker().clone());
+    Poll::Pending
+  }
+}
+
+/// Slot held by an admitted query. The slot is released when the query
+/// finishes or is dropped.
+pub(crate) struct Permit {
+  gate: Arc<Gate>
+}
+
+impl Drop for Permit {
+  fn drop (&mut self) {
+    let waiters = match self.gate.state.lock() {
+      Ok(mut state) => {
+        state.active -= 1;
+        std::mem::take(&mut state.waiters)
+      },
+      Err(_) => return
+    };
+    for waker in waiters {
+      waker.wake();
+    }
+  }
+}
@@ -0,0 +1,146 @@
+use crate::{DB,Point,Value,CacheMode,AuditOp};
+use crate::lock::Lock;
+use crate::Error;
+use random_access_storage::RandomAccess;
+use std::collections::{HashMap,HashSet};
+
+/// Summary of the rows that intersect a bounding box, returned by
+/// `db.aggregate()`.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Aggregate<P> where P: Point {
+  /// Number of rows.
+  pub count: u64,
+  /// Bounding box of the rows, or `None` if there are no rows or the point
+  /// type doesn't implement `Point::union_bounds()`.
+  pub bounds: Option<P::Bounds>
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Count the rows that intersect `bbox` without decoding any values.
+  ///
+  /// Data blocks that lie entirely inside of `bbox` are counted from the
+  /// number of set bits in their deletion bitfield, so only the blocks along
+  /// the edges of `bbox` have their points read.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut db: DB<_,_,(f32,f32),u32> = DB::open(storage)?;
+  /// let n = db.count(&((-0.5,-0.8),(0.3,-0.5)))?;
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn count (&mut self, bbox: &P::Bounds) -> Result<u64,Error> {
+    Ok(self.aggregate_rows(bbox, false)?.count)
+  }
+
+  /// Count the rows that intersect `bbox` like `count()` and also compute the
+  /// minimum and maximum coordinates of those rows along each dimension.
+  ///
+  /// Computing the bounds reads the points of every matching block, but still
+  /// skips over the values without decoding them.
+  pub fn aggregate (&mut self, bbox: &P::Bounds) -> Result<Aggregate<P>,Error> {
+    self.aggregate_rows(bbox, true)
+  }
+
+  fn aggregate_rows (&mut self, bbox: &P::Bounds, with_bounds: bool)
+  -> Result<Aggregate<P>,Error> {
+    self.check_open()?;
+    let _permit = self.admit()?;
+    let mut result = Aggregate { count: 0, bounds: None };
+    let mut seen = false; // whether any bounds were combined yet
+    let mut add_points = |result: &mut Aggregate<P>, points: Vec<P>| {
+      result.count += points.len() as u64;
+      if !with_bounds { return }
+      if let Some(b) = P::bounds(&points) {
+        result.bounds = match (seen,&result.bounds) {
+          (false,_) => Some(b),
+          (true,Some(a)) => P::union_bounds(a, &b),
+          (true,None) => None
+        };
+        seen = true;
+      }
+    };
+    let deletes = self.staging.delete_set.read_lock()?.clone();
+    {
+      let inserts = self.staging.inserts.read_lock()?;
+      let points = inserts.iter().enumerate()
+        .filter(|(i,(p,v))| {
+          !deletes.contains(&(0,*i as u32)) && p.overlaps(bbox) && self.is_visible(p,v)
+        })
+        .map(|(_,(p,_))| *p)
+        .collect();
+      add_points(&mut result, points);
+    }
+    let block_deletes = self.block_deletes()?;
+    let offsets = self.block_offsets(bbox)?;
+    let mut dstore = self.data_store.write_lock()?;
+    let entries = dstore.block_entries()?;
+    for offset in offsets {
+      if dstore.quarantine.contains(&offset) { continue }
+      let no_deletes = HashSet::new();
+      let deleted = block_deletes.get(&offset).unwrap_or(&no_deletes);
+      match entries.get(&offset) {
+        // hidden rows can only be told apart by reading them
+        _ if self.visible.is_some() => {
+          let points = dstore.list_or_quarantine(offset, CacheMode::Normal)?.iter()
+            .filter(|(p,v,(_,i))| {
+              !deleted.contains(i) && p.overlaps(bbox) && self.is_visible(p,v)
+            })
+            .map(|(p,_,_)| *p)
+            .collect();
+          add_points(&mut result, points);
+        },
+        Some((range,rows)) if !with_bounds && P::range_within(range, bbox) => {
+          let bits = dstore.live_bits(offset, *rows)?;
+          let live: u32 = bits.iter().map(|b| b.count_ones()).sum();
+          let staged = deleted.iter().filter(|i| {
+            let i = **i as usize;
+            i/8 < bits.len() && ((bits[i/8]>>(i%8))&1) == 1
+          }).count();
+          result.count += (live as u64) - (staged as u64);
+        },
+        _ => {
+          let points = dstore.points(offset)?.into_iter()
+            .filter(|(p,i)| !deleted.contains(i) && p.overlaps(bbox))
+            .map(|(p,_)| p)
+            .collect();
+          add_points(&mut result, points);
+        }
+      }
+    }
+    drop(dstore);
+    self.audit_event(AuditOp::Aggregate, bbox, result.count as usize);
+    Ok(result)
+  }
+
+  // staged deletes of rows in data blocks, by block offset
+  pub(crate) fn block_deletes (&self) -> Result<HashMap<u64,HashSet<u32>>,Error> {
+    let mut block_deletes: HashMap<u64,HashSet<u32>> = HashMap::new();
+    for (block,index) in self.staging.delete_set.read_lock()?.iter() {
+      if *block == 0 { continue }
+      block_deletes.entry(*block-1).or_default().insert(*index);
+    }
+    Ok(block_deletes)
+  }
+
+  // offsets of the data blocks in every tree whose bounds intersect `bbox`
+  pub(crate) fn block_offsets (&mut self, bbox: &P::Bounds) -> Result<Vec<u64>,Error> {
+    let mut offsets = vec![];
+    for tree in self.trees.iter() {
+      let mut t = tree.write_lock()?;
+      if t.is_empty()? { continue }
+      offsets.extend(t.query_offsets(bbox)?);
+    }
+    Ok(offsets)
+  }
+}
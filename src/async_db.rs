@@ -0,0 +1,968 @@
+//! Async interface to the database.
+//!
+//! `AsyncDB` opens a database over stores that implement
+//! `AsyncRandomAccess`, whose reads and writes complete as futures, so
+//! opening, writing, and querying can be awaited from async code without
+//! blocking the executor or spawning threads.
+//!
+//! The database reads its stores through a cache of pages that are fetched
+//! from the async stores the first time they are read, so a call only reads
+//! the blocks it touches and a database doesn't have to fit in memory. A
+//! call that reads a page that isn't cached stops there. The page is
+//! fetched, and the call goes on: queries pick up where they stopped, and
+//! other calls drop their writes, read the handle back from the stores, and
+//! run again from the start. The writes of a call reach the async stores in
+//! order once it is done, so the stores see the same sequence of writes as
+//! they would under a `DB`, and `Setup::wal()` protects a batch the same
+//! way. Calls run one at a time in the order they were made, as they would
+//! on a `DB`.
+
+use crate::{DB,Setup,Row,Point,Value,Location,QueryIterator,CacheMode,Admission,Executor};
+use crate::admission::Gate;
+use crate::Error;
+use random_access_storage::RandomAccess;
+use std::collections::{BTreeMap,BTreeSet,HashMap,VecDeque};
+use std::fmt;
+use std::future::{Future,poll_fn};
+use std::io;
+use std::ops::{Deref,DerefMut};
+use std::pin::Pin;
+use std::sync::{Arc,Mutex};
+use std::task::{Context,Poll,Waker};
+use std::time::Duration;
+
+// rows read at a time for `AsyncQuery::next()`
+const PAGE: usize = 256;
+// bytes of a cached page, the unit that async stores are read in
+const PAGE_SIZE: u64 = 4_096;
+// bytes of pages kept between calls by default
+const CACHE_BYTES: usize = 32 << 20;
+const ZEROS: &[u8] = &[0u8;PAGE_SIZE as usize];
+
+/// Future returned by the methods of `AsyncRandomAccess`.
+pub type StoreFuture<'a,T> = Pin<Box<dyn Future<Output=Result<T,failure::Error>>+Send+'a>>;
+
+/// Store whose reads and writes complete as futures, such as a file on an
+/// async runtime or an object in remote storage.
+///
+/// ```rust,ignore
+/// use eyros::async_db::{AsyncRandomAccess,StoreFuture};
+/// use tokio::{fs::File,io::{AsyncReadExt,AsyncSeekExt,AsyncWriteExt,SeekFrom}};
+///
+/// struct TokioFile(File);
+///
+/// impl AsyncRandomAccess for TokioFile {
+///   fn write<'a> (&'a mut self, offset: u64, data: &'a [u8]) -> StoreFuture<'a,()> {
+///     Box::pin(async move {
+///       self.0.seek(SeekFrom::Start(offset)).await?;
+///       Ok(self.0.write_all(data).await?)
+///     })
+///   }
+///   // ...
+/// }
+/// ```
+pub trait AsyncRandomAccess: Send {
+  /// Write `data` at `offset`, filling any gap past the end with zeros.
+  fn write<'a> (&'a mut self, offset: u64, data: &'a [u8]) -> StoreFuture<'a,()>;
+  /// Read `length` bytes at `offset`.
+  fn read (&mut self, offset: u64, length: u64) -> StoreFuture<'_,Vec<u8>>;
+  /// Shorten or extend the store to `length` bytes.
+  fn truncate (&mut self, length: u64) -> StoreFuture<'_,()>;
+  /// Length of the store in bytes.
+  fn len (&mut self) -> StoreFuture<'_,u64>;
+  /// Whether the store has no bytes.
+  fn is_empty (&mut self) -> StoreFuture<'_,bool> {
+    Box::pin(async move { Ok(self.len().await? == 0) })
+  }
+  /// Flush the writes so far to durable storage.
+  fn sync_all (&mut self) -> StoreFuture<'_,()>;
+}
+
+/// Storage function that `AsyncDB` runs its database over, reading through
+/// the pages cached from the async stores.
+pub type CachedStorage = Box<dyn Fn(&str) -> Result<CachedStore,failure::Error> + Send + Sync>;
+
+/// Database that `AsyncDB` wraps, for `AsyncDB::run()`.
+pub type CachedDB<P,V> = DB<CachedStore,CachedStorage,P,V>;
+
+/// Async database over `AsyncRandomAccess` stores.
+///
+/// ```rust,no_run
+/// use eyros::{Row,async_db::{AsyncDB,AsyncRandomAccess},Error};
+/// # type P = ((f32,f32),(f32,f32));
+/// # async fn run<A: AsyncRandomAccess+'static> (
+/// #   storage: fn(&str) -> eyros::async_db::StoreFuture<'static,A>
+/// # ) -> Result<(),Error> {
+/// let mut db: AsyncDB<_,_,P,u32> = AsyncDB::open(storage).await?;
+/// db.batch(&[Row::Insert(((0.0,0.1),(0.2,0.3)), 5)]).await?;
+/// let mut results = db.query(&((-0.5,-0.8),(0.3,0.5))).await?;
+/// while let Some(result) = results.next().await {
+///   let (point,value,location) = result?;
+///   // ...
+/// }
+/// db.close().await?;
+/// # Ok(()) }
+/// ```
+pub struct AsyncDB<A,O,P,V> where
+A: AsyncRandomAccess,
+O: Fn(&str) -> StoreFuture<'static,A>,
+P: Point, V: Value {
+  inner: Arc<Shared<Inner<A,O,P,V>>>,
+  cache: Arc<Mutex<Cache>>,
+  gate: Option<Arc<Gate>>,
+  maintenance: Option<Arc<Control>>
+}
+
+// the database and the async stores that it reads and writes through the cache
+struct Inner<A,O,P,V> where
+A: AsyncRandomAccess,
+O: Fn(&str) -> StoreFuture<'static,A>,
+P: Point, V: Value {
+  db: CachedDB<P,V>,
+  stores: Stores<A,O>,
+  // set while the handle holds the changes of a call whose writes were
+  // dropped, until it is read back from the stores
+  stale: Option<crate::Savepoint>
+}
+
+impl<A,O,P,V> Inner<A,O,P,V> where
+A: AsyncRandomAccess,
+O: Fn(&str) -> StoreFuture<'static,A>,
+P: Point, V: Value {
+  // run `f` until it runs without reading a page that isn't cached, then
+  // write its changes to the async stores and set off its effects
+  async fn call<T,F> (&mut self, mut f: F) -> Result<T,Error>
+  where F: FnMut(&mut CachedDB<P,V>) -> Result<T,Error> {
+    loop {
+      self.restore().await?;
+      let savepoint = self.db.savepoint();
+      self.db.hold_effects();
+      let result = f(&mut self.db);
+      match self.finish().await {
+        Ok(true) => {
+          self.db.release_effects();
+          return result;
+        },
+        Ok(false) => {},
+        Err(e) => {
+          self.db.discard_effects();
+          return Err(e);
+        }
+      }
+      drop(result);
+      self.db.discard_effects();
+      self.stores.discard()?;
+      self.stale = Some(savepoint);
+    }
+  }
+  // write the changes of a call to the async stores, or return `false` if it
+  // read a page that isn't cached
+  async fn finish (&mut self) -> Result<bool,Error> {
+    if self.stores.missed()? { return Ok(false) }
+    self.stores.keep()?;
+    self.stores.flush().await?;
+    self.stores.trim()?;
+    Ok(true)
+  }
+  // fetch what the last call missed and read the handle back from the stores,
+  // if the writes of that call were dropped. a call that was cancelled
+  // before it finished leaves effects that never happened.
+  async fn restore (&mut self) -> Result<(),Error> {
+    self.db.discard_effects();
+    while let Some(savepoint) = &self.stale {
+      self.stores.fetch().await?;
+      let result = self.db.rollback(savepoint);
+      if self.stores.missed()? {
+        self.stores.discard()?;
+        continue;
+      }
+      self.stores.keep()?;
+      self.stale = None;
+      result?;
+    }
+    Ok(())
+  }
+}
+
+impl<A,O,P,V> AsyncDB<A,O,P,V> where
+A: AsyncRandomAccess+'static,
+O: (Fn(&str) -> StoreFuture<'static,A>)+Send+Sync+'static,
+P: Point+Send+Sync+'static, V: Value+Send+Sync+'static,
+P::Bounds: Send+Sync+'static, P::Range: Send+Sync {
+  /// Open a database with the default configuration, like `DB::open()`.
+  pub async fn open (open_store: O) -> Result<Self,Error> {
+    Self::open_from_setup(open_store, |setup| setup).await
+  }
+  /// Open a database with the settings that `configure` applies to a
+  /// `Setup`, like `DB::open_from_setup()`.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{async_db::{AsyncDB,AsyncRandomAccess,StoreFuture},Error};
+  /// # async fn run<A: AsyncRandomAccess+'static> (
+  /// #   storage: fn(&str) -> StoreFuture<'static,A>
+  /// # ) -> Result<(),Error> {
+  /// let db: AsyncDB<_,_,(f32,f32),u32> = AsyncDB::open_from_setup(storage, |setup| {
+  ///   setup.max_data_size(500).wal(true)
+  /// }).await?;
+  /// # Ok(()) }
+  /// ```
+  pub async fn open_from_setup<F> (open_store: O, configure: F) -> Result<Self,Error>
+  where F: FnOnce(Setup<CachedStore,CachedStorage>) -> Setup<CachedStore,CachedStorage> {
+    let mut stores = Stores::new(open_store);
+    let mut fields = configure(Setup::new(stores.storage())).fields;
+    // a page that isn't cached is fetched before the call runs again, so
+    // there is no use in waiting for it to show up
+    let retryable = Arc::clone(&fields.retry.retryable);
+    fields.retry.retryable = Arc::new(move |err| !not_fetched(err) && retryable(err));
+    let db = loop {
+      let result = (Setup { open_store: stores.storage(), fields: fields.clone() }).build();
+      if stores.missed()? {
+        drop(result);
+        stores.discard()?;
+        stores.fetch().await?;
+        continue;
+      }
+      stores.keep()?;
+      stores.flush().await?;
+      break result?;
+    };
+    let gate = db.gate().cloned();
+    let cache = Arc::clone(&stores.cache);
+    let inner = Inner { db, stores, stale: None };
+    Ok(Self { inner: Arc::new(Shared::new(inner)), cache, gate, maintenance: None })
+  }
+  /// Keep at most `bytes` of cached pages between calls, dropping the least
+  /// recently used ones first. A call keeps the pages it reads until it is
+  /// done, so it can go over. The default is 32 MiB.
+  ///
+  /// Blocks that the database parses from the pages are cached apart from
+  /// them, within the bounds of `Setup::cache_bytes()`.
+  pub fn set_cache_bytes (&mut self, bytes: usize) -> Result<(),Error> {
+    lock_cache(&self.cache)?.max_bytes = bytes;
+    Ok(())
+  }
+  /// Write a collection of updates, like `DB::batch()`.
+  pub async fn batch (&mut self, rows: &[Row<P,V>]) -> Result<(),Error> {
+    self.run(|db| db.batch(rows)).await
+  }
+  /// Query for records that intersect `bbox`, like `DB::query()`.
+  ///
+  /// Rows are read a page at a time as `next()` is awaited. With
+  /// `Setup::max_queries()` in `Admission::Queue` mode, this waits for an
+  /// earlier query to finish when the limit is reached.
+  ///
+  /// A query that reads a block that isn't cached yet after another call
+  /// wrote to the database continues with the blocks as they are then, the
+  /// same as a `DB` query that is read after a batch.
+  pub async fn query (&mut self, bbox: &P::Bounds)
+  -> Result<AsyncQuery<A,O,P,V>,Error> {
+    loop {
+      let permit = match &self.gate {
+        Some(gate) if gate.mode == Admission::Queue => {
+          Some(poll_fn(|cx| Gate::poll_acquire(gate, cx)).await?)
+        },
+        _ => None
+      };
+      let mut inner = self.inner.lease().await;
+      inner.restore().await?;
+      let db = &mut inner.db;
+      let result = db.check_open().and_then(|_| {
+        let permit = match permit {
+          Some(permit) => Some(permit),
+          None => db.admit()?
+        };
+        db.query_admitted(bbox, CacheMode::Normal, permit, true)
+      });
+      // the permit went with the query, so a query that missed a page waits
+      // for a new one
+      if inner.stores.missed()? {
+        drop(result);
+        inner.stores.keep()?;
+        inner.stores.fetch().await?;
+        continue;
+      }
+      inner.stores.keep()?;
+      inner.stores.flush().await?;
+      let audit = inner.db.audit_query(bbox);
+      let iter = result?.audit(audit);
+      return Ok(AsyncQuery {
+        inner: Arc::clone(&self.inner),
+        iter: Some(iter),
+        rows: VecDeque::new()
+      });
+    }
+  }
+  /// Run `db.run_maintenance(budget)` every `interval`, from a task spawned
+  /// on `executor`, until `stop_maintenance()` or `close()`.
+  ///
+  /// Maintenance takes turns with the other calls, so `budget` bounds how
+  /// long a call may wait behind it. The pending jobs include compaction
+  /// with `Setup::compact_ratio()` and pruning the changes feed with
+  /// `Setup::retention()`. An error stops the task, and the next
+  /// `stop_maintenance()` or `close()` returns it.
+  pub fn start_maintenance<E> (&mut self, executor: E, interval: Duration,
+  budget: Duration) -> Result<(),Error> where E: Executor+Send+Sync+'static {
+    if self.maintenance.is_some() {
+      invalid!["maintenance is already running"];
+    }
+    let control = Arc::new(Control::default());
+    let inner = Arc::clone(&self.inner);
+    let task_control = Arc::clone(&control);
+    let spawner = Arc::new(executor);
+    let task_executor = Arc::clone(&spawner);
+    spawner.spawn(Box::pin(async move {
+      let control = Finish(task_control);
+      loop {
+        let mut sleep = task_executor.sleep(interval);
+        let stopped = poll_fn(|cx| {
+          if control.0.poll_stop(cx) { return Poll::Ready(true) }
+          sleep.as_mut().poll(cx).map(|_| false)
+        }).await;
+        if stopped { break }
+        let mut inner = inner.lease().await;
+        if let Err(e) = inner.call(|db| db.run_maintenance(budget)).await {
+          control.0.fail(e);
+          break;
+        }
+      }
+    }));
+    self.maintenance = Some(control);
+    Ok(())
+  }
+  /// Stop the task of `start_maintenance()` and wait for a maintenance run in
+  /// progress to finish. Returns the error that stopped the task early, if
+  /// any.
+  pub async fn stop_maintenance (&mut self) -> Result<(),Error> {
+    let control = match self.maintenance.take() {
+      Some(control) => control,
+      None => return Ok(())
+    };
+    control.stop();
+    poll_fn(|cx| control.poll_done(cx)).await
+  }
+  /// Stop maintenance, then flush and close the database like `DB::close()`.
+  /// Await this before dropping the handle, since the writes that a `DB`
+  /// makes when it is dropped can't reach the async stores.
+  pub async fn close (&mut self) -> Result<(),Error> {
+    let stopped = self.stop_maintenance().await;
+    self.run(|db| db.close()).await?;
+    stopped
+  }
+  /// Run `f` with the wrapped database, for the calls that `AsyncDB` doesn't
+  /// wrap, and write its changes to the async stores.
+  ///
+  /// When `f` reads a page that isn't cached, its writes are dropped and it
+  /// runs again from the start once the page is fetched. Trigger callbacks,
+  /// subscription notifications, and audit events wait until a run gets
+  /// through without a miss, so they happen once. Other callbacks that `f`
+  /// sets off itself run again with it.
+  pub async fn run<T,F> (&mut self, f: F) -> Result<T,Error> where
+  F: FnMut(&mut CachedDB<P,V>) -> Result<T,Error> {
+    self.inner.lease().await.call(f).await
+  }
+}
+
+impl<A,O,P,V> Drop for AsyncDB<A,O,P,V> where
+A: AsyncRandomAccess,
+O: Fn(&str) -> StoreFuture<'static,A>,
+P: Point, V: Value {
+  // the task keeps the database alive, so it has to stop with the handle
+  fn drop (&mut self) {
+    if let Some(control) = &self.maintenance {
+      control.stop();
+    }
+  }
+}
+
+/// Query results from `AsyncDB::query()`, read with `next().await`.
+pub struct AsyncQuery<A,O,P,V> where
+A: AsyncRandomAccess,
+O: Fn(&str) -> StoreFuture<'static,A>,
+P: Point, V: Value, P::Bounds: 'static {
+  inner: Arc<Shared<Inner<A,O,P,V>>>,
+  // `None` once the query is done
+  iter: Option<QueryIterator<'static,CachedStore,P,V>>,
+  rows: VecDeque<Result<(P,V,Location),Error>>
+}
+
+impl<A,O,P,V> AsyncQuery<A,O,P,V> where
+A: AsyncRandomAccess+'static,
+O: (Fn(&str) -> StoreFuture<'static,A>)+Send+Sync+'static,
+P: Point+Send+Sync+'static, V: Value+Send+Sync+'static,
+P::Bounds: Send+Sync+'static, P::Range: Send+Sync {
+  /// Return the next result, or `None` when the query is done.
+  pub async fn next (&mut self) -> Option<Result<(P,V,Location),Error>> {
+    if self.rows.is_empty() {
+      self.iter.as_ref()?;
+      // pages take turns with the other calls, and may quarantine blocks
+      let mut inner = self.inner.lease().await;
+      if let Err(e) = self.read_page(&mut *inner).await {
+        self.rows.push_back(Err(e));
+      }
+    }
+    self.rows.pop_front()
+  }
+  // a read that misses a page leaves the query where it was, so the query
+  // goes on once the page is fetched
+  async fn read_page (&mut self, inner: &mut Inner<A,O,P,V>) -> Result<(),Error> {
+    inner.restore().await?;
+    while self.rows.len() < PAGE {
+      let iter = match &mut self.iter {
+        Some(iter) => iter,
+        None => break
+      };
+      let next = iter.next();
+      let missed = inner.stores.missed()?;
+      match next {
+        Some(Err(_)) if missed => {},
+        Some(result) => self.rows.push_back(result),
+        None => self.iter = None
+      }
+      if missed {
+        inner.stores.keep()?;
+        inner.stores.fetch().await?;
+      }
+    }
+    inner.stores.keep()?;
+    inner.stores.flush().await?;
+    inner.stores.trim()
+  }
+}
+
+// shared between an `AsyncDB` and the task of `start_maintenance()`
+#[derive(Default)]
+struct Control {
+  state: Mutex<ControlState>
+}
+
+#[derive(Default)]
+struct ControlState {
+  stop: bool,
+  done: bool,
+  error: Option<Error>,
+  // the task while it sleeps, and whoever waits for it to finish
+  task: Option<Waker>,
+  closer: Option<Waker>
+}
+
+impl Control {
+  fn lock (&self) -> std::sync::MutexGuard<'_,ControlState> {
+    // every update leaves the state whole
+    self.state.lock().unwrap_or_else(|e| e.into_inner())
+  }
+  fn stop (&self) {
+    let waker = {
+      let mut state = self.lock();
+      state.stop = true;
+      state.task.take()
+    };
+    if let Some(waker) = waker { waker.wake() }
+  }
+  fn poll_stop (&self, cx: &mut Context) -> bool {
+    let mut state = self.lock();
+    if !state.stop { state.task = Some(cx.waker().clone()) }
+    state.stop
+  }
+  fn fail (&self, error: Error) {
+    self.lock().error = Some(error);
+  }
+  fn poll_done (&self, cx: &mut Context) -> Poll<Result<(),Error>> {
+    let mut state = self.lock();
+    if !state.done {
+      state.closer = Some(cx.waker().clone());
+      return Poll::Pending;
+    }
+    match state.error.take() {
+      Some(e) => Poll::Ready(Err(e)),
+      None => Poll::Ready(Ok(()))
+    }
+  }
+}
+
+// marks the task as done when it returns or when the executor drops it
+struct Finish(Arc<Control>);
+
+impl Drop for Finish {
+  fn drop (&mut self) {
+    let waker = {
+      let mut state = self.0.lock();
+      state.done = true;
+      state.closer.take()
+    };
+    if let Some(waker) = waker { waker.wake() }
+  }
+}
+
+// value that async calls take turns with
+struct Shared<T> {
+  state: Mutex<SharedState<T>>
+}
+
+struct SharedState<T> {
+  // `None` while a call holds it
+  value: Option<T>,
+  waiters: VecDeque<Waker>
+}
+
+impl<T> Shared<T> {
+  fn new (value: T) -> Self {
+    Self { state: Mutex::new(SharedState { value: Some(value), waiters: VecDeque::new() }) }
+  }
+  fn lock (&self) -> std::sync::MutexGuard<'_,SharedState<T>> {
+    // every update leaves the state whole
+    self.state.lock().unwrap_or_else(|e| e.into_inner())
+  }
+  async fn lease (self: &Arc<Self>) -> Lease<T> {
+    let value = poll_fn(|cx| {
+      let mut state = self.lock();
+      match state.value.take() {
+        Some(value) => Poll::Ready(value),
+        None => {
+          state.waiters.push_back(cx.waker().clone());
+          Poll::Pending
+        }
+      }
+    }).await;
+    Lease { shared: Arc::clone(self), value: Some(value) }
+  }
+}
+
+// puts the value back when the call is done, or when its future is dropped
+struct Lease<T> {
+  shared: Arc<Shared<T>>,
+  value: Option<T>
+}
+
+impl<T> Deref for Lease<T> {
+  type Target = T;
+  fn deref (&self) -> &T {
+    self.value.as_ref().expect("leased value")
+  }
+}
+
+impl<T> DerefMut for Lease<T> {
+  fn deref_mut (&mut self) -> &mut T {
+    self.value.as_mut().expect("leased value")
+  }
+}
+
+impl<T> Drop for Lease<T> {
+  fn drop (&mut self) {
+    // a waiter may have been dropped since it asked, so wake them all and
+    // let the first to run take the value
+    let waiters = {
+      let mut state = self.shared.lock();
+      state.value = self.value.take();
+      std::mem::take(&mut state.waiters)
+    };
+    for waker in waiters { waker.wake() }
+  }
+}
+
+// reason of the error returned for pages and lengths that aren't cached
+#[derive(Debug)]
+struct NotFetched;
+
+impl fmt::Display for NotFetched {
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "store data not fetched yet")
+  }
+}
+
+impl std::error::Error for NotFetched {}
+
+// whether `err` comes from reading a page that isn't fetched yet, which isn't
+// a failure of the block: the call runs again once it is
+fn not_fetched (err: &Error) -> bool {
+  err.downcast_ref::<io::Error>()
+    .and_then(|e| e.get_ref())
+    .map(|e| e.is::<NotFetched>())
+    .unwrap_or(false)
+}
+
+// what a call read that isn't cached
+#[derive(Clone,Copy,PartialEq,Eq,PartialOrd,Ord)]
+enum Need {
+  Len,
+  Page(u64)
+}
+
+// change to a store, to write to its async store
+enum Op {
+  Write(u64,Vec<u8>),
+  Truncate(u64),
+  Sync
+}
+
+// cached contents of an async store
+#[derive(Default)]
+struct File {
+  // `None` until fetched
+  len: Option<u64>,
+  // pages by index, with when they were last read
+  pages: HashMap<u64,(Vec<u8>,u64)>
+}
+
+// changes that the running call made to a store
+struct Draft {
+  len: u64,
+  // lowest length the store was truncated to, past which cached pages no
+  // longer hold its bytes
+  cut: Option<u64>,
+  pages: HashMap<u64,Vec<u8>>
+}
+
+fn pages (offset: u64, end: u64) -> std::ops::Range<u64> {
+  offset/PAGE_SIZE .. end.div_ceil(PAGE_SIZE)
+}
+
+// pages cached from the async stores, and the changes made over them
+struct Cache {
+  files: HashMap<String,File>,
+  // kept apart until the running call is done, so that a call that runs
+  // again can drop them
+  drafts: HashMap<String,Draft>,
+  draft_ops: Vec<(String,Op)>,
+  // changes of finished calls that the async stores don't have yet
+  queue: VecDeque<(String,Op)>,
+  missing: BTreeSet<(String,Need)>,
+  max_bytes: usize,
+  clock: u64
+}
+
+impl Default for Cache {
+  fn default () -> Self {
+    Self {
+      files: HashMap::new(),
+      drafts: HashMap::new(),
+      draft_ops: vec![],
+      queue: VecDeque::new(),
+      missing: BTreeSet::new(),
+      max_bytes: CACHE_BYTES,
+      clock: 0
+    }
+  }
+}
+
+fn lock_cache (cache: &Mutex<Cache>) -> Result<std::sync::MutexGuard<'_,Cache>,Error> {
+  cache.lock().map_err(|_| Error::Other("page cache poisoned".into()))
+}
+
+impl Cache {
+  fn miss<T> (&mut self, name: &str, needs: impl Iterator<Item=Need>)
+  -> Result<T,failure::Error> {
+    self.missing.extend(needs.map(|need| (name.to_string(),need)));
+    Err(io::Error::new(io::ErrorKind::WouldBlock, NotFetched).into())
+  }
+  // length of the store as the running call sees it
+  fn len (&mut self, name: &str) -> Result<u64,failure::Error> {
+    if let Some(draft) = self.drafts.get(name) {
+      return Ok(draft.len);
+    }
+    match self.files.get(name).and_then(|file| file.len) {
+      Some(len) => Ok(len),
+      None => self.miss(name, std::iter::once(Need::Len))
+    }
+  }
+  // page `index` as the running call sees it, or `None` if it needs fetching.
+  // pages past a truncation or past the end of the store are zeros.
+  fn page (&mut self, name: &str, index: u64) -> Option<&[u8]> {
+    let draft = self.drafts.get(name);
+    if let Some(page) = draft.and_then(|d| d.pages.get(&index)) {
+      return Some(page);
+    }
+    let cut = draft.and_then(|d| d.cut).unwrap_or(u64::MAX);
+    let file = self.files.get_mut(name)?;
+    if index*PAGE_SIZE >= cut.min(file.len?) {
+      return Some(ZEROS);
+    }
+    let (page,used) = file.pages.get_mut(&index)?;
+    *used = self.clock;
+    Some(page)
+  }
+  // check that pages `range` are cached, except the ones that `skip` accepts
+  fn check<F> (&mut self, name: &str, range: std::ops::Range<u64>, skip: F)
+  -> Result<(),failure::Error> where F: Fn(u64) -> bool {
+    let missing: Vec<u64> = range
+      .filter(|i| !skip(*i) && self.page(name, *i).is_none())
+      .collect();
+    if missing.is_empty() { return Ok(()) }
+    self.miss(name, missing.into_iter().map(Need::Page))
+  }
+  fn draft (&mut self, name: &str, len: u64) -> &mut Draft {
+    self.drafts.entry(name.to_string())
+      .or_insert_with(|| Draft { len, cut: None, pages: HashMap::new() })
+  }
+  // copy of page `index`, to change it in the draft
+  fn draft_page (&mut self, name: &str, len: u64, index: u64) -> Vec<u8> {
+    if let Some(page) = self.drafts.get_mut(name).and_then(|d| d.pages.remove(&index)) {
+      return page;
+    }
+    let page = self.page(name, index).map(|p| p.to_vec());
+    self.draft(name, len);
+    page.unwrap_or_else(|| ZEROS.to_vec())
+  }
+  fn read (&mut self, name: &str, offset: u64, length: u64)
+  -> Result<Vec<u8>,failure::Error> {
+    let len = self.len(name)?;
+    let end = offset + length;
+    if end > len {
+      failure::bail!["read of {} bytes at {} is out of bounds of {} ({} bytes)",
+        length, offset, name, len];
+    }
+    self.clock += 1;
+    self.check(name, pages(offset, end), |_| false)?;
+    let mut buf = Vec::with_capacity(length as usize);
+    for index in pages(offset, end) {
+      let start = index*PAGE_SIZE;
+      let page = self.page(name, index).expect("checked page");
+      let from = offset.max(start) - start;
+      let to = end.min(start + PAGE_SIZE) - start;
+      buf.extend_from_slice(&page[from as usize..to as usize]);
+    }
+    Ok(buf)
+  }
+  fn write (&mut self, name: &str, offset: u64, data: &[u8])
+  -> Result<(),failure::Error> {
+    let len = self.len(name)?;
+    let end = offset + data.len() as u64;
+    // pages that the write covers don't need their old bytes
+    self.check(name, pages(offset, end), |i| {
+      offset <= i*PAGE_SIZE && (i+1)*PAGE_SIZE <= end
+    })?;
+    for index in pages(offset, end) {
+      let start = index*PAGE_SIZE;
+      let mut page = self.draft_page(name, len, index);
+      let from = offset.max(start);
+      let to = end.min(start + PAGE_SIZE);
+      page[(from-start) as usize..(to-start) as usize]
+        .copy_from_slice(&data[(from-offset) as usize..(to-offset) as usize]);
+      self.draft(name, len).pages.insert(index, page);
+    }
+    let draft = self.draft(name, len);
+    draft.len = draft.len.max(end);
+    self.draft_ops.push((name.to_string(),Op::Write(offset,data.to_vec())));
+    Ok(())
+  }
+  fn truncate (&mut self, name: &str, length: u64) -> Result<(),failure::Error> {
+    let len = self.len(name)?;
+    if length < len {
+      // the page that the store now ends in keeps only its first bytes
+      let index = length / PAGE_SIZE;
+      let keep = (length % PAGE_SIZE) as usize;
+      if keep > 0 {
+        self.check(name, index..index+1, |_| false)?;
+        let mut page = self.draft_page(name, len, index);
+        page[keep..].fill(0);
+        self.draft(name, len).pages.insert(index, page);
+      }
+      let draft = self.draft(name, len);
+      draft.pages.retain(|i,_| i*PAGE_SIZE < length);
+      draft.cut = Some(draft.cut.map_or(length, |cut| cut.min(length)));
+    }
+    self.draft(name, len).len = length;
+    self.draft_ops.push((name.to_string(),Op::Truncate(length)));
+    Ok(())
+  }
+  // move the changes of the call that is done over the cached pages and queue
+  // them for the async stores
+  fn keep (&mut self) {
+    for (name,draft) in self.drafts.drain() {
+      let file = self.files.entry(name).or_default();
+      if let Some(cut) = draft.cut {
+        file.pages.retain(|i,_| (i+1)*PAGE_SIZE <= cut);
+      }
+      file.len = Some(draft.len);
+      for (index,page) in draft.pages {
+        file.pages.insert(index, (page,self.clock));
+      }
+    }
+    self.queue.extend(self.draft_ops.drain(..));
+  }
+  // drop the changes of a call that runs again
+  fn discard (&mut self) {
+    self.drafts.clear();
+    self.draft_ops.clear();
+  }
+  // drop the least recently read pages past `max_bytes`, once the async
+  // stores have every change
+  fn trim (&mut self) {
+    if !self.queue.is_empty() { return }
+    let mut pages: Vec<(u64,String,u64)> = self.files.iter()
+      .flat_map(|(name,file)| {
+        file.pages.iter().map(move |(index,(_,used))| (*used,name.clone(),*index))
+      })
+      .collect();
+    let keep = self.max_bytes / PAGE_SIZE as usize;
+    if pages.len() <= keep { return }
+    pages.sort_unstable();
+    let n = pages.len() - keep;
+    for (_,name,index) in pages.into_iter().take(n) {
+      if let Some(file) = self.files.get_mut(&name) {
+        file.pages.remove(&index);
+      }
+    }
+  }
+}
+
+/// Store that `AsyncDB` runs its database over. Reads come from pages cached
+/// from an `AsyncRandomAccess` store, and writes go to the async store once
+/// the call that made them is done.
+pub struct CachedStore {
+  name: String,
+  cache: Arc<Mutex<Cache>>
+}
+
+impl CachedStore {
+  fn lock (&self) -> Result<std::sync::MutexGuard<'_,Cache>,failure::Error> {
+    self.cache.lock().map_err(|_| failure::err_msg("page cache poisoned"))
+  }
+}
+
+impl RandomAccess for CachedStore {
+  type Error = failure::Error;
+  fn write (&mut self, offset: u64, data: &[u8]) -> Result<(),failure::Error> {
+    self.lock()?.write(&self.name, offset, data)
+  }
+  fn read (&mut self, offset: u64, length: u64) -> Result<Vec<u8>,failure::Error> {
+    self.lock()?.read(&self.name, offset, length)
+  }
+  fn read_to_writer (&mut self, offset: u64, length: u64,
+  buf: &mut impl io::Write) -> Result<(),failure::Error> {
+    Ok(buf.write_all(&self.read(offset, length)?)?)
+  }
+  fn del (&mut self, offset: u64, length: u64) -> Result<(),failure::Error> {
+    let len = self.len()?;
+    if offset >= len { return Ok(()) }
+    self.write(offset, &vec![0u8;(len - offset).min(length) as usize])
+  }
+  fn truncate (&mut self, length: u64) -> Result<(),failure::Error> {
+    self.lock()?.truncate(&self.name, length)
+  }
+  fn len (&self) -> Result<u64,failure::Error> {
+    self.lock()?.len(&self.name)
+  }
+  fn is_empty (&mut self) -> Result<bool,failure::Error> {
+    Ok(self.len()? == 0)
+  }
+  fn sync_all (&mut self) -> Result<(),failure::Error> {
+    self.lock()?.draft_ops.push((self.name.clone(),Op::Sync));
+    Ok(())
+  }
+}
+
+// async stores by name, with the pages cached from them
+struct Stores<A,O> where
+A: AsyncRandomAccess,
+O: Fn(&str) -> StoreFuture<'static,A> {
+  open_store: O,
+  stores: HashMap<String,A>,
+  cache: Arc<Mutex<Cache>>
+}
+
+impl<A,O> Stores<A,O> where
+A: AsyncRandomAccess,
+O: Fn(&str) -> StoreFuture<'static,A> {
+  fn new (open_store: O) -> Self {
+    Self { open_store, stores: HashMap::new(), cache: Arc::default() }
+  }
+  fn lock (&self) -> Result<std::sync::MutexGuard<'_,Cache>,Error> {
+    lock_cache(&self.cache)
+  }
+  // storage function that opens the stores through the cache
+  fn storage (&self) -> CachedStorage {
+    let cache = Arc::clone(&self.cache);
+    Box::new(move |name: &str| {
+      Ok(CachedStore { name: name.to_string(), cache: Arc::clone(&cache) })
+    })
+  }
+  // whether the last call read something that isn't cached
+  fn missed (&self) -> Result<bool,Error> {
+    Ok(!self.lock()?.missing.is_empty())
+  }
+  fn keep (&self) -> Result<(),Error> {
+    self.lock()?.keep();
+    Ok(())
+  }
+  fn discard (&self) -> Result<(),Error> {
+    self.lock()?.discard();
+    Ok(())
+  }
+  fn trim (&self) -> Result<(),Error> {
+    self.lock()?.trim();
+    Ok(())
+  }
+  async fn store (&mut self, name: &str) -> Result<&mut A,Error> {
+    if !self.stores.contains_key(name) {
+      let store = (self.open_store)(name).await?;
+      self.stores.insert(name.to_string(), store);
+    }
+    Ok(self.stores.get_mut(name).expect("opened store"))
+  }
+  // fetch the lengths and pages that the last call missed, reading each run
+  // of consecutive pages at once
+  async fn fetch (&mut self) -> Result<(),Error> {
+    // pages are read from the async stores, so they need the queued changes
+    self.flush().await?;
+    let missing = std::mem::take(&mut self.lock()?.missing);
+    let mut needs: BTreeMap<String,Vec<u64>> = BTreeMap::new();
+    for (name,need) in missing {
+      let pages = needs.entry(name).or_default();
+      if let Need::Page(index) = need { pages.push(index) }
+    }
+    for (name,pages) in needs {
+      let known = self.lock()?.files.get(&name).and_then(|file| file.len);
+      let len = match known {
+        Some(len) => len,
+        None => {
+          let len = self.store(&name).await?.len().await?;
+          self.lock()?.files.entry(name.clone()).or_default().len = Some(len);
+          len
+        }
+      };
+      let mut i = 0;
+      while i < pages.len() {
+        let mut j = i + 1;
+        while j < pages.len() && pages[j] == pages[j-1] + 1 { j += 1 }
+        let start = pages[i]*PAGE_SIZE;
+        let end = ((pages[j-1]+1)*PAGE_SIZE).min(len);
+        if start < end {
+          let buf = self.store(&name).await?.read(start, end - start).await?;
+          let mut cache = self.lock()?;
+          let used = cache.clock;
+          let file = cache.files.entry(name.clone()).or_default();
+          for (k,chunk) in buf.chunks(PAGE_SIZE as usize).enumerate() {
+            let mut page = chunk.to_vec();
+            page.resize(PAGE_SIZE as usize, 0);
+            file.pages.entry(pages[i] + k as u64).or_insert((page,used));
+          }
+        }
+        i = j;
+      }
+    }
+    Ok(())
+  }
+  // write the queued changes to the async stores, in order. a change is only
+  // dropped from the queue once it is done, so a flush that is cancelled
+  // carries on with the next call.
+  async fn flush (&mut self) -> Result<(),Error> {
+    loop {
+      let (name,op) = {
+        let cache = self.lock()?;
+        match cache.queue.front() {
+          Some((name,Op::Write(offset,data))) => (name.clone(),Op::Write(*offset,data.clone())),
+          Some((name,Op::Truncate(len))) => (name.clone(),Op::Truncate(*len)),
+          Some((name,Op::Sync)) => (name.clone(),Op::Sync),
+          None => return Ok(())
+        }
+      };
+      let store = self.store(&name).await?;
+      match op {
+        Op::Write(offset,data) => store.write(offset, &data).await?,
+        Op::Truncate(len) => store.truncate(len).await?,
+        Op::Sync => store.sync_all().await?
+      }
+      self.lock()?.queue.pop_front();
+    }
+  }
+}
@@ -0,0 +1,210 @@
+use crate::{DB,Point,Value};
+use random_access_storage::RandomAccess;
+use std::sync::{Arc,Mutex};
+
+/// Kind of access reported in an `AuditEvent`.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum AuditOp {
+  /// Rows were read by a query.
+  Query,
+  /// Rows were removed by `delete_query()` or `delete_query_filter()`.
+  Delete,
+  /// Rows were read by `nearest()` or `nearest_iter()`.
+  Nearest,
+  /// Rows were read by `top_k()` or `top_k_bounded()`.
+  TopK,
+  /// Rows were counted by `count()` or `aggregate()`.
+  Aggregate,
+  /// Rows were hashed by `query_digest()`.
+  Digest
+}
+
+/// Record of a query or delete passed to the callback set with
+/// `db.set_audit()`.
+#[derive(Debug)]
+pub struct AuditEvent<'a,P> where P: Point {
+  pub op: AuditOp,
+  /// Bounding box that was queried or deleted. Nearest-neighbor searches
+  /// have no bounding box, so they report the bounds of the rows returned.
+  pub bbox: &'a P::Bounds,
+  /// Number of rows returned to the caller, counted, hashed, or removed.
+  pub rows: usize,
+  /// Context set with `db.set_audit_context()` when the access started.
+  pub context: Option<&'a str>
+}
+
+pub(crate) type AuditFn<P> = Arc<dyn Fn(&AuditEvent<P>) + Send + Sync>;
+
+// events held back while a call may still run again, or `None` once they are
+// let go and later events go straight to their callback
+pub(crate) type AuditHold<P> = Arc<Mutex<Option<Vec<Held<P>>>>>;
+
+pub(crate) struct Held<P> where P: Point {
+  callback: AuditFn<P>,
+  op: AuditOp,
+  bbox: P::Bounds,
+  rows: usize,
+  context: Option<String>
+}
+
+// send the event, or hold it back if `hold` is still holding
+fn send<P> (hold: Option<&AuditHold<P>>, event: Held<P>) where P: Point {
+  if let Some(mut held) = hold.and_then(|h| h.lock().ok()) {
+    if let Some(held) = held.as_mut() {
+      held.push(event);
+      return;
+    }
+  }
+  (event.callback)(&AuditEvent {
+    op: event.op,
+    bbox: &event.bbox,
+    rows: event.rows,
+    context: event.context.as_deref()
+  });
+}
+
+// send the events that `hold` held back and every later one right away
+pub(crate) fn release<P> (hold: &AuditHold<P>) where P: Point {
+  let held = hold.lock().ok().and_then(|mut held| held.take());
+  for event in held.into_iter().flatten() {
+    send(None, event);
+  }
+}
+
+// pending event of a lazy query, reported once the query is done or dropped
+pub(crate) struct Audit<P> where P: Point {
+  callback: AuditFn<P>,
+  hold: Option<AuditHold<P>>,
+  op: AuditOp,
+  bbox: Option<P::Bounds>,
+  // rows returned by a search without a bounding box, to report their bounds
+  covered: Vec<P>,
+  context: Option<String>,
+  pub rows: usize
+}
+
+impl<P> Audit<P> where P: Point {
+  pub fn new (callback: AuditFn<P>, hold: Option<AuditHold<P>>, op: AuditOp,
+  bbox: Option<&P::Bounds>, context: Option<String>) -> Self {
+    Self {
+      callback, hold, op, bbox: bbox.copied(), covered: vec![], context, rows: 0
+    }
+  }
+  // count a row returned by a search without a bounding box
+  pub fn cover (&mut self, point: &P) {
+    self.rows += 1;
+    self.covered.push(*point);
+  }
+}
+
+impl<P> Drop for Audit<P> where P: Point {
+  fn drop (&mut self) {
+    let bbox = match self.bbox {
+      Some(bbox) => bbox,
+      None => match P::bounds(&self.covered) {
+        Some(bbox) => bbox,
+        None => return // nothing was returned, so there is no region to report
+      }
+    };
+    send(self.hold.as_ref(), Held {
+      callback: Arc::clone(&self.callback),
+      op: self.op,
+      bbox,
+      rows: self.rows,
+      context: self.context.take()
+    });
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Call `f` with an `AuditEvent` for each query and bbox delete made
+  /// through this handle, replacing any earlier callback.
+  ///
+  /// Queries are read lazily, so their event is sent when the iterator runs
+  /// out or is dropped, with the number of rows the caller actually received.
+  /// `query()`, `query_shared()`, `query_consistent()`, `query_for_each()`,
+  /// `query_resume()`, `scan()`, `query_region()`, `query_projected()`,
+  /// `query_corridor()`, and `within_radius()` are reported as
+  /// `AuditOp::Query`, where corridors send one event per box. `nearest()`
+  /// and `nearest_iter()` are reported as `AuditOp::Nearest` with the bounds
+  /// of the rows returned, and aren't reported if they return no rows.
+  /// `top_k()` and `top_k_bounded()` are reported as `AuditOp::TopK`,
+  /// `count()` and `aggregate()` as `AuditOp::Aggregate` with the number of
+  /// rows counted, and `query_digest()` as `AuditOp::Digest`.
+  /// `delete_query()` and `delete_query_filter()` are reported as
+  /// `AuditOp::Delete`. Deletes by location in `batch()` are not reported:
+  /// the locations come from an audited query.
+  ///
+  /// Whole-database copies and feeds aren't reads of a region and aren't
+  /// reported: `changes()`, `read_outbox()`, views, `backup()`, and
+  /// `export_sharded()` hand out rows without an event, so restrict access
+  /// to them separately.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,AuditEvent,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut db: DB<_,_,(f32,f32),u32> = DB::open(storage)?;
+  /// db.set_audit(|event: &AuditEvent<(f32,f32)>| {
+  ///   eprintln!["{:?} {:?} rows={} by {:?}",
+  ///     event.op, event.bbox, event.rows, event.context];
+  /// });
+  /// db.set_audit_context(Some("user=alice request=42".into()));
+  /// for result in db.query(&((-0.5,-0.8),(0.3,-0.5)))? {
+  ///   let (point,value,location) = result?;
+  ///   // ...
+  /// }
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  ///
+  /// Callbacks hold code, so they aren't persisted: set them each time the
+  /// database is opened.
+  pub fn set_audit<F> (&mut self, f: F) where
+  F: Fn(&AuditEvent<P>) + Send + Sync + 'static {
+    self.audit = Some(Arc::new(f));
+  }
+  /// Stop calling the callback set with `set_audit()`.
+  pub fn clear_audit (&mut self) {
+    self.audit = None;
+  }
+  /// Pass `context`, such as the user or request on whose behalf this handle
+  /// is reading, to the audit callback with every later event.
+  pub fn set_audit_context (&mut self, context: Option<String>) {
+    self.audit_context = context;
+  }
+
+  pub(crate) fn audit_query (&self, bbox: &P::Bounds) -> Option<Audit<P>> {
+    self.audit_read(AuditOp::Query, Some(bbox))
+  }
+
+  // pending event for a read, or for a search without a bounding box when
+  // `bbox` is `None`
+  pub(crate) fn audit_read (&self, op: AuditOp, bbox: Option<&P::Bounds>)
+  -> Option<Audit<P>> {
+    self.audit.as_ref().map(|f| {
+      Audit::new(Arc::clone(f), self.audit_hold(), op, bbox,
+        self.audit_context.clone())
+    })
+  }
+
+  pub(crate) fn audit_event (&self, op: AuditOp, bbox: &P::Bounds, rows: usize) {
+    if let Some(f) = &self.audit {
+      send(self.audit_hold().as_ref(), Held {
+        callback: Arc::clone(f),
+        op,
+        bbox: *bbox,
+        rows,
+        context: self.audit_context.clone()
+      });
+    }
+  }
+}
@@ -0,0 +1,288 @@
+use crate::{DB,Point,Value};
+use crate::lock::Lock;
+use crate::Error;
+use random_access_storage::RandomAccess;
+use std::marker::PhantomData;
+use std::sync::{Arc,Mutex,MutexGuard};
+
+// bytes copied per read, so that large data stores aren't read into memory
+// all at once
+const CHUNK: u64 = 1 << 20;
+
+// stores that are only appended to outside of compaction, history pruning,
+// and repair, so a backup can copy their prefix while writes continue
+const APPEND_ONLY: [&str;5] = ["data","range","changes","changes_index","outbox"];
+
+/// Counts from a `db.backup()`.
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct BackupReport {
+  /// Commit sequence number of the database when it was copied.
+  pub sequence: u64,
+  /// Sequence number of the last change in the feed when the database was
+  /// copied, or `0` without `Setup::changes()`. Pass this to `restore()` as
+  /// `backup` to roll the copy forward later.
+  pub last_change: u64,
+  /// Names of the stores that were copied.
+  pub stores: Vec<String>,
+  /// Bytes copied over all stores.
+  pub bytes: u64
+}
+
+// bytes that in-place writes replaced in the first `len` bytes of the data
+// store, oldest first
+struct BeforeImages {
+  len: u64,
+  writes: Vec<(u64,Vec<u8>)>
+}
+
+/// Record of the in-place writes to the data store while a backup copies it,
+/// shared between the data store and the backup.
+#[derive(Clone,Default)]
+pub(crate) struct Journal(Arc<Mutex<Option<BeforeImages>>>);
+
+impl Journal {
+  fn lock (&self) -> Result<MutexGuard<'_,Option<BeforeImages>>,Error> {
+    self.0.lock().map_err(|_| Error::Other("lock poisoned by a panicked thread".into()))
+  }
+  fn begin (&self, len: u64) -> Result<(),Error> {
+    let mut images = self.lock()?;
+    if images.is_some() { invalid!["a backup is already in progress"] }
+    *images = Some(BeforeImages { len, writes: vec![] });
+    Ok(())
+  }
+  pub(crate) fn is_active (&self) -> Result<bool,Error> {
+    Ok(self.lock()?.is_some())
+  }
+  /// Keep the `n` bytes at `offset` that a write is about to replace, reading
+  /// them with `read`, if a backup is copying them.
+  pub(crate) fn record<F> (&self, offset: u64, n: u64, read: F) -> Result<(),Error>
+  where F: FnOnce(u64,u64) -> Result<Vec<u8>,Error> {
+    let mut images = self.lock()?;
+    if let Some(images) = images.as_mut() {
+      if offset < images.len {
+        let buf = read(offset, n.min(images.len - offset))?;
+        images.writes.push((offset,buf));
+      }
+    }
+    Ok(())
+  }
+}
+
+/// A backup started by `db.begin_backup()` that copies the rest of the
+/// database without the handle.
+///
+/// Call `run()` to finish the copy. Dropping the backup without running it
+/// leaves the destination incomplete.
+pub struct Backup<S,T,W> where
+S: RandomAccess<Error=failure::Error>,
+T: RandomAccess<Error=failure::Error>,
+W: (Fn(&str) -> Result<T,failure::Error>) {
+  dest: W,
+  report: BackupReport,
+  // name, source, and length at the backup of each store left to copy
+  stores: Vec<(String,S,u64)>,
+  journal: Journal,
+  done: bool,
+  _marker: PhantomData<T>
+}
+
+impl<S,T,W> Backup<S,T,W> where
+S: RandomAccess<Error=failure::Error>,
+T: RandomAccess<Error=failure::Error>,
+W: (Fn(&str) -> Result<T,failure::Error>) {
+  /// Copy the stores that were left for later, up to their length when the
+  /// backup began, and put back the bytes that writes replaced since.
+  pub fn run (mut self) -> Result<BackupReport,Error> {
+    for (name,src,len) in self.stores.iter_mut() {
+      let mut dst = (self.dest)(name)?;
+      self.report.bytes += copy_store(src, &mut dst, *len)?;
+    }
+    let mut images = self.journal.lock()?;
+    if let Some(images) = images.as_ref() {
+      if !images.writes.is_empty() {
+        let mut dst = (self.dest)("data")?;
+        for (offset,buf) in images.writes.iter().rev() {
+          dst.write(*offset, buf)?;
+        }
+        dst.sync_all()?;
+      }
+    }
+    *images = None;
+    drop(images);
+    self.done = true;
+    Ok(self.report.clone())
+  }
+}
+
+impl<S,T,W> Drop for Backup<S,T,W> where
+S: RandomAccess<Error=failure::Error>,
+T: RandomAccess<Error=failure::Error>,
+W: (Fn(&str) -> Result<T,failure::Error>) {
+  fn drop (&mut self) {
+    if self.done { return }
+    if let Ok(mut images) = self.journal.lock() { *images = None }
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Copy every store of this database to the stores that `dest` opens, as
+  /// of the last committed batch. This is `begin_backup(dest)?.run()`.
+  ///
+  /// Destination stores are truncated to the length of their source, so
+  /// `dest` can point at an earlier backup. Open the copy with the same
+  /// settings as this database.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// let mut db: DB<_,_,(f32,f32),u32> = DB::open(storage("/tmp/eyros-db/"))?;
+  /// let report = db.backup(storage("/tmp/eyros-backup/"))?;
+  /// eprintln!["copied {} bytes at sequence {}", report.bytes, report.sequence];
+  /// # Ok(()) }
+  /// # fn storage(dir: &'static str)
+  /// # -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   move |name: &str| {
+  /// #     let mut p = PathBuf::from(dir);
+  /// #     p.push(name);
+  /// #     Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// #   }
+  /// # }
+  /// ```
+  pub fn backup<T,W> (&mut self, dest: W) -> Result<BackupReport,Error> where
+  T: RandomAccess<Error=failure::Error>,
+  W: (Fn(&str) -> Result<T,failure::Error>) {
+    self.begin_backup(dest)?.run()
+  }
+
+  /// Start a backup to the stores that `dest` opens, as of the last
+  /// committed batch, and return it to finish without the handle.
+  ///
+  /// Writes that are still buffered, such as staged rows, are committed
+  /// first, the same way `close()` commits them. The stores that batches
+  /// rewrite, such as the meta store, staging, and trees, are copied before
+  /// this returns. The data, range, summary, changes, and outbox stores are
+  /// only appended to, so `run()` copies them up to their length at the
+  /// backup while batches continue on the handle, and puts back the bytes of
+  /// the deletes and updates that rewrote rows in place in the meantime. The
+  /// copy is the database at the backup, whatever is written later.
+  ///
+  /// Compaction and history pruning rewrite whole stores, so `compact()`,
+  /// `prune_history()`, and `repair()` fail with `Error::Invalid` and
+  /// maintenance skips them until the backup is run or dropped. Only one
+  /// backup runs at a time.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Row,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// let mut db: DB<_,_,(f32,f32),u32> = DB::open(storage("/tmp/eyros-db/"))?;
+  /// let backup = db.begin_backup(storage("/tmp/eyros-backup/"))?;
+  /// let copy = std::thread::spawn(move || backup.run());
+  /// db.batch(&[Row::Insert((0.5,0.5),1)])?; // not in the backup
+  /// let report = copy.join().unwrap()?;
+  /// # Ok(()) }
+  /// # fn storage(dir: &'static str)
+  /// # -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   move |name: &str| {
+  /// #     let mut p = PathBuf::from(dir);
+  /// #     p.push(name);
+  /// #     Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// #   }
+  /// # }
+  /// ```
+  pub fn begin_backup<T,W> (&mut self, dest: W) -> Result<Backup<S,T,W>,Error> where
+  T: RandomAccess<Error=failure::Error>,
+  W: (Fn(&str) -> Result<T,failure::Error>) {
+    self.check_open()?;
+    if !self.fields.read_only {
+      let r = self.commit_stores();
+      self.poison_on_err(r)?;
+    }
+    let report = BackupReport {
+      sequence: self.meta.sequence,
+      last_change: self.last_change()?,
+      ..Default::default()
+    };
+    let journal = self.data_store.read_lock()?.journal.clone();
+    journal.begin(self.data_store.read_lock()?.store_bytes()?.0)?;
+    // dropping the backup from here on ends it
+    let mut backup = Backup {
+      dest,
+      report,
+      stores: vec![],
+      journal,
+      done: false,
+      _marker: PhantomData
+    };
+    for name in self.store_names()? {
+      let mut src = (self.open_store)(&name)?;
+      if APPEND_ONLY.contains(&name.as_str()) || name.starts_with("summary_") {
+        let len = src.len()?;
+        backup.stores.push((name.clone(),src,len));
+      } else {
+        let mut dst = (backup.dest)(&name)?;
+        let len = src.len()?;
+        backup.report.bytes += copy_store(&mut src, &mut dst, len)?;
+      }
+      backup.report.stores.push(name);
+    }
+    Ok(backup)
+  }
+
+  // Fail while a backup copies the stores that compaction, history pruning,
+  // and repair rewrite.
+  pub(crate) fn check_backup (&self) -> Result<(),Error> {
+    if self.data_store.read_lock()?.journal.is_active()? {
+      invalid!["a backup is in progress"];
+    }
+    Ok(())
+  }
+
+  fn commit_stores (&mut self) -> Result<(),Error> {
+    self.staging.commit()?;
+    self.data_store.write_lock()?.commit()?;
+    for tree in self.trees.iter() {
+      tree.write_lock()?.commit()?;
+    }
+    Ok(())
+  }
+
+  // Names of every store that this handle has opened.
+  pub(crate) fn store_names (&self) -> Result<Vec<String>,Error> {
+    let mut names: Vec<String> = ["meta","staging_inserts","staging_deletes",
+      "data","range","views"].iter().map(|s| s.to_string()).collect();
+    names.extend((0..self.trees.len()).map(|i| format!["tree{}",i]));
+    names.extend(self.views.iter().map(|v| format!["view_{}",v.name()]));
+    names.extend(self.data_store.read_lock()?.summaries.iter()
+      .map(|s| format!["summary_{}",s.name]));
+    if self.change_log.is_some() {
+      names.push("changes".to_string());
+      names.push("changes_index".to_string());
+    }
+    if self.outbox.is_some() { names.push("outbox".to_string()) }
+    if self.wal.is_some() { names.push("wal".to_string()) }
+    Ok(names)
+  }
+}
+
+// copy the first `len` bytes of `src` over `dst`
+fn copy_store<S,T> (src: &mut S, dst: &mut T, len: u64) -> Result<u64,Error> where
+S: RandomAccess<Error=failure::Error>,
+T: RandomAccess<Error=failure::Error> {
+  let mut offset = 0;
+  while offset < len {
+    let n = CHUNK.min(len - offset);
+    let buf = src.read(offset, n)?;
+    dst.write(offset, &buf)?;
+    offset += n;
+  }
+  if dst.len()? > len { dst.truncate(len)? }
+  dst.sync_all()?;
+  Ok(len)
+}
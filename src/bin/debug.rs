@@ -5,10 +5,11 @@ extern crate random_access_storage;
 use desert::CountBytes;
 
 #[path="../ensure.rs"]
+#[allow(unused_macros)]
 #[macro_use] mod ensure;
 
-use eyros::{Setup,DB};
-use failure::{Error,bail};
+use eyros::{Setup,DB,Lock,Error};
+use failure::bail;
 use random_access_disk::RandomAccessDisk;
 use random_access_storage::RandomAccess;
 use std::path::PathBuf;
@@ -24,7 +25,7 @@ use eyros::Point;
 type P = ((f32,f32),(f32,f32));
 type V = u32;
 
-fn main() -> Result<(),Error> {
+fn main() -> Result<(),failure::Error> {
   let args: Vec<String> = env::args().collect();
   if args.len() < 3 {
     bail!["usage: debug DBPATH COMMAND {...}"];
@@ -37,15 +38,16 @@ fn main() -> Result<(),Error> {
     .branch_factor(5)
     .max_data_size(3_000)
     .base_size(1_000)
+    .lock_file(PathBuf::from(&args[1]).join("lock"))
     .build()?;
   if args[2] == "info" {
-    let mut dstore = db.data_store.try_borrow_mut()?;
+    let mut dstore = db.data_store.write_lock()?;
     println!["# data\n{} bytes", dstore.bytes()?];
     println!["# staging\n{} bytes\n{} records",
       db.staging.bytes()?, db.staging.len()?];
     println!["# trees"];
     for (i,tree) in db.trees.iter().enumerate() {
-      let bytes = tree.try_borrow()?.bytes;
+      let bytes = tree.read_lock()?.bytes;
       if bytes == 0 {
         println!["[{}] empty", i];
       } else {
@@ -85,16 +87,16 @@ fn main() -> Result<(),Error> {
     }
   } else if args[2] == "data" {
     let i = args[3].parse::<u64>()?;
-    let mut dstore = db.data_store.try_borrow_mut()?;
+    let mut dstore = db.data_store.write_lock()?;
     let points = dstore.list(i)?;
     for p in points {
       println!["{:?}", p];
     }
   } else if args[2] == "staging-data" {
-    for pv in db.staging.inserts.try_borrow()?.iter() {
+    for pv in db.staging.inserts.read_lock()?.iter() {
       println!["{:?}", pv];
     }
-    for loc in db.staging.deletes.try_borrow()?.iter() {
+    for loc in db.staging.deletes.read_lock()?.iter() {
       println!["{:?} [DELETE]", loc];
     }
   } else if args[2] == "time-query" {
@@ -111,7 +113,8 @@ fn main() -> Result<(),Error> {
     println!["{} results in {} seconds", results.len(), elapsed];
   } else if args[2] == "branches" {
     let i = args[3].parse::<usize>()?;
-    let mut queue = vec![(0,0)];
+    let root = db.trees[i].write_lock()?.root()?;
+    let mut queue = vec![(root,0)];
     while !queue.is_empty() {
       let (offset,depth) = queue.pop().unwrap();
       let b = read_branch(&mut db, i, offset, depth)?;
@@ -139,11 +142,12 @@ pub struct Branch {
 }
 
 fn read_branch<S,U> (db: &mut DB<S,U,P,V>, tree_i: usize,
-offset: u64, depth: usize) -> Result<Branch,Error>
-where S: RandomAccess<Error=Error>, U: (Fn(&str) -> Result<S,Error>) {
-  let len = db.trees[tree_i].try_borrow()?.store.len()? as u64;
+offset: u64, depth: usize) -> Result<Branch,failure::Error>
+where S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>) {
+  let len = db.trees[tree_i].read_lock()?.store.len()? as u64;
   let buf = read_block(
-    &mut db.trees[tree_i].try_borrow_mut()?.store, offset, len, 1024
+    &mut db.trees[tree_i].write_lock()?.store, offset, len, 1024
   )?;
   let bf = db.fields.branch_factor;
   let n = bf*2-3;
@@ -0,0 +1,345 @@
+extern crate eyros;
+extern crate failure;
+extern crate random_access_disk;
+
+use eyros::{Setup,DB,Row,Point,Value};
+use failure::{Error,bail,format_err};
+use random_access_disk::RandomAccessDisk;
+use random_access_storage::RandomAccess;
+use std::env;
+use std::fs::File;
+use std::io::{self,BufRead,BufReader,BufWriter,Write};
+use std::path::{Path,PathBuf};
+use std::process;
+
+const USAGE: &str = "usage: eyros COMMAND DBPATH [ARGS] [OPTIONS]
+
+commands:
+  info                  print the sequence number, trees, and disk usage
+  query BBOX            print the rows that intersect BBOX as csv
+  dump                  print every row as csv
+  import-csv FILE       insert the rows of a csv file, or stdin for -
+  verify                read every tree and data block and report damage
+  repair                quarantine damaged data blocks and rebuild the range
+                        store
+
+BBOX lists the minimum coordinates and then the maximum coordinates,
+separated by commas: minx,miny,maxx,maxy for 2 dimensions. Csv rows list
+the coordinates of a point followed by its value.
+
+options:
+  --point TYPE          f32x2 (default), f32x3, f64x2, or f64x3
+  --value TYPE          u32 (default), u64, or text
+  --batch-size N        rows to insert per batch in import-csv (100000)
+  --skip-header         skip the first line of the csv file in import-csv";
+
+struct Options {
+  command: String,
+  path: PathBuf,
+  args: Vec<String>,
+  point: String,
+  value: String,
+  batch_size: usize,
+  skip_header: bool
+}
+
+impl Options {
+  fn parse (args: Vec<String>) -> Result<Self,Error> {
+    let mut positional = vec![];
+    let mut point = "f32x2".to_string();
+    let mut value = "u32".to_string();
+    let mut batch_size = 100_000;
+    let mut skip_header = false;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+      let mut next = || args.next().ok_or_else(|| format_err!["{} needs a value", arg]);
+      match arg.as_str() {
+        "--point" => point = next()?,
+        "--value" => value = next()?,
+        "--batch-size" => batch_size = next()?.parse()?,
+        "--skip-header" => skip_header = true,
+        "-h" | "--help" => bail!["{}", USAGE],
+        _ if arg.starts_with("--") => bail!["unknown option {}\n\n{}", arg, USAGE],
+        _ => positional.push(arg)
+      }
+    }
+    if positional.len() < 2 {
+      bail!["{}", USAGE];
+    }
+    if batch_size == 0 {
+      bail!["--batch-size must be at least 1"];
+    }
+    let command = positional.remove(0);
+    let path = PathBuf::from(positional.remove(0));
+    Ok(Self { command, path, args: positional, point, value, batch_size, skip_header })
+  }
+}
+
+/// Points that can be read from and written as a list of numbers.
+trait CliPoint: Point {
+  const DIMS: usize;
+  fn from_coords (coords: &[f64]) -> Self;
+  fn bounds_from_coords (coords: &[f64]) -> Self::Bounds;
+  /// Bounds that every point intersects.
+  fn everywhere () -> Self::Bounds;
+  fn format (&self) -> Vec<String>;
+}
+
+macro_rules! impl_cli_point {
+  ($P:ty, $T:ident, $dims:expr, $($i:tt),+) => {
+    impl CliPoint for $P {
+      const DIMS: usize = $dims;
+      fn from_coords (coords: &[f64]) -> Self {
+        ($(coords[$i] as $T,)+)
+      }
+      fn bounds_from_coords (coords: &[f64]) -> Self::Bounds {
+        (($(coords[$i] as $T,)+),($(coords[$i+$dims] as $T,)+))
+      }
+      fn everywhere () -> Self::Bounds {
+        (($({ let _ = $i; $T::NEG_INFINITY },)+),($({ let _ = $i; $T::INFINITY },)+))
+      }
+      fn format (&self) -> Vec<String> {
+        vec![$(self.$i.to_string()),+]
+      }
+    }
+  }
+}
+
+impl_cli_point!((f32,f32), f32, 2, 0, 1);
+impl_cli_point!((f32,f32,f32), f32, 3, 0, 1, 2);
+impl_cli_point!((f64,f64), f64, 2, 0, 1);
+impl_cli_point!((f64,f64,f64), f64, 3, 0, 1, 2);
+
+/// Values that can be read from and written as a csv field.
+trait CliValue: Value {
+  fn parse (field: &str) -> Result<Self,Error>;
+  fn format (&self) -> String;
+}
+
+impl CliValue for u32 {
+  fn parse (field: &str) -> Result<Self,Error> { Ok(field.trim().parse()?) }
+  fn format (&self) -> String { self.to_string() }
+}
+
+impl CliValue for u64 {
+  fn parse (field: &str) -> Result<Self,Error> { Ok(field.trim().parse()?) }
+  fn format (&self) -> String { self.to_string() }
+}
+
+// text is stored as its utf-8 bytes
+impl CliValue for Vec<u8> {
+  fn parse (field: &str) -> Result<Self,Error> { Ok(field.as_bytes().to_vec()) }
+  fn format (&self) -> String {
+    let s = String::from_utf8_lossy(self);
+    if s.contains(&[',', '"', '\n', '\r'][..]) {
+      format!["\"{}\"", s.replace('"', "\"\"")]
+    } else {
+      s.into_owned()
+    }
+  }
+}
+
+// split a csv line into fields, unquoting fields in double quotes
+fn split_csv (line: &str) -> Result<Vec<String>,Error> {
+  let mut fields = vec![];
+  let mut field = String::new();
+  let mut chars = line.chars().peekable();
+  let mut quoted = false;
+  while let Some(c) = chars.next() {
+    match c {
+      '"' if quoted && chars.peek() == Some(&'"') => { chars.next(); field.push('"') },
+      '"' if quoted => quoted = false,
+      '"' if field.is_empty() => quoted = true,
+      ',' if !quoted => fields.push(std::mem::take(&mut field)),
+      _ => field.push(c)
+    }
+  }
+  if quoted {
+    bail!["unterminated quote"];
+  }
+  fields.push(field);
+  Ok(fields)
+}
+
+fn parse_coords (fields: &[String]) -> Result<Vec<f64>,Error> {
+  fields.iter().map(|f| {
+    f.trim().parse::<f64>().map_err(|_| format_err!["invalid coordinate {:?}", f])
+  }).collect()
+}
+
+fn main() {
+  if let Err(err) = run(env::args().skip(1).collect()) {
+    // output piped into a command like head that exits early
+    let broken_pipe = err.downcast_ref::<io::Error>()
+      .map(|e| e.kind() == io::ErrorKind::BrokenPipe).unwrap_or(false);
+    if broken_pipe { return }
+    eprintln!["{}", err];
+    process::exit(1);
+  }
+}
+
+fn run (args: Vec<String>) -> Result<(),Error> {
+  let opts = Options::parse(args)?;
+  match opts.point.as_str() {
+    "f32x2" => with_value::<(f32,f32)>(&opts),
+    "f32x3" => with_value::<(f32,f32,f32)>(&opts),
+    "f64x2" => with_value::<(f64,f64)>(&opts),
+    "f64x3" => with_value::<(f64,f64,f64)>(&opts),
+    t => bail!["unknown point type {}", t]
+  }
+}
+
+fn with_value<P> (opts: &Options) -> Result<(),Error> where P: CliPoint {
+  match opts.value.as_str() {
+    "u32" => command::<P,u32>(opts),
+    "u64" => command::<P,u64>(opts),
+    "text" => command::<P,Vec<u8>>(opts),
+    t => bail!["unknown value type {}", t]
+  }
+}
+
+fn command<P,V> (opts: &Options) -> Result<(),Error> where P: CliPoint, V: CliValue {
+  let writable = opts.command == "import-csv" || opts.command == "repair";
+  if opts.command != "import-csv" && !opts.path.is_dir() {
+    bail!["no database at {}", opts.path.display()];
+  }
+  if writable {
+    std::fs::create_dir_all(&opts.path)?;
+  }
+  let path = opts.path.clone();
+  let mut db: DB<_,_,P,V> = Setup::new(move |name: &str| {
+    RandomAccessDisk::open(path.join(name))
+  }).read_only(!writable).lock_file(opts.path.join("lock")).build()?;
+  let stdout = io::stdout();
+  let mut out = BufWriter::new(stdout.lock());
+  match (opts.command.as_str(), opts.args.as_slice()) {
+    ("info", []) => info(&mut db, &mut out)?,
+    ("query", [bbox]) => {
+      let fields = split_csv(bbox)?;
+      if fields.len() != P::DIMS*2 {
+        bail!["BBOX needs {} coordinates, found {}", P::DIMS*2, fields.len()];
+      }
+      let bbox = P::bounds_from_coords(&parse_coords(&fields)?);
+      print_rows(&mut db, &bbox, &mut out)?;
+    },
+    ("dump", []) => print_rows(&mut db, &P::everywhere(), &mut out)?,
+    ("import-csv", [file]) => {
+      let n = if file == "-" {
+        import_csv(&mut db, io::stdin().lock(), opts)?
+      } else {
+        import_csv(&mut db, BufReader::new(File::open(Path::new(file))?), opts)?
+      };
+      eprintln!["imported {} rows", n];
+    },
+    ("verify", []) => verify(&mut db, &mut out)?,
+    ("repair", []) => repair(&mut db, &mut out)?,
+    _ => bail!["{}", USAGE]
+  }
+  out.flush()?;
+  Ok(())
+}
+
+fn info<S,U,P,V,W> (db: &mut DB<S,U,P,V>, out: &mut W) -> Result<(),Error> where
+S: RandomAccess<Error=Error>, U: (Fn(&str) -> Result<S,Error>),
+P: CliPoint, V: CliValue, W: Write {
+  let usage = db.disk_usage()?;
+  writeln![out, "sequence {}", db.sequence()]?;
+  writeln![out, "bytes {}", usage.total()]?;
+  writeln![out, "staging {} bytes of inserts, {} bytes of deletes",
+    usage.staging_inserts, usage.staging_deletes]?;
+  writeln![out, "data {} bytes live, {} bytes dead", usage.data_live, usage.data_dead]?;
+  for t in db.tree_stats()? {
+    writeln![out, "tree {}: depth {}, {} data blocks, {} of {} rows live, {} bytes{}",
+      t.index, t.depth, t.data_blocks, t.live_rows, t.rows, t.bytes(),
+      if t.frozen { ", frozen" } else { "" }]?;
+  }
+  let quarantined = db.quarantined()?;
+  if !quarantined.is_empty() {
+    writeln![out, "quarantined blocks {:?}", quarantined]?;
+  }
+  Ok(())
+}
+
+fn print_rows<S,U,P,V,W> (db: &mut DB<S,U,P,V>, bbox: &P::Bounds, out: &mut W)
+-> Result<(),Error> where
+S: RandomAccess<Error=Error>, U: (Fn(&str) -> Result<S,Error>),
+P: CliPoint, V: CliValue, W: Write {
+  for result in db.scan(bbox)? {
+    let (point,value,_) = result?;
+    writeln![out, "{},{}", point.format().join(","), value.format()]?;
+  }
+  Ok(())
+}
+
+fn import_csv<S,U,P,V,R> (db: &mut DB<S,U,P,V>, reader: R, opts: &Options)
+-> Result<u64,Error> where
+S: RandomAccess<Error=Error>, U: (Fn(&str) -> Result<S,Error>),
+P: CliPoint, V: CliValue, R: BufRead {
+  let mut batch: Vec<Row<P,V>> = Vec::with_capacity(opts.batch_size);
+  let mut n = 0;
+  for (i,line) in reader.lines().enumerate() {
+    let line = line?;
+    if (i == 0 && opts.skip_header) || line.trim().is_empty() { continue }
+    let fields = split_csv(&line).map_err(|e| format_err!["line {}: {}", i+1, e])?;
+    if fields.len() != P::DIMS + 1 {
+      bail!["line {}: expected {} coordinates and a value, found {} fields",
+        i+1, P::DIMS, fields.len()];
+    }
+    let coords = parse_coords(&fields[..P::DIMS])
+      .map_err(|e| format_err!["line {}: {}", i+1, e])?;
+    let value = V::parse(&fields[P::DIMS])
+      .map_err(|e| format_err!["line {}: invalid value: {}", i+1, e])?;
+    batch.push(Row::Insert(P::from_coords(&coords), value));
+    if batch.len() >= opts.batch_size {
+      db.batch(&batch)?;
+      n += batch.len() as u64;
+      batch.clear();
+    }
+  }
+  if !batch.is_empty() {
+    db.batch(&batch)?;
+    n += batch.len() as u64;
+  }
+  db.close()?;
+  Ok(n)
+}
+
+fn verify<S,U,P,V,W> (db: &mut DB<S,U,P,V>, out: &mut W) -> Result<(),Error> where
+S: RandomAccess<Error=Error>, U: (Fn(&str) -> Result<S,Error>),
+P: CliPoint, V: CliValue, W: Write {
+  let report = db.check()?;
+  let mut rows = 0u64;
+  for result in db.scan(&P::everywhere())? {
+    result?;
+    rows += 1;
+  }
+  writeln![out, "{} trees, {} data blocks, {} rows readable",
+    report.trees, report.blocks, rows]?;
+  for problem in report.problems.iter() {
+    writeln![out, "{}", problem]?;
+  }
+  if !report.quarantined.is_empty() {
+    writeln![out, "quarantined blocks {:?}", report.quarantined]?;
+  }
+  if !report.is_ok() || !report.quarantined.is_empty() {
+    out.flush()?;
+    bail!["{} problems and {} quarantined data blocks found",
+      report.problems.len(), report.quarantined.len()];
+  }
+  writeln![out, "ok"]?;
+  Ok(())
+}
+
+fn repair<S,U,P,V,W> (db: &mut DB<S,U,P,V>, out: &mut W) -> Result<(),Error> where
+S: RandomAccess<Error=Error>, U: (Fn(&str) -> Result<S,Error>),
+P: CliPoint, V: CliValue, W: Write {
+  let report = db.repair()?;
+  for problem in report.problems.iter() {
+    writeln![out, "{}", problem]?;
+  }
+  let quarantined = db.quarantined()?.len();
+  db.close()?;
+  writeln![out, "{} problems found, {} quarantined data blocks",
+    report.problems.len(), quarantined]?;
+  Ok(())
+}
@@ -2,11 +2,14 @@ use crate::{data::DataBatch,point::Point,Value,pivots};
 use crate::order::{order,order_len};
 use std::cmp::Ordering;
 use std::mem::size_of;
-use std::rc::Rc;
-use std::cell::RefCell;
-use failure::{Error,bail,format_err};
+use std::sync::{Arc,RwLock};
+use crate::lock::Lock;
+use crate::Error;
 use desert::ToBytes;
 
+// bytes of a built branch and the nodes that it points to
+type Built<D,P,V> = (Vec<u8>,Vec<Node<D,P,V>>);
+
 #[derive(Clone)]
 pub enum Node<D,P,V> where D: DataBatch<P,V>, P: Point, V: Value {
   Empty,
@@ -18,7 +21,7 @@ pub enum Node<D,P,V> where D: DataBatch<P,V>, P: Point, V: Value {
 pub struct Data<P,V> where P: Point, V: Value {
   pub offset: u64,
   bucket: Vec<usize>,
-  rows: Rc<Vec<((P,V),u64)>>
+  rows: Arc<Vec<((P,V),u64)>>
 }
 
 #[derive(Clone)]
@@ -28,10 +31,10 @@ pub struct Branch<D,P,V> where D: DataBatch<P,V>, P: Point, V: Value {
   pub index: usize,
   branch_factor: usize,
   max_data_size: usize,
-  data_batch: Rc<RefCell<D>>,
+  data_batch: Arc<RwLock<D>>,
   bucket: Vec<usize>,
   buckets: Vec<Vec<usize>>,
-  rows: Rc<Vec<((P,V),u64)>>,
+  rows: Arc<Vec<((P,V),u64)>>,
   pivots: Vec<P>,
   sorted: Vec<usize>,
   intersecting: Vec<Vec<usize>>,
@@ -40,12 +43,12 @@ pub struct Branch<D,P,V> where D: DataBatch<P,V>, P: Point, V: Value {
 
 impl<D,P,V> Branch<D,P,V> where D: DataBatch<P,V>, P: Point, V: Value {
   pub fn new (level: usize, index: usize, max_data_size: usize, bf: usize,
-  data_batch: Rc<RefCell<D>>, bucket: Vec<usize>, rows: Rc<Vec<((P,V),u64)>>)
+  data_batch: Arc<RwLock<D>>, bucket: Vec<usize>, rows: Arc<Vec<((P,V),u64)>>)
   -> Result<Self,Error> {
     let n = order_len(bf);
     let mut sorted: Vec<usize> = (0..bucket.len()).collect();
     sorted.sort_unstable_by(|a,b| {
-      (rows[bucket[*a]].0).0.cmp_at(&(rows[bucket[*b]].0).0, level)
+      (rows[bucket[*a]].0).0.cmp_upper_at(&(rows[bucket[*b]].0).0, level)
     });
     let mut pivots: Vec<P> =
       if sorted.len() == 2 {
@@ -67,11 +70,11 @@ impl<D,P,V> Branch<D,P,V> where D: DataBatch<P,V>, P: Point, V: Value {
       a.cmp_at(b, level)
     });
     if pivots.is_empty() {
-      bail!["empty set of pivots"]
+      return Err(Error::Other("empty set of pivots".into()))
     } else if pivots.len() == 1 {
       pivots = vec![pivots[0],pivots[0]];
       /*
-      bail!["not enough data to pad pivots. need at least 2, found {}",
+      invalid!["not enough data to pad pivots. need at least 2, found {}",
         pivots.len()];
       */
     } else {
@@ -122,7 +125,7 @@ impl<D,P,V> Branch<D,P,V> where D: DataBatch<P,V>, P: Point, V: Value {
     4 + pivot_size + bitfield_size + intersect_size + bucket_size
   }
   pub fn build (&mut self, alloc: &mut dyn FnMut (usize) -> u64)
-  -> Result<(Vec<u8>,Vec<Node<D,P,V>>),Error> {
+  -> Result<Built<D,P,V>,Error> {
     let n = order_len(self.branch_factor);
     let bf = self.branch_factor;
     for k in 0..n {
@@ -146,7 +149,7 @@ impl<D,P,V> Branch<D,P,V> where D: DataBatch<P,V>, P: Point, V: Value {
         match (row.0).0.cmp_at(&pivot, self.level) {
           Ordering::Less => { break },
           Ordering::Greater => j += 1,
-          Ordering::Equal => bail!["bucket interval intersects pivot"]
+          Ordering::Equal => return Err(Error::Other("bucket interval intersects pivot".into()))
         }
       }
       self.buckets[j].push(self.bucket[*i]);
@@ -164,7 +167,7 @@ impl<D,P,V> Branch<D,P,V> where D: DataBatch<P,V>, P: Point, V: Value {
           nodes.push(Node::Empty);
           bitfield.push(false);
         } else if size as usize <= self.max_data_size {
-          let mut dstore = self.data_batch.try_borrow_mut()?;
+          let mut dstore = self.data_batch.write_lock()?;
           let offset = dstore.batch(&bucket.iter().map(|b| {
             &self.rows[*b].0
           }).collect())?;
@@ -176,8 +179,8 @@ impl<D,P,V> Branch<D,P,V> where D: DataBatch<P,V>, P: Point, V: Value {
             self.index,
             self.max_data_size,
             self.branch_factor,
-            Rc::clone(&self.data_batch),
-            bucket.clone(), Rc::clone(&self.rows)
+            Arc::clone(&self.data_batch),
+            bucket.clone(), Arc::clone(&self.rows)
           )?;
           b.alloc(alloc);
           nodes.push(Node::Branch(b));
@@ -0,0 +1,193 @@
+use crate::{DB,Point,Value};
+use crate::data::DataBatch;
+use crate::lock::Lock;
+use crate::Error;
+use random_access_storage::RandomAccess;
+
+/// Counts from a `BulkLoader::finish()`.
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct BulkReport {
+  /// Rows written.
+  pub rows: u64,
+  /// Data blocks written.
+  pub blocks: u64,
+  /// Slot of the tree that holds the rows, or `None` if there were no rows.
+  pub tree: Option<usize>
+}
+
+/// Writer for the initial ingest of a large number of rows, which packs them
+/// into data blocks and builds one tree over the blocks instead of moving the
+/// rows through the staging area and merging trees batch after batch.
+///
+/// Rows are collected in memory until `finish()`. They are then ordered with
+/// sort-tile-recursive packing: sorted along the first axis, cut into slabs,
+/// each slab sorted along the next axis, and so on, so that every data block
+/// of `max_data_size` rows covers a compact region. Merges write the new rows
+/// of a batch into blocks in the order they arrived, so queries over a bulk
+/// loaded tree read far fewer blocks. The tree goes into the smallest free
+/// slot that holds every row and is committed with one meta update.
+///
+/// Bulk loaded rows don't pass through `batch()`, so triggers don't fire for
+/// them and the write-ahead log isn't used. Point types without
+/// `Point::bounds_axes()` are packed in the order they were added.
+///
+/// ```rust,no_run
+/// use eyros::{DB,BulkLoader,Error};
+/// # use std::path::PathBuf;
+/// # use random_access_disk::RandomAccessDisk;
+/// # fn main () -> Result<(),Error> {
+/// let mut db: DB<_,_,(f32,f32),u32> = DB::open(storage)?;
+/// let mut loader = BulkLoader::new(&mut db)?;
+/// for i in 0..1_000_000 {
+///   let x = (i % 1000) as f32 / 1000.0;
+///   let y = (i / 1000) as f32 / 1000.0;
+///   loader.push((x,y), i);
+/// }
+/// let report = loader.finish()?;
+/// assert_eq![report.rows, 1_000_000];
+/// # Ok(()) }
+/// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+/// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+/// #   p.push(name);
+/// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+/// # }
+/// ```
+pub struct BulkLoader<'a,S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  db: &'a mut DB<S,U,P,V>,
+  rows: Vec<(P,V)>
+}
+
+impl<'a,S,U,P,V> BulkLoader<'a,S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Start a bulk load into `db`.
+  ///
+  /// Fails if `db` has views or the changes feed enabled, since bulk loaded
+  /// rows would be missing from both.
+  pub fn new (db: &'a mut DB<S,U,P,V>) -> Result<Self,Error> {
+    db.check_writable()?;
+    if !db.views.is_empty() {
+      invalid!["cannot bulk load into a database with views"];
+    }
+    if db.change_log.is_some() {
+      invalid!["cannot bulk load with the changes feed enabled"];
+    }
+    Ok(Self { db, rows: vec![] })
+  }
+  /// Add a row.
+  pub fn push (&mut self, point: P, value: V) {
+    self.rows.push((point,value));
+  }
+  /// Add every row of `rows`.
+  pub fn extend<I> (&mut self, rows: I) where I: IntoIterator<Item=(P,V)> {
+    self.rows.extend(rows);
+  }
+  /// Number of rows added so far.
+  pub fn len (&self) -> usize {
+    self.rows.len()
+  }
+  /// Whether no rows have been added.
+  pub fn is_empty (&self) -> bool {
+    self.rows.is_empty()
+  }
+  /// Write the rows and commit them. Nothing is written if the loader is
+  /// dropped without calling this.
+  pub fn finish (self) -> Result<BulkReport,Error> {
+    let BulkLoader { db, rows } = self;
+    db.check_writable()?;
+    if rows.is_empty() { return Ok(BulkReport::default()) }
+    if db.fields.check_conflicts {
+      db.check_sequence()?;
+    }
+    let r = db.bulk_write(rows);
+    db.poison_on_err(r)
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  fn bulk_write (&mut self, mut rows: Vec<(P,V)>) -> Result<BulkReport,Error> {
+    let m = self.fields.max_data_size.max(1);
+    pack(&mut rows, m);
+    let mut blocks = Vec::with_capacity(rows.len().div_ceil(m));
+    {
+      let mut dstore = self.data_store.write_lock()?;
+      for chunk in rows.chunks(m) {
+        let offset = dstore.batch(&chunk.iter().collect())?;
+        match P::bounds(&chunk.iter().map(|(p,_)| *p).collect()) {
+          None => corrupt!["invalid data at offset {}", offset],
+          Some(bbox) => blocks.push((bbox,offset,chunk.len() as u64))
+        }
+      }
+      dstore.commit()?;
+    }
+    // the smallest free slot whose size class holds every row
+    let base = self.fields.base_size.max(1) as u64;
+    let n = rows.len() as u64;
+    let mut slot = 0;
+    while (base << slot) < n || self.meta.mask.get(slot) == Some(&true) {
+      slot += 1;
+    }
+    let report = BulkReport {
+      rows: n,
+      blocks: blocks.len() as u64,
+      tree: Some(slot)
+    };
+    self.create_tree(slot)?;
+    self.trees[slot].write_lock()?.build_from_blocks(blocks)?;
+    for _ in self.meta.mask.len()..slot+1 {
+      self.meta.mask.push(false);
+    }
+    self.meta.mask[slot] = true;
+    let mut merged = vec![];
+    if let Some(max) = self.fields.max_trees {
+      merged = self.consolidate(max, &[])?;
+    }
+    self.commit_meta()?;
+    for t in merged {
+      self.trees[t].write_lock()?.clear()?;
+    }
+    Ok(report)
+  }
+}
+
+// Order `rows` with sort-tile-recursive packing so that each run of `m` rows
+// covers a compact region.
+fn pack<P,V> (rows: &mut Vec<(P,V)>, m: usize) where P: Point, V: Value {
+  let dim = P::dim();
+  let mut centers = Vec::with_capacity(rows.len()*dim);
+  let mut one = Vec::with_capacity(1);
+  for (p,_) in rows.iter() {
+    one.clear();
+    one.push(*p);
+    let axes = match P::bounds(&one).and_then(|b| P::bounds_axes(&b)) {
+      Some(axes) => axes,
+      None => return
+    };
+    centers.extend(axes.iter().map(|(min,max)| (min+max)/2.0));
+  }
+  let mut order: Vec<usize> = (0..rows.len()).collect();
+  tile(&mut order, &centers, dim, 0, m);
+  let mut taken: Vec<Option<(P,V)>> = rows.drain(..).map(Some).collect();
+  rows.extend(order.iter().filter_map(|i| taken[*i].take()));
+}
+
+fn tile (order: &mut [usize], centers: &[f64], dim: usize, axis: usize, m: usize) {
+  order.sort_unstable_by(|a,b| {
+    centers[a*dim+axis].total_cmp(&centers[b*dim+axis])
+  });
+  if axis+1 >= dim { return }
+  let blocks = order.len().div_ceil(m);
+  let slabs = (blocks as f64).powf(1.0/((dim-axis) as f64)).ceil().max(1.0) as usize;
+  // slabs hold whole blocks, so that no block spans two slabs
+  let per = blocks.div_ceil(slabs) * m;
+  for slab in order.chunks_mut(per) {
+    tile(slab, centers, dim, axis+1, m);
+  }
+}
@@ -0,0 +1,305 @@
+use crate::{Point,Value,Location};
+use lru::LruCache;
+use std::any::Any;
+use std::hash::Hash;
+use std::mem::size_of;
+use std::sync::{Arc,Mutex,MutexGuard};
+
+pub(crate) type Rows<P,V> = Arc<[(P,V,Location)]>;
+
+// estimated bookkeeping per cached block: the lru node, hash slot, and arc
+const ENTRY_OVERHEAD: usize = 64;
+
+/// Least recently used cache of parsed data blocks, bounded by a number of
+/// blocks and optionally by an estimate of the memory the rows take up.
+///
+/// A block costs the inline size of its rows plus the bytes each value holds
+/// beyond its inline size, as reported by `CountBytes`. For values such as
+/// `Vec<u8>` this counts the encoded length of the contents, which is close
+/// to their heap size.
+///
+/// The blocks are either kept in the cache itself or, for the collections of
+/// a database, in a `SharedBlocks` cache under a namespace of their own.
+pub(crate) enum BlockCache<P,V> where P: Point, V: Value {
+  Local(Lru<u64,Rows<P,V>>),
+  Shared(SharedSlot<P,V>)
+}
+
+impl<P,V> BlockCache<P,V> where P: Point, V: Value {
+  pub fn new (cap: usize) -> Self {
+    BlockCache::Local(Lru::new(cap))
+  }
+  /// Evict blocks whenever the estimated size of the cache passes `max`.
+  pub fn set_max_bytes (&mut self, max: Option<usize>) {
+    match self {
+      BlockCache::Local(lru) => lru.set_max_bytes(max),
+      BlockCache::Shared(slot) => slot.lock().lru.set_max_bytes(max)
+    }
+  }
+  pub fn get (&mut self, offset: &u64) -> Option<Rows<P,V>> {
+    match self {
+      BlockCache::Local(lru) => lru.get(offset).cloned(),
+      BlockCache::Shared(slot) => {
+        let key = (slot.ns,*offset);
+        slot.lock().lru.get(&key).and_then(|rows| (slot.downcast)(rows))
+      }
+    }
+  }
+  pub fn peek (&self, offset: &u64) -> Option<Rows<P,V>> {
+    match self {
+      BlockCache::Local(lru) => lru.peek(offset).cloned(),
+      BlockCache::Shared(slot) => {
+        let key = (slot.ns,*offset);
+        slot.lock().lru.peek(&key).and_then(|rows| (slot.downcast)(rows))
+      }
+    }
+  }
+  pub fn put (&mut self, offset: u64, rows: Rows<P,V>) {
+    let size = Self::size(&rows);
+    match self {
+      BlockCache::Local(lru) => lru.put(offset, rows, size),
+      BlockCache::Shared(slot) => {
+        let erased = (slot.erase)(rows);
+        slot.lock().lru.put((slot.ns,offset), erased, size)
+      }
+    }
+  }
+  /// Replace the cached rows of the block at `offset` with `f(rows)`, if the
+  /// block is cached.
+  pub fn update<F> (&mut self, offset: &u64, f: F)
+  where F: FnOnce(&Rows<P,V>) -> Rows<P,V> {
+    let rows = match self.peek(offset) {
+      Some(rows) => f(&rows),
+      None => return
+    };
+    let size = Self::size(&rows);
+    match self {
+      BlockCache::Local(lru) => lru.update(offset, rows, size),
+      BlockCache::Shared(slot) => {
+        let erased = (slot.erase)(rows);
+        slot.lock().lru.update(&(slot.ns,*offset), erased, size)
+      }
+    }
+  }
+  pub fn pop (&mut self, offset: &u64) -> Option<Rows<P,V>> {
+    match self {
+      BlockCache::Local(lru) => lru.pop(offset),
+      BlockCache::Shared(slot) => {
+        let key = (slot.ns,*offset);
+        slot.lock().lru.pop(&key).and_then(|rows| (slot.downcast)(&rows))
+      }
+    }
+  }
+  pub fn clear (&mut self) {
+    match self {
+      BlockCache::Local(lru) => lru.clear(),
+      BlockCache::Shared(slot) => {
+        let ns = slot.ns;
+        let mut shared = slot.lock();
+        let keys: Vec<(u32,u64)> = shared.lru.lru.iter()
+          .map(|(k,_)| *k)
+          .filter(|(n,_)| *n == ns)
+          .collect();
+        for key in keys {
+          shared.lru.pop(&key);
+        }
+      }
+    }
+  }
+  pub fn len (&self) -> usize {
+    match self {
+      BlockCache::Local(lru) => lru.len(),
+      BlockCache::Shared(slot) => slot.lock().usage(slot.ns).0
+    }
+  }
+  /// Estimated memory held by the cached blocks.
+  pub fn bytes (&self) -> usize {
+    match self {
+      BlockCache::Local(lru) => lru.bytes,
+      BlockCache::Shared(slot) => slot.lock().usage(slot.ns).1
+    }
+  }
+  /// Move this cache into `shared` under a new namespace, keeping the blocks
+  /// it holds. The shared cache keeps its own bounds.
+  pub fn share (&mut self, shared: &SharedCache) where
+  P: Send+Sync+'static, V: Send+Sync {
+    let slot = SharedSlot::new(shared);
+    if let BlockCache::Local(lru) = self {
+      // oldest first, so that the most recently used blocks stay the newest
+      let blocks: Vec<(u64,Rows<P,V>,usize)> = lru.lru.iter()
+        .map(|(k,(rows,size))| (*k,Arc::clone(rows),*size))
+        .collect();
+      let mut shared = slot.lock();
+      for (offset,rows,size) in blocks.into_iter().rev() {
+        shared.lru.put((slot.ns,offset), (slot.erase)(rows), size);
+      }
+    }
+    *self = BlockCache::Shared(slot);
+  }
+  /// Take over the namespace of `old` if it keeps its blocks in a shared
+  /// cache, dropping the blocks it holds. For a data store that replaces
+  /// another one, such as on reload.
+  pub fn adopt (&mut self, old: &mut Self) {
+    if let BlockCache::Shared(_) = old {
+      old.clear();
+      *self = std::mem::replace(old, BlockCache::new(0));
+    }
+  }
+  /// Return the shared cache that holds these blocks, first moving the
+  /// blocks into a new shared cache with this cache's bounds if necessary.
+  pub fn shared (&mut self) -> SharedCache where
+  P: Send+Sync+'static, V: Send+Sync {
+    if let BlockCache::Shared(slot) = self {
+      return Arc::clone(&slot.shared);
+    }
+    let (cap,max_bytes) = match self {
+      BlockCache::Local(lru) => (lru.cap, lru.max_bytes),
+      BlockCache::Shared(_) => unreachable![]
+    };
+    let mut lru = Lru::new(cap);
+    lru.max_bytes = max_bytes;
+    let shared = Arc::new(Mutex::new(SharedBlocks { lru, next: 0 }));
+    self.share(&shared);
+    shared
+  }
+  fn size (rows: &Rows<P,V>) -> usize {
+    ENTRY_OVERHEAD + rows.iter().map(|row| {
+      size_of::<(P,V,Location)>() + row.1.count_bytes().saturating_sub(size_of::<V>())
+    }).sum::<usize>()
+  }
+}
+
+// boxed because lru 0.1 leaves entries uninitialized, which panics for the
+// vtable of a fat `Arc<dyn Any>`
+type Erased = Arc<Box<dyn Any+Send+Sync>>;
+
+/// Block cache shared by the collections of a database. Each collection keys
+/// its blocks by a namespace, and all of them count towards one set of
+/// bounds, so a busy collection can take the space of a quiet one.
+pub(crate) struct SharedBlocks {
+  lru: Lru<(u32,u64),Erased>,
+  next: u32
+}
+
+impl SharedBlocks {
+  // blocks and bytes held under namespace `ns`
+  fn usage (&self, ns: u32) -> (usize,usize) {
+    self.lru.lru.iter().filter(|((n,_),_)| *n == ns)
+      .fold((0,0), |(n,bytes),(_,(_,size))| (n+1, bytes+size))
+  }
+}
+
+pub(crate) type SharedCache = Arc<Mutex<SharedBlocks>>;
+
+// namespace of one collection in a shared cache, with the conversions of its
+// rows to and from the type-erased entries
+pub(crate) struct SharedSlot<P,V> where P: Point, V: Value {
+  shared: SharedCache,
+  ns: u32,
+  erase: fn (Rows<P,V>) -> Erased,
+  downcast: fn (&Erased) -> Option<Rows<P,V>>
+}
+
+impl<P,V> SharedSlot<P,V> where P: Point, V: Value {
+  fn new (shared: &SharedCache) -> Self where P: Send+Sync+'static, V: Send+Sync {
+    let ns = {
+      let mut s = shared.lock().unwrap_or_else(|e| e.into_inner());
+      s.next += 1;
+      s.next
+    };
+    Self {
+      shared: Arc::clone(shared),
+      ns,
+      erase: |rows| Arc::new(Box::new(rows)),
+      downcast: |rows| {
+        let any: &(dyn Any+Send+Sync) = &***rows;
+        any.downcast_ref::<Rows<P,V>>().cloned()
+      }
+    }
+  }
+  fn lock (&self) -> MutexGuard<'_,SharedBlocks> {
+    // the cache holds no invariants that a panic elsewhere could break
+    self.shared.lock().unwrap_or_else(|e| e.into_inner())
+  }
+}
+
+// lru cache that tracks the estimated size of its entries
+pub(crate) struct Lru<K,T> where K: Hash+Eq {
+  lru: LruCache<K,(T,usize)>,
+  cap: usize,
+  bytes: usize,
+  max_bytes: Option<usize>
+}
+
+impl<K,T> Lru<K,T> where K: Hash+Eq {
+  fn new (cap: usize) -> Self {
+    Self { lru: LruCache::new(cap), cap, bytes: 0, max_bytes: None }
+  }
+  fn set_max_bytes (&mut self, max: Option<usize>) {
+    self.max_bytes = max;
+    self.evict();
+  }
+  fn get (&mut self, key: &K) -> Option<&T> {
+    self.lru.get(key).map(|(x,_)| x)
+  }
+  fn peek (&self, key: &K) -> Option<&T> {
+    self.lru.peek(key).map(|(x,_)| x)
+  }
+  fn put (&mut self, key: K, x: T, size: usize) {
+    self.pop(&key);
+    if self.cap == 0 || self.max_bytes.map(|max| size > max).unwrap_or(false) {
+      return;
+    }
+    while self.lru.len() >= self.cap {
+      self.pop_lru();
+    }
+    self.lru.put(key, (x,size));
+    self.bytes += size;
+    self.evict();
+  }
+  fn update (&mut self, key: &K, x: T, size: usize) {
+    if let Some((y,s)) = self.lru.get_mut(key) {
+      *y = x;
+      self.bytes -= *s;
+      *s = size;
+      self.bytes += size;
+    }
+    self.evict();
+  }
+  fn pop (&mut self, key: &K) -> Option<T> {
+    let (x,size) = self.lru.pop(key)?;
+    self.bytes -= size;
+    Some(x)
+  }
+  fn clear (&mut self) {
+    self.lru.clear();
+    self.bytes = 0;
+  }
+  fn len (&self) -> usize {
+    self.lru.len()
+  }
+  fn pop_lru (&mut self) {
+    if let Some((_,(_,size))) = self.lru.pop_lru() {
+      self.bytes -= size;
+    }
+  }
+  fn evict (&mut self) {
+    if let Some(max) = self.max_bytes {
+      while self.bytes > max && !self.lru.is_empty() {
+        self.pop_lru();
+      }
+    }
+  }
+}
+
+/// Split a memory budget of `bytes` for `Setup::cache_bytes()` into a number
+/// of bbox cache entries, at most `bbox_cache_size`, and a byte budget for the
+/// block cache. Bbox entries have a fixed size, so the bbox cache keeps a
+/// count limit and gets at most half of the budget.
+pub(crate) fn split_budget<P> (bytes: u64, bbox_cache_size: usize)
+-> (usize,usize) where P: Point {
+  let bytes = bytes.min(usize::MAX as u64) as usize;
+  let entry = ENTRY_OVERHEAD/2 + size_of::<(u64,(P::Bounds,u64))>();
+  let bbox = bbox_cache_size.min(bytes/2/entry);
+  (bbox, bytes - bbox*entry)
+}
@@ -0,0 +1,36 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool,Ordering};
+
+/// Shared flag to stop a running query early, for example when a client
+/// disconnects or a newer request supersedes this one.
+///
+/// Clones share the same flag, so one clone can be passed to a query with
+/// `.cancel_on()` and another kept to call `cancel()` from elsewhere. The
+/// query then ends with a `Cancelled` error.
+///
+/// ```rust
+/// use eyros::CancelToken;
+///
+/// let token = CancelToken::new();
+/// let handle = token.clone();
+/// assert![!token.is_cancelled()];
+/// handle.cancel();
+/// assert![token.is_cancelled()];
+/// ```
+#[derive(Clone,Debug,Default)]
+pub struct CancelToken {
+  cancelled: Arc<AtomicBool>
+}
+
+impl CancelToken {
+  pub fn new () -> Self {
+    Self::default()
+  }
+  /// Signal every query holding this token to stop.
+  pub fn cancel (&self) {
+    self.cancelled.store(true, Ordering::SeqCst);
+  }
+  pub fn is_cancelled (&self) -> bool {
+    self.cancelled.load(Ordering::SeqCst)
+  }
+}
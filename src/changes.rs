@@ -0,0 +1,318 @@
+use crate::{DB,Point,Value,Row,Location,HistoryPruned};
+use crate::Error;
+use random_access_storage::RandomAccess;
+use desert::{ToBytes,FromBytes};
+use std::marker::PhantomData;
+use std::time::Duration;
+
+/// Row-level change recorded in the changes feed.
+#[derive(Clone,Debug,PartialEq)]
+pub enum Change<P,V> where P: Point, V: Value {
+  /// A row was inserted.
+  Insert(P,V),
+  /// A row was deleted from `location`, as it was known before the batch
+  /// that deleted it.
+  Delete(P,V,Location)
+}
+
+/// Change read from the changes feed with its sequence number.
+#[derive(Clone,Debug,PartialEq)]
+pub struct ChangeEntry<P,V> where P: Point, V: Value {
+  /// Position of the change in the feed, starting at 1.
+  pub seq: u64,
+  /// Time the change was written, since the unix epoch, from the clock of
+  /// the database that wrote it.
+  pub time: Duration,
+  pub change: Change<P,V>
+}
+
+/// Append-only log of changes. Each record in the log store is
+/// `[length (u32)][tag (u8)][time (u64)][point][value][location]`, where the
+/// length includes the length field itself, the time is in milliseconds since
+/// the unix epoch, and inserts have no location. The index store is
+/// `[first (u64)][offset (u64)]...`: the sequence number of the oldest change
+/// kept, followed by the offset of each record from that change on.
+pub struct ChangeLog<S> where S: RandomAccess<Error=failure::Error> {
+  log: S,
+  index: S,
+  first: u64
+}
+
+impl<S> ChangeLog<S> where S: RandomAccess<Error=failure::Error> {
+  pub fn open (log: S, mut index: S) -> Result<Self,Error> {
+    let first = if index.len()? < 8 { 1 } else { read_u64(&mut index, 0)? };
+    Ok(Self { log, index, first })
+  }
+  /// Return the sequence number of the oldest change kept.
+  pub fn first (&self) -> u64 {
+    self.first
+  }
+  /// Sizes in bytes of the log store and the index store.
+  pub fn store_bytes (&self) -> Result<(u64,u64),Error> {
+    Ok((self.log.len()?,self.index.len()?))
+  }
+  /// Return the sequence number of the last change, or `0`.
+  pub fn len (&self) -> Result<u64,Error> {
+    let ilen = self.index.len()?;
+    Ok(self.first - 1 + ilen.saturating_sub(8)/8)
+  }
+  pub fn append<P,V> (&mut self, changes: &[Change<P,V>], time: Duration)
+  -> Result<(),Error> where P: Point, V: Value {
+    if changes.is_empty() { return Ok(()) }
+    let millis = time.as_millis() as u64;
+    let start = self.log.len()?;
+    let mut buf = vec![];
+    let mut ibuf = Vec::with_capacity(changes.len()*8+8);
+    if self.index.len()? < 8 { // new log
+      ibuf.extend(&self.first.to_be_bytes());
+    }
+    for change in changes.iter() {
+      let record = match change {
+        Change::Insert(p,v) => {
+          let mut r = vec![0];
+          r.extend((millis,*p,v.clone()).to_bytes()?);
+          r
+        },
+        Change::Delete(p,v,loc) => {
+          let mut r = vec![1];
+          r.extend((millis,*p,v.clone(),*loc).to_bytes()?);
+          r
+        }
+      };
+      ibuf.extend(&(start + buf.len() as u64).to_be_bytes());
+      buf.extend(&((record.len()+4) as u32).to_be_bytes());
+      buf.extend(record);
+    }
+    // records past the end of the index are left over from a crash and are
+    // never read, so the log is written first
+    self.log.write(start, &buf)?;
+    self.log.sync_all()?;
+    let ilen = self.index.len()?;
+    let ioffset = if ilen < 8 { 0 } else { ilen };
+    self.index.write(ioffset, &ibuf)?;
+    self.index.sync_all()?;
+    Ok(())
+  }
+  /// Flush the log and the index to durable storage.
+  pub fn sync_all (&mut self) -> Result<(),Error> {
+    self.log.sync_all()?;
+    self.index.sync_all()?;
+    Ok(())
+  }
+  fn offset (&mut self, seq: u64) -> Result<u64,Error> {
+    read_u64(&mut self.index, 8 + (seq-self.first)*8)
+  }
+  /// Read the change with sequence number `seq`.
+  pub fn get<P,V> (&mut self, seq: u64) -> Result<ChangeEntry<P,V>,Error>
+  where P: Point, V: Value {
+    if seq < self.first {
+      return Err(HistoryPruned { first: self.first }.into());
+    }
+    let offset = self.offset(seq)?;
+    let lbuf = self.log.read(offset, 4)?;
+    let len = u32::from_be_bytes([lbuf[0],lbuf[1],lbuf[2],lbuf[3]]) as u64;
+    if len < 13 {
+      corrupt!["invalid change record length {} at offset {}", len, offset];
+    }
+    let buf = self.log.read(offset+4, len-4)?;
+    let (millis,change) = match buf[0] {
+      0 => {
+        let (_,(t,p,v)) = <(u64,P,V)>::from_bytes(&buf[1..])?;
+        (t,Change::Insert(p,v))
+      },
+      1 => {
+        let (_,(t,p,v,loc)) = <(u64,P,V,Location)>::from_bytes(&buf[1..])?;
+        (t,Change::Delete(p,v,loc))
+      },
+      tag => corrupt!["unknown change type {} at offset {}", tag, offset]
+    };
+    Ok(ChangeEntry { seq, time: Duration::from_millis(millis), change })
+  }
+  /// Read the time of the change with sequence number `seq`.
+  pub fn time (&mut self, seq: u64) -> Result<Duration,Error> {
+    let offset = self.offset(seq)?;
+    Ok(Duration::from_millis(read_u64(&mut self.log, offset+5)?))
+  }
+  /// Write the changes from sequence number `first` on to `log` and `index`,
+  /// empty stores that replace the stores of this log, and return the number
+  /// of changes left out. The kept records are moved to the start of the
+  /// log, so sequence numbers stay the same but offsets change. Nothing is
+  /// written if no change would be left out.
+  pub fn prune (&mut self, first: u64, log: &mut S, index: &mut S)
+  -> Result<u64,Error> {
+    let end = self.len()?;
+    let first = first.min(end+1);
+    if first <= self.first { return Ok(0) }
+    let removed = first - self.first;
+    let (start,records) = if first > end {
+      (self.log.len()?,vec![])
+    } else {
+      let start = self.offset(first)?;
+      let len = self.log.len()?;
+      (start,self.log.read(start, len-start)?)
+    };
+    let offsets = if first > end { vec![] } else {
+      let ilen = self.index.len()?;
+      let ioffset = 8 + (first-self.first)*8;
+      self.index.read(ioffset, ilen-ioffset)?
+    };
+    let mut ibuf = Vec::with_capacity(offsets.len()+8);
+    ibuf.extend(&first.to_be_bytes());
+    for chunk in offsets.chunks(8) {
+      let mut o = [0u8;8];
+      o.copy_from_slice(chunk);
+      ibuf.extend(&(u64::from_be_bytes(o)-start).to_be_bytes());
+    }
+    if !records.is_empty() { log.write(0, &records)? }
+    log.sync_all()?;
+    index.write(0, &ibuf)?;
+    index.sync_all()?;
+    Ok(removed)
+  }
+}
+
+fn read_u64<S> (store: &mut S, offset: u64) -> Result<u64,Error>
+where S: RandomAccess<Error=failure::Error> {
+  let buf = store.read(offset, 8)?;
+  let mut b = [0u8;8];
+  b.copy_from_slice(&buf);
+  Ok(u64::from_be_bytes(b))
+}
+
+/// Iterator over the changes feed, returned by `db.changes(since)`.
+///
+/// The iterator reads from its own handles to the changes stores and stops
+/// at the last change that was written when it was created.
+pub struct ChangesIterator<S,P,V> where
+S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  log: ChangeLog<S>,
+  next: u64,
+  end: u64,
+  _marker: PhantomData<(P,V)>
+}
+
+impl<S,P,V> Iterator for ChangesIterator<S,P,V> where
+S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  type Item = Result<ChangeEntry<P,V>,Error>;
+  fn next (&mut self) -> Option<Self::Item> {
+    if self.next > self.end { return None }
+    let r = self.log.get(self.next);
+    self.next = if r.is_ok() { self.next + 1 } else { self.end + 1 };
+    Some(r)
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Iterate over the changes written after the change with sequence number
+  /// `since`. Pass `0` to read the whole feed, then continue from the `seq`
+  /// of the last change read.
+  ///
+  /// The feed is only written when it is enabled with `Setup::changes()`.
+  /// Fails with `HistoryPruned` if changes after `since` were removed by the
+  /// retention policy, in which case a replica has to copy the whole database
+  /// again.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Setup,Row,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # type P = ((f32,f32),(f32,f32));
+  /// # type V = u32;
+  /// # fn main () -> Result<(),Error> {
+  /// let mut primary: DB<_,_,P,V> = Setup::new(storage("/tmp/eyros-a/"))
+  ///   .changes(true)
+  ///   .build()?;
+  /// let mut replica: DB<_,_,P,V> = Setup::new(storage("/tmp/eyros-b/"))
+  ///   .build()?;
+  /// primary.batch(&[Row::Insert(((0.0,0.1),(-0.6,-0.55)), 7)])?;
+  ///
+  /// let mut since = 0;
+  /// let changes = primary.changes(since)?.collect::<Result<Vec<_>,Error>>()?;
+  /// replica.apply_changes(&changes)?;
+  /// if let Some(last) = changes.last() { since = last.seq }
+  /// # Ok(()) }
+  /// # fn storage(dir: &'static str)
+  /// # -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   move |name: &str| {
+  /// #     let mut p = PathBuf::from(dir);
+  /// #     p.push(name);
+  /// #     Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// #   }
+  /// # }
+  /// ```
+  pub fn changes (&self, since: u64) -> Result<ChangesIterator<S,P,V>,Error> {
+    self.check_open()?;
+    if self.change_log.is_none() {
+      invalid!["changes feed is not enabled, use Setup::changes(true)"];
+    }
+    let log = ChangeLog::open(
+      (self.open_store)("changes")?,
+      (self.open_store)("changes_index")?
+    )?;
+    if since+1 < log.first() {
+      return Err(HistoryPruned { first: log.first() }.into());
+    }
+    let end = log.len()?;
+    Ok(ChangesIterator { log, next: since+1, end, _marker: PhantomData })
+  }
+
+  /// Return the sequence number of the last change in the feed, or `0`.
+  pub fn last_change (&self) -> Result<u64,Error> {
+    match &self.change_log {
+      Some(log) => log.len(),
+      None => Ok(0)
+    }
+  }
+
+  /// Apply changes read from another database's feed with `changes()`.
+  ///
+  /// Locations differ between databases, so a deleted row is found by its
+  /// point and value. Deletes of rows inserted earlier in `changes` cancel
+  /// out, and deletes of rows that aren't here are skipped. The changes are
+  /// written in one batch, which also records them in this database's own
+  /// feed if it is enabled.
+  pub fn apply_changes (&mut self, changes: &[ChangeEntry<P,V>])
+  -> Result<(),Error> {
+    self.check_writable()?;
+    let mut inserts: Vec<Option<(P,V,Vec<u8>)>> = vec![];
+    let mut deletes: Vec<Location> = vec![];
+    for entry in changes.iter() {
+      match &entry.change {
+        Change::Insert(p,v) => {
+          inserts.push(Some((*p,v.clone(),(*p,v.clone()).to_bytes()?)));
+        },
+        Change::Delete(p,v,_) => {
+          let key = (*p,v.clone()).to_bytes()?;
+          let pending = inserts.iter().rposition(|i| match i {
+            Some((_,_,k)) => *k == key,
+            None => false
+          });
+          if let Some(i) = pending {
+            inserts[i] = None;
+            continue;
+          }
+          let bbox = P::bounds(&vec![*p])
+            .ok_or_else(|| Error::Other(format!["no bounds for point {:?}", p]))?;
+          // writes see rows hidden by set_visibility()
+          let rows = self.query_mode(&bbox, crate::CacheMode::Normal, None)?
+            .visibility(None);
+          for result in rows {
+            let (q,w,loc) = result?;
+            if deletes.contains(&loc) { continue }
+            if (q,w).to_bytes()? == key {
+              deletes.push(loc);
+              break;
+            }
+          }
+        }
+      }
+    }
+    let mut rows: Vec<Row<P,V>> = deletes.into_iter().map(Row::Delete).collect();
+    rows.extend(inserts.into_iter().flatten().map(|(p,v,_)| Row::Insert(p,v)));
+    if rows.is_empty() { return Ok(()) }
+    self.batch(&rows)
+  }
+}
@@ -0,0 +1,242 @@
+use crate::{DB,Point,Value};
+use crate::lock::Lock;
+use desert::ToBytes;
+use crate::Error;
+use random_access_storage::RandomAccess;
+use std::collections::{HashMap,HashSet};
+use std::fmt;
+
+// range entries of data blocks as `(offset,range,rows)`
+type RangeEntries<P> = Vec<(u64,<P as Point>::Range,u64)>;
+
+/// Damage found by `db.check()`.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub enum CheckProblem {
+  /// A branch block of the tree in slot `tree` can't be read, so the data
+  /// blocks below it are out of reach. `repair()` can't fix this; use
+  /// `restore()` or rebuild the tree from a backup.
+  Branch { tree: usize, error: String },
+  /// The data block at `offset` can't be read or decoded, its length field
+  /// runs past the end of the data store, its checksum doesn't match, or its
+  /// bitfield doesn't cover exactly its rows.
+  Block { offset: u64, error: String },
+  /// The range store can't be decoded. Every block is reported with a
+  /// `MissingRange` too.
+  Ranges { error: String },
+  /// A tree references the data block at `offset`, which has no entry in the
+  /// range store.
+  MissingRange { offset: u64 },
+  /// The range store entry of the data block at `offset` records a different
+  /// number of rows than the block holds, or live rows lie outside of the
+  /// recorded range.
+  RangeMismatch { offset: u64, recorded_rows: u64, rows: u64 }
+}
+
+impl fmt::Display for CheckProblem {
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      CheckProblem::Branch { tree, error } => {
+        write!(f, "tree {} has an unreadable branch: {}", tree, error)
+      },
+      CheckProblem::Block { offset, error } => {
+        write!(f, "data block at offset {} is damaged: {}", offset, error)
+      },
+      CheckProblem::Ranges { error } => {
+        write!(f, "range store is damaged: {}", error)
+      },
+      CheckProblem::MissingRange { offset } => {
+        write!(f, "data block at offset {} has no range entry", offset)
+      },
+      CheckProblem::RangeMismatch { offset, recorded_rows, rows } => {
+        write!(f, "range entry of data block at offset {} doesn't match its \
+          rows ({} recorded, {} found)", offset, recorded_rows, rows)
+      }
+    }
+  }
+}
+
+/// Result of `db.check()` or `db.repair()`.
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct CheckReport {
+  /// Trees that were walked.
+  pub trees: usize,
+  /// Data blocks that were read.
+  pub blocks: u64,
+  /// Damage that was found, in the order it was found.
+  pub problems: Vec<CheckProblem>,
+  /// Data blocks that were skipped because they are quarantined.
+  pub quarantined: Vec<u64>
+}
+
+impl CheckReport {
+  /// Whether no damage was found. Quarantined blocks don't count.
+  pub fn is_ok (&self) -> bool {
+    self.problems.is_empty()
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Verify that every tree can be walked, that the data blocks the trees
+  /// reference can be read and match their checksums and bitfields, and that
+  /// the range store agrees with the blocks.
+  ///
+  /// This reads every branch and data block without going through the block
+  /// cache, so it takes about as long as a full `scan()`. Damage is reported
+  /// instead of returned as an error, and nothing is written: use `repair()`
+  /// to fix what can be fixed.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut db: DB<_,_,(f32,f32),u32> = DB::open(storage)?;
+  /// let report = db.check()?;
+  /// for problem in report.problems.iter() {
+  ///   eprintln!["{}", problem];
+  /// }
+  /// if !report.is_ok() {
+  ///   db.repair()?;
+  /// }
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn check (&mut self) -> Result<CheckReport,Error> {
+    self.check_open()?;
+    Ok(self.check_blocks()?.0)
+  }
+
+  /// Check the database like `check()`, then quarantine the data blocks that
+  /// are damaged and rebuild the range store from the blocks that are left.
+  ///
+  /// Returns the report of the damage found before repairing. Queries skip
+  /// quarantined blocks instead of failing on them, so their rows are lost
+  /// until the blocks are released with `release_quarantine()`. Unreadable
+  /// branches are reported but can't be repaired.
+  pub fn repair (&mut self) -> Result<CheckReport,Error> {
+    self.check_writable()?;
+    self.check_backup()?;
+    if self.fields.check_conflicts {
+      self.check_sequence()?;
+    }
+    let (report,ranges) = self.check_blocks()?;
+    {
+      let mut dstore = self.data_store.write_lock()?;
+      for problem in report.problems.iter() {
+        if let CheckProblem::Block { offset, error } = problem {
+          dstore.quarantine_block(*offset, &Error::Corrupt(error.to_string()));
+        }
+      }
+      let r = dstore.rewrite_ranges(&ranges);
+      drop(dstore);
+      self.poison_on_err(r)?;
+    }
+    self.save_quarantine()?;
+    Ok(report)
+  }
+
+  // Check every tree and the data blocks they reference, and return the
+  // report with the range entries rebuilt from the readable blocks, sorted by
+  // offset.
+  fn check_blocks (&mut self) -> Result<(CheckReport,RangeEntries<P>),Error> {
+    let mut report = CheckReport::default();
+    let mut offsets = vec![];
+    for (index,tree) in self.trees.iter().enumerate() {
+      if !self.meta.mask.get(index).cloned().unwrap_or(false) { continue }
+      report.trees += 1;
+      match tree.write_lock()?.shape() {
+        Ok((_,_,o)) => offsets.extend(o),
+        Err(err) => report.problems.push(CheckProblem::Branch {
+          tree: index,
+          error: err.to_string()
+        })
+      }
+    }
+    offsets.sort_unstable();
+    offsets.dedup();
+    let mut dstore = self.data_store.write_lock()?;
+    let entries: HashMap<u64,(P::Range,u64)> = match dstore.block_entries() {
+      Ok(entries) => entries,
+      Err(err) => {
+        report.problems.push(CheckProblem::Ranges { error: err.to_string() });
+        HashMap::new()
+      }
+    };
+    let quarantined: HashSet<u64> = dstore.quarantine.clone();
+    let mut ranges = vec![];
+    // blocks that can't be checked keep their entries, so that they can be
+    // released from quarantine later
+    let keep = |ranges: &mut RangeEntries<P>, offset: u64| {
+      if let Some((range,rows)) = entries.get(&offset) {
+        ranges.push((offset,*range,*rows));
+      }
+    };
+    for offset in offsets {
+      if quarantined.contains(&offset) {
+        report.quarantined.push(offset);
+        keep(&mut ranges, offset);
+        continue;
+      }
+      report.blocks += 1;
+      let (points,bitfield) = match dstore.check_block(offset) {
+        Ok(block) => block,
+        Err(err) => {
+          report.problems.push(CheckProblem::Block { offset, error: err.to_string() });
+          keep(&mut ranges, offset);
+          continue;
+        }
+      };
+      let live: Vec<P> = points.iter().enumerate()
+        .filter(|(i,_)| ((bitfield[i/8]>>(i%8))&1) == 1)
+        .map(|(_,p)| *p)
+        .collect();
+      let rows = points.len() as u64;
+      let range = match (P::bounds(&live), entries.get(&offset)) {
+        (Some(bbox),_) => P::bounds_to_range(bbox),
+        (None,Some((range,_))) => *range,
+        (None,None) => match P::bounds(&points) {
+          Some(bbox) => P::bounds_to_range(bbox),
+          None => {
+            report.problems.push(CheckProblem::Block {
+              offset,
+              error: "block has no rows".to_string()
+            });
+            continue;
+          }
+        }
+      };
+      match entries.get(&offset) {
+        None => report.problems.push(CheckProblem::MissingRange { offset }),
+        Some((recorded,recorded_rows)) => {
+          if *recorded_rows != rows || !range_covers::<P>(recorded, &range)? {
+            report.problems.push(CheckProblem::RangeMismatch {
+              offset,
+              recorded_rows: *recorded_rows,
+              rows
+            });
+          }
+        }
+      }
+      ranges.push((offset,range,rows));
+    }
+    Ok((report,ranges))
+  }
+}
+
+// Whether the recorded range of a block covers the range of its live rows.
+// Updates in place can move rows anywhere inside of the recorded range, so
+// the ranges only have to be equal for point types without range bounds.
+fn range_covers<P> (recorded: &P::Range, live: &P::Range) -> Result<bool,Error>
+where P: Point {
+  Ok(match P::range_bounds(recorded) {
+    Some(bbox) => P::range_within(live, &bbox),
+    None => recorded.to_bytes()? == live.to_bytes()?
+  })
+}
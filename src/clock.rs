@@ -0,0 +1,107 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64,Ordering};
+use std::thread;
+use std::time::{Duration,SystemTime,UNIX_EPOCH};
+
+/// Source of time for anything in the database that waits or timestamps,
+/// such as retry backoff.
+///
+/// The default is `SystemClock`. Tests can swap in a `ManualClock` so that
+/// waiting is simulated and runs are deterministic.
+pub trait Clock: Send+Sync {
+  /// Time elapsed since the unix epoch.
+  fn now (&self) -> Duration;
+  /// Wait for `duration` to pass.
+  fn sleep (&self, duration: Duration);
+}
+
+/// Clock backed by the operating system.
+#[derive(Clone,Copy,Debug,Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now (&self) -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+      .unwrap_or(Duration::from_secs(0))
+  }
+  fn sleep (&self, duration: Duration) {
+    thread::sleep(duration)
+  }
+}
+
+/// Clock that only moves when it is told to. Sleeping advances the clock
+/// immediately instead of blocking.
+///
+/// ```rust
+/// use eyros::{Clock,ManualClock};
+/// use std::time::Duration;
+///
+/// let clock = ManualClock::new(Duration::from_secs(100));
+/// clock.sleep(Duration::from_secs(5));
+/// clock.advance(Duration::from_millis(250));
+/// assert_eq![clock.now(), Duration::from_millis(105_250)];
+/// ```
+#[derive(Debug,Default)]
+pub struct ManualClock {
+  nanos: AtomicU64
+}
+
+impl ManualClock {
+  pub fn new (start: Duration) -> Self {
+    Self { nanos: AtomicU64::new(start.as_nanos() as u64) }
+  }
+  /// Move the clock forward by `duration`.
+  pub fn advance (&self, duration: Duration) {
+    self.nanos.fetch_add(duration.as_nanos() as u64, Ordering::SeqCst);
+  }
+  /// Set the clock to `now`.
+  pub fn set (&self, now: Duration) {
+    self.nanos.store(now.as_nanos() as u64, Ordering::SeqCst);
+  }
+}
+
+impl Clock for ManualClock {
+  fn now (&self) -> Duration {
+    Duration::from_nanos(self.nanos.load(Ordering::SeqCst))
+  }
+  fn sleep (&self, duration: Duration) {
+    self.advance(duration)
+  }
+}
+
+/// Return the clock that databases use unless `Setup::clock()` sets one.
+///
+/// This is `SystemClock`, except on `wasm32-unknown-unknown` where the
+/// system time and sleeping aren't available: there it is a `ManualClock`
+/// starting at the unix epoch, so retries don't wait. Browser builds that
+/// track block heat should set a clock backed by `Date.now()`.
+pub fn default_clock () -> Arc<dyn Clock> {
+  #[cfg(all(target_arch="wasm32",target_os="unknown"))]
+  { Arc::new(ManualClock::new(Duration::from_secs(0))) }
+  #[cfg(not(all(target_arch="wasm32",target_os="unknown")))]
+  { Arc::new(SystemClock) }
+}
+
+/// Small seedable pseudo-random number generator (splitmix64) for internal
+/// sampling decisions. The same seed always produces the same sequence.
+#[derive(Clone,Debug)]
+pub struct Rng {
+  state: u64
+}
+
+impl Rng {
+  pub fn new (seed: u64) -> Self {
+    Self { state: seed }
+  }
+  pub fn next_u64 (&mut self) -> u64 {
+    self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = self.state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+  }
+  /// Return a number in `0..n`. Returns 0 when `n` is 0.
+  pub fn below (&mut self, n: u64) -> u64 {
+    if n == 0 { 0 } else { self.next_u64() % n }
+  }
+}
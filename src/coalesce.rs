@@ -0,0 +1,69 @@
+use crate::Error;
+use random_access_storage::RandomAccess;
+use std::collections::HashMap;
+
+// bytes to read past the offset of the last branch in a span for its length
+// field and body, like the first read of read_block()
+const GUESS: u64 = 1024;
+
+/// Branch blocks read ahead by one tree traversal.
+///
+/// A branch's child branches are allocated next to each other when the tree
+/// is built, so once a branch points at several children, one read over the
+/// span of their offsets loads all of them where reading them one at a time
+/// would take one or two reads each. Siblings stay here until the traversal
+/// pops them off its stack.
+pub(crate) struct Coalescer {
+  max_span: u64,
+  branches: HashMap<u64,Vec<u8>>
+}
+
+impl Coalescer {
+  /// Coalesce reads that span at most `max_span` bytes. `0` turns read ahead
+  /// off.
+  pub fn new (max_span: u64) -> Self {
+    Self { max_span, branches: HashMap::new() }
+  }
+  pub fn enabled (&self) -> bool {
+    self.max_span > 0
+  }
+  /// Take the branch at `offset` without its length field, if it was read
+  /// ahead.
+  pub fn take (&mut self, offset: u64) -> Option<Vec<u8>> {
+    self.branches.remove(&offset)
+  }
+  /// Read ahead the branches at `offsets`, with one read for each run of
+  /// offsets that fits in `max_span` bytes. Branches that are alone in their
+  /// span or that run past the end of a read are left for `read_block()`.
+  pub fn fetch<S> (&mut self, store: &mut S, offsets: &[u64], tree_size: u64)
+  -> Result<(),Error> where S: RandomAccess<Error=failure::Error> {
+    if !self.enabled() { return Ok(()) }
+    let mut offsets: Vec<u64> = offsets.iter().copied()
+      .filter(|o| *o < tree_size && !self.branches.contains_key(o))
+      .collect();
+    if offsets.len() < 2 { return Ok(()) }
+    offsets.sort_unstable();
+    let mut i = 0;
+    while i < offsets.len() {
+      let start = offsets[i];
+      let mut j = i + 1;
+      while j < offsets.len() && offsets[j] + GUESS - start <= self.max_span {
+        j += 1;
+      }
+      if j - i > 1 {
+        let end = (offsets[j-1] + GUESS).min(tree_size);
+        let buf = store.read(start, end - start)?;
+        for offset in offsets[i..j].iter() {
+          let at = (offset - start) as usize;
+          if at + 4 > buf.len() { continue }
+          let len = u32::from_be_bytes([buf[at],buf[at+1],buf[at+2],buf[at+3]]) as usize;
+          // read_block() reports malformed lengths
+          if len < 4 || at + len > buf.len() { continue }
+          self.branches.insert(*offset, buf[at+4..at+len].to_vec());
+        }
+      }
+      i = j;
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,111 @@
+use crate::{DB,Point,Value};
+use crate::Error;
+use random_access_storage::RandomAccess;
+use desert::{ToBytes,FromBytes,CountBytes};
+use std::sync::Arc;
+use crate::lock::Lock;
+
+/// Encoding of the rows in data blocks, set with `db.set_codec()`.
+///
+/// Each row of a block is written with `serialize()` and the rows are stored
+/// back to back, so a codec must be able to tell where a row ends from the
+/// bytes that follow its start. Block headers, bitfields, compression,
+/// encryption, and the index files are not affected by the codec.
+///
+/// The default codec is `DesertCodec`. A fixed-width codec makes the rows of
+/// a block easy to read from other languages:
+///
+/// ```rust
+/// use eyros::{Codec,Error};
+///
+/// struct FixedWidth;
+/// impl Codec<(f32,f32),u32> for FixedWidth {
+///   fn serialize (&self, row: &((f32,f32),u32)) -> Result<Vec<u8>,Error> {
+///     let ((x,y),v) = row;
+///     let mut buf = Vec::with_capacity(12);
+///     buf.extend(&x.to_le_bytes());
+///     buf.extend(&y.to_le_bytes());
+///     buf.extend(&v.to_le_bytes());
+///     Ok(buf)
+///   }
+///   fn deserialize (&self, buf: &[u8]) -> Result<(usize,((f32,f32),u32)),Error> {
+///     if buf.len() < 12 {
+///       return Err(Error::Corrupt("row is too short".into()));
+///     }
+///     let f = |i: usize| [buf[i],buf[i+1],buf[i+2],buf[i+3]];
+///     let (x,y) = (f32::from_le_bytes(f(0)), f32::from_le_bytes(f(4)));
+///     Ok((12,((x,y),u32::from_le_bytes(f(8)))))
+///   }
+///   fn take_bytes (&self, _buf: &[u8]) -> Result<usize,Error> {
+///     Ok(12)
+///   }
+/// }
+/// ```
+pub trait Codec<P,V>: Send+Sync where P: Point, V: Value {
+  /// Encode a row.
+  fn serialize (&self, row: &(P,V)) -> Result<Vec<u8>,Error>;
+  /// Append the encoding of a row to `buf`. Override this to write rows
+  /// without allocating a vector for each of them.
+  fn serialize_into (&self, row: &(P,V), buf: &mut Vec<u8>) -> Result<(),Error> {
+    buf.extend_from_slice(&self.serialize(row)?);
+    Ok(())
+  }
+  /// Decode the row at the start of `buf` and return it with the number of
+  /// bytes it used.
+  fn deserialize (&self, buf: &[u8]) -> Result<(usize,(P,V)),Error>;
+  /// Return the number of bytes used by the row at the start of `buf`,
+  /// ideally without decoding it.
+  fn take_bytes (&self, buf: &[u8]) -> Result<usize,Error>;
+  /// Decode only the point of the row at the start of `buf`, for queries
+  /// that don't need values, and return it with the size of the whole row.
+  fn deserialize_point (&self, buf: &[u8]) -> Result<(usize,P),Error> {
+    let (size,(point,_)) = self.deserialize(buf)?;
+    Ok((size,point))
+  }
+}
+
+/// Default `Codec`, which encodes rows with `desert` like the other files of
+/// the database.
+#[derive(Clone,Copy,Debug,Default)]
+pub struct DesertCodec;
+
+impl<P,V> Codec<P,V> for DesertCodec where P: Point, V: Value {
+  fn serialize (&self, row: &(P,V)) -> Result<Vec<u8>,Error> {
+    Ok(row.to_bytes()?)
+  }
+  fn serialize_into (&self, row: &(P,V), buf: &mut Vec<u8>) -> Result<(),Error> {
+    let start = buf.len();
+    buf.resize(start + row.count_bytes(), 0);
+    let n = row.write_bytes(&mut buf[start..])?;
+    buf.truncate(start + n);
+    Ok(())
+  }
+  fn deserialize (&self, buf: &[u8]) -> Result<(usize,(P,V)),Error> {
+    Ok(<(P,V)>::from_bytes(buf)?)
+  }
+  fn take_bytes (&self, buf: &[u8]) -> Result<usize,Error> {
+    Ok(<(P,V)>::count_from_bytes(buf)?)
+  }
+  fn deserialize_point (&self, buf: &[u8]) -> Result<(usize,P),Error> {
+    let (psize,point) = P::from_bytes(buf)?;
+    Ok((psize + V::count_from_bytes(&buf[psize..])?, point))
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Read and write the rows of data blocks with `codec` instead of
+  /// `DesertCodec`, replacing any earlier codec.
+  ///
+  /// The codec isn't recorded in the database, so set the same codec each
+  /// time the database is opened, before the first query or batch. Blocks
+  /// written with one codec can't be read with another.
+  pub fn set_codec<C> (&mut self, codec: C) -> Result<(),Error>
+  where C: Codec<P,V>+'static {
+    self.check_open()?;
+    self.data_store.write_lock()?.set_codec(Arc::new(codec));
+    Ok(())
+  }
+}
@@ -0,0 +1,66 @@
+use crate::{DB,Setup,Point,Value};
+use crate::lock::Lock;
+use crate::Error;
+use random_access_storage::RandomAccess;
+
+/// Function that opens the stores of a collection.
+pub trait StoreOpener<S>: Fn(&str) -> Result<S,failure::Error> {}
+impl<S,F> StoreOpener<S> for F where F: Fn(&str) -> Result<S,failure::Error> {}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>) + Clone,
+P: Point+Send+Sync+'static, V: Value+Send+Sync {
+  /// Open the collection `name`: a database with its own point and value
+  /// types and its own trees, kept in the same storage as this one.
+  ///
+  /// The stores of a collection are named `"{name}.{store}"`, such as
+  /// `roads.meta` and `roads.data`, so collections never touch each other's
+  /// stores or the stores of this database. A collection is opened with the
+  /// settings of this database and keeps its data blocks in the same block
+  /// cache, so the collections of a database share one cache budget. Open a
+  /// collection again to get the rows written to it earlier.
+  ///
+  /// Collection names can use ascii letters, digits, `_`, and `-`.
+  ///
+  /// ```rust,no_run
+  /// use eyros::{DB,Row,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// let db: DB<_,_,(f32,f32),u32> = DB::open(storage)?;
+  /// let mut roads = db.collection::<((f32,f32),(f32,f32)),u64>("roads")?;
+  /// let mut sensors = db.collection::<(f32,f32,f32),Vec<u8>>("sensors")?;
+  /// roads.batch(&[Row::Insert(((0.1,0.3),(-0.4,-0.2)), 1047)])?;
+  /// sensors.batch(&[Row::Insert((0.2,-0.3,1.5), vec![7,2])])?;
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  ///
+  /// The lock file of `Setup::lock_file()` isn't taken again for collections:
+  /// the lock held by this handle covers them while it stays open.
+  pub fn collection<Q,W> (&self, name: &str)
+  -> Result<DB<S,impl StoreOpener<S>,Q,W>,Error>
+  where Q: Point+Send+Sync+'static, W: Value+Send+Sync {
+    self.check_open()?;
+    if name.is_empty()
+    || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+      invalid!["invalid collection name {:?}", name];
+    }
+    let open_store = self.open_store.clone();
+    let prefix = format!["{}.", name];
+    let mut setup = Setup::new(move |store: &str| {
+      open_store(&format!["{}{}", prefix, store])
+    });
+    setup.fields = self.fields.clone();
+    setup.fields.lock_file = None;
+    let db: DB<S,_,Q,W> = setup.build()?;
+    let cache = self.data_store.write_lock()?.shared_list_cache();
+    db.data_store.write_lock()?.share_list_cache(&cache);
+    Ok(db)
+  }
+}
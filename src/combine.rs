@@ -0,0 +1,101 @@
+use crate::{DB,Row,Point,Value};
+use crate::Error;
+use random_access_storage::RandomAccess;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Counts from a `merge()`.
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct MergeReport {
+  /// Rows read from the first database.
+  pub rows_a: usize,
+  /// Rows read from the second database.
+  pub rows_b: usize,
+  /// Rows that shared an id and went through the resolver.
+  pub conflicts: usize,
+  /// Rows written to the output database.
+  pub written: usize
+}
+
+/// Union the rows of two databases that were built independently (for
+/// example on offline field devices) into `out`.
+///
+/// Rows are matched by the stable id that `id` computes from each point and
+/// value. When two rows share an id, including duplicates within one
+/// database, `resolve` receives the id with the row seen first and the row
+/// seen second (rows from `a` are seen before rows from `b`) and returns the
+/// row to keep.
+///
+/// Only rows that intersect `bbox` are read, so pass a bounding box that
+/// covers the whole domain to merge everything. Rows are collected in memory
+/// before they are written to `out` in one batch.
+///
+/// ```rust,no_run
+/// use eyros::{DB,merge,Error};
+/// # use std::path::PathBuf;
+/// # use random_access_disk::RandomAccessDisk;
+/// # type P = ((f32,f32),(f32,f32));
+/// # fn main () -> Result<(),Error> {
+/// // values are (id,revision)
+/// let mut a: DB<_,_,P,(u64,u32)> = DB::open(|name| storage("a", name))?;
+/// let mut b: DB<_,_,P,(u64,u32)> = DB::open(|name| storage("b", name))?;
+/// let mut out: DB<_,_,P,(u64,u32)> = DB::open(|name| storage("out", name))?;
+/// let bbox = ((-180.0,-90.0),(180.0,90.0));
+/// merge(&mut a, &mut b, &mut out, &bbox,
+///   |_point,value| value.0,
+///   |_id,x,y| if (y.1).1 > (x.1).1 { y } else { x }
+/// )?;
+/// # Ok(()) }
+/// # fn storage(dir: &str, name: &str) -> Result<RandomAccessDisk,failure::Error> {
+/// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+/// #   p.push(dir);
+/// #   p.push(name);
+/// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+/// # }
+/// ```
+pub fn merge<SA,UA,SB,UB,SO,UO,P,V,K,I,R> (a: &mut DB<SA,UA,P,V>,
+b: &mut DB<SB,UB,P,V>, out: &mut DB<SO,UO,P,V>, bbox: &P::Bounds,
+id: I, mut resolve: R) -> Result<MergeReport,Error> where
+SA: RandomAccess<Error=failure::Error>, UA: (Fn(&str) -> Result<SA,failure::Error>),
+SB: RandomAccess<Error=failure::Error>, UB: (Fn(&str) -> Result<SB,failure::Error>),
+SO: RandomAccess<Error=failure::Error>, UO: (Fn(&str) -> Result<SO,failure::Error>),
+P: Point, V: Value, K: Hash+Eq,
+I: Fn(&P,&V) -> K,
+R: FnMut(&K,(P,V),(P,V)) -> (P,V) {
+  let mut report = MergeReport::default();
+  let mut rows: Vec<Option<(P,V)>> = vec![];
+  let mut index: HashMap<K,usize> = HashMap::new();
+  let mut add = |row: (P,V), report: &mut MergeReport| {
+    let key = id(&row.0, &row.1);
+    match index.get(&key) {
+      Some(i) => {
+        report.conflicts += 1;
+        let prev = rows[*i].take().unwrap();
+        rows[*i] = Some(resolve(&key, prev, row));
+      },
+      None => {
+        index.insert(key, rows.len());
+        rows.push(Some(row));
+      }
+    }
+  };
+  for result in a.scan(bbox)? {
+    let (p,v,_) = result?;
+    report.rows_a += 1;
+    add((p,v), &mut report);
+  }
+  for result in b.scan(bbox)? {
+    let (p,v,_) = result?;
+    report.rows_b += 1;
+    add((p,v), &mut report);
+  }
+  let batch: Vec<Row<P,V>> = rows.into_iter()
+    .map(|row| {
+      let (p,v) = row.unwrap();
+      Row::Insert(p,v)
+    })
+    .collect();
+  report.written = batch.len();
+  out.batch(&batch)?;
+  Ok(report)
+}
@@ -0,0 +1,181 @@
+use crate::{DB,Point,Value,Location};
+use crate::lock::Lock;
+use crate::swap::{self,SwapRecord};
+use crate::wal::Wal;
+use crate::Error;
+use random_access_storage::RandomAccess;
+
+const CHUNK: u64 = 1 << 20;
+
+/// Counts from a `compact()`.
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct CompactReport {
+  /// Blocks rewritten without their deleted rows.
+  pub rewritten: usize,
+  /// Blocks moved to a lower offset as they were.
+  pub moved: usize,
+  /// Blocks dropped because every row in them was deleted.
+  pub removed: usize,
+  /// Size of the data store before compacting.
+  pub bytes_before: u64,
+  /// Size of the data store after compacting.
+  pub bytes_after: u64,
+  /// Changes removed from the changes feed by the retention policy.
+  pub changes_pruned: u64
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Reclaim the space of deleted rows and of data blocks that no tree
+  /// references anymore.
+  ///
+  /// Deletes only clear bits in block bitfields, and merges leave the blocks
+  /// of the merged trees behind. Compacting slides the live blocks to the
+  /// start of the data store, rewrites blocks with deleted rows without those
+  /// rows, drops blocks whose rows were all deleted, points the trees at the
+  /// new offsets, and truncates the data store. Staged deletes of rows in data
+  /// blocks are applied first, and changes past the limits of
+  /// `Setup::retention()` are removed from the changes feed.
+  ///
+  /// The database stays open and usable, but the locations of rows in data
+  /// blocks change, so get locations from a new query afterward. The
+  /// compacted data, range, summary, and tree stores are written to stores
+  /// with a `.next` suffix first and then copied over the live stores, so
+  /// compacting needs free space for a second copy of them. A crash before
+  /// the copy begins leaves the database as it was, and a crash during the
+  /// copy is finished when the database is opened again.
+  ///
+  /// In a database from `Setup::encrypted()` that was opened with old keys
+  /// after `rotate_key()`, every other store is read and sealed again with
+  /// the current key as well, so the old keys are no longer needed once
+  /// compacting succeeds.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut db: DB<_,_,((f32,f32),(f32,f32)),u32> = DB::open(storage)?;
+  /// let usage = db.disk_usage()?;
+  /// if usage.data_dead > usage.data_live {
+  ///   let report = db.compact()?;
+  ///   eprintln!["data store {} => {} bytes",
+  ///     report.bytes_before, report.bytes_after];
+  /// }
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn compact (&mut self) -> Result<CompactReport,Error> {
+    self.check_writable()?;
+    self.check_backup()?;
+    if self.fields.check_conflicts {
+      self.check_sequence()?;
+    }
+    let r = self.compact_stores();
+    self.poison_on_err(r)
+  }
+
+  fn compact_stores (&mut self) -> Result<CompactReport,Error> {
+    // rows in data blocks are about to get new locations
+    let (blocks,staged): (Vec<Location>,Vec<Location>) = self.staging.deletes
+      .read_lock()?.iter().partition(|loc| loc.0 > 0);
+    if !blocks.is_empty() {
+      self.data_store.write_lock()?.delete(&blocks)?;
+      self.staging.clear_deletes()?;
+      self.staging.batch(&vec![], &staged)?;
+      self.staging.commit()?;
+    }
+    let mut offsets = vec![];
+    for tree in self.trees.iter() {
+      let mut t = tree.write_lock()?;
+      if t.is_empty()? { continue }
+      offsets.extend(t.data_offsets()?);
+    }
+    // the compacted stores are written next to the live ones and replace
+    // them in one swap, so a crash leaves either the old or the new stores
+    let summaries: Vec<String> = self.data_store.read_lock()?.summaries.iter()
+      .map(|s| format!["summary_{}",s.name]).collect();
+    let compacted = self.data_store.write_lock()?.compact(
+      &offsets,
+      &mut swap::open_next(&self.open_store, "data")?,
+      &mut swap::open_next(&self.open_store, "range")?,
+      summaries.iter().map(|name| swap::open_next(&self.open_store, name))
+        .collect::<Result<_,_>>()?
+    )?;
+    let mut stores = vec![
+      ("data".to_string(), compacted.lens.0),
+      ("range".to_string(), compacted.lens.1)
+    ];
+    stores.extend(summaries.into_iter().zip(compacted.lens.2.iter().cloned()));
+    for (i,tree) in self.trees.iter().enumerate() {
+      let mut t = tree.write_lock()?;
+      if t.is_empty()? { continue }
+      let name = format!["tree{}",i];
+      let len = t.remap_data(&compacted.moves,
+        &mut swap::open_next(&self.open_store, &name)?)?;
+      stores.push((name,len));
+    }
+    if self.fields.check_conflicts {
+      self.check_sequence()?;
+    }
+    let mut quarantine: Vec<u64> = compacted.quarantine.iter().cloned().collect();
+    quarantine.sort_unstable();
+    self.meta.quarantine = quarantine;
+    self.meta.sequence += 1;
+    let heat = self.data_store.write_lock()?.heat.take().map(|mut heat| {
+      heat.remap(&compacted.moves);
+      heat
+    });
+    let swapped: Vec<String> = stores.iter().map(|(name,_)| name.clone()).collect();
+    swap::run(&self.open_store, &SwapRecord { stores, meta: Some(self.meta.to_bytes()) })?;
+    let resealed = self.reseal(&swapped);
+    self.reload()?;
+    if resealed? {
+      // handles that stay open across reloads saw their stores rewritten
+      self.open_change_log()?;
+      self.outbox = None;
+      if self.wal.is_some() {
+        self.wal = Some(Wal::open((self.open_store)("wal")?));
+      }
+    }
+    if heat.is_some() {
+      self.data_store.write_lock()?.heat = heat;
+    }
+    let mut report = compacted.report;
+    report.changes_pruned = self.prune_changes()?;
+    Ok(report)
+  }
+
+  // seal the stores that compacting didn't rewrite again with the current
+  // key while old keys from before `rotate_key()` are around, so that
+  // they're no longer needed afterward
+  fn reseal (&mut self, swapped: &[String]) -> Result<bool,Error> {
+    let rotated = match &self.fields.store_keys {
+      Some(keys) => !keys.read_lock()?.old_keys.is_empty(),
+      None => false
+    };
+    if !rotated { return Ok(false) }
+    let mut names = self.store_names()?;
+    // the outbox is opened on first use, so it may be on disk but not open
+    if !names.iter().any(|name| name == "outbox") { names.push("outbox".to_string()) }
+    for name in names.iter().filter(|name| !swapped.contains(name)) {
+      let mut store = (self.open_store)(name)?;
+      let len = store.len()?;
+      let mut offset = 0;
+      while offset < len {
+        let n = CHUNK.min(len - offset);
+        let buf = store.read(offset, n)?;
+        store.write(offset, &buf)?;
+        offset += n;
+      }
+      store.sync_all()?;
+    }
+    Ok(true)
+  }
+}
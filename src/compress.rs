@@ -0,0 +1,107 @@
+use crate::Error;
+
+/// Compression for the rows of new data blocks, set with
+/// `Setup::compression()`.
+///
+/// Each block records the codec it was written with, so blocks with
+/// different codecs (or none) can be read side by side and changing the
+/// setting doesn't rewrite existing blocks. Blocks whose rows don't shrink
+/// are stored uncompressed. The `Lz4` and `Zstd` codecs need the `lz4` and
+/// `zstd` features.
+#[derive(Clone,Copy,Debug,PartialEq,Default)]
+pub enum Compression {
+  /// Store rows as they are encoded (the default).
+  #[default]
+  None,
+  /// Fast compression with lz4.
+  Lz4,
+  /// Compression with zstd at the given level, from 1 (fastest) to 22.
+  Zstd(i32)
+}
+
+impl Compression {
+  // codec ids recorded in block headers
+  pub(crate) const LZ4: u8 = 1;
+  pub(crate) const ZSTD: u8 = 2;
+
+  /// Return an error if this build of eyros doesn't support the codec.
+  pub fn check (&self) -> Result<(),Error> {
+    match self {
+      Compression::None => Ok(()),
+      Compression::Lz4 => {
+        if cfg!(feature="lz4") { Ok(()) }
+        else { invalid!["lz4 compression requires the lz4 feature"] }
+      },
+      Compression::Zstd(_) => {
+        if cfg!(feature="zstd") { Ok(()) }
+        else { invalid!["zstd compression requires the zstd feature"] }
+      }
+    }
+  }
+
+  /// Compress `buf`, returning the codec id and the compressed bytes, or
+  /// `None` if compression is disabled or doesn't make `buf` smaller.
+  pub(crate) fn compress (&self, buf: &[u8]) -> Result<Option<(u8,Vec<u8>)>,Error> {
+    let (codec,out) = match self {
+      Compression::None => return Ok(None),
+      Compression::Lz4 => (Self::LZ4, lz4_compress(buf)?),
+      Compression::Zstd(level) => (Self::ZSTD, zstd_compress(buf, *level)?)
+    };
+    Ok(if out.len() < buf.len() { Some((codec,out)) } else { None })
+  }
+}
+
+/// Decompress `buf` written with the codec id `codec` back to `len` bytes.
+pub(crate) fn decompress (codec: u8, buf: &[u8], len: usize)
+-> Result<Vec<u8>,Error> {
+  let out = match codec {
+    Compression::LZ4 => lz4_decompress(buf, len)?,
+    Compression::ZSTD => zstd_decompress(buf, len)?,
+    _ => corrupt!["unsupported data block codec {}", codec]
+  };
+  if out.len() != len {
+    corrupt!["decompressed {} bytes but the block header expects {}", out.len(), len]
+  }
+  Ok(out)
+}
+
+#[cfg(feature="lz4")]
+fn lz4_compress (buf: &[u8]) -> Result<Vec<u8>,Error> {
+  Ok(lz4_flex::block::compress(buf))
+}
+
+#[cfg(feature="lz4")]
+fn lz4_decompress (buf: &[u8], len: usize) -> Result<Vec<u8>,Error> {
+  lz4_flex::block::decompress(buf, len)
+    .map_err(|e| Error::Corrupt(format!["lz4 block: {}", e]))
+}
+
+#[cfg(not(feature="lz4"))]
+fn lz4_compress (_buf: &[u8]) -> Result<Vec<u8>,Error> {
+  invalid!["lz4 compression requires the lz4 feature"]
+}
+
+#[cfg(not(feature="lz4"))]
+fn lz4_decompress (_buf: &[u8], _len: usize) -> Result<Vec<u8>,Error> {
+  invalid!["reading lz4 compressed blocks requires the lz4 feature"]
+}
+
+#[cfg(feature="zstd")]
+fn zstd_compress (buf: &[u8], level: i32) -> Result<Vec<u8>,Error> {
+  Ok(zstd::bulk::compress(buf, level)?)
+}
+
+#[cfg(feature="zstd")]
+fn zstd_decompress (buf: &[u8], len: usize) -> Result<Vec<u8>,Error> {
+  Ok(zstd::bulk::decompress(buf, len)?)
+}
+
+#[cfg(not(feature="zstd"))]
+fn zstd_compress (_buf: &[u8], _level: i32) -> Result<Vec<u8>,Error> {
+  invalid!["zstd compression requires the zstd feature"]
+}
+
+#[cfg(not(feature="zstd"))]
+fn zstd_decompress (_buf: &[u8], _len: usize) -> Result<Vec<u8>,Error> {
+  invalid!["reading zstd compressed blocks requires the zstd feature"]
+}
@@ -0,0 +1,18 @@
+/// How up to date query results must be when several handles share the same
+/// storage, such as read replicas serving queries while a leader ingests.
+///
+/// Freshness is measured with commit sequence numbers: `db.sequence()` on the
+/// writer after a `batch()` is a token that readers can wait for. Every
+/// batch advances the sequence, whether it builds trees or only stages rows.
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Default)]
+pub enum Consistency {
+  /// Read whatever this handle has loaded, without touching storage first.
+  #[default]
+  Local,
+  /// Reload from storage first when this handle is more than `max_lag`
+  /// commits behind.
+  BoundedStaleness { max_lag: u64 },
+  /// Reload from storage first when this handle hasn't seen the commit with
+  /// this sequence number, and fail with `Stale` if storage hasn't either.
+  ReadYourWrites(u64)
+}
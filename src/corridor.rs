@@ -0,0 +1,240 @@
+use crate::{DB,Point,Value,Location,QueryIterator,CacheMode,AuditOp};
+use crate::Error;
+use random_access_storage::RandomAccess;
+use std::collections::HashSet;
+use std::f64::consts::PI;
+
+/// Mean radius of the earth in meters.
+pub const EARTH_RADIUS: f64 = 6_371_008.8;
+
+// longest piece of a segment covered by one bbox, in radians (about 1°)
+const MAX_PIECE: f64 = PI / 180.0;
+
+/// Points stored as `(longitude,latitude)` in degrees, such as EPSG:4326
+/// coordinates, for geodesic queries like `db.query_corridor()`.
+pub trait LonLat: Point {
+  /// Return the `(longitude,latitude)` of this point in degrees.
+  fn lon_lat (&self) -> (f64,f64);
+  /// Create a bounding box from `(longitude,latitude)` corners in degrees.
+  fn lon_lat_bounds (min: (f64,f64), max: (f64,f64)) -> Self::Bounds;
+}
+
+macro_rules! impl_lon_lat {
+  ($($T:ty),+) => {$(
+    impl LonLat for ($T,$T) {
+      fn lon_lat (&self) -> (f64,f64) { (self.0 as f64, self.1 as f64) }
+      fn lon_lat_bounds (min: (f64,f64), max: (f64,f64)) -> Self::Bounds {
+        ((min.0 as $T, min.1 as $T),(max.0 as $T, max.1 as $T))
+      }
+    }
+  )+}
+}
+impl_lon_lat![f32,f64];
+
+// unit vector for a (longitude,latitude) pair in degrees
+fn to_vec (p: (f64,f64)) -> [f64;3] {
+  let (lon,lat) = (p.0.to_radians(), p.1.to_radians());
+  [lat.cos()*lon.cos(), lat.cos()*lon.sin(), lat.sin()]
+}
+
+fn from_vec (v: [f64;3]) -> (f64,f64) {
+  let lon = v[1].atan2(v[0]).to_degrees();
+  let lat = v[2].atan2((v[0]*v[0]+v[1]*v[1]).sqrt()).to_degrees();
+  (lon,lat)
+}
+
+fn dot (a: &[f64;3], b: &[f64;3]) -> f64 {
+  a[0]*b[0] + a[1]*b[1] + a[2]*b[2]
+}
+
+fn cross (a: &[f64;3], b: &[f64;3]) -> [f64;3] {
+  [a[1]*b[2]-a[2]*b[1], a[2]*b[0]-a[0]*b[2], a[0]*b[1]-a[1]*b[0]]
+}
+
+fn angle (a: &[f64;3], b: &[f64;3]) -> f64 {
+  let c = cross(a,b);
+  dot(&c,&c).sqrt().atan2(dot(a,b))
+}
+
+/// Great-circle distance in meters between two `(longitude,latitude)` points.
+pub fn haversine (a: (f64,f64), b: (f64,f64)) -> f64 {
+  EARTH_RADIUS * angle(&to_vec(a), &to_vec(b))
+}
+
+/// Great-circle distance in meters from `p` to the nearest point on the
+/// shortest arc between `a` and `b`.
+pub fn segment_distance (p: (f64,f64), a: (f64,f64), b: (f64,f64)) -> f64 {
+  let (p,va,vb) = (to_vec(p), to_vec(a), to_vec(b));
+  let n = cross(&va,&vb);
+  let nn = dot(&n,&n).sqrt();
+  let endpoints = angle(&p,&va).min(angle(&p,&vb));
+  if nn < 1e-15 { return EARTH_RADIUS * endpoints } // a and b coincide
+  let n = [n[0]/nn, n[1]/nn, n[2]/nn];
+  // p projected onto the great circle through a and b
+  let d = dot(&p,&n);
+  let q = [p[0]-d*n[0], p[1]-d*n[1], p[2]-d*n[2]];
+  let within = dot(&cross(&va,&q),&n) >= 0.0 && dot(&cross(&q,&vb),&n) >= 0.0;
+  if within && dot(&q,&q) > 0.0 {
+    EARTH_RADIUS * d.abs().min(1.0).asin()
+  } else {
+    EARTH_RADIUS * endpoints
+  }
+}
+
+/// Return `(min,max)` longitude/latitude boxes that together cover every
+/// point within `width` meters of the `polyline`. Boxes that cross the
+/// antimeridian are split in two.
+pub fn corridor_boxes (polyline: &[(f64,f64)], width: f64)
+-> Vec<((f64,f64),(f64,f64))> {
+  let mut boxes = vec![];
+  let pad = width / EARTH_RADIUS;
+  let mut add = |min_lon: f64, min_lat: f64, max_lon: f64, max_lat: f64| {
+    let (min_lat,max_lat) = (min_lat.max(-90.0), max_lat.min(90.0));
+    if min_lon < -180.0 {
+      boxes.push(((min_lon+360.0,min_lat),(180.0,max_lat)));
+    }
+    if max_lon > 180.0 {
+      boxes.push(((-180.0,min_lat),(max_lon-360.0,max_lat)));
+    }
+    boxes.push(((min_lon.max(-180.0),min_lat),(max_lon.min(180.0),max_lat)));
+  };
+  let pairs = polyline.windows(2).map(|w| (w[0],w[1]))
+    .chain(if polyline.len() == 1 { Some((polyline[0],polyline[0])) } else { None });
+  for (a,b) in pairs {
+    let (va,vb) = (to_vec(a), to_vec(b));
+    let total = angle(&va,&vb);
+    let n = ((total / MAX_PIECE).ceil() as usize).max(1);
+    let mut points = Vec::with_capacity(n+1);
+    for i in 0..=n {
+      // spherical interpolation along the arc
+      let t = (i as f64) / (n as f64);
+      let p = if total < 1e-12 { va } else {
+        let (s0,s1) = (((1.0-t)*total).sin(), (t*total).sin());
+        let s = total.sin();
+        [
+          (s0*va[0]+s1*vb[0])/s, (s0*va[1]+s1*vb[1])/s, (s0*va[2]+s1*vb[2])/s
+        ]
+      };
+      points.push(from_vec(p));
+    }
+    // between samples the arc strays at most piece²/8 from the chord
+    let piece = total / (n as f64);
+    let lat_pad = (pad + piece*piece/8.0).to_degrees() * 1.000_001;
+    for w in points.windows(2) {
+      let (p0,p1) = (w[0],w[1]);
+      let min_lat = p0.1.min(p1.1) - lat_pad;
+      let max_lat = p0.1.max(p1.1) + lat_pad;
+      let max_abs = min_lat.abs().max(max_lat.abs());
+      if max_abs >= 90.0 {
+        add(-180.0, min_lat, 180.0, max_lat);
+        continue
+      }
+      let lon_pad = lat_pad / max_abs.to_radians().cos();
+      let (mut l0, mut l1) = (p0.0.min(p1.0), p0.0.max(p1.0));
+      if l1 - l0 > 180.0 { // piece crosses the antimeridian
+        let (lo,hi) = (l1, l0 + 360.0);
+        l0 = lo;
+        l1 = hi;
+      }
+      if l1 - l0 + 2.0*lon_pad >= 360.0 {
+        add(-180.0, min_lat, 180.0, max_lat);
+      } else {
+        add(l0 - lon_pad, min_lat, l1 + lon_pad, max_lat);
+      }
+    }
+  }
+  boxes
+}
+
+/// Iterator of `Result<(Point,Value,Location)>` data returned by
+/// `db.query_corridor()`.
+pub struct CorridorIterator<'b,S,P,V> where
+S: RandomAccess<Error=failure::Error>, P: LonLat, V: Value {
+  queries: Vec<QueryIterator<'b,S,P,V>>,
+  polyline: Vec<(f64,f64)>,
+  width: f64,
+  seen: HashSet<Location>
+}
+
+impl<'b,S,P,V> CorridorIterator<'b,S,P,V> where
+S: RandomAccess<Error=failure::Error>, P: LonLat, V: Value {
+  fn within (&self, point: &P) -> bool {
+    let p = point.lon_lat();
+    if self.polyline.len() == 1 {
+      return haversine(p, self.polyline[0]) <= self.width;
+    }
+    self.polyline.windows(2).any(|w| segment_distance(p, w[0], w[1]) <= self.width)
+  }
+}
+
+impl<'b,S,P,V> Iterator for CorridorIterator<'b,S,P,V> where
+S: RandomAccess<Error=failure::Error>, P: LonLat, V: Value {
+  type Item = Result<(P,V,Location),Error>;
+  fn next (&mut self) -> Option<Self::Item> {
+    while let Some(query) = self.queries.last_mut() {
+      match query.next() {
+        None => { self.queries.pop(); },
+        Some(Err(e)) => return Some(Err(e)),
+        Some(Ok(row)) => {
+          // boxes overlap, so a row can come up more than once
+          if self.seen.contains(&row.2) || !self.within(&row.0) { continue }
+          self.seen.insert(row.2);
+          return Some(Ok(row));
+        }
+      }
+    }
+    None
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: LonLat, V: Value {
+  /// Query for points within `width` meters of a route along the
+  /// `(longitude,latitude)` vertices of `polyline`, following great circles
+  /// between vertices.
+  ///
+  /// The buffered route is covered with bounding boxes to prune the search
+  /// and each candidate is then checked against its exact distance to the
+  /// route.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut db: DB<_,_,(f64,f64),u32> = DB::open(storage)?;
+  /// let route = vec![(13.37,52.51),(13.40,52.52),(13.45,52.50)];
+  /// for result in db.query_corridor(&route, 500.0)? {
+  ///   let (point,value,location) = result?;
+  ///   // ...
+  /// }
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn query_corridor<'b> (&mut self, polyline: &[(f64,f64)], width: f64)
+  -> Result<CorridorIterator<'b,S,P,V>,Error> {
+    if polyline.is_empty() {
+      invalid!["corridor polyline is empty"]
+    }
+    if width < 0.0 {
+      invalid!["corridor width must not be negative"]
+    }
+    let mut queries = vec![];
+    for (min,max) in corridor_boxes(polyline, width).iter().rev() {
+      let bbox = P::lon_lat_bounds(*min, *max);
+      queries.push(self.query_mode(&bbox, CacheMode::Normal, Some(AuditOp::Query))?);
+    }
+    Ok(CorridorIterator {
+      queries,
+      polyline: polyline.to_vec(),
+      width,
+      seen: HashSet::new()
+    })
+  }
+}
@@ -0,0 +1,115 @@
+use crate::{DB,Point,Value};
+use crate::lock::Lock;
+use crate::Error;
+use random_access_storage::RandomAccess;
+
+/// Predicted work of a query over a bounding box, returned by
+/// `db.estimate_cost()`.
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct QueryCost {
+  /// Data blocks whose bounds intersect the bounding box. A query reads each
+  /// of them unless it is in the block cache.
+  pub blocks: u64,
+  /// Bytes of those data blocks.
+  pub bytes: u64,
+  /// Rows written to those data blocks plus the staged rows that intersect
+  /// the bounding box. No query returns more rows than this.
+  pub rows: u64,
+  /// Rows written to data blocks that lie entirely inside of the bounding
+  /// box plus the staged rows that intersect it. Rows deleted from the blocks
+  /// since they were written are still included.
+  pub rows_inside: u64
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Predict how many blocks, bytes, and rows a query over `bbox` would read
+  /// without reading any data blocks.
+  ///
+  /// The estimate walks the branches of each tree and reads the block sizes
+  /// and row counts from the range metadata, so it is much cheaper than the
+  /// query itself. Use it to reject or queue queries that would read too much
+  /// before they spend any IO on data blocks. Block sizes are measured up to
+  /// the next block in the data store, so blocks followed by space that
+  /// `compact()` would reclaim count as larger than they are.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::DB;
+  /// # use failure::{Error,bail};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut db: DB<_,_,(f32,f32),u32> = DB::open(storage)?;
+  /// let bbox = ((-0.5,-0.8),(0.3,-0.5));
+  /// let cost = db.estimate_cost(&bbox)?;
+  /// if cost.bytes > 50_000_000 {
+  ///   bail!["query would read {} bytes", cost.bytes];
+  /// }
+  /// for result in db.query(&bbox)? {
+  ///   println!["{:?}", result?];
+  /// }
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn estimate_cost (&mut self, bbox: &P::Bounds) -> Result<QueryCost,Error> {
+    self.check_open()?;
+    let mut cost = QueryCost::default();
+    {
+      let deletes = self.staging.delete_set.read_lock()?;
+      let inserts = self.staging.inserts.read_lock()?;
+      let staged = inserts.iter().enumerate()
+        .filter(|(i,(p,_))| !deletes.contains(&(0,*i as u32)) && p.overlaps(bbox))
+        .count() as u64;
+      cost.rows += staged;
+      cost.rows_inside += staged;
+    }
+    let offsets = self.block_offsets(bbox)?;
+    let blocks = self.block_costs(vec![offsets], bbox)?.remove(0);
+    cost.blocks = blocks.blocks;
+    cost.bytes = blocks.bytes;
+    cost.rows += blocks.rows;
+    cost.rows_inside += blocks.rows_inside;
+    Ok(cost)
+  }
+
+  // Cost of reading each group of data blocks from the range metadata alone.
+  pub(crate) fn block_costs (&mut self, groups: Vec<Vec<u64>>, bbox: &P::Bounds)
+  -> Result<Vec<QueryCost>,Error> {
+    let mut dstore = self.data_store.write_lock()?;
+    let entries = dstore.block_entries()?;
+    // blocks are appended one after another, so each block ends where the
+    // next one starts
+    let mut starts: Vec<u64> = entries.keys().copied().collect();
+    starts.sort_unstable();
+    let end = dstore.store_bytes()?.0;
+    let mut costs = Vec::with_capacity(groups.len());
+    for offsets in groups {
+      let mut cost = QueryCost::default();
+      for offset in offsets {
+        if dstore.quarantine.contains(&offset) { continue }
+        let (range,rows) = match entries.get(&offset) {
+          Some(entry) => entry,
+          None => continue
+        };
+        let next = match starts.binary_search(&offset) {
+          Ok(i) => starts.get(i+1).copied().unwrap_or(end),
+          Err(_) => end
+        };
+        cost.blocks += 1;
+        cost.bytes += next.max(offset) - offset;
+        cost.rows += rows;
+        if P::range_within(range, bbox) {
+          cost.rows_inside += rows;
+        }
+      }
+      costs.push(cost);
+    }
+    Ok(costs)
+  }
+}
@@ -0,0 +1,283 @@
+use crate::{DB,Point,Value,QueryIterator,SubIterator,CacheMode};
+use crate::error::StaleCursor;
+use crate::prune::PruneState;
+use crate::stats::Timer;
+use crate::tree::Tree;
+use crate::lock::Lock;
+use desert::{ToBytes,FromBytes};
+use crate::Error;
+use random_access_storage::RandomAccess;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+// Position of one tree iterator: branches and data blocks left to read, and
+// the block being read with the index of its next row.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub(crate) struct TreeCursor {
+  pub slot: u32,
+  pub started: bool,
+  pub cursors: Vec<(u64,u32)>,
+  pub blocks: Vec<u64>,
+  pub block: Option<(u64,u32)>
+}
+
+/// Position of a query between two results, from `QueryIterator::cursor()`,
+/// to continue the query later with `db.query_resume()`.
+///
+/// A cursor holds the query's bounding box and what is left to read: the
+/// position in the staging area and, for each tree, the branches and data
+/// blocks still to visit and the next row of the block being read. Resuming
+/// reads none of the blocks that earlier pages finished, so a long scan can
+/// be served a page at a time, such as one page per HTTP request.
+///
+/// Cursors are stamped with the sequence number of the commit they were
+/// taken at and fail with `StaleCursor` once the database has moved on.
+/// Every batch and delete advances the sequence number.
+///
+/// The encoding starts with the `VERSION` byte. `Display` and `FromStr` use
+/// the same bytes in lowercase hex.
+///
+/// ```rust,no_run
+/// # use eyros::{DB,QueryCursor,Error};
+/// # use std::path::PathBuf;
+/// # use random_access_disk::RandomAccessDisk;
+/// # type P = (f32,f32);
+/// # fn main () -> Result<(),Error> {
+/// # let mut db: DB<_,_,P,u32> = DB::open(storage)?;
+/// let bbox = ((-0.5,-0.8),(0.3,-0.5));
+/// let mut page = db.query(&bbox)?.limit(100);
+/// let first: Vec<_> = page.by_ref().collect::<Result<_,_>>()?;
+/// // send `first` and `token` to the client
+/// let token = page.cursor().unwrap().to_string();
+/// drop(page);
+///
+/// // on the next request
+/// let cursor: QueryCursor<P> = token.parse()?;
+/// let second: Vec<_> = db.query_resume(&cursor)?.limit(100)
+///   .collect::<Result<_,_>>()?;
+/// # Ok(()) }
+/// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+/// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+/// #   p.push(name);
+/// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+/// # }
+/// ```
+#[derive(Clone,Debug)]
+pub struct QueryCursor<P> where P: Point {
+  /// Sequence number of the commit the cursor was taken at, from
+  /// `db.sequence()`.
+  pub generation: u64,
+  /// Bounding box of the query.
+  pub bbox: P::Bounds,
+  pub(crate) staging: Option<u32>,
+  pub(crate) trees: Vec<TreeCursor>
+}
+
+impl<P> QueryCursor<P> where P: Point {
+  /// Version of the encoding written by `encode()`.
+  pub const VERSION: u8 = 1;
+
+  /// Whether the query had returned every result when the cursor was taken.
+  pub fn is_done (&self) -> bool {
+    self.staging.is_none() && self.trees.is_empty()
+  }
+  pub fn encode (&self) -> Result<Vec<u8>,Error> {
+    let mut buf = vec![Self::VERSION];
+    buf.extend(&self.generation.to_be_bytes());
+    buf.extend(self.bbox.to_bytes()?);
+    match self.staging {
+      Some(i) => { buf.push(1); buf.extend(&i.to_be_bytes()) },
+      None => buf.push(0)
+    }
+    buf.extend(&(self.trees.len() as u32).to_be_bytes());
+    for t in self.trees.iter() {
+      buf.extend(&t.slot.to_be_bytes());
+      buf.push(t.started as u8);
+      buf.extend(&(t.cursors.len() as u32).to_be_bytes());
+      for (offset,depth) in t.cursors.iter() {
+        buf.extend(&offset.to_be_bytes());
+        buf.extend(&depth.to_be_bytes());
+      }
+      buf.extend(&(t.blocks.len() as u32).to_be_bytes());
+      for offset in t.blocks.iter() {
+        buf.extend(&offset.to_be_bytes());
+      }
+      match t.block {
+        Some((offset,index)) => {
+          buf.push(1);
+          buf.extend(&offset.to_be_bytes());
+          buf.extend(&index.to_be_bytes());
+        },
+        None => buf.push(0)
+      }
+    }
+    Ok(buf)
+  }
+  /// Decode a cursor written by `encode()`. Fails for encodings of other
+  /// versions.
+  pub fn decode (buf: &[u8]) -> Result<Self,Error> {
+    if buf.is_empty() {
+      invalid!["empty query cursor"]
+    }
+    if buf[0] != Self::VERSION {
+      invalid!["unsupported query cursor encoding version {}", buf[0]];
+    }
+    let mut r = Reader { buf, offset: 1 };
+    let generation = r.u64()?;
+    let (size,bbox) = P::Bounds::from_bytes(&buf[r.offset..])?;
+    r.offset += size;
+    let staging = if r.u8()? == 1 { Some(r.u32()?) } else { None };
+    let mut trees = vec![];
+    for _ in 0..r.u32()? {
+      let slot = r.u32()?;
+      let started = r.u8()? == 1;
+      let mut cursors = vec![];
+      for _ in 0..r.u32()? {
+        cursors.push((r.u64()?,r.u32()?));
+      }
+      let mut blocks = vec![];
+      for _ in 0..r.u32()? {
+        blocks.push(r.u64()?);
+      }
+      let block = if r.u8()? == 1 { Some((r.u64()?,r.u32()?)) } else { None };
+      trees.push(TreeCursor { slot, started, cursors, blocks, block });
+    }
+    if r.offset != buf.len() {
+      invalid!["trailing bytes after query cursor"]
+    }
+    Ok(Self { generation, bbox, staging, trees })
+  }
+}
+
+struct Reader<'a> {
+  buf: &'a [u8],
+  offset: usize
+}
+
+impl<'a> Reader<'a> {
+  fn take (&mut self, n: usize) -> Result<&'a [u8],Error> {
+    if self.offset + n > self.buf.len() {
+      invalid!["query cursor is truncated"]
+    }
+    let b = &self.buf[self.offset..self.offset+n];
+    self.offset += n;
+    Ok(b)
+  }
+  fn u8 (&mut self) -> Result<u8,Error> {
+    Ok(self.take(1)?[0])
+  }
+  fn u32 (&mut self) -> Result<u32,Error> {
+    let b = self.take(4)?;
+    Ok(u32::from_be_bytes([b[0],b[1],b[2],b[3]]))
+  }
+  fn u64 (&mut self) -> Result<u64,Error> {
+    let mut b = [0u8;8];
+    b.copy_from_slice(self.take(8)?);
+    Ok(u64::from_be_bytes(b))
+  }
+}
+
+impl<P> fmt::Display for QueryCursor<P> where P: Point {
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for b in self.encode().map_err(|_| fmt::Error)?.iter() {
+      write!(f, "{:02x}", b)?;
+    }
+    Ok(())
+  }
+}
+
+impl<P> FromStr for QueryCursor<P> where P: Point {
+  type Err = Error;
+  fn from_str (s: &str) -> Result<Self,Error> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+      invalid!["query cursor needs an even number of hex digits"]
+    }
+    let mut buf = vec![0u8;s.len()/2];
+    for (i,b) in buf.iter_mut().enumerate() {
+      *b = u8::from_str_radix(&s[i*2..i*2+2], 16)
+        .map_err(|e| Error::Invalid(format!["query cursor: {}", e]))?;
+    }
+    Self::decode(&buf)
+  }
+}
+
+impl<'b,S,P,V> QueryIterator<'b,S,P,V> where
+S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  /// Return a cursor to continue the query after the last result returned so
+  /// far with `db.query_resume()`. See `QueryCursor`.
+  ///
+  /// Returns `None` for queries that can't be resumed: queries read with
+  /// `Setup::query_threads()` and queries that didn't come from `db.query()`
+  /// or `db.query_resume()`.
+  pub fn cursor (&self) -> Option<QueryCursor<P>> {
+    if let Some(cursor) = &self.resume { return Some(cursor.clone()) }
+    let (generation,bbox) = match (self.sequence,self.bbox) {
+      (Some(sequence),Some(bbox)) => (sequence,bbox),
+      _ => return None
+    };
+    let mut cursor = QueryCursor { generation, bbox, staging: None, trees: vec![] };
+    for q in self.queries.iter() {
+      match q {
+        SubIterator::Staging(x) => cursor.staging = Some(x.position()),
+        SubIterator::Tree(x) => cursor.trees.push(x.as_ref().position()?),
+        SubIterator::Parallel(_) => return None
+      }
+    }
+    Some(cursor)
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Continue a query from a cursor returned by `QueryIterator::cursor()`.
+  ///
+  /// Fails with `StaleCursor` if the sequence number of the database changed
+  /// since the cursor was taken, since trees may have been rebuilt then.
+  /// Call `refresh()` first if another handle writes to the same storage.
+  pub fn query_resume<'b> (&mut self, cursor: &'b QueryCursor<P>)
+  -> Result<QueryIterator<'b,S,P,V>,Error> {
+    self.check_open()?;
+    if cursor.generation != self.meta.sequence {
+      return Err(StaleCursor {
+        generation: cursor.generation,
+        sequence: self.meta.sequence
+      }.into());
+    }
+    let permit = self.admit()?;
+    let audit = self.audit_query(&cursor.bbox);
+    let timer = Timer::new(self.counters()?, Arc::clone(&self.fields.clock));
+    let prune = match self.pruner.clone() {
+      Some(pruner) => Some(Arc::new(PruneState::load(
+        pruner, &mut *self.data_store.write_lock()?)?)),
+      None => None
+    };
+    let mut queries = Vec::with_capacity(1+cursor.trees.len());
+    if let Some(index) = cursor.staging {
+      queries.push(SubIterator::Staging(
+        self.staging.query(&cursor.bbox).resume(index)));
+    }
+    for t in cursor.trees.iter() {
+      let slot = t.slot as usize;
+      if slot >= self.trees.len() {
+        invalid!["query cursor refers to missing tree {}", slot]
+      }
+      let iter = Tree::query(Arc::clone(&self.trees[slot]), &cursor.bbox)?
+        .cache_mode(CacheMode::Normal)
+        .prune(prune.clone())
+        .resume(t)?;
+      queries.push(SubIterator::Tree(Box::new(iter)));
+    }
+    let mut iter = QueryIterator::new(queries, Arc::clone(&self.staging.delete_set))?;
+    iter.permit = permit;
+    iter.open_trees = self.fields.max_open_trees;
+    iter.buffered_blocks = self.fields.max_buffered_blocks;
+    iter.timer = Some(timer);
+    iter.clock = Arc::clone(&self.fields.clock);
+    iter.bbox = Some(cursor.bbox);
+    iter.sequence = Some(self.meta.sequence);
+    Ok(iter.visibility(self.visible.clone()).audit(audit))
+  }
+}
@@ -1,150 +1,734 @@
-use crate::{Point,Value,Location,read_block::read_block};
+use crate::{Point,Value,Location,RetryPolicy,Clock,default_clock,BlockHeat,
+  ChecksumMismatch,Compression,CompactReport,read_block::read_block,
+  summary::SummaryStore,compress::decompress,encrypt::Keyring,Codec,DesertCodec,
+  stats::{Counted,Counters},cache::{BlockCache,SharedCache,Rows},backup::Journal};
 use random_access_storage::RandomAccess;
-use failure::{Error,ensure,bail};
-use std::rc::Rc;
-use std::cell::RefCell;
+use crate::Error;
+use std::sync::{Arc,RwLock};
+use crate::lock::Lock;
 use lru::LruCache;
-use std::collections::HashMap;
-use desert::{FromBytes,ToBytes,CountBytes};
+use std::collections::{HashMap,HashSet};
+use std::ops::ControlFlow;
+use std::borrow::Cow;
+use std::mem::size_of;
+use std::marker::PhantomData;
+use desert::{FromBytes,ToBytes};
 
 pub trait DataBatch<P,V> where P: Point, V: Value {
   fn batch (&mut self, rows: &Vec<&(P,V)>) -> Result<u64,Error>;
 }
 
 pub struct DataMerge<S,P,V>
-where S: RandomAccess<Error=Error>, P: Point, V: Value {
-  data_store: Rc<RefCell<DataStore<S,P,V>>>
+where S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  data_store: Arc<RwLock<DataStore<S,P,V>>>
 }
 
 impl<S,P,V> DataMerge<S,P,V>
-where S: RandomAccess<Error=Error>, P: Point, V: Value {
-  pub fn new (data_store: Rc<RefCell<DataStore<S,P,V>>>) -> Self {
+where S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  pub fn new (data_store: Arc<RwLock<DataStore<S,P,V>>>) -> Self {
     Self { data_store }
   }
 }
 
 impl<S,P,V> DataBatch<P::Range,u64> for DataMerge<S,P,V>
-where S: RandomAccess<Error=Error>, P: Point, V: Value {
+where S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
   fn batch (&mut self, rows: &Vec<&(P::Range,u64)>) -> Result<u64,Error> {
     if rows.len() == 1 { // use existing address
       Ok(rows[0].1)
     } else { // combine addresses into a new block
-      let mut dstore = self.data_store.try_borrow_mut()?;
+      let mut dstore = self.data_store.write_lock()?;
       let max = dstore.max_data_size;
+      let mode = dstore.maintenance_cache;
       let mut combined: Vec<(P,V)> = vec![];
       for row in rows {
-        let pvs: Vec<(P,V)> = dstore.list(row.1)?.iter().map(|c| {
+        combined.extend(dstore.list_mode(row.1, mode)?.iter().map(|c| {
           (c.0, c.1.clone())
-        }).collect();
-        combined.extend(pvs);
+        }));
+      }
+      if combined.len() > max {
+        return Err(Error::DataSizeLimit { rows: combined.len(), max });
       }
-      ensure![combined.len() <= max, "data size limit exceeded in data merge"];
       dstore.batch(&combined.iter().collect())
     }
   }
 }
 
+/// How a read interacts with the block caches.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum CacheMode {
+  /// Read through the caches, inserting blocks that were missing and marking
+  /// hits as recently used.
+  Normal,
+  /// Use blocks that are already cached but never insert or promote entries,
+  /// so one-shot scans don't evict the working set of regular queries.
+  Bypass
+}
+
+/// Result of `DataStore::compact()`.
+pub(crate) struct Compacted {
+  /// New offset of each block, or `None` for blocks that were dropped.
+  pub moves: HashMap<u64,Option<u64>>,
+  pub report: CompactReport,
+  /// Offsets of the quarantined blocks in the new data store.
+  pub quarantine: HashSet<u64>,
+  /// Lengths of the new data, range, and summary stores.
+  pub lens: (u64,u64,Vec<u64>)
+}
+
+pub(crate) type QuarantineFn = Arc<dyn Fn(u64,&Error) + Send + Sync>;
+
+// Set in the bitfield length of blocks with an extended header: a flag byte
+// after the bitfield, followed by the fields that the flags enable.
+pub(crate) const EXTENDED: u16 = 0x8000;
+// Header flag for a crc32 checksum of the rows, as they are stored.
+const CHECKSUM: u8 = 0x01;
+// Header flag for compressed rows, followed by the codec id (u8) and the
+// uncompressed length of the rows (u32).
+const COMPRESSED: u8 = 0x02;
+// Header flag for encrypted rows, followed by the key epoch (u32). Rows are
+// compressed before they are encrypted.
+const ENCRYPTED: u8 = 0x04;
+
+// Positions in a block buffer as returned by `DataStore::read()`, which
+// strips the length field.
+pub(crate) struct Layout {
+  pub rows: usize,
+  pub checksum: Option<(usize,u32)>,
+  pub compressed: Option<(u8,usize)>,
+  pub encrypted: Option<u32>
+}
+
+// Number of live rows in the block in `buf` according to its bitfield, to
+// size the vectors that rows are parsed into.
+fn live_rows (buf: &[u8]) -> usize {
+  if buf.len() < 2 { return 0 }
+  let field = u16::from_be_bytes([buf[0],buf[1]]);
+  let end = (2 + (field & !EXTENDED) as usize).min(buf.len());
+  buf[2..end].iter().map(|b| b.count_ones() as usize).sum()
+}
+
+// Whether the store couldn't serve a read right now, such as a page of a
+// remote store that isn't fetched yet, which says nothing about the block.
+fn would_block (err: &Error) -> bool {
+  err.downcast_ref::<std::io::Error>()
+    .map(|e| e.kind() == std::io::ErrorKind::WouldBlock)
+    .unwrap_or(false)
+}
+
+pub(crate) fn layout (buf: &[u8]) -> Result<Layout,Error> {
+  ensure![buf.len() >= 2, "data block is too small"];
+  let field = u16::from_be_bytes([buf[0],buf[1]]);
+  let bitfield_len = (field & !EXTENDED) as usize;
+  let mut offset = 2 + bitfield_len;
+  if field & EXTENDED == 0 {
+    ensure![offset <= buf.len(), "bitfield past the end of the data block"];
+    return Ok(Layout {
+      rows: offset, checksum: None, compressed: None, encrypted: None
+    });
+  }
+  ensure![offset < buf.len(), "header past the end of the data block"];
+  let flags = buf[offset];
+  offset += 1;
+  let mut checksum = None;
+  if flags & CHECKSUM != 0 {
+    ensure![offset + 4 <= buf.len(), "header past the end of the data block"];
+    let c = &buf[offset..offset+4];
+    checksum = Some((offset,u32::from_be_bytes([c[0],c[1],c[2],c[3]])));
+    offset += 4;
+  }
+  let mut compressed = None;
+  if flags & COMPRESSED != 0 {
+    ensure![offset + 5 <= buf.len(), "header past the end of the data block"];
+    let c = &buf[offset+1..offset+5];
+    let len = u32::from_be_bytes([c[0],c[1],c[2],c[3]]) as usize;
+    compressed = Some((buf[offset],len));
+    offset += 5;
+  }
+  let mut encrypted = None;
+  if flags & ENCRYPTED != 0 {
+    ensure![offset + 4 <= buf.len(), "header past the end of the data block"];
+    let c = &buf[offset..offset+4];
+    encrypted = Some(u32::from_be_bytes([c[0],c[1],c[2],c[3]]));
+    offset += 4;
+  }
+  if flags & !(CHECKSUM|COMPRESSED|ENCRYPTED) != 0 {
+    corrupt!["unsupported data block flags {:#x}", flags]
+  }
+  Ok(Layout { rows: offset, checksum, compressed, encrypted })
+}
+
+// Call `f` with each live row of the decoded `rows` of a block and its index,
+// where `bitfield` starts with the block's bitfield.
+pub(crate) fn each_row<P,V,F> (codec: &dyn Codec<P,V>, bitfield: &[u8],
+rows: &[u8], mut f: F) -> Result<(),Error>
+where P: Point, V: Value, F: FnMut(P,V,u32) {
+  let mut offset = 0;
+  let mut index = 0;
+  while offset < rows.len() {
+    if ((bitfield[index/8]>>(index%8))&1) == 1 {
+      let (size,(p,v)) = codec.deserialize(&rows[offset..])?;
+      f(p, v, index as u32);
+      offset += size;
+    } else {
+      offset += codec.take_bytes(&rows[offset..])?;
+    }
+    index += 1;
+  }
+  Ok(())
+}
+
 //#[derive(Debug,Clone)]
 pub struct DataStore<S,P,V>
-where S: RandomAccess<Error=Error>, P: Point, V: Value {
-  store: S,
+where S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  store: Counted<S>,
   range: DataRange<S,P>,
-  list_cache: LruCache<u64,Vec<(P,V,Location)>>,
-  pub max_data_size: usize
+  pub(crate) counters: Arc<Counters>,
+  list_cache: BlockCache<P,V>,
+  pub max_data_size: usize,
+  /// Cache mode for sequential maintenance reads such as tree merges.
+  pub maintenance_cache: CacheMode,
+  /// Offsets of data blocks that failed to load and are skipped by queries.
+  pub quarantine: HashSet<u64>,
+  /// Called with the offset and the error of each newly quarantined block.
+  pub(crate) on_quarantine: Option<QuarantineFn>,
+  /// Retry policy for block reads.
+  pub retry: RetryPolicy,
+  pub clock: Arc<dyn Clock>,
+  /// Read counters for blocks that queries load, when tracking is enabled.
+  pub heat: Option<BlockHeat>,
+  /// Write a checksum of the rows into new blocks and verify the checksums
+  /// of blocks as they are read.
+  pub checksums: bool,
+  /// Compression for the rows of new blocks.
+  pub compression: Compression,
+  /// Keys to encrypt the rows of new blocks and decrypt existing blocks.
+  pub(crate) keys: Option<Keyring>,
+  /// Encoding of the rows in blocks.
+  codec: Arc<dyn Codec<P,V>>,
+  /// Registered summaries, written for each new block.
+  pub summaries: Vec<SummaryStore<S,P,V>>,
+  /// Bytes replaced by in-place writes while a backup is copying the store.
+  pub(crate) journal: Journal
 }
 
 impl<S,P,V> DataBatch<P,V> for DataStore<S,P,V>
-where S: RandomAccess<Error=Error>, P: Point, V: Value {
+where S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
   fn batch (&mut self, rows: &Vec<&(P,V)>) -> Result<u64,Error> {
-    ensure![rows.len() <= self.max_data_size,
-      "data size limit exceeded in data merge"];
-    let bitfield_len = (rows.len()+7)/8;
-    let mut len = 6 + bitfield_len;
-    for row in rows.iter() {
-      len += row.count_bytes();
-    }
-    let mut data = vec![0u8;len];
-    let mut offset = 0;
-    offset += (len as u32).write_bytes(&mut data[offset..])?;
-    offset += (bitfield_len as u16).write_bytes(&mut data[offset..])?;
-    for (i,_row) in rows.iter().enumerate() {
-      data[6+i/8] |= 1<<(i%8);
-    }
-    offset += bitfield_len;
-    for row in rows.iter() {
-      offset += row.write_bytes(&mut data[offset..])?;
+    if rows.len() > self.max_data_size {
+      return Err(Error::DataSizeLimit { rows: rows.len(), max: self.max_data_size });
     }
+    let (data,checksum) = self.encode(rows)?;
     let store_offset = self.store.len()?;
     self.store.write(store_offset, &data)?;
     let bbox = match P::bounds(&rows.iter().map(|(p,_)| *p).collect()) {
-      None => bail!["failed to calculate bounds"],
+      None => return Err(Error::Other("failed to calculate bounds".into())),
       Some(bbox) => bbox
     };
     self.range.write(&(store_offset,P::bounds_to_range(bbox),rows.len() as u64))?;
+    if let (false, Some(c)) = (self.summaries.is_empty(), checksum) {
+      let rows: Vec<(P,V)> = rows.iter().map(|(p,v)| (*p,v.clone())).collect();
+      for summary in self.summaries.iter_mut() {
+        summary.write(store_offset, c, &rows)?;
+      }
+    }
     Ok(store_offset)
   }
 }
 
 impl<S,P,V> DataStore<S,P,V>
-where S: RandomAccess<Error=Error>, P: Point, V: Value {
+where S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  // Encode `rows` as a block, including the length field, and return it with
+  // the checksum of its rows if checksums are enabled.
+  fn encode (&self, rows: &Vec<&(P,V)>) -> Result<(Vec<u8>,Option<u32>),Error> {
+    let bitfield_len = (rows.len()+7)/8;
+    ensure![bitfield_len < EXTENDED as usize, "too many rows for a data block"];
+    // about the encoded size of fixed-size rows with the default codec
+    let mut payload = Vec::with_capacity(rows.len() * size_of::<(P,V)>());
+    for row in rows.iter() {
+      self.codec.serialize_into(row, &mut payload)?;
+    }
+    let compressed = self.compression.compress(&payload)?;
+    let mut flags = 0;
+    if self.checksums { flags |= CHECKSUM }
+    if compressed.is_some() { flags |= COMPRESSED }
+    let raw_len = payload.len();
+    let (payload,codec) = match compressed {
+      Some((codec,buf)) => (buf,Some(codec)),
+      None => (payload,None)
+    };
+    let (payload,epoch) = match &self.keys {
+      Some(keys) => {
+        flags |= ENCRYPTED;
+        (keys.encrypt(&payload)?,Some(keys.epoch))
+      },
+      None => (payload,None)
+    };
+    let checksum = if self.checksums { Some(crc32fast::hash(&payload)) } else { None };
+    let mut header = vec![];
+    if flags != 0 {
+      header.push(flags);
+    }
+    if let Some(c) = checksum {
+      header.extend(&c.to_be_bytes());
+    }
+    if let Some(codec) = codec {
+      header.push(codec);
+      header.extend(&(raw_len as u32).to_be_bytes());
+    }
+    if let Some(epoch) = epoch {
+      header.extend(&epoch.to_be_bytes());
+    }
+    let len = 6 + bitfield_len + header.len() + payload.len();
+    let mut data = Vec::with_capacity(len);
+    data.extend(&(len as u32).to_be_bytes());
+    let field = if flags != 0 { EXTENDED } else { 0 };
+    data.extend(&((bitfield_len as u16) | field).to_be_bytes());
+    let mut bitfield = vec![0u8;bitfield_len];
+    for i in 0..rows.len() {
+      bitfield[i/8] |= 1<<(i%8);
+    }
+    data.extend(&bitfield);
+    data.extend(&header);
+    data.extend(&payload);
+    Ok((data,checksum))
+  }
   pub fn open (store: S, range_store: S, max_data_size: usize,
   bbox_cache_size: usize, list_cache_size: usize) -> Result<Self,Error> {
+    Self::open_counted(store, range_store, max_data_size, bbox_cache_size,
+      list_cache_size, Arc::new(Counters::default()))
+  }
+  /// Like `open()`, but count cache and storage use in `counters`.
+  pub(crate) fn open_counted (store: S, range_store: S, max_data_size: usize,
+  bbox_cache_size: usize, list_cache_size: usize, counters: Arc<Counters>)
+  -> Result<Self,Error> {
     Ok(Self {
-      store,
-      range: DataRange::new(range_store, bbox_cache_size),
-      list_cache: LruCache::new(list_cache_size),
-      max_data_size
+      store: Counted::new(store, Arc::clone(&counters)),
+      range: DataRange::counted(
+        Counted::new(range_store, Arc::clone(&counters)), bbox_cache_size),
+      counters,
+      list_cache: BlockCache::new(list_cache_size),
+      max_data_size,
+      maintenance_cache: CacheMode::Bypass,
+      quarantine: HashSet::new(),
+      on_quarantine: None,
+      retry: RetryPolicy::none(),
+      clock: default_clock(),
+      heat: None,
+      checksums: true,
+      compression: Compression::None,
+      keys: None,
+      codec: Arc::new(DesertCodec),
+      summaries: vec![],
+      journal: Journal::default()
     })
   }
+  /// Drop the cached rows of blocks that were rewritten under the cache.
+  pub(crate) fn forget_blocks<I> (&mut self, offsets: I) where I: Iterator<Item=u64> {
+    for offset in offsets {
+      self.list_cache.pop(&offset);
+    }
+  }
+  pub fn set_codec (&mut self, codec: Arc<dyn Codec<P,V>>) {
+    self.codec = codec;
+    self.list_cache.clear();
+  }
   pub fn commit (&mut self) -> Result<(),Error> {
     self.store.sync_all()?;
+    self.range.store.sync_all()?;
+    for summary in self.summaries.iter_mut() {
+      summary.commit()?;
+    }
     Ok(())
   }
   pub fn query (&mut self, offset: u64, bbox: &P::Bounds)
   -> Result<Vec<(P,V,Location)>,Error> {
-    let rows = self.list(offset)?;
+    let rows = self.list_shared(offset)?;
     Ok(rows.iter().filter(|row| {
       row.0.overlaps(bbox)
     }).map(|row| { row.clone() }).collect())
   }
   pub fn list (&mut self, offset: u64) -> Result<Vec<(P,V,Location)>,Error> {
-    match self.list_cache.get(&offset) {
-      Some(rows) => return Ok(rows.to_vec()),
-      None => {}
+    Ok(self.list_shared(offset)?.to_vec())
+  }
+  /// Return the rows for the block at `offset` as a slice shared with the
+  /// cache, so hot blocks can be read repeatedly without cloning any values.
+  pub fn list_shared (&mut self, offset: u64) -> Result<Rows<P,V>,Error> {
+    self.list_mode(offset, CacheMode::Normal)
+  }
+  /// Like `list_shared()`, but with an explicit cache `mode`.
+  pub fn list_mode (&mut self, offset: u64, mode: CacheMode)
+  -> Result<Rows<P,V>,Error> {
+    if self.quarantine.contains(&offset) {
+      return Ok(Vec::new().into());
     }
+    match mode {
+      CacheMode::Normal => {
+        if let Some(rows) = self.list_cache.get(&offset) {
+          Counters::bump(&self.counters.block_hits, 1);
+          return Ok(rows)
+        }
+        Counters::bump(&self.counters.block_misses, 1);
+        self.load(offset)
+      },
+      CacheMode::Bypass => {
+        if let Some(rows) = self.list_cache.peek(&offset) {
+          Counters::bump(&self.counters.block_hits, 1);
+          return Ok(rows)
+        }
+        Counters::bump(&self.counters.block_misses, 1);
+        self.read_rows(offset)
+      }
+    }
+  }
+  /// Call `f` with a reference to each row in the block at `offset` that
+  /// overlaps `bbox` without cloning rows out of the cache.
+  pub fn for_each (&mut self, offset: u64, bbox: &P::Bounds,
+  f: &mut dyn FnMut (&P,&V,&Location) -> ControlFlow<()>)
+  -> Result<ControlFlow<()>,Error> {
+    let rows = self.list_or_quarantine(offset, CacheMode::Normal)?;
+    for row in rows.iter() {
+      if !row.0.overlaps(bbox) { continue }
+      if let ControlFlow::Break(()) = f(&row.0, &row.1, &row.2) {
+        return Ok(ControlFlow::Break(()));
+      }
+    }
+    Ok(ControlFlow::Continue(()))
+  }
+  /// Like `list_mode()`, but a block that can't be read or parsed is added to
+  /// the quarantine list and treated as empty instead of failing the query.
+  /// Errors that the retry policy considers transient, and stores that can't
+  /// serve the read right now (`io::ErrorKind::WouldBlock`), are still
+  /// returned.
+  pub fn list_or_quarantine (&mut self, offset: u64, mode: CacheMode)
+  -> Result<Rows<P,V>,Error> {
+    match self.list_mode(offset, mode) {
+      Ok(rows) => {
+        if let Some(heat) = &mut self.heat {
+          heat.record(offset, self.clock.now());
+        }
+        Ok(rows)
+      },
+      Err(err) if (self.retry.retryable)(&err) || would_block(&err) => Err(err),
+      Err(err) => {
+        self.quarantine_block(offset, &err);
+        Ok(Vec::new().into())
+      }
+    }
+  }
+  /// Skip the data block at `offset` in future reads.
+  pub fn quarantine_block (&mut self, offset: u64, err: &Error) {
+    if self.quarantine.insert(offset) {
+      Counters::bump(&self.counters.quarantined, 1);
+      if let Some(f) = &self.on_quarantine {
+        f(offset, err);
+      }
+    }
+    self.list_cache.pop(&offset);
+    self.range.cache.pop(&offset);
+  }
+  fn load (&mut self, offset: u64) -> Result<Rows<P,V>,Error> {
+    let rows = self.read_rows(offset)?;
+    self.list_cache.put(offset, Arc::clone(&rows));
+    Ok(rows)
+  }
+  fn read_rows (&mut self, offset: u64) -> Result<Rows<P,V>,Error> {
     let buf = self.read(offset)?;
-    let rows = self.parse(&buf)?.iter().map(|row| {
-      (row.0,row.1.clone(),(offset+1,row.2))
-    }).collect();
-    self.list_cache.put(offset, rows);
-    Ok(self.list_cache.peek(&offset).unwrap().to_vec())
+    let mut rows = Vec::with_capacity(live_rows(&buf));
+    self.parse_each(&buf, |p,v,i| rows.push((p,v,(offset+1,i))))?;
+    Ok(rows.into())
   }
   pub fn parse (&self, buf: &Vec<u8>) -> Result<Vec<(P,V,u32)>,Error> {
-    let mut results = vec![];
+    let mut results = Vec::with_capacity(live_rows(buf));
+    self.parse_each(buf, |p,v,i| results.push((p,v,i)))?;
+    Ok(results)
+  }
+  // Call `f` with each live row of the block in `buf` and its index, so that
+  // callers can collect rows in their final shape without an intermediate
+  // vector.
+  fn parse_each<F> (&self, buf: &[u8], f: F) -> Result<(),Error>
+  where F: FnMut(P,V,u32) {
+    let rows = self.rows(buf)?;
+    each_row(&*self.codec, &buf[2..], &rows, f)
+  }
+  // Summarize the live rows of the block at `offset`, read into `buf`.
+  // Summaries are only kept for blocks with a checksum, which tells when
+  // their rows were updated.
+  fn write_summaries (&mut self, offset: u64, buf: &Vec<u8>) -> Result<(),Error> {
+    let mut summaries = std::mem::take(&mut self.summaries);
+    let r = self.summarize_into(&mut summaries, offset, buf);
+    self.summaries = summaries;
+    r
+  }
+  // Write the summaries of the block in `buf`, stored at `offset`, to
+  // `summaries`.
+  fn summarize_into (&self, summaries: &mut [SummaryStore<S,P,V>], offset: u64,
+  buf: &Vec<u8>) -> Result<(),Error> {
+    if summaries.is_empty() { return Ok(()) }
+    let checksum = match layout(buf)?.checksum {
+      Some((_,c)) => c,
+      None => return Ok(())
+    };
+    let rows: Vec<(P,V)> = self.parse(buf)?.into_iter()
+      .map(|(p,v,_)| (p,v)).collect();
+    if rows.is_empty() { return Ok(()) }
+    for summary in summaries.iter_mut() {
+      summary.write(offset, checksum, &rows)?;
+    }
+    Ok(())
+  }
+  // Layout of the block in `buf`, after verifying the checksum of the rows
+  // if the block has one.
+  fn checked_layout (&self, buf: &[u8]) -> Result<Layout,Error> {
+    let layout = layout(buf)?;
+    if let (true, Some((_,expected))) = (self.checksums, layout.checksum) {
+      let found = crc32fast::hash(&buf[layout.rows..]);
+      if found != expected {
+        return Err(ChecksumMismatch { expected, found }.into());
+      }
+    }
+    Ok(layout)
+  }
+  // Encoded rows of the block in `buf`, decrypted and decompressed if
+  // necessary.
+  fn rows<'a> (&self, buf: &'a [u8]) -> Result<Cow<'a,[u8]>,Error> {
+    let layout = self.checked_layout(buf)?;
+    let rows = match (layout.encrypted,&self.keys) {
+      (Some(epoch),Some(keys)) => Cow::Owned(keys.decrypt(epoch, &buf[layout.rows..])?),
+      (Some(epoch),None) => {
+        invalid!["data block is encrypted with key epoch {} but encryption is not \
+          set up, use Setup::encryption()", epoch]
+      },
+      (None,_) => Cow::Borrowed(&buf[layout.rows..])
+    };
+    Ok(match layout.compressed {
+      Some((codec,len)) => Cow::Owned(decompress(codec, &rows, len)?),
+      None => rows
+    })
+  }
+  // Whether the block in `buf` should be encrypted again with the current key.
+  fn stale_key (&self, buf: &[u8]) -> Result<bool,Error> {
+    Ok(match &self.keys {
+      Some(keys) => layout(buf)?.encrypted != Some(keys.epoch),
+      None => false
+    })
+  }
+  /// Size in bytes of the data block at `offset`, including its length field.
+  pub fn block_size (&mut self, offset: u64) -> Result<u64,Error> {
+    let store = &mut self.store;
+    let buf = self.retry.run(&*self.clock, || store.read(offset, 4))?;
+    Ok(u32::from_be_bytes([buf[0],buf[1],buf[2],buf[3]]) as u64)
+  }
+  /// Range recorded for each data block when it was written.
+  pub fn block_ranges (&mut self) -> Result<HashMap<u64,P::Range>,Error> {
+    self.range.ranges()
+  }
+  /// Range and number of written rows recorded for each data block.
+  pub fn block_entries (&mut self) -> Result<HashMap<u64,(P::Range,u64)>,Error> {
+    self.range.entries()
+  }
+  /// Read only the bitfield of the block at `offset` that was written with
+  /// `rows` rows. Set bits mark the rows that haven't been deleted.
+  pub fn live_bits (&mut self, offset: u64, rows: u64) -> Result<Vec<u8>,Error> {
+    let store = &mut self.store;
+    self.retry.run(&*self.clock, || store.read(offset+6, rows.div_ceil(8)))
+  }
+  /// Read only the header of the block at `offset` that was written with
+  /// `rows` rows, returning the number of live rows and the checksum of the
+  /// rows if the block has one. Updates in place change the checksum.
+  pub fn live_state (&mut self, offset: u64, rows: u64)
+  -> Result<(u64,Option<u32>),Error> {
+    let store = &mut self.store;
+    let buf = self.retry.run(&*self.clock, || {
+      // length, bitfield length, bitfield, flags, checksum, codec, and key
+      // epoch fields
+      let len = (4+2+rows.div_ceil(8)+1+4+5+4).min(store.len()? - offset);
+      store.read(offset, len)
+    })?;
+    let layout = layout(&buf[4..])?;
+    let live = buf[6..6+rows.div_ceil(8) as usize].iter()
+      .map(|b| b.count_ones() as u64).sum();
+    Ok((live,layout.checksum.map(|(_,c)| c)))
+  }
+  /// Read only the header of the block at `offset` and return the key epoch
+  /// its rows were encrypted with, or `None` if they aren't encrypted.
+  pub fn key_epoch (&mut self, offset: u64) -> Result<Option<u32>,Error> {
+    let store = &mut self.store;
+    let buf = self.retry.run(&*self.clock, || {
+      let end = store.len()?;
+      let head = store.read(offset, 6.min(end - offset))?;
+      ensure![head.len() == 6, "data block is too small"];
+      let field = u16::from_be_bytes([head[4],head[5]]);
+      // length, bitfield length, bitfield, flags, checksum, codec, and key
+      // epoch fields
+      let len = 4+2+((field & !EXTENDED) as u64)+1+4+5+4;
+      store.read(offset, len.min(end - offset))
+    })?;
+    Ok(layout(&buf[4..])?.encrypted)
+  }
+  /// Return the live points of the block at `offset` with their row indexes,
+  /// skipping over values without decoding them.
+  pub fn points (&mut self, offset: u64) -> Result<Vec<(P,u32)>,Error> {
+    if let Some(rows) = self.list_cache.peek(&offset) {
+      return Ok(rows.iter().map(|row| (row.0,(row.2).1)).collect());
+    }
+    let buf = self.read(offset)?;
+    let rows = self.rows(&buf)?;
     let mut offset = 0;
-    let bitfield_len = u16::from_be_bytes([buf[0],buf[1]]) as usize;
-    offset += 2;
-    let bitfield: &[u8] = &buf[offset..offset+bitfield_len];
-    offset += bitfield_len;
     let mut index = 0;
-    while offset < buf.len() {
-      if ((bitfield[index/8]>>(index%8))&1) == 1 {
-        let (size,pv) = <(P,V)>::from_bytes(&buf[offset..])?;
-        results.push((pv.0,pv.1,index as u32));
-        offset += size;
-      } else {
-        offset += <(P,V)>::count_from_bytes(&buf[offset..])?;
+    let mut points = Vec::with_capacity(live_rows(&buf));
+    while offset < rows.len() {
+      let (size,point) = self.codec.deserialize_point(&rows[offset..])?;
+      offset += size;
+      if ((buf[2+index/8]>>(index%8))&1) == 1 {
+        points.push((point,index as u32));
       }
       index += 1;
     }
-    Ok(results)
+    Ok(points)
+  }
+  /// Read the block at `offset` for an integrity check and return the points
+  /// of all of its rows, deleted or not, with its bitfield. Fails if the block
+  /// can't be read or decoded, its checksum doesn't match even when checksums
+  /// are turned off, or its bitfield doesn't cover exactly its rows.
+  pub(crate) fn check_block (&mut self, offset: u64) -> Result<(Vec<P>,Vec<u8>),Error> {
+    let buf = self.read(offset)?;
+    let layout = layout(&buf)?;
+    if let Some((_,expected)) = layout.checksum {
+      let found = crc32fast::hash(&buf[layout.rows..]);
+      if found != expected {
+        return Err(ChecksumMismatch { expected, found }.into());
+      }
+    }
+    let rows = self.rows(&buf)?;
+    let mut points = vec![];
+    let mut pos = 0;
+    while pos < rows.len() {
+      let (size,point) = self.codec.deserialize_point(&rows[pos..])?;
+      ensure![size > 0 && pos + size <= rows.len(), "row {} overruns the block", points.len()];
+      points.push(point);
+      pos += size;
+    }
+    let field = u16::from_be_bytes([buf[0],buf[1]]);
+    let bitfield = buf[2..2+(field & !EXTENDED) as usize].to_vec();
+    ensure![bitfield.len() == points.len().div_ceil(8),
+      "bitfield of {} bytes for {} rows", bitfield.len(), points.len()];
+    let padding = (points.len()..bitfield.len()*8)
+      .any(|i| ((bitfield[i/8]>>(i%8))&1) == 1);
+    ensure![!padding, "bitfield marks rows past the last of {} rows", points.len()];
+    Ok((points,bitfield))
+  }
+  /// Replace the entries of the range store with `ranges`.
+  pub(crate) fn rewrite_ranges (&mut self, ranges: &[(u64,P::Range,u64)])
+  -> Result<(),Error> {
+    self.range.store.truncate(0)?;
+    for range in ranges.iter() {
+      self.range.write(range)?;
+    }
+    self.range.store.sync_all()?;
+    self.range.cache.clear();
+    Ok(())
+  }
+  // overwrite bytes of an existing block, keeping the bytes it replaces for
+  // a backup that is copying the store
+  fn write_in_place (&mut self, offset: u64, data: &[u8]) -> Result<(),Error> {
+    let store = &mut self.store;
+    self.journal.record(offset, data.len() as u64, |o,n| Ok(store.read(o,n)?))?;
+    Ok(self.store.write(offset, data)?)
+  }
+  /// Sizes in bytes of the data store and the range store.
+  pub fn store_bytes (&self) -> Result<(u64,u64),Error> {
+    Ok((self.store.len()?,self.range.store.len()?))
   }
   pub fn read (&mut self, offset: u64) -> Result<Vec<u8>,Error> {
-    let len = self.store.len()? as u64;
-    read_block(&mut self.store, offset, len, 1024)
+    let store = &mut self.store;
+    self.retry.run(&*self.clock, || {
+      let len = store.len()?;
+      read_block(store, offset, len, 1024)
+    })
+  }
+  /// Overwrite rows in place with new points and values.
+  ///
+  /// An update is only written in place when the row still exists in an
+  /// uncompressed block, its encoded size doesn't change, and the new point
+  /// lies within the bounding box of its block, so that the trees still lead
+  /// every query that overlaps the point to it. The other updates are
+  /// returned so that the caller can delete and insert them.
+  pub fn replace (&mut self, updates: &[(Location,P,V)])
+  -> Result<Vec<(Location,P,V)>,Error> {
+    let mut rest = vec![];
+    let mut by_block: HashMap<u64,Vec<&(Location,P,V)>> = HashMap::new();
+    for update in updates.iter() {
+      let block = (update.0).0;
+      if block == 0 || self.quarantine.contains(&(block-1)) {
+        rest.push(update.clone());
+      } else {
+        by_block.entry(block-1).or_default().push(update);
+      }
+    }
+    for (block,updates) in by_block.iter() {
+      let bbox = match self.bbox(*block)? {
+        Some((bbox,_)) => bbox,
+        None => {
+          rest.extend(updates.iter().map(|u| (*u).clone()));
+          continue
+        }
+      };
+      let mut buf = self.read(*block)?;
+      let layout = self.checked_layout(&buf)?;
+      // compressed or encrypted rows can't be overwritten
+      if layout.compressed.is_some() || layout.encrypted.is_some() {
+        rest.extend(updates.iter().map(|u| (*u).clone()));
+        continue
+      }
+      let rows_start = layout.rows;
+      let mut positions: HashMap<u32,(usize,usize)> = HashMap::new();
+      let mut offset = rows_start;
+      let mut index = 0;
+      while offset < buf.len() {
+        let size = self.codec.take_bytes(&buf[offset..])?;
+        if ((buf[2+index/8]>>(index%8))&1) == 1 {
+          positions.insert(index as u32, (offset,size));
+        }
+        offset += size;
+        index += 1;
+      }
+      // without axes, `within()` falls back to `overlaps()`, which lets an
+      // interval reach past the block where queries won't find it
+      let exact = P::bounds_axes(&bbox).is_some();
+      let mut replaced: HashMap<u32,(P,V)> = HashMap::new();
+      for update in updates.iter() {
+        let ((_,index),point,value) = update;
+        let pv = (*point,value.clone());
+        let bytes = self.codec.serialize(&pv)?;
+        match positions.get(index) {
+          Some((pos,size)) if *size == bytes.len() && exact && point.within(&bbox) => {
+            // skip the u32 block length that read() strips
+            self.write_in_place(block + 4 + (*pos as u64), &bytes)?;
+            buf[*pos..*pos+bytes.len()].copy_from_slice(&bytes);
+            replaced.insert(*index, pv);
+          },
+          _ => rest.push((*update).clone())
+        }
+      }
+      if let (false, Some((pos,_))) = (replaced.is_empty(), layout.checksum) {
+        let c = crc32fast::hash(&buf[rows_start..]).to_be_bytes();
+        self.write_in_place(block + 4 + (pos as u64), &c)?;
+        buf[pos..pos+4].copy_from_slice(&c);
+      }
+      if !replaced.is_empty() {
+        self.write_summaries(*block, &buf)?;
+      }
+      self.list_cache.update(block, |rows| {
+        rows.iter().map(|row| {
+          match replaced.get(&(row.2).1) {
+            Some((p,v)) => (*p,v.clone(),row.2),
+            None => row.clone()
+          }
+        }).collect()
+      });
+    }
+    Ok(rest)
   }
-  // todo: replace() similar to delete but with an additional array of
-  // replacement candidates
   pub fn delete (&mut self, locations: &Vec<Location>) -> Result<(),Error> {
     let mut by_block: HashMap<u64,Vec<u32>> = HashMap::new();
     for (block,index) in locations {
@@ -161,14 +745,14 @@ where S: RandomAccess<Error=Error>, P: Point, V: Value {
     for (block,indexes) in by_block.iter() {
       let max_i = match indexes.iter().max() {
         Some(i) => *i as u64,
-        None => bail!["indexes is an empty array"],
+        None => return Err(Error::Other("indexes is an empty array".into())),
       };
       let len = 7 + max_i/8; // indexes start at 0, unlike lengths
       ensure![len <= self.store.len()?-block,
         "index length past the end of the block"];
       let mut header = self.store.read(*block, len)?;
       let block_size = u32::from_bytes(&header[0..])?.1 as u64;
-      let bitfield_len = u16::from_bytes(&header[4..])?.1;
+      let bitfield_len = u16::from_bytes(&header[4..])?.1 & !EXTENDED;
       ensure![len <= (bitfield_len as u64) + 6,
         "read length {} from index {} past expected bitfield length {} \
         for block size {} at offset {}",
@@ -179,69 +763,310 @@ where S: RandomAccess<Error=Error>, P: Point, V: Value {
         let i = *index as usize;
         header[6+i/8] &= 0xff - (1<<(i%8));
       }
-      self.store.write(block+6, &header[6..])?;
-      match self.list_cache.get_mut(block) {
-        Some(rows) => {
-          rows.retain(|row| !indexes.contains(&((row.2).1)));
-        },
-        None => {},
+      self.write_in_place(block+6, &header[6..])?;
+      if !self.summaries.is_empty() {
+        let buf = self.read(*block)?;
+        self.write_summaries(*block, &buf)?;
       }
+      self.list_cache.update(block, |rows| {
+        rows.iter()
+          .filter(|row| !indexes.contains(&((row.2).1)))
+          .cloned()
+          .collect()
+      });
     }
     Ok(())
   }
+  /// Write the blocks at `offsets` in offset order to `data`, rewriting
+  /// blocks with deleted rows to drop those rows, along with their range
+  /// records to `range` and their summaries to `summaries`, one store for
+  /// each registered summary. The new stores start out empty, and this store
+  /// isn't changed.
+  ///
+  /// `offsets` must include every block that a tree references. Returns the
+  /// new offset of each block, or `None` for blocks without live rows, which
+  /// are dropped, and the offsets of quarantined blocks in `data`, which are
+  /// copied as they are.
+  pub(crate) fn compact (&mut self, offsets: &[u64], data: &mut S, range: &mut S,
+  summaries: Vec<S>) -> Result<Compacted,Error> {
+    let mut offsets = offsets.to_vec();
+    offsets.sort_unstable();
+    offsets.dedup();
+    let entries = self.range.entries()?;
+    let mut report = CompactReport {
+      bytes_before: self.store.len()?,
+      ..Default::default()
+    };
+    let mut moves = HashMap::new();
+    let mut quarantine = HashSet::new();
+    let mut summaries: Vec<SummaryStore<S,P,V>> = self.summaries.iter()
+      .zip(summaries)
+      .map(|(s,store)| SummaryStore::open(&s.name, Arc::clone(&s.summarize), store))
+      .collect();
+    let mut dst = 0u64;
+    let mut range_len = 0u64;
+    for offset in offsets {
+      let size = self.block_size(offset)?;
+      let parsed = if self.quarantine.contains(&offset) { None } else {
+        let buf = self.read(offset)?;
+        match self.parse(&buf) {
+          Ok(rows) => Some((buf,rows)),
+          Err(e) => {
+            self.quarantine_block(offset, &e);
+            None
+          }
+        }
+      };
+      let written = entries.get(&offset).map(|(_,n)| *n);
+      let (buf,rows) = match parsed {
+        Some((_,rows)) if rows.is_empty() => {
+          moves.insert(offset, None);
+          report.removed += 1;
+          continue;
+        },
+        Some((buf,rows)) => (Some(buf),rows),
+        None => (None,vec![])
+      };
+      let live: Vec<(P,V)> = rows.into_iter().map(|(p,v,_)| (p,v)).collect();
+      let rewrite = match &buf {
+        Some(buf) => written.map(|n| n > live.len() as u64).unwrap_or(false)
+          || self.stale_key(buf)?,
+        None => false
+      };
+      let encoded = if rewrite {
+        Some(self.encode(&live.iter().collect())?)
+          .filter(|(data,_)| (data.len() as u64) <= size)
+      } else {
+        None
+      };
+      let bounds = match P::bounds(&live.iter().map(|(p,_)| *p).collect()) {
+        Some(bbox) => Some(P::bounds_to_range(bbox)),
+        None => entries.get(&offset).map(|(range,_)| *range)
+      };
+      let (size,rows) = match encoded {
+        Some((block,_)) => {
+          data.write(dst, &block)?;
+          self.summarize_into(&mut summaries, dst, &block[4..].to_vec())?;
+          report.rewritten += 1;
+          (block.len() as u64, Some(live.len() as u64))
+        },
+        None => {
+          let block = self.store.read(offset, size)?;
+          data.write(dst, &block)?;
+          if dst != offset { report.moved += 1 }
+          match &buf {
+            Some(buf) => self.summarize_into(&mut summaries, dst, buf)?,
+            None => { quarantine.insert(dst); }
+          }
+          (size, written)
+        }
+      };
+      if let (Some(bounds),Some(n)) = (bounds,rows) {
+        let record = (dst,bounds,n).to_bytes()?;
+        range.write(range_len, &record)?;
+        range_len += record.len() as u64;
+      }
+      moves.insert(offset, Some(dst));
+      dst += size;
+    }
+    data.sync_all()?;
+    range.sync_all()?;
+    for summary in summaries.iter_mut() {
+      summary.commit()?;
+    }
+    report.bytes_after = dst;
+    Ok(Compacted {
+      moves,
+      report,
+      quarantine,
+      lens: (dst, range_len, summaries.iter_mut().map(|s| s.len()).collect::<Result<_,_>>()?)
+    })
+  }
   pub fn bytes (&mut self) -> Result<u64,Error> {
     Ok(self.store.len()? as u64)
   }
+  /// Number of data blocks currently held in the list cache.
+  pub fn cached_blocks (&self) -> usize {
+    self.list_cache.len()
+  }
+  /// Estimated memory held by the blocks in the list cache.
+  pub fn cached_bytes (&self) -> usize {
+    self.list_cache.bytes()
+  }
+  /// Return the block cache that the list cache keeps its blocks in, for
+  /// another data store to share with `share_list_cache()`.
+  pub(crate) fn shared_list_cache (&mut self) -> SharedCache
+  where P: Send+Sync+'static, V: Send+Sync {
+    self.list_cache.shared()
+  }
+  /// Keep sharing the block cache of `old`, a data store that this one
+  /// replaces.
+  pub(crate) fn adopt_list_cache (&mut self, old: &mut Self) {
+    self.list_cache.adopt(&mut old.list_cache);
+    self.on_quarantine = old.on_quarantine.take();
+    self.journal = old.journal.clone();
+  }
+  /// Keep the blocks of the list cache in `cache`, next to the blocks of the
+  /// other data stores that share it.
+  pub(crate) fn share_list_cache (&mut self, cache: &SharedCache)
+  where P: Send+Sync+'static, V: Send+Sync {
+    self.list_cache.share(cache);
+  }
+  /// Bound the list cache by an estimate of the memory its blocks hold as
+  /// well as by its number of blocks.
+  pub fn set_list_cache_bytes (&mut self, max: Option<usize>) {
+    self.list_cache.set_max_bytes(max);
+  }
+  /// Read the range store `bytes` at a time.
+  pub fn set_range_chunk_size (&mut self, bytes: usize) {
+    self.range.chunk_size = bytes;
+  }
   pub fn bbox (&mut self, offset: u64)
   -> Result<Option<(P::Bounds,u64)>,Error> {
-    match self.range.cache.get(&offset) {
-      None => {},
-      Some(r) => return Ok(Some(*r))
+    self.bbox_mode(offset, CacheMode::Normal)
+  }
+  /// Like `bbox()`, but with an explicit cache `mode`.
+  pub fn bbox_mode (&mut self, offset: u64, mode: CacheMode)
+  -> Result<Option<(P::Bounds,u64)>,Error> {
+    let cached = match mode {
+      CacheMode::Normal => self.range.cache.get(&offset),
+      CacheMode::Bypass => self.range.cache.peek(&offset)
     };
-    let rows = self.list(offset)?;
+    match cached {
+      None => Counters::bump(&self.counters.bbox_misses, 1),
+      Some(r) => {
+        Counters::bump(&self.counters.bbox_hits, 1);
+        return Ok(Some(*r))
+      }
+    };
+    let rows = self.list_mode(offset, mode)?;
     if rows.is_empty() {
       return Ok(None);
     }
     let bbox = match P::bounds(&rows.iter().map(|(p,_,_)| *p).collect()) {
-      None => bail!["invalid data at offset {}", offset],
+      None => corrupt!["invalid data at offset {}", offset],
       Some(bbox) => bbox
     };
     let result = (bbox,rows.len() as u64);
-    self.range.cache.put(offset, result.clone());
+    if mode == CacheMode::Normal {
+      self.range.cache.put(offset, result);
+    }
     Ok(Some(result))
   }
 }
 
+/// Bytes that `DataRange` reads from the range store at a time by default.
+pub const RANGE_CHUNK_SIZE: usize = 64*1024;
+
 pub struct DataRange<S,P>
-where S: RandomAccess<Error=Error>, P: Point {
-  pub store: S,
-  pub cache: LruCache<u64,(P::Bounds,u64)>
+where S: RandomAccess<Error=failure::Error>, P: Point {
+  pub store: Counted<S>,
+  pub cache: LruCache<u64,(P::Bounds,u64)>,
+  /// Bytes read from the range store at a time when iterating over it.
+  pub chunk_size: usize
 }
 
 impl<S,P> DataRange<S,P>
-where S: RandomAccess<Error=Error>, P: Point {
+where S: RandomAccess<Error=failure::Error>, P: Point {
   pub fn new (store: S, cache_size: usize) -> Self {
+    Self::counted(Counted::new(store, Arc::new(Counters::default())), cache_size)
+  }
+  pub(crate) fn counted (store: Counted<S>, cache_size: usize) -> Self {
     Self {
       store,
-      cache: LruCache::new(cache_size)
+      cache: LruCache::new(cache_size),
+      chunk_size: RANGE_CHUNK_SIZE
     }
   }
   pub fn write (&mut self, b: &(u64,P::Range,u64)) -> Result<(),Error> {
     let offset = self.store.len()?;
     let data = b.to_bytes()?;
-    self.store.write(offset, &data)
+    Ok(self.store.write(offset, &data)?)
   }
-  pub fn list (&mut self) -> Result<Vec<(u64,P,u64)>,Error> {
-    let len = self.store.len()?;
-    // TODO: read in chunks instead of all at once
-    let buf = self.store.read(0, len)?;
-    let mut offset = 0usize;
-    let mut results: Vec<(u64,P,u64)> = vec![];
-    while (offset as u64) < len {
-      let (size, result) = <(u64,P,u64)>::from_bytes(&buf[offset..])?;
-      results.push(result);
-      offset += size;
+  /// Read the range recorded for each data block when it was written, keyed
+  /// by block offset.
+  pub fn ranges (&mut self) -> Result<HashMap<u64,P::Range>,Error> {
+    Ok(self.entries()?.into_iter().map(|(block,(range,_))| (block,range)).collect())
+  }
+  /// Like `ranges()`, but also with the number of rows each block was written
+  /// with.
+  pub fn entries (&mut self) -> Result<HashMap<u64,(P::Range,u64)>,Error> {
+    let mut results = HashMap::new();
+    for entry in self.iter::<(u64,P::Range,u64)>()? {
+      let (block,range,count) = entry?;
+      results.insert(block, (range,count));
     }
     Ok(results)
   }
+  /// Iterate over the records of the range store as `(offset,point,rows)`,
+  /// for points whose range is the point type itself.
+  pub fn list (&mut self) -> Result<DataRangeIter<'_,S,(u64,P,u64)>,Error> {
+    self.iter()
+  }
+  /// Iterate over the records of the range store, reading `chunk_size` bytes
+  /// at a time.
+  pub fn iter<T> (&mut self) -> Result<DataRangeIter<'_,S,T>,Error> where T: FromBytes {
+    let len = self.store.len()?;
+    Ok(DataRangeIter {
+      store: &mut self.store,
+      chunk_size: self.chunk_size.max(1),
+      len,
+      pos: 0,
+      buf: vec![],
+      offset: 0,
+      _marker: PhantomData
+    })
+  }
+}
+
+/// Iterator over the records of a range store, returned by `DataRange::iter()`
+/// and `DataRange::list()`.
+pub struct DataRangeIter<'a,S,T> where S: RandomAccess<Error=failure::Error> {
+  store: &'a mut Counted<S>,
+  chunk_size: usize,
+  len: u64,
+  // store offset of the end of `buf`
+  pos: u64,
+  buf: Vec<u8>,
+  // offset of the next record in `buf`
+  offset: usize,
+  _marker: PhantomData<T>
+}
+
+impl<'a,S,T> Iterator for DataRangeIter<'a,S,T>
+where S: RandomAccess<Error=failure::Error>, T: FromBytes {
+  type Item = Result<T,Error>;
+  fn next (&mut self) -> Option<Self::Item> {
+    loop {
+      if self.offset < self.buf.len() {
+        match T::from_bytes(&self.buf[self.offset..]) {
+          Ok((size,record)) => {
+            self.offset += size;
+            return Some(Ok(record));
+          },
+          // a record that is still cut short at the end of the store is
+          // damaged, otherwise it continues in the next chunk
+          Err(err) if self.pos >= self.len => {
+            self.offset = self.buf.len();
+            return Some(Err(err.into()));
+          },
+          Err(_) => {}
+        }
+      } else if self.pos >= self.len {
+        return None;
+      }
+      self.buf.drain(..self.offset);
+      self.offset = 0;
+      let n = (self.len - self.pos).min(self.chunk_size as u64);
+      match self.store.read(self.pos, n) {
+        Ok(chunk) => self.buf.extend_from_slice(&chunk),
+        Err(err) => {
+          self.pos = self.len;
+          self.buf.clear();
+          return Some(Err(err.into()));
+        }
+      }
+      self.pos += n;
+    }
+  }
 }
@@ -0,0 +1,162 @@
+#[doc(hidden)]
+pub mod __private {
+  pub use desert::{ToBytes,FromBytes,CountBytes};
+  pub use failure::Error;
+}
+
+/// Define a struct that implements `Point` through the tuple of its fields.
+///
+/// Each field is a scalar (such as `f32` or `u64`) or a `(min,max)` interval
+/// of a scalar, like the elements of the built-in tuple points, and there are
+/// 2 to 8 fields. Bounding boxes and ranges are the ones of the tuple, and
+/// rows are stored exactly like the tuple, so a database can switch between
+/// the struct and the tuple. `Clone`, `Copy`, and `Debug` are derived.
+///
+/// ```rust
+/// use eyros::{Point,point};
+///
+/// point! {
+///   #[derive(PartialEq)]
+///   pub struct Sighting {
+///     pub lon: f32,
+///     pub lat: f32,
+///     pub time: (u64,u64),
+///   }
+/// }
+///
+/// let s = Sighting { lon: 13.4, lat: 52.5, time: (1_000,2_000) };
+/// assert![s.overlaps(&((13.0,52.0,1_500),(14.0,53.0,1_600)))];
+/// assert_eq![Sighting::from((13.4,52.5,(1_000,2_000))), s];
+/// ```
+#[macro_export]
+macro_rules! point {
+  (
+    $(#[$meta:meta])*
+    $vis:vis struct $name:ident {
+      $($fvis:vis $field:ident : $fty:ty),+ $(,)?
+    }
+  ) => {
+    $(#[$meta])*
+    #[derive(Clone,Copy,Debug)]
+    $vis struct $name {
+      $($fvis $field: $fty),+
+    }
+
+    impl From<($($fty,)+)> for $name {
+      fn from (t: ($($fty,)+)) -> Self {
+        let ($($field,)+) = t;
+        Self { $($field),+ }
+      }
+    }
+
+    impl From<$name> for ($($fty,)+) {
+      fn from (p: $name) -> Self {
+        ($(p.$field,)+)
+      }
+    }
+
+    impl $crate::__private::ToBytes for $name {
+      fn to_bytes (&self) -> Result<Vec<u8>,$crate::__private::Error> {
+        $crate::__private::ToBytes::to_bytes(&<($($fty,)+)>::from(*self))
+      }
+      fn write_bytes (&self, dst: &mut [u8])
+      -> Result<usize,$crate::__private::Error> {
+        $crate::__private::ToBytes::write_bytes(&<($($fty,)+)>::from(*self), dst)
+      }
+    }
+
+    impl $crate::__private::FromBytes for $name {
+      fn from_bytes (src: &[u8])
+      -> Result<(usize,Self),$crate::__private::Error> {
+        let (size,t) =
+          <($($fty,)+) as $crate::__private::FromBytes>::from_bytes(src)?;
+        Ok((size,t.into()))
+      }
+    }
+
+    impl $crate::__private::CountBytes for $name {
+      fn count_from_bytes (buf: &[u8])
+      -> Result<usize,$crate::__private::Error> {
+        <($($fty,)+) as $crate::__private::CountBytes>::count_from_bytes(buf)
+      }
+      fn count_from_bytes_more (buf: &[u8])
+      -> Result<Option<usize>,$crate::__private::Error> {
+        <($($fty,)+) as $crate::__private::CountBytes>::count_from_bytes_more(buf)
+      }
+      fn count_bytes (&self) -> usize {
+        $crate::__private::CountBytes::count_bytes(&<($($fty,)+)>::from(*self))
+      }
+    }
+
+    impl $crate::Point for $name {
+      type Bounds = <($($fty,)+) as $crate::Point>::Bounds;
+      type Range = <($($fty,)+) as $crate::Point>::Range;
+      fn cmp_at (&self, other: &Self, level: usize) -> std::cmp::Ordering {
+        $crate::Point::cmp_at(&<($($fty,)+)>::from(*self), &(*other).into(), level)
+      }
+      fn cmp_upper_at (&self, other: &Self, level: usize) -> std::cmp::Ordering {
+        $crate::Point::cmp_upper_at(&<($($fty,)+)>::from(*self), &(*other).into(), level)
+      }
+      fn midpoint_upper (&self, other: &Self) -> Self {
+        let t = <($($fty,)+)>::from(*self);
+        $crate::Point::midpoint_upper(&t, &(*other).into()).into()
+      }
+      fn serialize_at (&self, level: usize, dst: &mut [u8])
+      -> Result<usize,$crate::Error> {
+        $crate::Point::serialize_at(&<($($fty,)+)>::from(*self), level, dst)
+      }
+      fn dim () -> usize {
+        <($($fty,)+) as $crate::Point>::dim()
+      }
+      fn overlaps (&self, bbox: &Self::Bounds) -> bool {
+        $crate::Point::overlaps(&<($($fty,)+)>::from(*self), bbox)
+      }
+      fn within (&self, bbox: &Self::Bounds) -> bool {
+        $crate::Point::within(&<($($fty,)+)>::from(*self), bbox)
+      }
+      fn contains (&self, bbox: &Self::Bounds) -> bool {
+        $crate::Point::contains(&<($($fty,)+)>::from(*self), bbox)
+      }
+      fn pivot_bytes_at (&self, level: usize) -> usize {
+        $crate::Point::pivot_bytes_at(&<($($fty,)+)>::from(*self), level)
+      }
+      fn count_bytes_at (buf: &[u8], level: usize)
+      -> Result<usize,$crate::Error> {
+        <($($fty,)+) as $crate::Point>::count_bytes_at(buf, level)
+      }
+      fn query_branch (buf: &[u8], bbox: &Self::Bounds, branch_factor: usize,
+      level: usize)
+      -> Result<(Vec<$crate::Cursor>,Vec<$crate::Block>),$crate::Error> {
+        <($($fty,)+) as $crate::Point>::query_branch(buf, bbox, branch_factor, level)
+      }
+      fn pivots_at (buf: &[u8], branch_factor: usize, level: usize)
+      -> Result<Option<Vec<f64>>,$crate::Error> {
+        <($($fty,)+) as $crate::Point>::pivots_at(buf, branch_factor, level)
+      }
+      fn bucket_ranges (buf: &[u8], range: &Self::Range, branch_factor: usize,
+      level: usize) -> Result<Option<Vec<Self::Range>>,$crate::Error> {
+        <($($fty,)+) as $crate::Point>::bucket_ranges(buf, range, branch_factor, level)
+      }
+      fn bounds (coords: &Vec<Self>) -> Option<Self::Bounds> {
+        let coords: Vec<($($fty,)+)> = coords.iter().map(|p| (*p).into()).collect();
+        <($($fty,)+) as $crate::Point>::bounds(&coords)
+      }
+      fn bounds_to_range (bbox: Self::Bounds) -> Self::Range {
+        <($($fty,)+) as $crate::Point>::bounds_to_range(bbox)
+      }
+      fn range_within (range: &Self::Range, bbox: &Self::Bounds) -> bool {
+        <($($fty,)+) as $crate::Point>::range_within(range, bbox)
+      }
+      fn range_bounds (range: &Self::Range) -> Option<Self::Bounds> {
+        <($($fty,)+) as $crate::Point>::range_bounds(range)
+      }
+      fn union_bounds (a: &Self::Bounds, b: &Self::Bounds) -> Option<Self::Bounds> {
+        <($($fty,)+) as $crate::Point>::union_bounds(a, b)
+      }
+      fn format_at (buf: &[u8], level: usize)
+      -> Result<String,$crate::Error> {
+        <($($fty,)+) as $crate::Point>::format_at(buf, level)
+      }
+    }
+  };
+}
@@ -0,0 +1,81 @@
+use crate::{DB,Point,Value,CacheMode,AuditOp};
+use crate::Error;
+use random_access_storage::RandomAccess;
+use desert::ToBytes;
+
+/// Order-independent digest of a set of rows, as returned by
+/// `db.query_digest()`.
+///
+/// Each row's point and value are encoded and hashed with 64-bit FNV-1a, and
+/// the hashes are summed, so the digest doesn't depend on the order that rows
+/// come back in or on their locations. Replicas that answer the same bounding
+/// box at the same sequence number should return equal digests.
+#[derive(Clone,Copy,Debug,Default,PartialEq,Eq,Hash)]
+pub struct QueryDigest {
+  /// Sequence number of the database when the rows were read.
+  pub sequence: u64,
+  /// Number of rows.
+  pub rows: u64,
+  /// Sum of the row hashes.
+  pub hash: u64
+}
+
+impl QueryDigest {
+  /// Start an empty digest for rows read at `sequence`.
+  pub fn new (sequence: u64) -> Self {
+    Self { sequence, rows: 0, hash: 0 }
+  }
+  /// Add a row to the digest.
+  pub fn add<P,V> (&mut self, point: &P, value: &V) -> Result<(),Error>
+  where P: Point, V: Value {
+    self.rows += 1;
+    self.hash = self.hash.wrapping_add(fnv1a(&(*point,value.clone()).to_bytes()?));
+    Ok(())
+  }
+}
+
+fn fnv1a (buf: &[u8]) -> u64 {
+  let mut hash: u64 = 0xcbf29ce484222325;
+  for b in buf {
+    hash ^= *b as u64;
+    hash = hash.wrapping_mul(0x100000001b3);
+  }
+  hash
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Compute a digest of the rows that intersect `bbox`, for checking that
+  /// replicas return the same answer as the primary.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut primary: DB<_,_,((f32,f32),(f32,f32)),u32> = DB::open(storage)?;
+  /// # let mut replica: DB<_,_,((f32,f32),(f32,f32)),u32> = DB::open(storage)?;
+  /// let bbox = ((-0.5,-0.8),(0.3,-0.5));
+  /// let expected = primary.query_digest(&bbox)?;
+  /// let found = replica.query_digest(&bbox)?;
+  /// if found.sequence == expected.sequence && found != expected {
+  ///   eprintln!["replica diverged"];
+  /// }
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn query_digest (&mut self, bbox: &P::Bounds) -> Result<QueryDigest,Error> {
+    let mut digest = QueryDigest::new(self.sequence());
+    for result in self.query_mode(bbox, CacheMode::Normal, Some(AuditOp::Digest))? {
+      let (point,value,_) = result?;
+      digest.add(&point, &value)?;
+    }
+    Ok(digest)
+  }
+}
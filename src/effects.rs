@@ -0,0 +1,83 @@
+use crate::{DB,Point,Value,Change};
+use crate::audit::{self,AuditHold};
+use crate::trigger::TriggerAction;
+use random_access_storage::RandomAccess;
+use std::sync::{Arc,Mutex};
+
+// effect of a call on code outside of the database
+pub(crate) enum Effect<P,V> where P: Point, V: Value {
+  // callback of the trigger with this name for an inserted row
+  Callback(String,P,V),
+  Notify(Vec<Change<P,V>>),
+  CloseSubscriptions
+}
+
+// effects of a call that `AsyncDB` may run again, held back until an attempt
+// finishes without reading a page that isn't cached
+pub(crate) struct Hold<P,V> where P: Point, V: Value {
+  pub effects: Vec<Effect<P,V>>,
+  audit: AuditHold<P>
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  pub(crate) fn hold_effects (&mut self) {
+    self.hold = Some(Hold {
+      effects: vec![],
+      audit: Arc::new(Mutex::new(Some(vec![])))
+    });
+  }
+
+  // set off the effects held since `hold_effects()` in the order they were
+  // made, and any later ones right away
+  pub(crate) fn release_effects (&mut self) {
+    let hold = match self.hold.take() {
+      Some(hold) => hold,
+      None => return
+    };
+    for effect in hold.effects {
+      self.apply(effect);
+    }
+    audit::release(&hold.audit);
+  }
+
+  // forget the effects of an attempt that runs again. lazy queries from the
+  // attempt were dropped with its result, so nothing sends to the hold later.
+  pub(crate) fn discard_effects (&mut self) {
+    self.hold = None;
+  }
+
+  pub(crate) fn audit_hold (&self) -> Option<AuditHold<P>> {
+    self.hold.as_ref().map(|hold| Arc::clone(&hold.audit))
+  }
+
+  pub(crate) fn notify (&mut self, changes: &[Change<P,V>]) {
+    match &mut self.hold {
+      Some(hold) => hold.effects.push(Effect::Notify(changes.to_vec())),
+      None => self.subscriptions.notify(changes)
+    }
+  }
+
+  pub(crate) fn close_subscriptions (&mut self) {
+    match &mut self.hold {
+      Some(hold) => hold.effects.push(Effect::CloseSubscriptions),
+      None => self.subscriptions.close()
+    }
+  }
+
+  fn apply (&mut self, effect: Effect<P,V>) {
+    match effect {
+      Effect::Callback(name,p,v) => {
+        for trigger in self.triggers.iter_mut().filter(|t| t.name == name) {
+          if let TriggerAction::Callback(f) = &mut trigger.action {
+            f(&trigger.name, &p, &v);
+          }
+        }
+      },
+      Effect::Notify(changes) => self.subscriptions.notify(&changes),
+      Effect::CloseSubscriptions => self.subscriptions.close()
+    }
+  }
+}
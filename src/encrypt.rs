@@ -0,0 +1,154 @@
+use crate::{DB,Point,Value};
+use crate::Error;
+use random_access_storage::RandomAccess;
+use std::collections::{BTreeMap,HashMap};
+use std::sync::{Arc,RwLock};
+use crate::lock::Lock;
+
+/// Cipher for the rows of data blocks, set with `Setup::encryption()`.
+///
+/// eyros doesn't ship a cipher: wrap an authenticated cipher from a crate
+/// you trust. `encrypt()` gets a fresh buffer for each block, so include the
+/// nonce in its output and read it back in `decrypt()`. Blocks are moved
+/// between offsets by compaction, so don't bind ciphertexts to an offset.
+///
+/// Only the rows are encrypted: block headers, branches, ranges, and the
+/// other stores are not. To encrypt every store, turn on the `aes` feature
+/// and open the database with `Setup::encrypted()` instead. Both take their
+/// keys from the same key epochs, so `rotate_key()` works with either.
+pub trait Cipher: Send+Sync {
+  /// Encrypt the encoded rows of a block with `key`.
+  fn encrypt (&self, key: &[u8], buf: &[u8]) -> Result<Vec<u8>,Error>;
+  /// Decrypt a buffer written by `encrypt()` with the same `key`.
+  fn decrypt (&self, key: &[u8], buf: &[u8]) -> Result<Vec<u8>,Error>;
+}
+
+/// Cipher and keys from `Setup::encryption()` and `Setup::old_key()`.
+#[derive(Clone)]
+pub struct Encryption {
+  pub cipher: Arc<dyn Cipher>,
+  /// Key for new blocks.
+  pub key: Vec<u8>,
+  /// Keys of earlier epochs, for blocks that haven't been re-encrypted yet.
+  pub old_keys: Vec<(u32,Vec<u8>)>
+}
+
+// the keys of a data store, by epoch
+pub(crate) struct Keyring {
+  cipher: Arc<dyn Cipher>,
+  pub epoch: u32,
+  keys: HashMap<u32,Vec<u8>>
+}
+
+impl Keyring {
+  pub fn new (encryption: &Encryption, epoch: u32) -> Self {
+    let mut keys: HashMap<u32,Vec<u8>> = encryption.old_keys.iter()
+      .cloned().collect();
+    keys.insert(epoch, encryption.key.clone());
+    Self { cipher: Arc::clone(&encryption.cipher), epoch, keys }
+  }
+  pub fn encrypt (&self, buf: &[u8]) -> Result<Vec<u8>,Error> {
+    self.cipher.encrypt(&self.keys[&self.epoch], buf)
+  }
+  pub fn decrypt (&self, epoch: u32, buf: &[u8]) -> Result<Vec<u8>,Error> {
+    match self.keys.get(&epoch) {
+      Some(key) => self.cipher.decrypt(key, buf),
+      None => invalid!["no key for epoch {}, pass it to Setup::old_key()", epoch]
+    }
+  }
+}
+
+// keys of the stores opened by `Setup::encrypted()`, shared between the
+// storage function and the database handle so that `rotate_key()` reaches
+// stores that are already open. stores pick up a new `version` on their next
+// read or write.
+pub(crate) struct StoreKeys {
+  pub key: Vec<u8>,
+  pub old_keys: Vec<(u32,Vec<u8>)>,
+  pub version: u64
+}
+
+pub(crate) type SharedKeys = Arc<RwLock<StoreKeys>>;
+
+impl StoreKeys {
+  pub fn add_old (&mut self, epoch: u32, key: Vec<u8>) {
+    self.old_keys.retain(|(e,_)| *e != epoch);
+    self.old_keys.push((epoch,key));
+    self.version += 1;
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Encrypt new data blocks with `key` under the next key epoch.
+  ///
+  /// Existing blocks record the epoch they were written with and are still
+  /// read with the old key, so nothing is rewritten up front. Tree merges
+  /// write their blocks with the new key, and `compact()` re-encrypts the
+  /// blocks that still use an older key. Use `key_epochs()` to see when the
+  /// old key is no longer needed.
+  ///
+  /// For a database opened with `Setup::encrypted()`, `key` is the new
+  /// `StorageKey` (`key.into()`). Every store seals the blocks it writes
+  /// from now on with the new key and reads older blocks with the key they
+  /// were sealed with, and `compact()` seals every store again with the new
+  /// key, after which the old key is no longer needed.
+  ///
+  /// Open the database with the new key in `Setup::encryption()` or
+  /// `Setup::encrypted()` from now on, and pass the old key to
+  /// `Setup::old_key()` until no block uses it.
+  pub fn rotate_key (&mut self, key: Vec<u8>) -> Result<u32,Error> {
+    self.check_writable()?;
+    if self.fields.check_conflicts {
+      self.check_sequence()?;
+    }
+    if self.fields.encryption.is_none() && self.fields.store_keys.is_none() {
+      invalid!["encryption is not set up, use Setup::encryption() or Setup::encrypted()"];
+    }
+    let epoch = self.meta.key_epoch;
+    let keyring = match &mut self.fields.encryption {
+      Some(encryption) => {
+        let old = std::mem::replace(&mut encryption.key, key.clone());
+        encryption.old_keys.retain(|(e,_)| *e != epoch);
+        encryption.old_keys.push((epoch,old));
+        Some(Keyring::new(encryption, epoch+1))
+      },
+      None => None
+    };
+    // the meta store is sealed with the new key along with the new epoch
+    if let Some(keys) = &self.fields.store_keys {
+      let mut keys = keys.write_lock()?;
+      let old = std::mem::replace(&mut keys.key, key);
+      keys.add_old(epoch, old);
+    }
+    self.meta.key_epoch = epoch+1;
+    let r = self.commit_meta();
+    self.poison_on_err(r)?;
+    if keyring.is_some() {
+      self.data_store.write_lock()?.keys = keyring;
+    }
+    Ok(epoch+1)
+  }
+
+  /// Count the data blocks in the trees by the key epoch they were encrypted
+  /// with, where `None` counts blocks that aren't encrypted.
+  pub fn key_epochs (&mut self) -> Result<BTreeMap<Option<u32>,usize>,Error> {
+    self.check_open()?;
+    let mut offsets = vec![];
+    for tree in self.trees.iter() {
+      let mut t = tree.write_lock()?;
+      if t.is_empty()? { continue }
+      offsets.extend(t.data_offsets()?);
+    }
+    offsets.sort_unstable();
+    offsets.dedup();
+    let mut dstore = self.data_store.write_lock()?;
+    let mut epochs = BTreeMap::new();
+    for offset in offsets {
+      *epochs.entry(dstore.key_epoch(offset)?).or_insert(0) += 1;
+    }
+    Ok(epochs)
+  }
+}
@@ -0,0 +1,536 @@
+use crate::{Setup,Error};
+use crate::error::DecryptFailed;
+use crate::encrypt::{StoreKeys,SharedKeys};
+use crate::lock::Lock;
+use failure::bail;
+use random_access_storage::RandomAccess;
+use ring::aead::{Aad,LessSafeKey,Nonce,UnboundKey,AES_256_GCM,NONCE_LEN};
+use ring::hmac;
+use ring::rand::{SecureRandom,SystemRandom};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::io;
+use std::num::NonZeroU32;
+use std::sync::{Arc,RwLock};
+
+/// Bytes of plaintext in each encrypted block of a store.
+pub const ENCRYPTED_BLOCK_SIZE: usize = 4096;
+const TAG_LEN: usize = 16;
+// id of the key that sealed a slot or header, in the clear at its start
+const KEY_ID_LEN: usize = 4;
+// generation and plaintext length at the start of every sealed slot
+const SLOT_HEADER: usize = 12;
+const SLOT: u64 = (KEY_ID_LEN + NONCE_LEN + SLOT_HEADER + ENCRYPTED_BLOCK_SIZE + TAG_LEN) as u64;
+const SALT_LEN: usize = 16;
+const MAC_LEN: usize = 32;
+const HEADER_BODY: usize = KEY_ID_LEN + SALT_LEN + 8;
+const HEADER_SLOT: u64 = (HEADER_BODY + MAC_LEN) as u64;
+const HEADER_LEN: u64 = 2 * HEADER_SLOT;
+// random 96-bit nonces are safe for 2^32 messages per key. stop short of
+// that, since a crash can lose the last reservation of the count.
+const MAX_SEALS: u64 = (1 << 32) - (1 << 24);
+const SEAL_RESERVATION: u64 = 1 << 12;
+const PBKDF2_ITERATIONS: u32 = 100_000;
+
+/// 256-bit key for `Encrypted` stores.
+#[derive(Clone)]
+pub struct StorageKey([u8;32]);
+
+impl StorageKey {
+  /// Use 32 bytes from a key management system or a secure random source.
+  pub fn new (key: [u8;32]) -> Self {
+    Self(key)
+  }
+  /// Derive a key from a passphrase with PBKDF2-HMAC-SHA256 and 100,000
+  /// iterations. Store `salt` next to the database, since the same
+  /// passphrase and salt are needed to open it again.
+  pub fn from_passphrase (passphrase: &[u8], salt: &[u8]) -> Self {
+    let mut key = [0u8;32];
+    ring::pbkdf2::derive(ring::pbkdf2::PBKDF2_HMAC_SHA256,
+      NonZeroU32::new(PBKDF2_ITERATIONS).unwrap(), salt, passphrase, &mut key);
+    Self(key)
+  }
+}
+
+impl fmt::Debug for StorageKey {
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "StorageKey(..)")
+  }
+}
+
+/// The key bytes, for `db.rotate_key()` and `Setup::old_key()`.
+impl From<StorageKey> for Vec<u8> {
+  fn from (key: StorageKey) -> Vec<u8> {
+    key.0.to_vec()
+  }
+}
+
+type KeyId = [u8;KEY_ID_LEN];
+
+// key of a store and the id that the slots and headers it seals start with
+struct MacKey {
+  id: KeyId,
+  mac: hmac::Key
+}
+
+impl MacKey {
+  fn new (key: &[u8]) -> Self {
+    let mac = hmac::Key::new(hmac::HMAC_SHA256, key);
+    let id = hmac::sign(&mac, b"eyros key id").as_ref()[0..KEY_ID_LEN].try_into().unwrap();
+    Self { id, mac }
+  }
+}
+
+/// Store that encrypts everything written to `inner` with AES-256-GCM.
+///
+/// The plaintext is split into blocks of `ENCRYPTED_BLOCK_SIZE` bytes. Each
+/// block has two fixed slots in `inner`, and every write seals the new
+/// contents of a block with a fresh random nonce into the slot that doesn't
+/// hold its current contents, along with a generation number one past the
+/// current one. Reads use the slot with the highest generation that
+/// decrypts, so a write that is torn by a crash leaves the previous contents
+/// of the block in place instead of corrupting the bytes around the write.
+/// This doubles the size of the stores. Slots are bound to their position
+/// and to the store name, so slots that are altered, swapped, or copied
+/// between stores fail to decrypt with a `DecryptFailed` error. The lengths
+/// of the stores are not hidden.
+///
+/// Random 96-bit nonces are only safe for about 2^32 messages per key, so
+/// each store seals its blocks with a key derived from `key` and a random
+/// salt that is kept in a header at the start of the store, and counts the
+/// blocks it has sealed with the key there. Once a store comes close to 2^32
+/// sealed blocks, writes fail with `Error::Invalid`. Rotate the key before
+/// that with `db.rotate_key()` on a database from `Setup::encrypted()`.
+/// Slots and headers start with an id of the key that sealed them, so
+/// blocks sealed before the rotation are still read with the old key from
+/// `Setup::old_key()` while writes seal blocks with the new one, and
+/// `db.compact()` seals every store again with the new key.
+///
+/// Writes that don't cover whole blocks read and rewrite the blocks at their
+/// edges. Decrypted data blocks are kept by the database's block caches, so
+/// repeated queries over hot blocks don't decrypt them again. The length of
+/// the store is read when it is opened, so open it again to see writes from
+/// other handles.
+pub struct Encrypted<S> {
+  inner: S,
+  keys: SharedKeys,
+  // version of `keys` that the current and old keys were read from
+  version: u64,
+  key: MacKey,
+  old_keys: Vec<MacKey>,
+  name: String,
+  rng: SystemRandom,
+  // salt of the block keys once the store has a header, and the block key
+  // of every known key
+  salt: Option<[u8;SALT_LEN]>,
+  block_keys: HashMap<KeyId,LessSafeKey>,
+  header_slot: u64,
+  // key that the header counts sealed blocks for, the blocks sealed with it
+  // so far, and the count that the header allows
+  header_key: KeyId,
+  sealed: u64,
+  reserved: u64,
+  len: u64,
+  inner_len: u64
+}
+
+// slot of a block that holds its current contents
+struct Current {
+  slot: u64,
+  generation: u64,
+  data: Vec<u8>
+}
+
+impl<S> Encrypted<S> where S: RandomAccess<Error=failure::Error> {
+  /// Wrap the store `inner` that the storage function opened for `name`,
+  /// reading its header and length.
+  pub fn open (inner: S, key: &StorageKey, name: &str) -> Result<Self,failure::Error> {
+    Self::open_with(inner, shared_keys(key.0.to_vec()), name)
+  }
+  pub(crate) fn open_with (inner: S, keys: SharedKeys, name: &str)
+  -> Result<Self,failure::Error> {
+    let (key,old_keys,version) = read_keys(&keys)?;
+    let mut store = Self {
+      inner,
+      keys,
+      version,
+      key,
+      old_keys,
+      name: name.to_string(),
+      rng: SystemRandom::new(),
+      salt: None,
+      block_keys: HashMap::new(),
+      header_slot: 1,
+      header_key: [0;KEY_ID_LEN],
+      sealed: 0,
+      reserved: 0,
+      len: 0,
+      inner_len: 0
+    };
+    store.load()?;
+    Ok(store)
+  }
+  pub fn get_ref (&self) -> &S {
+    &self.inner
+  }
+  pub fn into_inner (self) -> S {
+    self.inner
+  }
+  fn load (&mut self) -> Result<(),failure::Error> {
+    self.inner_len = self.inner.len()?;
+    self.salt = None;
+    self.block_keys.clear();
+    self.len = 0;
+    // a store without a whole header slot was never written
+    if self.inner_len < HEADER_SLOT { return Ok(()) }
+    let n = self.inner_len.min(HEADER_LEN) / HEADER_SLOT * HEADER_SLOT;
+    let buf = self.inner.read(0, n)?;
+    let mut header: Option<(u64,KeyId,[u8;SALT_LEN],u64)> = None;
+    for (slot,bytes) in buf.chunks(HEADER_SLOT as usize).enumerate() {
+      let (body,tag) = bytes.split_at(HEADER_BODY);
+      let id: KeyId = body[0..KEY_ID_LEN].try_into().unwrap();
+      let key = match self.mac_key(&id) {
+        Some(key) => key,
+        None => continue
+      };
+      if hmac::verify(&key.mac, &self.header_mac_input(body), tag).is_err() { continue }
+      let reserved = u64::from_be_bytes(body[KEY_ID_LEN+SALT_LEN..].try_into().unwrap());
+      // the header of the current key counts the blocks that writes seal
+      let rank = (id == self.key.id, reserved);
+      if header.map(|(_,h,_,r)| rank > (h == self.key.id, r)).unwrap_or(true) {
+        let salt = body[KEY_ID_LEN..KEY_ID_LEN+SALT_LEN].try_into().unwrap();
+        header = Some((slot as u64, id, salt, reserved));
+      }
+    }
+    // a store that was truncated to its header holds nothing to decrypt, so
+    // a header from a key that is no longer around leaves it empty
+    if header.is_none() && self.inner_len <= HEADER_LEN { return Ok(()) }
+    let (slot,id,salt,reserved) = header.ok_or_else(|| Error::from(DecryptFailed {
+      name: self.name.clone(),
+      offset: 0
+    }))?;
+    self.header_slot = slot;
+    self.salt = Some(salt);
+    self.derive_keys();
+    // blocks may have been sealed up to the whole reservation
+    self.header_key = id;
+    self.sealed = reserved;
+    self.reserved = reserved;
+    let pairs = (self.inner_len.saturating_sub(HEADER_LEN) / SLOT).div_ceil(2);
+    if pairs > 0 {
+      let last = self.read_block(pairs-1)?;
+      self.len = (pairs-1) * ENCRYPTED_BLOCK_SIZE as u64 + last.data.len() as u64;
+    }
+    Ok(())
+  }
+  fn header_mac_input (&self, body: &[u8]) -> Vec<u8> {
+    let mut input = b"eyros header ".to_vec();
+    input.extend(self.name.as_bytes());
+    input.extend(body);
+    input
+  }
+  fn mac_key (&self, id: &KeyId) -> Option<&MacKey> {
+    std::iter::once(&self.key).chain(self.old_keys.iter()).find(|key| key.id == *id)
+  }
+  // derive the block key of every known key from the salt
+  fn derive_keys (&mut self) {
+    self.block_keys.clear();
+    let salt = match self.salt {
+      Some(salt) => salt,
+      None => return
+    };
+    let mut input = b"eyros blocks ".to_vec();
+    input.extend(&salt);
+    for key in std::iter::once(&self.key).chain(self.old_keys.iter()) {
+      let block_key = hmac::sign(&key.mac, &input);
+      self.block_keys.insert(key.id, LessSafeKey::new(
+        UnboundKey::new(&AES_256_GCM, block_key.as_ref()).expect("32 byte key")));
+    }
+  }
+  // pick up keys from `rotate_key()` since the last read or write
+  fn sync_keys (&mut self) -> Result<(),failure::Error> {
+    if self.keys.read_lock()?.version == self.version { return Ok(()) }
+    let (key,old_keys,version) = read_keys(&self.keys)?;
+    self.key = key;
+    self.old_keys = old_keys;
+    self.version = version;
+    self.derive_keys();
+    Ok(())
+  }
+  fn write_header (&mut self) -> Result<(),failure::Error> {
+    let mut body = self.key.id.to_vec();
+    body.extend(&self.salt.expect("salt"));
+    body.extend(&self.reserved.to_be_bytes());
+    let tag = hmac::sign(&self.key.mac, &self.header_mac_input(&body));
+    body.extend(tag.as_ref());
+    let slot = 1 - self.header_slot;
+    self.inner.write(slot * HEADER_SLOT, &body)?;
+    self.header_slot = slot;
+    self.inner_len = self.inner_len.max((slot + 1) * HEADER_SLOT);
+    Ok(())
+  }
+  // count one more sealed block, creating the header on the first write and
+  // extending its reservation as it runs out
+  fn count_seal (&mut self) -> Result<(),failure::Error> {
+    if self.salt.is_none() {
+      let mut salt = [0u8;SALT_LEN];
+      if self.rng.fill(&mut salt).is_err() {
+        bail!["no random source for encryption salts"];
+      }
+      self.salt = Some(salt);
+      self.derive_keys();
+      self.header_slot = 1;
+      self.header_key = self.key.id;
+      self.sealed = 0;
+      self.reserved = 0;
+    } else if self.header_key != self.key.id {
+      // a rotated key starts its own count in the other header slot
+      self.header_key = self.key.id;
+      self.sealed = 0;
+      self.reserved = 0;
+    }
+    if self.sealed >= MAX_SEALS {
+      return Err(Error::Invalid(format!["encrypted store {} has sealed {} blocks \
+        with its key. rotate the key with db.rotate_key()",
+        self.name, self.sealed]).into());
+    }
+    if self.sealed == self.reserved {
+      self.reserved = (self.reserved + SEAL_RESERVATION).min(MAX_SEALS);
+      self.write_header()?;
+    }
+    self.sealed += 1;
+    Ok(())
+  }
+  fn slot_offset (i: u64, slot: u64) -> u64 {
+    HEADER_LEN + (2*i + slot) * SLOT
+  }
+  fn aad (&self, i: u64, slot: u64) -> Vec<u8> {
+    let mut aad = self.name.as_bytes().to_vec();
+    aad.extend(&i.to_be_bytes());
+    aad.push(slot as u8);
+    aad
+  }
+  // decrypt the current contents of block `i`
+  fn read_block (&mut self, i: u64) -> Result<Current,failure::Error> {
+    let start = Self::slot_offset(i, 0);
+    let n = self.inner_len.saturating_sub(start).min(2*SLOT) / SLOT * SLOT;
+    let buf = if n > 0 { self.inner.read(start, n)? } else { vec![] };
+    let mut current: Option<Current> = None;
+    for (slot,bytes) in buf.chunks(SLOT as usize).enumerate() {
+      let slot = slot as u64;
+      let (id,bytes) = bytes.split_at(KEY_ID_LEN);
+      // slots sealed with a key that isn't known can't be read
+      let key = match self.block_keys.get(id) {
+        Some(key) => key,
+        None => continue
+      };
+      let mut sealed = bytes[NONCE_LEN..].to_vec();
+      let nonce = Nonce::assume_unique_for_key(bytes[0..NONCE_LEN].try_into().unwrap());
+      let aad = self.aad(i, slot);
+      let plain = match key.open_in_place(nonce, Aad::from(&aad), &mut sealed) {
+        Ok(plain) => plain,
+        Err(_) => continue
+      };
+      let generation = u64::from_be_bytes(plain[0..8].try_into().unwrap());
+      let len = u32::from_be_bytes(plain[8..12].try_into().unwrap()) as usize;
+      if len > ENCRYPTED_BLOCK_SIZE { continue }
+      if current.as_ref().map(|c| generation > c.generation).unwrap_or(true) {
+        let data = plain[SLOT_HEADER..SLOT_HEADER+len].to_vec();
+        current = Some(Current { slot, generation, data });
+      }
+    }
+    current.ok_or_else(|| Error::from(DecryptFailed {
+      name: self.name.clone(),
+      offset: i * ENCRYPTED_BLOCK_SIZE as u64
+    }).into())
+  }
+  // seal `plain` as the next generation of block `i`, in the slot that
+  // doesn't hold `current`
+  fn write_block (&mut self, i: u64, current: Option<&Current>, plain: &[u8])
+  -> Result<(),failure::Error> {
+    self.count_seal()?;
+    let (slot,generation) = match current {
+      Some(c) => (1 - c.slot, c.generation + 1),
+      None => (0, 1)
+    };
+    let mut nonce = [0u8;NONCE_LEN];
+    if self.rng.fill(&mut nonce).is_err() {
+      bail!["no random source for encryption nonces"];
+    }
+    let mut sealed = Vec::with_capacity(SLOT as usize);
+    sealed.extend(&generation.to_be_bytes());
+    sealed.extend(&(plain.len() as u32).to_be_bytes());
+    sealed.extend_from_slice(plain);
+    sealed.resize(SLOT_HEADER + ENCRYPTED_BLOCK_SIZE, 0);
+    let aad = self.aad(i, slot);
+    let key = &self.block_keys[&self.key.id];
+    if key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce),
+    Aad::from(&aad), &mut sealed).is_err() {
+      bail!["block {} of {} is too large to encrypt", i, self.name];
+    }
+    let mut buf = self.key.id.to_vec();
+    buf.extend(&nonce);
+    buf.extend(sealed);
+    let offset = Self::slot_offset(i, slot);
+    self.inner.write(offset, &buf)?;
+    self.inner_len = self.inner_len.max(offset + SLOT);
+    Ok(())
+  }
+  // load the header and length again if another handle changed the store
+  fn refresh (&mut self) -> Result<(),failure::Error> {
+    self.sync_keys()?;
+    if self.inner.len()? != self.inner_len { self.load()?; }
+    Ok(())
+  }
+}
+
+fn shared_keys (key: Vec<u8>) -> SharedKeys {
+  Arc::new(RwLock::new(StoreKeys { key, old_keys: vec![], version: 0 }))
+}
+
+// the current and old keys from `keys`, with the version they were read at
+fn read_keys (keys: &SharedKeys) -> Result<(MacKey,Vec<MacKey>,u64),failure::Error> {
+  let keys = keys.read_lock()?;
+  let old_keys = keys.old_keys.iter().map(|(_,key)| MacKey::new(key)).collect();
+  Ok((MacKey::new(&keys.key), old_keys, keys.version))
+}
+
+impl<S> RandomAccess for Encrypted<S> where S: RandomAccess<Error=failure::Error> {
+  type Error = failure::Error;
+  fn write (&mut self, offset: u64, data: &[u8]) -> Result<(),failure::Error> {
+    if data.is_empty() { return Ok(()) }
+    self.refresh()?;
+    let len = self.len;
+    let end = offset + data.len() as u64;
+    let bs = ENCRYPTED_BLOCK_SIZE as u64;
+    // writes past the end fill the gap with zeros, starting from the block
+    // that holds the current end
+    for i in offset.min(len)/bs ..= (end-1)/bs {
+      let start = i * bs;
+      let current = if start < len { Some(self.read_block(i)?) } else { None };
+      let mut block = current.as_ref().map(|c| c.data.clone()).unwrap_or_default();
+      let size = (block.len() as u64).max((end - start).min(bs));
+      block.resize(size as usize, 0);
+      let (from,to) = (offset.max(start), end.min(start + bs));
+      if from < to {
+        block[(from-start) as usize..(to-start) as usize]
+          .copy_from_slice(&data[(from-offset) as usize..(to-offset) as usize]);
+      }
+      self.write_block(i, current.as_ref(), &block)?;
+      self.len = self.len.max(start + size);
+    }
+    Ok(())
+  }
+  fn read (&mut self, offset: u64, length: u64) -> Result<Vec<u8>,failure::Error> {
+    self.refresh()?;
+    let len = self.len;
+    if offset + length > len {
+      bail!["read of {} bytes at {} is out of bounds of {} ({} bytes)",
+        length, offset, self.name, len];
+    }
+    let mut out = Vec::with_capacity(length as usize);
+    if length == 0 { return Ok(out) }
+    let bs = ENCRYPTED_BLOCK_SIZE as u64;
+    let end = offset + length;
+    for i in offset/bs ..= (end-1)/bs {
+      let start = i * bs;
+      let block = self.read_block(i)?.data;
+      let (from,to) = (offset.max(start), end.min(start + bs));
+      out.extend_from_slice(&block[(from-start) as usize..(to-start) as usize]);
+    }
+    Ok(out)
+  }
+  fn read_to_writer (&mut self, offset: u64, length: u64,
+  buf: &mut impl io::Write) -> Result<(),failure::Error> {
+    Ok(buf.write_all(&self.read(offset, length)?)?)
+  }
+  fn del (&mut self, offset: u64, length: u64) -> Result<(),failure::Error> {
+    self.refresh()?;
+    let len = self.len;
+    if offset >= len { return Ok(()) }
+    self.write(offset, &vec![0u8;(len - offset).min(length) as usize])
+  }
+  fn truncate (&mut self, length: u64) -> Result<(),failure::Error> {
+    self.refresh()?;
+    let len = self.len;
+    if length >= len {
+      return self.write(len, &vec![0u8;(length - len) as usize]);
+    }
+    let bs = ENCRYPTED_BLOCK_SIZE as u64;
+    let (i,rem) = (length / bs, length % bs);
+    // the header stays, so the count of sealed blocks carries on
+    let end = if rem > 0 {
+      let current = self.read_block(i)?;
+      self.write_block(i, Some(&current), &current.data[0..rem as usize])?;
+      Self::slot_offset(i+1, 0)
+    } else {
+      Self::slot_offset(i, 0)
+    };
+    if end < self.inner_len {
+      self.inner.truncate(end)?;
+      self.inner_len = end;
+    }
+    self.len = length;
+    Ok(())
+  }
+  fn len (&self) -> Result<u64,failure::Error> {
+    Ok(self.len)
+  }
+  fn is_empty (&mut self) -> Result<bool,failure::Error> {
+    self.refresh()?;
+    Ok(self.len == 0)
+  }
+  fn sync_all (&mut self) -> Result<(),failure::Error> {
+    self.inner.sync_all()
+  }
+}
+
+/// Storage function that opens every store encrypted with one key, from
+/// `encrypt_storage()` or `Setup::encrypted()`.
+pub type EncryptedStorage<S> =
+  Box<dyn Fn(&str) -> Result<Encrypted<S>,failure::Error> + Send + Sync>;
+
+/// Wrap a storage function so that every store it opens is `Encrypted` with
+/// `key`. Use `Setup::encrypted()` to open a database whose key can be
+/// rotated with `db.rotate_key()`.
+pub fn encrypt_storage<S,F> (key: StorageKey, open_store: F) -> EncryptedStorage<S>
+where S: RandomAccess<Error=failure::Error>,
+F: Fn(&str) -> Result<S,failure::Error> + Send + Sync + 'static {
+  encrypt_with(shared_keys(key.0.to_vec()), open_store)
+}
+
+fn encrypt_with<S,F> (keys: SharedKeys, open_store: F) -> EncryptedStorage<S>
+where S: RandomAccess<Error=failure::Error>,
+F: Fn(&str) -> Result<S,failure::Error> + Send + Sync + 'static {
+  Box::new(move |name: &str| Encrypted::open_with(open_store(name)?, Arc::clone(&keys), name))
+}
+
+impl<S> Setup<Encrypted<S>,EncryptedStorage<S>>
+where S: RandomAccess<Error=failure::Error> {
+  /// Create a `Setup` whose stores are all encrypted at rest with `key`, for
+  /// databases on untrusted disks or object storage. Opening the database
+  /// with another key fails with a `DecryptFailed` error.
+  ///
+  /// Rotate the key with `db.rotate_key(new_key.into())`. Until the database
+  /// is compacted after that, open it with the new key here and the old one
+  /// in `old_key()`.
+  ///
+  /// ```rust,no_run
+  /// use eyros::{DB,Setup,StorageKey,Error};
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// let key = StorageKey::from_passphrase(b"correct horse", b"eyros-db-salt");
+  /// let mut db: DB<_,_,(f32,f32),u32> = Setup::encrypted(|name: &str| {
+  ///   RandomAccessDisk::open(std::path::Path::new("/tmp/eyros-db").join(name))
+  /// }, key).build()?;
+  /// # Ok(()) }
+  /// ```
+  pub fn encrypted<F> (open_store: F, key: StorageKey) -> Self
+  where F: Fn(&str) -> Result<S,failure::Error> + Send + Sync + 'static {
+    let keys = shared_keys(key.into());
+    let mut setup = Setup::new(encrypt_with(Arc::clone(&keys), open_store));
+    setup.fields.store_keys = Some(keys);
+    setup
+  }
+}
@@ -1,3 +1,25 @@
+// Return early with an `Error::Corrupt` built from a format string.
+macro_rules! corrupt {
+  ($($arg:tt)*) => {
+    return Err($crate::Error::Corrupt(format!($($arg)*)).into())
+  };
+}
+
+// Return early with an `Error::Corrupt` unless the condition holds, for checks
+// on stored data.
+macro_rules! ensure {
+  ($cond:expr, $($arg:tt)*) => {
+    if !$cond { corrupt!($($arg)*) }
+  };
+}
+
+// Return early with an `Error::Invalid` built from a format string.
+macro_rules! invalid {
+  ($($arg:tt)*) => {
+    return Err($crate::Error::Invalid(format!($($arg)*)).into())
+  };
+}
+
 #[doc(hidden)]
 #[macro_export]
 macro_rules! ensure_eq {
@@ -5,9 +27,9 @@ macro_rules! ensure_eq {
     match (&$left, &$right) {
       (left_val, right_val) => {
         if !(*left_val == *right_val) {
-          return Err(format_err!(r#"assertion failed: `(left == right)`
+          return Err($crate::Error::Corrupt(format!(r#"assertion failed: `(left == right)`
   left: `{:?}`,
- right: `{:?}`"#, left_val, right_val));
+ right: `{:?}`"#, left_val, right_val)));
         }
       }
     }
@@ -16,9 +38,9 @@ macro_rules! ensure_eq {
     match (&($left), &($right)) {
       (left_val, right_val) => {
         if !(*left_val == *right_val) {
-          return Err(format_err!(r#"assertion failed: `(left == right)`
+          return Err($crate::Error::Corrupt(format!(r#"assertion failed: `(left == right)`
   left: `{:?}`,
- right: `{:?}`: {}"#, left_val, right_val, format_args!($($arg)*)));
+ right: `{:?}`: {}"#, left_val, right_val, format_args!($($arg)*))));
         }
       }
     }
@@ -32,9 +54,9 @@ macro_rules! ensure_eq_some {
     match (&$left, &$right) {
       (left_val, right_val) => {
         if !(*left_val == *right_val) {
-          return Some(Err(format_err!(r#"assertion failed: `(left == right)`
+          return Some(Err($crate::Error::Corrupt(format!(r#"assertion failed: `(left == right)`
   left: `{:?}`,
- right: `{:?}`"#, left_val, right_val)));
+ right: `{:?}`"#, left_val, right_val))));
         }
       }
     }
@@ -43,9 +65,9 @@ macro_rules! ensure_eq_some {
     match (&($left), &($right)) {
       (left_val, right_val) => {
         if !(*left_val == *right_val) {
-          return Some(Err(format_err!(r#"assertion failed: `(left == right)`
+          return Some(Err($crate::Error::Corrupt(format!(r#"assertion failed: `(left == right)`
   left: `{:?}`,
- right: `{:?}`: {}"#, left_val, right_val, format_args!($($arg)*))));
+ right: `{:?}`: {}"#, left_val, right_val, format_args!($($arg)*)))));
         }
       }
     }
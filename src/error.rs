@@ -0,0 +1,472 @@
+use std::{fmt,io};
+use std::time::Duration;
+use std::any::Any;
+use thiserror::Error;
+use failure::Compat;
+
+/// Error type of every eyros operation.
+///
+/// Match on the variant to tell the failures apart: `Storage` and `Io` come
+/// from the storage backend or the filesystem and may go away when the
+/// operation is retried, `Corrupt` means that stored data couldn't be
+/// decoded, `DataSizeLimit` means that a block would hold more rows than
+/// `Setup::max_data_size()`, `Invalid` means that the arguments or settings can't be used,
+/// and the other variants each carry one of the typed errors in this module.
+/// Clones keep the variant, but `Storage` and `Io` errors are rebuilt from
+/// their messages.
+///
+/// ```rust,no_run
+/// use eyros::{DB,Error};
+/// # use std::path::PathBuf;
+/// # use random_access_disk::RandomAccessDisk;
+/// # fn main () -> Result<(),Error> {
+/// # let mut db: DB<_,_,(f32,f32),u32> = DB::open(storage)?;
+/// db.close()?;
+/// match db.query(&((0.0,0.0),(1.0,1.0))) {
+///   Err(Error::Closed(_)) => {},
+///   Err(e) => return Err(e),
+///   Ok(_) => panic!["query after close"]
+/// }
+/// # Ok(()) }
+/// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+/// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+/// #   p.push(name);
+/// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+/// # }
+/// ```
+#[derive(Debug,Error)]
+#[non_exhaustive]
+pub enum Error {
+  /// Error from a storage backend, or from other code built on `failure`
+  /// such as a callback. Storage functions and `RandomAccess` stores keep
+  /// returning `failure::Error`, which converts into this variant unless it
+  /// holds an I/O error or an eyros error.
+  #[error("{0}")]
+  Storage(Box<dyn std::error::Error + Send + Sync>),
+  /// I/O error from the filesystem or from a storage backend.
+  #[error(transparent)]
+  Io(#[from] io::Error),
+  /// Stored data that couldn't be decoded, from a torn write, bit rot, or a
+  /// store that doesn't belong to an eyros database.
+  #[error("corrupt data: {0}")]
+  Corrupt(String),
+  /// Data block with more rows than `Setup::max_data_size()` allows.
+  #[error("{rows} rows exceed the data size limit of {max}")]
+  DataSizeLimit {
+    /// Rows that were to be written into the block.
+    rows: usize,
+    /// Limit set with `Setup::max_data_size()`.
+    max: usize
+  },
+  /// Arguments or settings that can't be used, or a feature that this build
+  /// doesn't include.
+  #[error("{0}")]
+  Invalid(String),
+  #[error(transparent)]
+  Closed(#[from] Closed),
+  #[error(transparent)]
+  Poisoned(#[from] Poisoned),
+  #[error(transparent)]
+  Conflict(#[from] Conflict),
+  #[error(transparent)]
+  Stale(#[from] Stale),
+  #[error(transparent)]
+  StaleLocation(#[from] StaleLocation),
+  #[error(transparent)]
+  StaleCursor(#[from] StaleCursor),
+  #[error(transparent)]
+  ChecksumMismatch(#[from] ChecksumMismatch),
+  #[error(transparent)]
+  Overloaded(#[from] Overloaded),
+  #[error(transparent)]
+  Backpressure(#[from] Backpressure),
+  #[error(transparent)]
+  HistoryPruned(#[from] HistoryPruned),
+  #[error(transparent)]
+  ReadOnly(#[from] ReadOnly),
+  #[error(transparent)]
+  Locked(#[from] Locked),
+  #[error(transparent)]
+  FormatVersion(#[from] FormatVersion),
+  #[error(transparent)]
+  DecryptFailed(#[from] DecryptFailed),
+  #[error(transparent)]
+  Cancelled(#[from] Cancelled),
+  #[error(transparent)]
+  TimedOut(#[from] TimedOut),
+  /// Any other failure, such as a lock poisoned by a panicked thread.
+  #[error("{0}")]
+  Other(String)
+}
+
+impl Error {
+  /// Return the typed error inside this error, if it has type `T`. Errors
+  /// from storage backends are searched too.
+  ///
+  /// This eases porting code that called `downcast_ref()` on
+  /// `failure::Error`; matching on the variants is usually clearer.
+  pub fn downcast_ref<T> (&self) -> Option<&T>
+  where T: std::error::Error + Send + Sync + 'static {
+    let inner: &dyn Any = match self {
+      Error::Storage(e) => return match e.downcast_ref::<Compat<failure::Error>>() {
+        Some(compat) => compat.get_ref().downcast_ref::<T>(),
+        None => e.downcast_ref::<T>()
+      },
+      Error::Io(e) => e,
+      Error::Corrupt(_) | Error::DataSizeLimit { .. } | Error::Invalid(_)
+        | Error::Other(_) => return None,
+      Error::Closed(e) => e,
+      Error::Poisoned(e) => e,
+      Error::Conflict(e) => e,
+      Error::Stale(e) => e,
+      Error::StaleLocation(e) => e,
+      Error::StaleCursor(e) => e,
+      Error::ChecksumMismatch(e) => e,
+      Error::Overloaded(e) => e,
+      Error::Backpressure(e) => e,
+      Error::HistoryPruned(e) => e,
+      Error::ReadOnly(e) => e,
+      Error::Locked(e) => e,
+      Error::FormatVersion(e) => e,
+      Error::DecryptFailed(e) => e,
+      Error::Cancelled(e) => e,
+      Error::TimedOut(e) => e
+    };
+    inner.downcast_ref::<T>()
+  }
+}
+
+// one failure can be returned to every caller that shared the operation, such
+// as the writers of a group commit. `Storage` and `Io` errors are rebuilt from
+// their messages, keeping the kind of io errors so that retries still see them
+impl Clone for Error {
+  fn clone (&self) -> Self {
+    match self {
+      Error::Storage(e) => Error::Storage(e.to_string().into()),
+      Error::Io(e) => Error::Io(io::Error::new(e.kind(), e.to_string())),
+      Error::Corrupt(s) => Error::Corrupt(s.clone()),
+      Error::DataSizeLimit { rows, max } => Error::DataSizeLimit { rows: *rows, max: *max },
+      Error::Invalid(s) => Error::Invalid(s.clone()),
+      Error::Closed(e) => Error::Closed(*e),
+      Error::Poisoned(e) => Error::Poisoned(e.clone()),
+      Error::Conflict(e) => Error::Conflict(*e),
+      Error::Stale(e) => Error::Stale(*e),
+      Error::StaleLocation(e) => Error::StaleLocation(*e),
+      Error::StaleCursor(e) => Error::StaleCursor(*e),
+      Error::ChecksumMismatch(e) => Error::ChecksumMismatch(*e),
+      Error::Overloaded(e) => Error::Overloaded(*e),
+      Error::Backpressure(e) => Error::Backpressure(*e),
+      Error::HistoryPruned(e) => Error::HistoryPruned(*e),
+      Error::ReadOnly(e) => Error::ReadOnly(*e),
+      Error::Locked(e) => Error::Locked(e.clone()),
+      Error::FormatVersion(e) => Error::FormatVersion(*e),
+      Error::DecryptFailed(e) => Error::DecryptFailed(e.clone()),
+      Error::Cancelled(e) => Error::Cancelled(*e),
+      Error::TimedOut(e) => Error::TimedOut(*e),
+      Error::Other(s) => Error::Other(s.clone())
+    }
+  }
+}
+
+impl From<failure::Error> for Error {
+  fn from (err: failure::Error) -> Self {
+    // unwrap eyros errors that passed through a storage backend or callback,
+    // and keep the I/O errors of storage backends matchable as `Io`
+    let err = match err.downcast::<Error>() {
+      Ok(e) => return e,
+      Err(err) => err
+    };
+    match err.downcast::<io::Error>() {
+      Ok(e) => Error::Io(e),
+      Err(err) => Error::Storage(Box::new(err.compat()))
+    }
+  }
+}
+
+#[cfg(any(feature="geojson",feature="etcd-lease"))]
+impl From<serde_json::Error> for Error {
+  fn from (err: serde_json::Error) -> Self {
+    if err.is_io() { Error::Io(err.into()) } else { Error::Invalid(err.to_string()) }
+  }
+}
+
+/// Error returned by operations on a database handle after `close()`.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Closed;
+
+impl fmt::Display for Closed {
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "database handle is closed")
+  }
+}
+
+impl std::error::Error for Closed {}
+
+/// Error returned by operations on a database handle after a write failed
+/// partway through and left the in-memory state out of sync with storage.
+///
+/// Call `db.try_recover()` to reload the handle from storage.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Poisoned {
+  /// Message of the error that poisoned the handle.
+  pub reason: String
+}
+
+impl fmt::Display for Poisoned {
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "database handle is poisoned by an earlier error: {}", self.reason)
+  }
+}
+
+impl std::error::Error for Poisoned {}
+
+/// Error returned by `batch()` when conflict checks are enabled and another
+/// writer committed to the same storage after this handle last loaded it.
+///
+/// Call `db.try_recover()` to load the other writer's changes before writing
+/// the batch again.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Conflict {
+  /// Sequence number this handle expected to find in storage.
+  pub expected: u64,
+  /// Sequence number found in storage.
+  pub found: u64
+}
+
+impl fmt::Display for Conflict {
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "conflicting commit from another writer (expected sequence {}, found {})",
+      self.expected, self.found)
+  }
+}
+
+impl std::error::Error for Conflict {}
+
+/// Error returned by `query_consistent()` when storage doesn't have the
+/// commit that a `Consistency::ReadYourWrites` token requires.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Stale {
+  /// Latest sequence number found in storage.
+  pub sequence: u64,
+  /// Sequence number the query required.
+  pub required: u64
+}
+
+impl fmt::Display for Stale {
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "storage is at sequence {} but the query requires {}",
+      self.sequence, self.required)
+  }
+}
+
+impl std::error::Error for Stale {}
+
+/// Error returned by `db.resolve_location()` for a `StableLocation` read
+/// before the database's latest commit.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct StaleLocation {
+  /// Sequence number the location was read at.
+  pub generation: u64,
+  /// Sequence number of the database's latest commit.
+  pub sequence: u64
+}
+
+impl fmt::Display for StaleLocation {
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "location from sequence {} is stale at sequence {}",
+      self.generation, self.sequence)
+  }
+}
+
+impl std::error::Error for StaleLocation {}
+
+/// Error returned by `db.query_resume()` for a `QueryCursor` taken before
+/// the database's latest commit.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct StaleCursor {
+  /// Sequence number the cursor was taken at.
+  pub generation: u64,
+  /// Sequence number of the database's latest commit.
+  pub sequence: u64
+}
+
+impl fmt::Display for StaleCursor {
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "query cursor from sequence {} is stale at sequence {}",
+      self.generation, self.sequence)
+  }
+}
+
+impl std::error::Error for StaleCursor {}
+
+/// Error returned when the rows of a data block don't match the checksum in
+/// its header, usually because of bit rot or a torn write.
+///
+/// Queries quarantine blocks that fail with this error.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct ChecksumMismatch {
+  /// Checksum stored in the block header.
+  pub expected: u32,
+  /// Checksum of the rows that were read.
+  pub found: u32
+}
+
+impl fmt::Display for ChecksumMismatch {
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "data block checksum mismatch (expected {:08x}, found {:08x})",
+      self.expected, self.found)
+  }
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
+/// Error returned by a query when `Setup::max_queries()` queries are already
+/// in flight on the database handle.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Overloaded {
+  /// Maximum number of queries in flight.
+  pub limit: usize
+}
+
+impl fmt::Display for Overloaded {
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "too many queries in flight (limit {})", self.limit)
+  }
+}
+
+impl std::error::Error for Overloaded {}
+
+/// Error returned by `batch()` when more staged rows wait for maintenance
+/// than `Setup::realtime()` allows.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Backpressure {
+  /// Number of staged rows past `base_size()`.
+  pub debt: usize,
+  /// Maximum debt before batches are refused.
+  pub limit: usize
+}
+
+impl fmt::Display for Backpressure {
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} staged rows wait for maintenance (limit {})",
+      self.debt, self.limit)
+  }
+}
+
+impl std::error::Error for Backpressure {}
+
+/// Error returned by `db.changes(since)` when changes after `since` were
+/// removed from the changes feed by the retention policy.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct HistoryPruned {
+  /// Sequence number of the oldest change still in the feed.
+  pub first: u64
+}
+
+impl fmt::Display for HistoryPruned {
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "changes before sequence {} were pruned", self.first)
+  }
+}
+
+impl std::error::Error for HistoryPruned {}
+
+/// Error returned by writes to a database opened with `Setup::read_only()`,
+/// such as one opened with `DB::open_remote()`.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct ReadOnly;
+
+impl fmt::Display for ReadOnly {
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "database handle is read-only")
+  }
+}
+
+impl std::error::Error for ReadOnly {}
+
+/// Error returned when opening a database for writing while another handle,
+/// in this process or another one, holds its `Setup::lock_file()`. Open the
+/// database with `Setup::read_only()` to read next to the writer.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Locked {
+  /// Path of the lock file.
+  pub path: String
+}
+
+impl fmt::Display for Locked {
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "database is locked by another writer ({})", self.path)
+  }
+}
+
+impl std::error::Error for Locked {}
+
+/// Error returned when opening a database whose meta store was written in a
+/// format version that this version of eyros can't read, either because it
+/// is newer than `FORMAT_VERSION` or because it needs an upgrade that a
+/// `Setup::read_only()` handle can't write.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct FormatVersion {
+  /// Version recorded in the meta store.
+  pub found: u16,
+  /// Newest version that this version of eyros reads and writes.
+  pub supported: u16
+}
+
+impl fmt::Display for FormatVersion {
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "unsupported database format version {} (supported: {})",
+      self.found, self.supported)
+  }
+}
+
+impl std::error::Error for FormatVersion {}
+
+/// Error returned when a block of an `Encrypted` store fails to decrypt,
+/// because the store was opened with another key or its contents were
+/// altered.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct DecryptFailed {
+  /// Name of the store.
+  pub name: String,
+  /// Offset of the block in the decrypted store.
+  pub offset: u64
+}
+
+impl fmt::Display for DecryptFailed {
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "block at offset {} of store {} failed to decrypt (wrong key or \
+      altered data)", self.offset, self.name)
+  }
+}
+
+impl std::error::Error for DecryptFailed {}
+
+/// Error returned by a query once the `CancelToken` passed to
+/// `.cancel_on()` is cancelled.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "query was cancelled")
+  }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Error returned by a query that passed the deadline set with `.timeout()`
+/// or `.deadline()`.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct TimedOut {
+  /// Deadline on the database clock, as time since the unix epoch.
+  pub deadline: Duration
+}
+
+impl fmt::Display for TimedOut {
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "query passed its deadline at {:?}", self.deadline)
+  }
+}
+
+impl std::error::Error for TimedOut {}
@@ -0,0 +1,126 @@
+use crate::{DB,Point,Value,QueryCost};
+use crate::lock::Lock;
+use crate::Error;
+use random_access_storage::RandomAccess;
+use std::fmt;
+
+/// Steps that a query over a bounding box would take, returned by
+/// `db.explain()`.
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct QueryPlan {
+  /// Rows in the staging area, which every query scans in full.
+  pub staged: u64,
+  /// Staged deletes, which every query checks its results against.
+  pub staged_deletes: u64,
+  /// Staged rows that intersect the bounding box.
+  pub staged_matches: u64,
+  /// Trees that the query would visit, in the order it visits them. Empty
+  /// trees are skipped.
+  pub trees: Vec<TreePlan>,
+  /// Totals of the staged matches and of every tree.
+  pub cost: QueryCost
+}
+
+/// Part of a `QueryPlan` for one tree.
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct TreePlan {
+  /// Slot of the tree. Trees in higher slots hold more rows.
+  pub index: usize,
+  /// Rows that the selectivity statistics expect the tree to return, or
+  /// `None` without `Setup::selectivity_order()`.
+  pub estimate: Option<f64>,
+  /// Data blocks, bytes, and rows of the tree that the query would read.
+  pub cost: QueryCost
+}
+
+impl fmt::Display for QueryPlan {
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "staging: {} rows, {} deletes, {} matching",
+      self.staged, self.staged_deletes, self.staged_matches)?;
+    for t in self.trees.iter() {
+      write!(f, "tree {}: {} blocks, {} bytes, {} rows ({} inside)",
+        t.index, t.cost.blocks, t.cost.bytes, t.cost.rows, t.cost.rows_inside)?;
+      match t.estimate {
+        Some(n) => writeln!(f, ", expecting {:.0} results", n)?,
+        None => writeln!(f)?
+      }
+    }
+    write!(f, "total: {} blocks, {} bytes, at most {} rows",
+      self.cost.blocks, self.cost.bytes, self.cost.rows)
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Describe how a query over `bbox` would run without reading any data
+  /// blocks: the size of the staging area, and for each tree in the order
+  /// that `query()` visits them, the blocks, bytes, and rows it would read.
+  ///
+  /// The counts come from the same range metadata as `estimate_cost()`, which
+  /// returns the totals alone. Many small trees that each read a few blocks
+  /// point at a `base_size` that is too small for the batches, and a large
+  /// staging area that every query scans points at one that is too large.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut db: DB<_,_,(f32,f32),u32> = DB::open(storage)?;
+  /// let plan = db.explain(&((-0.5,-0.8),(0.3,-0.5)))?;
+  /// println!["{}", plan];
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn explain (&mut self, bbox: &P::Bounds) -> Result<QueryPlan,Error> {
+    self.check_open()?;
+    let mut plan = QueryPlan::default();
+    {
+      let deletes = self.staging.delete_set.read_lock()?;
+      let inserts = self.staging.inserts.read_lock()?;
+      plan.staged = inserts.len() as u64;
+      plan.staged_deletes = deletes.len() as u64;
+      plan.staged_matches = inserts.iter().enumerate()
+        .filter(|(i,(p,_))| !deletes.contains(&(0,*i as u32)) && p.overlaps(bbox))
+        .count() as u64;
+    }
+    let axes = if self.fields.selectivity_order { P::bounds_axes(bbox) } else { None };
+    let stats = match &axes {
+      Some(_) => self.selectivity()?,
+      None => vec![None;self.trees.len()]
+    };
+    let mut trees = vec![];
+    let mut groups = vec![];
+    for (i,tree) in self.trees.iter().enumerate() {
+      let mut t = tree.write_lock()?;
+      if t.is_empty()? { continue }
+      let estimate = match (&axes,&stats[i]) {
+        (Some(axes),Some(s)) => Some(s.estimate(axes)),
+        _ => None
+      };
+      trees.push(TreePlan { index: i, estimate, cost: QueryCost::default() });
+      groups.push(t.query_offsets(bbox)?);
+    }
+    for (t,cost) in trees.iter_mut().zip(self.block_costs(groups, bbox)?) {
+      t.cost = cost;
+    }
+    // same order as query(): trees with the most expected results first
+    trees.sort_by(|a,b| b.estimate.unwrap_or(0.0).total_cmp(&a.estimate.unwrap_or(0.0)));
+    plan.cost.rows = plan.staged_matches;
+    plan.cost.rows_inside = plan.staged_matches;
+    for t in trees.iter() {
+      plan.cost.blocks += t.cost.blocks;
+      plan.cost.bytes += t.cost.bytes;
+      plan.cost.rows += t.cost.rows;
+      plan.cost.rows_inside += t.cost.rows_inside;
+    }
+    plan.trees = trees;
+    Ok(plan)
+  }
+}
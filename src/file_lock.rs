@@ -0,0 +1,128 @@
+use crate::{DB,Setup,Point,Value};
+use crate::error::Locked;
+use crate::Error;
+use random_access_storage::RandomAccess;
+use std::path::{Path,PathBuf};
+
+// advisory lock on a file that a writable handle holds until it is closed
+pub(crate) struct WriterLock {
+  #[cfg(not(target_arch="wasm32"))]
+  _file: std::fs::File
+}
+
+impl WriterLock {
+  #[cfg(not(target_arch="wasm32"))]
+  pub fn acquire (path: &Path) -> Result<Self,Error> {
+    use std::fs::{OpenOptions,TryLockError};
+    let file = OpenOptions::new().create(true).truncate(false).write(true)
+      .open(path)?;
+    match file.try_lock() {
+      Ok(()) => Ok(Self { _file: file }),
+      Err(TryLockError::WouldBlock) => {
+        Err(Locked { path: path.display().to_string() }.into())
+      },
+      Err(TryLockError::Error(e)) => Err(e.into())
+    }
+  }
+  // lock files need a filesystem, which browsers don't have
+  #[cfg(target_arch="wasm32")]
+  pub fn acquire (_path: &Path) -> Result<Self,Error> {
+    failure::bail!["lock files aren't supported on wasm32"]
+  }
+}
+
+impl<S,U> Setup<S,U> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>) {
+  /// Hold an advisory lock on the file at `path` while the handle is open,
+  /// so that a second writable handle on the same stores, from this process
+  /// or another one, fails to open with a `Locked` error instead of
+  /// corrupting them. Use a path next to the stores, such as a `lock` file
+  /// in their directory. `DB::open_dir()` does this by default.
+  ///
+  /// A storage function alone doesn't say where its stores live, so
+  /// databases opened with `DB::open()` or `Setup::new()` take no lock until
+  /// a path is given here.
+  ///
+  /// The lock is released by `close()` or when the handle is dropped, and
+  /// by the operating system if the process dies. Handles opened with
+  /// `read_only()` don't take the lock, so readers can attach while a writer
+  /// is open. Not available on wasm32.
+  pub fn lock_file<T> (mut self, path: T) -> Self where T: Into<PathBuf> {
+    self.fields.lock_file = Some(path.into());
+    self
+  }
+  /// Take the lock of `lock_file()` when the database opens. On by default.
+  /// Pass `false` to open a writable handle without the lock, such as on
+  /// storage that another mechanism already keeps to a single writer.
+  pub fn lock (mut self, enabled: bool) -> Self {
+    self.fields.lock = enabled;
+    self
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Open a database for queries only, like `Setup::read_only()`. The handle
+  /// never writes to the stores and doesn't take the lock of
+  /// `Setup::lock_file()`, so it can be opened next to a writer.
+  pub fn open_read_only (open_store: U) -> Result<Self,Error> {
+    Setup::new(open_store).read_only(true).build()
+  }
+}
+
+#[cfg(not(target_arch="wasm32"))]
+pub use disk::{DiskStorage,disk_storage};
+
+#[cfg(not(target_arch="wasm32"))]
+mod disk {
+  use crate::{DB,Setup,Point,Value};
+  use crate::Error;
+  use random_access_disk::RandomAccessDisk;
+  use std::path::PathBuf;
+
+  /// Storage function of `DB::open_dir()`, which opens each store as a file
+  /// in one directory.
+  pub type DiskStorage = Box<dyn Fn(&str) -> Result<RandomAccessDisk,failure::Error> + Send + Sync>;
+
+  /// Create a storage function over files in `dir`.
+  pub fn disk_storage<T> (dir: T) -> DiskStorage where T: Into<PathBuf> {
+    let dir = dir.into();
+    Box::new(move |name: &str| RandomAccessDisk::open(dir.join(name)))
+  }
+
+  impl<P,V> DB<RandomAccessDisk,DiskStorage,P,V> where P: Point, V: Value {
+    /// Open the database in the directory `dir`, configured by `setup`,
+    /// creating the directory if needed.
+    ///
+    /// Writable handles lock the file `lock` in `dir` with
+    /// `Setup::lock_file()`, so opening the database for writing while
+    /// another handle has it open fails with a `Locked` error. Pass a setup
+    /// with `read_only(true)` to attach a reader instead, or with
+    /// `lock(false)` to opt out of the lock.
+    ///
+    /// ```rust,no_run
+    /// use eyros::{DB,Error};
+    /// # fn main () -> Result<(),Error> {
+    /// let db: Result<DB<_,_,(f32,f32),u32>,Error> = DB::open_dir("/tmp/eyros-db", |s| s);
+    /// let mut db = match db {
+    ///   Err(Error::Locked(_)) => {
+    ///     // another process is writing, so only read
+    ///     DB::open_dir("/tmp/eyros-db", |s| s.read_only(true))?
+    ///   },
+    ///   db => db?
+    /// };
+    /// # Ok(()) }
+    /// ```
+    pub fn open_dir<T,F> (dir: T, setup: F) -> Result<Self,Error>
+    where T: Into<PathBuf>,
+    F: FnOnce(Setup<RandomAccessDisk,DiskStorage>) -> Setup<RandomAccessDisk,DiskStorage> {
+      let dir = dir.into();
+      std::fs::create_dir_all(&dir)?;
+      let lock = dir.join("lock");
+      setup(Setup::new(disk_storage(dir)).lock_file(lock)).build()
+    }
+  }
+}
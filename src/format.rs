@@ -0,0 +1,75 @@
+use crate::Error;
+
+/// Format settings recorded in a header at the start of each tree file.
+///
+/// Every tree keeps the format it was built with, so trees written with
+/// different settings can be read side by side. A new format applies to trees
+/// as soon as they are built or merged, without rewriting older trees.
+///
+/// Tree files from before headers were introduced have no header and are read
+/// as `TreeFormat::legacy()`.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub struct TreeFormat {
+  /// Layout version. Version `0` is the original layout without a header.
+  pub version: u8,
+  /// Codec used to encode blocks. `0` is the default desert encoding.
+  pub codec: u8,
+  /// Compression applied to blocks. `0` means uncompressed.
+  pub compression: u8
+}
+
+impl TreeFormat {
+  /// Newest layout version that this crate writes.
+  pub const VERSION: u8 = 1;
+  /// Size of the header in bytes.
+  pub const HEADER_LEN: u64 = 8;
+
+  /// Format of tree files written without a header.
+  pub fn legacy () -> Self {
+    Self { version: 0, codec: 0, compression: 0 }
+  }
+  pub fn codec (mut self, codec: u8) -> Self {
+    self.codec = codec;
+    self
+  }
+  pub fn compression (mut self, compression: u8) -> Self {
+    self.compression = compression;
+    self
+  }
+  /// Return an error if this version of eyros can't read or write trees in
+  /// this format.
+  pub fn check (&self) -> Result<(),Error> {
+    if self.version > Self::VERSION {
+      invalid!["unsupported tree format version {}", self.version]
+    }
+    if self.codec != 0 {
+      invalid!["unsupported tree codec {}", self.codec]
+    }
+    if self.compression != 0 {
+      invalid!["unsupported tree compression {}", self.compression]
+    }
+    Ok(())
+  }
+  /// Serialize the header. Headers start with a zero length field, which no
+  /// branch block can have, to tell them apart from headerless trees.
+  pub fn to_header (&self) -> [u8;8] {
+    [0,0,0,0,self.version,self.codec,self.compression,0]
+  }
+  /// Parse a header from the first bytes of a tree file, or return `None` if
+  /// the file starts with a branch block instead.
+  pub fn from_header (buf: &[u8]) -> Result<Option<Self>,Error> {
+    if buf.len() < 4 || buf[0..4] != [0,0,0,0] {
+      return Ok(None);
+    }
+    if (buf.len() as u64) < Self::HEADER_LEN {
+      corrupt!["tree header too short ({} bytes)", buf.len()]
+    }
+    Ok(Some(Self { version: buf[4], codec: buf[5], compression: buf[6] }))
+  }
+}
+
+impl Default for TreeFormat {
+  fn default () -> Self {
+    Self { version: Self::VERSION, codec: 0, compression: 0 }
+  }
+}
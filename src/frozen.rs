@@ -0,0 +1,59 @@
+use crate::Error;
+use crate::tree::Pointers;
+use std::collections::HashMap;
+
+/// In-memory image of a tree file for trees that won't change until their
+/// next merge.
+///
+/// The whole tree file is read once and the location of every branch block
+/// is resolved up front, so queries slice branches straight out of the image
+/// instead of issuing a read per branch. The image only lives as long as the
+/// handle: it isn't persisted or memory-mapped, so it is built again by
+/// `DB::freeze_tree()` after each open.
+pub struct FrozenTree {
+  buf: Vec<u8>,
+  branches: HashMap<u64,(usize,usize)>,
+  data: Vec<u64>
+}
+
+impl FrozenTree {
+  /// Build an image from the bytes of a tree file with its root branch at
+  /// `root`. `children` returns the branch cursors and data block offsets
+  /// referenced by a branch block.
+  pub fn new<F> (buf: Vec<u8>, root: u64, children: F) -> Result<Self,Error>
+  where F: Fn(&[u8],usize) -> Result<Pointers,Error> {
+    let mut branches = HashMap::new();
+    let mut data = vec![];
+    let mut cursors: Vec<(u64,usize)> = vec![(root,0)];
+    while let Some((cursor,depth)) = cursors.pop() {
+      let start = cursor as usize;
+      if start + 4 > buf.len() {
+        corrupt!["branch at offset {} past the end of the tree", cursor]
+      }
+      let len = u32::from_be_bytes([
+        buf[start], buf[start+1], buf[start+2], buf[start+3]
+      ]) as usize;
+      if len < 4 || start + len > buf.len() {
+        corrupt!["invalid branch length {} at offset {}", len, cursor]
+      }
+      let range = (start+4,start+len);
+      let (next,blocks) = children(&buf[range.0..range.1], depth)?;
+      branches.insert(cursor, range);
+      data.extend(blocks);
+      cursors.extend(next);
+    }
+    Ok(Self { buf, branches, data })
+  }
+  /// Branch block at `offset`, without its length field.
+  pub fn branch (&self, offset: u64) -> Option<&[u8]> {
+    self.branches.get(&offset).map(|(start,end)| &self.buf[*start..*end])
+  }
+  /// Offsets of the data blocks that the tree references.
+  pub fn data_offsets (&self) -> &[u64] {
+    &self.data
+  }
+  /// Size of the image in bytes.
+  pub fn bytes (&self) -> usize {
+    self.buf.len()
+  }
+}
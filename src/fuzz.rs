@@ -0,0 +1,192 @@
+use crate::{DB,Point,Value,Location,QueryIterator,ShardInfo,Rng};
+use crate::Error;
+use random_access_storage::RandomAccess;
+
+/// Precision reduction for coordinates handed out by `db.query_fuzzed()` and
+/// `db.export_sharded_fuzzed()`, so that datasets with sensitive exact
+/// positions can be shared.
+///
+/// Distances are in the units that points are stored in.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Fuzz {
+  /// Move each point by a random offset of up to `radius` along each axis.
+  ///
+  /// The offset is derived from `seed` and the exact coordinates, so a point
+  /// moves the same way in every query and export and can't be recovered by
+  /// averaging repeated results. Keep the seed secret. Intervals are moved
+  /// without changing their width.
+  Jitter { radius: f64, seed: u64 },
+  /// Snap each point to the center of its cell in a grid of `cell` sized
+  /// cells aligned to the origin. Intervals grow to cover the cells they
+  /// touch.
+  Snap { cell: f64 }
+}
+
+impl Fuzz {
+  fn check (&self) -> Result<(),Error> {
+    match self {
+      Fuzz::Jitter { radius, .. } => {
+        if *radius < 0.0 || !radius.is_finite() {
+          invalid!["jitter radius must be finite and not negative"]
+        }
+      },
+      Fuzz::Snap { cell } => {
+        if *cell <= 0.0 || !cell.is_finite() {
+          invalid!["snap cell size must be finite and positive"]
+        }
+      }
+    }
+    Ok(())
+  }
+  // `n` offsets in -radius..radius keyed by the exact `coords`
+  fn offsets (radius: f64, seed: u64, coords: &[f64], n: usize) -> Vec<f64> {
+    let key = coords.iter().fold(seed, |key,x| {
+      Rng::new(key ^ x.to_bits()).next_u64()
+    });
+    let mut rng = Rng::new(key);
+    (0..n).map(|_| {
+      let u = (rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+      radius * (2.0*u - 1.0)
+    }).collect()
+  }
+  /// Apply to the coordinates of a point.
+  pub fn point (&self, coords: &mut [f64]) {
+    match *self {
+      Fuzz::Jitter { radius, seed } => {
+        let offsets = Self::offsets(radius, seed, coords, coords.len());
+        for (x,d) in coords.iter_mut().zip(offsets) { *x += d }
+      },
+      Fuzz::Snap { cell } => {
+        for x in coords.iter_mut() { *x = (*x/cell).floor()*cell + cell/2.0 }
+      }
+    }
+  }
+  /// Apply to the `(min,max)` bounds of an interval along each axis.
+  pub fn interval (&self, bounds: &mut [(f64,f64)]) {
+    match *self {
+      Fuzz::Jitter { radius, seed } => {
+        let flat: Vec<f64> = bounds.iter().flat_map(|(a,b)| vec![*a,*b]).collect();
+        let offsets = Self::offsets(radius, seed, &flat, bounds.len());
+        for ((a,b),d) in bounds.iter_mut().zip(offsets) {
+          *a += d;
+          *b += d;
+        }
+      },
+      Fuzz::Snap { cell } => {
+        for (a,b) in bounds.iter_mut() {
+          *a = (*a/cell).floor()*cell;
+          *b = ((*b/cell).floor()+1.0)*cell;
+        }
+      }
+    }
+  }
+}
+
+/// Points whose coordinates can be reduced in precision with a `Fuzz`.
+pub trait Fuzzable: Point {
+  fn fuzz (&self, fuzz: &Fuzz) -> Self;
+}
+
+macro_rules! impl_fuzzable {
+  ($($T:ty),+) => {$(
+    impl Fuzzable for ($T,$T) {
+      fn fuzz (&self, fuzz: &Fuzz) -> Self {
+        let mut c = [self.0 as f64, self.1 as f64];
+        fuzz.point(&mut c);
+        (c[0] as $T, c[1] as $T)
+      }
+    }
+    impl Fuzzable for ($T,$T,$T) {
+      fn fuzz (&self, fuzz: &Fuzz) -> Self {
+        let mut c = [self.0 as f64, self.1 as f64, self.2 as f64];
+        fuzz.point(&mut c);
+        (c[0] as $T, c[1] as $T, c[2] as $T)
+      }
+    }
+    impl Fuzzable for (($T,$T),($T,$T)) {
+      fn fuzz (&self, fuzz: &Fuzz) -> Self {
+        let ((x0,x1),(y0,y1)) = *self;
+        let mut c = [(x0 as f64, x1 as f64), (y0 as f64, y1 as f64)];
+        fuzz.interval(&mut c);
+        ((c[0].0 as $T, c[0].1 as $T), (c[1].0 as $T, c[1].1 as $T))
+      }
+    }
+    impl Fuzzable for (($T,$T),($T,$T),($T,$T)) {
+      fn fuzz (&self, fuzz: &Fuzz) -> Self {
+        let ((x0,x1),(y0,y1),(z0,z1)) = *self;
+        let mut c = [(x0 as f64, x1 as f64), (y0 as f64, y1 as f64),
+          (z0 as f64, z1 as f64)];
+        fuzz.interval(&mut c);
+        (
+          (c[0].0 as $T, c[0].1 as $T),
+          (c[1].0 as $T, c[1].1 as $T),
+          (c[2].0 as $T, c[2].1 as $T)
+        )
+      }
+    }
+  )+}
+}
+impl_fuzzable![f32,f64];
+
+/// Iterator of `Result<(Point,Value,Location)>` data with fuzzed points,
+/// returned by `db.query_fuzzed()`.
+pub struct FuzzedQuery<'b,S,P,V> where
+S: RandomAccess<Error=failure::Error>, P: Fuzzable, V: Value {
+  iter: QueryIterator<'b,S,P,V>,
+  fuzz: Fuzz
+}
+
+impl<'b,S,P,V> Iterator for FuzzedQuery<'b,S,P,V> where
+S: RandomAccess<Error=failure::Error>, P: Fuzzable, V: Value {
+  type Item = Result<(P,V,Location),Error>;
+  fn next (&mut self) -> Option<Self::Item> {
+    self.iter.next().map(|r| r.map(|(p,v,loc)| (p.fuzz(&self.fuzz),v,loc)))
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Fuzzable, V: Value {
+  /// Query like `query()`, but with each point passed through `fuzz` before
+  /// it is returned.
+  ///
+  /// Rows are selected by their exact points, so fuzzed points near the
+  /// edges of `bbox` may lie outside of it.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Fuzz,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut db: DB<_,_,(f64,f64),u32> = DB::open(storage)?;
+  /// // about 1km in degrees of latitude
+  /// let fuzz = Fuzz::Jitter { radius: 0.01, seed: 0x5eed };
+  /// for result in db.query_fuzzed(&((13.0,52.0),(14.0,53.0)), fuzz)? {
+  ///   let (point,value,location) = result?;
+  ///   // ...
+  /// }
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn query_fuzzed<'b> (&mut self, bbox: &'b P::Bounds, fuzz: Fuzz)
+  -> Result<FuzzedQuery<'b,S,P,V>,Error> {
+    fuzz.check()?;
+    Ok(FuzzedQuery { iter: self.query(bbox)?, fuzz })
+  }
+
+  /// Export shards like `export_sharded()`, with each point passed through
+  /// `fuzz` before it is written. Rows are assigned to cells by their exact
+  /// points.
+  pub fn export_sharded_fuzzed<T,F> (&mut self, grid: &[(String,P::Bounds)],
+  fuzz: Fuzz, open_shard: F) -> Result<Vec<ShardInfo>,Error> where
+  T: RandomAccess<Error=failure::Error>,
+  F: Fn(&str,&str) -> Result<T,failure::Error> {
+    fuzz.check()?;
+    self.export_shards(grid, open_shard, |p| p.fuzz(&fuzz))
+  }
+}
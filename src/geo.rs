@@ -0,0 +1,43 @@
+use crate::{DB,Value,LonLat,CorridorIterator};
+use crate::Error;
+use random_access_storage::RandomAccess;
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: LonLat, V: Value {
+  /// Query for points within `meters` great-circle distance of the
+  /// `(longitude,latitude)` `center`, in degrees.
+  ///
+  /// The radius is converted to latitude and longitude padding around the
+  /// center to prune the trees. The box is split in two where it crosses the
+  /// antimeridian and spans every longitude where it reaches a pole. Each
+  /// candidate is then checked with `haversine()`.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut db: DB<_,_,(f64,f64),u32> = DB::open(storage)?;
+  /// // within 2km of the Brandenburg Gate
+  /// for result in db.within_radius((13.3777,52.5163), 2_000.0)? {
+  ///   let (point,value,location) = result?;
+  ///   // ...
+  /// }
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn within_radius<'b> (&mut self, center: (f64,f64), meters: f64)
+  -> Result<CorridorIterator<'b,S,P,V>,Error> {
+    if !(-90.0..=90.0).contains(&center.1) {
+      invalid!["latitude {} is out of range", center.1]
+    }
+    // a corridor around a single vertex is a circle
+    self.query_corridor(&[center], meters)
+  }
+}
@@ -0,0 +1,212 @@
+//! Read GeoJSON features into rows and write query results as GeoJSON.
+//!
+//! Each feature becomes one row. Point geometries are stored as points and
+//! the other geometries as the interval of their bounding box, as far as the
+//! point type of the database supports them (see `GeoPoint`). Values are
+//! converted with a function of your choice, for example to keep the
+//! properties of each feature as JSON bytes:
+//!
+//! ```rust,no_run
+//! # use eyros::{DB,Mix2,geojson,Error};
+//! # use std::path::PathBuf;
+//! # use random_access_disk::RandomAccessDisk;
+//! # fn main () -> Result<(),Error> {
+//! # let mut db: DB<_,_,Mix2<f32,f32>,Vec<u8>> = DB::open(storage)?;
+//! let file = std::fs::File::open("parks.geojson")?;
+//! let rows = geojson::read(file, |feature| {
+//!   Ok(serde_json::to_vec(&feature["properties"])?)
+//! })?;
+//! db.batch(&rows)?;
+//!
+//! let bbox = ((13.0,52.0),(14.0,53.0));
+//! let mut out = std::fs::File::create("berlin.geojson")?;
+//! geojson::write(&mut out, db.query(&bbox)?, |value| {
+//!   Ok(serde_json::from_slice(value)?)
+//! })?;
+//! # Ok(()) }
+//! # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+//! #   let mut p = PathBuf::from("/tmp/eyros-db/");
+//! #   p.push(name);
+//! #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+//! # }
+//! ```
+
+use crate::{Point,Value,Row,Location,Mix,Mix2};
+use crate::Error;
+use serde_json::{Value as Json,json};
+use std::io::{Read,Write};
+
+/// Two-dimensional `(longitude,latitude)` point types that GeoJSON
+/// geometries can be stored as.
+pub trait GeoPoint: Point {
+  /// Convert the bounding box `(min,max)` of a geometry, where `min == max`
+  /// for a single position. Return `None` if this type can't hold it.
+  fn from_bbox (min: (f64,f64), max: (f64,f64)) -> Option<Self>;
+  /// Return a GeoJSON geometry: a `Point` for a position, a `LineString` for
+  /// an interval along one axis, and a `Polygon` for a box.
+  fn geometry (&self) -> Json;
+}
+
+fn geometry (min: (f64,f64), max: (f64,f64)) -> Json {
+  if min == max {
+    json!({ "type": "Point", "coordinates": [min.0,min.1] })
+  } else if min.0 == max.0 || min.1 == max.1 {
+    json!({ "type": "LineString", "coordinates": [[min.0,min.1],[max.0,max.1]] })
+  } else {
+    json!({ "type": "Polygon", "coordinates": [[
+      [min.0,min.1],[max.0,min.1],[max.0,max.1],[min.0,max.1],[min.0,min.1]
+    ]] })
+  }
+}
+
+macro_rules! impl_geo_point {
+  ($($T:ty),+) => {$(
+    impl GeoPoint for ($T,$T) {
+      fn from_bbox (min: (f64,f64), max: (f64,f64)) -> Option<Self> {
+        if min == max { Some((min.0 as $T, min.1 as $T)) } else { None }
+      }
+      fn geometry (&self) -> Json {
+        let p = (self.0 as f64, self.1 as f64);
+        geometry(p, p)
+      }
+    }
+    impl GeoPoint for (($T,$T),($T,$T)) {
+      fn from_bbox (min: (f64,f64), max: (f64,f64)) -> Option<Self> {
+        Some(((min.0 as $T, max.0 as $T),(min.1 as $T, max.1 as $T)))
+      }
+      fn geometry (&self) -> Json {
+        let ((x0,x1),(y0,y1)) = *self;
+        geometry((x0 as f64, y0 as f64), (x1 as f64, y1 as f64))
+      }
+    }
+    impl GeoPoint for Mix2<$T,$T> {
+      fn from_bbox (min: (f64,f64), max: (f64,f64)) -> Option<Self> {
+        let axis = |a: f64, b: f64| {
+          if a == b { Mix::Scalar(a as $T) } else { Mix::Interval(a as $T, b as $T) }
+        };
+        Some(Mix2::new(axis(min.0,max.0), axis(min.1,max.1)))
+      }
+      fn geometry (&self) -> Json {
+        let axis = |m: &Mix<$T>| match m {
+          Mix::Scalar(x) => (*x as f64, *x as f64),
+          Mix::Interval(a,b) => (*a as f64, *b as f64)
+        };
+        let ((x0,x1),(y0,y1)) = (axis(&self.v0), axis(&self.v1));
+        geometry((x0,y0), (x1,y1))
+      }
+    }
+  )+}
+}
+impl_geo_point![f32,f64];
+
+// extend `bbox` with every position in nested `coordinates`
+fn extend_bbox (coords: &Json, bbox: &mut Option<((f64,f64),(f64,f64))>)
+-> Result<(),Error> {
+  let items = coords.as_array()
+    .ok_or_else(|| Error::Invalid("coordinates must be arrays".into()))?;
+  if items.first().map(|x| x.is_number()).unwrap_or(false) {
+    let (x,y) = match (items.get(0).and_then(Json::as_f64),
+    items.get(1).and_then(Json::as_f64)) {
+      (Some(x),Some(y)) => (x,y),
+      _ => invalid!["position must have a longitude and latitude"]
+    };
+    *bbox = Some(match bbox {
+      Some((min,max)) => ((min.0.min(x),min.1.min(y)),(max.0.max(x),max.1.max(y))),
+      None => ((x,y),(x,y))
+    });
+    return Ok(());
+  }
+  for item in items.iter() {
+    extend_bbox(item, bbox)?;
+  }
+  Ok(())
+}
+
+fn geometry_bbox (geometry: &Json, bbox: &mut Option<((f64,f64),(f64,f64))>)
+-> Result<(),Error> {
+  match geometry["type"].as_str() {
+    Some("GeometryCollection") => {
+      let geometries = geometry["geometries"].as_array()
+        .ok_or_else(|| Error::Invalid("GeometryCollection without geometries".into()))?;
+      for g in geometries.iter() {
+        geometry_bbox(g, bbox)?;
+      }
+      Ok(())
+    },
+    Some("Point") | Some("MultiPoint") | Some("LineString")
+    | Some("MultiLineString") | Some("Polygon") | Some("MultiPolygon") => {
+      extend_bbox(&geometry["coordinates"], bbox)
+    },
+    Some(t) => invalid!["unsupported geometry type {}", t],
+    None => invalid!["geometry without a type"]
+  }
+}
+
+/// Convert a GeoJSON `Feature` into a row, with `value` computing the value
+/// from the feature.
+pub fn feature_row<P,V,F> (feature: &Json, mut value: F) -> Result<Row<P,V>,Error>
+where P: GeoPoint, V: Value, F: FnMut(&Json) -> Result<V,Error> {
+  if feature["type"] != "Feature" {
+    invalid!["expected a Feature, found {}", feature["type"]];
+  }
+  let mut bbox = None;
+  geometry_bbox(&feature["geometry"], &mut bbox)?;
+  let (min,max) = bbox.ok_or_else(|| Error::Invalid("feature has no positions".into()))?;
+  let point = P::from_bbox(min, max).ok_or_else(|| {
+    Error::Invalid(format!["{} geometry doesn't fit in this point type",
+      feature["geometry"]["type"]])
+  })?;
+  Ok(Row::Insert(point, value(feature)?))
+}
+
+// I/O errors stay I/O errors, the rest is malformed input
+/// Read rows from a stream of GeoJSON: a `FeatureCollection`, a single
+/// `Feature`, or a sequence of either, such as newline-delimited features.
+pub fn read<R,P,V,F> (reader: R, mut value: F) -> Result<Vec<Row<P,V>>,Error>
+where R: Read, P: GeoPoint, V: Value, F: FnMut(&Json) -> Result<V,Error> {
+  let mut rows = vec![];
+  for doc in serde_json::Deserializer::from_reader(reader).into_iter::<Json>() {
+    let doc = doc?;
+    if doc["type"] == "FeatureCollection" {
+      let features = doc["features"].as_array()
+        .ok_or_else(|| Error::Invalid("FeatureCollection without features".into()))?;
+      for feature in features.iter() {
+        rows.push(feature_row(feature, &mut value)?);
+      }
+    } else {
+      rows.push(feature_row(&doc, &mut value)?);
+    }
+  }
+  Ok(rows)
+}
+
+/// Convert a point and value into a GeoJSON `Feature`, with `properties`
+/// computing the properties from the value.
+pub fn row_feature<P,V,F> (point: &P, value: &V, mut properties: F)
+-> Result<Json,Error> where P: GeoPoint, V: Value,
+F: FnMut(&V) -> Result<Json,Error> {
+  Ok(json!({
+    "type": "Feature",
+    "geometry": point.geometry(),
+    "properties": properties(value)?
+  }))
+}
+
+/// Write query results to `writer` as a `FeatureCollection` and return the
+/// number of features written. Features are written as results arrive, so
+/// large results are not held in memory.
+pub fn write<W,P,V,I,F> (mut writer: W, results: I, mut properties: F)
+-> Result<usize,Error> where W: Write, P: GeoPoint, V: Value,
+I: IntoIterator<Item=Result<(P,V,Location),Error>>,
+F: FnMut(&V) -> Result<Json,Error> {
+  writer.write_all(br#"{"type":"FeatureCollection","features":["#)?;
+  let mut n = 0;
+  for result in results {
+    let (point,value,_) = result?;
+    if n > 0 { writer.write_all(b",")? }
+    serde_json::to_writer(&mut writer, &row_feature(&point, &value, &mut properties)?)?;
+    n += 1;
+  }
+  writer.write_all(b"]}")?;
+  Ok(n)
+}
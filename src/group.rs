@@ -0,0 +1,116 @@
+use crate::{DB,Point,Value,Row};
+use crate::Error;
+use random_access_storage::RandomAccess;
+use std::sync::{Arc,Mutex,MutexGuard};
+
+type Slot = Arc<Mutex<Option<Result<(),Error>>>>;
+// batches waiting to be committed, each with the slot for its result
+type Queue<P,V> = Vec<(Vec<Row<P,V>>,Slot)>;
+
+/// Database handle shared by many writer threads that commits their batches
+/// in groups.
+///
+/// Each `batch()` queues its rows and waits for the database. Whichever
+/// writer gets the database next writes every queued batch with a single
+/// `db.batch()`, so concurrent writers share one commit instead of taking
+/// turns, and each call returns once its own rows are committed:
+///
+/// ```rust,no_run
+/// use eyros::{DB,Row,GroupCommit,Error};
+/// # use std::path::PathBuf;
+/// # use random_access_disk::RandomAccessDisk;
+/// use std::{sync::Arc,thread};
+/// # fn main () -> Result<(),Error> {
+/// let db: DB<_,_,(f32,f32),u32> = DB::open(storage)?;
+/// let group = Arc::new(GroupCommit::new(db));
+/// let writers: Vec<_> = (0..8).map(|i| {
+///   let group = Arc::clone(&group);
+///   thread::spawn(move || group.batch(&[Row::Insert((0.5,0.5),i)]))
+/// }).collect();
+/// for w in writers {
+///   w.join().unwrap()?;
+/// }
+/// let count = group.lock()?.query(&((0.0,0.0),(1.0,1.0)))?.count();
+/// # Ok(()) }
+/// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+/// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+/// #   p.push(name);
+/// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+/// # }
+/// ```
+///
+/// The rows of each writer are checked before they join a group, so a writer
+/// with rows that can't be written, such as a point with NaN coordinates,
+/// fails on its own. Beyond that a group commits or fails as a whole, and
+/// every writer in it gets the same error. Rows in a group get one sequence
+/// number and one changes feed entry, and triggers see them as one batch.
+pub struct GroupCommit<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  db: Mutex<DB<S,U,P,V>>,
+  queue: Mutex<Queue<P,V>>
+}
+
+impl<S,U,P,V> GroupCommit<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  pub fn new (db: DB<S,U,P,V>) -> Self {
+    Self { db: Mutex::new(db), queue: Mutex::new(vec![]) }
+  }
+  /// Write `rows` together with the batches of other writers, returning once
+  /// they are committed.
+  pub fn batch (&self, rows: &[Row<P,V>]) -> Result<(),Error> {
+    let slot: Slot = Arc::new(Mutex::new(None));
+    self.queue_lock()?.push((rows.to_vec(), Arc::clone(&slot)));
+    let mut db = self.lock()?;
+    // an earlier writer may have committed these rows while this one waited
+    if let Some(result) = slot_lock(&slot)?.take() {
+      return result;
+    }
+    let group: Queue<P,V> = self.queue_lock()?.drain(..).collect();
+    let mut all = Vec::with_capacity(group.iter().map(|(rows,_)| rows.len()).sum());
+    let mut checked = Vec::with_capacity(group.len());
+    for (rows,_) in group.iter() {
+      let r = db.check_rows(rows);
+      if r.is_ok() {
+        all.extend_from_slice(rows);
+      }
+      checked.push(r);
+    }
+    let result = if checked.iter().any(|r| r.is_ok()) { db.batch(&all) } else { Ok(()) };
+    let mut own = Ok(());
+    for ((_,s),r) in group.iter().zip(checked) {
+      let r = r.and_then(|_| result.clone());
+      if Arc::ptr_eq(s, &slot) {
+        own = r;
+      } else {
+        *slot_lock(s)? = Some(r);
+      }
+    }
+    own
+  }
+  /// Number of batches waiting for the next commit.
+  pub fn pending (&self) -> Result<usize,Error> {
+    Ok(self.queue_lock()?.len())
+  }
+  /// Lock the database to query or configure it. Writers wait until the
+  /// guard is dropped, and their batches pile up into the next group.
+  pub fn lock (&self) -> Result<MutexGuard<'_,DB<S,U,P,V>>,Error> {
+    self.db.lock().map_err(|_| Error::Other("lock poisoned by a panicked thread".into()))
+  }
+  pub fn into_inner (self) -> Result<DB<S,U,P,V>,Error> {
+    self.db.into_inner()
+      .map_err(|_| Error::Other("lock poisoned by a panicked thread".into()))
+  }
+  fn queue_lock (&self)
+  -> Result<MutexGuard<'_,Queue<P,V>>,Error> {
+    self.queue.lock().map_err(|_| Error::Other("lock poisoned by a panicked thread".into()))
+  }
+}
+
+fn slot_lock (slot: &Slot)
+-> Result<MutexGuard<'_,Option<Result<(),Error>>>,Error> {
+  slot.lock().map_err(|_| Error::Other("lock poisoned by a panicked thread".into()))
+}
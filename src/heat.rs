@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Read counters for data blocks that decay exponentially over time, so
+/// recently and frequently read blocks score highest.
+///
+/// Each read adds `1` to a block's score, and scores halve every `half_life`.
+/// Tiering and cache pinning can use the scores to decide which blocks to
+/// keep on fast storage.
+///
+/// ```rust
+/// use eyros::BlockHeat;
+/// use std::time::Duration;
+///
+/// let mut heat = BlockHeat::new(Duration::from_secs(60));
+/// heat.record(0, Duration::from_secs(0));
+/// heat.record(0, Duration::from_secs(0));
+/// heat.record(512, Duration::from_secs(0));
+/// assert_eq![heat.score(0, Duration::from_secs(60)), 1.0];
+/// assert_eq![heat.snapshot(Duration::from_secs(0)), vec![(0,2.0),(512,1.0)]];
+/// ```
+#[derive(Clone,Debug)]
+pub struct BlockHeat {
+  half_life: Duration,
+  // offset => (score, milliseconds of the last update)
+  blocks: HashMap<u64,(f32,u64)>
+}
+
+impl BlockHeat {
+  pub fn new (half_life: Duration) -> Self {
+    Self { half_life, blocks: HashMap::new() }
+  }
+  /// Count a read of the block at `offset` at time `now`.
+  pub fn record (&mut self, offset: u64, now: Duration) {
+    let now_ms = now.as_millis() as u64;
+    let half_life = self.half_life;
+    let entry = self.blocks.entry(offset).or_insert((0.0,now_ms));
+    entry.0 = decay(entry.0, entry.1, now_ms, half_life) + 1.0;
+    entry.1 = now_ms;
+  }
+  /// Score of the block at `offset` at time `now`.
+  pub fn score (&self, offset: u64, now: Duration) -> f64 {
+    match self.blocks.get(&offset) {
+      Some((score,at)) => {
+        decay(*score, *at, now.as_millis() as u64, self.half_life) as f64
+      },
+      None => 0.0
+    }
+  }
+  /// Forget the block at `offset`.
+  pub fn remove (&mut self, offset: u64) {
+    self.blocks.remove(&offset);
+  }
+  /// Move scores to the new offsets of blocks that were compacted, dropping
+  /// the scores of blocks that are gone.
+  pub fn remap (&mut self, moves: &HashMap<u64,Option<u64>>) {
+    self.blocks = self.blocks.drain()
+      .filter_map(|(offset,score)| {
+        moves.get(&offset).cloned().flatten().map(|o| (o,score))
+      })
+      .collect();
+  }
+  /// Number of blocks with a score.
+  pub fn len (&self) -> usize {
+    self.blocks.len()
+  }
+  pub fn is_empty (&self) -> bool {
+    self.blocks.is_empty()
+  }
+  /// Scores of every block at time `now`, hottest first (ties are ordered by
+  /// offset).
+  pub fn snapshot (&self, now: Duration) -> Vec<(u64,f64)> {
+    let mut scores: Vec<(u64,f64)> = self.blocks.keys()
+      .map(|offset| (*offset,self.score(*offset, now)))
+      .collect();
+    scores.sort_by(|a,b| {
+      b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(&b.0))
+    });
+    scores
+  }
+}
+
+fn decay (score: f32, at: u64, now: u64, half_life: Duration) -> f32 {
+  let half_life = half_life.as_millis() as f64;
+  if now <= at || half_life == 0.0 { return score }
+  (score as f64 * 0.5f64.powf((now-at) as f64 / half_life)) as f32
+}
@@ -0,0 +1,201 @@
+use failure::{Error,bail};
+use random_access_storage::RandomAccess;
+use std::collections::BTreeMap;
+use std::io;
+
+/// Append-only log of blocks, such as a hypercore, that backs a
+/// `HypercoreStore`.
+///
+/// Implement this for the handle of your hypercore binding, blocking on its
+/// futures where it is asynchronous. `get()` of an entry that isn't stored
+/// locally should download it from peers, so that a replica only fetches the
+/// entries that its queries read.
+pub trait Feed {
+  /// Number of entries in the log.
+  fn len (&self) -> Result<u64,Error>;
+  /// Read the entry at `index`.
+  fn get (&mut self, index: u64) -> Result<Vec<u8>,Error>;
+  /// Append an entry to the log.
+  fn append (&mut self, data: &[u8]) -> Result<(),Error>;
+  /// Flush appended entries to durable storage.
+  fn flush (&mut self) -> Result<(),Error> {
+    Ok(())
+  }
+}
+
+const WRITE: u8 = 0;
+const TRUNCATE: u8 = 1;
+const RECORD_LEN: usize = 25;
+
+// bytes [start,end) of a store are stored in content entry `seq`, from byte
+// `skip` of the entry on
+#[derive(Clone,Copy,Debug)]
+struct Extent {
+  end: u64,
+  seq: u64,
+  skip: u64
+}
+
+/// Store that keeps a random access file in two append-only feeds, so that
+/// eyros databases can be replicated over the hypercore protocol.
+///
+/// Every write appends its bytes to the `content` feed and a record of where
+/// they go to the `index` feed. The index is read in full when the store is
+/// opened, and it is small: 25 bytes per write. Reads only get the content
+/// entries that hold the requested bytes, so a replica that downloads the
+/// index feeds sparsely fetches the data blocks and tree branches its queries
+/// touch. Feeds never shrink: overwritten bytes and truncated stores keep
+/// their entries.
+pub struct HypercoreStore<F> where F: Feed {
+  index: F,
+  content: F,
+  extents: BTreeMap<u64,Extent>,
+  len: u64
+}
+
+impl<F> HypercoreStore<F> where F: Feed {
+  /// Open a store over its `index` and `content` feeds, replaying the index.
+  pub fn open (index: F, content: F) -> Result<Self,Error> {
+    let mut store = Self { index, content, extents: BTreeMap::new(), len: 0 };
+    for i in 0..store.index.len()? {
+      let buf = store.index.get(i)?;
+      if buf.len() != RECORD_LEN {
+        bail!["index record {} has {} bytes instead of {}", i, buf.len(), RECORD_LEN];
+      }
+      let u = |j: usize| {
+        let mut b = [0u8;8];
+        b.copy_from_slice(&buf[j..j+8]);
+        u64::from_be_bytes(b)
+      };
+      match buf[0] {
+        WRITE => store.insert(u(1), u(1)+u(9), u(17)),
+        TRUNCATE => store.cut(u(1)),
+        kind => bail!["unknown index record type {} at {}", kind, i]
+      }
+    }
+    Ok(store)
+  }
+  /// Number of entries in the index and content feeds.
+  pub fn feed_lens (&self) -> Result<(u64,u64),Error> {
+    Ok((self.index.len()?, self.content.len()?))
+  }
+  fn record (&mut self, kind: u8, a: u64, b: u64, seq: u64) -> Result<(),Error> {
+    let mut buf = Vec::with_capacity(RECORD_LEN);
+    buf.push(kind);
+    buf.extend(&a.to_be_bytes());
+    buf.extend(&b.to_be_bytes());
+    buf.extend(&seq.to_be_bytes());
+    self.index.append(&buf)
+  }
+  // map [start,end) to content entry `seq`, splitting the extents it overlaps
+  fn insert (&mut self, start: u64, end: u64, seq: u64) {
+    if start == end { return }
+    let overlaps: Vec<(u64,Extent)> = self.extents.range(..end).rev()
+      .take_while(|(_,e)| e.end > start)
+      .map(|(k,e)| (*k,*e))
+      .collect();
+    for (k,e) in overlaps {
+      self.extents.remove(&k);
+      if k < start {
+        self.extents.insert(k, Extent { end: start, ..e });
+      }
+      if e.end > end {
+        self.extents.insert(end, Extent { end: e.end, seq: e.seq, skip: e.skip + (end-k) });
+      }
+    }
+    self.extents.insert(start, Extent { end, seq, skip: 0 });
+    self.len = self.len.max(end);
+  }
+  fn cut (&mut self, length: u64) {
+    let tail: Vec<u64> = self.extents.range(length..).map(|(k,_)| *k).collect();
+    for k in tail {
+      self.extents.remove(&k);
+    }
+    if let Some((_,e)) = self.extents.range_mut(..length).next_back() {
+      e.end = e.end.min(length);
+    }
+    self.len = length;
+  }
+}
+
+impl<F> RandomAccess for HypercoreStore<F> where F: Feed {
+  type Error = Error;
+  fn write (&mut self, offset: u64, data: &[u8]) -> Result<(),Error> {
+    if data.is_empty() { return Ok(()) }
+    let seq = self.content.len()?;
+    self.content.append(data)?;
+    self.record(WRITE, offset, data.len() as u64, seq)?;
+    self.insert(offset, offset + data.len() as u64, seq);
+    Ok(())
+  }
+  fn read (&mut self, offset: u64, length: u64) -> Result<Vec<u8>,Error> {
+    let end = offset + length;
+    if end > self.len {
+      bail!["read of {} bytes at {} is out of bounds ({} bytes)", length, offset, self.len];
+    }
+    // bytes that were never written, such as gaps left by writes past the
+    // end, read as zeros
+    let mut out = vec![0u8;length as usize];
+    let first = self.extents.range(..=offset).next_back().map(|(k,_)| *k).unwrap_or(0);
+    let extents: Vec<(u64,Extent)> = self.extents.range(first..end)
+      .filter(|(_,e)| e.end > offset)
+      .map(|(k,e)| (*k,*e))
+      .collect();
+    for (k,e) in extents {
+      let (from,to) = (k.max(offset), e.end.min(end));
+      let entry = self.content.get(e.seq)?;
+      let skip = (e.skip + (from-k)) as usize;
+      if skip + (to-from) as usize > entry.len() {
+        bail!["content entry {} is shorter than its index record", e.seq];
+      }
+      out[(from-offset) as usize..(to-offset) as usize]
+        .copy_from_slice(&entry[skip..skip+(to-from) as usize]);
+    }
+    Ok(out)
+  }
+  fn read_to_writer (&mut self, offset: u64, length: u64,
+  buf: &mut impl io::Write) -> Result<(),Error> {
+    Ok(buf.write_all(&self.read(offset, length)?)?)
+  }
+  fn del (&mut self, offset: u64, length: u64) -> Result<(),Error> {
+    if offset >= self.len { return Ok(()) }
+    let n = length.min(self.len - offset);
+    self.write(offset, &vec![0u8;n as usize])
+  }
+  fn truncate (&mut self, length: u64) -> Result<(),Error> {
+    if length > self.len {
+      let len = self.len;
+      return self.write(len, &vec![0u8;(length - len) as usize]);
+    }
+    self.record(TRUNCATE, length, 0, 0)?;
+    self.cut(length);
+    Ok(())
+  }
+  fn len (&self) -> Result<u64,Error> {
+    Ok(self.len)
+  }
+  fn is_empty (&mut self) -> Result<bool,Error> {
+    Ok(self.len == 0)
+  }
+  fn sync_all (&mut self) -> Result<(),Error> {
+    self.content.flush()?;
+    self.index.flush()
+  }
+}
+
+/// Create a storage function that keeps each store of a database in the
+/// feeds that `open_feed` returns for `"{name}.index"` and `"{name}"`.
+///
+/// ```rust,ignore
+/// use eyros::{DB,hypercore_storage};
+///
+/// // open_core(name) returns Result<impl Feed,failure::Error>
+/// let mut db: DB<_,_,((f32,f32),(f32,f32)),u32> = DB::open(hypercore_storage(open_core))?;
+/// ```
+pub fn hypercore_storage<F,O> (open_feed: O)
+-> impl Fn(&str) -> Result<HypercoreStore<F>,Error>
+where F: Feed, O: Fn(&str) -> Result<F,Error> {
+  move |name: &str| {
+    HypercoreStore::open(open_feed(&format!["{}.index", name])?, open_feed(name)?)
+  }
+}
@@ -0,0 +1,31 @@
+use crate::Point;
+
+/// Which rows a query returns, relative to the query's bounding box. Set with
+/// `QueryIterator::intersect()`.
+///
+/// For interval points such as time ranges, `Within` answers "which ranges
+/// fall inside of this period" and `Contains` answers "which ranges cover
+/// this period", or this instant for a bounding box with `min == max`. For
+/// scalar coordinates `Within` is the same as `Overlaps`, and `Contains`
+/// only matches a coordinate equal to both ends of the box.
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Default)]
+pub enum Intersect {
+  /// Rows that intersect with the bounding box (the default).
+  #[default]
+  Overlaps,
+  /// Rows that lie entirely inside of the bounding box.
+  Within,
+  /// Rows that cover all of the bounding box.
+  Contains
+}
+
+impl Intersect {
+  /// Whether `point` matches a query for `bbox`.
+  pub fn matches<P> (&self, point: &P, bbox: &P::Bounds) -> bool where P: Point {
+    match self {
+      Intersect::Overlaps => point.overlaps(bbox),
+      Intersect::Within => point.within(bbox),
+      Intersect::Contains => point.contains(bbox)
+    }
+  }
+}
@@ -0,0 +1,324 @@
+use crate::Error;
+
+/// Pluggable leader election for deployments where several nodes share one
+/// database, so that only the leader ingests while the other nodes serve reads.
+///
+/// Leadership is a lease: call `acquire()` to try to become the leader and
+/// `renew()` periodically (well within the lease duration) to stay the leader.
+/// A node that fails to renew must stop writing.
+///
+/// With the `file-lease` feature, `FileLease` implements this trait on top of
+/// a lock file, and with the `etcd-lease` feature, `EtcdLease` implements it
+/// on top of an etcd lease. Other backends can implement the trait directly.
+pub trait Leadership {
+  /// Try to become the leader. Returns whether this node is now the leader.
+  fn acquire (&mut self) -> Result<bool,Error>;
+  /// Extend the lease. Returns `false` when another node has taken over.
+  fn renew (&mut self) -> Result<bool,Error>;
+  /// Give up leadership so that another node can take over right away.
+  fn release (&mut self) -> Result<(),Error>;
+  /// Whether this node held the lease when it was last acquired or renewed.
+  fn is_leader (&self) -> bool;
+}
+
+#[cfg(all(feature="file-lease",not(target_arch="wasm32")))]
+pub use file::FileLease;
+
+// lock files need a filesystem, which browsers don't have
+#[cfg(all(feature="file-lease",not(target_arch="wasm32")))]
+mod file {
+  use super::Leadership;
+  use crate::{Clock,SystemClock};
+  use crate::Error;
+  use std::fs::{self,OpenOptions};
+  use std::io::{self,Write};
+  use std::path::PathBuf;
+  use std::sync::Arc;
+  use std::time::Duration;
+
+  /// Lease backed by a lock file that holds the leader's id and the time its
+  /// lease expires.
+  ///
+  /// The file is created atomically, so two nodes can't both take a free
+  /// lease. Taking over an expired lease deletes and recreates the file, which
+  /// is only safe when clocks are roughly in sync and renewals happen well
+  /// before the lease expires.
+  ///
+  /// ```rust,no_run
+  /// use eyros::{Leadership,FileLease,Error};
+  /// use std::time::Duration;
+  /// # fn main () -> Result<(),Error> {
+  /// let mut lease = FileLease::new("/tmp/eyros-db/leader", 1, Duration::from_secs(10));
+  /// if lease.acquire()? {
+  ///   // ingest, calling lease.renew()? every few seconds
+  /// } else {
+  ///   // serve reads
+  /// }
+  /// # Ok(()) }
+  /// ```
+  pub struct FileLease {
+    path: PathBuf,
+    id: u64,
+    duration: Duration,
+    clock: Arc<dyn Clock>,
+    leader: bool
+  }
+
+  impl FileLease {
+    /// Create a lease on the lock file at `path` for the node `id`. Each node
+    /// needs a distinct id.
+    pub fn new<T> (path: T, id: u64, duration: Duration) -> Self
+    where T: Into<PathBuf> {
+      Self {
+        path: path.into(),
+        id,
+        duration,
+        clock: Arc::new(SystemClock),
+        leader: false
+      }
+    }
+    /// Set the clock used to compute lease expiry.
+    pub fn clock (mut self, clock: Arc<dyn Clock>) -> Self {
+      self.clock = clock;
+      self
+    }
+    fn record (&self) -> Vec<u8> {
+      let expires = (self.clock.now() + self.duration).as_nanos() as u64;
+      let mut buf = Vec::with_capacity(16);
+      buf.extend(&self.id.to_be_bytes());
+      buf.extend(&expires.to_be_bytes());
+      buf
+    }
+    // (holder id, expiry in nanoseconds since the epoch)
+    fn read (&self) -> Result<Option<(u64,u64)>,Error> {
+      let buf = match fs::read(&self.path) {
+        Ok(buf) => buf,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into())
+      };
+      if buf.len() != 16 {
+        corrupt!("unexpected lease file length {}", buf.len());
+      }
+      let mut id = [0u8;8];
+      let mut expires = [0u8;8];
+      id.copy_from_slice(&buf[0..8]);
+      expires.copy_from_slice(&buf[8..16]);
+      Ok(Some((u64::from_be_bytes(id),u64::from_be_bytes(expires))))
+    }
+    fn create (&self) -> Result<bool,Error> {
+      let mut file = match OpenOptions::new().write(true).create_new(true)
+      .open(&self.path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Ok(false),
+        Err(e) => return Err(e.into())
+      };
+      file.write_all(&self.record())?;
+      file.sync_all()?;
+      Ok(true)
+    }
+  }
+
+  impl Leadership for FileLease {
+    fn acquire (&mut self) -> Result<bool,Error> {
+      if self.create()? {
+        self.leader = true;
+        return Ok(true);
+      }
+      match self.read()? {
+        Some((id,_)) if id == self.id => return self.renew(),
+        Some((_,expires)) if expires > self.clock.now().as_nanos() as u64 => {
+          self.leader = false;
+          return Ok(false);
+        },
+        _ => {}
+      }
+      match fs::remove_file(&self.path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+      }
+      self.leader = self.create()?;
+      Ok(self.leader)
+    }
+    fn renew (&mut self) -> Result<bool,Error> {
+      self.leader = match self.read()? {
+        Some((id,_)) if id == self.id => {
+          let mut file = OpenOptions::new().write(true).open(&self.path)?;
+          file.write_all(&self.record())?;
+          file.sync_all()?;
+          true
+        },
+        _ => false
+      };
+      Ok(self.leader)
+    }
+    fn release (&mut self) -> Result<(),Error> {
+      if let Some((id,_)) = self.read()? {
+        if id == self.id {
+          fs::remove_file(&self.path)?;
+        }
+      }
+      self.leader = false;
+      Ok(())
+    }
+    fn is_leader (&self) -> bool {
+      self.leader
+    }
+  }
+}
+
+#[cfg(all(feature="etcd-lease",not(target_arch="wasm32")))]
+pub use etcd::EtcdLease;
+
+// etcd serves a JSON gateway next to its gRPC API, which the http client that
+// `HttpFetch` uses can talk to without an async runtime
+#[cfg(all(feature="etcd-lease",not(target_arch="wasm32")))]
+mod etcd {
+  use super::Leadership;
+  use crate::Error;
+  use serde_json::{json,Value};
+  use std::time::Duration;
+
+  /// Lease backed by a key in etcd that holds the leader's id.
+  ///
+  /// The key is attached to an etcd lease with a time to live, and is only
+  /// written when it doesn't exist yet, so two nodes can't both take it. etcd
+  /// deletes the key when its lease expires or is revoked, which leaves
+  /// expiry to the etcd cluster instead of to the clocks of the nodes.
+  ///
+  /// ```rust,no_run
+  /// use eyros::{Leadership,EtcdLease,Error};
+  /// use std::time::Duration;
+  /// # fn main () -> Result<(),Error> {
+  /// let mut lease = EtcdLease::new("http://127.0.0.1:2379", "eyros/leader", 1,
+  ///   Duration::from_secs(10));
+  /// if lease.acquire()? {
+  ///   // ingest, calling lease.renew()? every few seconds
+  /// } else {
+  ///   // serve reads
+  /// }
+  /// # Ok(()) }
+  /// ```
+  pub struct EtcdLease {
+    agent: ureq::Agent,
+    endpoint: String,
+    key: String,
+    value: String,
+    ttl: Duration,
+    lease: Option<i64>,
+    leader: bool
+  }
+
+  impl EtcdLease {
+    /// Create a lease on `key` for the node `id`, through the etcd member
+    /// at `endpoint`. Each node needs a distinct id.
+    pub fn new<T> (endpoint: T, key: &str, id: u64, ttl: Duration) -> Self
+    where T: Into<String> {
+      Self {
+        agent: ureq::AgentBuilder::new()
+          .timeout(Duration::from_secs(10))
+          .build(),
+        endpoint: endpoint.into().trim_end_matches('/').to_string(),
+        key: base64(key.as_bytes()),
+        value: base64(&id.to_be_bytes()),
+        ttl,
+        lease: None,
+        leader: false
+      }
+    }
+    /// Use an agent configured with other timeouts, proxies, or TLS settings.
+    pub fn agent (mut self, agent: ureq::Agent) -> Self {
+      self.agent = agent;
+      self
+    }
+    fn call (&self, path: &str, body: Value) -> Result<Value,Error> {
+      let url = format!["{}/v3/{}", self.endpoint, path];
+      let res = self.agent.post(&url).send_string(&body.to_string())
+        .map_err(|e| Error::Other(format!["request to {} failed: {}", url, e]))?;
+      Ok(serde_json::from_str(&res.into_string()?)?)
+    }
+    // keep the lease alive, returning whether it hadn't expired yet
+    fn keep_alive (&self, lease: i64) -> Result<bool,Error> {
+      let res = self.call("lease/keepalive", json!({ "ID": lease.to_string() }))?;
+      Ok(int(&res["result"]["TTL"]).unwrap_or(0) > 0)
+    }
+    fn grant (&mut self) -> Result<i64,Error> {
+      let ttl = self.ttl.as_secs().max(1);
+      let res = self.call("lease/grant", json!({ "TTL": ttl.to_string() }))?;
+      let lease = match int(&res["ID"]) {
+        Some(id) => id,
+        None => return Err(Error::Other(format!["etcd granted no lease: {}", res]))
+      };
+      self.lease = Some(lease);
+      Ok(lease)
+    }
+  }
+
+  impl Leadership for EtcdLease {
+    fn acquire (&mut self) -> Result<bool,Error> {
+      let lease = match self.lease {
+        Some(lease) if self.keep_alive(lease)? => lease,
+        _ => self.grant()?
+      };
+      let res = self.call("kv/txn", json!({
+        "compare": [{
+          "target": "CREATE", "result": "EQUAL",
+          "key": self.key, "create_revision": "0"
+        }],
+        "success": [{
+          "request_put": { "key": self.key, "value": self.value, "lease": lease.to_string() }
+        }],
+        "failure": [{ "request_range": { "key": self.key } }]
+      }))?;
+      // the key is already ours when this node took it with the same lease
+      let held = &res["responses"][0]["response_range"]["kvs"][0];
+      self.leader = res["succeeded"].as_bool() == Some(true)
+        || (held["value"].as_str() == Some(&self.value)
+          && int(&held["lease"]) == Some(lease));
+      Ok(self.leader)
+    }
+    fn renew (&mut self) -> Result<bool,Error> {
+      self.leader = match self.lease {
+        Some(lease) if self.leader => self.keep_alive(lease)?,
+        _ => false
+      };
+      Ok(self.leader)
+    }
+    fn release (&mut self) -> Result<(),Error> {
+      // revoking the lease deletes the key
+      if let Some(lease) = self.lease.take() {
+        self.call("lease/revoke", json!({ "ID": lease.to_string() }))?;
+      }
+      self.leader = false;
+      Ok(())
+    }
+    fn is_leader (&self) -> bool {
+      self.leader
+    }
+  }
+
+  // the gateway writes 64-bit integers as strings
+  fn int (value: &Value) -> Option<i64> {
+    match value {
+      Value::String(s) => s.parse().ok(),
+      value => value.as_i64()
+    }
+  }
+
+  // keys and values are sent as base64
+  fn base64 (bytes: &[u8]) -> String {
+    const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3)*4);
+    for chunk in bytes.chunks(3) {
+      let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+      let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+      for i in 0..4 {
+        if i <= chunk.len() {
+          out.push(CHARS[(n >> (18 - 6*i) & 63) as usize] as char);
+        } else {
+          out.push('=');
+        }
+      }
+    }
+    out
+  }
+}
@@ -11,7 +11,9 @@
 //! * good for geospatial and time-series data
 //!
 //! eyros operates on scalar (x) or interval (min,max) coordinates for each
-//! dimension. There are 2 operations: batched write (for inserting and deleting)
+//! dimension. Points are tuples of 2 to 8 dimensions, and each coordinate is
+//! an `f32`, `f64`, or any integer type from `u8`/`i8` to `u64`/`i64`, or a
+//! `(min,max)` interval of one of those. There are 2 operations: batched write (for inserting and deleting)
 //! and query by bounding box. All features that intersect the bounding box are
 //! returned in the query results.
 //!
@@ -33,9 +35,8 @@
 //! `time` scalar that is between `0.0` and `100.0` are printed to stdout.
 //!
 //! ```rust,no_run
-//! use eyros::{DB,Row};
+//! use eyros::{DB,Row,Error};
 //! use rand::random;
-//! use failure::Error;
 //! use random_access_disk::RandomAccessDisk;
 //! use std::path::PathBuf;
 //!
@@ -63,7 +64,7 @@
 //!   Ok(())
 //! }
 //!
-//! fn storage(name:&str) -> Result<RandomAccessDisk,Error> {
+//! fn storage(name:&str) -> Result<RandomAccessDisk,failure::Error> {
 //!   let mut p = PathBuf::from("/tmp/eyros-db/");
 //!   p.push(name);
 //!   Ok(RandomAccessDisk::builder(p)
@@ -116,9 +117,8 @@
 //! that bounding box queries will return both types of features.
 //!
 //! ```rust,no_run
-//! use eyros::{DB,Row,Mix,Mix2};
+//! use eyros::{DB,Row,Mix,Mix2,Error};
 //! use rand::random;
-//! use failure::Error;
 //! use random_access_disk::RandomAccessDisk;
 //! use std::path::PathBuf;
 //!
@@ -156,7 +156,7 @@
 //!   Ok(())
 //! }
 //!
-//! fn storage(name:&str) -> Result<RandomAccessDisk,Error> {
+//! fn storage(name:&str) -> Result<RandomAccessDisk,failure::Error> {
 //!   let mut p = PathBuf::from("/tmp/eyros-mix-db/");
 //!   p.push(name);
 //!   Ok(RandomAccessDisk::builder(p)
@@ -182,31 +182,201 @@ mod data;
 mod read_block;
 mod pivots;
 mod write_cache;
+mod retry;
+mod clock;
+mod maintenance;
+mod error;
+mod leader;
+mod consistency;
+mod combine;
+mod shard;
+mod multi;
+mod usage;
+mod heat;
+mod view;
+mod trigger;
+mod outbox;
+mod nearest;
+mod frozen;
+mod format;
+mod cancel;
+mod aggregate;
+mod corridor;
+mod geo;
+mod top_k;
+mod wal;
+mod summary;
+mod compress;
+mod compact;
+mod digest;
+mod admission;
+mod lock;
+mod prune;
+mod storage;
+mod changes;
+mod retention;
+mod restore;
+mod region;
+mod encrypt;
+mod audit;
+mod effects;
+mod codec;
+mod fuzz;
+mod derive;
+mod visibility;
+mod parallel;
+mod group;
+mod stats;
+mod cache;
+mod coalesce;
+mod location;
+mod tree_stats;
+mod cost;
+mod explain;
+mod swap;
+mod collection;
+mod user_meta;
+mod check;
+mod backup;
+mod selectivity;
+mod bulk;
+mod intersect;
+mod cursor;
+mod realtime;
+mod tune;
+mod subscribe;
+mod transaction;
+mod migrate;
+mod file_lock;
+mod profile;
+mod remote;
+#[doc(hidden)] pub use crate::derive::__private;
+#[cfg(feature="proj")] mod proj;
+#[cfg(feature="geojson")] pub mod geojson;
+#[cfg(feature="memory")] mod memory;
+#[cfg(feature="aes")] mod encrypted;
+#[cfg(feature="hypercore")] mod hypercore;
+#[cfg(feature="wasm")] mod wasm;
+pub mod async_db;
+pub mod raw;
 
 pub use crate::setup::{Setup,SetupFields};
 use crate::staging::{Staging,StagingIterator};
 use crate::planner::plan;
-pub use crate::point::{Point,Scalar,Cursor,Block};
+pub use crate::point::{Point,Distance,ToF64,Midpoint,Scalar,Cursor,Block};
 pub use crate::mix::{Mix,Mix2,Mix3,Mix4,Mix5,Mix6,Mix7,Mix8};
 #[doc(hidden)] pub use crate::tree::{Tree,TreeIterator,TreeOpts};
+#[doc(hidden)] pub use crate::frozen::FrozenTree;
 #[doc(hidden)] pub use crate::branch::Branch;
-#[doc(hidden)] pub use crate::data::{DataStore,DataRange};
+#[doc(hidden)] pub use crate::lock::Lock;
+#[doc(hidden)] pub use crate::data::{DataStore,DataRange,DataRangeIter};
+pub use crate::data::CacheMode;
+pub use crate::retry::{RetryPolicy,is_transient};
+pub use crate::clock::{Clock,SystemClock,ManualClock,Rng,default_clock};
+pub use crate::maintenance::{Job,MaintenanceReport,Executor};
+pub use crate::error::{Error,Closed,Poisoned,Conflict,Stale,ChecksumMismatch,
+  Overloaded,HistoryPruned,ReadOnly,StaleLocation,StaleCursor,
+  Backpressure,Locked,FormatVersion,DecryptFailed,Cancelled,TimedOut};
+pub use crate::migrate::FORMAT_VERSION;
+pub use crate::consistency::Consistency;
+pub use crate::combine::{merge,MergeReport};
+pub use crate::shard::ShardInfo;
+pub use crate::multi::{MultiDB,MultiQueryIterator};
+pub use crate::usage::DiskUsage;
+pub use crate::tree_stats::TreeStats;
+pub use crate::cost::QueryCost;
+pub use crate::explain::{QueryPlan,TreePlan};
+pub use crate::check::{CheckReport,CheckProblem};
+pub use crate::backup::{Backup,BackupReport};
+pub use crate::bulk::{BulkLoader,BulkReport};
+pub use crate::intersect::Intersect;
+pub use crate::cursor::QueryCursor;
+pub use crate::tune::{AutoTune,Tuning,TuningState};
+pub use crate::subscribe::Subscription;
+pub use crate::transaction::Transaction;
+#[cfg(not(target_arch="wasm32"))]
+pub use crate::file_lock::{DiskStorage,disk_storage};
+pub use crate::heat::BlockHeat;
+pub use crate::view::View;
+pub use crate::trigger::{Trigger,TriggerAction};
+pub use crate::outbox::OutboxEvent;
+pub use crate::nearest::NearestIterator;
+pub use crate::format::TreeFormat;
+pub use crate::cancel::CancelToken;
+pub use crate::summary::Summarize;
+pub use crate::compress::Compression;
+pub use crate::compact::CompactReport;
+pub use crate::digest::QueryDigest;
+pub use crate::admission::Admission;
+pub use crate::prune::{Pruner,BlockInfo,Verdict};
+pub use crate::storage::{Adapter,adapt};
+#[cfg(feature="hypercore")]
+pub use crate::hypercore::{Feed,HypercoreStore,hypercore_storage};
+#[cfg(feature="aes")]
+pub use crate::encrypted::{Encrypted,EncryptedStorage,StorageKey,encrypt_storage,
+  ENCRYPTED_BLOCK_SIZE};
+pub use crate::changes::{Change,ChangeEntry,ChangesIterator};
+pub use crate::retention::Retention;
+pub use crate::restore::RestoreReport;
+pub use crate::region::{QueryRegion,Polygon,Circle,RegionIterator};
+pub use crate::encrypt::{Cipher,Encryption};
+pub use crate::audit::{AuditOp,AuditEvent};
+pub use crate::codec::{Codec,DesertCodec};
+pub use crate::fuzz::{Fuzz,Fuzzable,FuzzedQuery};
+pub use crate::parallel::ParallelIterator;
+pub use crate::group::GroupCommit;
+pub use crate::stats::{Stats,Latency};
+pub use crate::profile::Profile;
+pub use crate::location::StableLocation;
+pub use crate::remote::{Fetch,RemoteStore,RemoteStorage,remote_storage,s3_url};
+#[cfg(all(feature="http",not(target_arch="wasm32")))]
+pub use crate::remote::HttpFetch;
+use crate::stats::{Counters,Timer};
+use crate::prune::PruneState;
+use crate::encrypt::Keyring;
+use crate::audit::{Audit,AuditFn};
+use crate::visibility::VisibleFn;
+use crate::admission::{Gate,Permit};
+pub use crate::aggregate::Aggregate;
+pub use crate::corridor::{LonLat,CorridorIterator,EARTH_RADIUS,haversine,
+  segment_distance,corridor_boxes};
+#[cfg(feature="proj")]
+pub use crate::proj::{Projection,Projectable,Coordinate,WebMercator,ProjectedQuery};
+#[cfg(feature="memory")]
+pub use crate::memory::{MemoryStore,MemoryStorage,memory_storage};
+#[cfg(feature="wasm")]
+pub use crate::wasm::{WasmFilter,WasmLimits,WasmQueryIterator};
+use crate::outbox::Outbox;
+use crate::changes::ChangeLog;
+use crate::subscribe::Registry;
+use crate::effects::{Hold,Effect};
+use crate::file_lock::WriterLock;
+use crate::wal::{Wal,WalRecord,encode_rows,decode_rows};
+pub use crate::leader::Leadership;
+#[cfg(all(feature="file-lease",not(target_arch="wasm32")))]
+pub use crate::leader::FileLease;
+#[cfg(all(feature="etcd-lease",not(target_arch="wasm32")))]
+pub use crate::leader::EtcdLease;
 use crate::meta::Meta;
 pub use order::{order,order_len};
 
 use random_access_storage::RandomAccess;
-use failure::{Error,format_err};
 use desert::{ToBytes,FromBytes,CountBytes};
 use std::fmt::Debug;
-use std::cell::RefCell;
-use std::rc::Rc;
-use std::collections::HashSet;
+use std::sync::{Arc,RwLock};
+use std::collections::{BTreeMap,HashSet};
+use std::ops::{ControlFlow,Deref};
+use std::time::Duration;
+
+// meta, staging, and data stores that a database opens first
+type Stores<S,P,V> = (Meta<S>,Staging<S,P,V>,DataStore<S,P,V>);
 
 #[doc(hidden)]
 pub enum SubIterator<'b,S,P,V>
-where S: RandomAccess<Error=Error>, P: Point, V: Value {
-  Tree(TreeIterator<'b,S,P,V>),
-  Staging(StagingIterator<'b,P,V>)
+where S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  Tree(Box<TreeIterator<'b,S,P,V>>),
+  Staging(StagingIterator<'b,P,V>),
+  Parallel(Box<ParallelIterator<P,V>>)
 }
 
 /// Data to use for the payload portion stored at a coordinate.
@@ -218,43 +388,129 @@ impl<T> Value for T where T: Debug+Clone+ToBytes+FromBytes+CountBytes+'static {}
 /// yet to ensure that batches will invalidate existing locations, so you will
 /// need to be careful of this yourself. Otherwise the wrong data could be
 /// deleted.
+///
+/// The first field is the offset of the data block plus one, or `0` for rows
+/// in the staging area, and the second is the index of the row. Convert
+/// locations that are kept outside of the process to a `StableLocation` with
+/// `db.stable_location()`, which records the commit and checks it when the
+/// location is resolved.
 pub type Location = (u64,u32);
 
-/// Container to insert or delete data for a `batch()`.
+/// Container to insert, delete, or update data for a `batch()`.
+///
+/// `Update(location,point,value)` replaces the row at `location`. Rows in the
+/// staging area are replaced in place. Rows in data blocks are overwritten in
+/// place when the new row has the same encoded size and the point stays within
+/// the bounds of the block. Otherwise the row is deleted and the new row is
+/// inserted as part of the same batch. If the row at `location` was already
+/// deleted, the new row is inserted.
 #[derive(Clone,Debug)]
 pub enum Row<P,V> where P: Point, V: Value {
   Insert(P,V),
-  Delete(Location)
+  Delete(Location),
+  Update(Location,P,V)
+}
+
+/// Query result that shares its storage with the data block cache.
+///
+/// Rows read from a tree hold a reference-counted pointer into the cached
+/// block instead of a copy, so large values are only cloned when you ask for
+/// an owned row with `into_owned()`. Rows from the staging area are owned.
+#[derive(Clone,Debug)]
+pub enum SharedRow<P,V> where P: Point, V: Value {
+  Block(Arc<[(P,V,Location)]>,usize),
+  Owned((P,V,Location))
+}
+
+impl<P,V> SharedRow<P,V> where P: Point, V: Value {
+  pub fn point (&self) -> &P { &self.row().0 }
+  pub fn value (&self) -> &V { &self.row().1 }
+  pub fn location (&self) -> &Location { &self.row().2 }
+  pub fn row (&self) -> &(P,V,Location) {
+    match self {
+      SharedRow::Block(rows,i) => &rows[*i],
+      SharedRow::Owned(row) => row
+    }
+  }
+  pub fn into_owned (self) -> (P,V,Location) {
+    match self {
+      SharedRow::Block(rows,i) => rows[i].clone(),
+      SharedRow::Owned(row) => row
+    }
+  }
+}
+
+impl<P,V> Deref for SharedRow<P,V> where P: Point, V: Value {
+  type Target = (P,V,Location);
+  fn deref (&self) -> &Self::Target { self.row() }
 }
 
 /// Top-level database API.
+///
+/// Trees, the data store, and the staging area are shared behind
+/// `Arc<RwLock<_>>`, so a handle is `Send` and `Sync` when its storage, storage
+/// function, point, and value types are. Query iterators only share those
+/// locks with the handle, so several queries can be read from different
+/// threads at once.
 pub struct DB<S,U,P,V> where
-S: RandomAccess<Error=Error>,
-U: (Fn(&str) -> Result<S,Error>),
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
 P: Point, V: Value {
   open_store: U,
-  pub trees: Vec<Rc<RefCell<Tree<S,P,V>>>>,
+  pub trees: Vec<Arc<RwLock<Tree<S,P,V>>>>,
   pub staging: Staging<S,P,V>,
-  pub data_store: Rc<RefCell<DataStore<S,P,V>>>,
+  pub data_store: Arc<RwLock<DataStore<S,P,V>>>,
   meta: Meta<S>,
-  pub fields: SetupFields
+  // entries from put_meta() and delete_meta() for the next batch, with `None`
+  // for a deleted entry
+  pending_meta: BTreeMap<String,Option<Vec<u8>>>,
+  pub fields: SetupFields,
+  closed: bool,
+  poisoned: Option<String>,
+  views: Vec<View<S,P,V>>,
+  triggers: Vec<Trigger<P,V>>,
+  outbox: Option<Outbox<S>>,
+  wal: Option<Wal<S>>,
+  change_log: Option<ChangeLog<S>>,
+  gate: Option<Arc<Gate>>,
+  pruner: Option<Arc<dyn Pruner<P,V>>>,
+  audit: Option<AuditFn<P>>,
+  audit_context: Option<String>,
+  visible: Option<VisibleFn<P,V>>,
+  // nanoseconds per row that the last build of staged rows took
+  build_cost: Option<f64>,
+  subscriptions: Registry<P,V>,
+  // effects held back while `AsyncDB` may run the call again
+  hold: Option<Hold<P,V>>,
+  writer_lock: Option<WriterLock>
+}
+
+// in-memory state of a handle that reading the stores back doesn't restore,
+// from `savepoint()` for `rollback()`
+pub(crate) struct Savepoint {
+  pending_meta: BTreeMap<String,Option<Vec<u8>>>,
+  poisoned: Option<String>
 }
 
 impl<S,U,P,V> DB<S,U,P,V> where
-S: RandomAccess<Error=Error>,
-U: (Fn(&str) -> Result<S,Error>),
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
 P: Point, V: Value {
   /// Create a new database instance from `open_store`, a function that receives
   /// a string path as an argument and returns a Result with a RandomAccess
   /// store. The database will be created with the default configuration.
   ///
+  /// The storage function doesn't tell the database where its stores live,
+  /// so this handle takes no lock: two writable handles on the same stores
+  /// will corrupt them. Open a directory with `DB::open_dir()`, which locks
+  /// it, or pass a path to `Setup::lock_file()`.
+  ///
   /// For example:
   ///
   /// ```rust,no_run
-  /// use eyros::DB;
+  /// use eyros::{DB,Error};
   /// use random_access_disk::RandomAccessDisk;
   /// use std::path::PathBuf;
-  /// use failure::Error;
   ///
   /// type P = ((f32,f32),(f32,f32));
   /// type V = u32;
@@ -265,7 +521,7 @@ P: Point, V: Value {
   ///   Ok(())
   /// }
   ///
-  /// fn storage (name: &str) -> Result<RandomAccessDisk,Error> {
+  /// fn storage (name: &str) -> Result<RandomAccessDisk,failure::Error> {
   ///   let mut p = PathBuf::from("/tmp/eyros-db/");
   ///   p.push(name);
   ///   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
@@ -278,8 +534,7 @@ P: Point, V: Value {
   /// Create a new database instance from `setup`, a configuration builder.
   ///
   /// ```rust,no_run
-  /// # use eyros::{DB,Setup};
-  /// # use failure::Error;
+  /// # use eyros::{DB,Setup,Error};
   /// # use random_access_disk::RandomAccessDisk;
   /// # use std::path::PathBuf;
   /// # fn main () -> Result<(),Error> {
@@ -293,7 +548,7 @@ P: Point, V: Value {
   /// )?;
   /// # Ok(()) }
   /// #
-  /// # fn storage(name: &str) -> Result<RandomAccessDisk,Error> {
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
   /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
   /// #   p.push(name);
   /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
@@ -303,8 +558,7 @@ P: Point, V: Value {
   /// You can also use `Setup`'s `.build()?` method to get a `DB` instance:
   ///
   /// ```rust,no_run
-  /// use eyros::{DB,Setup};
-  /// # use failure::Error;
+  /// use eyros::{DB,Setup,Error};
   /// # use std::path::PathBuf;
   /// # use random_access_disk::RandomAccessDisk;
   ///
@@ -318,7 +572,7 @@ P: Point, V: Value {
   ///   .build()?;
   /// # Ok(()) }
   /// #
-  /// # fn storage(name: &str) -> Result<RandomAccessDisk,Error> {
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
   /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
   /// #   p.push(name);
   /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
@@ -328,80 +582,973 @@ P: Point, V: Value {
   /// Always open a database with the same settings. Things will break if you
   /// change . There is no runtime check yet to ensure a database is opened with
   /// the same configuration that it was created with.
+  ///
+  /// Like `DB::open()`, the handle takes no lock unless `setup` has a
+  /// `Setup::lock_file()`.
   pub fn open_from_setup(setup: Setup<S,U>) -> Result<Self,Error> {
-    let meta = Meta::open((setup.open_store)("meta")?)?;
-    let staging = Staging::open(
-      (setup.open_store)("staging_inserts")?,
-      (setup.open_store)("staging_deletes")?
-    )?;
-    let data_store = DataStore::open(
-      (setup.open_store)("data")?,
-      (setup.open_store)("range")?,
-      setup.fields.max_data_size,
-      setup.fields.bbox_cache_size,
-      setup.fields.data_list_cache_size
-    )?;
+    setup.fields.tree_format.check()?;
+    setup.fields.compression.check()?;
+    // lock before reading any store, since the writer may be mid-commit
+    let writer_lock = match &setup.fields.lock_file {
+      Some(path) if setup.fields.lock && !setup.fields.read_only => {
+        Some(WriterLock::acquire(path)?)
+      },
+      _ => None
+    };
+    migrate::run(&setup.open_store, setup.fields.read_only)?;
+    if !setup.fields.read_only {
+      swap::recover(&setup.open_store)?;
+    }
+    let (meta,staging,data_store) = Self::open_stores(
+      &setup.open_store, &setup.fields, Arc::new(Counters::default()))?;
+    let gate = setup.fields.max_queries
+      .map(|limit| Gate::new(limit, setup.fields.admission));
     let mut db = Self {
       open_store: setup.open_store,
       staging,
-      data_store: Rc::new(RefCell::new(data_store)),
+      data_store: Arc::new(RwLock::new(data_store)),
       meta: meta,
+      pending_meta: BTreeMap::new(),
       trees: vec![],
-      fields: setup.fields
+      fields: setup.fields,
+      closed: false,
+      poisoned: None,
+      views: vec![],
+      triggers: vec![],
+      outbox: None,
+      wal: None,
+      change_log: None,
+      gate,
+      pruner: None,
+      audit: None,
+      audit_context: None,
+      visible: None,
+      build_cost: None,
+      subscriptions: Registry::default(),
+      hold: None,
+      writer_lock
     };
+    db.use_tuning()?;
     for i in 0..db.meta.mask.len() {
       db.create_tree(i)?;
     }
+    db.open_views()?;
+    db.open_change_log()?;
+    if db.fields.wal && !db.fields.read_only {
+      db.wal = Some(Wal::open((db.open_store)("wal")?));
+      db.recover_wal()?;
+    }
     Ok(db)
   }
 
+  fn open_stores (open_store: &U, fields: &SetupFields, counters: Arc<Counters>)
+  -> Result<Stores<S,P,V>,Error> {
+    let meta = Meta::open(open_store("meta")?)?;
+    let mut staging = Staging::open(
+      open_store("staging_inserts")?,
+      open_store("staging_deletes")?,
+      Arc::clone(&counters)
+    )?;
+    staging.max_dirty_bytes(fields.max_dirty_bytes)?;
+    let (bbox_cache_size,list_cache_bytes) = match fields.cache_bytes {
+      Some(bytes) => {
+        let (n,bytes) = cache::split_budget::<P>(bytes, fields.bbox_cache_size);
+        (n, Some(bytes))
+      },
+      None => (fields.bbox_cache_size, None)
+    };
+    let mut data_store = DataStore::open_counted(
+      open_store("data")?,
+      open_store("range")?,
+      fields.max_data_size,
+      bbox_cache_size,
+      fields.data_list_cache_size,
+      counters
+    )?;
+    data_store.set_list_cache_bytes(list_cache_bytes);
+    data_store.set_range_chunk_size(fields.range_chunk_size);
+    data_store.maintenance_cache = fields.maintenance_cache;
+    data_store.quarantine = meta.quarantine.iter().cloned().collect();
+    data_store.retry = fields.retry.clone();
+    data_store.clock = Arc::clone(&fields.clock);
+    data_store.heat = fields.heat_half_life.map(BlockHeat::new);
+    data_store.checksums = fields.block_checksums;
+    data_store.compression = fields.compression;
+    data_store.keys = fields.encryption.as_ref()
+      .map(|e| Keyring::new(e, meta.key_epoch));
+    Ok((meta,staging,data_store))
+  }
+
+  /// Reload a poisoned handle from storage.
+  ///
+  /// The meta, staging, and data stores are opened again and the root block
+  /// of every tree is read back. The handle stays poisoned if any of these
+  /// checks fail. Rows from the batch that poisoned the handle may or may not
+  /// be present afterward, so write that batch again if you need it.
+  pub fn try_recover (&mut self) -> Result<(),Error> {
+    if self.closed { return Err(Closed.into()) }
+    if !self.fields.read_only {
+      swap::recover(&self.open_store)?;
+    }
+    self.reload()?;
+    self.poisoned = None;
+    let r = self.recover_wal();
+    self.poison_on_err(r)
+  }
+
+  fn begin_wal (&mut self, rows: &[Row<P,V>], events: &[u8]) -> Result<(),Error> {
+    if self.wal.is_none() { return Ok(()) }
+    let has_update = rows.iter().any(|r| matches![r, Row::Update(..)]);
+    // batches that reach the base size may truncate and rewrite staging
+    let snapshot = if has_update
+    || self.staging.len()? + rows.len() >= self.fields.base_size {
+      Some(self.staging.snapshot()?)
+    } else {
+      None
+    };
+    // deletes and in-place updates rewrite these blocks, and replaying the
+    // batch over them would read the rows they already changed
+    let mut offsets: Vec<u64> = rows.iter().filter_map(|r| match r {
+      Row::Delete(loc) | Row::Update(loc,_,_) if loc.0 != 0 => Some(loc.0-1),
+      _ => None
+    }).collect();
+    offsets.sort_unstable();
+    offsets.dedup();
+    let blocks = {
+      let mut dstore = self.data_store.write_lock()?;
+      offsets.into_iter()
+        .map(|offset| Ok((offset,dstore.read(offset)?)))
+        .collect::<Result<Vec<_>,Error>>()?
+    };
+    // the batch appends to these before it commits
+    let mut tails = vec![];
+    if let Some(log) = &self.change_log {
+      let (log_len,index_len) = log.store_bytes()?;
+      tails.push(("changes".to_string(),log_len));
+      tails.push(("changes_index".to_string(),index_len));
+    }
+    if !events.is_empty() {
+      tails.push(("outbox".to_string(),self.outbox_store()?.len()?));
+    }
+    let record = WalRecord {
+      sequence: self.meta.sequence + 1,
+      staging: self.staging.store_bytes()?,
+      data: self.data_store.read_lock()?.store_bytes()?,
+      meta: self.meta.to_bytes(),
+      snapshot,
+      rows: encode_rows(rows)?,
+      blocks,
+      tails,
+      events: events.to_vec()
+    };
+    self.wal.as_mut().unwrap().begin(&record)
+  }
+
+  fn end_wal (&mut self) -> Result<(),Error> {
+    match &mut self.wal {
+      Some(wal) => wal.clear(),
+      None => Ok(())
+    }
+  }
+
+  /// Finish or redo the batch left in the write-ahead log by a crash.
+  fn recover_wal (&mut self) -> Result<(),Error> {
+    let record = match &mut self.wal {
+      Some(wal) => wal.read()?,
+      None => return Ok(())
+    };
+    let record = match record {
+      Some(record) => record,
+      None => return self.end_wal() // empty or torn before the batch began
+    };
+    if self.meta.sequence >= record.sequence { // committed
+      self.clear_unmasked_trees()?;
+      return self.end_wal();
+    }
+    // roll back the partial writes of the batch, then apply it again
+    self.meta.restore(&record.meta)?;
+    let mut istore = (self.open_store)("staging_inserts")?;
+    let mut dstore = (self.open_store)("staging_deletes")?;
+    match &record.snapshot {
+      Some((ibuf,dbuf)) => {
+        istore.truncate(0)?;
+        if !ibuf.is_empty() { istore.write(0, ibuf)? }
+        dstore.truncate(0)?;
+        if !dbuf.is_empty() { dstore.write(0, dbuf)? }
+      },
+      None => {
+        istore.truncate(record.staging.0)?;
+        dstore.truncate(record.staging.1)?;
+      }
+    }
+    istore.sync_all()?;
+    dstore.sync_all()?;
+    let stores = [("data",record.data.0),("range",record.data.1)];
+    let tails = record.tails.iter().map(|(name,len)| (name.as_str(),*len));
+    for (name,len) in stores.iter().cloned().chain(tails) {
+      let mut store = (self.open_store)(name)?;
+      if store.len()? > len {
+        store.truncate(len)?;
+        store.sync_all()?;
+      }
+    }
+    if !record.blocks.is_empty() {
+      let mut store = (self.open_store)("data")?;
+      for (offset,body) in record.blocks.iter() {
+        // skip the u32 block length
+        store.write(offset+4, body)?;
+      }
+      store.sync_all()?;
+    }
+    self.reload()?;
+    self.data_store.write_lock()?
+      .forget_blocks(record.blocks.iter().map(|(offset,_)| *offset));
+    self.clear_unmasked_trees()?;
+    if self.change_log.is_some() {
+      self.change_log = Some(ChangeLog::open(
+        (self.open_store)("changes")?,
+        (self.open_store)("changes_index")?
+      )?);
+    }
+    self.outbox = None;
+    self.rebuild_views()?;
+    let rows = decode_rows(&record.rows)?;
+    let changes = self.batch_changes(&rows)?;
+    self.commit_batch(&rows, changes.as_deref(), &record.events)
+  }
+
+  // trees outside of the committed mask are left over from an interrupted or
+  // unfinished merge
+  fn clear_unmasked_trees (&mut self) -> Result<(),Error> {
+    for (i,tree) in self.trees.iter().enumerate() {
+      if self.meta.mask.get(i).cloned().unwrap_or(false) { continue }
+      let mut t = tree.write_lock()?;
+      if !t.is_empty()? { t.clear()? }
+    }
+    Ok(())
+  }
+
+  /// Load commits that other handles wrote to the same storage.
+  pub fn refresh (&mut self) -> Result<(),Error> {
+    self.check_open()?;
+    self.reload()
+  }
+
+  /// Return the sequence number of the last commit this handle wrote or
+  /// loaded. Pass it to `Consistency::ReadYourWrites` on another handle to
+  /// make sure that handle sees the commit.
+  pub fn sequence (&self) -> u64 {
+    self.meta.sequence
+  }
+
+  fn reload (&mut self) -> Result<(),Error> {
+    let (meta,staging,mut data_store) = Self::open_stores(
+      &self.open_store, &self.fields, self.counters()?)?;
+    let summaries: Vec<_> = {
+      let mut old = self.data_store.write_lock()?;
+      data_store.adopt_list_cache(&mut old);
+      // cloned, so that a reload that fails keeps them for the next one
+      old.summaries.iter().map(|s| (s.name.clone(),Arc::clone(&s.summarize))).collect()
+    };
+    self.meta = meta;
+    self.pending_meta.clear();
+    self.staging = staging;
+    self.data_store = Arc::new(RwLock::new(data_store));
+    self.use_tuning()?;
+    for (name,summarize) in summaries {
+      self.open_summary(&name, summarize)?;
+    }
+    let frozen = self.frozen_trees()?;
+    self.trees.clear();
+    for i in 0..self.meta.mask.len() {
+      self.create_tree(i)?;
+    }
+    for (i,tree) in self.trees.iter().enumerate() {
+      let mut t = tree.write_lock()?;
+      if frozen.contains(&i) {
+        t.freeze()?;
+      } else if !t.is_empty()? {
+        let (root,bytes) = (t.root()?,t.bytes);
+        t.read_block(root, bytes)?;
+      }
+    }
+    self.open_views()
+  }
+
+  pub(crate) fn savepoint (&self) -> Savepoint {
+    Savepoint {
+      pending_meta: self.pending_meta.clone(),
+      poisoned: self.poisoned.clone()
+    }
+  }
+
+  // read the stores back after the writes of a call were dropped, for
+  // `AsyncDB` to run the call again once the blocks it missed are fetched
+  pub(crate) fn rollback (&mut self, savepoint: &Savepoint) -> Result<(),Error> {
+    self.poisoned = savepoint.poisoned.clone();
+    let r = self.reload().and_then(|_| self.open_change_log());
+    self.pending_meta = savepoint.pending_meta.clone();
+    self.poison_on_err(r)
+  }
+
+  fn open_change_log (&mut self) -> Result<(),Error> {
+    if self.fields.changes {
+      self.change_log = Some(ChangeLog::open(
+        (self.open_store)("changes")?,
+        (self.open_store)("changes_index")?
+      )?);
+    }
+    Ok(())
+  }
+
+  fn open_views (&mut self) -> Result<(),Error> {
+    let names = view::load_names(&mut (self.open_store)("views")?)?;
+    self.views.clear();
+    for name in names {
+      let store = (self.open_store)(&format!("view_{}",name))?;
+      self.views.push(View::open(&name, store)?);
+    }
+    Ok(())
+  }
+
+  fn save_view_names (&mut self) -> Result<(),Error> {
+    let names: Vec<&str> = self.views.iter().map(|v| v.name()).collect();
+    view::save_names(&mut (self.open_store)("views")?, &names)
+  }
+
+  /// Create a materialized view named `name` over `bbox`.
+  ///
+  /// The view starts out with the rows that currently intersect `bbox` and
+  /// every later `batch()` updates it, so `db.view(name)` can serve the result
+  /// set without running a query. Views persist across reopens until they
+  /// are dropped with `drop_view()`.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Row,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut db: DB<_,_,((f32,f32),(f32,f32)),u32> = DB::open(storage)?;
+  /// db.create_view("harbor", ((-0.5,-0.8),(0.3,-0.5)))?;
+  /// db.batch(&[Row::Insert(((0.0,0.1),(-0.6,-0.55)), 7)])?;
+  /// let view = db.view("harbor").unwrap();
+  /// for (point,value) in view.rows() {
+  ///   // ...
+  /// }
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  ///
+  /// View names may contain ascii letters, digits, `-`, and `_`.
+  pub fn create_view (&mut self, name: &str, bbox: P::Bounds)
+  -> Result<(),Error> {
+    self.check_writable()?;
+    if name.is_empty() || !name.chars().all(|c| {
+      c.is_ascii_alphanumeric() || c == '-' || c == '_'
+    }) {
+      invalid!["invalid view name {:?}", name];
+    }
+    if self.views.iter().any(|v| v.name() == name) {
+      invalid!["view {} already exists", name];
+    }
+    let mut rows = vec![];
+    for result in self.scan(&bbox)? {
+      let (p,v,_) = result?;
+      rows.push((p,v));
+    }
+    let store = (self.open_store)(&format!("view_{}",name))?;
+    self.views.push(View::create(name, bbox, rows, store)?);
+    self.save_view_names()
+  }
+
+  /// Return the materialized view named `name`.
+  pub fn view (&self, name: &str) -> Option<&View<S,P,V>> {
+    self.views.iter().find(|v| v.name() == name)
+  }
+
+  /// Return the names of the materialized views.
+  pub fn views (&self) -> Vec<&str> {
+    self.views.iter().map(|v| v.name()).collect()
+  }
+
+  /// Remove the materialized view named `name`. Returns `false` if there is no
+  /// such view.
+  pub fn drop_view (&mut self, name: &str) -> Result<bool,Error> {
+    self.check_writable()?;
+    match self.views.iter().position(|v| v.name() == name) {
+      Some(i) => {
+        let mut view = self.views.remove(i);
+        view.destroy()?;
+        self.save_view_names()?;
+        Ok(true)
+      },
+      None => Ok(false)
+    }
+  }
+
+  // rows inserted and deleted by a batch, resolved before the batch runs
+  // because locations change once it has
+  fn row_changes (&mut self, rows: &[Row<P,V>]) -> Result<Vec<Change<P,V>>,Error> {
+    let mut changes = vec![];
+    for row in rows.iter() {
+      match row {
+        Row::Insert(p,v) => changes.push(Change::Insert(*p,v.clone())),
+        Row::Delete(loc) => {
+          if let Some((p,v)) = self.row_at(loc)? {
+            changes.push(Change::Delete(p,v,*loc));
+          }
+        },
+        Row::Update(loc,p,v) => {
+          if let Some((q,w)) = self.row_at(loc)? {
+            changes.push(Change::Delete(q,w,*loc));
+          }
+          changes.push(Change::Insert(*p,v.clone()));
+        }
+      }
+    }
+    Ok(changes)
+  }
+
+  // reject rows that would fail partway through writing a batch: points with
+  // coordinates that don't compare, such as NaN, and locations past the end
+  // of the data store
+  pub(crate) fn check_rows (&self, rows: &[Row<P,V>]) -> Result<(),Error> {
+    let data_len = self.data_store.read_lock()?.store_bytes()?.0;
+    for row in rows.iter() {
+      let (loc,point) = match row {
+        Row::Insert(p,_) => (None,Some(p)),
+        Row::Delete(loc) => (Some(loc),None),
+        Row::Update(loc,p,_) => (Some(loc),Some(p))
+      };
+      if let Some(p) = point {
+        if P::bounds(&vec![*p,*p]).is_none() {
+          invalid!["point {:?} has coordinates that can't be compared", p];
+        }
+      }
+      if let Some(loc) = loc {
+        if loc.0 > data_len {
+          invalid!["location {:?} is past the end of the data store", loc];
+        }
+      }
+    }
+    Ok(())
+  }
+
+  fn row_at (&mut self, loc: &Location) -> Result<Option<(P,V)>,Error> {
+    if loc.0 == 0 {
+      return Ok(self.staging.inserts.read_lock()?.get(loc.1 as usize).cloned());
+    }
+    let block = self.data_store.write_lock()?.list_shared(loc.0-1)?;
+    Ok(block.iter().find(|r| r.2 == *loc).map(|r| (r.0,r.1.clone())))
+  }
+
+  fn update_views (&mut self, changes: &[Change<P,V>]) -> Result<(),Error> {
+    let mut inserts = vec![];
+    let mut deletes = vec![];
+    for change in changes.iter() {
+      match change {
+        Change::Insert(p,v) => inserts.push((*p,v.clone())),
+        Change::Delete(p,v,_) => deletes.push((*p,v.clone()))
+      }
+    }
+    for view in self.views.iter_mut() {
+      if view.apply(&inserts, &deletes)? {
+        view.save()?;
+      }
+    }
+    Ok(())
+  }
+
+  // views are rewritten in place, so a batch that didn't commit can only be
+  // undone by reading each view back from the trees
+  fn rebuild_views (&mut self) -> Result<(),Error> {
+    for i in 0..self.views.len() {
+      let bbox = *self.views[i].bbox();
+      let mut rows = vec![];
+      for result in self.scan(&bbox)? {
+        let (p,v,_) = result?;
+        rows.push((p,v));
+      }
+      self.views[i].reset(rows)?;
+    }
+    Ok(())
+  }
+
+  fn record_changes (&mut self, changes: &[Change<P,V>]) -> Result<(),Error> {
+    if !self.views.is_empty() {
+      self.update_views(changes)?;
+    }
+    let now = self.fields.clock.now();
+    match &mut self.change_log {
+      Some(log) => log.append(changes, now),
+      None => Ok(())
+    }
+  }
+
+  /// Return whether a failed write has poisoned this handle.
+  pub fn is_poisoned (&self) -> bool {
+    self.poisoned.is_some()
+  }
+
+  fn poison_on_err<T> (&mut self, r: Result<T,Error>) -> Result<T,Error> {
+    if let Err(e) = &r {
+      self.poisoned = Some(e.to_string());
+    }
+    r
+  }
+
   /// Write a collection of updates to the database. Each update can be a
-  /// `Row::Insert(point,value)` or a `Row::Delete(location)`.
+  /// `Row::Insert(point,value)`, a `Row::Delete(location)`, or a
+  /// `Row::Update(location,point,value)`.
+  ///
+  /// Inserts and deletes in the same batch are committed together, so moving
+  /// a record is a delete of its old location and an insert of the new row:
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Row,Error};
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut db: DB<_,_,(f32,f32),u32> = DB::open(|name: &str| {
+  /// #   Ok(RandomAccessDisk::builder(name.into()).build()?)
+  /// # })?;
+  /// let bbox = ((-0.5,-0.5),(-0.4,-0.4));
+  /// let mut rows = vec![];
+  /// for result in db.query(&bbox)? {
+  ///   let (_,value,location) = result?;
+  ///   rows.push(Row::Delete(location));
+  ///   rows.push(Row::Insert((0.5,0.5), value));
+  /// }
+  /// db.batch(&rows)?;
+  /// # Ok(()) }
+  /// ```
   pub fn batch (&mut self, rows: &[Row<P,V>]) -> Result<(),Error> {
-    let inserts: Vec<(P,V)> = rows.iter()
-      .filter(|r| match r { Row::Insert(_p,_v) => true, _ => false })
+    self.check_writable()?;
+    let start = self.fields.clock.now();
+    self.check_debt()?;
+    let changes = self.batch_changes(rows)?;
+    let events = self.outbox_events(rows)?;
+    let counters = self.counters()?;
+    let io = counters.io();
+    self.commit_batch(rows, changes.as_deref(), &events)?;
+    self.fire_callbacks(rows);
+    self.build_within_deadline(start)?;
+    if let Some(changes) = &changes {
+      self.notify(changes);
+    }
+    counters.record_batch(io);
+    Ok(())
+  }
+
+  fn batch_changes (&mut self, rows: &[Row<P,V>])
+  -> Result<Option<Vec<Change<P,V>>>,Error> {
+    if self.views.is_empty() && self.change_log.is_none()
+    && self.subscriptions.is_empty() {
+      return Ok(None);
+    }
+    Ok(Some(self.row_changes(rows)?))
+  }
+
+  // With a write-ahead log, the changes feed, the views, and the outbox are
+  // written before the batch commits, and recovery undoes them along with the
+  // batch if it doesn't. Without one they are written after the commit, so a
+  // crash in between leaves a committed batch out of them.
+  fn commit_batch (&mut self, rows: &[Row<P,V>], changes: Option<&[Change<P,V>]>,
+  events: &[u8]) -> Result<(),Error> {
+    // before the first write, so that a stale writer fails without touching
+    // the stores that another writer committed
+    if self.fields.check_conflicts {
+      self.check_sequence()?;
+    }
+    self.stage_meta();
+    let r = self.begin_wal(rows, events);
+    self.poison_on_err(r)?;
+    let logged = self.wal.is_some();
+    if logged {
+      let r = self.record_batch(changes, events);
+      self.poison_on_err(r)?;
+    }
+    let r = self.batch_rows(rows);
+    self.poison_on_err(r)?;
+    let r = self.end_wal();
+    self.poison_on_err(r)?;
+    if !logged {
+      let r = self.record_batch(changes, events);
+      self.poison_on_err(r)?;
+    }
+    Ok(())
+  }
+
+  fn record_batch (&mut self, changes: Option<&[Change<P,V>]>, events: &[u8])
+  -> Result<(),Error> {
+    if let Some(changes) = changes {
+      self.record_changes(changes)?;
+    }
+    if !events.is_empty() {
+      self.outbox_store()?.append_bytes(events)?;
+    }
+    Ok(())
+  }
+
+  /// Delete every row that intersects `bbox` and return the number of rows
+  /// removed.
+  ///
+  /// Rows in data blocks are removed by clearing their bits in the block
+  /// bitfields as the trees are walked, one block at a time, so there is no
+  /// need to collect locations with a query and pass them back in a separate
+  /// batch.
+  pub fn delete_query (&mut self, bbox: &P::Bounds) -> Result<usize,Error> {
+    self.delete_query_filter(bbox, |_,_| true)
+  }
+
+  /// Like `delete_query()`, but only delete rows where `filter(point,value)`
+  /// returns `true`.
+  pub fn delete_query_filter<F> (&mut self, bbox: &P::Bounds, filter: F)
+  -> Result<usize,Error> where F: Fn(&P,&V) -> bool {
+    self.check_writable()?;
+    if self.fields.check_conflicts {
+      self.check_sequence()?;
+    }
+    let _permit = self.admit()?;
+    let _timer = Timer::new(self.counters()?, Arc::clone(&self.fields.clock));
+    let record = !self.views.is_empty() || self.change_log.is_some()
+      || !self.subscriptions.is_empty();
+    let mut removed = vec![];
+    let staged: Vec<Location> = {
+      let inserts = self.staging.inserts.read_lock()?;
+      let deletes = self.staging.delete_set.read_lock()?;
+      inserts.iter().enumerate()
+        .map(|(i,(p,v))| (p,v,(0,i as u32)))
+        .filter(|(p,v,location)| {
+          !deletes.contains(location) && p.overlaps(bbox) && self.is_visible(p,v)
+            && filter(p,v)
+        })
+        .map(|(p,v,location)| {
+          if record { removed.push(Change::Delete(*p,v.clone(),location)) }
+          location
+        })
+        .collect()
+    };
+    let mut blocks = 0;
+    let r = self.delete_blocks(bbox, &filter, record, &mut blocks, &mut removed);
+    // bits are cleared block by block, so a failure after the first one
+    // leaves the delete half done
+    if blocks > 0 { self.poison_on_err(r)?; } else { r?; }
+    let count = blocks + staged.len();
+    self.audit_event(AuditOp::Delete, bbox, count);
+    if count == 0 { return Ok(0) }
+    let r = self.commit_deletes(blocks > 0, &staged);
+    self.poison_on_err(r)?;
+    if !removed.is_empty() {
+      let r = self.record_changes(&removed);
+      self.poison_on_err(r)?;
+      self.notify(&removed);
+    }
+    Ok(count)
+  }
+
+  // walk the trees for the blocks that intersect `bbox` and clear the bits of
+  // the matching rows in each block as it is read, counting them in `count`
+  fn delete_blocks<F> (&mut self, bbox: &P::Bounds, filter: &F, record: bool,
+  count: &mut usize, removed: &mut Vec<Change<P,V>>) -> Result<(),Error>
+  where F: Fn(&P,&V) -> bool {
+    let block_deletes = self.block_deletes()?;
+    let no_deletes = HashSet::new();
+    let mut seen = HashSet::new();
+    for offset in self.block_offsets(bbox)? {
+      // a block can be referenced from more than one tree while they merge
+      if !seen.insert(offset) { continue }
+      let deleted = block_deletes.get(&offset).unwrap_or(&no_deletes);
+      let mut dstore = self.data_store.write_lock()?;
+      let rows = dstore.list_or_quarantine(offset, CacheMode::Normal)?;
+      let mut locations = vec![];
+      for (p,v,location) in rows.iter() {
+        if deleted.contains(&location.1) || !p.overlaps(bbox)
+          || !self.is_visible(p,v) || !filter(p,v) { continue }
+        locations.push(*location);
+        if record { removed.push(Change::Delete(*p,v.clone(),*location)) }
+      }
+      if locations.is_empty() { continue }
+      dstore.delete(&locations)?;
+      *count += locations.len();
+    }
+    Ok(())
+  }
+
+  // sync the cleared block bits and mark the `staged` rows deleted
+  fn commit_deletes (&mut self, blocks: bool, staged: &Vec<Location>)
+  -> Result<(),Error> {
+    if blocks {
+      self.data_store.write_lock()?.commit()?;
+    }
+    if !staged.is_empty() {
+      // staging rows are addressed by index, so they are marked deleted like
+      // any other staged delete until the next merge
+      self.staging.batch(&vec![], staged)?;
+      self.staging.commit()?;
+    }
+    self.commit_meta()
+  }
+
+  /// Register a trigger that runs for matching rows in later batches.
+  pub fn add_trigger (&mut self, trigger: Trigger<P,V>) -> Result<(),Error> {
+    self.check_open()?;
+    if self.triggers.iter().any(|t| t.name == trigger.name) {
+      invalid!["trigger {} already exists", trigger.name];
+    }
+    self.triggers.push(trigger);
+    Ok(())
+  }
+
+  /// Unregister the trigger named `name`. Returns `false` if there is no such
+  /// trigger.
+  pub fn remove_trigger (&mut self, name: &str) -> bool {
+    match self.triggers.iter().position(|t| t.name == name) {
+      Some(i) => {
+        self.triggers.remove(i);
+        true
+      },
+      None => false
+    }
+  }
+
+  /// Return the names of the registered triggers.
+  pub fn triggers (&self) -> Vec<&str> {
+    self.triggers.iter().map(|t| t.name.as_str()).collect()
+  }
+
+  /// Read up to `limit` outbox events starting at `offset`. Start at `0` and
+  /// continue from the `next` offset of the last event read.
+  pub fn read_outbox (&mut self, offset: u64, limit: usize)
+  -> Result<Vec<OutboxEvent<P,V>>,Error> {
+    self.check_open()?;
+    self.outbox_store()?.read(offset, limit)
+  }
+
+  /// Return the outbox offset that `consumer` acknowledged last, or `0`.
+  pub fn outbox_offset (&self, consumer: &str) -> u64 {
+    self.meta.consumers.iter()
+      .find(|(name,_)| name == consumer)
+      .map(|(_,offset)| *offset)
+      .unwrap_or(0)
+  }
+
+  /// Read up to `limit` outbox events that `consumer` hasn't acknowledged.
+  ///
+  /// Polling doesn't move the consumer forward: handle the events, then pass
+  /// the `next` offset of the last one to `ack_outbox()`. After a crash the
+  /// consumer resumes at its last acknowledged offset, so events are
+  /// delivered again until they are acknowledged. Use each event's `offset`
+  /// as an idempotency key to apply side effects exactly once.
+  pub fn poll_outbox (&mut self, consumer: &str, limit: usize)
+  -> Result<Vec<OutboxEvent<P,V>>,Error> {
+    let offset = self.outbox_offset(consumer);
+    self.read_outbox(offset, limit)
+  }
+
+  /// Persist `offset` as the position of `consumer` in the outbox.
+  pub fn ack_outbox (&mut self, consumer: &str, offset: u64) -> Result<(),Error> {
+    self.check_writable()?;
+    if consumer.len() > u16::MAX as usize {
+      invalid!["consumer name too long"];
+    }
+    if self.fields.check_conflicts {
+      self.check_sequence()?;
+    }
+    match self.meta.consumers.iter_mut().find(|(name,_)| name == consumer) {
+      Some(c) => c.1 = offset,
+      None => self.meta.consumers.push((consumer.to_string(),offset))
+    }
+    let r = self.commit_meta();
+    self.poison_on_err(r)
+  }
+
+  fn outbox_store (&mut self) -> Result<&mut Outbox<S>,Error> {
+    if self.outbox.is_none() {
+      let store = (self.open_store)("outbox")?;
+      self.outbox = Some(Outbox::open(store, !self.fields.read_only)?);
+    }
+    Ok(self.outbox.as_mut().unwrap())
+  }
+
+  // outbox records for the rows that match outbox triggers
+  fn outbox_events (&self, rows: &[Row<P,V>]) -> Result<Vec<u8>,Error> {
+    let mut events = vec![];
+    for row in rows.iter() {
+      let (p,v) = match row {
+        Row::Insert(p,v) => (p,v),
+        Row::Update(_,p,v) => (p,v),
+        Row::Delete(_) => continue
+      };
+      for trigger in self.triggers.iter() {
+        if let TriggerAction::Outbox = trigger.action {
+          if trigger.matches(p,v) {
+            events.push((trigger.name.as_str(),p,v));
+          }
+        }
+      }
+    }
+    Outbox::<S>::encode(&events)
+  }
+
+  // run the callbacks of matching triggers once their batch has committed
+  fn fire_callbacks (&mut self, rows: &[Row<P,V>]) {
+    for row in rows.iter() {
+      let (p,v) = match row {
+        Row::Insert(p,v) => (p,v),
+        Row::Update(_,p,v) => (p,v),
+        Row::Delete(_) => continue
+      };
+      for trigger in self.triggers.iter_mut() {
+        if !trigger.matches(p,v) { continue }
+        if let TriggerAction::Callback(f) = &mut trigger.action {
+          match &mut self.hold {
+            Some(hold) => {
+              hold.effects.push(Effect::Callback(trigger.name.clone(), *p, v.clone()));
+            },
+            None => f(&trigger.name, p, v)
+          }
+        }
+      }
+    }
+  }
+
+  fn batch_rows (&mut self, rows: &[Row<P,V>]) -> Result<(),Error> {
+    self.write_rows(rows, self.fields.realtime_deadline.is_some())
+  }
+
+  // with `defer`, rows stay in staging instead of being built into trees
+  pub(crate) fn write_rows (&mut self, rows: &[Row<P,V>], defer: bool)
+  -> Result<(),Error> {
+    let mut inserts: Vec<(P,V)> = rows.iter()
+      .filter(|r| matches![r, Row::Insert(..)])
       .map(|r| match r {
         Row::Insert(p,v) => (p.clone(),v.clone()),
         _ => panic!["unexpected non-insert row type"]
       })
       .collect();
     let mut deletes: Vec<Location> = rows.iter()
-      .filter(|r| match r { Row::Delete(_loc) => true, _ => false })
+      .filter(|r| matches![r, Row::Delete(_)])
       .map(|r| match r {
         Row::Delete(loc) => *loc,
         _ => panic!["unexpected non-delete row type"]
       })
       .collect();
-    let n = (self.staging.inserts.try_borrow()?.len()+inserts.len()) as u64;
-    let ndel = (self.staging.deletes.try_borrow()?.len()+deletes.len()) as u64;
+    let updates: Vec<(Location,P,V)> = rows.iter()
+      .filter_map(|r| match r {
+        Row::Update(loc,p,v) => Some((*loc,*p,v.clone())),
+        _ => None
+      })
+      .collect();
+    if !updates.is_empty() {
+      let (live,gone): (Vec<_>,Vec<_>) = {
+        let delete_set = self.staging.delete_set.read_lock()?;
+        updates.into_iter().partition(|u| !delete_set.contains(&u.0))
+      };
+      inserts.extend(gone.into_iter().map(|(_,p,v)| (p,v)));
+      let (staged,live): (Vec<_>,Vec<_>) = live.into_iter()
+        .partition(|u| (u.0).0 == 0);
+      let slen = self.staging.inserts.read_lock()?.len();
+      let (staged,missing): (Vec<_>,Vec<_>) = staged.into_iter()
+        .partition(|u| ((u.0).1 as usize) < slen);
+      inserts.extend(missing.into_iter().map(|(_,p,v)| (p,v)));
+      self.staging.replace(&staged.into_iter()
+        .map(|((_,i),p,v)| (i,p,v)).collect::<Vec<_>>())?;
+      let moved = {
+        let mut dstore = self.data_store.write_lock()?;
+        let moved = dstore.replace(&live)?;
+        if moved.len() < live.len() {
+          dstore.commit()?;
+        }
+        moved
+      };
+      for (loc,p,v) in moved {
+        deletes.push(loc);
+        inserts.push((p,v));
+      }
+    }
+    let n = (self.staging.inserts.read_lock()?.len()+inserts.len()) as u64;
+    let ndel = (
+      self.staging.deletes.read_lock()?.iter().filter(|loc| loc.0 != 0).count()
+      + deletes.iter().filter(|loc| loc.0 != 0).count()
+    ) as u64;
     let base = self.fields.base_size as u64;
     if ndel >= base && n <= base {
-      deletes.extend_from_slice(&self.staging.deletes.try_borrow()?);
-      let mut dstore = self.data_store.try_borrow_mut()?;
-      dstore.delete(&deletes)?;
-      dstore.commit()?;
-      self.staging.batch(&inserts, &vec![])?;
-      self.staging.delete(&deletes)?;
+      // stage the whole batch before writing the data store, so that a crash
+      // in between leaves the deletes staged instead of dropping them while
+      // keeping the inserts
+      self.staging.batch(&inserts, &deletes)?;
+      self.staging.commit()?;
+      let (staged,blocks): (Vec<Location>,Vec<Location>) = self.staging
+        .deletes.read_lock()?.iter().partition(|loc| loc.0 == 0);
+      {
+        let mut dstore = self.data_store.write_lock()?;
+        dstore.delete(&blocks)?;
+        dstore.commit()?;
+      }
+      // staged rows are addressed by index, so their deletes stay staged until
+      // the rows are merged into a tree
       self.staging.clear_deletes()?;
+      self.staging.batch(&vec![], &staged)?;
       self.staging.commit()?;
+      self.commit_meta()?;
       return Ok(())
-    } else if n <= base {
+    } else if n <= base || defer {
       self.staging.batch(&inserts, &deletes)?;
       self.staging.commit()?;
+      self.commit_meta()?;
       return Ok(())
     }
-    let count = (n/base)*base;
+    // staged rows deleted earlier or in this batch are dropped here instead of
+    // being merged into a tree
+    let (staged_deletes,mut deletes): (Vec<Location>,Vec<Location>) = deletes
+      .into_iter().partition(|loc| loc.0 == 0);
+    let staged: Vec<(P,V)> = {
+      let delete_set = self.staging.delete_set.read_lock()?;
+      let staged_deletes: HashSet<Location> = staged_deletes.into_iter()
+        .collect();
+      self.staging.inserts.read_lock()?.iter().enumerate()
+        .filter(|(i,_)| {
+          let loc = (0,*i as u32);
+          !delete_set.contains(&loc) && !staged_deletes.contains(&loc)
+        })
+        .map(|(_,row)| row.clone())
+        .collect()
+    };
+    deletes.extend(self.staging.deletes.read_lock()?.iter()
+      .filter(|loc| loc.0 != 0));
+    let n = (staged.len()+inserts.len()) as u64;
+    let absorb = self.absorb_target(inserts.len(), n)?;
+    // an absorbing tree takes every row, so nothing is left in staging
+    let count = if absorb.is_some() { n } else { (n/base)*base };
     let rem = n - count;
     let mut mask = vec![];
     for tree in self.trees.iter_mut() {
-      mask.push(!tree.try_borrow_mut()?.is_empty()?);
+      mask.push(!tree.write_lock()?.is_empty()?);
     }
-    let p = plan(
-      &bits::num_to_bits(n/base),
-      &mask
-    );
+    let p = match absorb {
+      Some(_) => vec![],
+      None => plan(&bits::num_to_bits(n/base), &mask)
+    };
     let mut offset = 0;
-    let slen = self.staging.inserts.try_borrow()?.len();
+    let slen = staged.len();
+    let mut merged = vec![];
+    if let Some((src,dst)) = absorb {
+      let mut srows = staged.clone();
+      srows.extend(inserts.iter().cloned());
+      self.create_tree(dst)?;
+      for _ in self.meta.mask.len()..dst+1 {
+        self.meta.mask.push(false);
+      }
+      self.meta.mask[dst] = true;
+      self.meta.mask[src] = false;
+      merged.push(src);
+      Tree::merge(&mut self.trees, dst, vec![src], &srows)?;
+      offset = srows.len();
+    }
     for (i,staging,trees) in p {
       let mut irows: Vec<(usize,usize)> = vec![];
       for j in staging {
@@ -420,58 +1567,472 @@ P: Point, V: Value {
       for (i,j) in irows {
         for k in i..j {
           srows.push(
-            if k < slen { self.staging.inserts.try_borrow()?[k].clone() }
+            if k < slen { staged[k].clone() }
             else { inserts[k-slen].clone() }
           );
         }
       }
       if trees.is_empty() {
         self.meta.mask[i] = true;
-        self.trees[i].try_borrow_mut()?.build(&srows)?;
+        self.trees[i].write_lock()?.build(&srows)?;
       } else {
         self.meta.mask[i] = true;
         for t in trees.iter() {
           self.meta.mask[*t] = false;
         }
+        merged.extend_from_slice(&trees);
         Tree::merge(&mut self.trees, i, trees, &srows)?;
       }
     }
+    if let Some(max) = self.fields.max_trees {
+      let forced = self.consolidate(max, &merged)?;
+      merged.extend(forced);
+    }
     ensure_eq!(n-(offset as u64), rem, "offset-n ({}-{}={}) != rem ({}) ",
       offset, n, (offset as u64)-n, rem);
     let mut rem_rows = vec![];
     for k in offset..n as usize {
       rem_rows.push(
-        if k < slen { self.staging.inserts.try_borrow()?[k].clone() }
+        if k < slen { staged[k].clone() }
         else { inserts[k-slen].clone() }
       );
     }
     ensure_eq!(rem_rows.len(), rem as usize,
       "unexpected number of remaining rows (expected {}, actual {})",
       rem, rem_rows.len());
-    deletes.extend_from_slice(&self.staging.deletes.try_borrow()?);
     self.staging.clear()?;
     self.staging.batch(&rem_rows, &vec![])?;
-    self.staging.delete(&deletes)?;
     self.staging.commit()?;
-    if !deletes.is_empty() {
-      let mut dstore = self.data_store.try_borrow_mut()?;
-      dstore.delete(&deletes)?;
+    {
+      // the new trees point at these blocks, so they reach storage before meta
+      let mut dstore = self.data_store.write_lock()?;
+      if !deletes.is_empty() {
+        dstore.delete(&deletes)?;
+      }
       dstore.commit()?;
     }
-    self.meta.save()?;
+    self.meta.ingested += offset as u64;
+    self.check_tuning()?;
+    self.commit_meta()?;
+    // merged trees stay readable until the new trees are committed
+    for t in merged {
+      self.trees[t].write_lock()?.clear()?;
+    }
     Ok(())
   }
 
-  fn create_tree (&mut self, index: usize) -> Result<(),Error> {
-    for i in self.trees.len()..index+1 {
-      let store = (self.open_store)(&format!("tree{}",i))?;
-      self.trees.push(Rc::new(RefCell::new(Tree::open(TreeOpts {
-        store,
-        index,
-        data_store: Rc::clone(&self.data_store),
-        branch_factor: self.fields.branch_factor,
-        max_data_size: self.fields.max_data_size,
-      })?)));
+  fn stored_sequence (&self) -> Result<u64,Error> {
+    // open the meta file again so that stores which cache their length see
+    // writes from other handles
+    Ok(Meta::open((self.open_store)("meta")?)?.sequence)
+  }
+
+  fn check_sequence (&mut self) -> Result<(),Error> {
+    let found = self.stored_sequence()?;
+    if found != self.meta.sequence {
+      return Err(Conflict { expected: self.meta.sequence, found }.into());
+    }
+    Ok(())
+  }
+
+  // writers check the sequence before they write to shared stores as well,
+  // and this catches one that committed in between
+  fn commit_meta (&mut self) -> Result<(),Error> {
+    if self.fields.check_conflicts {
+      self.check_sequence()?;
+    }
+    self.sync_quarantine()?;
+    self.meta.sequence += 1;
+    self.meta.save()
+  }
+
+  /// Return the offsets of data blocks that are quarantined.
+  ///
+  /// When a query finds a data block that can't be read or parsed, the block
+  /// is quarantined: the block is skipped, and later queries won't try to
+  /// read it again. Quarantined blocks are counted in
+  /// `Stats::quarantined_blocks` and passed to the callback of
+  /// `set_quarantine_callback()`. The list is persisted in the meta store on
+  /// the next `batch()` or `save_quarantine()`.
+  pub fn quarantined (&self) -> Result<Vec<u64>,Error> {
+    let mut offsets: Vec<u64> = self.data_store.read_lock()?
+      .quarantine.iter().cloned().collect();
+    offsets.sort_unstable();
+    Ok(offsets)
+  }
+
+  /// Call `f` with the offset of each data block that gets quarantined and
+  /// the error that reading it failed with, replacing any earlier callback.
+  /// Use it to log or alert on damaged blocks, which queries skip silently.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut db: DB<_,_,(f32,f32),u32> = DB::open(storage)?;
+  /// db.set_quarantine_callback(|offset, err| {
+  ///   eprintln!["quarantined data block at offset {}: {}", offset, err];
+  /// })?;
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn set_quarantine_callback<F> (&mut self, f: F) -> Result<(),Error> where
+  F: Fn(u64,&Error) + Send + Sync + 'static {
+    self.data_store.write_lock()?.on_quarantine = Some(Arc::new(f));
+    Ok(())
+  }
+
+  /// Stop calling the callback set with `set_quarantine_callback()`.
+  pub fn clear_quarantine_callback (&mut self) -> Result<(),Error> {
+    self.data_store.write_lock()?.on_quarantine = None;
+    Ok(())
+  }
+
+  /// Manually quarantine the data block at `offset` and persist the list.
+  pub fn quarantine (&mut self, offset: u64) -> Result<(),Error> {
+    self.check_writable()?;
+    self.data_store.write_lock()?.quarantine_block(
+      offset, &Error::Other("quarantined manually".into())
+    );
+    self.save_quarantine()
+  }
+
+  /// Remove the data block at `offset` from the quarantine list (for example,
+  /// after it has been repaired) and persist the list.
+  pub fn release_quarantine (&mut self, offset: u64) -> Result<(),Error> {
+    self.check_writable()?;
+    self.data_store.write_lock()?.quarantine.remove(&offset);
+    self.save_quarantine()
+  }
+
+  /// Persist blocks that were quarantined during queries to the meta store.
+  pub fn save_quarantine (&mut self) -> Result<(),Error> {
+    self.check_writable()?;
+    if self.fields.check_conflicts {
+      self.check_sequence()?;
+    }
+    let r = self.commit_meta();
+    self.poison_on_err(r)
+  }
+
+  fn sync_quarantine (&mut self) -> Result<(),Error> {
+    self.meta.quarantine = self.quarantined()?;
+    Ok(())
+  }
+
+  /// Return the maintenance jobs that are waiting to run. History pruning and
+  /// compaction wait for a backup in progress to finish.
+  pub fn pending_maintenance (&mut self) -> Result<Vec<Job>,Error> {
+    let mut jobs = vec![];
+    if self.quarantined()? != self.meta.quarantine {
+      jobs.push(Job::SaveQuarantine);
+    }
+    if self.debt()? > 0 {
+      jobs.push(Job::BuildTrees);
+    }
+    // a backup is copying the stores that these jobs rewrite
+    if self.data_store.read_lock()?.journal.is_active()? {
+      return Ok(jobs);
+    }
+    if self.history_to_prune()? > 0 {
+      jobs.push(Job::PruneHistory);
+    }
+    if let Some(ratio) = self.fields.compact_ratio {
+      let usage = self.disk_usage()?;
+      let data = usage.data_live + usage.data_dead;
+      if usage.data_dead > 0 && usage.data_dead as f64 > ratio * data as f64 {
+        jobs.push(Job::Compact);
+      }
+    }
+    Ok(jobs)
+  }
+
+  /// Run pending maintenance jobs until there are none left or `budget` has
+  /// elapsed on the database clock. At least one job runs when any are
+  /// pending.
+  ///
+  /// Maintenance needs the handle mutably, so it runs on whichever task or
+  /// thread holds the handle. Call this between requests or from a periodic
+  /// tick, or let `AsyncDB::start_maintenance()` schedule it on an executor:
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// use std::time::Duration;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut db: DB<_,_,((f32,f32),(f32,f32)),u32> = DB::open(storage)?;
+  /// let report = db.run_maintenance(Duration::from_millis(5))?;
+  /// if report.remaining > 0 {
+  ///   // schedule another slice soon
+  /// }
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn run_maintenance (&mut self, budget: Duration)
+  -> Result<MaintenanceReport,Error> {
+    self.check_writable()?;
+    let clock = Arc::clone(&self.fields.clock);
+    let start = clock.now();
+    let mut report = MaintenanceReport::default();
+    let mut jobs = self.pending_maintenance()?;
+    jobs.reverse();
+    while let Some(job) = jobs.pop() {
+      if !report.completed.is_empty() && clock.now() - start >= budget {
+        jobs.push(job);
+        break;
+      }
+      self.run_job(job)?;
+      report.completed.push(job);
+    }
+    report.remaining = jobs.len();
+    report.elapsed = clock.now() - start;
+    Ok(report)
+  }
+
+  fn run_job (&mut self, job: Job) -> Result<(),Error> {
+    match job {
+      Job::SaveQuarantine => self.save_quarantine(),
+      Job::BuildTrees => self.build_staged(),
+      Job::PruneHistory => self.prune_history().map(|_| ()),
+      Job::Compact => self.compact().map(|_| ())
+    }
+  }
+
+  /// Flush the database and close the handle.
+  ///
+  /// Pending maintenance that keeps data in memory, saving quarantined blocks
+  /// and building staged rows into trees, runs to completion and every store
+  /// is synced. Any further operation on the handle fails with a `Closed`
+  /// error. Calling `close()` again is a no-op.
+  ///
+  /// A poisoned or read-only handle is closed without writing anything.
+  ///
+  /// Dropping a handle closes it too, but errors are ignored there, so call
+  /// `close()` when you need to know that everything reached storage.
+  pub fn close (&mut self) -> Result<(),Error> {
+    if self.closed { return Ok(()) }
+    if self.poisoned.is_some() || self.fields.read_only {
+      self.close_subscriptions();
+      self.closed = true;
+      self.writer_lock = None;
+      return Ok(())
+    }
+    for job in self.pending_maintenance()? {
+      if let Job::SaveQuarantine | Job::BuildTrees = job {
+        self.run_job(job)?;
+      }
+    }
+    self.staging.commit()?;
+    self.data_store.write_lock()?.commit()?;
+    for tree in self.trees.iter() {
+      tree.write_lock()?.commit()?;
+    }
+    self.meta.sync_all()?;
+    if let Some(log) = &mut self.change_log {
+      log.sync_all()?;
+    }
+    if let Some(outbox) = &mut self.outbox {
+      outbox.sync_all()?;
+    }
+    for view in self.views.iter_mut() {
+      view.sync_all()?;
+    }
+    if let Some(wal) = &mut self.wal {
+      wal.sync_all()?;
+    }
+    self.close_subscriptions();
+    self.closed = true;
+    self.writer_lock = None;
+    Ok(())
+  }
+
+  /// Return whether `close()` has been called on this handle.
+  pub fn is_closed (&self) -> bool {
+    self.closed
+  }
+
+  fn check_open (&self) -> Result<(),Error> {
+    if self.closed { return Err(Closed.into()) }
+    if let Some(reason) = &self.poisoned {
+      return Err(Poisoned { reason: reason.clone() }.into());
+    }
+    Ok(())
+  }
+
+  fn check_writable (&self) -> Result<(),Error> {
+    self.check_open()?;
+    if self.fields.read_only { return Err(ReadOnly.into()) }
+    Ok(())
+  }
+
+  /// Return the heat score of every data block that queries have read, hottest
+  /// first, as `(offset,score)` pairs. The list is empty unless the database
+  /// was opened with `Setup::track_heat()`.
+  ///
+  /// Scores live in memory and start over when the database is opened.
+  pub fn block_heat (&self) -> Result<Vec<(u64,f64)>,Error> {
+    let dstore = self.data_store.read_lock()?;
+    Ok(match &dstore.heat {
+      Some(heat) => heat.snapshot(dstore.clock.now()),
+      None => vec![]
+    })
+  }
+
+  /// Return the format that each tree was written with, indexed by tree
+  /// level, or `None` for empty trees.
+  pub fn tree_formats (&self) -> Result<Vec<Option<TreeFormat>>,Error> {
+    let mut formats = Vec::with_capacity(self.trees.len());
+    for tree in self.trees.iter() {
+      let mut t = tree.write_lock()?;
+      formats.push(if t.is_empty()? { None } else { Some(t.format()?) });
+    }
+    Ok(formats)
+  }
+
+  /// Load tree `index` into an in-memory image so that queries read its
+  /// branches without going through the store.
+  ///
+  /// This suits the largest trees, which hold most of the data and are only
+  /// rewritten when smaller trees are merged into them. Newer trees keep using
+  /// the regular read path. The image is dropped when the tree is next
+  /// rebuilt. It isn't persisted or memory-mapped, so freeze trees again
+  /// after opening.
+  /// Returns `false` if there is no tree at `index` or the tree is empty.
+  pub fn freeze_tree (&mut self, index: usize) -> Result<bool,Error> {
+    self.check_open()?;
+    let tree = match self.trees.get(index) {
+      Some(tree) => tree,
+      None => return Ok(false)
+    };
+    let mut t = tree.write_lock()?;
+    t.freeze()?;
+    Ok(t.frozen().is_some())
+  }
+
+  /// Drop the frozen image of tree `index`, if there is one.
+  pub fn thaw_tree (&mut self, index: usize) -> Result<(),Error> {
+    if let Some(tree) = self.trees.get(index) {
+      tree.write_lock()?.thaw();
+    }
+    Ok(())
+  }
+
+  /// Return the indexes of the trees that currently have a frozen image.
+  pub fn frozen_trees (&self) -> Result<Vec<usize>,Error> {
+    let mut frozen = vec![];
+    for (i,tree) in self.trees.iter().enumerate() {
+      if tree.read_lock()?.frozen().is_some() {
+        frozen.push(i);
+      }
+    }
+    Ok(frozen)
+  }
+
+  /// Return the number of bytes used by each part of the database.
+  ///
+  /// Data blocks are split into live blocks that a tree references and dead
+  /// blocks that don't, such as blocks left behind by tree merges. Finding the
+  /// live blocks reads every branch block of every tree.
+  pub fn disk_usage (&mut self) -> Result<DiskUsage,Error> {
+    self.check_open()?;
+    let (staging_inserts,staging_deletes) = self.staging.store_bytes()?;
+    let mut usage = DiskUsage {
+      meta: self.meta.bytes()?,
+      staging_inserts,
+      staging_deletes,
+      ..Default::default()
+    };
+    let mut offsets = HashSet::new();
+    for tree in self.trees.iter() {
+      let mut t = tree.write_lock()?;
+      usage.trees.push(t.store.len()?);
+      offsets.extend(t.data_offsets()?);
+    }
+    let mut dstore = self.data_store.write_lock()?;
+    let (data,range) = dstore.store_bytes()?;
+    for offset in offsets {
+      usage.data_live += dstore.block_size(offset)?;
+    }
+    usage.data_dead = data.saturating_sub(usage.data_live);
+    usage.range = range;
+    Ok(usage)
+  }
+
+  // With `Setup::absorb()`, pick the smallest tree to take a flush of `n` rows
+  // caused by a batch of `inserts` rows, and the free slot it moves to: the
+  // lowest one whose size class holds the tree's rows and the new ones.
+  fn absorb_target (&mut self, inserts: usize, n: u64)
+  -> Result<Option<(usize,usize)>,Error> {
+    match self.fields.absorb_batch_size {
+      Some(max) if inserts <= max => {},
+      _ => return Ok(None)
+    }
+    let src = match self.meta.mask.iter().position(|m| *m) {
+      Some(i) => i,
+      None => return Ok(None)
+    };
+    let rows = self.trees[src].write_lock()?.count_rows()? + n;
+    if rows > self.fields.absorb_tree_size as u64 { return Ok(None) }
+    let base = self.fields.base_size as u64;
+    let mut dst = 0;
+    while (base << dst) < rows {
+      dst += 1;
+    }
+    while dst == src || self.meta.mask.get(dst) == Some(&true) {
+      dst += 1;
+    }
+    Ok(Some((src,dst)))
+  }
+
+  // Merge the smallest trees into the first free slot above them so that at
+  // most `max` trees are left. Returns the merged trees, which stay readable
+  // until the meta store is committed. Slots in `merged` are still in use.
+  fn consolidate (&mut self, max: usize, merged: &[usize])
+  -> Result<Vec<usize>,Error> {
+    let live: Vec<usize> = self.meta.mask.iter().enumerate()
+      .filter(|(_,m)| **m).map(|(i,_)| i).collect();
+    if live.len() <= max { return Ok(vec![]) }
+    let src = live[0..=live.len()-max].to_vec();
+    let mut dst = src[src.len()-1]+1;
+    while self.meta.mask.get(dst) == Some(&true) || merged.contains(&dst) {
+      dst += 1;
+    }
+    self.create_tree(dst)?;
+    for _ in self.meta.mask.len()..dst+1 {
+      self.meta.mask.push(false);
+    }
+    self.meta.mask[dst] = true;
+    for t in src.iter() {
+      self.meta.mask[*t] = false;
+    }
+    Tree::merge(&mut self.trees, dst, src.clone(), &vec![])?;
+    Ok(src)
+  }
+
+  fn create_tree (&mut self, index: usize) -> Result<(),Error> {
+    for i in self.trees.len()..index+1 {
+      let store = (self.open_store)(&format!("tree{}",i))?;
+      self.trees.push(Arc::new(RwLock::new(Tree::open(TreeOpts {
+        store,
+        index,
+        data_store: Arc::clone(&self.data_store),
+        branch_factor: self.fields.branch_factor,
+        max_data_size: self.fields.max_data_size,
+        retry: self.fields.retry.clone(),
+        clock: Arc::clone(&self.fields.clock),
+        format: self.fields.tree_format,
+        coalesce_span: self.fields.coalesce_span,
+      })?)));
     }
     Ok(())
   }
@@ -487,8 +2048,7 @@ P: Point, V: Value {
   /// that you can step through like this:
   ///
   /// ```rust,no_run
-  /// # use eyros::DB;
-  /// # use failure::Error;
+  /// # use eyros::{DB,Error};
   /// # use std::path::PathBuf;
   /// # use random_access_disk::RandomAccessDisk;
   /// # fn main () -> Result<(),Error> {
@@ -499,7 +2059,7 @@ P: Point, V: Value {
   ///   // ...
   /// }
   /// # Ok(()) }
-  /// # fn storage(name: &str) -> Result<RandomAccessDisk,Error> {
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
   /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
   /// #   p.push(name);
   /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
@@ -509,68 +2069,557 @@ P: Point, V: Value {
   /// If you want to delete records, you will need to use the `Location` records
   /// you get from a query. However, these locations are only valid until the
   /// next `.batch()`.
+  ///
+  /// Data blocks are skipped or reordered according to the pruner set with
+  /// `set_pruner()`, if any.
   pub fn query<'b> (&mut self, bbox: &'b P::Bounds)
   -> Result<QueryIterator<'b,S,P,V>,Error> {
+    self.check_open()?;
+    let permit = self.admit()?;
+    let audit = self.audit_query(bbox);
+    Ok(self.query_admitted(bbox, CacheMode::Normal, permit, true)?.audit(audit))
+  }
+
+  /// Query the database like `query()`, but without inserting blocks into or
+  /// promoting blocks within the data caches.
+  ///
+  /// Use this for one-shot scans such as full exports so that they don't evict
+  /// the blocks that regular queries are keeping hot.
+  pub fn scan<'b> (&mut self, bbox: &'b P::Bounds)
+  -> Result<QueryIterator<'b,S,P,V>,Error> {
+    self.query_mode(bbox, CacheMode::Bypass, Some(AuditOp::Query))
+  }
+
+  /// Query the database like `query()` after making sure that this handle is
+  /// as up to date as `level` requires, reloading from storage if necessary.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Consistency,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut writer: DB<_,_,((f32,f32),(f32,f32)),u32> = DB::open(storage)?;
+  /// # let mut replica: DB<_,_,((f32,f32),(f32,f32)),u32> = DB::open(storage)?;
+  /// let token = writer.sequence();
+  /// let bbox = ((-0.5,-0.8),(0.3,-0.5));
+  /// for result in replica.query_consistent(&bbox, Consistency::ReadYourWrites(token))? {
+  ///   let (point,value,location) = result?;
+  ///   // ...
+  /// }
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn query_consistent<'b> (&mut self, bbox: &'b P::Bounds,
+  level: Consistency) -> Result<QueryIterator<'b,S,P,V>,Error> {
+    self.check_open()?;
+    match level {
+      Consistency::Local => {},
+      Consistency::BoundedStaleness { max_lag } => {
+        let stored = self.stored_sequence()?;
+        if stored.saturating_sub(self.meta.sequence) > max_lag {
+          self.reload()?;
+        }
+      },
+      Consistency::ReadYourWrites(required) => {
+        if self.meta.sequence < required {
+          self.reload()?;
+        }
+        if self.meta.sequence < required {
+          return Err(Stale { sequence: self.meta.sequence, required }.into());
+        }
+      }
+    }
+    self.query(bbox)
+  }
+
+  /// Return the number of queries holding a slot under
+  /// `Setup::max_queries()`, or `0` when queries are unlimited.
+  pub fn queries_in_flight (&self) -> usize {
+    self.gate.as_ref().map(|g| g.active()).unwrap_or(0)
+  }
+
+  fn admit (&self) -> Result<Option<Permit>,Error> {
+    match &self.gate {
+      Some(gate) => Ok(Some(Gate::try_acquire(gate)?)),
+      None => Ok(None)
+    }
+  }
+
+  pub(crate) fn gate (&self) -> Option<&Arc<Gate>> {
+    self.gate.as_ref()
+  }
+
+  // rows read for the caller are reported to the audit callback as `op`,
+  // rows read on behalf of a write (`op` of `None`) aren't
+  pub(crate) fn query_mode<'b> (&mut self, bbox: &P::Bounds, mode: CacheMode,
+  op: Option<AuditOp>) -> Result<QueryIterator<'b,S,P,V>,Error> {
+    self.check_open()?;
+    let permit = self.admit()?;
+    let audit = op.and_then(|op| self.audit_read(op, Some(bbox)));
+    Ok(self.query_admitted(bbox, mode, permit, false)?.audit(audit))
+  }
+
+  pub(crate) fn query_admitted<'b> (&mut self, bbox: &P::Bounds,
+  mode: CacheMode, permit: Option<Permit>, prune: bool)
+  -> Result<QueryIterator<'b,S,P,V>,Error> {
+    let pruner = if prune { self.pruner.clone() } else { None };
+    self.query_pruned(bbox, mode, permit, pruner)
+  }
+
+  pub(crate) fn query_pruned<'b> (&mut self, bbox: &P::Bounds,
+  mode: CacheMode, permit: Option<Permit>, pruner: Option<Arc<dyn Pruner<P,V>>>)
+  -> Result<QueryIterator<'b,S,P,V>,Error> {
+    self.check_open()?;
+    let timer = Timer::new(self.counters()?, Arc::clone(&self.fields.clock));
+    let prune = match pruner {
+      Some(pruner) => Some(Arc::new(PruneState::load(
+        pruner, &mut *self.data_store.write_lock()?)?)),
+      None => None
+    };
     let mut mask: Vec<bool> = vec![];
     for tree in self.trees.iter_mut() {
-      mask.push(!tree.try_borrow_mut()?.is_empty()?);
+      mask.push(!tree.write_lock()?.is_empty()?);
     }
-    let mut queries = Vec::with_capacity(1+self.trees.len());
-    queries.push(SubIterator::Staging(self.staging.query(bbox)));
+    let axes = if self.fields.selectivity_order { P::bounds_axes(bbox) } else { None };
+    let stats = match &axes {
+      Some(_) => self.selectivity()?,
+      None => vec![None;self.trees.len()]
+    };
+    let mut trees = Vec::with_capacity(self.trees.len());
     for (i,tree) in self.trees.iter_mut().enumerate() {
       if !mask[i] { continue }
-      queries.push(SubIterator::Tree(Tree::query(Rc::clone(tree),bbox)?));
+      let (iter,estimate) = match (&axes,&stats[i]) {
+        (Some(axes),Some(s)) => (
+          Tree::query(Arc::clone(tree),bbox)?.order(Some(Arc::clone(s)), axes.clone()),
+          s.estimate(axes)
+        ),
+        _ => (Tree::query(Arc::clone(tree),bbox)?, 0.0)
+      };
+      trees.push((estimate, iter.cache_mode(mode).prune(prune.clone()).slot(i)));
+    }
+    // trees with the most expected results first
+    trees.sort_by(|a,b| b.0.total_cmp(&a.0));
+    let mut queries = Vec::with_capacity(1+trees.len());
+    queries.push(SubIterator::Staging(self.staging.query(bbox)));
+    queries.extend(trees.into_iter().map(|(_,iter)| SubIterator::Tree(Box::new(iter))));
+    let mut iter = QueryIterator::new(queries, Arc::clone(&self.staging.delete_set))?;
+    iter.permit = permit;
+    iter.open_trees = self.fields.max_open_trees;
+    iter.buffered_blocks = self.fields.max_buffered_blocks;
+    iter.timer = Some(timer);
+    iter.clock = Arc::clone(&self.fields.clock);
+    iter.bbox = Some(*bbox);
+    iter.sequence = Some(self.meta.sequence);
+    Ok(iter.visibility(self.visible.clone()))
+  }
+
+  /// Query the database like `query()`, but yield `SharedRow` results that
+  /// point into the block cache instead of cloning each value.
+  ///
+  /// Repeated queries over hot blocks then only bump a reference count per
+  /// result, which matters when values are large.
+  pub fn query_shared<'b> (&mut self, bbox: &'b P::Bounds)
+  -> Result<SharedQueryIterator<'b,S,P,V>,Error> {
+    Ok(SharedQueryIterator { iter: self.query(bbox)? })
+  }
+
+  /// Query the database like `query()`, but only yield rows whose value
+  /// passes `keep`.
+  ///
+  /// The predicate sees each value by reference where it sits in the staging
+  /// area or the block cache, so rows that fail it are skipped without being
+  /// cloned. Use this instead of `.filter()` on the results when most rows
+  /// are discarded:
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut db: DB<_,_,((f32,f32),(f32,f32)),u32> = DB::open(storage)?;
+  /// let bbox = ((-0.5,-0.8),(0.3,-0.5));
+  /// for result in db.query_filter(&bbox, |value| value % 10 == 0)? {
+  ///   let (point,value,location) = result?;
+  ///   // ...
+  /// }
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn query_filter<'b,F> (&mut self, bbox: &'b P::Bounds, keep: F)
+  -> Result<QueryIterator<'b,S,P,V>,Error> where
+  F: Fn(&V) -> bool + Send + Sync + 'static, P: 'static, V: 'static {
+    Ok(self.query(bbox)?.filter_values(keep))
+  }
+
+  /// Call `f` with each point and value that intersects the bounding box.
+  ///
+  /// Unlike `query()`, rows are passed by reference straight out of the
+  /// staging area and the block cache, so no result iterator or cloned rows
+  /// are allocated. Return `ControlFlow::Break(())` from the closure to stop
+  /// the traversal early:
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// use std::ops::ControlFlow;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut db: DB<_,_,((f32,f32),(f32,f32)),u32> = DB::open(storage)?;
+  /// let bbox = ((-0.5,-0.8),(0.3,-0.5));
+  /// let mut sum = 0u64;
+  /// let mut count = 0;
+  /// db.query_for_each(&bbox, |_point,value| {
+  ///   sum += *value as u64;
+  ///   count += 1;
+  ///   if count < 100 { ControlFlow::Continue(()) }
+  ///   else { ControlFlow::Break(()) }
+  /// })?;
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn query_for_each<F> (&mut self, bbox: &P::Bounds, mut f: F)
+  -> Result<(),Error> where F: FnMut (&P,&V) -> ControlFlow<()> {
+    self.check_open()?;
+    let _permit = self.admit()?;
+    // reported when this function returns, however it returns
+    let mut audit = self.audit_query(bbox);
+    let deletes = self.staging.delete_set.read_lock()?;
+    {
+      let inserts = self.staging.inserts.read_lock()?;
+      for (i,(point,value)) in inserts.iter().enumerate() {
+        if deletes.contains(&(0,i as u32)) { continue }
+        if !point.overlaps(bbox) || !self.is_visible(point,value) { continue }
+        if let Some(a) = &mut audit { a.rows += 1 }
+        if let ControlFlow::Break(()) = f(point,value) { return Ok(()) }
+      }
+    }
+    let visible = |point: &P, value: &V| self.is_visible(point,value);
+    let mut g = |point: &P, value: &V, loc: &Location| {
+      if deletes.contains(loc) || !visible(point,value) {
+        return ControlFlow::Continue(())
+      }
+      if let Some(a) = &mut audit { a.rows += 1 }
+      f(point,value)
+    };
+    for tree in self.trees.iter() {
+      if let ControlFlow::Break(()) = tree.write_lock()?.for_each(bbox, &mut g)? {
+        break;
+      }
     }
-    QueryIterator::new(queries, Rc::clone(&self.staging.delete_set))
+    Ok(())
+  }
+}
+
+impl<S,U,P,V> Drop for DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  fn drop (&mut self) {
+    // a call that didn't finish has no effects to set off
+    self.hold = None;
+    let _ = self.close();
   }
 }
 
 /// Iterator of `Result<(Point,Value,Location)>` data returned by `db.query()`.
+///
+/// Results are read lazily: data blocks are only loaded from storage as the
+/// iterator reaches them.
 pub struct QueryIterator<'b,S,P,V> where
-S: RandomAccess<Error=Error>, P: Point, V: Value {
+S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
   index: usize,
   queries: Vec<SubIterator<'b,S,P,V>>,
-  deletes: Rc<RefCell<HashSet<Location>>>
+  deletes: Arc<RwLock<HashSet<Location>>>,
+  limit: Option<usize>,
+  cancel: Option<CancelToken>,
+  clock: Arc<dyn Clock>,
+  deadline: Option<Duration>,
+  permit: Option<Permit>,
+  audit: Option<Audit<P>>,
+  visible: Option<VisibleFn<P,V>>,
+  open_trees: Option<usize>,
+  buffered_blocks: Option<usize>,
+  timer: Option<Timer>,
+  bbox: Option<P::Bounds>,
+  intersect: Intersect,
+  // commit the query started at, for cursors
+  sequence: Option<u64>,
+  // cursor saved when the query stopped early
+  resume: Option<QueryCursor<P>>
 }
 
 impl<'b,S,P,V> QueryIterator<'b,S,P,V> where
-S: RandomAccess<Error=Error>, P: Point, V: Value {
+S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
   pub fn new (queries: Vec<SubIterator<'b,S,P,V>>,
-  deletes: Rc<RefCell<HashSet<Location>>>) -> Result<Self,Error> {
-    Ok(Self { deletes, queries, index: 0 })
+  deletes: Arc<RwLock<HashSet<Location>>>) -> Result<Self,Error> {
+    Ok(Self {
+      deletes, queries, index: 0, limit: None, cancel: None,
+      clock: default_clock(), deadline: None, permit: None,
+      audit: None, visible: None, open_trees: None, buffered_blocks: None,
+      timer: None, bbox: None,
+      intersect: Intersect::Overlaps, sequence: None, resume: None
+    })
   }
-}
-
-impl<'b,S,P,V> Iterator for QueryIterator<'b,S,P,V> where
-S: RandomAccess<Error=Error>, P: Point, V: Value {
-  type Item = Result<(P,V,Location),Error>;
-  fn next (&mut self) -> Option<Self::Item> {
+  /// Stop after `n` results.
+  ///
+  /// Unlike `.take(n)`, a limited query drains the staging area and each tree
+  /// in turn instead of alternating between them, so it only reads as many
+  /// data blocks as it needs to produce `n` results.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut db: DB<_,_,((f32,f32),(f32,f32)),u32> = DB::open(storage)?;
+  /// let bbox = ((-0.5,-0.8),(0.3,-0.5));
+  /// let first: Vec<_> = db.query(&bbox)?.limit(100).collect::<Result<_,_>>()?;
+  /// assert![first.len() <= 100];
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn limit (mut self, n: usize) -> Self {
+    self.limit = Some(n);
+    self
+  }
+  /// Interleave results from at most `n` trees at a time. A tree joins once
+  /// an earlier one is done. Pass `1` to read the trees one after the other.
+  ///
+  /// Trees that haven't joined yet hold no branches or blocks, so together
+  /// with `max_buffered_blocks()` this keeps the memory of a query flat
+  /// however many trees it spans.
+  pub fn max_open_trees (mut self, n: usize) -> Self {
+    self.open_trees = Some(n.max(1));
+    self
+  }
+  /// Hold the rows of at most `n` data blocks across the trees that take
+  /// turns, instead of one block for each open tree.
+  ///
+  /// A tree that passes its turn while it holds a block past the limit
+  /// spills it: the tree keeps only the block offset and its next row, and
+  /// reads the block again through the block cache on its next turn. Spilled
+  /// blocks that were evicted from the cache in between are read from
+  /// storage again, so small limits trade reads for memory. For
+  /// `db.query_parallel()`, set the limit with `Setup::max_buffered_blocks()`:
+  /// workers then send copies of their rows ahead of a lagging consumer
+  /// instead of rows that hold their blocks.
+  pub fn max_buffered_blocks (mut self, n: usize) -> Self {
+    self.buffered_blocks = Some(n.max(1));
+    self
+  }
+  /// Return the number of data blocks whose rows the trees of this query
+  /// hold, not counting rows handed out by `db.query_shared()`.
+  pub fn buffered_blocks (&self) -> usize {
+    self.queries.iter().filter(|q| match q {
+      SubIterator::Tree(x) => x.holds_block(),
+      _ => false
+    }).count()
+  }
+  /// Skip rows whose value fails `keep` before they are cloned out of the
+  /// block cache. Unlike `.filter()`, rejected rows also don't count towards
+  /// `limit()`.
+  pub fn filter_values<F> (mut self, keep: F) -> Self where
+  F: Fn(&V) -> bool + Send + Sync + 'static, P: 'static, V: 'static {
+    self.visible = Some(match self.visible.take() {
+      Some(visible) => Arc::new(move |p: &P, v: &V| keep(v) && visible(p,v)),
+      None => Arc::new(move |_: &P, v: &V| keep(v)),
+    });
+    self
+  }
+  /// Only return rows that match the query's bounding box by `mode`, such as
+  /// intervals that lie within it or cover it. Rows are still found through
+  /// the trees by overlap, so other modes read as many blocks as the default,
+  /// but rows that don't match don't count towards `limit()`.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Intersect,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// // intervals of time along x, scalar values along y
+  /// let mut db: DB<_,_,((f32,f32),f32),u32> = DB::open(storage)?;
+  /// // rows whose interval covers t=5
+  /// let bbox = ((5.0,-1.0),(5.0,1.0));
+  /// let covering = db.query(&bbox)?.intersect(Intersect::Contains);
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn intersect (mut self, mode: Intersect) -> Self {
+    self.intersect = mode;
+    self
+  }
+  /// End the query without reading any more blocks once `token` is
+  /// cancelled. The next result is then a `Cancelled` error, after which the
+  /// iterator is done. Results produced before cancellation are unaffected,
+  /// and `cursor()` still resumes after the last of them.
+  pub fn cancel_on (mut self, token: CancelToken) -> Self {
+    self.cancel = Some(token);
+    self
+  }
+  /// End the query with a `TimedOut` error once `duration` has passed on the
+  /// database clock, counting from now.
+  ///
+  /// The deadline is checked before each row is read, so a query doesn't
+  /// read any more blocks after it passes. Use this to hold queries to a
+  /// per-request budget:
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Error};
+  /// # use std::path::PathBuf;
+  /// # use std::time::Duration;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut db: DB<_,_,((f32,f32),(f32,f32)),u32> = DB::open(storage)?;
+  /// let bbox = ((-0.5,-0.8),(0.3,-0.5));
+  /// for result in db.query(&bbox)?.timeout(Duration::from_millis(200)) {
+  ///   match result {
+  ///     Ok((point,value,location)) => { /* ... */ },
+  ///     Err(Error::TimedOut(_)) => break, // send the partial response
+  ///     Err(e) => return Err(e)
+  ///   }
+  /// }
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn timeout (self, duration: Duration) -> Self {
+    let deadline = self.clock.now() + duration;
+    self.deadline(deadline)
+  }
+  /// End the query with a `TimedOut` error once the database clock reaches
+  /// `deadline`, given as time since the unix epoch, such as a deadline
+  /// computed when a request came in.
+  pub fn deadline (mut self, deadline: Duration) -> Self {
+    self.deadline = Some(self.deadline.map_or(deadline, |d| d.min(deadline)));
+    self
+  }
+  pub(crate) fn audit (mut self, audit: Option<Audit<P>>) -> Self {
+    self.audit = audit;
+    self
+  }
+  pub(crate) fn visibility (mut self, visible: Option<VisibleFn<P,V>>) -> Self {
+    self.visible = visible;
+    self
+  }
+  // error to end the query with, if it was cancelled or passed its deadline
+  fn interrupted (&self) -> Option<Error> {
+    if self.cancel.as_ref().map(|c| c.is_cancelled()).unwrap_or(false) {
+      return Some(Cancelled.into());
+    }
+    match self.deadline {
+      Some(deadline) if self.clock.now() >= deadline => Some(TimedOut { deadline }.into()),
+      _ => None
+    }
+  }
+  fn stop (&mut self) {
+    if self.resume.is_none() && !self.queries.is_empty() {
+      self.resume = self.cursor();
+    }
+    self.queries.clear(); // release cached blocks held by the sub-iterators
+    self.permit = None;
+    self.audit = None;
+    self.timer = None;
+  }
+  fn next_shared (&mut self) -> Option<Result<SharedRow<P,V>,Error>> {
+    if self.limit == Some(0) {
+      self.stop();
+      return None;
+    }
+    let mut result = self.next_row();
+    let visible = self.visible.clone();
+    let intersect = match (&self.bbox, self.intersect) {
+      (Some(bbox), mode) if mode != Intersect::Overlaps => Some((*bbox,mode)),
+      _ => None
+    };
+    if visible.is_some() || intersect.is_some() {
+      while let Some(Ok(row)) = &result {
+        let (point,value) = (row.point(), row.value());
+        if intersect.map(|(bbox,mode)| mode.matches(point, &bbox)).unwrap_or(true)
+        && visible.as_ref().map(|f| f(point,value)).unwrap_or(true) { break }
+        result = self.next_row();
+      }
+    }
+    if result.is_none() {
+      self.permit = None;
+      self.audit = None;
+      self.timer = None;
+    }
+    if let Some(Ok(_)) = &result {
+      if let Some(n) = &mut self.limit { *n -= 1 }
+      if let Some(audit) = &mut self.audit { audit.rows += 1 }
+    }
+    result
+  }
+  // release the block of the tree that is passing its turn when the trees
+  // that take turns hold more than `max_buffered_blocks()`
+  fn spill (&mut self, len: usize) {
+    let max = match self.buffered_blocks {
+      Some(max) => max,
+      None => return
+    };
+    let held = self.queries[..len].iter().filter(|q| match q {
+      SubIterator::Tree(x) => x.holds_block(),
+      _ => false
+    }).count();
+    if held > max {
+      if let SubIterator::Tree(x) = &mut self.queries[self.index] {
+        x.spill();
+      }
+    }
+  }
+  fn next_row (&mut self) -> Option<Result<SharedRow<P,V>,Error>> {
+    // limited queries stay on one sub-iterator until it runs out
+    let step = if self.limit.is_some() { 0 } else { 1 };
     while !self.queries.is_empty() {
-      let len = self.queries.len();
+      // checked before every row, also deleted and filtered ones, so that no
+      // more blocks are read once the query is interrupted
+      if let Some(err) = self.interrupted() {
+        self.stop();
+        return Some(Err(err));
+      }
+      // only the first `open_trees` sub-iterators take turns
+      let len = self.queries.len().min(self.open_trees.unwrap_or(usize::MAX));
       {
         let q = &mut self.queries[self.index];
-        let next = match q {
-          SubIterator::Tree(x) => {
-            let result = x.next();
-            match &result {
-              Some(Ok((_,_,loc))) => {
-                if iwrap![self.deletes.try_borrow()].contains(loc) {
-                  self.index = (self.index+1) % len;
-                  continue;
-                }
-              },
-              _ => {}
-            };
-            result
-          },
-          SubIterator::Staging(x) => x.next()
+        // the staging iterator already skips rows deleted in staging
+        let (next,stored) = match q {
+          SubIterator::Tree(x) => (x.next_shared(), true),
+          SubIterator::Parallel(x) => (x.next_shared(), true),
+          SubIterator::Staging(x) => (x.next().map(|r| r.map(SharedRow::Owned)), false)
         };
-        match next {
-          Some(result) => {
-            self.index = (self.index+1) % len;
-            return Some(result);
-          },
-          None => {}
+        if let (true, Some(Ok(row))) = (stored, &next) {
+          if iwrap![self.deletes.read_lock()].contains(row.location()) {
+            self.index = (self.index+step) % len;
+            continue;
+          }
+        }
+        if let Some(result) = next {
+          if step > 0 && len > 1 { self.spill(len) }
+          self.index = (self.index+step) % len;
+          return Some(result);
         }
       }
       self.queries.remove(self.index);
@@ -581,3 +2630,25 @@ S: RandomAccess<Error=Error>, P: Point, V: Value {
     None
   }
 }
+
+impl<'b,S,P,V> Iterator for QueryIterator<'b,S,P,V> where
+S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  type Item = Result<(P,V,Location),Error>;
+  fn next (&mut self) -> Option<Self::Item> {
+    self.next_shared().map(|r| r.map(|row| row.into_owned()))
+  }
+}
+
+/// Iterator of `Result<SharedRow>` data returned by `db.query_shared()`.
+pub struct SharedQueryIterator<'b,S,P,V> where
+S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  iter: QueryIterator<'b,S,P,V>
+}
+
+impl<'b,S,P,V> Iterator for SharedQueryIterator<'b,S,P,V> where
+S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  type Item = Result<SharedRow<P,V>,Error>;
+  fn next (&mut self) -> Option<Self::Item> {
+    self.iter.next_shared()
+  }
+}
@@ -0,0 +1,179 @@
+use crate::{DB,Point,Value,Location};
+use crate::error::StaleLocation;
+use desert::{ToBytes,FromBytes,CountBytes};
+use crate::Error;
+use random_access_storage::RandomAccess;
+use std::fmt;
+use std::str::FromStr;
+
+/// `Location` of a row together with the commit it was read at, with an
+/// encoding that stays the same across platforms and eyros versions.
+///
+/// A `Location` can point at another row once a batch merges the staging
+/// area or compaction rewrites blocks, and its tuple form may change between
+/// versions. Store a `StableLocation` instead when a location leaves the
+/// process, such as in a job queue or another database:
+/// `db.resolve_location()` turns it back into a `Location` and fails with
+/// `StaleLocation` once the database has moved on.
+///
+/// The encoding is `ENCODED_LEN` bytes: the `VERSION` byte, then
+/// `generation`, `offset`, and `index` in big-endian order. `Display` and
+/// `FromStr` use the same bytes in lowercase hex. It is also a `Value`, so it
+/// can be stored in another eyros database.
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash)]
+pub struct StableLocation {
+  /// Sequence number of the commit the location was read at, from
+  /// `db.sequence()`.
+  pub generation: u64,
+  /// Offset of the data block plus one, or `0` for rows in the staging area.
+  pub offset: u64,
+  /// Index of the row in its data block or in the staging area.
+  pub index: u32
+}
+
+impl StableLocation {
+  /// Version of the encoding written by `encode()`.
+  pub const VERSION: u8 = 1;
+  /// Length in bytes of an encoded location.
+  pub const ENCODED_LEN: usize = 21;
+
+  pub fn new (location: Location, generation: u64) -> Self {
+    Self { generation, offset: location.0, index: location.1 }
+  }
+  /// Return the location without checking its generation. Prefer
+  /// `db.resolve_location()`.
+  pub fn location (&self) -> Location {
+    (self.offset, self.index)
+  }
+  pub fn encode (&self) -> [u8;21] {
+    let mut buf = [0u8;21];
+    buf[0] = Self::VERSION;
+    buf[1..9].copy_from_slice(&self.generation.to_be_bytes());
+    buf[9..17].copy_from_slice(&self.offset.to_be_bytes());
+    buf[17..21].copy_from_slice(&self.index.to_be_bytes());
+    buf
+  }
+  /// Decode a location written by `encode()`. Fails for encodings of other
+  /// versions.
+  pub fn decode (buf: &[u8]) -> Result<Self,Error> {
+    if buf.len() < Self::ENCODED_LEN {
+      invalid!["encoded location needs {} bytes, found {}",
+        Self::ENCODED_LEN, buf.len()]
+    }
+    if buf[0] != Self::VERSION {
+      invalid!["unsupported location encoding version {}", buf[0]];
+    }
+    let mut generation = [0u8;8];
+    let mut offset = [0u8;8];
+    let mut index = [0u8;4];
+    generation.copy_from_slice(&buf[1..9]);
+    offset.copy_from_slice(&buf[9..17]);
+    index.copy_from_slice(&buf[17..21]);
+    Ok(Self {
+      generation: u64::from_be_bytes(generation),
+      offset: u64::from_be_bytes(offset),
+      index: u32::from_be_bytes(index)
+    })
+  }
+  /// Hash the encoded location into a 64-bit id keyed by `seed`.
+  ///
+  /// Ids depend only on the encoding and the seed, never on the platform or
+  /// the process, so they can key rows in other systems. Use a secret seed
+  /// if ids are shown to clients that shouldn't learn locations from them.
+  pub fn id (&self, seed: u64) -> u64 {
+    // fnv-1a over the encoding, then the splitmix64 finalizer to spread the
+    // low-entropy input over every bit
+    let mut h = 0xcbf29ce484222325u64 ^ seed;
+    for b in self.encode().iter() {
+      h ^= *b as u64;
+      h = h.wrapping_mul(0x100000001b3);
+    }
+    h ^= h >> 30;
+    h = h.wrapping_mul(0xbf58476d1ce4e5b9);
+    h ^= h >> 27;
+    h = h.wrapping_mul(0x94d049bb133111eb);
+    h ^ (h >> 31)
+  }
+}
+
+impl fmt::Display for StableLocation {
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for b in self.encode().iter() {
+      write!(f, "{:02x}", b)?;
+    }
+    Ok(())
+  }
+}
+
+impl FromStr for StableLocation {
+  type Err = Error;
+  fn from_str (s: &str) -> Result<Self,Error> {
+    if s.len() != Self::ENCODED_LEN*2 || !s.is_ascii() {
+      invalid!["encoded location needs {} hex digits", Self::ENCODED_LEN*2]
+    }
+    let mut buf = [0u8;21];
+    for (i,b) in buf.iter_mut().enumerate() {
+      *b = u8::from_str_radix(&s[i*2..i*2+2], 16)
+        .map_err(|e| Error::Invalid(format!["encoded location: {}", e]))?;
+    }
+    Self::decode(&buf)
+  }
+}
+
+impl ToBytes for StableLocation {
+  fn to_bytes (&self) -> Result<Vec<u8>,failure::Error> {
+    Ok(self.encode().to_vec())
+  }
+  fn write_bytes (&self, dst: &mut [u8]) -> Result<usize,failure::Error> {
+    if dst.len() < Self::ENCODED_LEN {
+      failure::bail!["buffer too small for location"]
+    }
+    dst[..Self::ENCODED_LEN].copy_from_slice(&self.encode());
+    Ok(Self::ENCODED_LEN)
+  }
+}
+
+impl FromBytes for StableLocation {
+  fn from_bytes (src: &[u8]) -> Result<(usize,Self),failure::Error> {
+    Ok((Self::ENCODED_LEN, Self::decode(src)?))
+  }
+}
+
+impl CountBytes for StableLocation {
+  fn count_from_bytes (_buf: &[u8]) -> Result<usize,failure::Error> {
+    Ok(Self::ENCODED_LEN)
+  }
+  fn count_from_bytes_more (buf: &[u8]) -> Result<Option<usize>,failure::Error> {
+    Ok(if buf.len() < Self::ENCODED_LEN { None } else { Some(Self::ENCODED_LEN) })
+  }
+  fn count_bytes (&self) -> usize {
+    Self::ENCODED_LEN
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Stamp `location`, from a query on this handle, with the current commit
+  /// so that it can be stored outside of the database.
+  pub fn stable_location (&self, location: &Location) -> StableLocation {
+    StableLocation::new(*location, self.meta.sequence)
+  }
+  /// Turn a `StableLocation` back into a `Location` to delete or update its
+  /// row. Fails with `StaleLocation` if the sequence number of the database
+  /// changed since the location was read, since rows may have moved then.
+  /// Every batch and delete advances the sequence number. Call `refresh()`
+  /// first if another handle writes to the same storage.
+  pub fn resolve_location (&self, stable: &StableLocation)
+  -> Result<Location,Error> {
+    self.check_open()?;
+    if stable.generation != self.meta.sequence {
+      return Err(StaleLocation {
+        generation: stable.generation,
+        sequence: self.meta.sequence
+      }.into());
+    }
+    Ok(stable.location())
+  }
+}
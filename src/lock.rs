@@ -0,0 +1,21 @@
+use crate::Error;
+use std::sync::{RwLock,RwLockReadGuard,RwLockWriteGuard};
+
+/// Lock state shared between the database handle, its trees, and query
+/// iterators, turning poisoned locks into errors.
+///
+/// A lock is poisoned when a thread panics while holding it, which leaves the
+/// state behind it half-updated.
+pub trait Lock<T> {
+  fn read_lock (&self) -> Result<RwLockReadGuard<'_,T>,Error>;
+  fn write_lock (&self) -> Result<RwLockWriteGuard<'_,T>,Error>;
+}
+
+impl<T> Lock<T> for RwLock<T> {
+  fn read_lock (&self) -> Result<RwLockReadGuard<'_,T>,Error> {
+    self.read().map_err(|_| Error::Other("lock poisoned by a panicked thread".into()))
+  }
+  fn write_lock (&self) -> Result<RwLockWriteGuard<'_,T>,Error> {
+    self.write().map_err(|_| Error::Other("lock poisoned by a panicked thread".into()))
+  }
+}
@@ -0,0 +1,55 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// Deferred work that the database performs outside of `batch()` and `query()`
+/// when `run_maintenance()` is called.
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash)]
+pub enum Job {
+  /// Persist data blocks that queries quarantined to the meta store.
+  SaveQuarantine,
+  /// Write rows that `Setup::realtime()` left in the staging area into trees.
+  BuildTrees,
+  /// Remove changes past the limits of `Setup::retention()` from the changes
+  /// feed, like `prune_history()`.
+  PruneHistory,
+  /// Reclaim dead data blocks past `Setup::compact_ratio()`, like
+  /// `compact()`.
+  Compact
+}
+
+/// Summary of a `run_maintenance()` call.
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct MaintenanceReport {
+  /// Jobs that ran to completion, in order.
+  pub completed: Vec<Job>,
+  /// Number of jobs still pending because the time budget ran out.
+  pub remaining: usize,
+  /// Time spent according to the database clock.
+  pub elapsed: Duration
+}
+
+/// Executor that `AsyncDB::start_maintenance()` runs its task on, such as a
+/// handle to a tokio or async-std runtime.
+///
+/// ```rust,ignore
+/// use eyros::Executor;
+/// use std::{future::Future,pin::Pin,time::Duration};
+///
+/// struct Tokio(tokio::runtime::Handle);
+///
+/// impl Executor for Tokio {
+///   fn spawn (&self, task: Pin<Box<dyn Future<Output=()>+Send>>) {
+///     self.0.spawn(task);
+///   }
+///   fn sleep (&self, duration: Duration) -> Pin<Box<dyn Future<Output=()>+Send>> {
+///     Box::pin(tokio::time::sleep(duration))
+///   }
+/// }
+/// ```
+pub trait Executor {
+  /// Run `task` to completion in the background.
+  fn spawn (&self, task: Pin<Box<dyn Future<Output=()>+Send>>);
+  /// Return a future that is ready once `duration` has passed.
+  fn sleep (&self, duration: Duration) -> Pin<Box<dyn Future<Output=()>+Send>>;
+}
@@ -0,0 +1,113 @@
+use crate::{DB,Setup,Point,Value};
+use failure::{Error,format_err};
+use random_access_memory::RandomAccessMemory;
+use random_access_storage::RandomAccess;
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{Arc,Mutex,MutexGuard};
+
+const PAGE_SIZE: usize = 64 * 1024;
+
+/// Storage function of `DB::open_memory()`, which hands out the same
+/// `MemoryStore` each time a name is opened.
+pub type MemoryStorage = Box<dyn Fn(&str) -> Result<MemoryStore,Error> + Send + Sync>;
+
+/// Store backed by `random_access_memory::RandomAccessMemory`, shared by
+/// every handle opened under the same name.
+///
+/// The database reopens stores by name, such as when a poisoned handle is
+/// recovered, so handles share their bytes instead of each starting empty.
+/// `truncate()` and `read_to_writer()` are implemented here, since the
+/// underlying store doesn't support them.
+#[derive(Clone)]
+pub struct MemoryStore {
+  store: Arc<Mutex<RandomAccessMemory>>
+}
+
+impl MemoryStore {
+  pub fn new () -> Self {
+    Self { store: Arc::new(Mutex::new(RandomAccessMemory::new(PAGE_SIZE))) }
+  }
+  fn lock (&self) -> Result<MutexGuard<'_,RandomAccessMemory>,Error> {
+    self.store.lock().map_err(|_| format_err!["lock poisoned by a panicked thread"])
+  }
+}
+
+impl Default for MemoryStore {
+  fn default () -> Self { Self::new() }
+}
+
+impl RandomAccess for MemoryStore {
+  type Error = Error;
+  fn write (&mut self, offset: u64, data: &[u8]) -> Result<(),Error> {
+    self.lock()?.write(offset, data).map_err(|e| format_err!["{}", e])
+  }
+  fn read (&mut self, offset: u64, length: u64) -> Result<Vec<u8>,Error> {
+    self.lock()?.read(offset, length).map_err(|e| format_err!["{}", e])
+  }
+  fn read_to_writer (&mut self, offset: u64, length: u64,
+  buf: &mut impl Write) -> Result<(),Error> {
+    buf.write_all(&self.read(offset, length)?)?;
+    Ok(())
+  }
+  fn del (&mut self, offset: u64, length: u64) -> Result<(),Error> {
+    self.lock()?.del(offset, length).map_err(|e| format_err!["{}", e])
+  }
+  fn truncate (&mut self, length: u64) -> Result<(),Error> {
+    let mut store = self.lock()?;
+    let len = store.len().map_err(|e| format_err!["{}", e])?;
+    if length > len {
+      let zeros = vec![0;(length - len) as usize];
+      return store.write(len, &zeros).map_err(|e| format_err!["{}", e]);
+    }
+    let kept = store.read(0, length).map_err(|e| format_err!["{}", e])?;
+    *store = RandomAccessMemory::new(PAGE_SIZE);
+    if !kept.is_empty() {
+      store.write(0, &kept).map_err(|e| format_err!["{}", e])?;
+    }
+    Ok(())
+  }
+  fn len (&self) -> Result<u64,Error> {
+    self.lock()?.len().map_err(|e| format_err!["{}", e])
+  }
+  fn is_empty (&mut self) -> Result<bool,Error> {
+    Ok(self.len()? == 0)
+  }
+  fn sync_all (&mut self) -> Result<(),Error> {
+    Ok(())
+  }
+}
+
+/// Create a storage function over in-memory stores. Databases opened with a
+/// reference to the same function see the same stores.
+pub fn memory_storage () -> MemoryStorage {
+  let stores: Arc<Mutex<HashMap<String,MemoryStore>>> = Arc::new(Mutex::new(HashMap::new()));
+  Box::new(move |name: &str| {
+    let mut stores = stores.lock()
+      .map_err(|_| format_err!["lock poisoned by a panicked thread"])?;
+    Ok(stores.entry(name.to_string()).or_default().clone())
+  })
+}
+
+impl<P,V> DB<MemoryStore,MemoryStorage,P,V> where P: Point, V: Value {
+  /// Open a database whose stores all live in memory, configured by `setup`.
+  ///
+  /// This needs no temporary directory or storage function, which suits
+  /// tests and scratch databases. Everything is gone once the handle is
+  /// dropped.
+  ///
+  /// ```rust
+  /// use eyros::{DB,Row,Error};
+  /// # fn main () -> Result<(),Error> {
+  /// let mut db: DB<_,_,(f32,f32),u32> = DB::open_memory(|setup| {
+  ///   setup.max_data_size(500).base_size(2_000)
+  /// })?;
+  /// db.batch(&[Row::Insert((0.5,0.5),7)])?;
+  /// assert_eq![db.query(&((0.0,0.0),(1.0,1.0)))?.count(), 1];
+  /// # Ok(()) }
+  /// ```
+  pub fn open_memory<F> (setup: F) -> Result<Self,crate::Error>
+  where F: FnOnce(Setup<MemoryStore,MemoryStorage>) -> Setup<MemoryStore,MemoryStorage> {
+    setup(Setup::new(memory_storage())).build()
+  }
+}
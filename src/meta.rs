@@ -1,51 +1,345 @@
-use failure::{Error,bail};
+use crate::Error;
+use crate::tune::Tuning;
+use crate::migrate::FORMAT_VERSION;
+use crate::error::FormatVersion;
 //use std::mem::size_of;
 use random_access_storage::RandomAccess;
+use std::collections::BTreeMap;
 
 #[derive(Debug)]
-pub struct Meta<S> where S: RandomAccess<Error=Error> {
+pub struct Meta<S> where S: RandomAccess<Error=failure::Error> {
   store: S,
   pub mask: Vec<bool>,
-  pub branch_factor: u16
+  pub branch_factor: u16,
+  pub quarantine: Vec<u64>,
+  pub sequence: u64,
+  /// Outbox offset acknowledged by each consumer.
+  pub consumers: Vec<(String,u64)>,
+  /// Epoch of the key that new data blocks are encrypted with.
+  pub key_epoch: u32,
+  /// Rows written into trees from staging, for `Setup::auto_tune()`.
+  pub ingested: u64,
+  /// Settings chosen by `Setup::auto_tune()`.
+  pub tuning: Option<Tuning>,
+  /// Format version read from the header, or `0` for files without one.
+  pub version: u16,
+  /// Key-value pairs set with `db.put_meta()`.
+  pub user: BTreeMap<String,Vec<u8>>,
+  /// Whether `user` changed since the meta store was last saved.
+  pub user_changed: bool,
+  // record that the store currently holds, which `save()` must not overwrite
+  current: Option<Record>,
+  // format version in the header of the store, or `0` for none
+  stored_version: u16
 }
 
-impl<S> Meta<S> where S: RandomAccess<Error=Error> {
+// start of the header, which can't be mistaken for the branch factor that
+// headerless files start with
+const MAGIC: [u8;4] = *b"EYRS";
+const HEADER_LEN: usize = 8;
+
+// Since version 2, the header is followed by two slots that each point at a
+// record written by `to_bytes()`:
+// `[magic][sequence (u64)][offset (u64)][length (u32)][record crc32][slot crc32]`.
+// `save()` writes a new record where it doesn't overlap the current one and
+// then commits it by writing the older slot, so a crash while saving leaves
+// the previous record in place. Slots are small enough to be written
+// atomically, and a torn slot fails its checksum anyway.
+const SLOT_MAGIC: [u8;4] = *b"EYSB";
+const SLOT_LEN: usize = 32;
+const RECORDS_START: usize = HEADER_LEN + 2*SLOT_LEN;
+
+#[derive(Debug,Clone,Copy)]
+struct Record {
+  slot: usize,
+  sequence: u64,
+  offset: usize,
+  len: usize
+}
+
+impl Record {
+  fn to_bytes (self, crc: u32) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(SLOT_LEN);
+    bytes.extend(&SLOT_MAGIC);
+    bytes.extend(&self.sequence.to_be_bytes());
+    bytes.extend(&(self.offset as u64).to_be_bytes());
+    bytes.extend(&(self.len as u32).to_be_bytes());
+    bytes.extend(&crc.to_be_bytes());
+    bytes.extend(&crc32fast::hash(&bytes).to_be_bytes());
+    bytes
+  }
+  // Read slot `slot` of `buf` if it is intact and points at an intact record.
+  fn from_bytes (buf: &[u8], slot: usize) -> Option<Self> {
+    let start = HEADER_LEN + slot*SLOT_LEN;
+    if buf.len() < start+SLOT_LEN { return None }
+    let b = &buf[start..start+SLOT_LEN];
+    let u32_at = |i: usize| u32::from_be_bytes([b[i],b[i+1],b[i+2],b[i+3]]);
+    let u64_at = |i: usize| {
+      let mut x = [0u8;8];
+      x.copy_from_slice(&b[i..i+8]);
+      u64::from_be_bytes(x)
+    };
+    if b[0..4] != SLOT_MAGIC || crc32fast::hash(&b[0..28]) != u32_at(28) {
+      return None;
+    }
+    let record = Self {
+      slot,
+      sequence: u64_at(4),
+      offset: u64_at(12) as usize,
+      len: u32_at(20) as usize
+    };
+    if record.offset < RECORDS_START || record.offset+record.len > buf.len()
+    || crc32fast::hash(&buf[record.offset..record.offset+record.len]) != u32_at(24) {
+      return None;
+    }
+    Some(record)
+  }
+}
+
+fn header () -> Vec<u8> {
+  let mut bytes = vec![];
+  bytes.extend(&MAGIC);
+  bytes.extend(&FORMAT_VERSION.to_be_bytes());
+  bytes.extend(&[0,0]);
+  bytes
+}
+
+impl<S> Meta<S> where S: RandomAccess<Error=failure::Error> {
   pub fn open(store: S) -> Result<Self,Error> {
     let mut meta = Self {
       store,
       mask: vec![],
-      branch_factor: 9
+      branch_factor: 9,
+      quarantine: vec![],
+      sequence: 0,
+      consumers: vec![],
+      key_epoch: 0,
+      ingested: 0,
+      tuning: None,
+      version: FORMAT_VERSION,
+      user: BTreeMap::new(),
+      user_changed: false,
+      current: None,
+      stored_version: 0
     };
     if !meta.store.is_empty()? {
       let len = meta.store.len()?;
       let buf = meta.store.read(0,len)?;
-      meta.load_buffer(&buf)?;
+      meta.load_store(&buf)?;
     }
     Ok(meta)
   }
+  pub fn bytes (&self) -> Result<u64,Error> {
+    Ok(self.store.len()?)
+  }
+  /// Write a new record and commit it, without touching the current one.
   pub fn save (&mut self) -> Result<(),Error> {
-    let mut bytes = vec![];
+    let bytes = self.to_bytes();
+    if self.current.is_none() && self.stored_version != FORMAT_VERSION {
+      // new store: the header with empty slots comes first
+      let mut head = header();
+      head.resize(RECORDS_START, 0);
+      self.store.write(0, &head)?;
+      self.stored_version = FORMAT_VERSION;
+    }
+    let record = match self.current {
+      None => Record { slot: 0, sequence: 1, offset: RECORDS_START, len: bytes.len() },
+      Some(c) => Record {
+        slot: 1 - c.slot,
+        sequence: c.sequence + 1,
+        offset: if RECORDS_START + bytes.len() <= c.offset {
+          RECORDS_START
+        } else {
+          (c.offset + c.len).max(RECORDS_START)
+        },
+        len: bytes.len()
+      }
+    };
+    self.store.write(record.offset as u64, &bytes)?;
+    self.store.sync_all()?;
+    let slot = record.to_bytes(crc32fast::hash(&bytes));
+    self.store.write((HEADER_LEN + record.slot*SLOT_LEN) as u64, &slot)?;
+    self.store.sync_all()?;
+    self.current = Some(record);
+    if self.stored_version != FORMAT_VERSION {
+      // older stores get the new header once the first record is committed
+      self.store.write(0, &header())?;
+      self.store.sync_all()?;
+      self.stored_version = FORMAT_VERSION;
+    }
+    let end = (record.offset + record.len) as u64;
+    if self.store.len()? > end {
+      self.store.truncate(end)?;
+    }
+    self.user_changed = false;
+    Ok(())
+  }
+  pub fn sync_all (&mut self) -> Result<(),Error> {
+    Ok(self.store.sync_all()?)
+  }
+  /// Replace the stored meta data with `buf`, as written by `to_bytes()`.
+  pub fn restore (&mut self, buf: &[u8]) -> Result<(),Error> {
+    self.load_buffer(buf)?;
+    self.save()
+  }
+  /// Serialize in the newest format, with a header.
+  pub fn to_bytes (&self) -> Vec<u8> {
+    let mut bytes = header();
     bytes.extend(&self.branch_factor.to_be_bytes());
     bytes.extend(&(self.mask.len() as u32).to_be_bytes());
     let mbytes: Vec<u8> = (0..(self.mask.len()+7)/8).map(|i| {
       let mut b = 0u8;
       for j in 0..8 {
-        b += (self.mask[i] as u8)*(1<<j);
+        let bit = self.mask.get(i*8+j).cloned().unwrap_or(false);
+        b += (bit as u8)*(1<<j);
       }
       b
     }).collect();
     bytes.extend(&mbytes);
-    self.store.write(0, &bytes)?;
+    bytes.extend(&(self.quarantine.len() as u32).to_be_bytes());
+    for offset in self.quarantine.iter() {
+      bytes.extend(&offset.to_be_bytes());
+    }
+    bytes.extend(&self.sequence.to_be_bytes());
+    bytes.extend(&(self.consumers.len() as u32).to_be_bytes());
+    for (name,offset) in self.consumers.iter() {
+      bytes.extend(&(name.len() as u16).to_be_bytes());
+      bytes.extend(name.as_bytes());
+      bytes.extend(&offset.to_be_bytes());
+    }
+    bytes.extend(&self.key_epoch.to_be_bytes());
+    bytes.extend(&self.ingested.to_be_bytes());
+    match &self.tuning {
+      Some(t) => { bytes.push(1); bytes.extend(t.to_bytes()) },
+      None => bytes.push(0)
+    }
+    bytes.extend(&(self.user.len() as u32).to_be_bytes());
+    for (key,value) in self.user.iter() {
+      bytes.extend(&(key.len() as u16).to_be_bytes());
+      bytes.extend(key.as_bytes());
+      bytes.extend(&(value.len() as u32).to_be_bytes());
+      bytes.extend(value);
+    }
+    bytes
+  }
+  // Load the user key-value pairs, which take up all of `buf`.
+  fn load_user (&mut self, buf: &[u8]) -> Result<(),Error> {
+    if buf.len() < 4 {
+      corrupt!("unexpected buffer length for user meta data");
+    }
+    let n = u32::from_be_bytes([buf[0],buf[1],buf[2],buf[3]]) as usize;
+    let mut offset = 4;
+    for _ in 0..n {
+      if offset+2 > buf.len() {
+        corrupt!("unexpected buffer length for user meta data");
+      }
+      let klen = u16::from_be_bytes([buf[offset],buf[offset+1]]) as usize;
+      offset += 2;
+      if offset+klen+4 > buf.len() {
+        corrupt!("unexpected buffer length for user meta data");
+      }
+      let key = String::from_utf8(buf[offset..offset+klen].to_vec())
+        .map_err(|e| Error::Corrupt(format!["user meta data key: {}", e]))?;
+      offset += klen;
+      let b = &buf[offset..offset+4];
+      let vlen = u32::from_be_bytes([b[0],b[1],b[2],b[3]]) as usize;
+      offset += 4;
+      if offset+vlen > buf.len() {
+        corrupt!("unexpected buffer length for user meta data");
+      }
+      self.user.insert(key, buf[offset..offset+vlen].to_vec());
+      offset += vlen;
+    }
+    if offset != buf.len() {
+      corrupt!("unexpected buffer length for user meta data");
+    }
     Ok(())
   }
-  fn load_buffer(&mut self, buf: &Vec<u8>) -> Result<(),Error> {
+  // Load the consumer list at the start of `buf` and return its length.
+  fn load_consumers (&mut self, buf: &[u8]) -> Result<usize,Error> {
+    if buf.len() < 4 {
+      corrupt!("unexpected buffer length for consumer list");
+    }
+    let n = u32::from_be_bytes([buf[0],buf[1],buf[2],buf[3]]) as usize;
+    let mut offset = 4;
+    for _ in 0..n {
+      if offset+2 > buf.len() {
+        corrupt!("unexpected buffer length for consumer list");
+      }
+      let len = u16::from_be_bytes([buf[offset],buf[offset+1]]) as usize;
+      offset += 2;
+      if offset+len+8 > buf.len() {
+        corrupt!("unexpected buffer length for consumer list");
+      }
+      let name = String::from_utf8(buf[offset..offset+len].to_vec())
+        .map_err(|e| Error::Corrupt(format!["consumer name: {}", e]))?;
+      offset += len;
+      let mut b = [0u8;8];
+      b.copy_from_slice(&buf[offset..offset+8]);
+      offset += 8;
+      self.consumers.push((name,u64::from_be_bytes(b)));
+    }
+    Ok(offset)
+  }
+  // Load the newest intact record of a store, or the whole store if it was
+  // written before version 2.
+  fn load_store (&mut self, buf: &[u8]) -> Result<(),Error> {
+    let header = buf.len() >= HEADER_LEN && buf[0..4] == MAGIC;
+    self.stored_version = if header { u16::from_be_bytes([buf[4],buf[5]]) } else { 0 };
+    if self.stored_version > FORMAT_VERSION {
+      return Err(FormatVersion {
+        found: self.stored_version,
+        supported: FORMAT_VERSION
+      }.into());
+    }
+    // slots are checked in older stores too, in case an upgrade was
+    // interrupted before the header was written
+    let newest = (0..2).filter_map(|i| Record::from_bytes(buf, i))
+      .max_by_key(|r| r.sequence);
+    if let Some(r) = newest {
+      self.load_buffer(&buf[r.offset..r.offset+r.len])?;
+      self.current = Some(r);
+    } else if self.stored_version >= 2 {
+      let slots = &buf[HEADER_LEN..RECORDS_START.min(buf.len())];
+      if slots.iter().any(|b| *b != 0) {
+        corrupt!("meta store has no intact record");
+      }
+      // the first save didn't commit a record, so the store is still new
+    } else {
+      self.load_buffer(buf)?;
+      // keep the old layout until a record is committed after it
+      self.current = Some(Record { slot: 1, sequence: 0, offset: 0, len: buf.len() });
+    }
+    Ok(())
+  }
+  fn load_buffer(&mut self, buf: &[u8]) -> Result<(),Error> {
+    let buf = if buf.len() >= 4 && buf[0..4] == MAGIC {
+      if buf.len() < HEADER_LEN {
+        corrupt!("meta header too short ({} bytes)", buf.len());
+      }
+      self.version = u16::from_be_bytes([buf[4],buf[5]]);
+      if self.version > FORMAT_VERSION {
+        return Err(FormatVersion {
+          found: self.version,
+          supported: FORMAT_VERSION
+        }.into());
+      }
+      &buf[HEADER_LEN..]
+    } else {
+      self.version = 0;
+      buf
+    };
+    if buf.len() < 6 {
+      corrupt!("unexpected buffer length");
+    }
     self.branch_factor = u16::from_be_bytes([buf[0],buf[1]]);
     self.mask.clear();
     let len = u32::from_be_bytes([buf[2],buf[3],buf[4],buf[5]]) as usize;
-    if (len+7)/8+6 != buf.len() {
-      bail!("unexpected buffer length");
+    let mask_end = len.div_ceil(8)+6;
+    if mask_end > buf.len() {
+      corrupt!("unexpected buffer length");
     }
-    for i in 0..(len+7)/8 {
+    for i in 0..len.div_ceil(8) {
       let b = buf[i+6];
       for j in 0..8 {
         if i*8+j >= len { break }
@@ -53,7 +347,72 @@ impl<S> Meta<S> where S: RandomAccess<Error=Error> {
       }
     }
     if self.mask.len() != len {
-      bail!("mask has unexpected length");
+      corrupt!("mask has unexpected length");
+    }
+    self.quarantine.clear();
+    self.sequence = 0;
+    self.consumers.clear();
+    self.key_epoch = 0;
+    self.ingested = 0;
+    self.tuning = None;
+    self.user.clear();
+    self.user_changed = false;
+    if buf.len() > mask_end { // older files end after the mask
+      if buf.len() < mask_end+4 {
+        corrupt!("unexpected buffer length for quarantine list");
+      }
+      let qlen = u32::from_be_bytes([
+        buf[mask_end], buf[mask_end+1], buf[mask_end+2], buf[mask_end+3]
+      ]) as usize;
+      let q_start = mask_end+4;
+      let q_end = q_start+qlen*8;
+      if q_end != buf.len() && q_end+8 > buf.len() {
+        corrupt!("unexpected buffer length for quarantine list");
+      }
+      for i in 0..qlen {
+        let mut b = [0u8;8];
+        b.copy_from_slice(&buf[q_start+i*8..q_start+i*8+8]);
+        self.quarantine.push(u64::from_be_bytes(b));
+      }
+      if q_end+8 <= buf.len() { // older files end after the quarantine list
+        let mut b = [0u8;8];
+        b.copy_from_slice(&buf[q_end..q_end+8]);
+        self.sequence = u64::from_be_bytes(b);
+      }
+      let c_start = q_end+8;
+      if c_start < buf.len() { // older files end after the sequence
+        let k_start = c_start + self.load_consumers(&buf[c_start..])?;
+        if k_start < buf.len() { // older files end after the consumer list
+          if k_start+4 > buf.len() {
+            corrupt!("unexpected buffer length for key epoch");
+          }
+          let k = &buf[k_start..];
+          self.key_epoch = u32::from_be_bytes([k[0],k[1],k[2],k[3]]);
+          let t_start = k_start+4;
+          if t_start < buf.len() { // older files end after the key epoch
+            if t_start+9 > buf.len() {
+              corrupt!("unexpected buffer length for tuning");
+            }
+            let mut b = [0u8;8];
+            b.copy_from_slice(&buf[t_start..t_start+8]);
+            self.ingested = u64::from_be_bytes(b);
+            let u_start = match buf[t_start+8] {
+              0 => t_start+9,
+              1 => t_start+9+Tuning::ENCODED_LEN,
+              _ => corrupt!("unexpected tuning flag")
+            };
+            if u_start > buf.len() {
+              corrupt!("unexpected buffer length for tuning");
+            }
+            if buf[t_start+8] == 1 {
+              self.tuning = Some(Tuning::from_bytes(&buf[t_start+9..u_start])?);
+            }
+            if u_start < buf.len() { // older files end after the tuning
+              self.load_user(&buf[u_start..])?;
+            }
+          }
+        }
+      }
     }
     Ok(())
   }
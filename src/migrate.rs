@@ -0,0 +1,63 @@
+use crate::meta::Meta;
+use crate::error::FormatVersion;
+use crate::Error;
+use random_access_storage::RandomAccess;
+
+/// Format version of the meta store that this version of eyros writes.
+///
+/// The version is recorded in a header at the start of the meta store.
+/// Databases written before the header was introduced are version `0`.
+/// Opening a database with an older version upgrades its stores to this
+/// version, and opening one with a newer version fails with a
+/// `FormatVersion` error instead of misreading it.
+pub const FORMAT_VERSION: u16 = 2;
+
+// Upgrade the stores from version `from` to version `from+1`. Register a
+// step here with each format bump. Steps can rewrite any store, but the
+// meta store is saved in the new format once every step has run, so
+// `Meta::open()` must still read each older layout.
+fn step<S,U> (from: u16, _open_store: &U) -> Result<(),Error> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>) {
+  match from {
+    // version 1 adds the meta header and doesn't change any store
+    0 => Ok(()),
+    // version 2 keeps the meta store in records that are committed through
+    // checksummed slots, which `Meta::save()` writes on its own
+    1 => Ok(()),
+    _ => invalid!["no upgrade step from format version {}", from]
+  }
+}
+
+// Whether read-only handles, which can't upgrade the stores, can read stores
+// of the older version `version` as they are.
+fn readable (version: u16) -> bool {
+  // version 0 only lacks the header and version 1 only lacks the slots
+  matches![version, 0 | 1]
+}
+
+/// Upgrade the stores to `FORMAT_VERSION` if they have an older version.
+pub fn run<S,U> (open_store: &U, read_only: bool) -> Result<(),Error> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>) {
+  let meta = Meta::open(open_store("meta")?)?;
+  if meta.version == FORMAT_VERSION || meta.bytes()? == 0 {
+    return Ok(());
+  }
+  if read_only {
+    if !readable(meta.version) {
+      return Err(FormatVersion {
+        found: meta.version,
+        supported: FORMAT_VERSION
+      }.into());
+    }
+    return Ok(());
+  }
+  for from in meta.version..FORMAT_VERSION {
+    step(from, open_store)?;
+  }
+  // open the meta store again, since the steps may have rewritten it
+  let mut meta = Meta::open(open_store("meta")?)?;
+  meta.save()?;
+  Ok(())
+}
@@ -1,9 +1,9 @@
-use crate::{Point,Cursor,Block,order,order_len};
-use failure::{Error,bail};
+use crate::{Point,Distance,Cursor,Block,order,order_len};
+use crate::point::{ToF64,Midpoint,interval_gap};
+use crate::Error;
 use std::mem::size_of;
 
 use std::cmp::{Ordering,PartialOrd};
-use std::ops::{Add,Div};
 use desert::{FromBytes,ToBytes,CountBytes};
 use std::fmt::Debug;
 
@@ -56,6 +56,20 @@ use std::fmt::Debug;
 /// let m: Mix2<f32,f32> = tuple.into();
 /// assert_eq![m, Mix2::new(Mix::Scalar(2.0),Mix::Interval(-3.0,1.0))];
 /// ```
+///
+/// Plain scalars and `(min,max)` tuples convert into `Mix` elements, so points
+/// of interest and the bounding boxes of road segments can be built with the
+/// same constructor:
+///
+/// ```rust
+/// use eyros::{Mix,Mix2};
+///
+/// let poi: Mix2<f32,f32> = Mix2::new(13.4.into(), 52.5.into());
+/// let road: Mix2<f32,f32> = Mix2::new((13.3,13.5).into(), (52.4,52.6).into());
+/// assert_eq![poi.v0, Mix::Scalar(13.4)];
+/// assert_eq![road.v1, Mix::Interval(52.4,52.6)];
+/// assert_eq![road.v0.bounds(), (13.3,13.5)];
+/// ```
 
 /// Define a value to use for a single dimension: either a scalar
 /// (a single value) or an interval (min, max).
@@ -65,6 +79,32 @@ pub enum Mix<T> {
   Interval(T,T)
 }
 
+impl<T> Mix<T> where T: PartialOrd+Copy {
+  /// Return the `(min,max)` extent of this element. For a scalar, both values
+  /// are the scalar itself.
+  pub fn bounds (&self) -> (T,T) {
+    match self {
+      Mix::Scalar(x) => (*x,*x),
+      Mix::Interval(x0,x1) => (*x0,*x1)
+    }
+  }
+  /// Return whether this element is a scalar.
+  pub fn is_scalar (&self) -> bool {
+    match self {
+      Mix::Scalar(_) => true,
+      Mix::Interval(_,_) => false
+    }
+  }
+}
+
+impl<T> From<T> for Mix<T> {
+  fn from (x: T) -> Self { Mix::Scalar(x) }
+}
+
+impl<T> From<(T,T)> for Mix<T> {
+  fn from (iv: (T,T)) -> Self { Mix::Interval(iv.0,iv.1) }
+}
+
 macro_rules! impl_mix {
   ($M:ident,$dim:expr,($($T:tt),+),($($v:tt),+),($($i:tt),+)) => {
     #[derive(Copy,Clone,Debug,Eq,PartialEq)]
@@ -101,8 +141,8 @@ macro_rules! impl_mix {
           Mix::Interval(x0,x1) => x0.count_bytes() + x1.count_bytes(),
         })+
       }
-      fn count_from_bytes(buf: &[u8]) -> Result<usize,Error> {
-        if buf.len() < 1 { bail!["buffer too small for type in count"] }
+      fn count_from_bytes(buf: &[u8]) -> Result<usize,failure::Error> {
+        if buf.len() < 1 { failure::bail!["buffer too small for type in count"] }
         let mut offset = 1;
         $(if ((buf[0]>>$i)&1) == 0 {
           offset += $T::count_from_bytes(&buf[offset..])?;
@@ -115,15 +155,15 @@ macro_rules! impl_mix {
     }
 
     impl<$($T),+> ToBytes for $M<$($T),+> where $($T: ToBytes+CountBytes),+ {
-      fn to_bytes(&self) -> Result<Vec<u8>,Error> {
+      fn to_bytes(&self) -> Result<Vec<u8>,failure::Error> {
         let count = self.count_bytes();
         let mut bytes = vec![0u8;count];
         let size = self.write_bytes(&mut bytes)?;
-        if size != count { bail!["unexpected size while writing into buffer"] }
+        if size != count { failure::bail!["unexpected size while writing into buffer"] }
         Ok(bytes)
       }
-      fn write_bytes(&self, dst: &mut [u8]) -> Result<usize,Error> {
-        if dst.len() < 1 { bail!["dst buffer too small"] }
+      fn write_bytes(&self, dst: &mut [u8]) -> Result<usize,failure::Error> {
+        if dst.len() < 1 { failure::bail!["dst buffer too small"] }
         let mut offset = 1;
         dst[0] = 0;
         $(match &self.$v {
@@ -141,9 +181,9 @@ macro_rules! impl_mix {
     }
 
     impl<$($T),+> FromBytes for $M<$($T),+> where $($T: FromBytes),+ {
-      fn from_bytes(src: &[u8]) -> Result<(usize,Self),Error> {
+      fn from_bytes(src: &[u8]) -> Result<(usize,Self),failure::Error> {
         if src.len() < 1 {
-          bail!["buffer too small while loading from bytes"]
+          failure::bail!["buffer too small while loading from bytes"]
         }
         let mut offset = 1;
         $(let $v = if (src[0]>>$i)&1 == 0 {
@@ -162,8 +202,7 @@ macro_rules! impl_mix {
     }
 
     impl<$($T),+> Point for $M<$($T),+> where ($(($T,$T)),+): Point,
-    $($T: ToBytes+FromBytes+CountBytes+Copy+Debug+PartialOrd
-    +Add<Output=$T>+Div<Output=$T>+From<u8>),+ {
+    $($T: ToBytes+FromBytes+CountBytes+Copy+Debug+PartialOrd+Midpoint),+ {
       type Bounds = (($($T),+),($($T),+));
       type Range = ($(($T,$T)),+);
 
@@ -183,7 +222,7 @@ macro_rules! impl_mix {
                 if a >= b0 && a <= b1 {
                   Some(Ordering::Equal)
                 } else {
-                  b0.partial_cmp(&a)
+                  a.partial_cmp(&b0)
                 }
               },
               (Mix::Interval(a0,a1),Mix::Interval(b0,b1)) => {
@@ -200,24 +239,36 @@ macro_rules! impl_mix {
         match order { Some(x) => x, None => Ordering::Less }
       }
 
+      fn cmp_upper_at (&self, other: &Self, level: usize) -> Ordering where Self: Sized {
+        let order = match level % Self::dim() {
+          $($i => {
+            let a = match self.$v { Mix::Scalar(x) => x, Mix::Interval(_,x) => x };
+            let b = match other.$v { Mix::Scalar(x) => x, Mix::Interval(_,x) => x };
+            a.partial_cmp(&b)
+          },)+
+          _ => panic!["match case beyond dimension"]
+        };
+        order.unwrap_or(Ordering::Equal)
+      }
+
       fn midpoint_upper (&self, other: &Self) -> Self where Self: Sized {
         $(let $v = Mix::Scalar(match (self.$v, other.$v) {
-          (Mix::Scalar(a),Mix::Scalar(b)) => a/2.into()+b/2.into(),
-          (Mix::Interval(_,a),Mix::Scalar(b)) => a/2.into()+b/2.into(),
-          (Mix::Scalar(a),Mix::Interval(_,b)) => a/2.into()+b/2.into(),
-          (Mix::Interval(_,a),Mix::Interval(_,b)) => a/2.into()+b/2.into(),
+          (Mix::Scalar(a),Mix::Scalar(b)) => Midpoint::midpoint(a,b),
+          (Mix::Interval(_,a),Mix::Scalar(b)) => Midpoint::midpoint(a,b),
+          (Mix::Scalar(a),Mix::Interval(_,b)) => Midpoint::midpoint(a,b),
+          (Mix::Interval(_,a),Mix::Interval(_,b)) => Midpoint::midpoint(a,b),
         });)+
         Self { $($v),+ }
       }
 
       fn serialize_at (&self, level: usize, dst: &mut [u8]) -> Result<usize,Error> {
-        match level % Self::dim() {
+        Ok(match level % Self::dim() {
           $($i => match self.$v {
-            Mix::Scalar(x) => x.write_bytes(dst),
-            Mix::Interval(_,x) => x.write_bytes(dst),
+            Mix::Scalar(x) => x.write_bytes(dst)?,
+            Mix::Interval(_,x) => x.write_bytes(dst)?,
           }),+
           _ => panic!["match case beyond dimension"]
-        }
+        })
       }
 
       fn dim () -> usize { $dim }
@@ -229,6 +280,20 @@ macro_rules! impl_mix {
         }))+
       }
 
+      fn within (&self, bbox: &Self::Bounds) -> bool {
+        true $(&& (match self.$v {
+          Mix::Scalar(x) => (bbox.0).$i <= x && x <= (bbox.1).$i,
+          Mix::Interval(x0,x1) => (bbox.0).$i <= x0 && x1 <= (bbox.1).$i
+        }))+
+      }
+
+      fn contains (&self, bbox: &Self::Bounds) -> bool {
+        true $(&& (match self.$v {
+          Mix::Scalar(x) => x <= (bbox.0).$i && (bbox.1).$i <= x,
+          Mix::Interval(x0,x1) => x0 <= (bbox.0).$i && (bbox.1).$i <= x1
+        }))+
+      }
+
       fn query_branch (buf: &[u8], bbox: &Self::Bounds, bf: usize, level: usize)
       -> Result<(Vec<Cursor>,Vec<Block>),Error> {
         let mut cursors = vec![];
@@ -325,6 +390,33 @@ macro_rules! impl_mix {
         })
       }
 
+      fn pivots_at (buf: &[u8], bf: usize, level: usize)
+      -> Result<Option<Vec<f64>>,Error> {
+        // pivots are written like those of the interval tuple
+        <($(($T,$T)),+) as Point>::pivots_at(buf, bf, level)
+      }
+      fn bucket_ranges (buf: &[u8], range: &Self::Range, bf: usize, level: usize)
+      -> Result<Option<Vec<Self::Range>>,Error> {
+        let n = order_len(bf);
+        match level % Self::dim() {
+          $($i => {
+            let mut pivots = Vec::with_capacity(n);
+            let mut offset = 0;
+            for _i in 0..n {
+              let (size,pivot) = $T::from_bytes(&buf[offset..])?;
+              pivots.push(pivot);
+              offset += size;
+            }
+            Ok(Some((0..bf).map(|k| {
+              let mut r = *range;
+              if k > 0 && (r.$i).0 < pivots[2*(k-1)] { (r.$i).0 = pivots[2*(k-1)] }
+              if k < bf-1 && pivots[2*k] < (r.$i).1 { (r.$i).1 = pivots[2*k] }
+              r
+            }).collect()))
+          },)+
+          _ => panic!["dimension not expected"]
+        }
+      }
       fn bounds (points: &Vec<Self>) -> Option<Self::Bounds> {
         if points.is_empty() { return None }
         fn lower<T> (x: &Mix<T>) -> &T {
@@ -364,9 +456,60 @@ macro_rules! impl_mix {
         ($(((bbox.0).$i,(bbox.1).$i)),+)
       }
 
-      fn format_at (_buf: &[u8], _level: usize)
+      fn range_within (range: &Self::Range, bbox: &Self::Bounds) -> bool {
+        true $(&& (bbox.0).$i <= (range.$i).0 && (range.$i).1 <= (bbox.1).$i)+
+      }
+
+      fn union_bounds (a: &Self::Bounds, b: &Self::Bounds)
+      -> Option<Self::Bounds> {
+        Some((
+          ($(if (b.0).$i < (a.0).$i { (b.0).$i } else { (a.0).$i }),+),
+          ($(if (b.1).$i > (a.1).$i { (b.1).$i } else { (a.1).$i }),+)
+        ))
+      }
+
+      fn format_at (buf: &[u8], level: usize)
       -> Result<String,Error> {
-        unimplemented![]
+        Ok(match level % Self::dim() {
+          $($i => {
+            let (_,p) = $T::from_bytes(buf)?;
+            format!["{:?}", p]
+          }),+
+          _ => panic!["match case beyond dimension"]
+        })
+      }
+    }
+
+    impl<$($T),+> Distance for $M<$($T),+> where ($(($T,$T)),+): Point,
+    $($T: ToBytes+FromBytes+CountBytes+Copy+Debug+PartialOrd+Midpoint+ToF64),+ {
+      type Target = ($($T),+);
+      fn distance_sq (&self, target: &Self::Target) -> f64 {
+        0.0 $(+ {
+          let (x0,x1) = match self.$v {
+            Mix::Scalar(x) => (x,x),
+            Mix::Interval(x0,x1) => (x0,x1)
+          };
+          let d = interval_gap(x0, x1, target.$i);
+          d*d
+        })+
+      }
+      fn bounds_distance_sq (bbox: &Self::Bounds, target: &Self::Target) -> f64 {
+        0.0 $(+ {
+          let d = interval_gap((bbox.0).$i, (bbox.1).$i, target.$i);
+          d*d
+        })+
+      }
+      fn range_distance_sq (range: &Self::Range, target: &Self::Target) -> f64 {
+        0.0 $(+ {
+          let d = interval_gap((range.$i).0, (range.$i).1, target.$i);
+          d*d
+        })+
+      }
+      fn axes_distance_sq (axes: &[(f64,f64)], target: &Self::Target) -> f64 {
+        0.0 $(+ {
+          let d = interval_gap(axes[$i].0, axes[$i].1, target.$i.to_f64());
+          d*d
+        })+
       }
     }
   }
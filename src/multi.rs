@@ -0,0 +1,146 @@
+use crate::{DB,Point,Value,Setup,Location,QueryIterator};
+use crate::Error;
+use random_access_storage::RandomAccess;
+use std::rc::Rc;
+
+type OpenStore<S> = Box<dyn Fn(&str) -> Result<S,failure::Error>>;
+type OpenBundle<S> = Rc<dyn Fn(&str,&str) -> Result<S,failure::Error>>;
+type Bundle<S,P,V> = (String,DB<S,OpenStore<S>,P,V>);
+
+/// Collection of shard databases (such as the ones written by
+/// `db.export_sharded()`) that are attached at runtime and queried together.
+///
+/// Bundles are located by name with the `open_bundle(bundle, store_name)`
+/// function given to `MultiDB::new()`, which can map names to directories,
+/// URLs, or anything else that provides a `RandomAccess` store. A client can
+/// attach shards one at a time as the user moves into new regions:
+///
+/// ```rust,no_run
+/// use eyros::{MultiDB,Error};
+/// use random_access_disk::RandomAccessDisk;
+/// use std::path::PathBuf;
+/// # fn main () -> Result<(),Error> {
+/// let mut multi: MultiDB<_,((f32,f32),(f32,f32)),u32> = MultiDB::new(|bundle,name| {
+///   let mut p = PathBuf::from("/tmp/eyros-shards/");
+///   p.push(bundle);
+///   p.push(name);
+///   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+/// });
+/// multi.attach_bundle("west")?;
+/// multi.attach_bundle("east")?;
+/// for result in multi.query(&((-10.0,-10.0),(10.0,10.0)))? {
+///   let (point,value,_location) = result?;
+///   // ...
+/// }
+/// # Ok(()) }
+/// ```
+pub struct MultiDB<S,P,V> where
+S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  open_bundle: OpenBundle<S>,
+  bundles: Vec<Bundle<S,P,V>>,
+  branch_factor: usize,
+  max_data_size: usize,
+  base_size: usize
+}
+
+impl<S,P,V> MultiDB<S,P,V> where
+S: RandomAccess<Error=failure::Error>+'static, P: Point, V: Value {
+  /// Create an empty collection that opens bundles with `open_bundle`.
+  pub fn new<F> (open_bundle: F) -> Self
+  where F: Fn(&str,&str) -> Result<S,failure::Error> + 'static {
+    Self {
+      open_bundle: Rc::new(open_bundle),
+      bundles: vec![],
+      branch_factor: 5,
+      max_data_size: 3_000,
+      base_size: 9_000
+    }
+  }
+  /// Set the branch factor the bundles were written with.
+  pub fn branch_factor (mut self, bf: usize) -> Self {
+    self.branch_factor = bf;
+    self
+  }
+  /// Set the maximum data block size the bundles were written with.
+  pub fn max_data_size (mut self, size: usize) -> Self {
+    self.max_data_size = size;
+    self
+  }
+  /// Set the base size the bundles were written with.
+  pub fn base_size (mut self, size: usize) -> Self {
+    self.base_size = size;
+    self
+  }
+  /// Open the bundle called `name` read-only and include it in queries.
+  /// Returns `false` if the bundle was already attached.
+  pub fn attach_bundle (&mut self, name: &str) -> Result<bool,Error> {
+    if self.bundles.iter().any(|(n,_)| n == name) {
+      return Ok(false);
+    }
+    let open_bundle = Rc::clone(&self.open_bundle);
+    let bundle = name.to_string();
+    let open_store: OpenStore<S> = Box::new(move |store: &str| {
+      open_bundle(&bundle, store)
+    });
+    let db = Setup::new(open_store)
+      .branch_factor(self.branch_factor)
+      .max_data_size(self.max_data_size)
+      .base_size(self.base_size)
+      .read_only(true)
+      .build()?;
+    self.bundles.push((name.to_string(),db));
+    Ok(true)
+  }
+  /// Close the bundle called `name` and leave it out of further queries.
+  /// Returns `false` if the bundle wasn't attached.
+  pub fn detach_bundle (&mut self, name: &str) -> Result<bool,Error> {
+    match self.bundles.iter().position(|(n,_)| n == name) {
+      Some(i) => {
+        let (_,mut db) = self.bundles.remove(i);
+        db.close()?;
+        Ok(true)
+      },
+      None => Ok(false)
+    }
+  }
+  /// Names of the attached bundles, in the order they were attached.
+  pub fn bundles (&self) -> Vec<&str> {
+    self.bundles.iter().map(|(n,_)| n.as_str()).collect()
+  }
+  /// Query every attached bundle for records that intersect the bounding
+  /// box.
+  ///
+  /// Results are not deduplicated, so rows that were copied into several
+  /// shards are returned once per shard. Each `Location` refers to the bundle
+  /// the row came from.
+  pub fn query<'b> (&mut self, bbox: &'b P::Bounds)
+  -> Result<MultiQueryIterator<'b,S,P,V>,Error> {
+    let mut queries = Vec::with_capacity(self.bundles.len());
+    for (_,db) in self.bundles.iter_mut() {
+      queries.push(db.query(bbox)?);
+    }
+    queries.reverse();
+    Ok(MultiQueryIterator { queries })
+  }
+}
+
+/// Iterator of `Result<(Point,Value,Location)>` data returned by
+/// `multi.query()`.
+pub struct MultiQueryIterator<'b,S,P,V> where
+S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  queries: Vec<QueryIterator<'b,S,P,V>>
+}
+
+impl<'b,S,P,V> Iterator for MultiQueryIterator<'b,S,P,V> where
+S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  type Item = Result<(P,V,Location),Error>;
+  fn next (&mut self) -> Option<Self::Item> {
+    while let Some(q) = self.queries.last_mut() {
+      match q.next() {
+        Some(result) => return Some(result),
+        None => { self.queries.pop(); }
+      }
+    }
+    None
+  }
+}
@@ -0,0 +1,200 @@
+use crate::{DB,Distance,Value,Location,CacheMode,Tree,
+  data::DataStore,visibility::VisibleFn,point::Cursor,tree::Extent,
+  admission::Permit,audit::Audit,AuditOp};
+use crate::Error;
+use random_access_storage::RandomAccess;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap,HashSet};
+use std::sync::{Arc,RwLock};
+use crate::lock::Lock;
+
+// heap entry ordered so that the smallest distance is popped first
+pub(crate) struct Nearer<T> {
+  pub(crate) dist: f64,
+  pub(crate) seq: u64,
+  pub(crate) item: T
+}
+
+impl<T> PartialEq for Nearer<T> {
+  fn eq (&self, other: &Self) -> bool { self.cmp(other) == Ordering::Equal }
+}
+impl<T> Eq for Nearer<T> {}
+impl<T> PartialOrd for Nearer<T> {
+  fn partial_cmp (&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+impl<T> Ord for Nearer<T> {
+  fn cmp (&self, other: &Self) -> Ordering {
+    other.dist.total_cmp(&self.dist).then(other.seq.cmp(&self.seq))
+  }
+}
+
+// part of the index waiting to be searched: a branch of the tree at an
+// index, or a data block
+enum Node {
+  Branch(usize,Cursor,Extent),
+  Block(u64)
+}
+
+/// Iterator of `Result<(Point,Value,Location)>` data in order of increasing
+/// distance, returned by `db.nearest_iter()`.
+pub struct NearestIterator<S,P,V> where
+S: RandomAccess<Error=failure::Error>, P: Distance, V: Value {
+  target: P::Target,
+  trees: Vec<Arc<RwLock<Tree<S,P,V>>>>,
+  data_store: Arc<RwLock<DataStore<S,P,V>>>,
+  deletes: Arc<RwLock<HashSet<Location>>>,
+  nodes: BinaryHeap<Nearer<Node>>,
+  loaded: HashSet<u64>,
+  rows: BinaryHeap<Nearer<(P,V,Location)>>,
+  visible: Option<VisibleFn<P,V>>,
+  seq: u64,
+  // event reported to `set_audit()` with the rows returned once dropped
+  audit: Option<Audit<P>>,
+  // slot under `Setup::max_queries()`, released when the iterator is dropped
+  _permit: Option<Permit>
+}
+
+impl<S,P,V> NearestIterator<S,P,V> where
+S: RandomAccess<Error=failure::Error>, P: Distance, V: Value {
+  fn push_row (&mut self, row: (P,V,Location)) {
+    if let Some(visible) = &self.visible {
+      if !visible(&row.0,&row.1) { return }
+    }
+    let dist = row.0.distance_sq(&self.target);
+    self.seq += 1;
+    self.rows.push(Nearer { dist, seq: self.seq, item: row });
+  }
+  fn push_node (&mut self, extent: &Extent, node: Node) {
+    let dist = P::axes_distance_sq(extent, &self.target);
+    self.seq += 1;
+    self.nodes.push(Nearer { dist, seq: self.seq, item: node });
+  }
+  fn expand (&mut self, tree: usize, cursor: Cursor, extent: &Extent)
+  -> Result<(),Error> {
+    let (cursors,blocks) = self.trees[tree].write_lock()?
+      .expand_block(cursor, extent)?;
+    for (c,e) in cursors {
+      self.push_node(&e, Node::Branch(tree,c,e.clone()));
+    }
+    for (offset,e) in blocks {
+      self.push_node(&e, Node::Block(offset));
+    }
+    Ok(())
+  }
+  fn load_block (&mut self, offset: u64) -> Result<(),Error> {
+    // a block can be referenced from more than one tree while they merge
+    if !self.loaded.insert(offset) { return Ok(()) }
+    let rows = self.data_store.write_lock()?
+      .list_or_quarantine(offset, CacheMode::Normal)?;
+    for row in rows.iter() {
+      if self.deletes.read_lock()?.contains(&row.2) { continue }
+      self.push_row(row.clone());
+    }
+    Ok(())
+  }
+}
+
+impl<S,P,V> Iterator for NearestIterator<S,P,V> where
+S: RandomAccess<Error=failure::Error>, P: Distance, V: Value {
+  type Item = Result<(P,V,Location),Error>;
+  fn next (&mut self) -> Option<Self::Item> {
+    loop {
+      // a row is final once nothing left to search can hold anything closer
+      let row_first = match (self.rows.peek(), self.nodes.peek()) {
+        (None, None) => return None,
+        (Some(_), None) => true,
+        (None, Some(_)) => false,
+        (Some(r), Some(b)) => r.dist <= b.dist
+      };
+      if row_first {
+        let row = self.rows.pop()?.item;
+        if let Some(audit) = &mut self.audit {
+          audit.cover(&row.0);
+        }
+        return Some(Ok(row));
+      }
+      let result = match self.nodes.pop().unwrap().item {
+        Node::Branch(tree,cursor,extent) => self.expand(tree, cursor, &extent),
+        Node::Block(offset) => self.load_block(offset)
+      };
+      if let Err(e) = result {
+        return Some(Err(e));
+      }
+    }
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Distance, V: Value {
+  /// Return the `k` rows closest to `target`, nearest first.
+  ///
+  /// The trees are searched best-first from their roots, in order of the
+  /// distance to the part of the index under each branch, so only branches
+  /// and data blocks that could hold one of the `k` nearest rows are read.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut db: DB<_,_,((f32,f32),(f32,f32)),u32> = DB::open(storage)?;
+  /// for (point,value,location) in db.nearest(&(0.3,-0.5), 5)? {
+  ///   // ...
+  /// }
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn nearest (&mut self, target: &P::Target, k: usize)
+  -> Result<Vec<(P,V,Location)>,Error> {
+    self.nearest_iter(target)?.take(k).collect()
+  }
+
+  /// Stream every row in order of increasing distance from `target`. Blocks
+  /// are read lazily, so stopping early skips the rest of the database.
+  ///
+  /// The iterator holds a slot under `Setup::max_queries()` like `query()`
+  /// until it is dropped, and is reported to `set_audit()` with the bounds of
+  /// the rows it returned.
+  pub fn nearest_iter (&mut self, target: &P::Target)
+  -> Result<NearestIterator<S,P,V>,Error> {
+    self.check_open()?;
+    let permit = self.admit()?;
+    let mut iter = NearestIterator {
+      target: *target,
+      trees: self.trees.clone(),
+      data_store: Arc::clone(&self.data_store),
+      deletes: Arc::clone(&self.staging.delete_set),
+      nodes: BinaryHeap::new(),
+      loaded: HashSet::new(),
+      rows: BinaryHeap::new(),
+      visible: self.visible.clone(),
+      seq: 0,
+      audit: self.audit_read(AuditOp::Nearest, None),
+      _permit: permit
+    };
+    let extent: Extent = vec![(f64::NEG_INFINITY,f64::INFINITY);P::dim()];
+    for (i,tree) in self.trees.iter().enumerate() {
+      let mut t = tree.write_lock()?;
+      if t.is_empty()? { continue }
+      let root = t.root()?;
+      if t.store.len()? <= root { continue }
+      iter.push_node(&extent, Node::Branch(i,(root,0),extent.clone()));
+    }
+    let inserts = self.staging.inserts.read_lock()?;
+    let deletes = self.staging.delete_set.read_lock()?;
+    for (i,(point,value)) in inserts.iter().enumerate() {
+      let location = (0,i as u32);
+      if deletes.contains(&location) { continue }
+      iter.push_row((*point,value.clone(),location));
+    }
+    Ok(iter)
+  }
+}
@@ -0,0 +1,98 @@
+use crate::{Point,Value};
+use crate::Error;
+use random_access_storage::RandomAccess;
+use desert::{ToBytes,FromBytes};
+
+/// Event appended to the outbox by a trigger.
+#[derive(Clone,Debug,PartialEq)]
+pub struct OutboxEvent<P,V> where P: Point, V: Value {
+  /// Byte offset of the event in the outbox.
+  pub offset: u64,
+  /// Offset of the event that follows this one.
+  pub next: u64,
+  /// Name of the trigger that matched.
+  pub trigger: String,
+  pub point: P,
+  pub value: V
+}
+
+/// Append-only log of trigger events. Each record is
+/// `[length (u32)][trigger name][point][value]`, where the length includes
+/// the length field itself.
+pub struct Outbox<S> where S: RandomAccess<Error=failure::Error> {
+  store: S
+}
+
+impl<S> Outbox<S> where S: RandomAccess<Error=failure::Error> {
+  /// Open the outbox in `store`. A record at the end that a crash cut short
+  /// is truncated away when `writable`, so later appends start at a record
+  /// boundary.
+  pub fn open (mut store: S, writable: bool) -> Result<Self,Error> {
+    let len = store.len()?;
+    let mut end = 0;
+    while end + 4 <= len {
+      let lbuf = store.read(end, 4)?;
+      let rlen = u32::from_be_bytes([lbuf[0],lbuf[1],lbuf[2],lbuf[3]]) as u64;
+      if rlen < 4 || end + rlen > len { break }
+      end += rlen;
+    }
+    if end < len && writable {
+      store.truncate(end)?;
+      store.sync_all()?;
+    }
+    Ok(Self { store })
+  }
+  /// Encode `events` as outbox records for `append_bytes()`.
+  pub fn encode<P,V> (events: &[(&str,&P,&V)]) -> Result<Vec<u8>,Error>
+  where P: Point, V: Value {
+    let mut buf = vec![];
+    for (name,point,value) in events.iter() {
+      let record = (name.as_bytes().to_vec(),**point,(*value).clone()).to_bytes()?;
+      buf.extend(&((record.len()+4) as u32).to_be_bytes());
+      buf.extend(record);
+    }
+    Ok(buf)
+  }
+  pub fn append_bytes (&mut self, buf: &[u8]) -> Result<(),Error> {
+    if buf.is_empty() { return Ok(()) }
+    let offset = self.store.len()?;
+    self.store.write(offset, buf)?;
+    self.store.sync_all()?;
+    Ok(())
+  }
+  pub fn len (&self) -> Result<u64,Error> {
+    Ok(self.store.len()?)
+  }
+  pub fn sync_all (&mut self) -> Result<(),Error> {
+    Ok(self.store.sync_all()?)
+  }
+  /// Read up to `limit` events starting at the event at `offset`.
+  pub fn read<P,V> (&mut self, offset: u64, limit: usize)
+  -> Result<Vec<OutboxEvent<P,V>>,Error> where P: Point, V: Value {
+    let end = self.store.len()?;
+    let mut events = vec![];
+    let mut offset = offset;
+    while offset < end && events.len() < limit {
+      // a record that is still being appended or that a crash cut short
+      if offset + 4 > end { break }
+      let lbuf = self.store.read(offset, 4)?;
+      let len = u32::from_be_bytes([lbuf[0],lbuf[1],lbuf[2],lbuf[3]]) as u64;
+      if len < 4 {
+        corrupt!["invalid outbox record length {} at offset {}", len, offset];
+      }
+      if offset + len > end { break }
+      let buf = self.store.read(offset+4, len-4)?;
+      let (_,(name,point,value)) = <(Vec<u8>,P,V)>::from_bytes(&buf)?;
+      events.push(OutboxEvent {
+        offset,
+        next: offset + len,
+        trigger: String::from_utf8(name)
+          .map_err(|e| Error::Corrupt(format!["trigger name: {}", e]))?,
+        point,
+        value
+      });
+      offset += len;
+    }
+    Ok(events)
+  }
+}
@@ -0,0 +1,133 @@
+use crate::{DB,Point,Value,QueryIterator,SubIterator,SharedRow,TreeIterator};
+use crate::Error;
+use random_access_storage::RandomAccess;
+use std::sync::{Arc,Mutex,mpsc};
+use std::thread;
+
+// rows each worker can read ahead of the consumer
+const READ_AHEAD: usize = 256;
+
+/// Rows of several trees read on worker threads, in the order the workers
+/// produce them. Created by `db.query_parallel()`.
+///
+/// Workers stop at their next row once the iterator is dropped.
+pub struct ParallelIterator<P,V> where P: Point, V: Value {
+  receiver: mpsc::Receiver<Result<SharedRow<P,V>,Error>>
+}
+
+impl<P,V> ParallelIterator<P,V> where
+P: Point+Send+Sync+'static, V: Value+Send+Sync,
+P::Bounds: Send+Sync+'static, P::Range: Send+Sync {
+  // with `owned`, rows are copied out of their blocks before they are sent,
+  // so rows waiting for the consumer don't hold blocks
+  fn spawn<S> (trees: Vec<TreeIterator<'static,S,P,V>>, threads: usize,
+  owned: bool) -> Self where S: RandomAccess<Error=failure::Error>+Send+Sync+'static {
+    let workers = threads.min(trees.len());
+    // workers pop trees from the end
+    let queue = Arc::new(Mutex::new(trees.into_iter().rev().collect::<Vec<_>>()));
+    let (sender,receiver) = mpsc::sync_channel(READ_AHEAD*workers);
+    for _ in 0..workers {
+      let queue = Arc::clone(&queue);
+      let sender = sender.clone();
+      thread::spawn(move || loop {
+        let tree = match queue.lock() {
+          Ok(mut q) => q.pop(),
+          Err(_) => None
+        };
+        let mut tree = match tree {
+          Some(tree) => tree,
+          None => return
+        };
+        while let Some(mut result) = tree.next_shared() {
+          if owned {
+            result = result.map(|row| SharedRow::Owned(row.into_owned()));
+          }
+          let failed = result.is_err();
+          if sender.send(result).is_err() { return } // query dropped
+          if failed { break }
+        }
+      });
+    }
+    Self { receiver }
+  }
+}
+
+impl<P,V> ParallelIterator<P,V> where P: Point, V: Value {
+  pub(crate) fn next_shared (&mut self) -> Option<Result<SharedRow<P,V>,Error>> {
+    self.receiver.recv().ok()
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>+Send+Sync+'static,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point+Send+Sync+'static, V: Value+Send+Sync,
+P::Bounds: Send+Sync+'static, P::Range: Send+Sync {
+  /// Query the database like `query()`, but read the trees on up to
+  /// `Setup::query_threads()` worker threads while the staging area is read on
+  /// the calling thread.
+  ///
+  /// Results from different trees arrive in whichever order the workers
+  /// produce them. Branches are walked and blocks filtered concurrently, but
+  /// the trees share one data store, so blocks are still loaded from storage
+  /// one at a time. Parallel queries help most when blocks are already cached
+  /// or the bounding box matches a small part of each block.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Setup,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// let mut db: DB<_,_,((f32,f32),(f32,f32)),u32> = Setup::new(storage)
+  ///   .query_threads(4)
+  ///   .build()?;
+  /// let bbox = ((-0.5,-0.8),(0.3,-0.5));
+  /// for result in db.query_parallel(&bbox)? {
+  ///   let (point,value,location) = result?;
+  ///   // ...
+  /// }
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  ///
+  /// The number of workers is also capped by `max_open_trees()`. Without
+  /// threads (wasm) or with one thread, this is the same as `query()`.
+  pub fn query_parallel<'b> (&mut self, bbox: &'b P::Bounds)
+  -> Result<QueryIterator<'b,S,P,V>,Error> {
+    let threads = self.fields.query_threads;
+    Ok(self.query(bbox)?.parallel(threads))
+  }
+}
+
+impl<'b,S,P,V> QueryIterator<'b,S,P,V> where
+S: RandomAccess<Error=failure::Error>+Send+Sync+'static,
+P: Point+Send+Sync+'static, V: Value+Send+Sync,
+P::Bounds: Send+Sync+'static, P::Range: Send+Sync {
+  pub(crate) fn parallel (mut self, threads: usize) -> Self {
+    let threads = threads.min(self.open_trees.unwrap_or(usize::MAX));
+    let trees = self.queries.iter()
+      .filter(|q| matches![q, SubIterator::Tree(_)]).count();
+    if threads <= 1 || trees <= 1 || cfg!(target_arch="wasm32") {
+      return self;
+    }
+    let mut queries = Vec::with_capacity(2);
+    let mut trees = Vec::with_capacity(trees);
+    for q in self.queries.drain(..) {
+      match q {
+        SubIterator::Tree(x) => trees.push(x.detach()),
+        q => queries.push(q)
+      }
+    }
+    let owned = self.buffered_blocks.is_some();
+    queries.push(SubIterator::Parallel(Box::new(
+      ParallelIterator::spawn(trees, threads, owned)
+    )));
+    self.queries = queries;
+    self.index = 0;
+    self
+  }
+}
@@ -1,6 +1,6 @@
 use std::cmp::Ordering;
 use std::ops::{Div,Add};
-use failure::{Error,format_err};
+use crate::Error;
 use std::fmt::Debug;
 use std::mem::size_of;
 use crate::order;
@@ -32,6 +32,13 @@ pub trait Point: Copy+Clone+Debug+ToBytes+FromBytes+CountBytes {
   /// at an index corresponding to `level % dimension`.
   fn cmp_at (&self, other: &Self, level: usize) -> Ordering where Self: Sized;
 
+  /// Compare the upper bounds of elements at a level of tree depth, which is
+  /// the value pivots are made from. Unlike `cmp_at()`, overlapping intervals
+  /// aren't equal, so this is a total order that is safe to sort by.
+  fn cmp_upper_at (&self, other: &Self, level: usize) -> Ordering where Self: Sized {
+    self.cmp_at(other, level)
+  }
+
   /// For intervals, calculate the midpoint of the greater (upper) interval
   /// bound (ex: `iv.1`) for two intervals, returning a new interval where both
   /// elements are the midpoint result.
@@ -49,6 +56,26 @@ pub trait Point: Copy+Clone+Debug+ToBytes+FromBytes+CountBytes {
   /// Return whether the current point intersects with a bounding box.
   fn overlaps (&self, bbox: &Self::Bounds) -> bool;
 
+  /// Return whether the current point lies entirely inside of a bounding box.
+  /// The default compares the extents from `bounds_axes()` and falls back to
+  /// `overlaps()` for point types without them.
+  fn within (&self, bbox: &Self::Bounds) -> bool {
+    match point_axes(self).zip(Self::bounds_axes(bbox)) {
+      Some((p,q)) => p.iter().zip(q.iter()).all(|(a,b)| b.0 <= a.0 && a.1 <= b.1),
+      None => self.overlaps(bbox)
+    }
+  }
+
+  /// Return whether the current point covers all of a bounding box, so that
+  /// interval points can be stabbed with a point or a region. The default
+  /// works like the default of `within()`.
+  fn contains (&self, bbox: &Self::Bounds) -> bool {
+    match point_axes(self).zip(Self::bounds_axes(bbox)) {
+      Some((p,q)) => p.iter().zip(q.iter()).all(|(a,b)| a.0 <= b.0 && b.1 <= a.1),
+      None => self.overlaps(bbox)
+    }
+  }
+
   /// Return the size in bytes of the pivot-form of the element corresponding to
   /// the tree depth `level`.
   fn pivot_bytes_at (&self, level: usize) -> usize;
@@ -64,6 +91,26 @@ pub trait Point: Copy+Clone+Debug+ToBytes+FromBytes+CountBytes {
   fn query_branch (buf: &[u8], bbox: &Self::Bounds, branch_factor: usize,
     level: usize) -> Result<(Vec<Cursor>,Vec<Block>),Error>;
 
+  /// Return the pivots of the branch in `buf` at the tree depth `level` as
+  /// `f64`, in ascending order, so that best-first searches such as
+  /// `db.nearest()` can bound the rows of each bucket by the pivots on either
+  /// side of it. The default returns `None`, which bounds every child of a
+  /// branch by the bounds of the branch itself.
+  fn pivots_at (_buf: &[u8], _branch_factor: usize, _level: usize)
+  -> Result<Option<Vec<f64>>,Error> {
+    Ok(None)
+  }
+
+  /// Narrow `range`, a range that holds every row under the branch in `buf`
+  /// at the tree depth `level`, to a range for each of the `branch_factor`
+  /// buckets of the branch, like `pivots_at()` but without leaving the
+  /// element types. The default returns `None`, which leaves every bucket
+  /// with `range`.
+  fn bucket_ranges (_buf: &[u8], _range: &Self::Range, _branch_factor: usize,
+  _level: usize) -> Result<Option<Vec<Self::Range>>,Error> {
+    Ok(None)
+  }
+
   /// Return a bounding box for a set of coordinates, if possible.
   fn bounds (coords: &Vec<Self>) -> Option<Self::Bounds>;
 
@@ -77,6 +124,33 @@ pub trait Point: Copy+Clone+Debug+ToBytes+FromBytes+CountBytes {
   /// `((-1.0,3.0),(0.0,0.8),(-4.0,2.5))` (range)
   fn bounds_to_range (bbox: Self::Bounds) -> Self::Range;
 
+  /// Return whether every point inside of `range` also lies inside of `bbox`,
+  /// so that aggregate queries can count a whole data block without reading
+  /// its rows. The default always returns `false`.
+  fn range_within (_range: &Self::Range, _bbox: &Self::Bounds) -> bool {
+    false
+  }
+
+  /// Return the bounding box that covers `range`, so that hooks such as
+  /// `QueryRegion` can test data blocks without reading them. The default
+  /// returns `None`.
+  fn range_bounds (_range: &Self::Range) -> Option<Self::Bounds> {
+    None
+  }
+
+  /// Return a bounding box covering both `a` and `b`, if possible.
+  /// The default returns `None`.
+  fn union_bounds (_a: &Self::Bounds, _b: &Self::Bounds) -> Option<Self::Bounds> {
+    None
+  }
+
+  /// Return the `(min,max)` extent of `bbox` along each axis as `f64`, for
+  /// the per-axis histograms that `Setup::selectivity_order()` keeps. The
+  /// default returns `None`, which leaves queries in their usual order.
+  fn bounds_axes (_bbox: &Self::Bounds) -> Option<Vec<(f64,f64)>> {
+    None
+  }
+
   /// Return a string representation of the element in a buffer slice
   /// corresponding to the tree depth level.
   fn format_at (buf: &[u8], level: usize)
@@ -84,9 +158,9 @@ pub trait Point: Copy+Clone+Debug+ToBytes+FromBytes+CountBytes {
 }
 
 pub trait Num<T>: PartialOrd+Copy+ToBytes+FromBytes+CountBytes
-  +Debug+Scalar+From<u8>+Div<T,Output=T>+Add<T,Output=T> {}
+  +Debug+Scalar+ToF64+Midpoint+Div<T,Output=T>+Add<T,Output=T> {}
 impl<T> Num<T> for T where T: PartialOrd+Copy+ToBytes+FromBytes+CountBytes
-  +Debug+Scalar+From<u8>+Div<T,Output=T>+Add<T,Output=T> {}
+  +Debug+Scalar+ToF64+Midpoint+Div<T,Output=T>+Add<T,Output=T> {}
 
 /// Types representing a single value (as opposed to an interval, which has
 /// minimum and maximum values).
@@ -104,11 +178,21 @@ impl Scalar for i16 {}
 impl Scalar for i32 {}
 impl Scalar for i64 {}
 
+// Extent of `point` along each axis, from the bounds of the point alone.
+#[cfg_attr(test, allow(dead_code))]
+fn point_axes<P> (point: &P) -> Option<Vec<(f64,f64)>> where P: Point {
+  P::bounds(&vec![*point]).and_then(|b| P::bounds_axes(&b))
+}
+
 trait Coord<T> {
   fn cmp (&self, other: &Self) -> Option<Ordering>;
   fn midpoint_upper (&self, other: &Self) -> Self;
   fn upper (&self) -> T;
   fn overlaps (&self, a: &T, b: &T) -> bool;
+  #[cfg_attr(test, allow(dead_code))]
+  fn within (&self, a: &T, b: &T) -> bool;
+  #[cfg_attr(test, allow(dead_code))]
+  fn contains (&self, a: &T, b: &T) -> bool;
   fn bounds (coords: Vec<&Self>) -> Option<(T,T)>;
 }
 
@@ -117,12 +201,18 @@ impl<T> Coord<T> for T where T: Scalar+PartialOrd+Num<T> {
     self.partial_cmp(&other)
   }
   fn midpoint_upper (&self, other: &Self) -> Self {
-    (*self + *other) / 2.into()
+    Midpoint::midpoint(*self, *other)
   }
   fn upper (&self) -> T { *self }
   fn overlaps (&self, min: &T, max: &T) -> bool {
     *min <= *self && *self <= *max
   }
+  fn within (&self, min: &T, max: &T) -> bool {
+    self.overlaps(min, max)
+  }
+  fn contains (&self, min: &T, max: &T) -> bool {
+    *self <= *min && *max <= *self
+  }
   fn bounds (coords: Vec<&Self>) -> Option<(T,T)> {
     if coords.len() == 0 { return None }
     let mut min = coords[0];
@@ -153,13 +243,19 @@ impl<T> Coord<T> for (T,T) where T: Scalar+PartialOrd+Num<T> {
     }
   }
   fn midpoint_upper (&self, other: &Self) -> Self {
-    let x = self.1/2.into() + other.1/2.into();
+    let x = Midpoint::midpoint(self.1, other.1);
     (x,x)
   }
   fn upper (&self) -> T { self.1 }
   fn overlaps (&self, min: &T, max: &T) -> bool {
     *min <= self.1 && self.0 <= *max
   }
+  fn within (&self, min: &T, max: &T) -> bool {
+    *min <= self.0 && self.1 <= *max
+  }
+  fn contains (&self, min: &T, max: &T) -> bool {
+    self.0 <= *min && *max <= self.1
+  }
   fn bounds (coords: Vec<&Self>) -> Option<(T,T)> {
     if coords.len() == 0 { return None }
     let mut min = coords[0].0;
@@ -181,6 +277,94 @@ impl<T> Coord<T> for (T,T) where T: Scalar+PartialOrd+Num<T> {
   }
 }
 
+/// Scalar types that can be converted to `f64` to measure distances.
+pub trait ToF64: Copy {
+  #[cfg_attr(test, allow(dead_code))]
+  fn to_f64 (&self) -> f64;
+}
+
+macro_rules! impl_to_f64 {
+  ($($T:ty),+) => {
+    $(impl ToF64 for $T {
+      fn to_f64 (&self) -> f64 { *self as f64 }
+    })+
+  }
+}
+impl_to_f64![f32,f64,u8,u16,u32,u64,i8,i16,i32,i64];
+
+/// Scalar types with a midpoint that doesn't overflow, used to pick pivots.
+pub trait Midpoint: Copy {
+  fn midpoint (a: Self, b: Self) -> Self;
+}
+
+macro_rules! impl_midpoint {
+  (float: $($T:ty),+) => {
+    $(impl Midpoint for $T {
+      fn midpoint (a: Self, b: Self) -> Self { a/2.0 + b/2.0 }
+    })+
+  };
+  (int: $($T:ty),+) => {
+    $(impl Midpoint for $T {
+      // halve first so that coordinates near the limits of the type don't
+      // overflow, then add back the remainders that halving dropped
+      fn midpoint (a: Self, b: Self) -> Self { a/2 + b/2 + (a%2 + b%2)/2 }
+    })+
+  };
+}
+impl_midpoint![float: f32,f64];
+impl_midpoint![int: u8,u16,u32,u64,i8,i16,i32,i64];
+
+/// Points that support nearest-neighbor queries with `db.nearest()`.
+///
+/// Distances are squared euclidean distances between a scalar target and the
+/// closest part of each element, so an interval that contains the target
+/// coordinate contributes nothing. Elements of different scalar types are
+/// compared as `f64` without scaling.
+#[cfg_attr(test, allow(dead_code))]
+pub trait Distance: Point {
+  /// Scalar point to measure distances from, such as `(f32,f32)`.
+  type Target: Copy+Clone+Debug;
+
+  /// Squared distance from the target to this point.
+  fn distance_sq (&self, target: &Self::Target) -> f64;
+
+  /// Squared distance from the target to the nearest edge of a bounding box,
+  /// or `0.0` if the target is inside.
+  fn bounds_distance_sq (bbox: &Self::Bounds, target: &Self::Target) -> f64;
+
+  /// Squared distance from the target to a range, as stored for each data
+  /// block.
+  fn range_distance_sq (range: &Self::Range, target: &Self::Target) -> f64;
+
+  /// Squared distance from the target to a region given as the `(min,max)`
+  /// extent along each axis, such as the part of the index under a tree
+  /// branch. The default returns `0.0`, so that every branch is searched.
+  fn axes_distance_sq (_axes: &[(f64,f64)], _target: &Self::Target) -> f64 {
+    0.0
+  }
+}
+
+#[cfg_attr(test, allow(dead_code))]
+pub(crate) fn interval_gap<T> (min: T, max: T, x: T) -> f64 where T: ToF64 {
+  let (min,max,x) = (min.to_f64(),max.to_f64(),x.to_f64());
+  if x < min { min - x }
+  else if x > max { x - max }
+  else { 0.0 }
+}
+
+#[cfg_attr(test, allow(dead_code))]
+trait Gap<T> {
+  fn gap (&self, x: &T) -> f64;
+}
+
+impl<T> Gap<T> for T where T: Scalar+ToF64 {
+  fn gap (&self, x: &T) -> f64 { interval_gap(*self, *self, *x) }
+}
+
+impl<T> Gap<T> for (T,T) where T: Scalar+ToF64 {
+  fn gap (&self, x: &T) -> f64 { interval_gap(self.0, self.1, *x) }
+}
+
 macro_rules! impl_point {
   (($($T:tt),+),($($U:tt),+),($($i:tt),+),$dim:expr) => {
     impl<$($T),+> Point for ($($U),+)
@@ -194,6 +378,13 @@ macro_rules! impl_point {
         };
         match order { Some(x) => x, None => Ordering::Less }
       }
+      fn cmp_upper_at (&self, other: &Self, level: usize) -> Ordering {
+        let order = match level%Self::dim() {
+          $($i => self.$i.upper().partial_cmp(&other.$i.upper()),)+
+          _ => panic!("match case beyond dimension")
+        };
+        order.unwrap_or(Ordering::Equal)
+      }
       fn midpoint_upper (&self, other: &Self) -> Self {
         ($(
           Coord::midpoint_upper(&self.$i, &other.$i)
@@ -202,7 +393,7 @@ macro_rules! impl_point {
       fn serialize_at (&self, level: usize, dst: &mut [u8])
       -> Result<usize,Error> {
         match level%Self::dim() {
-          $($i => self.$i.upper().write_bytes(dst),)+
+          $($i => Ok(self.$i.upper().write_bytes(dst)?),)+
           _ => panic!("match case beyond dimension")
         }
       }
@@ -210,6 +401,12 @@ macro_rules! impl_point {
       fn overlaps (&self, bbox: &Self::Bounds) -> bool {
         $(Coord::overlaps(&self.$i, &(bbox.0).$i, &(bbox.1).$i) &&)+ true
       }
+      fn within (&self, bbox: &Self::Bounds) -> bool {
+        $(Coord::within(&self.$i, &(bbox.0).$i, &(bbox.1).$i) &&)+ true
+      }
+      fn contains (&self, bbox: &Self::Bounds) -> bool {
+        $(Coord::contains(&self.$i, &(bbox.0).$i, &(bbox.1).$i) &&)+ true
+      }
       fn pivot_bytes_at (&self, i: usize) -> usize {
         match i % $dim {
           $($i => size_of::<$T>(),)+
@@ -218,7 +415,7 @@ macro_rules! impl_point {
       }
       fn count_bytes_at (buf: &[u8], i: usize) -> Result<usize,Error> {
         match i % $dim {
-          $($i => $T::count_from_bytes(buf),)+
+          $($i => Ok($T::count_from_bytes(buf)?),)+
           _ => panic!("dimension out of bounds")
         }
       }
@@ -314,6 +511,45 @@ macro_rules! impl_point {
         }
         Ok((cursors,blocks))
       }
+      fn pivots_at (buf: &[u8], bf: usize, level: usize)
+      -> Result<Option<Vec<f64>>,Error> {
+        let n = order::order_len(bf);
+        let mut pivots = Vec::with_capacity(n);
+        let mut offset = 0;
+        for _i in 0..n {
+          match level % $dim {
+            $($i => {
+              let (size,x) = $T::from_bytes(&buf[offset..])?;
+              pivots.push(x.to_f64());
+              offset += size;
+            },)+
+            _ => panic!["dimension out of bounds"]
+          };
+        }
+        Ok(Some(pivots))
+      }
+      fn bucket_ranges (buf: &[u8], range: &Self::Range, bf: usize, level: usize)
+      -> Result<Option<Vec<Self::Range>>,Error> {
+        let n = order::order_len(bf);
+        match level % $dim {
+          $($i => {
+            let mut pivots = Vec::with_capacity(n);
+            let mut offset = 0;
+            for _i in 0..n {
+              let (size,x) = $T::from_bytes(&buf[offset..])?;
+              pivots.push(x);
+              offset += size;
+            }
+            Ok(Some((0..bf).map(|k| {
+              let mut r = *range;
+              if k > 0 && (r.$i).0 < pivots[2*(k-1)] { (r.$i).0 = pivots[2*(k-1)] }
+              if k < bf-1 && pivots[2*k] < (r.$i).1 { (r.$i).1 = pivots[2*k] }
+              r
+            }).collect()))
+          },)+
+          _ => panic!["dimension out of bounds"]
+        }
+      }
       fn bounds (points: &Vec<Self>) -> Option<Self::Bounds> {
         if points.is_empty() { return None }
         let pairs = ($({
@@ -332,6 +568,21 @@ macro_rules! impl_point {
       fn bounds_to_range (bounds: Self::Bounds) -> Self::Range {
         ($(((bounds.0).$i,(bounds.1).$i)),+)
       }
+      fn range_within (range: &Self::Range, bbox: &Self::Bounds) -> bool {
+        $((bbox.0).$i <= (range.$i).0 && (range.$i).1 <= (bbox.1).$i &&)+ true
+      }
+      fn range_bounds (range: &Self::Range) -> Option<Self::Bounds> {
+        Some((($((range.$i).0,)+),($((range.$i).1,)+)))
+      }
+      fn union_bounds (a: &Self::Bounds, b: &Self::Bounds) -> Option<Self::Bounds> {
+        Some((
+          ($(if (b.0).$i < (a.0).$i { (b.0).$i } else { (a.0).$i },)+),
+          ($(if (b.1).$i > (a.1).$i { (b.1).$i } else { (a.1).$i },)+)
+        ))
+      }
+      fn bounds_axes (bbox: &Self::Bounds) -> Option<Vec<(f64,f64)>> {
+        Some(vec![$(((bbox.0).$i.to_f64(),(bbox.1).$i.to_f64())),+])
+      }
       fn format_at (buf: &[u8], level: usize) -> Result<String,Error> {
         Ok(match level % Self::dim() {
           $($i => {
@@ -342,6 +593,34 @@ macro_rules! impl_point {
         })
       }
     }
+    impl<$($T),+> Distance for ($($U),+)
+    where $($T: Num<$T>+ToF64),+ {
+      type Target = ($($T,)+);
+      fn distance_sq (&self, target: &Self::Target) -> f64 {
+        0.0 $(+ {
+          let d = <$U as Gap<$T>>::gap(&self.$i, &target.$i);
+          d*d
+        })+
+      }
+      fn bounds_distance_sq (bbox: &Self::Bounds, target: &Self::Target) -> f64 {
+        0.0 $(+ {
+          let d = interval_gap((bbox.0).$i, (bbox.1).$i, target.$i);
+          d*d
+        })+
+      }
+      fn range_distance_sq (range: &Self::Range, target: &Self::Target) -> f64 {
+        0.0 $(+ {
+          let d = interval_gap((range.$i).0, (range.$i).1, target.$i);
+          d*d
+        })+
+      }
+      fn axes_distance_sq (axes: &[(f64,f64)], target: &Self::Target) -> f64 {
+        0.0 $(+ {
+          let d = interval_gap(axes[$i].0, axes[$i].1, target.$i.to_f64());
+          d*d
+        })+
+      }
+    }
   }
 }
 
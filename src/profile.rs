@@ -0,0 +1,118 @@
+use crate::{Setup,RetryPolicy,Compression};
+use random_access_storage::RandomAccess;
+use std::time::Duration;
+
+/// Deployment environment for `Setup::preset()`.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Profile {
+  /// Local disk or SSD on a server or desktop: cheap reads, plenty of memory
+  /// and cores.
+  LocalSSD,
+  /// Browser storage such as IndexedDB, reached from a single thread with a
+  /// modest memory budget and writes that arrive a few rows at a time.
+  Browser,
+  /// Remote object store such as S3: every read is a slow round trip that
+  /// can fail transiently, and locks aren't available.
+  ObjectStore,
+  /// Small device with little memory, where power can drop at any time.
+  Embedded
+}
+
+impl<S,U> Setup<S,U> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>) {
+  /// Configure block sizes, cache budgets, merge policy, and durability for
+  /// the environment described by `profile`.
+  ///
+  /// A preset only sets builder options, so call it first and override any
+  /// of its choices with later calls:
+  ///
+  /// ```rust,no_run
+  /// use eyros::{DB,Setup,Profile,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// let mut db: DB<_,_,(f32,f32),u32> = Setup::new(storage)
+  ///   .preset(Profile::LocalSSD)
+  ///   .cache_bytes(1 << 30)
+  ///   .build()?;
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  ///
+  /// | | `LocalSSD` | `Browser` | `ObjectStore` | `Embedded` |
+  /// |---|---|---|---|---|
+  /// | `max_data_size` | 3000 | 1000 | 10000 | 500 |
+  /// | `base_size` | 9000 | 3000 | 30000 | 2000 |
+  /// | `cache_bytes` | 256 MB | 32 MB | 512 MB | 4 MB |
+  /// | `max_dirty_bytes` | 64 MB | 8 MB | 64 MB | 1 MB |
+  /// | `query_threads` | 4 | 1 | 8 | 1 |
+  /// | `max_trees` | 12 | 8 | 6 | 8 |
+  /// | `absorb` | off | 1000 into 30000 | off | 500 into 10000 |
+  /// | `wal` | on | off | on | on |
+  ///
+  /// `ObjectStore` also reads sibling branches in runs of up to 64 KB with
+  /// `coalesce_branches()`, retries transient read errors with backoff,
+  /// turns on `check_conflicts()`, and compresses blocks with zstd or lz4
+  /// when one of those features is enabled. `Embedded` reads 2 trees at a
+  /// time per query with `max_open_trees()`. `Browser` skips the write-ahead
+  /// log since browser storage commits each write on its own anyway.
+  pub fn preset (self, profile: Profile) -> Self {
+    match profile {
+      Profile::LocalSSD => self
+        .max_data_size(3_000)
+        .base_size(9_000)
+        .cache_bytes(256 << 20)
+        .max_dirty_bytes(64 << 20)
+        .query_threads(4)
+        .max_trees(12)
+        .wal(true),
+      Profile::Browser => self
+        .max_data_size(1_000)
+        .base_size(3_000)
+        .cache_bytes(32 << 20)
+        .max_dirty_bytes(8 << 20)
+        .query_threads(1)
+        .max_trees(8)
+        .absorb(1_000, 30_000)
+        .wal(false),
+      Profile::ObjectStore => self
+        .max_data_size(10_000)
+        .base_size(30_000)
+        .cache_bytes(512 << 20)
+        .max_dirty_bytes(64 << 20)
+        .query_threads(8)
+        .max_trees(6)
+        .wal(true)
+        .check_conflicts(true)
+        .coalesce_branches(64 << 10)
+        .retry(RetryPolicy::new(5)
+          .backoff(Duration::from_millis(100))
+          .max_backoff(Duration::from_secs(5)))
+        .compression(remote_compression()),
+      Profile::Embedded => self
+        .max_data_size(500)
+        .base_size(2_000)
+        .bbox_cache_size(1_000)
+        .data_list_cache_size(256)
+        .cache_bytes(4 << 20)
+        .max_dirty_bytes(1 << 20)
+        .query_threads(1)
+        .max_open_trees(2)
+        .max_trees(8)
+        .absorb(500, 10_000)
+        .wal(true)
+    }
+  }
+}
+
+// round trips cost more than cpu for remote stores, so compress if possible
+fn remote_compression () -> Compression {
+  if cfg!(feature="zstd") { Compression::Zstd(3) }
+  else if cfg!(feature="lz4") { Compression::Lz4 }
+  else { Compression::None }
+}
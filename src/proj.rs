@@ -0,0 +1,181 @@
+use crate::{DB,Point,Value,Location,QueryIterator,CacheMode,AuditOp};
+use crate::Error;
+use random_access_storage::RandomAccess;
+use std::f64::consts::PI;
+
+/// Transform between the coordinate reference system that points are stored
+/// in and a target system that results are requested in.
+///
+/// Implement this trait to plug in other projection libraries. Bounding boxes
+/// are transformed through their corners, which is exact for projections that
+/// transform each axis independently (such as `WebMercator`) and an
+/// approximation for others.
+pub trait Projection {
+  /// Transform a stored `(x,y)` coordinate into the target system.
+  fn forward (&self, x: f64, y: f64) -> (f64,f64);
+  /// Transform a coordinate in the target system back into the stored system.
+  fn inverse (&self, x: f64, y: f64) -> (f64,f64);
+}
+
+/// Spherical Web Mercator (EPSG:3857) in meters, for points stored as
+/// `(longitude,latitude)` degrees (EPSG:4326).
+///
+/// Latitudes are clamped to the `±85.0511°` range that the projection covers.
+#[derive(Clone,Copy,Debug,Default)]
+pub struct WebMercator;
+
+const RADIUS: f64 = 6_378_137.0;
+const MAX_LATITUDE: f64 = 85.051_128_779_806_59;
+
+impl Projection for WebMercator {
+  fn forward (&self, lon: f64, lat: f64) -> (f64,f64) {
+    let lat = lat.max(-MAX_LATITUDE).min(MAX_LATITUDE).to_radians();
+    (RADIUS * lon.to_radians(), RADIUS * (PI/4.0 + lat/2.0).tan().ln())
+  }
+  fn inverse (&self, x: f64, y: f64) -> (f64,f64) {
+    let lat = 2.0 * (y / RADIUS).exp().atan() - PI/2.0;
+    ((x / RADIUS).to_degrees(), lat.to_degrees())
+  }
+}
+
+/// Floating point coordinate types that projections can convert.
+pub trait Coordinate: Copy {
+  fn to_f64 (self) -> f64;
+  fn from_f64 (x: f64) -> Self;
+}
+
+impl Coordinate for f32 {
+  fn to_f64 (self) -> f64 { self as f64 }
+  fn from_f64 (x: f64) -> Self { x as f32 }
+}
+
+impl Coordinate for f64 {
+  fn to_f64 (self) -> f64 { self }
+  fn from_f64 (x: f64) -> Self { x }
+}
+
+/// Two-dimensional points that can be reprojected with a `Projection`.
+pub trait Projectable: Point {
+  /// Transform this point into the target system of `proj`.
+  fn project<T> (&self, proj: &T) -> Self where T: Projection+?Sized;
+  /// Transform a bounding box in the target system of `proj` back into the
+  /// stored system.
+  fn unproject_bounds<T> (bbox: &Self::Bounds, proj: &T) -> Self::Bounds
+  where T: Projection+?Sized;
+}
+
+fn hull<F> (x0: f64, y0: f64, x1: f64, y1: f64, f: F) -> ((f64,f64),(f64,f64))
+where F: Fn(f64,f64) -> (f64,f64) {
+  let corners = [f(x0,y0),f(x0,y1),f(x1,y0),f(x1,y1)];
+  let mut min = corners[0];
+  let mut max = corners[0];
+  for c in corners.iter().skip(1) {
+    min = (min.0.min(c.0), min.1.min(c.1));
+    max = (max.0.max(c.0), max.1.max(c.1));
+  }
+  (min,max)
+}
+
+fn unproject<C,T> (bbox: &((C,C),(C,C)), proj: &T) -> ((C,C),(C,C))
+where C: Coordinate, T: Projection+?Sized {
+  let ((x0,y0),(x1,y1)) = *bbox;
+  let (min,max) = hull(x0.to_f64(), y0.to_f64(), x1.to_f64(), y1.to_f64(),
+    |x,y| proj.inverse(x,y));
+  ((C::from_f64(min.0),C::from_f64(min.1)),(C::from_f64(max.0),C::from_f64(max.1)))
+}
+
+macro_rules! impl_projectable {
+  ($($T:ty),+) => {$(
+    impl Projectable for ($T,$T) {
+      fn project<T> (&self, proj: &T) -> Self where T: Projection+?Sized {
+        let (x,y) = proj.forward(self.0.to_f64(), self.1.to_f64());
+        (<$T>::from_f64(x), <$T>::from_f64(y))
+      }
+      fn unproject_bounds<T> (bbox: &Self::Bounds, proj: &T) -> Self::Bounds
+      where T: Projection+?Sized {
+        unproject(bbox, proj)
+      }
+    }
+    impl Projectable for (($T,$T),($T,$T)) {
+      fn project<T> (&self, proj: &T) -> Self where T: Projection+?Sized {
+        let ((x0,x1),(y0,y1)) = *self;
+        let (min,max) = hull(x0.to_f64(), y0.to_f64(), x1.to_f64(), y1.to_f64(),
+          |x,y| proj.forward(x,y));
+        (
+          (<$T>::from_f64(min.0),<$T>::from_f64(max.0)),
+          (<$T>::from_f64(min.1),<$T>::from_f64(max.1))
+        )
+      }
+      fn unproject_bounds<T> (bbox: &Self::Bounds, proj: &T) -> Self::Bounds
+      where T: Projection+?Sized {
+        unproject(bbox, proj)
+      }
+    }
+  )+}
+}
+impl_projectable![f32,f64];
+
+/// Iterator of `Result<(Point,Value,Location)>` data with points in the target
+/// system, returned by `db.query_projected()`.
+pub struct ProjectedQuery<'a,S,P,V,T> where
+S: RandomAccess<Error=failure::Error>, P: Projectable, V: Value, T: Projection+?Sized {
+  iter: QueryIterator<'a,S,P,V>,
+  bbox: P::Bounds,
+  proj: &'a T
+}
+
+impl<'a,S,P,V,T> Iterator for ProjectedQuery<'a,S,P,V,T> where
+S: RandomAccess<Error=failure::Error>, P: Projectable, V: Value, T: Projection+?Sized {
+  type Item = Result<(P,V,Location),Error>;
+  fn next (&mut self) -> Option<Self::Item> {
+    loop {
+      let (point,value,location) = match self.iter.next()? {
+        Ok(row) => row,
+        Err(e) => return Some(Err(e))
+      };
+      let point = point.project(self.proj);
+      // the unprojected bbox can be wider than the requested area
+      if point.overlaps(&self.bbox) {
+        return Some(Ok((point,value,location)));
+      }
+    }
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Projectable, V: Value {
+  /// Query with a `bbox` given in the target system of `proj` and receive
+  /// points transformed into that system, so that map renderers don't need a
+  /// separate reprojection pass.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,WebMercator,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut db: DB<_,_,(f64,f64),u32> = DB::open(storage)?;
+  /// // web mercator meters around berlin
+  /// let bbox = ((1_480_000.0,6_880_000.0),(1_500_000.0,6_900_000.0));
+  /// for result in db.query_projected(&bbox, &WebMercator)? {
+  ///   let (point,value,location) = result?;
+  ///   // ...
+  /// }
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn query_projected<'a,T> (&mut self, bbox: &P::Bounds, proj: &'a T)
+  -> Result<ProjectedQuery<'a,S,P,V,T>,Error> where T: Projection+?Sized {
+    let stored = P::unproject_bounds(bbox, proj);
+    Ok(ProjectedQuery {
+      iter: self.query_mode(&stored, CacheMode::Normal, Some(AuditOp::Query))?,
+      bbox: *bbox,
+      proj
+    })
+  }
+}
@@ -0,0 +1,184 @@
+use crate::{DB,Point,Value,DataStore,Summarize};
+use crate::summary::{is_current,SummaryEntries};
+use crate::Error;
+use random_access_storage::RandomAccess;
+use desert::FromBytes;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// Decision a `Pruner` makes about a data block before it is read.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Verdict {
+  /// Don't read the block. None of its rows show up in the results.
+  Skip,
+  /// Read the block. Blocks with a higher priority are read first among the
+  /// blocks found under the same branches.
+  Read(i64)
+}
+
+impl Default for Verdict {
+  fn default () -> Self { Verdict::Read(0) }
+}
+
+/// What is known about a data block without reading it, as passed to
+/// `Pruner::block()`.
+pub struct BlockInfo<'a,P,V> where P: Point, V: Value {
+  /// Offset of the block in the data store.
+  pub offset: u64,
+  /// Range of the block's points, recorded when the block was written.
+  pub range: Option<&'a P::Range>,
+  /// Number of rows the block was written with, including rows that have
+  /// since been deleted.
+  pub rows: Option<u64>,
+  summaries: Vec<(&'a str,&'a [u8])>,
+  _value: PhantomData<V>
+}
+
+impl<'a,P,V> BlockInfo<'a,P,V> where P: Point, V: Value {
+  /// Return the stored summary of the block for `summary`, if the pruner
+  /// asked for it in `Pruner::summaries()` and the stored summary is current.
+  ///
+  /// Rows deleted in the staging area are only removed from the block on the
+  /// next merge, so the summary may still cover them.
+  pub fn summary<M> (&self, summary: &M) -> Result<Option<M::Summary>,Error>
+  where M: Summarize<P,V> {
+    match self.summaries.iter().find(|(name,_)| *name == summary.name()) {
+      Some((_,buf)) => Ok(Some(M::Summary::from_bytes(buf)?.1)),
+      None => Ok(None)
+    }
+  }
+}
+
+/// Hook consulted by `db.query()` for each data block that the tree
+/// traversal reaches, so that domain knowledge can skip blocks or read the
+/// most promising blocks first.
+///
+/// A pruner sees the query bounding box and what the database knows about a
+/// block without reading it: its recorded range and row count and the stored
+/// summaries that `summaries()` names. Rows in the staging area aren't in
+/// blocks, so they are always returned.
+///
+/// ```rust,no_run
+/// # use eyros::{DB,Summarize,Pruner,BlockInfo,Verdict,Error};
+/// # use std::path::PathBuf;
+/// # use random_access_disk::RandomAccessDisk;
+/// type P = (f32,f32);
+/// type V = u32;
+///
+/// // largest value in each block
+/// struct Max;
+/// impl Summarize<P,V> for Max {
+///   type Summary = u32;
+///   fn name (&self) -> &str { "max" }
+///   fn summarize (&self, rows: &[(P,V)]) -> u32 {
+///     rows.iter().map(|(_,v)| *v).max().unwrap_or(0)
+///   }
+///   fn combine (&self, a: &u32, b: &u32) -> u32 { *a.max(b) }
+/// }
+///
+/// // skip blocks that only hold small values
+/// struct AtLeast(u32);
+/// impl Pruner<P,V> for AtLeast {
+///   fn summaries (&self) -> Vec<String> { vec!["max".into()] }
+///   fn block (&self, _bbox: &((f32,f32),(f32,f32)), block: &BlockInfo<P,V>) -> Verdict {
+///     match block.summary(&Max) {
+///       Ok(Some(max)) if max < self.0 => Verdict::Skip,
+///       _ => Verdict::default()
+///     }
+///   }
+/// }
+///
+/// # fn main () -> Result<(),Error> {
+/// # let mut db: DB<_,_,P,V> = DB::open(storage)?;
+/// db.add_summary(Max)?;
+/// db.set_pruner(AtLeast(1_000));
+/// for result in db.query(&((-0.5,-0.8),(0.3,-0.5)))? {
+///   let (point,value,location) = result?;
+///   // blocks without any value >= 1000 were never read
+/// }
+/// # Ok(()) }
+/// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+/// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+/// #   p.push(name);
+/// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+/// # }
+/// ```
+pub trait Pruner<P,V>: Send+Sync where P: Point, V: Value {
+  /// Names of the registered summaries to pass to `block()`. Summaries are
+  /// loaded once per query. The default loads none.
+  fn summaries (&self) -> Vec<String> { vec![] }
+  /// Decide whether to read `block` for a query over `bbox`, and how soon.
+  fn block (&self, bbox: &P::Bounds, block: &BlockInfo<P,V>) -> Verdict;
+}
+
+// a pruner with the block entries and stored summaries for one query
+pub(crate) struct PruneState<P,V> where P: Point, V: Value {
+  pruner: Arc<dyn Pruner<P,V>>,
+  entries: HashMap<u64,(P::Range,u64)>,
+  summaries: Vec<(String,SummaryEntries)>
+}
+
+impl<P,V> PruneState<P,V> where P: Point, V: Value {
+  pub fn load<S> (pruner: Arc<dyn Pruner<P,V>>, dstore: &mut DataStore<S,P,V>)
+  -> Result<Self,Error> where S: RandomAccess<Error=failure::Error> {
+    let entries = dstore.block_entries()?;
+    let mut summaries = vec![];
+    for name in pruner.summaries() {
+      if let Some(s) = dstore.summaries.iter_mut().find(|s| s.name == name) {
+        summaries.push((name,s.entries()?));
+      }
+    }
+    Ok(Self { pruner, entries, summaries })
+  }
+  /// Drop the blocks in `blocks` that the pruner skips and sort the rest so
+  /// that the block with the highest priority is last.
+  pub fn order<S> (&self, dstore: &mut DataStore<S,P,V>, bbox: &P::Bounds,
+  blocks: Vec<u64>) -> Result<Vec<u64>,Error> where S: RandomAccess<Error=failure::Error> {
+    let mut read = Vec::with_capacity(blocks.len());
+    for offset in blocks {
+      let entry = self.entries.get(&offset);
+      let mut summaries = vec![];
+      for (name,stored) in self.summaries.iter() {
+        if let (Some((_,rows)),Some((live,checksum,buf))) = (entry,stored.get(&offset)) {
+          if is_current(dstore, offset, *rows, *live, *checksum)? {
+            summaries.push((name.as_str(),buf.as_slice()));
+          }
+        }
+      }
+      let info = BlockInfo {
+        offset,
+        range: entry.map(|(range,_)| range),
+        rows: entry.map(|(_,rows)| *rows),
+        summaries,
+        _value: PhantomData
+      };
+      match self.pruner.block(bbox, &info) {
+        Verdict::Skip => {},
+        Verdict::Read(priority) => read.push((priority,offset))
+      }
+    }
+    read.sort_by_key(|(priority,_)| *priority);
+    Ok(read.into_iter().map(|(_,offset)| offset).collect())
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Consult `pruner` for each data block that `query()`, `query_shared()`,
+  /// and `query_consistent()` reach, replacing any earlier pruner. See
+  /// `Pruner` for an example.
+  ///
+  /// Scans, digests, aggregates, deletes, and the other query variants read
+  /// every block. Pruners hold code, so they aren't persisted: set them each
+  /// time the database is opened.
+  pub fn set_pruner<R> (&mut self, pruner: R) where R: Pruner<P,V>+'static {
+    self.pruner = Some(Arc::new(pruner));
+  }
+  /// Stop consulting the pruner set with `set_pruner()`.
+  pub fn clear_pruner (&mut self) {
+    self.pruner = None;
+  }
+}
@@ -0,0 +1,166 @@
+//! Low-level access to the files of a database, for tools that read them
+//! without opening a `DB`: hex inspectors, data recovery scripts and format
+//! converters.
+//!
+//! Data blocks are stored back to back in the `data` store. Each block starts
+//! with a u32 length field that counts itself, followed by a u16 bitfield
+//! length, a bitfield with a bit set for each live row, an optional header
+//! with a checksum, compression and encryption settings, and the encoded
+//! rows. The `range` store holds an entry for each block that was written:
+//! the block's offset, the range of its rows and how many rows it was
+//! written with. Blocks that trees no longer point to stay in both stores
+//! until the data store is compacted.
+//!
+//! ```rust,no_run
+//! use eyros::{raw,Error};
+//! # use std::path::PathBuf;
+//! # use random_access_disk::RandomAccessDisk;
+//! # fn main () -> Result<(),Error> {
+//! type P = (f32,f32);
+//! let mut data = storage("data")?;
+//! let mut range = storage("range")?;
+//! for entry in raw::range_entries::<_,P>(&mut range)? {
+//!   let (offset,bounds,count) = entry?;
+//!   let block = raw::read_block(&mut data, offset)?;
+//!   let header = raw::parse_header(&block)?;
+//!   println!["block {}: {:?}, {} of {} rows live", offset, bounds,
+//!     header.live_rows(), count];
+//!   for (point,value,index) in raw::parse_rows::<P,u32>(&block)? {
+//!     println!["  {} {:?} {}", index, point, value];
+//!   }
+//! }
+//! # Ok(()) }
+//! # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+//! #   let mut p = PathBuf::from("/tmp/eyros-db/");
+//! #   p.push(name);
+//! #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+//! # }
+//! ```
+
+use crate::{Point,Value,Codec,DesertCodec,ChecksumMismatch};
+use crate::data::{EXTENDED,layout,each_row};
+use crate::compress::decompress;
+use desert::FromBytes;
+use crate::Error;
+use random_access_storage::RandomAccess;
+
+/// Read the data block at `offset` of a data store. The block is returned
+/// without its length field, which is the form the other functions of this
+/// module take.
+pub fn read_block<S> (store: &mut S, offset: u64) -> Result<Vec<u8>,Error>
+where S: RandomAccess<Error=failure::Error> {
+  let len = store.len()?;
+  crate::read_block::read_block(store, offset, len, 1024)
+}
+
+/// Header fields of a data block.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct BlockHeader {
+  /// Bit `i % 8` of byte `i / 8` is set if row `i` is live.
+  pub bitfield: Vec<u8>,
+  /// crc32 of the rows as they are stored, if the block has one.
+  pub checksum: Option<u32>,
+  /// Compression codec id and uncompressed length of the rows, if the rows
+  /// are compressed.
+  pub compressed: Option<(u8,usize)>,
+  /// Key epoch of the rows, if the rows are encrypted.
+  pub key_epoch: Option<u32>,
+  /// Position of the stored rows in the block.
+  pub rows_offset: usize
+}
+
+impl BlockHeader {
+  /// Whether row `index` is live.
+  pub fn is_live (&self, index: usize) -> bool {
+    self.bitfield.get(index/8).map(|b| (b>>(index%8))&1 == 1).unwrap_or(false)
+  }
+  /// Number of live rows.
+  pub fn live_rows (&self) -> usize {
+    self.bitfield.iter().map(|b| b.count_ones() as usize).sum()
+  }
+}
+
+/// Parse the header of a block returned by `read_block()`.
+pub fn parse_header (buf: &[u8]) -> Result<BlockHeader,Error> {
+  let l = layout(buf)?;
+  let field = u16::from_be_bytes([buf[0],buf[1]]) & !EXTENDED;
+  Ok(BlockHeader {
+    bitfield: buf[2..2+field as usize].to_vec(),
+    checksum: l.checksum.map(|(_,c)| c),
+    compressed: l.compressed,
+    key_epoch: l.encrypted,
+    rows_offset: l.rows
+  })
+}
+
+/// Parse the live rows of a block returned by `read_block()` with the
+/// default codec, along with the index of each row in the block. The index
+/// and the block offset plus one make up the row's `Location`.
+///
+/// Checksums are verified and compressed rows are decompressed. Encrypted
+/// blocks can't be parsed without their key and return an error.
+pub fn parse_rows<P,V> (buf: &[u8]) -> Result<Vec<(P,V,u32)>,Error>
+where P: Point, V: Value {
+  parse_rows_with(buf, &DesertCodec)
+}
+
+/// Like `parse_rows()`, for databases set up with a custom `Codec`.
+pub fn parse_rows_with<P,V> (buf: &[u8], codec: &dyn Codec<P,V>)
+-> Result<Vec<(P,V,u32)>,Error> where P: Point, V: Value {
+  let header = parse_header(buf)?;
+  if let Some(epoch) = header.key_epoch {
+    invalid!["data block is encrypted with key epoch {}", epoch]
+  }
+  let stored = &buf[header.rows_offset..];
+  if let Some(expected) = header.checksum {
+    let found = crc32fast::hash(stored);
+    if found != expected {
+      return Err(ChecksumMismatch { expected, found }.into());
+    }
+  }
+  let decompressed;
+  let rows = match header.compressed {
+    Some((id,len)) => {
+      decompressed = decompress(id, stored, len)?;
+      &decompressed[..]
+    },
+    None => stored
+  };
+  let mut results = Vec::with_capacity(header.live_rows());
+  each_row(codec, &header.bitfield, rows, |p,v,i| results.push((p,v,i)))?;
+  Ok(results)
+}
+
+/// Iterate over the entries of a range store: the offset of each data block,
+/// the range of its rows and the number of rows it was written with.
+pub fn range_entries<S,P> (store: &mut S) -> Result<RangeEntries<P>,Error>
+where S: RandomAccess<Error=failure::Error>, P: Point {
+  let len = store.len()?;
+  let buf = if len == 0 { vec![] } else { store.read(0, len)? };
+  Ok(RangeEntries { buf, offset: 0, _point: std::marker::PhantomData })
+}
+
+/// Iterator returned by `range_entries()`. Iteration stops after the first
+/// entry that fails to parse.
+pub struct RangeEntries<P> where P: Point {
+  buf: Vec<u8>,
+  offset: usize,
+  _point: std::marker::PhantomData<P>
+}
+
+impl<P> Iterator for RangeEntries<P> where P: Point {
+  type Item = Result<(u64,P::Range,u64),Error>;
+  fn next (&mut self) -> Option<Self::Item> {
+    if self.offset >= self.buf.len() { return None }
+    match <(u64,P::Range,u64)>::from_bytes(&self.buf[self.offset..]) {
+      Ok((size,entry)) => {
+        self.offset += size;
+        Some(Ok(entry))
+      },
+      Err(e) => {
+        self.offset = self.buf.len();
+        Some(Err(e.into()))
+      }
+    }
+  }
+}
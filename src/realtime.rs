@@ -0,0 +1,65 @@
+use crate::{DB,Point,Value};
+use crate::error::Backpressure;
+use crate::lock::Lock;
+use crate::Error;
+use random_access_storage::RandomAccess;
+use std::sync::Arc;
+use std::time::Duration;
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Return the number of staged rows past `base_size()`, which a regular
+  /// batch would have written into trees. Only `Setup::realtime()` leaves
+  /// rows behind like this, for `run_maintenance()` to build into trees.
+  pub fn debt (&self) -> Result<usize,Error> {
+    let staged = self.staging.inserts.read_lock()?.len();
+    Ok(staged.saturating_sub(self.fields.base_size))
+  }
+
+  // refuse batches while the debt is over the limit of realtime mode
+  pub(crate) fn check_debt (&self) -> Result<(),Error> {
+    if self.fields.realtime_deadline.is_none() { return Ok(()) }
+    let debt = self.debt()?;
+    if debt > self.fields.max_debt {
+      return Err(Backpressure { debt, limit: self.fields.max_debt }.into());
+    }
+    Ok(())
+  }
+
+  // build the staged rows into trees if the last build says it fits in what is
+  // left of the deadline of a batch that started at `start`
+  pub(crate) fn build_within_deadline (&mut self, start: Duration)
+  -> Result<(),Error> {
+    let (deadline,cost) = match (self.fields.realtime_deadline,self.build_cost) {
+      (Some(deadline),Some(cost)) => (deadline,cost),
+      _ => return Ok(())
+    };
+    if self.debt()? == 0 { return Ok(()) }
+    let left = (start + deadline).checked_sub(self.fields.clock.now())
+      .unwrap_or_default();
+    let rows = self.staging.inserts.read_lock()?.len();
+    if cost * rows as f64 <= left.as_nanos() as f64 {
+      self.build_staged()?;
+    }
+    Ok(())
+  }
+
+  // write the staged rows into trees like a regular batch would, timing the
+  // build for the next deadline check
+  pub(crate) fn build_staged (&mut self) -> Result<(),Error> {
+    self.check_writable()?;
+    if self.fields.check_conflicts {
+      self.check_sequence()?;
+    }
+    let clock = Arc::clone(&self.fields.clock);
+    let start = clock.now();
+    let rows = self.staging.inserts.read_lock()?.len();
+    let r = self.write_rows(&[], false);
+    self.poison_on_err(r)?;
+    let elapsed = clock.now().checked_sub(start).unwrap_or_default();
+    self.build_cost = Some(elapsed.as_nanos() as f64 / rows.max(1) as f64);
+    Ok(())
+  }
+}
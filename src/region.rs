@@ -0,0 +1,233 @@
+use crate::{DB,Point,Value,Location,QueryIterator,CacheMode};
+use crate::prune::{Pruner,BlockInfo,Verdict};
+use crate::Error;
+use random_access_storage::RandomAccess;
+use std::sync::Arc;
+
+/// Query region that is more precise than a bounding box, for
+/// `db.query_region()`.
+///
+/// The tree traversal is pruned with the bounding box from `bbox()`. Data
+/// blocks whose recorded range doesn't overlap the region according to
+/// `overlaps_bbox()` are skipped without being read, and rows are only
+/// returned when `contains_point()` accepts their point.
+pub trait QueryRegion<P>: Send+Sync where P: Point {
+  /// Bounding box that covers the whole region.
+  fn bbox (&self) -> P::Bounds;
+  /// Return whether any part of the region might lie inside `bbox`.
+  /// Returning `true` for a box that the region only comes close to is safe,
+  /// but returning `false` for a box the region overlaps drops results.
+  fn overlaps_bbox (&self, bbox: &P::Bounds) -> bool;
+  /// Return whether `point` is inside the region.
+  fn contains_point (&self, point: &P) -> bool;
+}
+
+/// Polygon with `(x,y)` vertices, such as an administrative boundary.
+///
+/// Each ring is a list of vertices with an implied edge from the last vertex
+/// back to the first. Points are inside when they are inside an odd number of
+/// rings, so holes and polygons with several parts are lists of rings.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Polygon {
+  pub rings: Vec<Vec<(f64,f64)>>
+}
+
+impl Polygon {
+  pub fn new (rings: Vec<Vec<(f64,f64)>>) -> Self {
+    Self { rings }
+  }
+  fn edges (&self) -> impl Iterator<Item=((f64,f64),(f64,f64))> + '_ {
+    self.rings.iter().flat_map(|ring| {
+      (0..ring.len()).map(move |i| (ring[i], ring[(i+1)%ring.len()]))
+    })
+  }
+  fn bounds (&self) -> ((f64,f64),(f64,f64)) {
+    let mut min = (f64::INFINITY,f64::INFINITY);
+    let mut max = (f64::NEG_INFINITY,f64::NEG_INFINITY);
+    for (x,y) in self.rings.iter().flatten() {
+      min = (min.0.min(*x), min.1.min(*y));
+      max = (max.0.max(*x), max.1.max(*y));
+    }
+    (min,max)
+  }
+  fn contains (&self, p: (f64,f64)) -> bool {
+    let mut inside = false;
+    for (a,b) in self.edges() {
+      if (a.1 > p.1) != (b.1 > p.1)
+      && p.0 < a.0 + (p.1-a.1) / (b.1-a.1) * (b.0-a.0) {
+        inside = !inside;
+      }
+    }
+    inside
+  }
+  fn overlaps (&self, min: (f64,f64), max: (f64,f64)) -> bool {
+    let (pmin,pmax) = self.bounds();
+    if pmax.0 < min.0 || pmin.0 > max.0 || pmax.1 < min.1 || pmin.1 > max.1 {
+      return false;
+    }
+    // either the box is inside the polygon or an edge crosses into the box
+    self.contains(min) || self.edges().any(|(a,b)| clip(a, b, min, max))
+  }
+}
+
+// whether the segment from a to b touches the box, by Liang-Barsky clipping
+fn clip (a: (f64,f64), b: (f64,f64), min: (f64,f64), max: (f64,f64)) -> bool {
+  let (dx,dy) = (b.0-a.0, b.1-a.1);
+  let (mut t0, mut t1) = (0.0f64, 1.0f64);
+  for (p,q) in [(-dx,a.0-min.0),(dx,max.0-a.0),(-dy,a.1-min.1),(dy,max.1-a.1)] {
+    if p == 0.0 {
+      if q < 0.0 { return false }
+    } else if p < 0.0 {
+      t0 = t0.max(q/p);
+    } else {
+      t1 = t1.min(q/p);
+    }
+    if t0 > t1 { return false }
+  }
+  true
+}
+
+/// Circle of `radius` around `center` in the same `(x,y)` units as the
+/// points.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Circle {
+  pub center: (f64,f64),
+  pub radius: f64
+}
+
+impl Circle {
+  pub fn new (center: (f64,f64), radius: f64) -> Self {
+    Self { center, radius }
+  }
+  fn overlaps (&self, min: (f64,f64), max: (f64,f64)) -> bool {
+    let x = self.center.0.max(min.0).min(max.0);
+    let y = self.center.1.max(min.1).min(max.1);
+    self.contains((x,y))
+  }
+  fn contains (&self, p: (f64,f64)) -> bool {
+    let (dx,dy) = (p.0-self.center.0, p.1-self.center.1);
+    dx*dx + dy*dy <= self.radius*self.radius
+  }
+}
+
+macro_rules! impl_region {
+  ($($T:ty),+) => {$(
+    impl QueryRegion<($T,$T)> for Polygon {
+      fn bbox (&self) -> (($T,$T),($T,$T)) {
+        let (min,max) = self.bounds();
+        ((min.0 as $T, min.1 as $T),(max.0 as $T, max.1 as $T))
+      }
+      fn overlaps_bbox (&self, bbox: &(($T,$T),($T,$T))) -> bool {
+        let ((x0,y0),(x1,y1)) = *bbox;
+        self.overlaps((x0 as f64, y0 as f64), (x1 as f64, y1 as f64))
+      }
+      fn contains_point (&self, point: &($T,$T)) -> bool {
+        self.contains((point.0 as f64, point.1 as f64))
+      }
+    }
+    impl QueryRegion<($T,$T)> for Circle {
+      fn bbox (&self) -> (($T,$T),($T,$T)) {
+        let (x,y,r) = (self.center.0, self.center.1, self.radius);
+        (((x-r) as $T, (y-r) as $T),((x+r) as $T, (y+r) as $T))
+      }
+      fn overlaps_bbox (&self, bbox: &(($T,$T),($T,$T))) -> bool {
+        let ((x0,y0),(x1,y1)) = *bbox;
+        self.overlaps((x0 as f64, y0 as f64), (x1 as f64, y1 as f64))
+      }
+      fn contains_point (&self, point: &($T,$T)) -> bool {
+        self.contains((point.0 as f64, point.1 as f64))
+      }
+    }
+  )+}
+}
+impl_region![f32,f64];
+
+// skips blocks outside of the region before consulting the db's own pruner
+struct RegionPruner<P,V,R> where P: Point, V: Value, R: QueryRegion<P> {
+  region: Arc<R>,
+  inner: Option<Arc<dyn Pruner<P,V>>>
+}
+
+impl<P,V,R> Pruner<P,V> for RegionPruner<P,V,R>
+where P: Point, V: Value, R: QueryRegion<P> {
+  fn summaries (&self) -> Vec<String> {
+    self.inner.as_ref().map(|p| p.summaries()).unwrap_or_default()
+  }
+  fn block (&self, bbox: &P::Bounds, block: &BlockInfo<P,V>) -> Verdict {
+    if let Some(range) = block.range.and_then(|r| P::range_bounds(r)) {
+      if !self.region.overlaps_bbox(&range) { return Verdict::Skip }
+    }
+    match &self.inner {
+      Some(pruner) => pruner.block(bbox, block),
+      None => Verdict::default()
+    }
+  }
+}
+
+/// Iterator of `Result<(Point,Value,Location)>` data returned by
+/// `db.query_region()`.
+pub struct RegionIterator<'b,S,P,V,R> where
+S: RandomAccess<Error=failure::Error>, P: Point, V: Value, R: QueryRegion<P> {
+  query: QueryIterator<'b,S,P,V>,
+  region: Arc<R>
+}
+
+impl<'b,S,P,V,R> Iterator for RegionIterator<'b,S,P,V,R> where
+S: RandomAccess<Error=failure::Error>, P: Point, V: Value, R: QueryRegion<P> {
+  type Item = Result<(P,V,Location),Error>;
+  fn next (&mut self) -> Option<Self::Item> {
+    loop {
+      match self.query.next()? {
+        Ok(row) if !self.region.contains_point(&row.0) => continue,
+        result => return Some(result)
+      }
+    }
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Query for points inside of `region`, such as a `Polygon` or a `Circle`.
+  ///
+  /// The trees are searched with the bounding box of the region, blocks that
+  /// the region doesn't overlap are skipped, and each candidate row is then
+  /// checked against the exact region. The pruner set with `set_pruner()` is
+  /// consulted for the blocks that remain.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Polygon,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut db: DB<_,_,(f32,f32),u32> = DB::open(storage)?;
+  /// let triangle = Polygon::new(vec![vec![(0.0,0.0),(0.5,0.0),(0.0,0.5)]]);
+  /// for result in db.query_region(triangle)? {
+  ///   let (point,value,location) = result?;
+  ///   // ...
+  /// }
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn query_region<'b,R> (&mut self, region: R)
+  -> Result<RegionIterator<'b,S,P,V,R>,Error>
+  where R: QueryRegion<P>+'static, P: 'static {
+    self.check_open()?;
+    let permit = self.admit()?;
+    let region = Arc::new(region);
+    let pruner: Arc<dyn Pruner<P,V>> = Arc::new(RegionPruner {
+      region: Arc::clone(&region),
+      inner: self.pruner.clone()
+    });
+    let bbox = region.bbox();
+    let audit = self.audit_query(&bbox);
+    let query = self.query_pruned(&bbox, CacheMode::Normal, permit, Some(pruner))?
+      .audit(audit);
+    Ok(RegionIterator { query, region })
+  }
+}
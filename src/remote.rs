@@ -0,0 +1,311 @@
+use crate::{DB,Setup,Point,Value,RetryPolicy};
+use failure::{Error,bail};
+use lru::LruCache;
+use random_access_storage::RandomAccess;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+const PAGE_SIZE: u64 = 64 * 1024;
+const CACHE_PAGES: usize = 256;
+// reads of this many pages or more are data blocks, which the block cache
+// keeps decoded, so paging them would hold the same rows twice
+const BYPASS_PAGES: u64 = 4;
+
+/// Source of byte ranges of remote objects, such as files served over HTTP
+/// or objects in an S3 bucket.
+///
+/// Implement this to sign requests or to use another HTTP client. Return
+/// `io::Error`s for failures worth repeating so that `Setup::retry()` can
+/// classify them with `is_transient()`.
+pub trait Fetch: Send + Sync {
+  /// Fetch bytes `start..end` of the object at `url`. The result may be
+  /// shorter if the object ends before `end`.
+  fn fetch (&self, url: &str, start: u64, end: u64) -> Result<Vec<u8>,Error>;
+  /// Return the size in bytes of the object at `url`, or `None` if it doesn't
+  /// exist.
+  fn size (&self, url: &str) -> Result<Option<u64>,Error>;
+}
+
+/// Storage function of `DB::open_remote()`, which opens each store as the
+/// object named after it under a base URL.
+pub type RemoteStorage<F> = Box<dyn Fn(&str) -> Result<RemoteStore<F>,Error> + Send + Sync>;
+
+/// Read-only store over range requests to a remote object.
+///
+/// Small reads, such as the bbox and tree branch reads at the start of a
+/// query, are served from an LRU cache of fixed-size pages. Runs of missing
+/// pages are fetched with one request each, so a read that straddles pages
+/// costs one round trip. Reads of a few pages or more, which are data
+/// blocks, skip the page cache and are fetched as they are: the database
+/// keeps their decoded rows in its block cache, which `Setup::cache_bytes()`
+/// bounds.
+///
+/// Objects that don't exist read as empty stores. Writes fail.
+pub struct RemoteStore<F> where F: Fetch {
+  fetch: Arc<F>,
+  url: String,
+  len: u64,
+  page_size: u64,
+  pages: LruCache<u64,Vec<u8>>
+}
+
+impl<F> RemoteStore<F> where F: Fetch {
+  /// Open the object at `url`, requesting its size right away.
+  pub fn open (fetch: Arc<F>, url: &str) -> Result<Self,Error> {
+    let len = fetch.size(url)?.unwrap_or(0);
+    Ok(Self {
+      fetch,
+      url: url.to_string(),
+      len,
+      page_size: PAGE_SIZE,
+      pages: LruCache::new(CACHE_PAGES)
+    })
+  }
+  /// Set the size of cached pages. Defaults to 64KB.
+  pub fn page_size (mut self, size: u64) -> Self {
+    self.page_size = size.max(1);
+    self.pages.clear();
+    self
+  }
+  /// Keep at most `n` pages in the page cache. Defaults to 256.
+  pub fn cache_pages (mut self, n: usize) -> Self {
+    self.pages.resize(n.max(1));
+    self
+  }
+  /// URL of the remote object.
+  pub fn url (&self) -> &str {
+    &self.url
+  }
+
+  fn fetch_range (&self, start: u64, end: u64) -> Result<Vec<u8>,Error> {
+    let buf = self.fetch.fetch(&self.url, start, end)?;
+    if (buf.len() as u64) < end - start {
+      bail!["short read from {}: expected {} bytes at {}, got {}",
+        self.url, end - start, start, buf.len()];
+    }
+    Ok(buf)
+  }
+}
+
+fn copy_page (out: &mut Vec<u8>, page: &[u8], page_start: u64, start: u64, end: u64) {
+  let from = (start.max(page_start) - page_start) as usize;
+  let to = ((end.min(page_start + page.len() as u64)) - page_start) as usize;
+  out.extend_from_slice(&page[from..to]);
+}
+
+impl<F> RandomAccess for RemoteStore<F> where F: Fetch {
+  type Error = Error;
+  fn write (&mut self, _offset: u64, _data: &[u8]) -> Result<(),Error> {
+    bail!["{} is read-only", self.url]
+  }
+  fn read (&mut self, offset: u64, length: u64) -> Result<Vec<u8>,Error> {
+    let end = offset + length;
+    if end > self.len {
+      bail!["read of {} bytes at {} is past the end of {} ({} bytes)",
+        length, offset, self.url, self.len];
+    }
+    if length == 0 { return Ok(vec![]) }
+    let ps = self.page_size;
+    if length >= ps * BYPASS_PAGES {
+      return self.fetch_range(offset, end);
+    }
+    let (first,last) = (offset / ps, (end - 1) / ps);
+    let mut out = Vec::with_capacity(length as usize);
+    let mut i = first;
+    while i <= last {
+      if let Some(page) = self.pages.get(&i) {
+        copy_page(&mut out, page, i * ps, offset, end);
+        i += 1;
+        continue;
+      }
+      let mut j = i;
+      while j < last && !self.pages.contains(&(j+1)) { j += 1 }
+      let buf = self.fetch_range(i * ps, ((j + 1) * ps).min(self.len))?;
+      for (k,chunk) in buf.chunks(ps as usize).enumerate() {
+        let n = i + k as u64;
+        copy_page(&mut out, chunk, n * ps, offset, end);
+        self.pages.put(n, chunk.to_vec());
+      }
+      i = j + 1;
+    }
+    Ok(out)
+  }
+  fn read_to_writer (&mut self, offset: u64, length: u64,
+  buf: &mut impl Write) -> Result<(),Error> {
+    buf.write_all(&self.read(offset, length)?)?;
+    Ok(())
+  }
+  fn del (&mut self, _offset: u64, _length: u64) -> Result<(),Error> {
+    bail!["{} is read-only", self.url]
+  }
+  fn truncate (&mut self, _length: u64) -> Result<(),Error> {
+    bail!["{} is read-only", self.url]
+  }
+  fn len (&self) -> Result<u64,Error> {
+    Ok(self.len)
+  }
+  fn is_empty (&mut self) -> Result<bool,Error> {
+    Ok(self.len == 0)
+  }
+  fn sync_all (&mut self) -> Result<(),Error> {
+    Ok(())
+  }
+}
+
+/// Create a storage function that opens each store as the object named
+/// after it under `base_url`, such as `{base_url}/meta` for the meta store.
+pub fn remote_storage<F> (base_url: &str, fetch: F) -> RemoteStorage<F>
+where F: Fetch + 'static {
+  let base = base_url.trim_end_matches('/').to_string();
+  let fetch = Arc::new(fetch);
+  Box::new(move |name: &str| {
+    RemoteStore::open(Arc::clone(&fetch), &format!["{}/{}", base, name])
+  })
+}
+
+/// Return the virtual-hosted style URL of the objects under `prefix` in an S3
+/// bucket, to pass to `DB::open_remote()`.
+///
+/// Anonymous requests only read public buckets, which should also allow
+/// listing so that missing stores answer 404 rather than 403. Implement
+/// `Fetch` to sign requests for private buckets.
+pub fn s3_url (bucket: &str, region: &str, prefix: &str) -> String {
+  let prefix = prefix.trim_matches('/');
+  if prefix.is_empty() {
+    format!["https://{}.s3.{}.amazonaws.com", bucket, region]
+  } else {
+    format!["https://{}.s3.{}.amazonaws.com/{}", bucket, region, prefix]
+  }
+}
+
+impl<F,P,V> DB<RemoteStore<F>,RemoteStorage<F>,P,V>
+where F: Fetch + 'static, P: Point, V: Value {
+  /// Open a read-only database from the objects under `base_url`, fetching
+  /// byte ranges with `fetch` and configured by `setup`.
+  ///
+  /// Reads are retried 3 times and sibling branches are read in runs of up
+  /// to 64 KB with `Setup::coalesce_branches()`, unless `setup` says
+  /// otherwise.
+  /// The handle is always read-only: writes fail with a `ReadOnly` error.
+  pub fn open_remote_with<S> (fetch: F, base_url: &str, setup: S) -> Result<Self,crate::Error>
+  where S: FnOnce(Setup<RemoteStore<F>,RemoteStorage<F>>)
+  -> Setup<RemoteStore<F>,RemoteStorage<F>> {
+    let retry = RetryPolicy::new(3)
+      .backoff(Duration::from_millis(100))
+      .max_backoff(Duration::from_secs(2));
+    let defaults = Setup::new(remote_storage(base_url, fetch))
+      .retry(retry)
+      .coalesce_branches(64 << 10);
+    setup(defaults)
+      .read_only(true)
+      .build()
+  }
+}
+
+#[cfg(all(feature="http",not(target_arch="wasm32")))]
+pub use http::HttpFetch;
+
+#[cfg(all(feature="http",not(target_arch="wasm32")))]
+mod http {
+  use super::{Fetch,RemoteStore,RemoteStorage};
+  use crate::{DB,Setup,Point,Value};
+  use failure::{Error,bail,format_err};
+  use std::io::{self,Read};
+  use std::time::Duration;
+
+  /// `Fetch` over HTTP range requests with `ureq`.
+  pub struct HttpFetch {
+    agent: ureq::Agent
+  }
+
+  impl HttpFetch {
+    pub fn new () -> Self {
+      Self::from_agent(ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(30))
+        .build())
+    }
+    /// Use an agent configured with other timeouts, proxies, or TLS settings.
+    pub fn from_agent (agent: ureq::Agent) -> Self {
+      Self { agent }
+    }
+  }
+
+  impl Default for HttpFetch {
+    fn default () -> Self { Self::new() }
+  }
+
+  // map failures worth repeating to the io errors that is_transient() retries
+  fn request_error (url: &str, err: ureq::Error) -> Error {
+    match err {
+      ureq::Error::Status(code, _) if code == 429 || code >= 500 => {
+        io::Error::new(io::ErrorKind::Interrupted,
+          format!["{} answered with status {}", url, code]).into()
+      },
+      ureq::Error::Status(code, _) => {
+        format_err!["{} answered with status {}", url, code]
+      },
+      ureq::Error::Transport(t) => {
+        io::Error::new(io::ErrorKind::ConnectionAborted,
+          format!["request to {} failed: {}", url, t]).into()
+      }
+    }
+  }
+
+  impl Fetch for HttpFetch {
+    fn fetch (&self, url: &str, start: u64, end: u64) -> Result<Vec<u8>,Error> {
+      if end <= start { return Ok(vec![]) }
+      let res = self.agent.get(url)
+        .set("Range", &format!["bytes={}-{}", start, end - 1])
+        .call();
+      let res = match res {
+        Ok(res) => res,
+        Err(ureq::Error::Status(416, _)) => return Ok(vec![]),
+        Err(e) => return Err(request_error(url, e))
+      };
+      // servers that ignore the range header send the whole object
+      let skip = if res.status() == 206 { 0 } else { start };
+      let mut reader = res.into_reader();
+      io::copy(&mut (&mut reader).take(skip), &mut io::sink())?;
+      let mut buf = Vec::with_capacity((end - start) as usize);
+      reader.take(end - start).read_to_end(&mut buf)?;
+      Ok(buf)
+    }
+    fn size (&self, url: &str) -> Result<Option<u64>,Error> {
+      let res = match self.agent.head(url).call() {
+        Ok(res) => res,
+        Err(ureq::Error::Status(404, _)) => return Ok(None),
+        Err(e) => return Err(request_error(url, e))
+      };
+      match res.header("Content-Length").and_then(|n| n.parse().ok()) {
+        Some(n) => Ok(Some(n)),
+        None => bail!["{} has no content length", url]
+      }
+    }
+  }
+
+  impl<P,V> DB<RemoteStore<HttpFetch>,RemoteStorage<HttpFetch>,P,V>
+  where P: Point, V: Value {
+    /// Open a read-only database from the objects under `base_url` over HTTP
+    /// range requests, configured by `setup`. Each store is the object named
+    /// after it, such as `{base_url}/meta`. See `open_remote_with()`.
+    ///
+    /// ```rust,no_run
+    /// use eyros::{DB,s3_url,Error};
+    /// # fn main () -> Result<(),Error> {
+    /// let url = s3_url("my-bucket", "eu-west-1", "ships/2020");
+    /// let mut db: DB<_,_,(f32,f32),u32> = DB::open_remote(&url, |setup| {
+    ///   setup.cache_bytes(64 << 20)
+    /// })?;
+    /// for result in db.query(&((4.0,52.0),(5.0,53.0)))? {
+    ///   println!["{:?}", result?];
+    /// }
+    /// # Ok(()) }
+    /// ```
+    pub fn open_remote<S> (base_url: &str, setup: S) -> Result<Self,crate::Error>
+    where S: FnOnce(Setup<RemoteStore<HttpFetch>,RemoteStorage<HttpFetch>>)
+    -> Setup<RemoteStore<HttpFetch>,RemoteStorage<HttpFetch>> {
+      Self::open_remote_with(HttpFetch::new(), base_url, setup)
+    }
+  }
+}
@@ -0,0 +1,80 @@
+use crate::{DB,Point,Value};
+use crate::Error;
+use random_access_storage::RandomAccess;
+
+/// Counts from a `db.restore()`.
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct RestoreReport {
+  /// Changes replayed into the destination.
+  pub changes: u64,
+  /// Sequence number of the last change the destination now reflects.
+  pub seq: u64
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Rebuild this database as of change `until` into `dest` by replaying the
+  /// changes feed.
+  ///
+  /// `dest` is either a new, empty database with `backup` set to `0`, or a
+  /// copy of this database's files taken when `last_change()` returned
+  /// `backup`. Changes `backup+1` through `until` are applied in order, in
+  /// batches of `chunk` changes, so the result holds the same rows this
+  /// database held right after change `until` was written.
+  ///
+  /// The feed has to be enabled with `Setup::changes()` and still hold the
+  /// changes after `backup`: if the retention policy removed them, this fails
+  /// with `HistoryPruned` and you need a more recent backup.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Setup,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # type P = ((f32,f32),(f32,f32));
+  /// # type V = u32;
+  /// # fn main () -> Result<(),Error> {
+  /// let db: DB<_,_,P,V> = Setup::new(storage("/tmp/eyros-db/"))
+  ///   .changes(true)
+  ///   .build()?;
+  /// // the backup was copied when db.last_change() was 5000
+  /// let mut dest: DB<_,_,P,V> = DB::open(storage("/tmp/eyros-backup/"))?;
+  /// let report = db.restore(&mut dest, 5000, 7250, 10_000)?;
+  /// assert_eq![report.seq, 7250];
+  /// # Ok(()) }
+  /// # fn storage(dir: &'static str)
+  /// # -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   move |name: &str| {
+  /// #     let mut p = PathBuf::from(dir);
+  /// #     p.push(name);
+  /// #     Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// #   }
+  /// # }
+  /// ```
+  pub fn restore<T,W> (&self, dest: &mut DB<T,W,P,V>, backup: u64,
+  until: u64, chunk: usize) -> Result<RestoreReport,Error> where
+  T: RandomAccess<Error=failure::Error>,
+  W: (Fn(&str) -> Result<T,failure::Error>) {
+    if until < backup {
+      invalid!["cannot restore to change {} from a backup at change {}",
+        until, backup];
+    }
+    let last = self.last_change()?;
+    if until > last {
+      invalid!["cannot restore to change {} past the last change {}",
+        until, last];
+    }
+    let mut changes = self.changes(backup)?.take((until-backup) as usize);
+    let mut report = RestoreReport { changes: 0, seq: backup };
+    loop {
+      let batch = changes.by_ref().take(chunk.max(1))
+        .collect::<Result<Vec<_>,Error>>()?;
+      if batch.is_empty() { break }
+      dest.apply_changes(&batch)?;
+      report.changes += batch.len() as u64;
+      report.seq = batch.last().unwrap().seq;
+    }
+    Ok(report)
+  }
+}
@@ -0,0 +1,124 @@
+use crate::{DB,Point,Value};
+use crate::changes::ChangeLog;
+use crate::swap::{self,SwapRecord};
+use crate::Error;
+use random_access_storage::RandomAccess;
+use std::time::Duration;
+
+/// Limits on how much history the changes feed keeps, set with
+/// `Setup::retention()`.
+///
+/// Changes past either limit are removed by `db.prune_history()` and by
+/// `db.compact()`. The default keeps every change.
+///
+/// ```rust
+/// use eyros::Retention;
+/// use std::time::Duration;
+///
+/// // keep the last million changes, and none older than 30 days
+/// let retention = Retention::default()
+///   .max_changes(1_000_000)
+///   .max_age(Duration::from_secs(30*24*60*60));
+/// ```
+#[derive(Clone,Copy,Debug,Default,PartialEq,Eq)]
+pub struct Retention {
+  /// Number of most recent changes to keep.
+  pub max_changes: Option<u64>,
+  /// Age past which changes are removed, measured with the database clock.
+  pub max_age: Option<Duration>
+}
+
+impl Retention {
+  pub fn max_changes (mut self, n: u64) -> Self {
+    self.max_changes = Some(n);
+    self
+  }
+  pub fn max_age (mut self, age: Duration) -> Self {
+    self.max_age = Some(age);
+    self
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Remove changes from the changes feed that are past the limits of
+  /// `Setup::retention()` and return the number of changes removed.
+  ///
+  /// Sequence numbers don't change, but readers asking for changes that were
+  /// removed get a `HistoryPruned` error. The kept changes are written to new
+  /// stores that replace the feed's stores in one swap, so a prune that a
+  /// crash interrupts either removes the changes or leaves the feed as it
+  /// was.
+  pub fn prune_history (&mut self) -> Result<u64,Error> {
+    self.check_writable()?;
+    self.check_backup()?;
+    if self.fields.check_conflicts {
+      self.check_sequence()?;
+    }
+    let r = self.prune_changes();
+    self.poison_on_err(r)
+  }
+
+  pub(crate) fn prune_changes (&mut self) -> Result<u64,Error> {
+    let first = match self.history_start()? {
+      Some(first) => first,
+      None => return Ok(0)
+    };
+    let log = match &mut self.change_log {
+      Some(log) => log,
+      None => return Ok(0)
+    };
+    let removed = log.prune(first,
+      &mut swap::open_next(&self.open_store, "changes")?,
+      &mut swap::open_next(&self.open_store, "changes_index")?)?;
+    if removed == 0 { return Ok(0) }
+    let mut stores = vec![];
+    for name in ["changes","changes_index"].iter() {
+      let len = (self.open_store)(&swap::next_name(name))?.len()?;
+      stores.push((name.to_string(),len));
+    }
+    swap::run(&self.open_store, &SwapRecord { stores, meta: None })?;
+    self.change_log = Some(ChangeLog::open(
+      (self.open_store)("changes")?,
+      (self.open_store)("changes_index")?
+    )?);
+    Ok(removed)
+  }
+
+  // number of changes past the limits of the retention policy
+  pub(crate) fn history_to_prune (&mut self) -> Result<u64,Error> {
+    match (self.history_start()?,&self.change_log) {
+      (Some(first),Some(log)) => Ok(first.saturating_sub(log.first())),
+      _ => Ok(0)
+    }
+  }
+
+  // sequence number of the oldest change that the retention policy keeps
+  fn history_start (&mut self) -> Result<Option<u64>,Error> {
+    let retention = self.fields.retention;
+    let now = self.fields.clock.now();
+    let log = match &mut self.change_log {
+      Some(log) => log,
+      None => return Ok(None)
+    };
+    let end = log.len()?;
+    let mut first = log.first();
+    if let Some(n) = retention.max_changes {
+      first = first.max((end+1).saturating_sub(n));
+    }
+    if let Some(age) = retention.max_age {
+      let cutoff = now.checked_sub(age).unwrap_or_default();
+      // changes are appended in time order, so find the first one that is
+      // recent enough
+      let (mut lo, mut hi) = (first, end+1);
+      while lo < hi {
+        let mid = lo + (hi-lo)/2;
+        if log.time(mid)? < cutoff { lo = mid+1 } else { hi = mid }
+      }
+      first = lo;
+    }
+    Ok(Some(first))
+  }
+}
@@ -0,0 +1,128 @@
+use crate::Clock;
+use crate::Error;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Policy for retrying block reads against flaky storage backends, such as
+/// stores that fetch blocks over the network.
+///
+/// A failed read is attempted again when the `retryable` classifier accepts
+/// the error, waiting `backoff` before the first retry and doubling the delay
+/// after each further failure up to `max_backoff`.
+///
+/// The default policy makes a single attempt, so reads fail immediately.
+///
+/// ```rust
+/// use eyros::RetryPolicy;
+/// use std::time::Duration;
+///
+/// let policy = RetryPolicy::new(5)
+///   .backoff(Duration::from_millis(20))
+///   .max_backoff(Duration::from_secs(1));
+/// assert_eq![policy.delay(1), Duration::from_millis(20)];
+/// assert_eq![policy.delay(3), Duration::from_millis(80)];
+/// ```
+#[derive(Clone)]
+pub struct RetryPolicy {
+  /// Maximum number of attempts, including the first one.
+  pub attempts: usize,
+  /// Delay before the first retry.
+  pub backoff: Duration,
+  /// Upper bound for the delay between attempts.
+  pub max_backoff: Duration,
+  /// Decide whether an error is transient and worth retrying.
+  pub retryable: Arc<dyn Fn(&Error) -> bool + Send + Sync>
+}
+
+impl RetryPolicy {
+  /// Create a policy that makes up to `attempts` attempts, retrying errors
+  /// accepted by `is_transient()`.
+  pub fn new (attempts: usize) -> Self {
+    Self {
+      attempts: attempts.max(1),
+      backoff: Duration::from_millis(10),
+      max_backoff: Duration::from_secs(2),
+      retryable: Arc::new(is_transient)
+    }
+  }
+  /// Create a policy that never retries.
+  pub fn none () -> Self {
+    Self::new(1)
+  }
+  pub fn attempts (mut self, attempts: usize) -> Self {
+    self.attempts = attempts.max(1);
+    self
+  }
+  pub fn backoff (mut self, backoff: Duration) -> Self {
+    self.backoff = backoff;
+    self
+  }
+  pub fn max_backoff (mut self, max_backoff: Duration) -> Self {
+    self.max_backoff = max_backoff;
+    self
+  }
+  /// Set the classifier that decides which errors are retried.
+  pub fn retryable<F> (mut self, f: F) -> Self
+  where F: Fn(&Error) -> bool + Send + Sync + 'static {
+    self.retryable = Arc::new(f);
+    self
+  }
+  /// Delay to wait before retry number `retry` (starting at 1).
+  pub fn delay (&self, retry: usize) -> Duration {
+    let shift = (retry.max(1)-1).min(31) as u32;
+    match self.backoff.checked_mul(1u32 << shift) {
+      Some(d) => d.min(self.max_backoff),
+      None => self.max_backoff
+    }
+  }
+  /// Run `f` until it succeeds, fails with an error that isn't retryable, or
+  /// runs out of attempts, waiting between attempts with `clock`.
+  pub fn run<T,E,F> (&self, clock: &dyn Clock, mut f: F) -> Result<T,Error>
+  where F: FnMut () -> Result<T,E>, Error: From<E> {
+    let mut attempt = 1;
+    loop {
+      match f() {
+        Ok(x) => return Ok(x),
+        Err(e) => {
+          let e = Error::from(e);
+          if attempt >= self.attempts || !(self.retryable)(&e) {
+            return Err(e);
+          }
+          clock.sleep(self.delay(attempt));
+          attempt += 1;
+        }
+      }
+    }
+  }
+}
+
+impl Default for RetryPolicy {
+  fn default () -> Self { Self::none() }
+}
+
+impl fmt::Debug for RetryPolicy {
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("RetryPolicy")
+      .field("attempts", &self.attempts)
+      .field("backoff", &self.backoff)
+      .field("max_backoff", &self.max_backoff)
+      .finish()
+  }
+}
+
+/// Default error classifier: I/O errors that usually go away when the request
+/// is repeated (interruptions, timeouts, and dropped connections).
+pub fn is_transient (err: &Error) -> bool {
+  match err.downcast_ref::<io::Error>() {
+    Some(e) => matches![e.kind(),
+      io::ErrorKind::Interrupted
+      | io::ErrorKind::TimedOut
+      | io::ErrorKind::WouldBlock
+      | io::ErrorKind::ConnectionReset
+      | io::ErrorKind::ConnectionAborted
+      | io::ErrorKind::BrokenPipe],
+    None => false
+  }
+}
@@ -0,0 +1,188 @@
+use crate::{DB,Point,Value};
+use crate::lock::Lock;
+use crate::tree::Tree;
+use crate::Error;
+use random_access_storage::RandomAccess;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// buckets in the histogram of each axis
+const BUCKETS: usize = 64;
+
+// Fraction of the interval `a` that lies inside of the interval `b`.
+fn overlap (a: (f64,f64), b: (f64,f64)) -> f64 {
+  let (lo,hi) = (a.0.max(b.0), a.1.min(b.1));
+  if hi < lo { 0.0 }
+  else if a.1 > a.0 { (hi-lo)/(a.1-a.0) }
+  else { 1.0 }
+}
+
+// Extent of the rows under a data block or a branch, along each axis.
+#[derive(Clone,Debug)]
+struct Extent {
+  axes: Vec<(f64,f64)>,
+  rows: f64
+}
+
+impl Extent {
+  // Rows expected inside of `bbox` if the rows are spread evenly over the
+  // extent.
+  fn hits (&self, bbox: &[(f64,f64)]) -> f64 {
+    self.axes.iter().zip(bbox).fold(self.rows, |n,(a,b)| n * overlap(*a,*b))
+  }
+  fn extend (&mut self, other: &Extent) {
+    if self.axes.is_empty() {
+      self.axes = other.axes.clone();
+    } else {
+      for (a,b) in self.axes.iter_mut().zip(other.axes.iter()) {
+        *a = (a.0.min(b.0), a.1.max(b.1));
+      }
+    }
+    self.rows += other.rows;
+  }
+}
+
+// Rows of a tree along one axis. The rows of each data block are spread over
+// the buckets that the block's bounds cover.
+#[derive(Clone,Debug)]
+struct Histogram {
+  min: f64,
+  max: f64,
+  buckets: Vec<f64>
+}
+
+impl Histogram {
+  fn new (min: f64, max: f64) -> Self {
+    let n = if max > min { BUCKETS } else { 1 };
+    Self { min, max, buckets: vec![0.0;n] }
+  }
+  fn bucket (&self, i: usize) -> (f64,f64) {
+    let width = (self.max - self.min) / (self.buckets.len() as f64);
+    (self.min + width*(i as f64), self.min + width*((i+1) as f64))
+  }
+  fn add (&mut self, (lo,hi): (f64,f64), rows: f64) {
+    let n = self.buckets.len();
+    if hi > lo {
+      for i in 0..n {
+        let share = overlap((lo,hi), self.bucket(i));
+        self.buckets[i] += rows * share;
+      }
+    } else { // every row has the same coordinate
+      let i = ((lo - self.min) / (self.max - self.min) * (n as f64)) as usize;
+      self.buckets[i.min(n-1)] += rows;
+    }
+  }
+  // Fraction of the rows that lie inside of `(lo,hi)`.
+  fn fraction (&self, q: (f64,f64)) -> f64 {
+    let total: f64 = self.buckets.iter().sum();
+    if total <= 0.0 { return 0.0 }
+    let inside: f64 = self.buckets.iter().enumerate()
+      .map(|(i,rows)| rows * overlap(self.bucket(i), q))
+      .sum();
+    inside / total
+  }
+}
+
+/// Statistics of a tree that order the branches and data blocks a query
+/// reads, and the trees of a query, by the number of results expected from
+/// each.
+#[derive(Clone,Debug)]
+pub struct Selectivity {
+  rows: f64,
+  axes: Vec<Histogram>,
+  branches: HashMap<u64,Extent>,
+  blocks: HashMap<u64,Extent>
+}
+
+impl Selectivity {
+  /// Build the statistics of `tree` from its branches and the range store
+  /// entries of its data blocks. Returns `None` for point types without
+  /// `Point::bounds_axes()`.
+  pub fn build<S,P,V> (tree: &mut Tree<S,P,V>,
+  entries: &HashMap<u64,(P::Range,u64)>) -> Result<Option<Self>,Error>
+  where S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+    let walk = tree.branches()?;
+    let mut blocks = HashMap::new();
+    for offset in walk.iter().flat_map(|(_,_,blocks)| blocks.iter()) {
+      let (range,rows) = match entries.get(offset) {
+        Some(entry) => entry,
+        None => continue
+      };
+      let axes = match P::range_bounds(range).and_then(|b| P::bounds_axes(&b)) {
+        Some(axes) => axes,
+        None => return Ok(None)
+      };
+      blocks.insert(*offset, Extent { axes, rows: *rows as f64 });
+    }
+    // children are walked after their parents
+    let mut branches: HashMap<u64,Extent> = HashMap::new();
+    for (offset,cursors,offsets) in walk.iter().rev() {
+      let mut extent = Extent { axes: vec![], rows: 0.0 };
+      for e in cursors.iter().filter_map(|c| branches.get(c))
+      .chain(offsets.iter().filter_map(|o| blocks.get(o))) {
+        extent.extend(e);
+      }
+      if !extent.axes.is_empty() {
+        branches.insert(*offset, extent);
+      }
+    }
+    let mut total = Extent { axes: vec![], rows: 0.0 };
+    for e in blocks.values() {
+      total.extend(e);
+    }
+    let mut axes: Vec<Histogram> = total.axes.iter()
+      .map(|(min,max)| Histogram::new(*min,*max))
+      .collect();
+    for e in blocks.values() {
+      for (h,a) in axes.iter_mut().zip(e.axes.iter()) {
+        h.add(*a, e.rows);
+      }
+    }
+    Ok(Some(Self { rows: total.rows, axes, branches, blocks }))
+  }
+  /// Number of rows expected inside of `bbox`, assuming that the axes are
+  /// independent.
+  pub fn estimate (&self, bbox: &[(f64,f64)]) -> f64 {
+    self.axes.iter().zip(bbox).fold(self.rows, |n,(h,q)| n * h.fraction(*q))
+  }
+  /// Sort data block offsets so that the block with the most expected results
+  /// comes last, where the tree iterator's stack pops it first.
+  pub fn order_blocks (&self, bbox: &[(f64,f64)], blocks: &mut [u64]) {
+    let hits = |offset: &u64| {
+      self.blocks.get(offset).map(|e| e.hits(bbox)).unwrap_or(0.0)
+    };
+    blocks.sort_by(|a,b| hits(a).total_cmp(&hits(b)));
+  }
+  /// Sort branch cursors like `order_blocks()`.
+  pub fn order_cursors (&self, bbox: &[(f64,f64)], cursors: &mut [(u64,usize)]) {
+    let hits = |cursor: &(u64,usize)| {
+      self.branches.get(&cursor.0).map(|e| e.hits(bbox)).unwrap_or(0.0)
+    };
+    cursors.sort_by(|a,b| hits(a).total_cmp(&hits(b)));
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Return the selectivity statistics of each tree, indexed by tree level,
+  /// building those that are missing. Trees keep their statistics until they
+  /// are written again.
+  pub(crate) fn selectivity (&mut self) -> Result<Vec<Option<Arc<Selectivity>>>,Error> {
+    let mut entries = None;
+    let mut stats = Vec::with_capacity(self.trees.len());
+    for tree in self.trees.iter() {
+      let mut t = tree.write_lock()?;
+      if t.selectivity.is_none() && !t.is_empty()? {
+        if entries.is_none() {
+          entries = Some(self.data_store.write_lock()?.block_entries()?);
+        }
+        t.selectivity = Selectivity::build(&mut t, entries.as_ref().unwrap())?
+          .map(Arc::new);
+      }
+      stats.push(t.selectivity.clone());
+    }
+    Ok(stats)
+  }
+}
@@ -1,14 +1,56 @@
-use crate::{DB,Point,Value};
-use failure::Error;
+use crate::{DB,Point,Value,CacheMode,RetryPolicy,Clock,default_clock,
+  ManualClock,TreeFormat,Compression,Admission,Retention,Cipher,Encryption,
+  AutoTune};
+use crate::encrypt::SharedKeys;
+use std::sync::Arc;
+use std::time::Duration;
+use std::path::PathBuf;
+use crate::data::RANGE_CHUNK_SIZE;
+use crate::Error;
 use random_access_storage::RandomAccess;
 
 /// Struct for reading database properties.
+#[derive(Clone)]
 pub struct SetupFields {
   pub max_data_size: usize,
   pub base_size: usize,
   pub branch_factor: usize,
   pub bbox_cache_size: usize,
-  pub data_list_cache_size: usize
+  pub data_list_cache_size: usize,
+  pub cache_bytes: Option<u64>,
+  pub range_chunk_size: usize,
+  pub maintenance_cache: CacheMode,
+  pub retry: RetryPolicy,
+  pub clock: Arc<dyn Clock>,
+  pub seed: u64,
+  pub check_conflicts: bool,
+  pub heat_half_life: Option<Duration>,
+  pub tree_format: TreeFormat,
+  pub wal: bool,
+  pub changes: bool,
+  pub retention: Retention,
+  pub compact_ratio: Option<f64>,
+  pub block_checksums: bool,
+  pub compression: Compression,
+  pub encryption: Option<Encryption>,
+  pub(crate) store_keys: Option<SharedKeys>,
+  pub max_queries: Option<usize>,
+  pub admission: Admission,
+  pub max_open_trees: Option<usize>,
+  pub max_buffered_blocks: Option<usize>,
+  pub query_threads: usize,
+  pub max_trees: Option<usize>,
+  pub absorb_batch_size: Option<usize>,
+  pub absorb_tree_size: usize,
+  pub max_dirty_bytes: Option<u64>,
+  pub read_only: bool,
+  pub coalesce_span: u64,
+  pub selectivity_order: bool,
+  pub realtime_deadline: Option<Duration>,
+  pub max_debt: usize,
+  pub auto_tune: Option<(u64,AutoTune)>,
+  pub lock_file: Option<PathBuf>,
+  pub lock: bool
 }
 
 /// Builder to configure and instantiate an eyros database.
@@ -17,10 +59,9 @@ pub struct SetupFields {
 /// configuration:
 ///
 /// ```rust,no_run
-/// use eyros::{DB,Setup};
+/// use eyros::{DB,Setup,Error};
 /// use random_access_disk::RandomAccessDisk;
 /// use std::path::PathBuf;
-/// # use failure::Error;
 ///
 /// type P = ((f32,f32),(f32,f32));
 /// type V = u32;
@@ -33,22 +74,22 @@ pub struct SetupFields {
 ///   .build()?;
 /// # Ok(()) }
 ///
-/// fn storage(name: &str) -> Result<RandomAccessDisk,Error> {
+/// fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
 ///   let mut p = PathBuf::from("/tmp/eyros-db/");
 ///   p.push(name);
 ///   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
 /// }
 /// ```
 pub struct Setup<S,U> where
-S: RandomAccess<Error=Error>,
-U: (Fn(&str) -> Result<S,Error>) {
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>) {
   pub open_store: U,
   pub fields: SetupFields
 }
 
 impl<S,U> Setup<S,U> where
-S: RandomAccess<Error=Error>,
-U: (Fn(&str) -> Result<S,Error>) {
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>) {
   /// Create a new `Setup` builder from a storage function.
   pub fn new (open_store: U) -> Self {
     Self {
@@ -58,7 +99,41 @@ U: (Fn(&str) -> Result<S,Error>) {
         max_data_size: 3_000,
         base_size: 9_000,
         bbox_cache_size: 10_000,
-        data_list_cache_size: 16_000
+        data_list_cache_size: 16_000,
+        cache_bytes: None,
+        range_chunk_size: RANGE_CHUNK_SIZE,
+        maintenance_cache: CacheMode::Bypass,
+        retry: RetryPolicy::none(),
+        clock: default_clock(),
+        seed: 0,
+        check_conflicts: false,
+        heat_half_life: None,
+        tree_format: TreeFormat::default(),
+        wal: false,
+        changes: false,
+        retention: Retention::default(),
+        compact_ratio: None,
+        block_checksums: true,
+        compression: Compression::None,
+        encryption: None,
+        store_keys: None,
+        max_queries: None,
+        admission: Admission::Reject,
+        max_open_trees: None,
+        max_buffered_blocks: None,
+        query_threads: 1,
+        max_trees: None,
+        absorb_batch_size: None,
+        absorb_tree_size: 0,
+        max_dirty_bytes: None,
+        read_only: false,
+        coalesce_span: 0,
+        selectivity_order: false,
+        realtime_deadline: None,
+        max_debt: 0,
+        auto_tune: None,
+        lock_file: None,
+        lock: true
       }
     }
   }
@@ -82,6 +157,277 @@ U: (Fn(&str) -> Result<S,Error>) {
     self.fields.data_list_cache_size = size;
     self
   }
+  /// Keep the bbox and data list caches within about `n` bytes of memory.
+  ///
+  /// Blocks vary widely in size, so an entry count alone doesn't bound
+  /// memory. With a budget, the data list cache evicts its least recently
+  /// used blocks once their estimated size passes what's left after the bbox
+  /// cache, which gets at most half. The entry counts from
+  /// `bbox_cache_size()` and `data_list_cache_size()` still apply, so raise
+  /// them to let the budget decide. Unlimited by default.
+  pub fn cache_bytes (mut self, n: u64) -> Self {
+    self.fields.cache_bytes = Some(n);
+    self
+  }
+  /// Read the range store `bytes` at a time when loading the ranges of the
+  /// data blocks, instead of reading it all at once. The default is 64 KiB.
+  pub fn range_chunk_size (mut self, bytes: usize) -> Self {
+    self.fields.range_chunk_size = bytes;
+    self
+  }
+  /// Set whether sequential maintenance reads (tree merges) go through the
+  /// block caches. The default, `CacheMode::Bypass`, keeps merges from
+  /// evicting blocks that queries are using.
+  pub fn maintenance_cache (mut self, mode: CacheMode) -> Self {
+    self.fields.maintenance_cache = mode;
+    self
+  }
+  /// Set the retry policy for data and tree block reads. Use this with
+  /// network-backed stores so that transient failures don't abort queries.
+  pub fn retry (mut self, policy: RetryPolicy) -> Self {
+    self.fields.retry = policy;
+    self
+  }
+  /// Set the clock used for timestamps and for waiting between retries.
+  pub fn clock (mut self, clock: Arc<dyn Clock>) -> Self {
+    self.fields.clock = clock;
+    self
+  }
+  /// Set the seed for internal sampling decisions.
+  pub fn seed (mut self, seed: u64) -> Self {
+    self.fields.seed = seed;
+    self
+  }
+  /// Configure the database for reproducible test runs: time comes from a
+  /// `ManualClock` starting at the unix epoch (so waiting never blocks) and
+  /// sampling uses `seed`. Returns the clock so tests can advance it.
+  pub fn deterministic (self, seed: u64) -> (Self,Arc<ManualClock>) {
+    let clock = Arc::new(ManualClock::new(Duration::from_secs(0)));
+    let c: Arc<dyn Clock> = clock.clone();
+    (self.clock(c).seed(seed), clock)
+  }
+  /// Detect commits from other writers sharing the same storage, for backends
+  /// where file locks are unreliable (such as NFS or object stores).
+  ///
+  /// Every batch then records a sequence number in the meta file, and fails
+  /// with a `Conflict` error instead of overwriting another writer's commit.
+  /// The check is optimistic: it narrows the window for lost updates but
+  /// doesn't replace locking on backends that support it.
+  pub fn check_conflicts (mut self, check: bool) -> Self {
+    self.fields.check_conflicts = check;
+    self
+  }
+  /// Track how often queries read each data block, with counts that halve
+  /// every `half_life`. Read the scores with `db.block_heat()`.
+  pub fn track_heat (mut self, half_life: Duration) -> Self {
+    self.fields.heat_half_life = Some(half_life);
+    self
+  }
+  /// Set the format for trees built from now on. Existing trees keep the
+  /// format they were written with until they are merged into a new tree.
+  pub fn tree_format (mut self, format: TreeFormat) -> Self {
+    self.fields.tree_format = format;
+    self
+  }
+  /// Log each batch to a write-ahead log before writing it to the other
+  /// stores. Opening the database after a crash then either finishes the
+  /// last batch or rolls back its partial writes and applies it again. The
+  /// changes feed, views, and outbox events of a batch are written before it
+  /// commits and rolled back with it.
+  ///
+  /// This costs an extra synced write per batch, plus a copy of the staging
+  /// area for batches that may rewrite it and of each data block that the
+  /// batch deletes rows from or updates.
+  pub fn wal (mut self, enabled: bool) -> Self {
+    self.fields.wal = enabled;
+    self
+  }
+  /// Record every inserted and deleted row in an append-only changes feed,
+  /// read with `db.changes(since)` and replayed on another database with
+  /// `apply_changes()`.
+  ///
+  /// Without `wal(true)`, changes are appended after their batch commits, so
+  /// a crash between the two can leave a committed batch out of the feed.
+  pub fn changes (mut self, enabled: bool) -> Self {
+    self.fields.changes = enabled;
+    self
+  }
+  /// Limit how much history the changes feed keeps. Changes past the limits
+  /// are removed by `db.prune_history()` and `db.compact()`.
+  pub fn retention (mut self, retention: Retention) -> Self {
+    self.fields.retention = retention;
+    self
+  }
+  /// Let maintenance compact the data store once dead blocks, which no tree
+  /// references anymore, take up more than `ratio` of it. Checking reads
+  /// every branch block, like `db.disk_usage()`. Off by default.
+  pub fn compact_ratio (mut self, ratio: f64) -> Self {
+    self.fields.compact_ratio = Some(ratio);
+    self
+  }
+  /// Set whether new data blocks carry a checksum of their rows and whether
+  /// checksums are verified when blocks are read. Blocks that fail the check
+  /// are quarantined like other unreadable blocks. Enabled by default.
+  ///
+  /// Blocks written before checksums were added are read without checks.
+  /// Disable this to keep writing blocks that older versions of eyros can
+  /// read.
+  pub fn block_checksums (mut self, enabled: bool) -> Self {
+    self.fields.block_checksums = enabled;
+    self
+  }
+  /// Set the compression for the rows of data blocks written from now on.
+  /// Existing blocks keep the codec they were written with.
+  pub fn compression (mut self, compression: Compression) -> Self {
+    self.fields.compression = compression;
+    self
+  }
+  /// Encrypt the rows of data blocks written from now on with `cipher` and
+  /// `key`. Existing blocks are read with the key of the epoch they were
+  /// written with and re-encrypted by `db.compact()`.
+  ///
+  /// After `db.rotate_key()`, pass the new key here and the earlier keys to
+  /// `old_key()`. Blocks whose key is missing can't be read and are
+  /// quarantined like other unreadable blocks.
+  pub fn encryption (mut self, cipher: Arc<dyn Cipher>, key: Vec<u8>) -> Self {
+    let old_keys = self.fields.encryption.take()
+      .map(|e| e.old_keys).unwrap_or_default();
+    self.fields.encryption = Some(Encryption { cipher, key, old_keys });
+    self
+  }
+  /// Add the key of an earlier key `epoch` to read blocks that haven't been
+  /// re-encrypted since `db.rotate_key()`. Call this after `encryption()`,
+  /// or on a `Setup` from `Setup::encrypted()` with the old `StorageKey`
+  /// (`key.into()`) to read stores that haven't been compacted since.
+  pub fn old_key (mut self, epoch: u32, key: Vec<u8>) -> Self {
+    if let Some(keys) = &self.fields.store_keys {
+      if let Ok(mut keys) = keys.write() {
+        keys.add_old(epoch, key.clone());
+      }
+    }
+    if let Some(encryption) = &mut self.fields.encryption {
+      encryption.old_keys.push((epoch,key));
+    }
+    self
+  }
+  /// Limit the number of queries in flight on the database handle to `limit`.
+  /// A query holds its slot from the call to `query()` until its iterator is
+  /// exhausted or dropped. `nearest_iter()` holds a slot the same way, and
+  /// `nearest()`, `top_k()`, `count()`, `aggregate()`, and `query_digest()`
+  /// hold one while they run. `mode` decides whether queries past the limit
+  /// fail with an `Overloaded` error or wait for a slot. Unlimited by default.
+  pub fn max_queries (mut self, limit: usize, mode: Admission) -> Self {
+    self.fields.max_queries = Some(limit);
+    self.fields.admission = mode;
+    self
+  }
+  /// Interleave the results of at most `n` trees at a time in each query.
+  /// See `QueryIterator::max_open_trees()`. Unlimited by default.
+  pub fn max_open_trees (mut self, n: usize) -> Self {
+    self.fields.max_open_trees = Some(n.max(1));
+    self
+  }
+  /// Hold the rows of at most `n` data blocks in each query, spilling the
+  /// rest. See `QueryIterator::max_buffered_blocks()`. Unlimited by default.
+  pub fn max_buffered_blocks (mut self, n: usize) -> Self {
+    self.fields.max_buffered_blocks = Some(n.max(1));
+    self
+  }
+  /// Read the trees of `db.query_parallel()` queries on up to `n` worker
+  /// threads. Defaults to `1`, which reads them on the calling thread like
+  /// `query()`.
+  pub fn query_threads (mut self, n: usize) -> Self {
+    self.fields.query_threads = n.max(1);
+    self
+  }
+  /// Keep at most `n` trees. A batch that leaves more trees than that merges
+  /// the smallest ones, even where the regular merge schedule wouldn't, since
+  /// every query visits every tree. Unlimited by default.
+  pub fn max_trees (mut self, n: usize) -> Self {
+    self.fields.max_trees = Some(n.max(1));
+    self
+  }
+  /// When a batch of at most `batch_size` rows fills the staging area, write
+  /// the staged and new rows into the smallest tree instead of starting a new
+  /// one, as long as that tree ends up with at most `tree_size` rows. The tree
+  /// moves to the smallest free size class that fits its rows, rebuilding its
+  /// branches but keeping its data blocks, so drip-fed ingestion doesn't pile
+  /// up small trees that later need merging. Off by default.
+  pub fn absorb (mut self, batch_size: usize, tree_size: usize) -> Self {
+    self.fields.absorb_batch_size = Some(batch_size);
+    self.fields.absorb_tree_size = tree_size;
+    self
+  }
+  /// Buffer at most `n` bytes of writes to each staging store between
+  /// commits. Past that, the oldest buffered writes go to the store right
+  /// away, so a large batch doesn't hold all of its staged rows in memory
+  /// twice. Reads see flushed and buffered writes alike. Unlimited by default.
+  pub fn max_dirty_bytes (mut self, n: u64) -> Self {
+    self.fields.max_dirty_bytes = Some(n);
+    self
+  }
+  /// When a query reaches a branch that points at several child branches,
+  /// read the children with one request for each run of them that spans at
+  /// most `max_span` bytes, instead of one or two requests each.
+  ///
+  /// Children are written next to each other, so this cuts the number of
+  /// reads for deep trees at the cost of also reading siblings that the
+  /// query skips. Use it where each request costs a round trip, as with
+  /// `DB::open_remote()`. Off by default.
+  pub fn coalesce_branches (mut self, max_span: u64) -> Self {
+    self.fields.coalesce_span = max_span;
+    self
+  }
+  /// Keep per-axis histograms of the data block bounds of each tree and use
+  /// them to visit the trees of each query, and the branches and data blocks
+  /// within each tree, in order of how many results they are expected to
+  /// hold, so that a query with `limit()` reads fewer blocks before it has
+  /// its results.
+  ///
+  /// The histograms live in memory. They are built from the branches and the
+  /// range store the first time a query reaches a tree after it was written.
+  /// Off by default.
+  pub fn selectivity_order (mut self, enabled: bool) -> Self {
+    self.fields.selectivity_order = enabled;
+    self
+  }
+  /// Keep batches within `deadline` on the database clock by leaving the
+  /// building and merging of trees, and the summaries and indexes that come
+  /// with them, to `run_maintenance()`. Batches write their rows to the
+  /// staging area, which queries scan in full, and only build trees inline
+  /// when the time the last build took per row says that it fits in what is
+  /// left of `deadline`.
+  ///
+  /// Rows that wait in staging past `base_size()` are the maintenance debt,
+  /// from `db.debt()`. Once it exceeds `max_debt` rows, batches fail with a
+  /// `Backpressure` error without writing anything until maintenance catches
+  /// up. Off by default.
+  pub fn realtime (mut self, deadline: Duration, max_debt: usize) -> Self {
+    self.fields.realtime_deadline = Some(deadline);
+    self.fields.max_debt = max_debt;
+    self
+  }
+  /// Once `rows` rows have been written into trees, look at the data blocks
+  /// to choose a `max_data_size()` that suits the data: blocks of about
+  /// 64 KB given the bytes each row takes, and twice that when blocks
+  /// overlap a lot, since queries then read many blocks anyway.
+  ///
+  /// The analysis runs once. Its findings and decision are kept in the meta
+  /// store, where `db.tuning()` returns them, and an applied tuning
+  /// overrides `max_data_size()` for data written from then on, including
+  /// after the database is opened again. With `AutoTune::Confirm` the new
+  /// settings wait for `db.apply_tuning()`. Off by default.
+  pub fn auto_tune (mut self, rows: u64, mode: AutoTune) -> Self {
+    self.fields.auto_tune = Some((rows,mode));
+    self
+  }
+  /// Open the database for queries only. Batches, deletes, maintenance, and
+  /// other writes fail with a `ReadOnly` error without touching storage, and
+  /// the write-ahead log isn't opened or recovered. Off by default.
+  pub fn read_only (mut self, enabled: bool) -> Self {
+    self.fields.read_only = enabled;
+    self
+  }
   pub fn build<P,V> (self) -> Result<DB<S,U,P,V>,Error>
   where P: Point, V: Value {
     DB::open_from_setup(self)
@@ -0,0 +1,82 @@
+use crate::{DB,Row,Point,Value,Setup};
+use crate::Error;
+use random_access_storage::RandomAccess;
+
+/// Summary of one shard written by `export_sharded()`.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct ShardInfo {
+  /// Name of the grid cell.
+  pub name: String,
+  /// Number of rows written to the shard.
+  pub rows: usize
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Split the database into one shard per grid cell so that clients can
+  /// download or deploy only the regions they need.
+  ///
+  /// `grid` is a list of cell names and bounding boxes (such as countries or
+  /// map tiles). Each shard is a standalone database holding the rows that
+  /// intersect its cell, written with this database's settings to the stores
+  /// that `open_shard(cell_name, store_name)` returns. Rows that span several
+  /// cells are copied into each of them.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Error};
+  /// use random_access_disk::RandomAccessDisk;
+  /// use std::path::PathBuf;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut db: DB<_,_,((f32,f32),(f32,f32)),u32> = DB::open(storage)?;
+  /// let grid = vec![
+  ///   ("west".to_string(), ((-180.0,-90.0),(0.0,90.0))),
+  ///   ("east".to_string(), ((0.0,-90.0),(180.0,90.0))),
+  /// ];
+  /// db.export_sharded(&grid, |cell,name| {
+  ///   let mut p = PathBuf::from("/tmp/eyros-shards/");
+  ///   p.push(cell);
+  ///   p.push(name);
+  ///   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// })?;
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn export_sharded<T,F> (&mut self, grid: &[(String,P::Bounds)],
+  open_shard: F) -> Result<Vec<ShardInfo>,Error> where
+  T: RandomAccess<Error=failure::Error>,
+  F: Fn(&str,&str) -> Result<T,failure::Error> {
+    self.export_shards(grid, open_shard, |p| p)
+  }
+
+  // export shards with each point passed through `map`
+  pub(crate) fn export_shards<T,F,M> (&mut self, grid: &[(String,P::Bounds)],
+  open_shard: F, map: M) -> Result<Vec<ShardInfo>,Error> where
+  T: RandomAccess<Error=failure::Error>,
+  F: Fn(&str,&str) -> Result<T,failure::Error>,
+  M: Fn(P) -> P {
+    let mut shards = Vec::with_capacity(grid.len());
+    for (cell,bbox) in grid.iter() {
+      let mut rows = vec![];
+      for result in self.scan(bbox)? {
+        let (p,v,_) = result?;
+        rows.push(Row::Insert(map(p),v));
+      }
+      let mut shard: DB<T,_,P,V> = Setup::new(|name: &str| open_shard(cell, name))
+        .branch_factor(self.fields.branch_factor)
+        .max_data_size(self.fields.max_data_size)
+        .base_size(self.fields.base_size)
+        .tree_format(self.fields.tree_format)
+        .build()?;
+      shard.batch(&rows)?;
+      shard.close()?;
+      shards.push(ShardInfo { name: cell.clone(), rows: rows.len() });
+    }
+    Ok(shards)
+  }
+}
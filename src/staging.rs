@@ -1,22 +1,34 @@
 use crate::{Point,Value,Location,write_cache::WriteCache};
-use failure::{Error};
+use crate::stats::{Counted,Counters};
+use crate::Error;
 use random_access_storage::RandomAccess;
 use std::collections::HashSet;
-use std::rc::Rc;
-use std::cell::RefCell;
+use std::sync::{Arc,RwLock};
+use crate::lock::Lock;
+use std::marker::PhantomData;
 use desert::{FromBytes,ToBytes,CountBytes};
 
 pub struct StagingIterator<'b,P,V> where P: Point, V: Value {
-  inserts: Rc<RefCell<Vec<(P,V)>>>,
-  deletes: Rc<RefCell<HashSet<Location>>>,
-  bbox: &'b P::Bounds,
-  index: u32
+  inserts: Arc<RwLock<Vec<(P,V)>>>,
+  deletes: Arc<RwLock<HashSet<Location>>>,
+  bbox: P::Bounds,
+  index: u32,
+  _bbox: PhantomData<&'b P::Bounds>
 }
 
 impl<'b,P,V> StagingIterator<'b,P,V> where P: Point, V: Value {
-  pub fn new (inserts: Rc<RefCell<Vec<(P,V)>>>,
-  deletes: Rc<RefCell<HashSet<Location>>>, bbox: &'b P::Bounds) -> Self {
-    Self { index: 0, bbox, inserts, deletes }
+  pub fn new (inserts: Arc<RwLock<Vec<(P,V)>>>,
+  deletes: Arc<RwLock<HashSet<Location>>>, bbox: &P::Bounds) -> Self {
+    Self { index: 0, bbox: *bbox, inserts, deletes, _bbox: PhantomData }
+  }
+  /// Index of the next staged row to check.
+  pub(crate) fn position (&self) -> u32 {
+    self.index
+  }
+  /// Continue from a position returned by `position()`.
+  pub(crate) fn resume (mut self, index: u32) -> Self {
+    self.index = index;
+    self
   }
 }
 
@@ -24,15 +36,15 @@ impl<'b,P,V> Iterator for StagingIterator<'b,P,V>
 where P: Point, V: Value {
   type Item = Result<(P,V,Location),Error>;
   fn next (&mut self) -> Option<Self::Item> {
-    let len = iwrap![self.inserts.try_borrow()].len();
+    let len = iwrap![self.inserts.read_lock()].len();
     while (self.index as usize) < len {
       let i = self.index;
       self.index += 1;
-      if iwrap![self.deletes.try_borrow()].contains(&(0,i)) {
+      if iwrap![self.deletes.read_lock()].contains(&(0,i)) {
         continue;
       }
-      let (point,value) = &iwrap![self.inserts.try_borrow()][i as usize];
-      if point.overlaps(self.bbox) {
+      let (point,value) = &iwrap![self.inserts.read_lock()][i as usize];
+      if point.overlaps(&self.bbox) {
         return Some(Ok((*point,value.clone(),(0, i))));
       }
     }
@@ -41,49 +53,56 @@ where P: Point, V: Value {
 }
 
 pub struct Staging<S,P,V>
-where S: RandomAccess<Error=Error>, P: Point, V: Value {
-  insert_store: WriteCache<S>,
-  delete_store: WriteCache<S>,
-  pub inserts: Rc<RefCell<Vec<(P,V)>>>,
-  pub deletes: Rc<RefCell<Vec<Location>>>,
-  pub delete_set: Rc<RefCell<HashSet<Location>>>
+where S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  insert_store: WriteCache<Counted<S>>,
+  delete_store: WriteCache<Counted<S>>,
+  pub inserts: Arc<RwLock<Vec<(P,V)>>>,
+  pub deletes: Arc<RwLock<Vec<Location>>>,
+  pub delete_set: Arc<RwLock<HashSet<Location>>>
 }
 
 impl<S,P,V> Staging<S,P,V>
-where S: RandomAccess<Error=Error>, P: Point, V: Value {
-  pub fn open (istore: S, dstore: S) -> Result<Self,Error> {
+where S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  pub(crate) fn open (istore: S, dstore: S, counters: Arc<Counters>)
+  -> Result<Self,Error> {
     let mut staging = Self {
-      insert_store: WriteCache::open(istore)?,
-      delete_store: WriteCache::open(dstore)?,
-      inserts: Rc::new(RefCell::new(vec![])),
-      deletes: Rc::new(RefCell::new(vec![])),
-      delete_set: Rc::new(RefCell::new(HashSet::new()))
+      insert_store: WriteCache::open(Counted::new(istore, Arc::clone(&counters)))?,
+      delete_store: WriteCache::open(Counted::new(dstore, counters))?,
+      inserts: Arc::new(RwLock::new(vec![])),
+      deletes: Arc::new(RwLock::new(vec![])),
+      delete_set: Arc::new(RwLock::new(HashSet::new()))
     };
     staging.load()?;
     Ok(staging)
   }
+  /// Limit the writes each staging store buffers until `commit()`.
+  pub fn max_dirty_bytes (&mut self, max: Option<u64>) -> Result<(),Error> {
+    self.insert_store.max_dirty_bytes(max)?;
+    self.delete_store.max_dirty_bytes(max)?;
+    Ok(())
+  }
   fn load (&mut self) -> Result<(),Error> {
     if !self.insert_store.is_empty()? {
-      self.inserts.try_borrow_mut()?.clear();
+      self.inserts.write_lock()?.clear();
       let len = self.insert_store.len()?;
       let buf = self.insert_store.read(0, len)?;
       let mut offset = 0;
       while offset < len as usize {
         let (size,pv) = <(P,V)>::from_bytes(&buf[offset..])?;
-        self.inserts.try_borrow_mut()?.push(pv);
+        self.inserts.write_lock()?.push(pv);
         offset += size;
       }
     }
     if !self.delete_store.is_empty()? {
-      self.deletes.try_borrow_mut()?.clear();
-      self.delete_set.try_borrow_mut()?.clear();
+      self.deletes.write_lock()?.clear();
+      self.delete_set.write_lock()?.clear();
       let len = self.delete_store.len()?;
       let buf = self.delete_store.read(0, len)?;
       let mut offset = 0;
       while offset < len as usize {
         let (size,loc) = Location::from_bytes(&buf[offset..])?;
-        self.deletes.try_borrow_mut()?.push(loc);
-        self.delete_set.try_borrow_mut()?.insert(loc);
+        self.deletes.write_lock()?.push(loc);
+        self.delete_set.write_lock()?.insert(loc);
         offset += size;
       }
     }
@@ -96,33 +115,50 @@ where S: RandomAccess<Error=Error>, P: Point, V: Value {
   }
   pub fn clear_inserts (&mut self) -> Result<(),Error> {
     self.insert_store.truncate(0)?;
-    self.inserts.try_borrow_mut()?.clear();
+    self.inserts.write_lock()?.clear();
     Ok(())
   }
   pub fn clear_deletes (&mut self) -> Result<(),Error> {
     self.delete_store.truncate(0)?;
-    self.deletes.try_borrow_mut()?.clear();
-    self.delete_set.try_borrow_mut()?.clear();
+    self.deletes.write_lock()?.clear();
+    self.delete_set.write_lock()?.clear();
     Ok(())
   }
-  pub fn delete (&mut self, deletes: &Vec<Location>) -> Result<(),Error> {
-    let mut del_set: HashSet<u32> = HashSet::new();
-    for delete in deletes {
-      if delete.0 == 0 { del_set.insert(delete.1); }
+  /// Replace staged rows by index and rewrite the insert store.
+  pub fn replace (&mut self, updates: &[(u32,P,V)]) -> Result<(),Error> {
+    if updates.is_empty() { return Ok(()) }
+    let mut inserts = self.inserts.write_lock()?;
+    for (i,point,value) in updates.iter() {
+      if let Some(row) = inserts.get_mut(*i as usize) {
+        *row = (*point,value.clone());
+      }
+    }
+    let mut buf = Vec::with_capacity(self.insert_store.len()? as usize);
+    for row in inserts.iter() {
+      buf.extend(row.to_bytes()?);
     }
-    let mut i = 0;
-    self.inserts.try_borrow_mut()?.retain(|_row| {
-      let j = i;
-      i += 1;
-      !del_set.contains(&j)
-    });
+    self.insert_store.truncate(0)?;
+    self.insert_store.write(0, &buf)?;
     Ok(())
   }
   pub fn bytes (&mut self) -> Result<u64,Error> {
     Ok(self.insert_store.len()? + self.delete_store.len()?)
   }
+  /// Sizes in bytes of the insert store and the delete store.
+  pub fn store_bytes (&mut self) -> Result<(u64,u64),Error> {
+    Ok((self.insert_store.len()?,self.delete_store.len()?))
+  }
+  /// Read the raw contents of the insert store and the delete store.
+  pub fn snapshot (&mut self) -> Result<(Vec<u8>,Vec<u8>),Error> {
+    let ilen = self.insert_store.len()?;
+    let dlen = self.delete_store.len()?;
+    Ok((
+      if ilen > 0 { self.insert_store.read(0, ilen)? } else { vec![] },
+      if dlen > 0 { self.delete_store.read(0, dlen)? } else { vec![] }
+    ))
+  }
   pub fn len (&mut self) -> Result<usize,Error> {
-    Ok(self.inserts.try_borrow()?.len() + self.deletes.try_borrow()?.len())
+    Ok(self.inserts.read_lock()?.len() + self.deletes.read_lock()?.len())
   }
   pub fn batch (&mut self, inserts: &Vec<(P,V)>, deletes: &Vec<Location>)
   -> Result<(),Error> {
@@ -155,10 +191,10 @@ where S: RandomAccess<Error=Error>, P: Point, V: Value {
     self.insert_store.write(i_offset,&ibuf)?;
     let d_offset = self.delete_store.len()?;
     self.delete_store.write(d_offset,&dbuf)?;
-    self.inserts.try_borrow_mut()?.extend_from_slice(inserts);
-    self.deletes.try_borrow_mut()?.extend_from_slice(deletes);
+    self.inserts.write_lock()?.extend_from_slice(inserts);
+    self.deletes.write_lock()?.extend_from_slice(deletes);
     for delete in deletes {
-      self.delete_set.try_borrow_mut()?.insert(*delete);
+      self.delete_set.write_lock()?.insert(*delete);
     }
     Ok(())
   }
@@ -167,11 +203,11 @@ where S: RandomAccess<Error=Error>, P: Point, V: Value {
     self.delete_store.sync_all()?;
     Ok(())
   }
-  pub fn query<'b> (&mut self, bbox: &'b P::Bounds)
+  pub fn query<'b> (&mut self, bbox: &P::Bounds)
   -> StagingIterator<'b,P,V> {
     <StagingIterator<'b,P,V>>::new(
-      Rc::clone(&self.inserts),
-      Rc::clone(&self.delete_set),
+      Arc::clone(&self.inserts),
+      Arc::clone(&self.delete_set),
       bbox
     )
   }
@@ -0,0 +1,332 @@
+use crate::{DB,Point,Value,Clock};
+use crate::lock::Lock;
+use crate::Error;
+use random_access_storage::RandomAccess;
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64,Ordering};
+use std::time::Duration;
+
+/// Cache and storage counters of a database handle, from `db.stats()`.
+///
+/// Counters start at zero when the database is opened and with
+/// `db.reset_stats()`. Bytes are counted for the data, range, tree, and
+/// staging stores, at the point where they reach the storage backend.
+#[derive(Clone,Copy,Debug,Default,PartialEq,Eq)]
+pub struct Stats {
+  /// Data block reads answered by the block cache.
+  pub block_cache_hits: u64,
+  /// Data block reads that loaded and parsed the block from storage.
+  pub block_cache_misses: u64,
+  /// Block bounding box lookups answered by the bbox cache.
+  pub bbox_cache_hits: u64,
+  /// Block bounding box lookups that read the block.
+  pub bbox_cache_misses: u64,
+  /// Bytes read from storage.
+  pub bytes_read: u64,
+  /// Read requests sent to storage.
+  pub reads: u64,
+  /// Bytes written to storage.
+  pub bytes_written: u64,
+  /// Bytes read from storage by the last `batch()`.
+  pub batch_bytes_read: u64,
+  /// Bytes written to storage by the last `batch()`.
+  pub batch_bytes_written: u64,
+  /// Data blocks that were quarantined because they couldn't be read.
+  pub quarantined_blocks: u64
+}
+
+/// Query durations of a database handle, from `db.latency_percentiles()`.
+///
+/// Percentiles are read from a histogram with 8 buckets per power of two, so
+/// they overstate the true value by less than 12.5%. They are never more
+/// than `max`, which is exact. Every duration is zero when `count` is zero.
+#[derive(Clone,Copy,Debug,Default,PartialEq,Eq)]
+pub struct Latency {
+  /// Number of queries recorded.
+  pub count: u64,
+  pub p50: Duration,
+  pub p95: Duration,
+  pub p99: Duration,
+  pub max: Duration
+}
+
+// sub-buckets per power of two, as a shift
+const SUB_BITS: u32 = 3;
+const BUCKETS: usize = ((64 - SUB_BITS as usize) << SUB_BITS) + (1 << SUB_BITS);
+
+// log-linear histogram of nanoseconds
+pub(crate) struct Histogram {
+  buckets: Vec<AtomicU64>,
+  max: AtomicU64
+}
+
+impl Default for Histogram {
+  fn default () -> Self {
+    Self {
+      buckets: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+      max: AtomicU64::new(0)
+    }
+  }
+}
+
+impl Histogram {
+  pub fn record (&self, duration: Duration) {
+    let nanos = duration.as_nanos().min(u64::MAX as u128) as u64;
+    self.buckets[Self::bucket(nanos)].fetch_add(1, Ordering::Relaxed);
+    self.max.fetch_max(nanos, Ordering::Relaxed);
+  }
+  fn bucket (nanos: u64) -> usize {
+    if nanos < (1 << SUB_BITS) { return nanos as usize }
+    let exp = 63 - nanos.leading_zeros();
+    let sub = (nanos >> (exp - SUB_BITS)) & ((1 << SUB_BITS) - 1);
+    (((exp - SUB_BITS + 1) as usize) << SUB_BITS) + sub as usize
+  }
+  // largest value that falls into bucket i
+  fn upper (i: usize) -> u64 {
+    if i < (1 << SUB_BITS) { return i as u64 }
+    let exp = (i >> SUB_BITS) as u32 + SUB_BITS - 1;
+    let sub = (i & ((1 << SUB_BITS) - 1)) as u64;
+    let width = 1u64 << (exp - SUB_BITS);
+    (((1 << SUB_BITS) + sub) << (exp - SUB_BITS)).saturating_add(width - 1)
+  }
+  fn percentile (&self, counts: &[u64], total: u64, max: u64, q: f64) -> Duration {
+    if total == 0 { return Duration::from_nanos(0) }
+    let rank = ((q * total as f64).ceil() as u64).max(1);
+    let mut seen = 0;
+    for (i,n) in counts.iter().enumerate() {
+      seen += n;
+      if seen >= rank { return Duration::from_nanos(Self::upper(i).min(max)) }
+    }
+    Duration::from_nanos(max)
+  }
+  fn latency (&self) -> Latency {
+    let counts: Vec<u64> = self.buckets.iter()
+      .map(|b| b.load(Ordering::Relaxed)).collect();
+    let total = counts.iter().sum();
+    let max = self.max.load(Ordering::Relaxed);
+    Latency {
+      count: total,
+      p50: self.percentile(&counts, total, max, 0.50),
+      p95: self.percentile(&counts, total, max, 0.95),
+      p99: self.percentile(&counts, total, max, 0.99),
+      max: if total == 0 { Duration::from_nanos(0) } else { Duration::from_nanos(max) }
+    }
+  }
+  fn reset (&self) {
+    for b in self.buckets.iter() {
+      b.store(0, Ordering::Relaxed);
+    }
+    self.max.store(0, Ordering::Relaxed);
+  }
+}
+
+#[derive(Default)]
+pub(crate) struct Counters {
+  pub block_hits: AtomicU64,
+  pub block_misses: AtomicU64,
+  pub bbox_hits: AtomicU64,
+  pub bbox_misses: AtomicU64,
+  pub bytes_read: AtomicU64,
+  pub reads: AtomicU64,
+  pub bytes_written: AtomicU64,
+  pub batch_read: AtomicU64,
+  pub batch_written: AtomicU64,
+  pub quarantined: AtomicU64,
+  pub latency: Histogram
+}
+
+// running query, recorded in the latency histogram once it is done or dropped
+pub(crate) struct Timer {
+  counters: Arc<Counters>,
+  clock: Arc<dyn Clock>,
+  start: Duration
+}
+
+impl Timer {
+  pub fn new (counters: Arc<Counters>, clock: Arc<dyn Clock>) -> Self {
+    let start = clock.now();
+    Self { counters, clock, start }
+  }
+}
+
+impl Drop for Timer {
+  fn drop (&mut self) {
+    let elapsed = self.clock.now().checked_sub(self.start)
+      .unwrap_or(Duration::from_nanos(0));
+    self.counters.latency.record(elapsed);
+  }
+}
+
+impl Counters {
+  pub fn bump (counter: &AtomicU64, n: u64) {
+    counter.fetch_add(n, Ordering::Relaxed);
+  }
+  /// Bytes read and written so far, to pass to `record_batch()`.
+  pub fn io (&self) -> (u64,u64) {
+    (Self::get(&self.bytes_read), Self::get(&self.bytes_written))
+  }
+  /// Record the bytes read and written since `io()` returned `start` as the
+  /// bytes of the last batch.
+  pub fn record_batch (&self, start: (u64,u64)) {
+    let (read,written) = self.io();
+    self.batch_read.store(read.saturating_sub(start.0), Ordering::Relaxed);
+    self.batch_written.store(written.saturating_sub(start.1), Ordering::Relaxed);
+  }
+  fn get (counter: &AtomicU64) -> u64 {
+    counter.load(Ordering::Relaxed)
+  }
+  fn stats (&self) -> Stats {
+    Stats {
+      block_cache_hits: Self::get(&self.block_hits),
+      block_cache_misses: Self::get(&self.block_misses),
+      bbox_cache_hits: Self::get(&self.bbox_hits),
+      bbox_cache_misses: Self::get(&self.bbox_misses),
+      bytes_read: Self::get(&self.bytes_read),
+      reads: Self::get(&self.reads),
+      bytes_written: Self::get(&self.bytes_written),
+      batch_bytes_read: Self::get(&self.batch_read),
+      batch_bytes_written: Self::get(&self.batch_written),
+      quarantined_blocks: Self::get(&self.quarantined)
+    }
+  }
+  fn reset (&self) {
+    for c in [&self.block_hits, &self.block_misses, &self.bbox_hits,
+    &self.bbox_misses, &self.bytes_read, &self.reads, &self.bytes_written, &self.batch_read,
+    &self.batch_written, &self.quarantined].iter() {
+      c.store(0, Ordering::Relaxed);
+    }
+  }
+}
+
+/// Storage wrapper that counts the bytes read from and written to `store`.
+pub struct Counted<S> where S: RandomAccess {
+  store: S,
+  pub(crate) counters: Arc<Counters>
+}
+
+impl<S> Counted<S> where S: RandomAccess {
+  pub(crate) fn new (store: S, counters: Arc<Counters>) -> Self {
+    Self { store, counters }
+  }
+}
+
+impl<S> RandomAccess for Counted<S> where S: RandomAccess {
+  type Error = S::Error;
+  fn write (&mut self, offset: u64, data: &[u8]) -> Result<(),Self::Error> {
+    self.store.write(offset, data)?;
+    Counters::bump(&self.counters.bytes_written, data.len() as u64);
+    Ok(())
+  }
+  fn read (&mut self, offset: u64, length: u64)
+  -> Result<Vec<u8>,Self::Error> {
+    let buf = self.store.read(offset, length)?;
+    Counters::bump(&self.counters.bytes_read, buf.len() as u64);
+    Counters::bump(&self.counters.reads, 1);
+    Ok(buf)
+  }
+  fn read_to_writer (&mut self, offset: u64, length: u64,
+  buf: &mut impl Write) -> Result<(),Self::Error> {
+    self.store.read_to_writer(offset, length, buf)?;
+    Counters::bump(&self.counters.bytes_read, length);
+    Counters::bump(&self.counters.reads, 1);
+    Ok(())
+  }
+  fn del (&mut self, offset: u64, length: u64) -> Result<(),Self::Error> {
+    self.store.del(offset, length)
+  }
+  fn truncate (&mut self, length: u64) -> Result<(),Self::Error> {
+    self.store.truncate(length)
+  }
+  fn len (&self) -> Result<u64,Self::Error> {
+    self.store.len()
+  }
+  fn is_empty (&mut self) -> Result<bool,Self::Error> {
+    self.store.is_empty()
+  }
+  fn sync_all (&mut self) -> Result<(),Self::Error> {
+    self.store.sync_all()
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Read the cache and storage counters of this handle.
+  ///
+  /// Compare hits and misses to size `Setup::data_list_cache_size()` and
+  /// `Setup::bbox_cache_size()`, and the bytes per batch to choose
+  /// `Setup::max_data_size()` for a workload:
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Row,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut db: DB<_,_,((f32,f32),(f32,f32)),u32> = DB::open(storage)?;
+  /// # let rows: Vec<Row<((f32,f32),(f32,f32)),u32>> = vec![];
+  /// db.batch(&rows)?;
+  /// let stats = db.stats()?;
+  /// eprintln!["batch wrote {} bytes", stats.batch_bytes_written];
+  /// let lookups = stats.block_cache_hits + stats.block_cache_misses;
+  /// if lookups > 0 {
+  ///   eprintln!["block cache hit rate {:.2}",
+  ///     stats.block_cache_hits as f64 / lookups as f64];
+  /// }
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn stats (&self) -> Result<Stats,Error> {
+    Ok(self.data_store.read_lock()?.counters.stats())
+  }
+  /// Set every counter in `stats()` back to zero.
+  pub fn reset_stats (&self) -> Result<(),Error> {
+    self.data_store.read_lock()?.counters.reset();
+    Ok(())
+  }
+
+  /// Read the p50, p95, and p99 durations of the queries made through this
+  /// handle since it was opened or since `reset_latency()`.
+  ///
+  /// A query is timed on the database clock from the call that starts it
+  /// until its iterator runs out or is dropped, so time the caller spends
+  /// between rows counts too. Queries behind `count()`, `nearest()`,
+  /// `top_k()`, `delete_query()`, and the other read helpers are recorded as
+  /// well. Report and reset on an interval to get per-interval percentiles:
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut db: DB<_,_,(f32,f32),u32> = DB::open(storage)?;
+  /// let latency = db.latency_percentiles()?;
+  /// eprintln!["{} queries p50={:?} p95={:?} p99={:?}",
+  ///   latency.count, latency.p50, latency.p95, latency.p99];
+  /// db.reset_latency()?;
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn latency_percentiles (&self) -> Result<Latency,Error> {
+    Ok(self.data_store.read_lock()?.counters.latency.latency())
+  }
+  /// Clear the durations behind `latency_percentiles()`. `reset_stats()`
+  /// leaves them alone.
+  pub fn reset_latency (&self) -> Result<(),Error> {
+    self.data_store.read_lock()?.counters.latency.reset();
+    Ok(())
+  }
+
+  pub(crate) fn counters (&self) -> Result<Arc<Counters>,Error> {
+    Ok(Arc::clone(&self.data_store.read_lock()?.counters))
+  }
+}
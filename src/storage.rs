@@ -0,0 +1,73 @@
+use failure::Error;
+use random_access_storage::RandomAccess;
+use std::io;
+
+/// Wrapper for a `RandomAccess` store with its own error type, such as an
+/// IndexedDB-backed store in the browser, so that it can back a database.
+///
+/// The database expects stores whose errors are `failure::Error`. `Adapter`
+/// converts any error that `failure::Error` can be built from, which
+/// includes every type that implements `std::error::Error + Send + Sync`.
+/// Use `adapt()` to wrap a whole storage function.
+pub struct Adapter<T> {
+  inner: T
+}
+
+impl<T> Adapter<T> {
+  pub fn new (inner: T) -> Self {
+    Self { inner }
+  }
+  pub fn get_ref (&self) -> &T {
+    &self.inner
+  }
+  pub fn get_mut (&mut self) -> &mut T {
+    &mut self.inner
+  }
+  pub fn into_inner (self) -> T {
+    self.inner
+  }
+}
+
+impl<T,E> RandomAccess for Adapter<T>
+where T: RandomAccess<Error=E>, Error: From<E> {
+  type Error = Error;
+  fn write (&mut self, offset: u64, data: &[u8]) -> Result<(),Error> {
+    Ok(self.inner.write(offset, data)?)
+  }
+  fn read (&mut self, offset: u64, length: u64) -> Result<Vec<u8>,Error> {
+    Ok(self.inner.read(offset, length)?)
+  }
+  fn read_to_writer (&mut self, offset: u64, length: u64,
+  buf: &mut impl io::Write) -> Result<(),Error> {
+    Ok(self.inner.read_to_writer(offset, length, buf)?)
+  }
+  fn del (&mut self, offset: u64, length: u64) -> Result<(),Error> {
+    Ok(self.inner.del(offset, length)?)
+  }
+  fn truncate (&mut self, length: u64) -> Result<(),Error> {
+    Ok(self.inner.truncate(length)?)
+  }
+  fn len (&self) -> Result<u64,Error> {
+    Ok(self.inner.len()?)
+  }
+  fn is_empty (&mut self) -> Result<bool,Error> {
+    Ok(self.inner.is_empty()?)
+  }
+  fn sync_all (&mut self) -> Result<(),Error> {
+    Ok(self.inner.sync_all()?)
+  }
+}
+
+/// Turn a storage function that returns stores with their own error type
+/// into one that `DB::open()` and `Setup::new()` accept.
+///
+/// ```rust,ignore
+/// use eyros::{DB,adapt};
+///
+/// // open_idb(name) returns Result<impl RandomAccess<Error=E>,E>
+/// let mut db: DB<_,_,((f32,f32),(f32,f32)),u32> = DB::open(adapt(open_idb))?;
+/// ```
+pub fn adapt<T,E,F> (open: F) -> impl Fn(&str) -> Result<Adapter<T>,Error>
+where T: RandomAccess<Error=E>, Error: From<E>, F: Fn(&str) -> Result<T,E> {
+  move |name: &str| Ok(Adapter::new(open(name)?))
+}
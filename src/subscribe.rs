@@ -0,0 +1,162 @@
+use crate::{DB,Point,Value,Change};
+use crate::Error;
+use random_access_storage::RandomAccess;
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::sync::{Arc,Mutex};
+use std::task::{Poll,Waker};
+
+/// Live query from `db.subscribe()` that receives the changes of later
+/// batches to rows that intersect its bounding box.
+///
+/// Changes queue up until they are read, with `try_next()` from synchronous
+/// code or `next().await` from async code, so read them regularly or drop the
+/// subscription. Once the database is closed or dropped, the subscription
+/// ends after the queued changes.
+pub struct Subscription<P,V> where P: Point, V: Value {
+  shared: Queue<P,V>
+}
+
+struct Shared<P,V> where P: Point, V: Value {
+  queue: VecDeque<Change<P,V>>,
+  waker: Option<Waker>,
+  closed: bool
+}
+
+impl<P,V> Subscription<P,V> where P: Point, V: Value {
+  /// Return the next queued change, or `None` if there is none right now.
+  pub fn try_next (&mut self) -> Option<Change<P,V>> {
+    self.shared.lock().ok()?.queue.pop_front()
+  }
+  /// Wait for the next change. Returns `None` once the database is closed
+  /// and every queued change was read.
+  pub async fn next (&mut self) -> Option<Change<P,V>> {
+    poll_fn(|cx| {
+      let mut shared = match self.shared.lock() {
+        Ok(shared) => shared,
+        Err(_) => return Poll::Ready(None)
+      };
+      if let Some(change) = shared.queue.pop_front() {
+        return Poll::Ready(Some(change));
+      }
+      if shared.closed { return Poll::Ready(None) }
+      shared.waker = Some(cx.waker().clone());
+      Poll::Pending
+    }).await
+  }
+  /// Return the number of changes waiting to be read.
+  pub fn pending (&self) -> usize {
+    self.shared.lock().map(|s| s.queue.len()).unwrap_or(0)
+  }
+  /// Return whether the database was closed or dropped.
+  pub fn is_closed (&self) -> bool {
+    self.shared.lock().map(|s| s.closed).unwrap_or(true)
+  }
+}
+
+type Queue<P,V> = Arc<Mutex<Shared<P,V>>>;
+
+// subscriptions of a database handle with their bounding boxes
+pub(crate) struct Registry<P,V> where P: Point, V: Value {
+  entries: Vec<(P::Bounds,Queue<P,V>)>
+}
+
+impl<P,V> Default for Registry<P,V> where P: Point, V: Value {
+  fn default () -> Self {
+    Self { entries: vec![] }
+  }
+}
+
+impl<P,V> Registry<P,V> where P: Point, V: Value {
+  pub fn is_empty (&self) -> bool {
+    self.entries.is_empty()
+  }
+  // queue the changes that intersect each subscription and wake its reader,
+  // forgetting subscriptions that were dropped
+  pub fn notify (&mut self, changes: &[Change<P,V>]) {
+    self.entries.retain(|(_,shared)| Arc::strong_count(shared) > 1);
+    for (bbox,shared) in self.entries.iter() {
+      let mut shared = match shared.lock() {
+        Ok(shared) => shared,
+        Err(_) => continue
+      };
+      let len = shared.queue.len();
+      for change in changes.iter() {
+        let point = match change {
+          Change::Insert(p,_) => p,
+          Change::Delete(p,_,_) => p
+        };
+        if point.overlaps(bbox) {
+          shared.queue.push_back(change.clone());
+        }
+      }
+      if shared.queue.len() > len {
+        if let Some(waker) = shared.waker.take() { waker.wake() }
+      }
+    }
+  }
+  pub fn close (&mut self) {
+    for (_,shared) in self.entries.drain(..) {
+      if let Ok(mut shared) = shared.lock() {
+        shared.closed = true;
+        if let Some(waker) = shared.waker.take() { waker.wake() }
+      }
+    }
+  }
+}
+
+impl<P,V> Drop for Registry<P,V> where P: Point, V: Value {
+  fn drop (&mut self) {
+    self.close();
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Subscribe to the rows that later batches insert or delete within `bbox`.
+  ///
+  /// Once a `batch()` or `delete_query()` commits, its changes to rows that
+  /// intersect `bbox` are queued on the subscription in the order of the
+  /// batch: `Change::Insert` for new rows and `Change::Delete` with the old
+  /// point, value, and location for deleted ones. An update shows up as a
+  /// delete followed by an insert. Rows already in the database aren't
+  /// replayed, so query `bbox` first for those.
+  ///
+  /// Subscriptions live in memory on this handle, and only see batches
+  /// written through it.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Row,Change,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut db: DB<_,_,(f32,f32),u32> = DB::open(storage)?;
+  /// let viewport = ((-0.5,-0.8),(0.3,-0.5));
+  /// let mut sub = db.subscribe(&viewport)?;
+  /// db.batch(&[Row::Insert((0.1,-0.6), 7)])?;
+  /// while let Some(change) = sub.try_next() {
+  ///   match change {
+  ///     Change::Insert(point,value) => { /* add a marker */ },
+  ///     Change::Delete(point,value,location) => { /* remove it */ }
+  ///   }
+  /// }
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn subscribe (&mut self, bbox: &P::Bounds) -> Result<Subscription<P,V>,Error> {
+    self.check_open()?;
+    let shared = Arc::new(Mutex::new(Shared {
+      queue: VecDeque::new(),
+      waker: None,
+      closed: false
+    }));
+    self.subscriptions.entries.push((*bbox, Arc::clone(&shared)));
+    Ok(Subscription { shared })
+  }
+}
@@ -0,0 +1,271 @@
+use crate::{DB,Point,Value,DataStore};
+use crate::Error;
+use random_access_storage::RandomAccess;
+use desert::{ToBytes,FromBytes};
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::lock::Lock;
+
+// number of live rows, checksum, and encoded summary of each block, keyed by
+// block offset
+pub(crate) type SummaryEntries = HashMap<u64,(u64,u32,Vec<u8>)>;
+
+/// Custom aggregate over rows, such as a sum, a minimum and maximum, or a
+/// sketch, that is computed for each data block as the block is written.
+///
+/// Register a summary with `db.add_summary()` and query it with
+/// `db.summarize()`. Blocks that lie inside of the query's bounding box and
+/// whose stored summary is current contribute that summary, so their rows
+/// are never read. Summaries hold code, so they aren't persisted: register them
+/// each time the database is opened. Blocks written while a summary wasn't
+/// registered are summarized from their rows at query time.
+///
+/// ```rust,no_run
+/// # use eyros::{DB,Row,Summarize,Error};
+/// # use std::path::PathBuf;
+/// # use random_access_disk::RandomAccessDisk;
+/// type P = (f32,f32);
+/// type V = u32;
+///
+/// struct Total;
+/// impl Summarize<P,V> for Total {
+///   type Summary = u64;
+///   fn name (&self) -> &str { "total" }
+///   fn summarize (&self, rows: &[(P,V)]) -> u64 {
+///     rows.iter().map(|(_,v)| *v as u64).sum()
+///   }
+///   fn combine (&self, a: &u64, b: &u64) -> u64 { a + b }
+/// }
+///
+/// # fn main () -> Result<(),Error> {
+/// # let mut db: DB<_,_,P,V> = DB::open(storage)?;
+/// db.add_summary(Total)?;
+/// let total = db.summarize(&((-0.5,-0.8),(0.3,-0.5)), &Total)?;
+/// # Ok(()) }
+/// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+/// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+/// #   p.push(name);
+/// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+/// # }
+/// ```
+pub trait Summarize<P,V>: Send+Sync+'static where P: Point, V: Value {
+  type Summary: ToBytes+FromBytes+Clone;
+  /// Name of the summary. Summaries are stored in a store named
+  /// `summary_{name}`, so changing what a summary computes calls for a new
+  /// name.
+  fn name (&self) -> &str;
+  /// Summarize a non-empty list of rows.
+  fn summarize (&self, rows: &[(P,V)]) -> Self::Summary;
+  /// Combine the summaries of two sets of rows.
+  fn combine (&self, a: &Self::Summary, b: &Self::Summary) -> Self::Summary;
+}
+
+pub type SummaryFn<P,V> = Arc<dyn Fn(&[(P,V)]) -> Result<Vec<u8>,Error> + Send + Sync>;
+
+/// Encoded summaries of data blocks for one registered summary, as records of
+/// block offset, number of live rows summarized, checksum of the block's
+/// rows, and summary. A later record for a block replaces earlier ones.
+pub struct SummaryStore<S,P,V> where
+S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  pub name: String,
+  pub summarize: SummaryFn<P,V>,
+  store: S
+}
+
+impl<S,P,V> SummaryStore<S,P,V> where
+S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  pub fn open (name: &str, summarize: SummaryFn<P,V>, store: S) -> Self {
+    Self { name: name.to_string(), summarize, store }
+  }
+  /// Record the summary of `rows`, the live rows of the block at `block`
+  /// whose rows have the checksum `checksum`.
+  pub fn write (&mut self, block: u64, checksum: u32, rows: &[(P,V)])
+  -> Result<(),Error> {
+    let summary = (self.summarize)(rows)?;
+    let offset = self.store.len()?;
+    let record = (block,rows.len() as u64,checksum,summary);
+    Ok(self.store.write(offset, &record.to_bytes()?)?)
+  }
+  /// Read the latest number of live rows, checksum, and encoded summary of
+  /// each block, keyed by block offset.
+  pub fn entries (&mut self) -> Result<SummaryEntries,Error> {
+    let len = self.store.len()?;
+    let mut results = HashMap::new();
+    if len == 0 { return Ok(results) }
+    let buf = self.store.read(0, len)?;
+    let mut offset = 0usize;
+    while (offset as u64) < len {
+      let (size,(block,live,checksum,summary)) =
+        <(u64,u64,u32,Vec<u8>)>::from_bytes(&buf[offset..])?;
+      results.insert(block, (live,checksum,summary));
+      offset += size;
+    }
+    Ok(results)
+  }
+  /// Drop every record, for when block offsets change.
+  pub fn clear (&mut self) -> Result<(),Error> {
+    Ok(self.store.truncate(0)?)
+  }
+  pub fn commit (&mut self) -> Result<(),Error> {
+    Ok(self.store.sync_all()?)
+  }
+  pub fn len (&self) -> Result<u64,Error> {
+    Ok(self.store.len()?)
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Compute `summary` for every data block written from now on. See
+  /// `Summarize` for an example.
+  pub fn add_summary<M> (&mut self, summary: M) -> Result<(),Error>
+  where M: Summarize<P,V> {
+    self.check_open()?;
+    let name = summary.name().to_string();
+    if name.is_empty() || !name.chars().all(|c| {
+      c.is_ascii_alphanumeric() || c == '-' || c == '_'
+    }) {
+      invalid!["invalid summary name {:?}", name];
+    }
+    if self.data_store.read_lock()?.summaries.iter().any(|s| s.name == name) {
+      invalid!["summary {} already exists", name];
+    }
+    let summarize: SummaryFn<P,V> = Arc::new(move |rows| {
+      Ok(summary.summarize(rows).to_bytes()?)
+    });
+    self.open_summary(&name, summarize)
+  }
+
+  pub(crate) fn open_summary (&mut self, name: &str, summarize: SummaryFn<P,V>)
+  -> Result<(),Error> {
+    let store = (self.open_store)(&format!("summary_{}",name))?;
+    self.data_store.write_lock()?.summaries
+      .push(SummaryStore::open(name, summarize, store));
+    Ok(())
+  }
+
+  /// Unregister the summary named `name`. Its stored summaries are kept for
+  /// when it is registered again. Returns `false` if there is no such summary.
+  pub fn remove_summary (&mut self, name: &str) -> Result<bool,Error> {
+    let summaries = &mut self.data_store.write_lock()?.summaries;
+    match summaries.iter().position(|s| s.name == name) {
+      Some(i) => {
+        summaries.remove(i);
+        Ok(true)
+      },
+      None => Ok(false)
+    }
+  }
+
+  /// Combine `summary` over the rows that intersect `bbox`, or return `None`
+  /// if there are no such rows.
+  ///
+  /// Stored summaries are used for blocks that lie entirely inside of `bbox`
+  /// and whose summary is current. Deletes and in-place updates rewrite the
+  /// summaries of the blocks they touch, but rows deleted in the staging area
+  /// and blocks changed while the summary wasn't registered make a summary
+  /// out of date, and blocks written without checksums have no stored
+  /// summaries. The rows of the other blocks are read and summarized, which
+  /// also gives correct results for summaries that were never registered with
+  /// `add_summary()`.
+  pub fn summarize<M> (&mut self, bbox: &P::Bounds, summary: &M)
+  -> Result<Option<M::Summary>,Error> where M: Summarize<P,V> {
+    self.check_open()?;
+    let mut result: Option<M::Summary> = None;
+    let add = |result: &mut Option<M::Summary>, s: M::Summary| {
+      *result = Some(match result.take() {
+        Some(r) => summary.combine(&r, &s),
+        None => s
+      });
+    };
+    let deletes = self.staging.delete_set.read_lock()?.clone();
+    {
+      let inserts = self.staging.inserts.read_lock()?;
+      let rows: Vec<(P,V)> = inserts.iter().enumerate()
+        .filter(|(i,(p,_))| !deletes.contains(&(0,*i as u32)) && p.overlaps(bbox))
+        .map(|(_,row)| row.clone())
+        .collect();
+      if !rows.is_empty() { add(&mut result, summary.summarize(&rows)) }
+    }
+    let block_deletes = self.block_deletes()?;
+    let offsets = self.block_offsets(bbox)?;
+    let mut dstore = self.data_store.write_lock()?;
+    let entries = dstore.block_entries()?;
+    let stored = match dstore.summaries.iter_mut().find(|s| s.name == summary.name()) {
+      Some(s) => s.entries()?,
+      None => HashMap::new()
+    };
+    for offset in offsets {
+      if dstore.quarantine.contains(&offset) { continue }
+      let deleted = block_deletes.get(&offset);
+      if let (None, Some((range,rows)), Some((live,checksum,buf))) =
+      (deleted, entries.get(&offset), stored.get(&offset)) {
+        if P::range_within(range, bbox)
+        && is_current(&mut dstore, offset, *rows, *live, *checksum)? {
+          add(&mut result, M::Summary::from_bytes(buf)?.1);
+          continue;
+        }
+      }
+      let rows: Vec<(P,V)> = dstore.list_shared(offset)?.iter()
+        .filter(|(p,_,(_,i))| {
+          deleted.map(|d| !d.contains(i)).unwrap_or(true) && p.overlaps(bbox)
+        })
+        .map(|(p,v,_)| (*p,v.clone()))
+        .collect();
+      if !rows.is_empty() { add(&mut result, summary.summarize(&rows)) }
+    }
+    Ok(result)
+  }
+
+  /// Check the stored summaries of every data block referenced by a tree
+  /// against a summary computed from the block's live rows. Returns the
+  /// offsets of blocks whose stored summary claims to be current but doesn't
+  /// match, which should always be empty.
+  pub fn verify_summaries<M> (&mut self, summary: &M) -> Result<Vec<u64>,Error>
+  where M: Summarize<P,V>, M::Summary: PartialEq {
+    self.check_open()?;
+    let mut offsets = vec![];
+    for tree in self.trees.iter() {
+      let mut t = tree.write_lock()?;
+      if t.is_empty()? { continue }
+      offsets.extend(t.data_offsets()?);
+    }
+    offsets.sort_unstable();
+    offsets.dedup();
+    let mut dstore = self.data_store.write_lock()?;
+    let entries = dstore.block_entries()?;
+    let stored = match dstore.summaries.iter_mut().find(|s| s.name == summary.name()) {
+      Some(s) => s.entries()?,
+      None => HashMap::new()
+    };
+    let mut stale = vec![];
+    for offset in offsets {
+      if dstore.quarantine.contains(&offset) { continue }
+      let (rows,(live,checksum,buf)) = match (entries.get(&offset), stored.get(&offset)) {
+        (Some((_,rows)), Some(s)) => (rows,s),
+        _ => continue
+      };
+      if !is_current(&mut dstore, offset, *rows, *live, *checksum)? { continue }
+      let rows: Vec<(P,V)> = dstore.list_shared(offset)?.iter()
+        .map(|(p,v,_)| (*p,v.clone()))
+        .collect();
+      if M::Summary::from_bytes(buf)?.1 != summary.summarize(&rows) {
+        stale.push(offset);
+      }
+    }
+    Ok(stale)
+  }
+}
+
+// Whether a stored summary of `live` rows with the checksum `checksum` still
+// covers the live rows of the block at `offset` that was written with `rows`
+// rows. Deletes only clear bits and updates in place change the checksum, so
+// equal counts and checksums mean the same rows.
+pub(crate) fn is_current<S,P,V> (dstore: &mut DataStore<S,P,V>, offset: u64, rows: u64,
+live: u64, checksum: u32) -> Result<bool,Error>
+where S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  if live == 0 { return Ok(false) }
+  Ok(dstore.live_state(offset, rows)? == (live,Some(checksum)))
+}
@@ -0,0 +1,171 @@
+use crate::meta::Meta;
+use crate::Error;
+use random_access_storage::RandomAccess;
+
+// bytes copied at a time when a store is replaced
+const CHUNK: u64 = 1 << 20;
+
+/// Stores that replace the contents of other stores as one commit, along with
+/// the meta store that they are committed with.
+#[derive(Clone,Debug,PartialEq)]
+pub struct SwapRecord {
+  /// Name and length of each store to replace. The new contents of a store
+  /// are written to the store named by `next_name()` first.
+  pub stores: Vec<(String,u64)>,
+  /// Contents of the meta store after the swap, as written by
+  /// `Meta::to_bytes()`, or `None` to leave the meta store as it is.
+  pub meta: Option<Vec<u8>>
+}
+
+/// Log of the swap in progress, for rewrites such as compaction that replace
+/// whole stores. The new contents are written to the `.next` stores, and the
+/// swap is committed once its record is durably in the log. Copying each
+/// `.next` store over its store can be run again after a crash, so a
+/// committed swap is finished when the database is opened, while one that a
+/// crash interrupted before the record was written leaves every store as it
+/// was. The log is a single record `[length (u32)][record][crc32 (u32)]` like
+/// the write-ahead log.
+pub struct Swap<S> where S: RandomAccess<Error=failure::Error> {
+  store: S
+}
+
+impl<S> Swap<S> where S: RandomAccess<Error=failure::Error> {
+  pub fn open (store: S) -> Self {
+    Self { store }
+  }
+  /// Durably write `record`, which commits the swap.
+  pub fn begin (&mut self, record: &SwapRecord) -> Result<(),Error> {
+    let mut body = vec![];
+    body.extend(&(record.stores.len() as u32).to_be_bytes());
+    for (name,len) in record.stores.iter() {
+      body.extend(&(name.len() as u16).to_be_bytes());
+      body.extend(name.as_bytes());
+      body.extend(&len.to_be_bytes());
+    }
+    if let Some(meta) = &record.meta {
+      body.extend(meta);
+    }
+    let mut buf = Vec::with_capacity(body.len()+8);
+    buf.extend(&((body.len()+4) as u32).to_be_bytes());
+    buf.extend(&body);
+    buf.extend(&crc32fast::hash(&body).to_be_bytes());
+    self.store.truncate(0)?;
+    self.store.write(0, &buf)?;
+    self.store.sync_all()?;
+    Ok(())
+  }
+  /// Return the logged record, or `None` if there is no committed swap.
+  pub fn read (&mut self) -> Result<Option<SwapRecord>,Error> {
+    let len = self.store.len()?;
+    if len < 8 { return Ok(None) }
+    let buf = self.store.read(0, len)?;
+    let rlen = u32::from_be_bytes([buf[0],buf[1],buf[2],buf[3]]) as usize;
+    if rlen < 4 || 4+rlen > buf.len() { return Ok(None) }
+    let body = &buf[4..rlen];
+    let c = &buf[rlen..rlen+4];
+    if crc32fast::hash(body) != u32::from_be_bytes([c[0],c[1],c[2],c[3]]) {
+      return Ok(None);
+    }
+    if body.len() < 4 {
+      corrupt!["unexpected swap record length"];
+    }
+    let n = u32::from_be_bytes([body[0],body[1],body[2],body[3]]) as usize;
+    let mut offset = 4;
+    let mut stores = Vec::with_capacity(n);
+    for _ in 0..n {
+      if offset+2 > body.len() {
+        corrupt!["unexpected swap record length"];
+      }
+      let nlen = u16::from_be_bytes([body[offset],body[offset+1]]) as usize;
+      offset += 2;
+      if offset+nlen+8 > body.len() {
+        corrupt!["unexpected swap record length"];
+      }
+      let name = String::from_utf8(body[offset..offset+nlen].to_vec())
+        .map_err(|e| Error::Corrupt(format!["swap store name: {}", e]))?;
+      offset += nlen;
+      let mut b = [0u8;8];
+      b.copy_from_slice(&body[offset..offset+8]);
+      offset += 8;
+      stores.push((name,u64::from_be_bytes(b)));
+    }
+    let meta = if offset < body.len() { Some(body[offset..].to_vec()) } else { None };
+    Ok(Some(SwapRecord { stores, meta }))
+  }
+  /// Remove the record once every store is replaced.
+  pub fn clear (&mut self) -> Result<(),Error> {
+    self.store.truncate(0)?;
+    self.store.sync_all()?;
+    Ok(())
+  }
+}
+
+/// Name of the store that holds the new contents of `name` during a swap.
+pub fn next_name (name: &str) -> String {
+  format!["{}.next", name]
+}
+
+/// Open the store that holds the new contents of `name`, emptied of anything
+/// an interrupted swap left behind.
+pub fn open_next<S,U> (open_store: &U, name: &str) -> Result<S,Error> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>) {
+  let mut store = open_store(&next_name(name))?;
+  if !store.is_empty()? { store.truncate(0)? }
+  Ok(store)
+}
+
+/// Commit `record`, copy each `.next` store over its store and write the
+/// meta store, then drop the record and empty the `.next` stores.
+pub fn run<S,U> (open_store: &U, record: &SwapRecord) -> Result<(),Error> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>) {
+  let mut swap = Swap::open(open_store("swap")?);
+  swap.begin(record)?;
+  finish(open_store, &mut swap, record)
+}
+
+/// Finish a swap that was committed before a crash.
+pub fn recover<S,U> (open_store: &U) -> Result<(),Error> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>) {
+  let mut swap = Swap::open(open_store("swap")?);
+  match swap.read()? {
+    Some(record) => finish(open_store, &mut swap, &record),
+    None => Ok(())
+  }
+}
+
+fn finish<S,U> (open_store: &U, swap: &mut Swap<S>, record: &SwapRecord)
+-> Result<(),Error> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>) {
+  for (name,len) in record.stores.iter() {
+    let mut src = open_store(&next_name(name))?;
+    if src.len()? < *len {
+      corrupt!["swap store {} is shorter than {} bytes", next_name(name), len];
+    }
+    let mut dst = open_store(name)?;
+    let mut offset = 0;
+    while offset < *len {
+      let n = CHUNK.min(len - offset);
+      let buf = src.read(offset, n)?;
+      dst.write(offset, &buf)?;
+      offset += n;
+    }
+    if dst.len()? > *len { dst.truncate(*len)? }
+    dst.sync_all()?;
+  }
+  if let Some(meta) = &record.meta {
+    Meta::open(open_store("meta")?)?.restore(meta)?;
+  }
+  // the record goes first: replaying it from empty `.next` stores would
+  // empty the stores
+  swap.clear()?;
+  for (name,_) in record.stores.iter() {
+    let mut src = open_store(&next_name(name))?;
+    src.truncate(0)?;
+    src.sync_all()?;
+  }
+  Ok(())
+}
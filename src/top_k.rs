@@ -0,0 +1,141 @@
+use crate::{DB,Point,Value,Location,CacheMode,AuditOp,nearest::Nearer,point::Cursor};
+use crate::lock::Lock;
+use crate::Error;
+use random_access_storage::RandomAccess;
+use std::collections::{BinaryHeap,HashSet};
+
+// part of the index waiting to be read: a branch of the tree at an index with
+// a range that holds its rows, or a data block
+enum Node<R> {
+  Branch(usize,Cursor,R),
+  Block(u64)
+}
+
+// bounded heap that keeps the k highest scores, lowest on top
+struct TopK<P,V> {
+  k: usize,
+  seq: u64,
+  heap: BinaryHeap<Nearer<(P,V,Location)>>
+}
+
+impl<P,V> TopK<P,V> {
+  fn push (&mut self, score: f64, row: (P,V,Location)) {
+    if self.k == 0 { return }
+    if self.heap.len() == self.k {
+      match self.heap.peek() {
+        Some(low) if low.dist.total_cmp(&score).is_lt() => { self.heap.pop(); },
+        _ => return
+      }
+    }
+    self.seq += 1;
+    self.heap.push(Nearer { dist: score, seq: self.seq, item: row });
+  }
+  // lowest score that a row needs to make it into a full heap
+  fn threshold (&self) -> Option<f64> {
+    if self.heap.len() < self.k { return None }
+    self.heap.peek().map(|low| low.dist)
+  }
+  fn into_sorted (self) -> Vec<(P,V,Location)> {
+    // ascending heap order is highest score first
+    self.heap.into_sorted_vec().into_iter().map(|r| r.item).collect()
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Return the `k` rows in `bbox` with the highest `score`, highest first.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// // values are city populations
+  /// # let mut db: DB<_,_,(f32,f32),u64> = DB::open(storage)?;
+  /// let bbox = ((-0.5,-0.8),(0.3,-0.5));
+  /// let biggest = db.top_k(&bbox, 10, |_point,population| *population as f64)?;
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn top_k<F> (&mut self, bbox: &P::Bounds, k: usize, score: F)
+  -> Result<Vec<(P,V,Location)>,Error> where F: Fn(&P,&V) -> f64 {
+    self.top_k_bounded(bbox, k, score, |_| f64::INFINITY)
+  }
+
+  /// Like `top_k()`, with `bound` returning an upper bound of the score of
+  /// any row inside of a range.
+  ///
+  /// The trees are searched from their roots in order of decreasing bound,
+  /// where the range of a branch or data block is `bbox` narrowed by the
+  /// pivots of the branches above it, and reading stops as soon as nothing
+  /// left can beat the current top `k`. Use this when scores depend on
+  /// position, such as a weight that falls off with the distance from a
+  /// point of interest.
+  pub fn top_k_bounded<F,B> (&mut self, bbox: &P::Bounds, k: usize, score: F,
+  bound: B) -> Result<Vec<(P,V,Location)>,Error>
+  where F: Fn(&P,&V) -> f64, B: Fn(&P::Range) -> f64 {
+    self.check_open()?;
+    let _permit = self.admit()?;
+    let mut top = TopK { k, seq: 0, heap: BinaryHeap::with_capacity(k+1) };
+    let deletes = self.staging.delete_set.read_lock()?.clone();
+    for (i,(point,value)) in self.staging.inserts.read_lock()?.iter().enumerate() {
+      let location = (0,i as u32);
+      if deletes.contains(&location) || !point.overlaps(bbox)
+        || !self.is_visible(point,value) { continue }
+      top.push(score(point,value), (*point,value.clone(),location));
+    }
+    // bounds are negated so that the highest is popped first
+    let mut nodes = BinaryHeap::new();
+    let mut seq = 0;
+    let mut push = |nodes: &mut BinaryHeap<Nearer<Node<P::Range>>>, max: f64, node| {
+      seq += 1;
+      nodes.push(Nearer { dist: -max, seq, item: node });
+    };
+    let range = P::bounds_to_range(*bbox);
+    for (i,tree) in self.trees.iter().enumerate() {
+      let mut t = tree.write_lock()?;
+      if t.is_empty()? { continue }
+      let root = t.root()?;
+      if t.store.len()? <= root { continue }
+      push(&mut nodes, bound(&range), Node::Branch(i,(root,0),range));
+    }
+    let mut loaded = HashSet::new();
+    while let Some(node) = nodes.pop() {
+      if let Some(t) = top.threshold() {
+        if -node.dist <= t { break }
+      }
+      let offset = match node.item {
+        Node::Block(offset) => offset,
+        Node::Branch(i,cursor,range) => {
+          let (cursors,blocks) = self.trees[i].write_lock()?
+            .query_block_ranges(cursor, bbox, &range)?;
+          for (c,r) in cursors {
+            push(&mut nodes, bound(&r), Node::Branch(i,c,r));
+          }
+          for (b,r) in blocks {
+            push(&mut nodes, bound(&r), Node::Block(b));
+          }
+          continue;
+        }
+      };
+      // a block can be referenced from more than one tree while they merge
+      if !loaded.insert(offset) { continue }
+      let rows = self.data_store.write_lock()?
+        .list_or_quarantine(offset, CacheMode::Normal)?;
+      for row in rows.iter() {
+        if deletes.contains(&row.2) || !row.0.overlaps(bbox)
+          || !self.is_visible(&row.0,&row.1) { continue }
+        top.push(score(&row.0,&row.1), row.clone());
+      }
+    }
+    let rows = top.into_sorted();
+    self.audit_event(AuditOp::TopK, bbox, rows.len());
+    Ok(rows)
+  }
+}
@@ -0,0 +1,115 @@
+use crate::{DB,Point,Value,Row,Location};
+use crate::Error;
+use crate::error::Conflict;
+use random_access_storage::RandomAccess;
+
+/// Rows buffered across several calls and written to the database in one
+/// batch, from `db.transaction()`.
+///
+/// Nothing reaches storage until `commit()`, which writes every buffered row
+/// with a single `db.batch()`: one staging write, one tree merge if the
+/// staging area fills up, and one sync of the data store. `rollback()`, or
+/// dropping the transaction without committing, discards the rows, so a
+/// multi-step operation that fails halfway leaves the database as it was.
+///
+/// Queries don't see buffered rows. Turn on `Setup::wal()` for the commit to
+/// be atomic across crashes too.
+///
+/// Locations for deletes and updates are only valid until the next commit,
+/// so `commit()` fails with `Conflict` if anything else was committed to the
+/// database after the transaction started, such as a `batch()` through
+/// `tx.db()`.
+///
+/// ```rust,no_run
+/// use eyros::{DB,Row};
+/// # use failure::{Error,bail};
+/// # use std::path::PathBuf;
+/// # use random_access_disk::RandomAccessDisk;
+/// # fn main () -> Result<(),Error> {
+/// # let mut db: DB<_,_,(f32,f32),u32> = DB::open(storage)?;
+/// let mut tx = db.transaction();
+/// tx.insert((0.1,0.2), 7);
+/// tx.insert((0.3,0.4), 8);
+/// if tx.len() > 100 {
+///   tx.rollback();
+///   bail!["too many rows"];
+/// }
+/// tx.commit()?;
+/// # Ok(()) }
+/// # fn storage(name: &str) -> Result<RandomAccessDisk,Error> {
+/// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+/// #   p.push(name);
+/// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+/// # }
+/// ```
+pub struct Transaction<'a,S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  db: &'a mut DB<S,U,P,V>,
+  rows: Vec<Row<P,V>>,
+  // sequence number when the transaction started
+  sequence: u64
+}
+
+impl<'a,S,U,P,V> Transaction<'a,S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Buffer an insert of `value` at `point`.
+  pub fn insert (&mut self, point: P, value: V) {
+    self.rows.push(Row::Insert(point,value));
+  }
+  /// Buffer a delete of the row at `location`.
+  pub fn delete (&mut self, location: Location) {
+    self.rows.push(Row::Delete(location));
+  }
+  /// Buffer a replacement of the row at `location`, as in `Row::Update`.
+  pub fn update (&mut self, location: Location, point: P, value: V) {
+    self.rows.push(Row::Update(location,point,value));
+  }
+  /// Buffer every row of `rows`.
+  pub fn batch (&mut self, rows: &[Row<P,V>]) {
+    self.rows.extend_from_slice(rows);
+  }
+  /// Return the number of buffered rows.
+  pub fn len (&self) -> usize {
+    self.rows.len()
+  }
+  /// Return whether no rows are buffered.
+  pub fn is_empty (&self) -> bool {
+    self.rows.is_empty()
+  }
+  /// Borrow the database, for queries that inform the next rows. Queries
+  /// don't see the buffered rows. Writing through it fails the commit.
+  pub fn db (&mut self) -> &mut DB<S,U,P,V> {
+    self.db
+  }
+  /// Write the buffered rows with one `db.batch()`. An empty transaction
+  /// writes nothing. Fails with `Conflict`, writing nothing, if the database
+  /// committed anything since the transaction started.
+  pub fn commit (mut self) -> Result<(),Error> {
+    let rows = std::mem::take(&mut self.rows);
+    if rows.is_empty() { return Ok(()) }
+    let found = self.db.sequence();
+    if found != self.sequence {
+      return Err(Conflict { expected: self.sequence, found }.into());
+    }
+    self.db.batch(&rows)
+  }
+  /// Discard the buffered rows. Dropping the transaction does the same.
+  pub fn rollback (mut self) {
+    self.rows.clear();
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Start a `Transaction` that buffers rows until it is committed.
+  pub fn transaction (&mut self) -> Transaction<'_,S,U,P,V> {
+    let sequence = self.sequence();
+    Transaction { db: self, rows: vec![], sequence }
+  }
+}
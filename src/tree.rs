@@ -1,38 +1,177 @@
 use random_access_storage::RandomAccess;
-use failure::{Error,format_err,bail};
-use std::cell::RefCell;
-use std::rc::Rc;
+use crate::Error;
+use std::sync::{Arc,RwLock};
+use crate::lock::Lock;
 use std::mem::size_of;
+use std::ops::ControlFlow;
+use std::marker::PhantomData;
+use std::collections::HashMap;
+use desert::FromBytes;
 
-use crate::{Point,Value,Location};
+use crate::{Point,Value,Location,SharedRow,RetryPolicy,Clock};
+use crate::stats::Counted;
 use crate::branch::{Branch,Node};
-use crate::data::{DataStore,DataMerge,DataBatch};
+use crate::data::{DataStore,DataMerge,DataBatch,CacheMode};
+use crate::cache::Rows;
 use crate::read_block::read_block;
+use crate::frozen::FrozenTree;
+use crate::format::TreeFormat;
+use crate::prune::PruneState;
+use crate::selectivity::Selectivity;
+use crate::coalesce::Coalescer;
+use crate::point::{Cursor,Block};
+use crate::cursor::TreeCursor;
+
+/// Extent of the rows under a branch or data block along each axis, as
+/// `(min,max)` pairs.
+pub(crate) type Extent = Vec<(f64,f64)>;
+
+/// Branch cursors and data blocks that a branch references, each with a
+/// bound of its rows such as an `Extent`.
+pub(crate) type Children<B> = (Vec<(Cursor,B)>,Vec<(Block,B)>);
+
+/// Branch cursors and data blocks that a branch references.
+pub(crate) type Pointers = (Vec<Cursor>,Vec<Block>);
+
+/// Offset of each branch block with the offsets of the branch and data blocks
+/// that it references.
+pub(crate) type BranchWalk = Vec<(u64,Vec<u64>,Vec<Block>)>;
 
 pub struct TreeIterator<'b,S,P,V>
-where S: RandomAccess<Error=Error>, P: Point, V: Value {
-  tree: Rc<RefCell<Tree<S,P,V>>>,
-  bbox: &'b P::Bounds,
+where S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  tree: Arc<RwLock<Tree<S,P,V>>>,
+  bbox: P::Bounds,
+  _bbox: PhantomData<&'b P::Bounds>,
   cursors: Vec<(u64,usize)>,
+  started: bool,
   blocks: Vec<u64>,
-  queue: Vec<(P,V,Location)>,
-  tree_size: u64
+  block: Option<(Rows<P,V>,usize)>,
+  // offset and next row of a block released by `spill()`
+  spilled: Option<(u64,usize)>,
+  tree_size: u64,
+  cache_mode: CacheMode,
+  prune: Option<Arc<PruneState<P,V>>>,
+  order: Option<(Arc<Selectivity>,Extent)>,
+  coalescer: Coalescer,
+  slot: Option<usize>
 }
 
 impl<'b,S,P,V> TreeIterator<'b,S,P,V>
-where S: RandomAccess<Error=Error>, P: Point, V: Value {
-  pub fn new (tree: Rc<RefCell<Tree<S,P,V>>>, bbox: &'b P::Bounds)
+where S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  pub fn new (tree: Arc<RwLock<Tree<S,P,V>>>, bbox: &P::Bounds)
   -> Result<Self,Error> {
-    let tree_size = tree.try_borrow()?.store.len()? as u64;
+    let (tree_size,coalescer) = {
+      let t = tree.read_lock()?;
+      (t.store.len()?, t.coalescer())
+    };
     Ok(Self {
       tree,
       tree_size,
-      bbox,
-      cursors: vec![(0,0)],
+      bbox: *bbox,
+      _bbox: PhantomData,
+      cursors: vec![],
+      started: false,
       blocks: vec![],
-      queue: vec![]
+      block: None,
+      spilled: None,
+      cache_mode: CacheMode::Normal,
+      prune: None,
+      order: None,
+      coalescer,
+      slot: None
+    })
+  }
+  /// Set how data block reads for this iterator use the block cache.
+  pub fn cache_mode (mut self, mode: CacheMode) -> Self {
+    self.cache_mode = mode;
+    self
+  }
+  /// Drop the borrow of the query's bounding box, which is copied into the
+  /// iterator, so that the iterator can move to another thread.
+  pub(crate) fn detach (self) -> TreeIterator<'static,S,P,V> {
+    TreeIterator {
+      tree: self.tree,
+      bbox: self.bbox,
+      _bbox: PhantomData,
+      cursors: self.cursors,
+      started: self.started,
+      blocks: self.blocks,
+      block: self.block,
+      spilled: self.spilled,
+      tree_size: self.tree_size,
+      cache_mode: self.cache_mode,
+      prune: self.prune,
+      order: self.order,
+      coalescer: self.coalescer,
+      slot: self.slot
+    }
+  }
+  /// Skip and order data blocks with a query's pruner.
+  pub(crate) fn prune (mut self, prune: Option<Arc<PruneState<P,V>>>) -> Self {
+    self.prune = prune;
+    self
+  }
+  /// Read the branches and data blocks with the most expected results for
+  /// the query, whose extent along each axis is `bbox`, first.
+  pub(crate) fn order (mut self, selectivity: Option<Arc<Selectivity>>,
+  bbox: Extent) -> Self {
+    self.order = selectivity.map(|s| (s,bbox));
+    self
+  }
+  /// Record the slot of the tree in the database, for query cursors.
+  pub(crate) fn slot (mut self, slot: usize) -> Self {
+    self.slot = Some(slot);
+    self
+  }
+  /// Branches and data blocks left to read, or `None` if the slot of the
+  /// tree isn't known.
+  pub(crate) fn position (&self) -> Option<TreeCursor> {
+    let block = match &self.block {
+      // rows carry the block offset plus one in their locations
+      Some((rows,index)) if *index < rows.len() => {
+        Some(((rows[0].2).0 - 1, *index as u32))
+      },
+      _ => self.spilled.map(|(offset,index)| (offset, index as u32))
+    };
+    Some(TreeCursor {
+      slot: self.slot? as u32,
+      started: self.started,
+      cursors: self.cursors.iter().map(|(c,d)| (*c,*d as u32)).collect(),
+      blocks: self.blocks.clone(),
+      block
     })
   }
+  /// Continue from a position returned by `position()`.
+  pub(crate) fn resume (mut self, cursor: &TreeCursor) -> Result<Self,Error> {
+    self.slot = Some(cursor.slot as usize);
+    self.started = cursor.started;
+    self.cursors = cursor.cursors.iter().map(|(c,d)| (*c,*d as usize)).collect();
+    self.blocks = cursor.blocks.clone();
+    self.spilled = None;
+    if let Some((offset,index)) = cursor.block {
+      let rows = {
+        let tree = self.tree.read_lock()?;
+        let mut dstore = tree.data_store.write_lock()?;
+        dstore.list_or_quarantine(offset, self.cache_mode)?
+      };
+      self.block = Some((rows, index as usize));
+    }
+    Ok(self)
+  }
+  /// Whether the iterator holds the rows of a data block it is reading.
+  pub(crate) fn holds_block (&self) -> bool {
+    self.block.is_some()
+  }
+  /// Release the rows of the data block being read and keep only its offset
+  /// and the next row, to read the block again on the next call.
+  pub(crate) fn spill (&mut self) {
+    if let Some((rows,index)) = self.block.take() {
+      if index < rows.len() {
+        // rows carry the block offset plus one in their locations
+        self.spilled = Some(((rows[0].2).0 - 1, index));
+      }
+    }
+  }
 }
 
 #[doc(hidden)]
@@ -46,81 +185,276 @@ macro_rules! iwrap {
   };
 }
 
-impl<'b,S,P,V> Iterator for TreeIterator<'b,S,P,V>
-where S: RandomAccess<Error=Error>, P: Point, V: Value {
-  type Item = Result<(P,V,Location),Error>;
-  fn next (&mut self) -> Option<Self::Item> {
-    let bf = iwrap![self.tree.try_borrow()].branch_factor;
-
+impl<'b,S,P,V> TreeIterator<'b,S,P,V>
+where S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  /// Return the next result as a row that shares storage with the cached data
+  /// block it was read from.
+  pub fn next_shared (&mut self) -> Option<Result<SharedRow<P,V>,Error>> {
+    // a failed read leaves the position as it was, so that calling this
+    // again after a transient error reads the same block again
+    if !self.started { // the root offset depends on the tree header
+      let root = iwrap![iwrap![self.tree.write_lock()].root()];
+      self.started = true;
+      self.cursors.push((root,0));
+    }
     // todo: used cached size or rolling max to implicitly read an appropriate
     // amount of data
-    while !self.cursors.is_empty() || !self.blocks.is_empty()
-    || !self.queue.is_empty() {
-      if !self.queue.is_empty() {
-        return Some(Ok(self.queue.pop().unwrap()));
+    if let Some((offset,index)) = self.spilled {
+      let tree = iwrap![self.tree.read_lock()];
+      let mut dstore = iwrap![tree.data_store.write_lock()];
+      self.block = Some((
+        iwrap![dstore.list_or_quarantine(offset, self.cache_mode)],
+        index
+      ));
+      self.spilled = None;
+    }
+    loop {
+      if let Some((rows,index)) = &mut self.block {
+        while *index < rows.len() {
+          let i = *index;
+          *index += 1;
+          if rows[i].0.overlaps(&self.bbox) {
+            return Some(Ok(SharedRow::Block(Arc::clone(rows), i)));
+          }
+        }
+        self.block = None;
       }
-      if !self.blocks.is_empty() { // data block:
-        let offset = self.blocks.pop().unwrap();
-        let tree = iwrap![self.tree.try_borrow()];
-        let mut dstore = iwrap![tree.data_store.try_borrow_mut()];
-        self.queue.extend(iwrap![dstore.query(offset, self.bbox)]);
+      if let Some(&offset) = self.blocks.last() { // data block:
+        let tree = iwrap![self.tree.read_lock()];
+        let mut dstore = iwrap![tree.data_store.write_lock()];
+        self.block = Some((
+          iwrap![dstore.list_or_quarantine(offset, self.cache_mode)],
+          0
+        ));
+        self.blocks.pop();
         continue
       }
       // branch block:
-      let (cursor,depth) = self.cursors.pop().unwrap();
-      if cursor >= self.tree_size { continue }
+      let (cursor,depth) = *self.cursors.last()?;
+      if cursor >= self.tree_size {
+        self.cursors.pop();
+        continue
+      }
 
-      let buf = {
-        let mut tree = iwrap![self.tree.try_borrow_mut()];
-        iwrap![read_block(&mut tree.store, cursor, self.tree_size, 1024)]
+      let (mut cursors,mut blocks) = {
+        let mut tree = iwrap![self.tree.write_lock()];
+        iwrap![tree.query_block_with(&mut self.coalescer, cursor, self.tree_size,
+          &self.bbox, depth)]
+      };
+      if let Some((selectivity,bbox)) = &self.order {
+        selectivity.order_blocks(bbox, &mut blocks);
+        selectivity.order_cursors(bbox, &mut cursors);
+      }
+      // branches are only read once every block found so far has been read,
+      // so ordering the new blocks orders the whole stack
+      let blocks = match &self.prune {
+        Some(prune) => {
+          let tree = iwrap![self.tree.read_lock()];
+          let mut dstore = iwrap![tree.data_store.write_lock()];
+          iwrap![prune.order(&mut dstore, &self.bbox, blocks)]
+        },
+        None => blocks
       };
-      let (cursors,blocks) = iwrap![
-        P::query_branch(&buf, &self.bbox, bf, depth)
-      ];
+      self.cursors.pop();
       self.blocks.extend(blocks);
       self.cursors.extend(cursors);
     }
-    None
+  }
+}
+
+impl<'b,S,P,V> Iterator for TreeIterator<'b,S,P,V>
+where S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  type Item = Result<(P,V,Location),Error>;
+  fn next (&mut self) -> Option<Self::Item> {
+    self.next_shared().map(|r| r.map(|row| row.into_owned()))
   }
 }
 
 pub struct TreeOpts<S,P,V>
-where S: RandomAccess<Error=Error>, P: Point, V: Value {
+where S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
   pub store: S,
-  pub data_store: Rc<RefCell<DataStore<S,P,V>>>,
+  pub data_store: Arc<RwLock<DataStore<S,P,V>>>,
   pub branch_factor: usize,
   pub max_data_size: usize,
   pub index: usize,
+  pub retry: RetryPolicy,
+  pub clock: Arc<dyn Clock>,
+  pub format: TreeFormat,
+  pub coalesce_span: u64,
 }
 
 pub struct Tree<S,P,V>
-where S: RandomAccess<Error=Error>, P: Point, V: Value {
-  pub store: S,
-  data_store: Rc<RefCell<DataStore<S,P,V>>>,
-  data_merge: Rc<RefCell<DataMerge<S,P,V>>>,
+where S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  pub store: Counted<S>,
+  data_store: Arc<RwLock<DataStore<S,P,V>>>,
+  data_merge: Arc<RwLock<DataMerge<S,P,V>>>,
   branch_factor: usize,
   pub bytes: u64,
   pub index: usize,
-  max_data_size: usize,
+  pub(crate) max_data_size: usize,
+  retry: RetryPolicy,
+  clock: Arc<dyn Clock>,
+  frozen: Option<FrozenTree>,
+  header: Option<(TreeFormat,u64)>,
+  write_format: TreeFormat,
+  coalesce_span: u64,
+  pub(crate) selectivity: Option<Arc<Selectivity>>,
 }
 
 impl<S,P,V> Tree<S,P,V>
-where S: RandomAccess<Error=Error>, P: Point, V: Value {
+where S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
   pub fn open (opts: TreeOpts<S,P,V>) -> Result<Self,Error> {
     let bytes = opts.store.len()? as u64;
-    let data_merge = Rc::new(RefCell::new(
-      DataMerge::new(Rc::clone(&opts.data_store))));
+    let data_merge = Arc::new(RwLock::new(
+      DataMerge::new(Arc::clone(&opts.data_store))));
+    let counters = Arc::clone(&opts.data_store.read_lock()?.counters);
     Ok(Self {
-      store: opts.store,
+      store: Counted::new(opts.store, counters),
       data_store: opts.data_store,
       data_merge,
       index: opts.index,
       bytes,
       branch_factor: opts.branch_factor,
       max_data_size: opts.max_data_size,
+      retry: opts.retry,
+      clock: opts.clock,
+      frozen: None,
+      header: None,
+      write_format: opts.format,
+      coalesce_span: opts.coalesce_span,
+      selectivity: None,
     })
   }
+  /// Read the branch block at `offset`, retrying according to the tree's
+  /// retry policy.
+  pub fn read_block (&mut self, offset: u64, tree_size: u64)
+  -> Result<Vec<u8>,Error> {
+    let store = &mut self.store;
+    self.retry.run(&*self.clock, || read_block(store, offset, tree_size, 1024))
+  }
+  /// Query the branch block at `offset`, reading it from the frozen image if
+  /// there is one.
+  pub fn query_block (&mut self, offset: u64, tree_size: u64,
+  bbox: &P::Bounds, depth: usize) -> Result<(Vec<Cursor>,Vec<Block>),Error> {
+    if let Some(branch) = self.frozen.as_ref().and_then(|f| f.branch(offset)) {
+      return P::query_branch(branch, bbox, self.branch_factor, depth);
+    }
+    let buf = self.read_block(offset, tree_size)?;
+    P::query_branch(&buf, bbox, self.branch_factor, depth)
+  }
+  /// Return the branch cursors and data block offsets that the branch block
+  /// at `cursor` references, each with the extent that its rows lie within,
+  /// given `extent` for the branch itself.
+  pub(crate) fn expand_block (&mut self, cursor: Cursor, extent: &Extent)
+  -> Result<Children<Extent>,Error> {
+    let (offset,depth) = cursor;
+    if let Some(branch) = self.frozen.as_ref().and_then(|f| f.branch(offset)) {
+      return Self::extents(branch, self.branch_factor, depth, extent);
+    }
+    let tree_size = self.store.len()?;
+    let buf = self.read_block(offset, tree_size)?;
+    Self::extents(&buf, self.branch_factor, depth, extent)
+  }
+  /// Like `query_block()`, with a range for each child that holds its rows
+  /// inside of `range`, a range for the branch itself.
+  pub(crate) fn query_block_ranges (&mut self, cursor: Cursor, bbox: &P::Bounds,
+  range: &P::Range) -> Result<Children<P::Range>,Error> {
+    let (offset,depth) = cursor;
+    if let Some(branch) = self.frozen.as_ref().and_then(|f| f.branch(offset)) {
+      return Self::ranges(branch, self.branch_factor, depth, bbox, range);
+    }
+    let tree_size = self.store.len()?;
+    let buf = self.read_block(offset, tree_size)?;
+    Self::ranges(&buf, self.branch_factor, depth, bbox, range)
+  }
+  /// Like `query_block()`, but take the branch from `coalescer` if it was
+  /// read ahead, and read ahead the child branches that it points to.
+  pub(crate) fn query_block_with (&mut self, coalescer: &mut Coalescer,
+  offset: u64, tree_size: u64, bbox: &P::Bounds, depth: usize)
+  -> Result<(Vec<Cursor>,Vec<Block>),Error> {
+    if self.frozen.is_some() || !coalescer.enabled() {
+      return self.query_block(offset, tree_size, bbox, depth);
+    }
+    let buf = match coalescer.take(offset) {
+      Some(buf) => buf,
+      None => self.read_block(offset, tree_size)?
+    };
+    let (cursors,blocks) = P::query_branch(&buf, bbox, self.branch_factor, depth)?;
+    if cursors.len() > 1 {
+      let offsets: Vec<u64> = cursors.iter().map(|c| c.0).collect();
+      let store = &mut self.store;
+      self.retry.run(&*self.clock, || coalescer.fetch(store, &offsets, tree_size))?;
+    }
+    Ok((cursors,blocks))
+  }
+  /// Read-ahead buffer for one traversal of this tree, set up with the span
+  /// from `Setup::coalesce_branches()`.
+  pub(crate) fn coalescer (&self) -> Coalescer {
+    Coalescer::new(self.coalesce_span)
+  }
+  /// Load the tree file into an in-memory image that queries use instead of
+  /// reading branches from the store. The image isn't written anywhere, and
+  /// is dropped when the tree is rebuilt.
+  pub fn freeze (&mut self) -> Result<(),Error> {
+    let len = self.store.len()?;
+    let root = self.root()?;
+    if len <= root {
+      self.frozen = None;
+      return Ok(());
+    }
+    let store = &mut self.store;
+    let buf = self.retry.run(&*self.clock, || store.read(0, len))?;
+    let bf = self.branch_factor;
+    self.frozen = Some(FrozenTree::new(buf, root, |branch,depth| {
+      Self::children(branch, bf, depth)
+    })?);
+    Ok(())
+  }
+  /// Read the format and root offset from the tree header on first use.
+  fn header (&mut self) -> Result<(TreeFormat,u64),Error> {
+    if let Some(header) = self.header {
+      return Ok(header);
+    }
+    let bytes = self.store.len()? as u64;
+    let found = if bytes >= 4 {
+      let store = &mut self.store;
+      let len = bytes.min(TreeFormat::HEADER_LEN);
+      let buf = self.retry.run(&*self.clock, || store.read(0, len))?;
+      TreeFormat::from_header(&buf)?
+    } else {
+      None
+    };
+    let header = match found {
+      Some(format) => {
+        format.check()?;
+        (format, TreeFormat::HEADER_LEN)
+      },
+      None if bytes == 0 => (self.write_format, TreeFormat::HEADER_LEN),
+      None => (TreeFormat::legacy(), 0)
+    };
+    self.header = Some(header);
+    Ok(header)
+  }
+  /// Format that this tree was written with.
+  pub fn format (&mut self) -> Result<TreeFormat,Error> {
+    Ok(self.header()?.0)
+  }
+  /// Offset of the root branch block.
+  pub fn root (&mut self) -> Result<u64,Error> {
+    Ok(self.header()?.1)
+  }
+  /// Drop the frozen image so queries read branches from the store again.
+  pub fn thaw (&mut self) {
+    self.frozen = None;
+  }
+  /// Return the frozen image, if this tree has one.
+  pub fn frozen (&self) -> Option<&FrozenTree> {
+    self.frozen.as_ref()
+  }
   pub fn clear (&mut self) -> Result<(),Error> {
+    self.frozen = None;
+    self.header = None;
+    self.selectivity = None;
     if self.bytes > 0 {
       self.bytes = 0;
       self.store.truncate(0)?;
@@ -128,14 +462,18 @@ where S: RandomAccess<Error=Error>, P: Point, V: Value {
     self.store.sync_all()?;
     Ok(())
   }
+  pub fn commit (&mut self) -> Result<(),Error> {
+    self.store.sync_all()?;
+    Ok(())
+  }
   pub fn is_empty (&mut self) -> Result<bool,Error> {
     let r = self.store.is_empty()?;
     Ok(r)
   }
   pub fn build (&mut self, rows: &Vec<(P,V)>) -> Result<(),Error> {
-    let dstore = Rc::clone(&self.data_store);
+    let dstore = Arc::clone(&self.data_store);
     self.builder(
-      Rc::new(rows.iter().map(|row| { (row.clone(),1u64) }).collect()),
+      Arc::new(rows.iter().map(|row| { (row.clone(),1u64) }).collect()),
       dstore
     )
   }
@@ -147,20 +485,23 @@ where S: RandomAccess<Error=Error>, P: Point, V: Value {
     let rows = blocks.iter().enumerate().map(|(i,(_,_,len))| {
       (inserts[i],*len)
     }).collect();
-    let dmerge = Rc::clone(&self.data_merge);
-    self.builder(Rc::new(rows), dmerge)
+    let dmerge = Arc::clone(&self.data_merge);
+    self.builder(Arc::new(rows), dmerge)
   }
-  pub fn builder<D,T,U> (&mut self, rows: Rc<Vec<((T,U),u64)>>,
-  data_store: Rc<RefCell<D>>) -> Result<(),Error>
+  pub fn builder<D,T,U> (&mut self, rows: Arc<Vec<((T,U),u64)>>,
+  data_store: Arc<RwLock<D>>) -> Result<(),Error>
   where D: DataBatch<T,U>, T: Point, U: Value {
     self.clear()?;
+    self.store.write(0, &self.write_format.to_header())?;
+    self.header = Some((self.write_format, TreeFormat::HEADER_LEN));
+    self.bytes = TreeFormat::HEADER_LEN;
     let bucket = (0..rows.len()).collect();
     let b = Branch::<D,T,U>::new(
       0,
       self.index,
       self.max_data_size,
       self.branch_factor,
-      Rc::clone(&data_store),
+      Arc::clone(&data_store),
       bucket, rows
     )?;
     let mut branches = vec![Node::Branch(b)];
@@ -193,24 +534,63 @@ where S: RandomAccess<Error=Error>, P: Point, V: Value {
     self.store.sync_all()?;
     Ok(())
   }
-  pub fn query<'a,'b> (tree: Rc<RefCell<Self>>, bbox: &'b P::Bounds)
+  pub fn query<'b> (tree: Arc<RwLock<Self>>, bbox: &P::Bounds)
   -> Result<TreeIterator<'b,S,P,V>,Error> {
     TreeIterator::new(tree, bbox)
   }
+  /// Walk the branches that intersect `bbox`, calling `f` for each matching
+  /// row until it returns `ControlFlow::Break`.
+  pub fn for_each (&mut self, bbox: &P::Bounds,
+  f: &mut dyn FnMut (&P,&V,&Location) -> ControlFlow<()>)
+  -> Result<ControlFlow<()>,Error> {
+    let tree_size = self.store.len()?;
+    let mut cursors: Vec<(u64,usize)> = vec![(self.root()?,0)];
+    let mut coalescer = self.coalescer();
+    while let Some((cursor,depth)) = cursors.pop() {
+      if cursor >= tree_size { continue }
+      let (next,blocks) = self.query_block_with(&mut coalescer, cursor, tree_size,
+        bbox, depth)?;
+      let mut dstore = self.data_store.write_lock()?;
+      for offset in blocks {
+        if let ControlFlow::Break(()) = dstore.for_each(offset, bbox, f)? {
+          return Ok(ControlFlow::Break(()));
+        }
+      }
+      cursors.extend(next);
+    }
+    Ok(ControlFlow::Continue(()))
+  }
+  /// Return the offsets of the data blocks whose branches overlap `bbox`.
+  pub fn query_offsets (&mut self, bbox: &P::Bounds) -> Result<Vec<u64>,Error> {
+    let tree_size = self.store.len()?;
+    let mut offsets = vec![];
+    let mut cursors: Vec<(u64,usize)> = vec![(self.root()?,0)];
+    let mut coalescer = self.coalescer();
+    while let Some((cursor,depth)) = cursors.pop() {
+      if cursor >= tree_size { continue }
+      let (next,blocks) = self.query_block_with(&mut coalescer, cursor, tree_size,
+        bbox, depth)?;
+      offsets.extend(blocks);
+      cursors.extend(next);
+    }
+    Ok(offsets)
+  }
   fn alloc (&mut self, bytes: usize) -> u64 {
     let addr = self.bytes;
     self.bytes += bytes as u64;
     addr
   }
-  pub fn merge (trees: &mut Vec<Rc<RefCell<Self>>>, dst: usize, src: Vec<usize>,
+  /// Build the tree at `dst` from `rows` and the data blocks of the `src`
+  /// trees. The `src` trees are left as they are.
+  pub fn merge (trees: &mut [Arc<RwLock<Self>>], dst: usize, src: Vec<usize>,
   rows: &Vec<(P,V)>) -> Result<(),Error> {
     let mut blocks = vec![];
     for i in src.iter() {
-      blocks.extend(trees[*i].try_borrow_mut()?.unbuild()?);
+      blocks.extend(trees[*i].write_lock()?.unbuild()?);
     }
     {
-      let tree = trees[dst].try_borrow()?;
-      let mut dstore = tree.data_store.try_borrow_mut()?;
+      let tree = trees[dst].read_lock()?;
+      let mut dstore = tree.data_store.write_lock()?;
       let m = tree.max_data_size;
       let mut srow_len = 0;
       for i in 0..(rows.len()+m-1)/m {
@@ -220,72 +600,203 @@ where S: RandomAccess<Error=Error>, P: Point, V: Value {
           .map(|(p,v)| (*p,v.clone())).collect();
         let offset = dstore.batch(&inserts.iter().map(|pv| pv).collect())?;
         match P::bounds(&inserts.iter().map(|(p,_)| *p).collect()) {
-          None => bail!["invalid data at offset {}", offset],
+          None => corrupt!["invalid data at offset {}", offset],
           Some(bbox) => blocks.push((bbox,offset,inserts.len() as u64))
         }
       }
       ensure_eq!(srow_len, rows.len(), "divided rows incorrectly");
     }
-    trees[dst].try_borrow_mut()?.build_from_blocks(blocks)?;
-    for i in src.iter() {
-      trees[*i].try_borrow_mut()?.clear()?
+    // the caller clears the src trees once the new tree is committed
+    trees[dst].write_lock()?.build_from_blocks(blocks)
+  }
+  /// Count the rows in the data blocks that this tree references.
+  pub fn count_rows (&mut self) -> Result<u64,Error> {
+    Ok(self.unbuild()?.iter().map(|(_,_,len)| len).sum())
+  }
+  /// Return the offsets of the data blocks that this tree references.
+  pub fn data_offsets (&mut self) -> Result<Vec<u64>,Error> {
+    if let Some(frozen) = &self.frozen {
+      return Ok(frozen.data_offsets().to_vec());
     }
-    Ok(())
+    let mut offsets: Vec<u64> = vec![];
+    let root = self.root()?;
+    let mut cursors: Vec<(u64,usize)> = vec![(root,0)];
+    let tree_size = self.store.len()? as u64;
+    if tree_size <= root { return Ok(offsets) }
+    while let Some((c,depth)) = cursors.pop() {
+      let buf = self.read_block(c, tree_size)?;
+      let (next,blocks) = Self::children(&buf, self.branch_factor, depth)?;
+      offsets.extend(blocks);
+      cursors.extend(next);
+    }
+    Ok(offsets)
   }
-  fn unbuild (&mut self) -> Result<Vec<(P::Bounds,u64,u64)>,Error> {
+  /// Walk every branch block and return the depth of the tree, the number of
+  /// branch blocks, and the offsets of the data blocks that it references.
+  pub fn shape (&mut self) -> Result<(usize,u64,Vec<u64>),Error> {
     let mut offsets: Vec<u64> = vec![];
-    let mut cursors: Vec<(u64,usize)> = vec![(0,0)];
-    let bf = self.branch_factor;
+    let root = self.root()?;
+    let tree_size = self.store.len()?;
+    if tree_size <= root { return Ok((0,0,offsets)) }
+    let (mut depth, mut branches) = (0,0);
+    let mut cursors: Vec<(u64,usize)> = vec![(root,0)];
+    while let Some((c,d)) = cursors.pop() {
+      let buf = self.read_block(c, tree_size)?;
+      let (next,blocks) = Self::children(&buf, self.branch_factor, d)?;
+      depth = depth.max(d+1);
+      branches += 1;
+      offsets.extend(blocks);
+      cursors.extend(next);
+    }
+    Ok((depth,branches,offsets))
+  }
+  /// Walk every branch block and return its offset with the branch cursors
+  /// and data block offsets that it references, parents before children.
+  pub(crate) fn branches (&mut self) -> Result<BranchWalk,Error> {
+    let mut walk = vec![];
+    let root = self.root()?;
+    let tree_size = self.store.len()?;
+    if tree_size <= root { return Ok(walk) }
+    let mut cursors: Vec<(u64,usize)> = vec![(root,0)];
+    while let Some((c,depth)) = cursors.pop() {
+      let buf = self.read_block(c, tree_size)?;
+      let (next,blocks) = Self::children(&buf, self.branch_factor, depth)?;
+      walk.push((c, next.iter().map(|(c,_)| *c).collect(), blocks));
+      cursors.extend(next);
+    }
+    Ok(walk)
+  }
+  /// Write a copy of this tree to `out`, an empty store, with the data
+  /// entries of every branch pointing at the new block offsets in `moves` and
+  /// the entries of blocks that were removed cleared. Returns the length of
+  /// the copy.
+  pub fn remap_data<T> (&mut self, moves: &HashMap<u64,Option<u64>>, out: &mut T)
+  -> Result<u64,Error> where T: RandomAccess<Error=failure::Error> {
+    let tree_size = self.store.len()?;
+    let mut offset = 0;
+    while offset < tree_size {
+      let n = (1u64 << 20).min(tree_size - offset);
+      let buf = self.store.read(offset, n)?;
+      out.write(offset, &buf)?;
+      offset += n;
+    }
+    let root = self.root()?;
+    if tree_size > root {
+      let mut cursors: Vec<(u64,usize)> = vec![(root,0)];
+      while let Some((c,depth)) = cursors.pop() {
+        let mut buf = self.read_block(c, tree_size)?;
+        let mut changed = false;
+        for (pos,is_data) in Self::pointers(&buf, self.branch_factor, depth)? {
+          let offset = u64::from_bytes(&buf[pos..])?.1;
+          if offset == 0 { continue }
+          if !is_data {
+            cursors.push((offset-1,depth+1));
+            continue;
+          }
+          if let Some(next) = moves.get(&(offset-1)) {
+            let next = next.map(|o| o+1).unwrap_or(0);
+            buf[pos..pos+8].copy_from_slice(&next.to_be_bytes());
+            changed = true;
+          }
+        }
+        if changed { // skip the length field that read_block() strips
+          out.write(c+4, &buf)?;
+        }
+      }
+    }
+    out.sync_all()?;
+    Ok(tree_size)
+  }
+  /// Return the branch cursors and data block offsets that the branch block
+  /// in `buf` references.
+  fn children (buf: &[u8], bf: usize, depth: usize) -> Result<Pointers,Error> {
+    let mut cursors = vec![];
+    let mut offsets = vec![];
+    for (pos,is_data) in Self::pointers(buf, bf, depth)? {
+      let offset = u64::from_bytes(&buf[pos..])?.1;
+      if offset > 0 && is_data {
+        offsets.push(offset-1);
+      } else if offset > 0 {
+        cursors.push((offset-1,depth+1));
+      }
+    }
+    Ok((cursors,offsets))
+  }
+  /// Like `children()`, with the extent of each child. Rows in a bucket lie
+  /// strictly between the pivots on either side of it, so buckets narrow
+  /// `extent` along the axis of the branch, while intersections keep it.
+  fn extents (buf: &[u8], bf: usize, depth: usize, extent: &Extent)
+  -> Result<Children<Extent>,Error> {
     let n = bf*2-3;
-    let tree_size = self.store.len()? as u64;
-    while !cursors.is_empty() {
-      let (c,depth) = cursors.pop().unwrap();
-      let buf = read_block(&mut self.store, c, tree_size, 1024)?;
-      let mut offset = 0;
-      for _i in 0..n {
-        offset += P::count_bytes_at(&buf[offset..], depth)?;
+    let pivots = P::pivots_at(buf, bf, depth)?;
+    let axis = depth % P::dim();
+    let mut cursors = vec![];
+    let mut blocks = vec![];
+    for (j,(pos,is_data)) in Self::pointers(buf, bf, depth)?.into_iter().enumerate() {
+      let offset = u64::from_bytes(&buf[pos..])?.1;
+      if offset == 0 { continue }
+      let mut e = extent.clone();
+      if let (Some(p),true) = (&pivots, j >= n) {
+        let k = j-n;
+        if k > 0 { e[axis].0 = e[axis].0.max(p[2*(k-1)]) }
+        if k < bf-1 { e[axis].1 = e[axis].1.min(p[2*k]) }
       }
-      let d_start = offset;
-      let i_start = d_start + (n+bf+7)/8;
-      let b_start = i_start + n*size_of::<u64>();
-      let b_end = b_start+bf*size_of::<u64>();
-      ensure_eq!(b_end, buf.len(), "unexpected block length");
-      for i in 0..n {
-        let offset = u64::from_be_bytes([
-          buf[i_start+i*8+0], buf[i_start+i*8+1],
-          buf[i_start+i*8+2], buf[i_start+i*8+3],
-          buf[i_start+i*8+4], buf[i_start+i*8+5],
-          buf[i_start+i*8+6], buf[i_start+i*8+7]
-        ]);
-        let is_data = ((buf[d_start+i/8]>>(i%8))&1) == 1;
-        if offset > 0 && is_data {
-          offsets.push(offset-1);
-        } else if offset > 0 {
-          cursors.push((offset-1,depth+1));
-        }
+      if is_data {
+        blocks.push((offset-1,e));
+      } else {
+        cursors.push(((offset-1,depth+1),e));
       }
-      for i in 0..bf {
-        let offset = u64::from_be_bytes([
-          buf[b_start+i*8+0], buf[b_start+i*8+1],
-          buf[b_start+i*8+2], buf[b_start+i*8+3],
-          buf[b_start+i*8+4], buf[b_start+i*8+5],
-          buf[b_start+i*8+6], buf[b_start+i*8+7]
-        ]);
-        let j = i + n;
-        let is_data = ((buf[d_start+(j/8)]>>(j%8))&1) == 1;
-        if offset > 0 && is_data {
-          offsets.push(offset-1);
-        } else if offset > 0 {
-          cursors.push((offset-1,depth+1));
-        }
+    }
+    Ok((cursors,blocks))
+  }
+  /// Like `extents()` for the children that overlap `bbox`, with ranges
+  /// narrowed by `P::bucket_ranges()`.
+  fn ranges (buf: &[u8], bf: usize, depth: usize, bbox: &P::Bounds,
+  range: &P::Range) -> Result<Children<P::Range>,Error> {
+    let n = bf*2-3;
+    let (cursors,blocks) = P::query_branch(buf, bbox, bf, depth)?;
+    let buckets = P::bucket_ranges(buf, range, bf, depth)?;
+    let mut ranges = HashMap::new();
+    if let Some(buckets) = buckets {
+      for (j,(pos,_)) in Self::pointers(buf, bf, depth)?.into_iter().enumerate() {
+        let offset = u64::from_bytes(&buf[pos..])?.1;
+        if j >= n && offset > 0 { ranges.insert(offset-1, buckets[j-n]); }
       }
     }
+    let child = |offset: u64| *ranges.get(&offset).unwrap_or(range);
+    Ok((
+      cursors.into_iter().map(|c| (c,child(c.0))).collect(),
+      blocks.into_iter().map(|b| (b,child(b))).collect()
+    ))
+  }
+  /// Return the position of each child pointer in the branch block in `buf`
+  /// and whether it points at a data block. Pointers hold offsets plus one,
+  /// or zero for no child.
+  fn pointers (buf: &[u8], bf: usize, depth: usize)
+  -> Result<Vec<(usize,bool)>,Error> {
+    let n = bf*2-3;
+    let mut offset = 0;
+    for _i in 0..n {
+      offset += P::count_bytes_at(&buf[offset..], depth)?;
+    }
+    let d_start = offset;
+    let i_start = d_start + (n+bf).div_ceil(8);
+    let b_start = i_start + n*size_of::<u64>();
+    let b_end = b_start+bf*size_of::<u64>();
+    ensure_eq!(b_end, buf.len(), "unexpected block length");
+    Ok((0..n+bf).map(|j| {
+      let pos = if j < n { i_start+j*8 } else { b_start+(j-n)*8 };
+      (pos, ((buf[d_start+j/8]>>(j%8))&1) == 1)
+    }).collect())
+  }
+  fn unbuild (&mut self) -> Result<Vec<(P::Bounds,u64,u64)>,Error> {
+    let offsets = self.data_offsets()?;
     let mut blocks = Vec::with_capacity(offsets.len());
-    let mut dstore = self.data_store.try_borrow_mut()?;
+    let mut dstore = self.data_store.write_lock()?;
+    let mode = dstore.maintenance_cache;
     for offset in offsets {
-      match dstore.bbox(offset)? {
-        Some((bbox,len)) => blocks.push((bbox,offset,len)),
-        None => {},
+      if let Some((bbox,len)) = dstore.bbox_mode(offset, mode)? {
+        blocks.push((bbox,offset,len));
       }
     }
     Ok(blocks)
@@ -0,0 +1,105 @@
+use crate::{DB,Point,Value};
+use crate::lock::Lock;
+use crate::Error;
+use random_access_storage::RandomAccess;
+
+/// Shape and contents of one tree, as returned by `db.tree_stats()`.
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct TreeStats {
+  /// Slot of the tree. Trees in higher slots hold more rows.
+  pub index: usize,
+  /// Number of branch levels from the root to the deepest branch.
+  pub depth: usize,
+  /// Number of branch blocks.
+  pub branches: u64,
+  /// Number of data blocks that the branches reference.
+  pub data_blocks: u64,
+  /// Rows written to the data blocks.
+  pub rows: u64,
+  /// Rows whose bit is still set in their block's bitfield.
+  pub live_rows: u64,
+  /// Rows deleted by clearing their bit. `compact()` reclaims their bytes.
+  pub deleted_rows: u64,
+  /// Bytes of the tree's branch blocks.
+  pub branch_bytes: u64,
+  /// Bytes of the data blocks, including deleted rows.
+  pub data_bytes: u64,
+  /// Whether queries read the branches from an in-memory image.
+  pub frozen: bool
+}
+
+impl TreeStats {
+  /// Bytes of the branch and data blocks.
+  pub fn bytes (&self) -> u64 {
+    self.branch_bytes + self.data_bytes
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Report the shape and row counts of every tree that holds rows, in slot
+  /// order.
+  ///
+  /// Many deleted rows suggest running `compact()`, and many trees or deep
+  /// trees for the number of rows point at a merge schedule that doesn't
+  /// suit the workload. This reads every branch block and the header of
+  /// every data block, so it is as slow as `disk_usage()`. Deletes that are
+  /// still staged don't count until the staging area is flushed, and
+  /// quarantined blocks are left out.
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// # let mut db: DB<_,_,(f32,f32),u32> = DB::open(storage)?;
+  /// for t in db.tree_stats()? {
+  ///   println!["tree {}: depth {}, {} of {} rows live, {} bytes",
+  ///     t.index, t.depth, t.live_rows, t.rows, t.bytes()];
+  /// }
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn tree_stats (&mut self) -> Result<Vec<TreeStats>,Error> {
+    self.check_open()?;
+    let mut stats = vec![];
+    // rows each block was written with, which bitfields are sized by
+    let entries = self.data_store.write_lock()?.block_entries()?;
+    for (index,tree) in self.trees.iter().enumerate() {
+      if !self.meta.mask.get(index).cloned().unwrap_or(false) { continue }
+      let mut t = tree.write_lock()?;
+      let (depth,branches,offsets) = t.shape()?;
+      let mut s = TreeStats {
+        index,
+        depth,
+        branches,
+        branch_bytes: t.store.len()?,
+        frozen: t.frozen().is_some(),
+        ..Default::default()
+      };
+      let mut dstore = self.data_store.write_lock()?;
+      for offset in offsets {
+        if dstore.quarantine.contains(&offset) { continue }
+        let rows = match entries.get(&offset) {
+          Some((_,rows)) => *rows,
+          None => continue
+        };
+        let live: u64 = dstore.live_bits(offset, rows)?.iter()
+          .map(|b| b.count_ones() as u64).sum();
+        s.data_blocks += 1;
+        s.rows += rows;
+        s.live_rows += live;
+        s.data_bytes += dstore.block_size(offset)?;
+      }
+      s.deleted_rows = s.rows - s.live_rows;
+      stats.push(s);
+    }
+    Ok(stats)
+  }
+}
@@ -0,0 +1,92 @@
+use crate::{Point,Value};
+
+type CallbackFn<P,V> = Box<dyn FnMut(&str,&P,&V) + Send + Sync>;
+type FilterFn<P,V> = Box<dyn Fn(&P,&V) -> bool + Send + Sync>;
+
+/// What a trigger does with each inserted row that matches it.
+pub enum TriggerAction<P,V> where P: Point, V: Value {
+  /// Call the function with the trigger name, point, and value.
+  Callback(CallbackFn<P,V>),
+  /// Append an event to the database's persistent outbox, read with
+  /// `db.read_outbox()`.
+  Outbox
+}
+
+/// Condition on inserted rows that runs an action during `batch()`, such as
+/// "notify when any point enters this zone".
+///
+/// A row matches when it intersects the trigger's region (if one is set) and
+/// passes its filter (if one is set). Triggers run after the batch has been
+/// written, so a batch that fails doesn't fire them. Triggers hold closures,
+/// so they aren't persisted: register them each time the database is opened.
+///
+/// ```rust,no_run
+/// # use eyros::{DB,Row,Trigger,Error};
+/// # use std::path::PathBuf;
+/// # use random_access_disk::RandomAccessDisk;
+/// # fn main () -> Result<(),Error> {
+/// # let mut db: DB<_,_,((f32,f32),(f32,f32)),u32> = DB::open(storage)?;
+/// db.add_trigger(Trigger::new("harbor")
+///   .region(((-0.5,-0.8),(0.3,-0.5)))
+///   .filter(|_point,value| *value > 100)
+///   .callback(|name,point,value| {
+///     println!["{}: {:?} {}", name, point, value];
+///   }))?;
+/// # Ok(()) }
+/// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+/// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+/// #   p.push(name);
+/// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+/// # }
+/// ```
+pub struct Trigger<P,V> where P: Point, V: Value {
+  pub name: String,
+  pub region: Option<P::Bounds>,
+  pub filter: Option<FilterFn<P,V>>,
+  pub action: TriggerAction<P,V>
+}
+
+impl<P,V> Trigger<P,V> where P: Point, V: Value {
+  /// Create a trigger that matches every inserted row and appends it to the
+  /// outbox.
+  pub fn new (name: &str) -> Self {
+    Self {
+      name: name.to_string(),
+      region: None,
+      filter: None,
+      action: TriggerAction::Outbox
+    }
+  }
+  /// Only match rows that intersect `bbox`.
+  pub fn region (mut self, bbox: P::Bounds) -> Self {
+    self.region = Some(bbox);
+    self
+  }
+  /// Only match rows for which `f` returns `true`.
+  pub fn filter<F> (mut self, f: F) -> Self
+  where F: Fn(&P,&V) -> bool + Send + Sync + 'static {
+    self.filter = Some(Box::new(f));
+    self
+  }
+  /// Call `f` for each matching row.
+  pub fn callback<F> (mut self, f: F) -> Self
+  where F: FnMut(&str,&P,&V) + Send + Sync + 'static {
+    self.action = TriggerAction::Callback(Box::new(f));
+    self
+  }
+  /// Append matching rows to the outbox (the default).
+  pub fn outbox (mut self) -> Self {
+    self.action = TriggerAction::Outbox;
+    self
+  }
+  /// Return whether the row matches the trigger's region and filter.
+  pub fn matches (&self, point: &P, value: &V) -> bool {
+    if let Some(bbox) = &self.region {
+      if !point.overlaps(bbox) { return false }
+    }
+    match &self.filter {
+      Some(f) => f(point, value),
+      None => true
+    }
+  }
+}
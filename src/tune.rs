@@ -0,0 +1,242 @@
+use crate::{DB,Point,Value};
+use crate::lock::Lock;
+use crate::Error;
+use random_access_storage::RandomAccess;
+
+// bytes of data block that auto-tuning aims for
+const BLOCK_BYTES: f64 = 64.0 * 1024.0;
+const MIN_DATA_SIZE: usize = 250;
+const MAX_DATA_SIZE: usize = 20_000;
+// data blocks whose rows are measured for their extents
+const SAMPLE_BLOCKS: usize = 8;
+
+/// Whether the decisions of `Setup::auto_tune()` take effect on their own.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum AutoTune {
+  /// Apply the new settings as soon as the analysis runs.
+  Apply,
+  /// Record the new settings as pending until `db.apply_tuning()` or
+  /// `db.reject_tuning()`.
+  Confirm
+}
+
+/// What became of a `Tuning`.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum TuningState {
+  /// Waiting for `db.apply_tuning()` or `db.reject_tuning()`.
+  Pending,
+  /// In effect for data written since.
+  Applied,
+  /// Turned down. The settings of `Setup` stay in effect.
+  Rejected
+}
+
+/// Settings that `Setup::auto_tune()` chose from the rows of the first large
+/// ingest, recorded in the meta store. See `db.tuning()`.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Tuning {
+  /// Rows written into trees when the analysis ran.
+  pub rows: u64,
+  /// Mean bytes per row in the data blocks, including the block headers.
+  /// Large values make for fewer rows in each block.
+  pub row_bytes: f64,
+  /// Mean share of the extent of all of the rows that a row spans along its
+  /// widest axis, from a sample of the data blocks, or `None` for point types
+  /// without `Point::bounds_axes()`. This is `0.0` for scalar coordinates and
+  /// grows with the length of intervals, which make most queries read many
+  /// blocks.
+  pub row_extent: Option<f64>,
+  /// `max_data_size` in effect before the analysis.
+  pub previous_max_data_size: usize,
+  /// `max_data_size` for data written once the tuning is applied.
+  pub max_data_size: usize,
+  pub state: TuningState
+}
+
+impl Tuning {
+  pub(crate) fn to_bytes (&self) -> Vec<u8> {
+    let mut buf = vec![];
+    buf.push(match self.state {
+      TuningState::Pending => 0,
+      TuningState::Applied => 1,
+      TuningState::Rejected => 2
+    });
+    buf.extend(&self.rows.to_be_bytes());
+    buf.extend(&self.row_bytes.to_bits().to_be_bytes());
+    buf.extend(&self.row_extent.unwrap_or(-1.0).to_bits().to_be_bytes());
+    buf.extend(&(self.previous_max_data_size as u32).to_be_bytes());
+    buf.extend(&(self.max_data_size as u32).to_be_bytes());
+    buf
+  }
+  pub(crate) const ENCODED_LEN: usize = 1+8+8+8+4+4;
+  pub(crate) fn from_bytes (buf: &[u8]) -> Result<Self,Error> {
+    if buf.len() != Self::ENCODED_LEN {
+      corrupt!("unexpected buffer length for tuning");
+    }
+    let u64_at = |i: usize| {
+      let mut b = [0u8;8];
+      b.copy_from_slice(&buf[i..i+8]);
+      u64::from_be_bytes(b)
+    };
+    let u32_at = |i: usize| {
+      u32::from_be_bytes([buf[i],buf[i+1],buf[i+2],buf[i+3]]) as usize
+    };
+    let state = match buf[0] {
+      0 => TuningState::Pending,
+      1 => TuningState::Applied,
+      2 => TuningState::Rejected,
+      x => corrupt!("unknown tuning state {}", x)
+    };
+    let row_extent = f64::from_bits(u64_at(17));
+    Ok(Self {
+      state,
+      rows: u64_at(1),
+      row_bytes: f64::from_bits(u64_at(9)),
+      row_extent: if row_extent < 0.0 { None } else { Some(row_extent) },
+      previous_max_data_size: u32_at(25),
+      max_data_size: u32_at(29)
+    })
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Return the settings chosen by `Setup::auto_tune()`, if the analysis ran.
+  pub fn tuning (&self) -> Option<&Tuning> {
+    self.meta.tuning.as_ref()
+  }
+
+  /// Put pending settings of `Setup::auto_tune()` into effect.
+  pub fn apply_tuning (&mut self) -> Result<(),Error> {
+    self.decide_tuning(TuningState::Applied)
+  }
+
+  /// Turn down pending settings of `Setup::auto_tune()`. The analysis doesn't
+  /// run again.
+  pub fn reject_tuning (&mut self) -> Result<(),Error> {
+    self.decide_tuning(TuningState::Rejected)
+  }
+
+  fn decide_tuning (&mut self, state: TuningState) -> Result<(),Error> {
+    self.check_writable()?;
+    if self.fields.check_conflicts {
+      self.check_sequence()?;
+    }
+    match &mut self.meta.tuning {
+      Some(t) if t.state == TuningState::Pending => t.state = state,
+      Some(_) => invalid!["tuning was already decided"],
+      None => invalid!["no tuning is pending"]
+    }
+    self.use_tuning()?;
+    let r = self.commit_meta();
+    self.poison_on_err(r)
+  }
+
+  // switch to the block size of an applied tuning
+  pub(crate) fn use_tuning (&mut self) -> Result<(),Error> {
+    let size = match &self.meta.tuning {
+      Some(t) if t.state == TuningState::Applied => t.max_data_size,
+      _ => return Ok(())
+    };
+    self.fields.max_data_size = size;
+    self.data_store.write_lock()?.max_data_size = size;
+    for tree in self.trees.iter() {
+      tree.write_lock()?.max_data_size = size;
+    }
+    Ok(())
+  }
+
+  // analyze the trees once enough rows went into them, before the meta store
+  // is committed
+  pub(crate) fn check_tuning (&mut self) -> Result<(),Error> {
+    let (after,mode) = match self.fields.auto_tune {
+      Some(x) => x,
+      None => return Ok(())
+    };
+    if self.meta.tuning.is_some() || self.meta.ingested < after {
+      return Ok(())
+    }
+    let mut tuning = self.analyze()?;
+    tuning.state = match mode {
+      AutoTune::Apply => TuningState::Applied,
+      AutoTune::Confirm => TuningState::Pending
+    };
+    self.meta.tuning = Some(tuning);
+    self.use_tuning()
+  }
+
+  fn analyze (&mut self) -> Result<Tuning,Error> {
+    let entries = self.data_store.write_lock()?.block_entries()?;
+    let (mut rows, mut bytes) = (0u64, 0u64);
+    let mut blocks = vec![];
+    let mut total: Option<Vec<(f64,f64)>> = None;
+    for (index,tree) in self.trees.iter().enumerate() {
+      if !self.meta.mask.get(index).cloned().unwrap_or(false) { continue }
+      let (_,_,offsets) = tree.write_lock()?.shape()?;
+      let mut dstore = self.data_store.write_lock()?;
+      for offset in offsets {
+        let (range,n) = match entries.get(&offset) {
+          Some(entry) => entry,
+          None => continue
+        };
+        rows += n;
+        bytes += dstore.block_size(offset)?;
+        blocks.push(offset);
+        if let Some(axes) = P::range_bounds(range).and_then(|b| P::bounds_axes(&b)) {
+          total = Some(match total {
+            Some(t) => t.iter().zip(axes.iter())
+              .map(|(a,b)| (a.0.min(b.0), a.1.max(b.1))).collect(),
+            None => axes
+          });
+        }
+      }
+    }
+    let row_bytes = bytes as f64 / rows.max(1) as f64;
+    let row_extent = match total {
+      Some(total) => Some(self.row_extent(&blocks, &total)?),
+      None => None
+    };
+    let mut size = (BLOCK_BYTES / row_bytes.max(1.0)) as usize;
+    // rows that span much of the space put many blocks in reach of every
+    // query, so read fewer and larger blocks
+    if row_extent.map(|x| x > 0.05).unwrap_or(false) { size *= 2 }
+    Ok(Tuning {
+      rows: self.meta.ingested,
+      row_bytes,
+      row_extent,
+      previous_max_data_size: self.fields.max_data_size,
+      max_data_size: size.clamp(MIN_DATA_SIZE, MAX_DATA_SIZE),
+      state: TuningState::Pending
+    })
+  }
+}
+
+// mean share of `total` that the rows of evenly spaced blocks span along
+// their widest axis
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  fn row_extent (&mut self, blocks: &[u64], total: &[(f64,f64)])
+  -> Result<f64,Error> {
+    let step = (blocks.len() / SAMPLE_BLOCKS).max(1);
+    let (mut sum, mut n) = (0.0, 0usize);
+    let mut dstore = self.data_store.write_lock()?;
+    for offset in blocks.iter().step_by(step) {
+      for (point,_,_) in dstore.list(*offset)? {
+        let axes = match P::bounds(&vec![point]).and_then(|b| P::bounds_axes(&b)) {
+          Some(axes) => axes,
+          None => continue
+        };
+        sum += axes.iter().zip(total.iter())
+          .filter(|(_,t)| t.1 > t.0)
+          .map(|(a,t)| (a.1 - a.0) / (t.1 - t.0))
+          .fold(0.0, f64::max);
+        n += 1;
+      }
+    }
+    Ok(sum / n.max(1) as f64)
+  }
+}
@@ -0,0 +1,28 @@
+/// Bytes used by each part of a database, as returned by `db.disk_usage()`.
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct DiskUsage {
+  /// Meta file (tree mask, quarantine list, and sequence number).
+  pub meta: u64,
+  /// Rows in the staging area waiting to be built into a tree.
+  pub staging_inserts: u64,
+  /// Deletes in the staging area.
+  pub staging_deletes: u64,
+  /// Data blocks referenced by a tree.
+  pub data_live: u64,
+  /// Data blocks that no tree references anymore, such as blocks left behind
+  /// when trees were merged. `compact()` reclaims them.
+  pub data_dead: u64,
+  /// Bounding boxes cached for data blocks.
+  pub range: u64,
+  /// Branch blocks for each tree, indexed by tree level.
+  pub trees: Vec<u64>
+}
+
+impl DiskUsage {
+  /// Bytes used by all parts of the database.
+  pub fn total (&self) -> u64 {
+    self.meta + self.staging_inserts + self.staging_deletes
+      + self.data_live + self.data_dead + self.range
+      + self.trees.iter().sum::<u64>()
+  }
+}
@@ -0,0 +1,92 @@
+use crate::{DB,Point,Value};
+use crate::Error;
+use random_access_storage::RandomAccess;
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Set the meta data entry `key` to `value`, for small bits of application
+  /// state such as an ingest cursor, a schema version, or attribution.
+  ///
+  /// Entries are held by the handle until the next batch, which writes them
+  /// as part of the same commit as its rows. Other writes that commit the
+  /// meta store, such as `ack_outbox()`, leave them pending. With
+  /// `Setup::wal()`, a batch that is interrupted by a crash is finished with
+  /// its entries on the next open. Call `batch(&[])` to commit entries
+  /// without any rows. `get_meta()` returns entries that aren't committed
+  /// yet, but reloading the handle drops them.
+  ///
+  /// The meta store is rewritten on every commit, so keep entries small.
+  ///
+  /// ```rust,no_run
+  /// use eyros::{DB,Row,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// let mut db: DB<_,_,(f32,f32),u32> = DB::open(storage)?;
+  /// let cursor = db.get_meta("ingest_cursor").map(|b| b.to_vec());
+  /// // ... read rows from the source after `cursor`
+  /// db.put_meta("ingest_cursor", b"2021-04-01T12:00:00Z")?;
+  /// db.batch(&[Row::Insert((0.3,-0.8), 91)])?;
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  pub fn put_meta (&mut self, key: &str, value: &[u8]) -> Result<(),Error> {
+    self.check_writable()?;
+    if key.len() > u16::MAX as usize {
+      invalid!["meta data key of {} bytes is too long", key.len()];
+    }
+    if value.len() > u32::MAX as usize {
+      invalid!["meta data value of {} bytes is too long", value.len()];
+    }
+    self.pending_meta.insert(key.to_string(), Some(value.to_vec()));
+    Ok(())
+  }
+
+  /// Return the meta data entry `key` set with `put_meta()`.
+  pub fn get_meta (&self, key: &str) -> Option<&[u8]> {
+    match self.pending_meta.get(key) {
+      Some(value) => value.as_deref(),
+      None => self.meta.user.get(key).map(|v| v.as_slice())
+    }
+  }
+
+  /// Remove the meta data entry `key` with the next batch. Returns `false` if
+  /// there is no such entry.
+  pub fn delete_meta (&mut self, key: &str) -> Result<bool,Error> {
+    self.check_writable()?;
+    let found = self.get_meta(key).is_some();
+    if found {
+      self.pending_meta.insert(key.to_string(), None);
+    }
+    Ok(found)
+  }
+
+  /// Return the keys of the meta data entries in sorted order.
+  pub fn meta_keys (&self) -> Vec<&str> {
+    let mut keys: Vec<&str> = self.meta.user.keys()
+      .filter(|k| !self.pending_meta.contains_key(*k))
+      .chain(self.pending_meta.iter().filter(|(_,v)| v.is_some()).map(|(k,_)| k))
+      .map(|k| k.as_str())
+      .collect();
+    keys.sort_unstable();
+    keys
+  }
+
+  // move the pending entries into the meta store for the commit of the batch
+  // that is starting
+  pub(crate) fn stage_meta (&mut self) {
+    for (key,value) in std::mem::take(&mut self.pending_meta) {
+      match value {
+        Some(value) => { self.meta.user.insert(key, value); },
+        None => { self.meta.user.remove(&key); }
+      }
+      self.meta.user_changed = true;
+    }
+  }
+}
@@ -0,0 +1,133 @@
+use crate::{Point,Value};
+use crate::Error;
+use random_access_storage::RandomAccess;
+use desert::{ToBytes,FromBytes};
+
+/// Result set of a bounding box query that the database keeps up to date
+/// across commits, created with `db.create_view()`.
+///
+/// Reading a view's rows doesn't touch the trees, which suits fixed
+/// dashboards and geofence membership lists. Each view is persisted in its
+/// own store and rewritten on every commit that changes it.
+pub struct View<S,P,V> where
+S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  name: String,
+  bbox: P::Bounds,
+  rows: Vec<(P,V)>,
+  store: S
+}
+
+impl<S,P,V> View<S,P,V> where
+S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  pub fn create (name: &str, bbox: P::Bounds, rows: Vec<(P,V)>, store: S)
+  -> Result<Self,Error> {
+    let mut view = Self { name: name.to_string(), bbox, rows, store };
+    view.save()?;
+    Ok(view)
+  }
+  pub fn open (name: &str, mut store: S) -> Result<Self,Error> {
+    if store.is_empty()? {
+      corrupt!["view {} is missing its store", name];
+    }
+    let len = store.len()?;
+    let buf = store.read(0, len)?;
+    let (size,(bbox,rows)) = <(P::Bounds,Vec<(P,V)>)>::from_bytes(&buf)?;
+    if size as u64 != len {
+      corrupt!["unexpected length for view {}", name];
+    }
+    Ok(Self { name: name.to_string(), bbox, rows, store })
+  }
+  /// Name the view was created with.
+  pub fn name (&self) -> &str {
+    &self.name
+  }
+  /// Bounding box the view tracks.
+  pub fn bbox (&self) -> &P::Bounds {
+    &self.bbox
+  }
+  /// Rows that intersect the view's bounding box, in no particular order.
+  pub fn rows (&self) -> &[(P,V)] {
+    &self.rows
+  }
+  pub fn len (&self) -> usize {
+    self.rows.len()
+  }
+  pub fn is_empty (&self) -> bool {
+    self.rows.is_empty()
+  }
+  /// Add inserted rows that intersect the view and remove deleted rows.
+  /// Returns whether the view changed.
+  pub fn apply (&mut self, inserts: &[(P,V)], deletes: &[(P,V)])
+  -> Result<bool,Error> {
+    let mut changed = false;
+    for row in deletes.iter() {
+      if !row.0.overlaps(&self.bbox) { continue }
+      let bytes = row.to_bytes()?;
+      let mut found = None;
+      for (i,r) in self.rows.iter().enumerate() {
+        if r.to_bytes()? == bytes {
+          found = Some(i);
+          break;
+        }
+      }
+      if let Some(i) = found {
+        self.rows.swap_remove(i);
+        changed = true;
+      }
+    }
+    for row in inserts.iter() {
+      if row.0.overlaps(&self.bbox) {
+        self.rows.push(row.clone());
+        changed = true;
+      }
+    }
+    Ok(changed)
+  }
+  /// Replace the rows of the view and save it.
+  pub fn reset (&mut self, rows: Vec<(P,V)>) -> Result<(),Error> {
+    self.rows = rows;
+    self.save()
+  }
+  pub fn save (&mut self) -> Result<(),Error> {
+    let bytes = (self.bbox,self.rows.clone()).to_bytes()?;
+    self.store.truncate(0)?;
+    self.store.write(0, &bytes)?;
+    self.store.sync_all()?;
+    Ok(())
+  }
+  pub fn sync_all (&mut self) -> Result<(),Error> {
+    Ok(self.store.sync_all()?)
+  }
+  /// Remove the view's data from storage.
+  pub fn destroy (&mut self) -> Result<(),Error> {
+    self.store.truncate(0)?;
+    self.store.sync_all()?;
+    Ok(())
+  }
+}
+
+/// Read the list of view names from the `views` store.
+pub fn load_names<S> (store: &mut S) -> Result<Vec<String>,Error>
+where S: RandomAccess<Error=failure::Error> {
+  if store.is_empty()? { return Ok(vec![]) }
+  let len = store.len()?;
+  let buf = store.read(0, len)?;
+  let (_,names) = Vec::<Vec<u8>>::from_bytes(&buf)?;
+  let mut result = Vec::with_capacity(names.len());
+  for name in names {
+    result.push(String::from_utf8(name)
+      .map_err(|e| Error::Corrupt(format!["view name: {}", e]))?);
+  }
+  Ok(result)
+}
+
+/// Write the list of view names to the `views` store.
+pub fn save_names<S> (store: &mut S, names: &[&str]) -> Result<(),Error>
+where S: RandomAccess<Error=failure::Error> {
+  let names: Vec<Vec<u8>> = names.iter().map(|n| n.as_bytes().to_vec()).collect();
+  let bytes = names.to_bytes()?;
+  store.truncate(0)?;
+  store.write(0, &bytes)?;
+  store.sync_all()?;
+  Ok(())
+}
@@ -0,0 +1,57 @@
+use crate::{DB,Point,Value};
+use random_access_storage::RandomAccess;
+use std::sync::Arc;
+
+pub(crate) type VisibleFn<P,V> = Arc<dyn Fn(&P,&V) -> bool + Send + Sync>;
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Hide every row for which `visible(point,value)` returns `false` from
+  /// this handle, replacing any earlier predicate.
+  ///
+  /// The predicate is applied inside the query stream, so a server can hand
+  /// out handles that only see the rows of one tenant of a shared database:
+  ///
+  /// ```rust,no_run
+  /// # use eyros::{DB,Error};
+  /// # use std::path::PathBuf;
+  /// # use random_access_disk::RandomAccessDisk;
+  /// # fn main () -> Result<(),Error> {
+  /// // values start with a tenant id
+  /// let mut db: DB<_,_,(f32,f32),(u32,u64)> = DB::open(storage)?;
+  /// let tenant = 7;
+  /// db.set_visibility(move |_point,value| value.0 == tenant);
+  /// for result in db.query(&((-0.5,-0.8),(0.3,-0.5)))? {
+  ///   let (point,(tenant_id,value),location) = result?;
+  ///   assert_eq![tenant_id, tenant];
+  /// }
+  /// # Ok(()) }
+  /// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+  /// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+  /// #   p.push(name);
+  /// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  /// # }
+  /// ```
+  ///
+  /// Queries and their variants, `query_for_each()`, `count()`,
+  /// `aggregate()`, `top_k()`, `nearest()`, digests, and `delete_query()`
+  /// only see visible rows. Counts then read every matching block instead of
+  /// counting whole blocks from their bitfields. Writes, the changes feed,
+  /// views, triggers, and maintenance still see every row, so don't hand out
+  /// those APIs. Predicates hold code, so they aren't persisted: set them each
+  /// time the database is opened.
+  pub fn set_visibility<F> (&mut self, visible: F) where
+  F: Fn(&P,&V) -> bool + Send + Sync + 'static {
+    self.visible = Some(Arc::new(visible));
+  }
+  /// Show every row again after `set_visibility()`.
+  pub fn clear_visibility (&mut self) {
+    self.visible = None;
+  }
+
+  pub(crate) fn is_visible (&self, point: &P, value: &V) -> bool {
+    self.visible.as_ref().map(|f| f(point,value)).unwrap_or(true)
+  }
+}
@@ -0,0 +1,164 @@
+use crate::{Point,Value,Row,Location};
+use crate::Error;
+use random_access_storage::RandomAccess;
+use desert::{ToBytes,FromBytes};
+
+/// Batch written to the write-ahead log before any other store is touched,
+/// along with the store lengths and block contents needed to undo a partial
+/// write of the batch.
+#[derive(Clone,Debug,PartialEq)]
+pub struct WalRecord {
+  /// Meta sequence number that the batch commits.
+  pub sequence: u64,
+  /// Lengths of the staging insert and delete stores before the batch.
+  pub staging: (u64,u64),
+  /// Lengths of the data and range stores before the batch.
+  pub data: (u64,u64),
+  /// Contents of the meta store before the batch.
+  pub meta: Vec<u8>,
+  /// Contents of the staging stores, for batches that may rewrite them.
+  pub snapshot: Option<(Vec<u8>,Vec<u8>)>,
+  /// Encoded rows of the batch.
+  pub rows: Vec<u8>,
+  /// Offset and body of each data block that the batch deletes rows from or
+  /// updates in place, before the batch. These writes land inside of blocks,
+  /// so truncating the data store doesn't undo them.
+  pub blocks: Vec<(u64,Vec<u8>)>,
+  /// Name and length before the batch of each append-only store, such as the
+  /// changes feed and the outbox, that the batch appends to before it
+  /// commits.
+  pub tails: Vec<(String,u64)>,
+  /// Outbox records of the batch. Triggers aren't registered yet when a
+  /// batch is redone at open, so the events it queued are kept here.
+  pub events: Vec<u8>
+}
+
+/// Write-ahead log holding the batch that is in progress. The log is a single
+/// record `[length (u32)][record][crc32 (u32)]`, where the length covers the
+/// record and checksum. A torn record fails the checksum and is discarded.
+pub struct Wal<S> where S: RandomAccess<Error=failure::Error> {
+  store: S
+}
+
+impl<S> Wal<S> where S: RandomAccess<Error=failure::Error> {
+  pub fn open (store: S) -> Self {
+    Self { store }
+  }
+  /// Durably write `record` in place of any earlier record.
+  pub fn begin (&mut self, record: &WalRecord) -> Result<(),Error> {
+    let (has_snapshot,(ibuf,dbuf)) = match &record.snapshot {
+      Some(s) => (1u8,s.clone()),
+      None => (0u8,(vec![],vec![]))
+    };
+    let mut body = (
+      record.sequence, record.staging, record.data, record.meta.clone(),
+      (has_snapshot, ibuf, dbuf), record.rows.clone()
+    ).to_bytes()?;
+    // after the fields that records without blocks, tails, or events end with
+    if !record.blocks.is_empty() || !record.tails.is_empty()
+    || !record.events.is_empty() {
+      let tails: Vec<(Vec<u8>,u64)> = record.tails.iter()
+        .map(|(name,len)| (name.as_bytes().to_vec(),*len))
+        .collect();
+      body.extend((record.blocks.clone(),tails,record.events.clone()).to_bytes()?);
+    }
+    let mut buf = Vec::with_capacity(body.len()+8);
+    buf.extend(&((body.len()+4) as u32).to_be_bytes());
+    buf.extend(&body);
+    buf.extend(&crc32fast::hash(&body).to_be_bytes());
+    self.store.truncate(0)?;
+    self.store.write(0, &buf)?;
+    self.store.sync_all()?;
+    Ok(())
+  }
+  /// Return the logged record, or `None` if the log is empty or the record
+  /// was torn by a crash while it was being written.
+  pub fn read (&mut self) -> Result<Option<WalRecord>,Error> {
+    let len = self.store.len()?;
+    if len < 8 { return Ok(None) }
+    let buf = self.store.read(0, len)?;
+    let rlen = u32::from_be_bytes([buf[0],buf[1],buf[2],buf[3]]) as usize;
+    if rlen < 4 || 4+rlen > buf.len() { return Ok(None) }
+    let body = &buf[4..rlen];
+    let c = &buf[rlen..rlen+4];
+    if crc32fast::hash(body) != u32::from_be_bytes([c[0],c[1],c[2],c[3]]) {
+      return Ok(None);
+    }
+    let (size,(sequence,staging,data,meta,(has_snapshot,ibuf,dbuf),rows)) = <(
+      u64,(u64,u64),(u64,u64),Vec<u8>,(u8,Vec<u8>,Vec<u8>),Vec<u8>
+    )>::from_bytes(body)?;
+    let snapshot = if has_snapshot == 1 { Some((ibuf,dbuf)) } else { None };
+    let (blocks,tails,events) = if size < body.len() {
+      <(Vec<(u64,Vec<u8>)>,Vec<(Vec<u8>,u64)>,Vec<u8>)>::from_bytes(&body[size..])?.1
+    } else {
+      (vec![],vec![],vec![])
+    };
+    let mut names = Vec::with_capacity(tails.len());
+    for (name,len) in tails {
+      names.push((String::from_utf8(name)
+        .map_err(|e| Error::Corrupt(format!["write-ahead log store name: {}", e]))?, len));
+    }
+    Ok(Some(WalRecord {
+      sequence, staging, data, meta, snapshot, rows, blocks, tails: names, events
+    }))
+  }
+  pub fn sync_all (&mut self) -> Result<(),Error> {
+    Ok(self.store.sync_all()?)
+  }
+  /// Remove the record once its batch is committed.
+  pub fn clear (&mut self) -> Result<(),Error> {
+    self.store.truncate(0)?;
+    self.store.sync_all()?;
+    Ok(())
+  }
+}
+
+pub fn encode_rows<P,V> (rows: &[Row<P,V>]) -> Result<Vec<u8>,Error>
+where P: Point, V: Value {
+  let mut buf = vec![];
+  for row in rows.iter() {
+    match row {
+      Row::Insert(p,v) => {
+        buf.push(0);
+        buf.extend((*p,v.clone()).to_bytes()?);
+      },
+      Row::Delete(loc) => {
+        buf.push(1);
+        buf.extend(loc.to_bytes()?);
+      },
+      Row::Update(loc,p,v) => {
+        buf.push(2);
+        buf.extend((*loc,*p,v.clone()).to_bytes()?);
+      }
+    }
+  }
+  Ok(buf)
+}
+
+pub fn decode_rows<P,V> (buf: &[u8]) -> Result<Vec<Row<P,V>>,Error>
+where P: Point, V: Value {
+  let mut rows = vec![];
+  let mut offset = 0;
+  while offset < buf.len() {
+    let tag = buf[offset];
+    offset += 1;
+    let (size,row) = match tag {
+      0 => {
+        let (size,(p,v)) = <(P,V)>::from_bytes(&buf[offset..])?;
+        (size,Row::Insert(p,v))
+      },
+      1 => {
+        let (size,loc) = Location::from_bytes(&buf[offset..])?;
+        (size,Row::Delete(loc))
+      },
+      2 => {
+        let (size,(loc,p,v)) = <(Location,P,V)>::from_bytes(&buf[offset..])?;
+        (size,Row::Update(loc,p,v))
+      },
+      _ => corrupt!["unknown row type {} in write-ahead log", tag]
+    };
+    rows.push(row);
+    offset += size;
+  }
+  Ok(rows)
+}
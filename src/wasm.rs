@@ -0,0 +1,283 @@
+use crate::{DB,Point,Value,Location,QueryIterator,CacheMode,Pruner,BlockInfo,Verdict};
+use crate::Error;
+use random_access_storage::RandomAccess;
+use std::sync::{Arc,Mutex};
+use wasmi::{Config,Engine,Linker,Memory,Module,Store,StoreLimits,
+  StoreLimitsBuilder,TypedFunc};
+
+/// Resources a `WasmFilter` may use.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub struct WasmLimits {
+  /// Fuel for each call into the module, roughly one unit per instruction.
+  /// Calls that run out trap and fail the query. Default: 1,000,000.
+  pub fuel: u64,
+  /// Largest size of the module's linear memory in bytes. Default: 16 MiB.
+  pub memory: usize
+}
+
+impl Default for WasmLimits {
+  fn default () -> Self {
+    Self { fuel: 1_000_000, memory: 16 << 20 }
+  }
+}
+
+/// Query filter compiled to WebAssembly, which `db.query_wasm()` runs in a
+/// sandbox so that query servers can accept filters from their users without
+/// recompiling.
+///
+/// Modules can't import anything, so a filter only sees the rows it is
+/// passed, and each call runs with the fuel and memory of `WasmLimits`.
+/// A module exports its `memory`, an `alloc(len: i32) -> i32` function that
+/// returns the address of `len` bytes that the database may write to, and at
+/// least one of:
+///
+/// * `filter(axes: i32, dim: i32, value: i32, value_len: i32) -> i32`, called
+///   for each candidate row. `axes` points to `dim` pairs of little-endian
+///   `f64` values with the `(min,max)` extent of the row's point along each
+///   axis, and `value` to the row's value as the database encodes it. The row
+///   is returned if the result isn't zero.
+/// * `block(axes: i32, dim: i32, rows: i64) -> i32`, called for each data
+///   block before it is read with the extent of the block's points and the
+///   number of rows the block was written with. The block is skipped if the
+///   result is zero.
+///
+/// Point types need `Point::bounds_axes()` for their coordinates to be
+/// passed to the module.
+///
+/// ```rust,no_run
+/// use eyros::{DB,WasmFilter,Error};
+/// # use std::path::PathBuf;
+/// # use std::sync::Arc;
+/// # use random_access_disk::RandomAccessDisk;
+/// # fn main () -> Result<(),Error> {
+/// # let mut db: DB<_,_,(f32,f32),u32> = DB::open(storage)?;
+/// let wasm = std::fs::read("filter.wasm")?;
+/// let filter = Arc::new(WasmFilter::new(&wasm)?);
+/// for result in db.query_wasm(&((-0.5,-0.8),(0.3,-0.5)), &filter)? {
+///   let (point,value,location) = result?;
+///   // ...
+/// }
+/// # Ok(()) }
+/// # fn storage(name: &str) -> Result<RandomAccessDisk,failure::Error> {
+/// #   let mut p = PathBuf::from("/tmp/eyros-db/");
+/// #   p.push(name);
+/// #   Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+/// # }
+/// ```
+pub struct WasmFilter {
+  runtime: Mutex<Runtime>,
+  fuel: u64,
+  has_filter: bool,
+  has_block: bool
+}
+
+struct Runtime {
+  store: Store<StoreLimits>,
+  memory: Memory,
+  alloc: TypedFunc<i32,i32>,
+  filter: Option<TypedFunc<(i32,i32,i32,i32),i32>>,
+  block: Option<TypedFunc<(i32,i32,i64),i32>>,
+  // address and size of the buffer that rows are written to
+  buf: Option<(i32,usize)>,
+  scratch: Vec<u8>
+}
+
+impl WasmFilter {
+  /// Compile and instantiate a module in the binary format with the default
+  /// `WasmLimits`.
+  pub fn new (wasm: &[u8]) -> Result<Self,Error> {
+    Self::with_limits(wasm, WasmLimits::default())
+  }
+  /// Like `new()`, with the resources in `limits`.
+  pub fn with_limits (wasm: &[u8], limits: WasmLimits) -> Result<Self,Error> {
+    let mut config = Config::default();
+    config.consume_fuel(true);
+    let engine = Engine::new(&config);
+    let module = Module::new(&engine, wasm)
+      .map_err(|e| Error::Invalid(format!["invalid wasm module: {}", e]))?;
+    if let Some(import) = module.imports().next() {
+      invalid!["wasm filters can't import anything, found {}::{}",
+        import.module(), import.name()]
+    }
+    let mut store = Store::new(&engine, StoreLimitsBuilder::new()
+      .memory_size(limits.memory)
+      .build());
+    store.limiter(|limits| limits);
+    store.set_fuel(limits.fuel).map_err(|e| Error::Other(format!["{}", e]))?;
+    let instance = Linker::<StoreLimits>::new(&engine)
+      .instantiate(&mut store, &module)
+      .and_then(|pre| pre.start(&mut store))
+      .map_err(|e| Error::Invalid(format!["failed to instantiate wasm module: {}", e]))?;
+    let memory = match instance.get_memory(&store, "memory") {
+      Some(memory) => memory,
+      None => invalid!["wasm module doesn't export its memory"]
+    };
+    let alloc = instance.get_typed_func::<i32,i32>(&store, "alloc")
+      .map_err(|e| Error::Invalid(format!["wasm module needs alloc(i32) -> i32: {}", e]))?;
+    let filter = instance.get_typed_func(&store, "filter").ok();
+    let block = instance.get_typed_func(&store, "block").ok();
+    if filter.is_none() && block.is_none() {
+      invalid!["wasm module exports neither filter() nor block()"]
+    }
+    Ok(Self {
+      fuel: limits.fuel,
+      has_filter: filter.is_some(),
+      has_block: block.is_some(),
+      runtime: Mutex::new(Runtime {
+        store, memory, alloc, filter, block, buf: None, scratch: vec![]
+      })
+    })
+  }
+  /// Return whether the module keeps the row with `point` and `value`, or
+  /// `true` if it doesn't export `filter()`.
+  pub fn matches<P,V> (&self, point: &P, value: &V) -> Result<bool,Error>
+  where P: Point, V: Value {
+    if !self.has_filter { return Ok(true) }
+    let axes = match P::bounds(&vec![*point]).and_then(|b| P::bounds_axes(&b)) {
+      Some(axes) => axes,
+      None => invalid!["point type has no bounds_axes() to pass to a wasm filter"]
+    };
+    let mut rt = self.lock()?;
+    rt.scratch.clear();
+    for (min,max) in axes.iter() {
+      rt.scratch.extend(&min.to_le_bytes());
+      rt.scratch.extend(&max.to_le_bytes());
+    }
+    let value_start = rt.scratch.len();
+    rt.scratch.extend(value.to_bytes()?);
+    let ptr = rt.write(self.fuel)?;
+    let value_len = rt.scratch.len() - value_start;
+    let f = rt.filter.unwrap();
+    rt.store.set_fuel(self.fuel).map_err(|e| Error::Other(format!["{}", e]))?;
+    let keep = f.call(&mut rt.store,
+      (ptr, axes.len() as i32, ptr + value_start as i32, value_len as i32))
+      .map_err(|e| Error::Other(format!["wasm filter failed: {}", e]))?;
+    Ok(keep != 0)
+  }
+  /// Return whether the module reads the data block whose points lie in
+  /// `range`, or `true` if it doesn't export `block()`.
+  pub fn matches_block<P> (&self, range: &P::Range, rows: u64)
+  -> Result<bool,Error> where P: Point {
+    if !self.has_block { return Ok(true) }
+    let axes = match P::range_bounds(range).and_then(|b| P::bounds_axes(&b)) {
+      Some(axes) => axes,
+      None => return Ok(true)
+    };
+    let mut rt = self.lock()?;
+    rt.scratch.clear();
+    for (min,max) in axes.iter() {
+      rt.scratch.extend(&min.to_le_bytes());
+      rt.scratch.extend(&max.to_le_bytes());
+    }
+    let ptr = rt.write(self.fuel)?;
+    let f = rt.block.unwrap();
+    rt.store.set_fuel(self.fuel).map_err(|e| Error::Other(format!["{}", e]))?;
+    let read = f.call(&mut rt.store, (ptr, axes.len() as i32, rows as i64))
+      .map_err(|e| Error::Other(format!["wasm block filter failed: {}", e]))?;
+    Ok(read != 0)
+  }
+  fn lock (&self) -> Result<std::sync::MutexGuard<'_,Runtime>,Error> {
+    self.runtime.lock().map_err(|_| Error::Other("wasm filter lock poisoned".into()))
+  }
+}
+
+impl Runtime {
+  // Copy `scratch` into the module's memory, allocating a larger buffer
+  // first if needed, and return its address.
+  fn write (&mut self, fuel: u64) -> Result<i32,Error> {
+    let len = self.scratch.len();
+    let ptr = match self.buf {
+      Some((ptr,size)) if size >= len => ptr,
+      _ => {
+        let size = len.next_power_of_two().max(64);
+        self.store.set_fuel(fuel).map_err(|e| Error::Other(format!["{}", e]))?;
+        let ptr = self.alloc.call(&mut self.store, size as i32)
+          .map_err(|e| Error::Other(format!["wasm alloc failed: {}", e]))?;
+        self.buf = Some((ptr,size));
+        ptr
+      }
+    };
+    self.memory.write(&mut self.store, ptr as usize, &self.scratch)
+      .map_err(|e| Error::Other(format!["wasm alloc returned an invalid address: {}", e]))?;
+    Ok(ptr)
+  }
+}
+
+// skips the blocks that the module's block() rejects before consulting the
+// db's own pruner
+struct WasmPruner<P,V> where P: Point, V: Value {
+  filter: Arc<WasmFilter>,
+  inner: Option<Arc<dyn Pruner<P,V>>>
+}
+
+impl<P,V> Pruner<P,V> for WasmPruner<P,V> where P: Point, V: Value {
+  fn summaries (&self) -> Vec<String> {
+    self.inner.as_ref().map(|p| p.summaries()).unwrap_or_default()
+  }
+  fn block (&self, bbox: &P::Bounds, block: &BlockInfo<P,V>) -> Verdict {
+    if let (Some(range),Some(rows)) = (block.range,block.rows) {
+      // blocks are read when the module fails, the row filter still applies
+      if let Ok(false) = self.filter.matches_block::<P>(range, rows) {
+        return Verdict::Skip;
+      }
+    }
+    match &self.inner {
+      Some(pruner) => pruner.block(bbox, block),
+      None => Verdict::default()
+    }
+  }
+}
+
+/// Iterator of `Result<(Point,Value,Location)>` data returned by
+/// `db.query_wasm()`.
+pub struct WasmQueryIterator<'b,S,P,V> where
+S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  query: QueryIterator<'b,S,P,V>,
+  filter: Arc<WasmFilter>
+}
+
+impl<'b,S,P,V> Iterator for WasmQueryIterator<'b,S,P,V> where
+S: RandomAccess<Error=failure::Error>, P: Point, V: Value {
+  type Item = Result<(P,V,Location),Error>;
+  fn next (&mut self) -> Option<Self::Item> {
+    loop {
+      match self.query.next()? {
+        Ok(row) => match self.filter.matches(&row.0, &row.1) {
+          Ok(true) => return Some(Ok(row)),
+          Ok(false) => continue,
+          Err(e) => return Some(Err(e))
+        },
+        result => return Some(result)
+      }
+    }
+  }
+}
+
+impl<S,U,P,V> DB<S,U,P,V> where
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>),
+P: Point, V: Value {
+  /// Query `bbox` like `query()`, returning only the rows that `filter`
+  /// keeps and skipping the data blocks that it rejects. The pruner set with
+  /// `set_pruner()` is consulted for the blocks that remain.
+  ///
+  /// A call that traps, such as one that runs out of fuel, fails the query
+  /// with an error for row filters. Blocks are read when `block()` traps.
+  pub fn query_wasm<'b> (&mut self, bbox: &'b P::Bounds, filter: &Arc<WasmFilter>)
+  -> Result<WasmQueryIterator<'b,S,P,V>,Error> where P: 'static {
+    self.check_open()?;
+    let permit = self.admit()?;
+    let pruner: Option<Arc<dyn Pruner<P,V>>> = if filter.has_block {
+      Some(Arc::new(WasmPruner {
+        filter: Arc::clone(filter),
+        inner: self.pruner.clone()
+      }))
+    } else {
+      self.pruner.clone()
+    };
+    let audit = self.audit_query(bbox);
+    let query = self.query_pruned(bbox, CacheMode::Normal, permit, pruner)?
+      .audit(audit);
+    Ok(WasmQueryIterator { query, filter: Arc::clone(filter) })
+  }
+}
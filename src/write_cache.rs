@@ -4,9 +4,13 @@ use std::io::Write;
 #[derive(Debug,Clone)]
 pub struct WriteCache<S> where S: RandomAccess {
   store: S,
-  queue: Vec<(u64,Vec<u8>)>,
+  // (offset,data,sequence of the oldest write merged into the entry)
+  queue: Vec<(u64,Vec<u8>,u64)>,
   length: u64,
-  enabled: bool
+  enabled: bool,
+  seq: u64,
+  dirty: u64,
+  max_dirty: Option<u64>
 }
 
 impl<S> WriteCache<S> where S: RandomAccess {
@@ -16,9 +20,34 @@ impl<S> WriteCache<S> where S: RandomAccess {
       store,
       queue: vec![],
       length,
-      enabled: true
+      enabled: true,
+      seq: 0,
+      dirty: 0,
+      max_dirty: None
     })
   }
+  /// Write the oldest queued writes through to the store whenever more than
+  /// `max` bytes are queued. `None` queues everything until `sync_all()`.
+  pub fn max_dirty_bytes (&mut self, max: Option<u64>) -> Result<(),S::Error> {
+    self.max_dirty = max;
+    self.flush_oldest()
+  }
+  fn flush_oldest (&mut self) -> Result<(),S::Error> {
+    let max = match self.max_dirty {
+      Some(max) => max,
+      None => return Ok(())
+    };
+    while self.dirty > max {
+      let oldest = (0..self.queue.len()).min_by_key(|i| self.queue[*i].2);
+      let (offset,data,_) = match oldest {
+        Some(i) => self.queue.remove(i),
+        None => break
+      };
+      self.store.write(offset, &data)?;
+      self.dirty -= data.len() as u64;
+    }
+    Ok(())
+  }
 }
 
 impl<S> RandomAccess for WriteCache<S> where S: RandomAccess {
@@ -34,12 +63,17 @@ impl<S> RandomAccess for WriteCache<S> where S: RandomAccess {
 
     let mut start = new_range.0;
     let mut end = new_range.1;
+    let mut seq = self.seq;
+    self.seq += 1;
     for i in overlapping.iter() {
       let q = &self.queue[*i];
       start = start.min(q.0);
       end = end.max(q.0 + ((q.1).len() as u64));
+      seq = seq.min(q.2);
+      self.dirty -= q.1.len() as u64;
     }
-    let mut merged = (start,vec![0;(end-start) as usize]);
+    let mut merged = (start,vec![0;(end-start) as usize],seq);
+    self.dirty += end-start;
     for i in overlapping.iter() {
       let q = &self.queue[*i];
       merged.1[(q.0-start) as usize..(q.0-start+(q.1.len() as u64)) as usize]
@@ -64,7 +98,7 @@ impl<S> RandomAccess for WriteCache<S> where S: RandomAccess {
       self.queue.insert(overlapping[0], merged);
     }
     self.length = self.length.max(end);
-    Ok(())
+    self.flush_oldest()
   }
   fn read (&mut self, offset: u64, length: u64)
   -> Result<Vec<u8>,Self::Error> {
@@ -117,15 +151,16 @@ impl<S> RandomAccess for WriteCache<S> where S: RandomAccess {
     while i < self.queue.len() {
       let q0 = self.queue[i].0;
       let qlen = self.queue[i].1.len() as u64;
-      if q0 < length {
+      if q0 >= length {
         self.queue.remove(i);
-      } else if q0 + qlen < length {
+      } else if q0 + qlen > length {
         self.queue[i].1.truncate((length - q0 as u64) as usize);
         i += 1;
       } else {
         i += 1;
       }
     }
+    self.dirty = self.queue.iter().map(|q| q.1.len() as u64).sum();
     self.store.truncate(length)?;
     self.length = length;
     Ok(())
@@ -143,7 +178,8 @@ impl<S> RandomAccess for WriteCache<S> where S: RandomAccess {
       self.store.write(q.0, &q.1)?;
     }
     self.queue.clear();
-    Ok(())
+    self.dirty = 0;
+    self.store.sync_all()
   }
 }
 
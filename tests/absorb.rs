@@ -0,0 +1,62 @@
+use eyros::{Setup,DB,Row};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn absorb() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(500)
+    .base_size(500)
+    .absorb(100, 4_000)
+    .build()?;
+  let live = |db: &mut DB<_,_,P,V>| -> Result<Vec<usize>,Error> {
+    let mut trees = vec![];
+    for (i,tree) in db.trees.iter().enumerate() {
+      if !tree.write().unwrap().is_empty()? { trees.push(i) }
+    }
+    Ok(trees)
+  };
+  let mut r = rand().seed([40,2]);
+  let mut n = 0;
+  let mut most = 0;
+  // drip-feed small batches
+  for _ in 0..50 {
+    let batch: Vec<Row<P,V>> = (0..100).map(|_| {
+      n += 1;
+      Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), n)
+    }).collect();
+    db.batch(&batch)?;
+    let trees = live(&mut db)?;
+    most = most.max(trees.len());
+    if n <= 4_000 {
+      assert![trees.len() <= 1, "absorbed into one tree: {:?}", trees];
+    }
+  }
+  assert_eq![most, 2, "a second tree starts once the first is full"];
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let rows = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?;
+  let mut values: Vec<V> = rows.iter().map(|row| row.1).collect();
+  values.sort();
+  assert_eq![values, (1..=n).collect::<Vec<V>>()];
+
+  // locations in absorbed trees stay valid
+  let deletes: Vec<Row<P,V>> = rows.iter().filter(|row| row.1 % 3 == 0)
+    .map(|row| Row::Delete(row.2)).collect();
+  db.batch(&deletes)?;
+  let mut values: Vec<V> = db.query(&bbox)?
+    .map(|r| r.map(|row| row.1)).collect::<Result<_,Error>>()?;
+  values.sort();
+  assert_eq![values, (1..=n).filter(|v| v % 3 != 0).collect::<Vec<V>>()];
+
+  Ok(())
+}
@@ -0,0 +1,124 @@
+use eyros::{DB,Row,Adapter,adapt};
+use eyros::Error;
+use random_access_storage::RandomAccess;
+use random::{Source,default as rand};
+use std::collections::HashMap;
+use std::{fmt,io};
+use std::sync::{Arc,Mutex};
+
+type P = ((f32,f32),(f32,f32));
+type V = u32;
+
+// in-memory store with its own error type, like a browser store would have
+struct MemoryStore {
+  data: Arc<Mutex<Vec<u8>>>
+}
+
+impl RandomAccess for MemoryStore {
+  type Error = io::Error;
+  fn write (&mut self, offset: u64, data: &[u8]) -> Result<(),io::Error> {
+    let mut buf = self.data.lock().unwrap();
+    let end = offset as usize + data.len();
+    if buf.len() < end { buf.resize(end, 0) }
+    buf[offset as usize..end].copy_from_slice(data);
+    Ok(())
+  }
+  fn read (&mut self, offset: u64, length: u64) -> Result<Vec<u8>,io::Error> {
+    let buf = self.data.lock().unwrap();
+    let end = (offset + length) as usize;
+    if end > buf.len() {
+      return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "read out of bounds"));
+    }
+    Ok(buf[offset as usize..end].to_vec())
+  }
+  fn read_to_writer (&mut self, offset: u64, length: u64,
+  w: &mut impl io::Write) -> Result<(),io::Error> {
+    let data = self.read(offset, length)?;
+    w.write_all(&data)
+  }
+  fn del (&mut self, offset: u64, length: u64) -> Result<(),io::Error> {
+    let mut buf = self.data.lock().unwrap();
+    let end = ((offset + length) as usize).min(buf.len());
+    for b in buf[(offset as usize).min(end)..end].iter_mut() { *b = 0 }
+    Ok(())
+  }
+  fn truncate (&mut self, length: u64) -> Result<(),io::Error> {
+    self.data.lock().unwrap().resize(length as usize, 0);
+    Ok(())
+  }
+  fn len (&self) -> Result<u64,io::Error> {
+    Ok(self.data.lock().unwrap().len() as u64)
+  }
+  fn is_empty (&mut self) -> Result<bool,io::Error> {
+    Ok(self.data.lock().unwrap().is_empty())
+  }
+  fn sync_all (&mut self) -> Result<(),io::Error> {
+    Ok(())
+  }
+}
+
+#[test]
+fn adapter() -> Result<(),Error> {
+  let files: Arc<Mutex<HashMap<String,Arc<Mutex<Vec<u8>>>>>>
+    = Arc::new(Mutex::new(HashMap::new()));
+  let open = {
+    let files = Arc::clone(&files);
+    move |name: &str| -> Result<MemoryStore,io::Error> {
+      let mut files = files.lock().unwrap();
+      let data = files.entry(name.to_string()).or_default();
+      Ok(MemoryStore { data: Arc::clone(data) })
+    }
+  };
+  let mut r = rand().seed([13,12]);
+  let inserts: Vec<Row<P,V>> = (0..500).map(|i| {
+    let x: f32 = r.read::<f32>()*2.0-1.0;
+    let y: f32 = r.read::<f32>()*2.0-1.0;
+    Row::Insert(((x,x+0.01),(y,y+0.01)), i)
+  }).collect();
+  let bbox = ((-0.5,-0.5),(0.5,0.5));
+  let expected = inserts.iter().filter(|row| match row {
+    Row::Insert(((x0,x1),(y0,y1)),_) => {
+      *x1 >= -0.5 && *x0 <= 0.5 && *y1 >= -0.5 && *y0 <= 0.5
+    },
+    _ => false
+  }).count();
+  {
+    let mut db: DB<_,_,P,V> = DB::open(adapt(open.clone()))?;
+    db.batch(&inserts)?;
+    assert_eq![db.query(&bbox)?.count(), expected];
+  }
+  {
+    let mut db: DB<_,_,P,V> = DB::open(adapt(open))?;
+    assert_eq![db.query(&bbox)?.count(), expected, "reopened from the same stores"];
+  }
+  Ok(())
+}
+
+#[derive(Debug)]
+struct Offline;
+
+impl fmt::Display for Offline {
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "storage is offline")
+  }
+}
+
+impl std::error::Error for Offline {}
+
+#[test]
+fn storage_errors() {
+  let open = |_: &str| -> Result<MemoryStore,io::Error> {
+    Err(io::Error::new(io::ErrorKind::PermissionDenied, "no access"))
+  };
+  match DB::<_,_,P,V>::open(adapt(open)).err() {
+    Some(Error::Io(e)) => assert_eq![e.kind(), io::ErrorKind::PermissionDenied],
+    e => panic!["expected an io error, got {:?}", e]
+  }
+  let open = |_: &str| -> Result<Adapter<MemoryStore>,failure::Error> {
+    Err(Offline.into())
+  };
+  let err = DB::<_,_,P,V>::open(open).err().expect("opening fails");
+  assert![matches![err, Error::Storage(_)], "{:?}", err];
+  assert![err.downcast_ref::<Offline>().is_some(), "backend error type is kept"];
+  assert![err.downcast_ref::<io::Error>().is_none()];
+}
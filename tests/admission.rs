@@ -0,0 +1,148 @@
+use eyros::{Setup,DB,Row,Admission,Overloaded};
+use eyros::async_db::{AsyncDB,AsyncRandomAccess,StoreFuture};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random_access_storage::RandomAccess;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context,Poll,RawWaker,RawWakerVTable,Waker};
+
+type P = (f32,f32);
+type V = u32;
+
+fn noop_waker () -> Waker {
+  fn raw () -> RawWaker {
+    fn clone (_: *const ()) -> RawWaker { raw() }
+    fn noop (_: *const ()) {}
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+    RawWaker::new(std::ptr::null(), &VTABLE)
+  }
+  unsafe { Waker::from_raw(raw()) }
+}
+
+// poll `f` until it is ready, for calls that wait for a query slot
+fn block_on<F: Future> (f: F) -> F::Output {
+  let waker = noop_waker();
+  let mut cx = Context::from_waker(&waker);
+  let mut f = Box::pin(f);
+  loop {
+    if let Poll::Ready(x) = f.as_mut().poll(&mut cx) { return x }
+  }
+}
+
+// disk store whose calls complete as soon as they are polled
+struct Disk(RandomAccessDisk);
+
+impl AsyncRandomAccess for Disk {
+  fn write<'a> (&'a mut self, offset: u64, data: &'a [u8]) -> StoreFuture<'a,()> {
+    Box::pin(async move { self.0.write(offset, data) })
+  }
+  fn read (&mut self, offset: u64, length: u64) -> StoreFuture<'_,Vec<u8>> {
+    Box::pin(async move { self.0.read(offset, length) })
+  }
+  fn truncate (&mut self, length: u64) -> StoreFuture<'_,()> {
+    Box::pin(async move { self.0.truncate(length) })
+  }
+  fn len (&mut self) -> StoreFuture<'_,u64> {
+    Box::pin(async move { self.0.len() })
+  }
+  fn sync_all (&mut self) -> StoreFuture<'_,()> {
+    Box::pin(async move { self.0.sync_all() })
+  }
+}
+
+fn async_storage (dir: PathBuf) -> impl Fn(&str) -> StoreFuture<'static,Disk> {
+  move |name: &str| {
+    let store = RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build();
+    Box::pin(async move { Ok(Disk(store?)) })
+  }
+}
+
+fn batch () -> Vec<Row<P,V>> {
+  let mut r = rand().seed([13,12]);
+  (0..1_200).map(|i| {
+    let x: f32 = r.read::<f32>()*2.0-1.0;
+    let y: f32 = r.read::<f32>()*2.0-1.0;
+    Row::Insert((x,y), i)
+  }).collect()
+}
+
+fn overloaded<T> (result: Result<T,Error>) -> bool {
+  match result {
+    Err(e) => e.downcast_ref::<Overloaded>().is_some(),
+    Ok(_) => false
+  }
+}
+
+#[test]
+fn reject_over_limit() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut db: DB<_,_,P,V> = Setup::new(|name: &str| {
+      Ok(RandomAccessDisk::builder(dir.path().join(name)).auto_sync(false).build()?)
+    })
+    .max_data_size(100)
+    .base_size(500)
+    .max_queries(2, Admission::Reject)
+    .build()?;
+  db.batch(&batch())?;
+  let bbox = ((-0.5,-0.5),(0.5,0.5));
+  let expected = db.query(&bbox)?.count();
+  assert_eq![db.queries_in_flight(), 0, "exhausted query releases its slot"];
+
+  let mut a = db.query(&bbox)?;
+  let b = db.query(&bbox)?;
+  assert_eq![db.queries_in_flight(), 2];
+  match db.query(&bbox) {
+    Err(e) => assert_eq![e.downcast_ref::<Overloaded>(), Some(&Overloaded { limit: 2 })],
+    Ok(_) => panic!["expected an Overloaded error"]
+  }
+  assert![a.next().is_some()];
+  drop(b);
+  assert_eq![db.queries_in_flight(), 1, "dropped query releases its slot"];
+  assert_eq![db.query(&bbox)?.count(), expected];
+  assert_eq![a.count() + 1, expected];
+  assert_eq![db.queries_in_flight(), 0];
+
+  // other reads take a slot too
+  let mut near = db.nearest_iter(&(0.0,0.0))?;
+  assert![near.next().is_some()];
+  let _b = db.query(&bbox)?;
+  assert_eq![db.queries_in_flight(), 2, "nearest_iter holds its slot"];
+  assert![overloaded(db.nearest(&(0.0,0.0), 3)), "nearest"];
+  assert![overloaded(db.top_k(&bbox, 3, |_,v| *v as f64)), "top_k"];
+  assert![overloaded(db.count(&bbox)), "count"];
+  assert![overloaded(db.aggregate(&bbox)), "aggregate"];
+  assert![overloaded(db.query_digest(&bbox)), "query_digest"];
+  drop(near);
+  assert_eq![db.count(&bbox)?, expected as u64];
+  assert_eq![db.queries_in_flight(), 1, "count releases its slot"];
+  Ok(())
+}
+
+#[test]
+fn queue_over_limit() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = async_storage(dir.path().to_path_buf());
+  let waker = noop_waker();
+  let mut cx = Context::from_waker(&waker);
+  let mut db: AsyncDB<_,_,P,V> = block_on(AsyncDB::open_from_setup(storage, |setup| {
+    setup.max_data_size(100).base_size(500).max_queries(1, Admission::Queue)
+  }))?;
+  block_on(db.batch(&batch()))?;
+  let bbox = ((-0.5,-0.5),(0.5,0.5));
+  let first = block_on(db.query(&bbox))?;
+  let sync_err = block_on(db.run(move |db| Ok(db.query(&bbox).is_err())))?;
+  assert![sync_err, "sync queries fail fast"];
+  {
+    let mut second: Pin<Box<dyn Future<Output=_>>> = Box::pin(db.query(&bbox));
+    assert![second.as_mut().poll(&mut cx).is_pending(), "waits for a slot"];
+    assert![second.as_mut().poll(&mut cx).is_pending()];
+    drop(first);
+    block_on(second)?;
+  }
+  assert_eq![block_on(db.run(|db| Ok(db.queries_in_flight())))?, 0];
+  Ok(())
+}
@@ -0,0 +1,66 @@
+use eyros::{Setup,DB,Row};
+use eyros::Error;
+use random::{Source,default as rand};
+use random_access_disk::RandomAccessDisk;
+use tempfile::Builder as Tmpfile;
+use std::alloc::{GlobalAlloc,Layout,System};
+use std::sync::atomic::{AtomicUsize,Ordering};
+
+// counts allocations made by every thread, so this file holds a single test
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+  unsafe fn alloc (&self, layout: Layout) -> *mut u8 {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    System.alloc(layout)
+  }
+  unsafe fn dealloc (&self, ptr: *mut u8, layout: Layout) {
+    System.dealloc(ptr, layout)
+  }
+  unsafe fn realloc (&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    System.realloc(ptr, layout, new_size)
+  }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn allocations () -> usize {
+  ALLOCATIONS.load(Ordering::Relaxed)
+}
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn allocations_per_row() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    Ok(RandomAccessDisk::builder(dir.path().join(name)).auto_sync(false).build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage).build()?;
+  let mut r = rand().seed([4,44]);
+  let n = 20_000;
+  let batch: Vec<Row<P,V>> = (0..n).map(|i| {
+    Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), i)
+  }).collect();
+
+  let before = allocations();
+  db.batch(&batch)?;
+  let build = allocations() - before;
+
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let before = allocations();
+  let rows = db.scan(&bbox)?.count();
+  let scan = allocations() - before;
+  assert_eq![rows, n as usize];
+
+  // rows are encoded into and parsed out of shared buffers, so the counts
+  // depend on the number of blocks rather than the number of rows
+  assert![build < n as usize / 10, "{} allocations to build {} rows", build, n];
+  assert![scan < n as usize / 100, "{} allocations to scan {} rows", scan, n];
+  Ok(())
+}
@@ -0,0 +1,308 @@
+use eyros::{Row,Trigger,AuditEvent,async_db::{AsyncDB,AsyncRandomAccess,StoreFuture}};
+use eyros::Error;
+use random::{Source,default as rand};
+use std::collections::HashMap;
+use std::future::{Future,poll_fn};
+use std::pin::Pin;
+use std::sync::{Arc,Mutex,atomic::{AtomicBool,AtomicU64,AtomicUsize,Ordering}};
+use std::task::{Context,Poll,RawWaker,RawWakerVTable,Waker};
+
+type P = (f32,f32);
+type V = u32;
+
+fn noop_waker () -> Waker {
+  fn raw () -> RawWaker {
+    fn clone (_: *const ()) -> RawWaker { raw() }
+    fn noop (_: *const ()) {}
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+    RawWaker::new(std::ptr::null(), &VTABLE)
+  }
+  unsafe { Waker::from_raw(raw()) }
+}
+
+fn block_on<F: Future> (mut f: F) -> F::Output {
+  let waker = noop_waker();
+  let mut cx = Context::from_waker(&waker);
+  let mut f = unsafe { Pin::new_unchecked(&mut f) };
+  loop {
+    if let Poll::Ready(x) = f.as_mut().poll(&mut cx) { return x }
+  }
+}
+
+// files in memory whose reads and writes complete once `ready` is set, like
+// requests to a slow disk
+#[derive(Clone,Default)]
+struct Disk {
+  files: Arc<Mutex<HashMap<String,Vec<u8>>>>,
+  ready: Arc<AtomicBool>,
+  // bytes read so far
+  read: Arc<AtomicU64>
+}
+
+impl Disk {
+  fn new () -> Self {
+    let disk = Self::default();
+    disk.ready.store(true, Ordering::SeqCst);
+    disk
+  }
+  fn storage (&self) -> impl Fn(&str) -> StoreFuture<'static,File> + Send + Sync {
+    let disk = self.clone();
+    move |name: &str| {
+      let file = File { name: name.to_string(), disk: disk.clone() };
+      Box::pin(async move { Ok(file) })
+    }
+  }
+}
+
+struct File {
+  name: String,
+  disk: Disk
+}
+
+impl File {
+  fn io<'a,T,F> (&'a mut self, f: F) -> StoreFuture<'a,T>
+  where T: Send+'a, F: FnOnce(&mut Vec<u8>) -> Result<T,failure::Error> + Send + 'a {
+    Box::pin(async move {
+      poll_fn(|cx| {
+        if self.disk.ready.load(Ordering::SeqCst) { return Poll::Ready(()) }
+        cx.waker().wake_by_ref();
+        Poll::Pending
+      }).await;
+      let mut files = self.disk.files.lock().unwrap();
+      f(files.entry(self.name.clone()).or_default())
+    })
+  }
+}
+
+impl AsyncRandomAccess for File {
+  fn write<'a> (&'a mut self, offset: u64, data: &'a [u8]) -> StoreFuture<'a,()> {
+    self.io(move |file| {
+      let end = offset as usize + data.len();
+      if file.len() < end { file.resize(end, 0) }
+      file[offset as usize..end].copy_from_slice(data);
+      Ok(())
+    })
+  }
+  fn read (&mut self, offset: u64, length: u64) -> StoreFuture<'_,Vec<u8>> {
+    self.disk.read.fetch_add(length, Ordering::SeqCst);
+    self.io(move |file| Ok(file[offset as usize..(offset+length) as usize].to_vec()))
+  }
+  fn truncate (&mut self, length: u64) -> StoreFuture<'_,()> {
+    self.io(move |file| Ok(file.resize(length as usize, 0)))
+  }
+  fn len (&mut self) -> StoreFuture<'_,u64> {
+    self.io(|file| Ok(file.len() as u64))
+  }
+  fn sync_all (&mut self) -> StoreFuture<'_,()> {
+    self.io(|_| Ok(()))
+  }
+}
+
+fn assert_send<T: Send> (_: &T) {}
+
+#[test]
+fn async_db() -> Result<(),Error> {
+  let disk = Disk::new();
+  let bbox = ((-0.5,-0.8),(0.3,0.5));
+  let mut r = rand().seed([13,12]);
+  let batch: Vec<Row<P,V>> = (0..1_200).map(|i| {
+    let x: f32 = r.read::<f32>()*2.0-1.0;
+    let y: f32 = r.read::<f32>()*2.0-1.0;
+    Row::Insert((x,y), i)
+  }).collect();
+  let expected = block_on(async {
+    let mut db: AsyncDB<_,_,P,V> = AsyncDB::open_from_setup(disk.storage(), |setup| {
+      setup.max_data_size(100).base_size(500)
+    }).await?;
+    let write = db.batch(&batch);
+    assert_send(&write);
+    write.await?;
+
+    let mut values = vec![];
+    let mut results = db.query(&bbox).await?;
+    while let Some(result) = results.next().await {
+      values.push(result?.1);
+    }
+    values.sort();
+    let mut expected: Vec<V> = db.run(move |db| {
+      db.query(&bbox)?.map(|r| r.map(|row| row.1)).collect()
+    }).await?;
+    expected.sort();
+    assert_eq![values, expected, "same results as the sync query"];
+    db.close().await?;
+    assert![db.batch(&batch).await.is_err(), "closed"];
+    Ok::<_,Error>(expected)
+  })?;
+
+  // the writes reached the async stores
+  assert![disk.files.lock().unwrap().get("data").map(|f| f.len()).unwrap_or(0) > 0];
+  block_on(async {
+    let mut db: AsyncDB<_,_,P,V> = AsyncDB::open_from_setup(disk.storage(), |setup| {
+      setup.max_data_size(100).base_size(500)
+    }).await?;
+    let mut values = vec![];
+    let mut results = db.query(&bbox).await?;
+    while let Some(result) = results.next().await {
+      values.push(result?.1);
+    }
+    values.sort();
+    assert_eq![values, expected, "same results after opening again"];
+    db.close().await
+  })
+}
+
+#[test]
+fn async_db_pending() -> Result<(),Error> {
+  let disk = Disk::default();
+  let waker = noop_waker();
+  let mut cx = Context::from_waker(&waker);
+
+  // calls wait on the store futures instead of blocking the polling thread
+  let mut open = Box::pin(AsyncDB::<_,_,P,V>::open(disk.storage()));
+  assert![open.as_mut().poll(&mut cx).is_pending()];
+  disk.ready.store(true, Ordering::SeqCst);
+  let mut db = block_on(open)?;
+
+  disk.ready.store(false, Ordering::SeqCst);
+  {
+    let mut write = Box::pin(db.batch(&[Row::Insert((0.1,0.2), 1)]));
+    assert![write.as_mut().poll(&mut cx).is_pending()];
+    assert![write.as_mut().poll(&mut cx).is_pending()];
+    disk.ready.store(true, Ordering::SeqCst);
+    block_on(write)?;
+  }
+  let mut results = block_on(db.query(&((0.0,0.0),(1.0,1.0))))?;
+  assert_eq![block_on(results.next()).transpose()?.map(|r| r.1), Some(1)];
+  assert![block_on(results.next()).is_none()];
+  block_on(db.close())
+}
+
+#[test]
+fn async_db_lazy() -> Result<(),Error> {
+  let disk = Disk::new();
+  let mut r = rand().seed([4,19]);
+  let mut rows: Vec<(P,V)> = vec![];
+  block_on(async {
+    let mut db: AsyncDB<_,_,P,V> = AsyncDB::open_from_setup(disk.storage(), |setup| {
+      setup.max_data_size(100).base_size(500)
+    }).await?;
+    for _ in 0..10 {
+      let batch: Vec<Row<P,V>> = (0..2_000).map(|_| {
+        let point = (r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0);
+        rows.push((point,rows.len() as V));
+        Row::Insert(point, rows.len() as V - 1)
+      }).collect();
+      db.batch(&batch).await?;
+    }
+    db.close().await
+  })?;
+  let total: usize = disk.files.lock().unwrap().values().map(|f| f.len()).sum();
+  let expected = |bbox: ((f32,f32),(f32,f32))| -> Vec<V> {
+    let ((x0,y0),(x1,y1)) = bbox;
+    rows.iter().filter(|((x,y),_)| *x >= x0 && *x <= x1 && *y >= y0 && *y <= y1)
+      .map(|(_,v)| *v).collect()
+  };
+
+  disk.read.store(0, Ordering::SeqCst);
+  block_on(async {
+    let mut db: AsyncDB<_,_,P,V> = AsyncDB::open_from_setup(disk.storage(), |setup| {
+      setup.max_data_size(100).base_size(500)
+    }).await?;
+    // opening only reads the blocks it touches
+    let read = disk.read.load(Ordering::SeqCst) as usize;
+    assert![read*10 < total, "read {} of {} bytes", read, total];
+    let bbox = ((0.1,0.1),(0.2,0.2));
+    let mut values = vec![];
+    let mut results = db.query(&bbox).await?;
+    while let Some(result) = results.next().await {
+      values.push(result?.1);
+    }
+    values.sort();
+    assert_eq![values, expected(bbox)];
+
+    // with no pages kept between calls, every call fetches what it reads
+    // again, and writes run again until they read only fetched pages
+    db.set_cache_bytes(0)?;
+    let all = ((-1.0,-1.0),(1.0,1.0));
+    let mut deletes = vec![];
+    let mut results = db.query(&all).await?;
+    while let Some(result) = results.next().await {
+      let (_,value,location) = result?;
+      if value % 3 == 0 { deletes.push(Row::Delete(location)) }
+    }
+    assert_eq![deletes.len(), expected(all).iter().filter(|v| *v % 3 == 0).count()];
+    db.batch(&deletes).await?;
+    let mut values = vec![];
+    let mut results = db.query(&all).await?;
+    while let Some(result) = results.next().await {
+      values.push(result?.1);
+    }
+    values.sort();
+    let kept: Vec<V> = expected(all).into_iter().filter(|v| v % 3 != 0).collect();
+    assert_eq![values, kept];
+    db.close().await
+  })
+}
+
+#[test]
+fn async_db_effects() -> Result<(),Error> {
+  let disk = Disk::new();
+  let mut r = rand().seed([7,31]);
+  let rows: Vec<Row<P,V>> = (0..4_000).map(|i| {
+    Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), i)
+  }).collect();
+  block_on(async {
+    let mut db: AsyncDB<_,_,P,V> = AsyncDB::open_from_setup(disk.storage(), |setup| {
+      setup.max_data_size(100).base_size(500).changes(true)
+    }).await?;
+    db.batch(&rows).await?;
+    db.close().await
+  })?;
+  block_on(async {
+    let mut db: AsyncDB<_,_,P,V> = AsyncDB::open_from_setup(disk.storage(), |setup| {
+      setup.max_data_size(100).base_size(500).changes(true)
+    }).await?;
+    let fired = Arc::new(AtomicUsize::new(0));
+    let audited = Arc::new(AtomicUsize::new(0));
+    let (f,a) = (Arc::clone(&fired), Arc::clone(&audited));
+    let all = ((-1.0,-1.0),(1.0,1.0));
+    let changes = db.run(move |db| {
+      let f = Arc::clone(&f);
+      db.add_trigger(Trigger::new("all").callback(move |_,_,_| {
+        f.fetch_add(1, Ordering::SeqCst);
+      }))?;
+      let a = Arc::clone(&a);
+      db.set_audit(move |_: &AuditEvent<P>| { a.fetch_add(1, Ordering::SeqCst); });
+      db.subscribe(&all)
+    }).await?;
+    // every call misses the pages it reads and runs again
+    db.set_cache_bytes(0)?;
+    let runs = AtomicUsize::new(0);
+    let bbox = ((-0.5,-0.5),(0.0,0.0));
+    let removed = db.run(|db| {
+      runs.fetch_add(1, Ordering::SeqCst);
+      db.delete_query(&bbox)
+    }).await?;
+    assert![runs.load(Ordering::SeqCst) > 1, "delete_query ran once"];
+    assert![removed > 0];
+    assert_eq![audited.load(Ordering::SeqCst), 1, "one audit event"];
+    assert_eq![changes.pending(), removed, "one notification per row"];
+
+    runs.store(0, Ordering::SeqCst);
+    let inserts: Vec<Row<P,V>> = (0..600).map(|i| {
+      Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), 10_000+i)
+    }).collect();
+    db.run(|db| {
+      runs.fetch_add(1, Ordering::SeqCst);
+      db.batch(&inserts)
+    }).await?;
+    assert![runs.load(Ordering::SeqCst) > 1, "batch ran once"];
+    assert_eq![fired.load(Ordering::SeqCst), inserts.len(), "one callback per row"];
+    assert_eq![changes.pending(), removed + inserts.len()];
+    let logged = db.run(|db| db.changes(0)?.collect::<Result<Vec<_>,Error>>()).await?;
+    assert_eq![logged.len(), rows.len() + removed + inserts.len(), "one change per row"];
+    db.close().await?;
+    assert![changes.is_closed()];
+    Ok(())
+  })
+}
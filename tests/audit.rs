@@ -0,0 +1,214 @@
+use eyros::{Setup,DB,Row,AuditOp,AuditEvent};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::sync::{Arc,Mutex};
+
+type P = (f32,f32);
+type V = u32;
+type B = ((f32,f32),(f32,f32));
+type Log = Arc<Mutex<Vec<(AuditOp,B,usize,Option<String>)>>>;
+type Open = Box<dyn Fn(&str) -> Result<RandomAccessDisk,failure::Error>>;
+type Db = DB<RandomAccessDisk,Open,P,V>;
+
+#[test]
+fn audit() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let points: Vec<P> = (0..2_000).map(|_| {
+    (r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0)
+  }).collect();
+  let batch: Vec<Row<P,V>> = points.iter().enumerate()
+    .map(|(i,p)| Row::Insert(*p,i as u32))
+    .collect();
+  db.batch(&batch)?;
+
+  let log: Log = Arc::default();
+  {
+    let log = Arc::clone(&log);
+    db.set_audit(move |event: &AuditEvent<P>| {
+      log.lock().unwrap().push((event.op, *event.bbox, event.rows,
+        event.context.map(|c| c.to_string())));
+    });
+  }
+  let inside = |bbox: &B| points.iter().filter(|p| {
+    (bbox.0).0 <= p.0 && p.0 <= (bbox.1).0 && (bbox.0).1 <= p.1 && p.1 <= (bbox.1).1
+  }).count();
+
+  let a = ((-0.5,-0.5),(0.0,0.0));
+  let n = db.query(&a)?.collect::<Result<Vec<_>,Error>>()?.len();
+  assert_eq![n, inside(&a)];
+
+  db.set_audit_context(Some("user=alice".into()));
+  let b = ((0.0,0.0),(0.5,0.5));
+  {
+    let mut iter = db.query(&b)?;
+    for _ in 0..10 { iter.next().unwrap()?; }
+    assert_eq![log.lock().unwrap().len(), 1, "lazy query reports when done"];
+  }
+  let c = ((0.5,0.5),(1.0,1.0));
+  let mut seen = 0;
+  db.query_for_each(&c, |_,_| {
+    seen += 1;
+    if seen < 20 { ControlFlow::Continue(()) } else { ControlFlow::Break(()) }
+  })?;
+  let d = ((-1.0,-1.0),(-0.5,0.0));
+  let removed = db.delete_query(&d)?;
+  assert_eq![removed, inside(&d)];
+
+  db.clear_audit();
+  db.query(&a)?.count();
+
+  let log = log.lock().unwrap();
+  let alice = Some("user=alice".to_string());
+  assert_eq![*log, vec![
+    (AuditOp::Query, a, inside(&a), None),
+    (AuditOp::Query, b, 10, alice.clone()),
+    (AuditOp::Query, c, 20, alice.clone()),
+    (AuditOp::Delete, d, removed, alice.clone()),
+  ]];
+  Ok(())
+}
+
+// database of random points that logs every audit event
+fn open(dir: &Path) -> Result<(Db,Vec<P>,Log),Error> {
+  let dir = dir.to_path_buf();
+  let open: Open = Box::new(move |name: &str| {
+    RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build()
+  });
+  let mut db: DB<_,_,P,V> = Setup::new(open)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let points: Vec<P> = (0..1_500).map(|_| {
+    (r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0)
+  }).collect();
+  let batch: Vec<Row<P,V>> = points.iter().enumerate()
+    .map(|(i,p)| Row::Insert(*p,i as u32))
+    .collect();
+  db.batch(&batch[0..1_400])?;
+  db.batch(&batch[1_400..])?; // some rows stay in staging
+  let log: Log = Arc::default();
+  {
+    let log = Arc::clone(&log);
+    db.set_audit(move |event: &AuditEvent<P>| {
+      log.lock().unwrap().push((event.op, *event.bbox, event.rows,
+        event.context.map(|c| c.to_string())));
+    });
+  }
+  Ok((db,points,log))
+}
+
+fn inside(points: &[P], bbox: &B) -> usize {
+  points.iter().filter(|p| {
+    (bbox.0).0 <= p.0 && p.0 <= (bbox.1).0 && (bbox.0).1 <= p.1 && p.1 <= (bbox.1).1
+  }).count()
+}
+
+fn bounds(points: &[P]) -> B {
+  points.iter().fold(((f32::INFINITY,f32::INFINITY),(f32::NEG_INFINITY,f32::NEG_INFINITY)),
+    |((x0,y0),(x1,y1)),p| ((x0.min(p.0),y0.min(p.1)),(x1.max(p.0),y1.max(p.1))))
+}
+
+const BBOX: B = ((-0.5,-0.5),(0.2,0.3));
+
+#[test]
+fn audit_scan() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let (mut db,points,log) = open(dir.path())?;
+  let n = db.scan(&BBOX)?.count();
+  assert_eq![n, inside(&points, &BBOX)];
+  assert_eq![*log.lock().unwrap(), vec![(AuditOp::Query, BBOX, n, None)]];
+  Ok(())
+}
+
+#[test]
+fn audit_nearest() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let (mut db,_,log) = open(dir.path())?;
+  let found: Vec<P> = db.nearest(&(0.1,0.2), 6)?.iter().map(|r| r.0).collect();
+  assert_eq![found.len(), 6];
+  assert_eq![*log.lock().unwrap(), vec![(AuditOp::Nearest, bounds(&found), 6, None)]];
+  Ok(())
+}
+
+#[test]
+fn audit_nearest_iter() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let (mut db,_,log) = open(dir.path())?;
+  drop(db.nearest_iter(&(0.1,0.2))?);
+  assert_eq![log.lock().unwrap().len(), 0, "nothing returned, nothing reported"];
+  let found = {
+    let mut iter = db.nearest_iter(&(-0.3,0.4))?;
+    let found = (0..4).map(|_| Ok(iter.next().unwrap()?.0))
+      .collect::<Result<Vec<P>,Error>>()?;
+    assert_eq![log.lock().unwrap().len(), 0, "lazy search reports when dropped"];
+    found
+  };
+  assert_eq![*log.lock().unwrap(), vec![(AuditOp::Nearest, bounds(&found), 4, None)]];
+  Ok(())
+}
+
+#[test]
+fn audit_top_k() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let (mut db,_,log) = open(dir.path())?;
+  let top = db.top_k(&BBOX, 9, |p,_| p.0 as f64)?;
+  assert_eq![top.len(), 9];
+  assert_eq![*log.lock().unwrap(), vec![(AuditOp::TopK, BBOX, 9, None)]];
+  Ok(())
+}
+
+#[test]
+fn audit_top_k_bounded() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let (mut db,_,log) = open(dir.path())?;
+  let top = db.top_k_bounded(&BBOX, 5, |p,_| p.1 as f64, |_| f64::INFINITY)?;
+  assert_eq![top.len(), 5];
+  assert_eq![*log.lock().unwrap(), vec![(AuditOp::TopK, BBOX, 5, None)]];
+  Ok(())
+}
+
+#[test]
+fn audit_count() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let (mut db,points,log) = open(dir.path())?;
+  let n = inside(&points, &BBOX);
+  assert_eq![db.count(&BBOX)?, n as u64];
+  assert_eq![*log.lock().unwrap(), vec![(AuditOp::Aggregate, BBOX, n, None)]];
+  Ok(())
+}
+
+#[test]
+fn audit_aggregate() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let (mut db,points,log) = open(dir.path())?;
+  let n = inside(&points, &BBOX);
+  assert_eq![db.aggregate(&BBOX)?.count, n as u64];
+  assert_eq![*log.lock().unwrap(), vec![(AuditOp::Aggregate, BBOX, n, None)]];
+  Ok(())
+}
+
+#[test]
+fn audit_query_digest() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let (mut db,points,log) = open(dir.path())?;
+  let n = inside(&points, &BBOX);
+  assert_eq![db.query_digest(&BBOX)?.rows, n as u64];
+  assert_eq![*log.lock().unwrap(), vec![(AuditOp::Digest, BBOX, n, None)]];
+  Ok(())
+}
@@ -0,0 +1,74 @@
+use eyros::{Setup,DB,Row,AutoTune,TuningState};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::path::PathBuf;
+
+type P = (f32,f32);
+type V = Vec<u8>;
+
+fn storage (dir: PathBuf) -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> {
+  move |name: &str| {
+    RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build()
+  }
+}
+
+#[test]
+fn auto_tune() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let setup = || Setup::new(storage(dir.path().to_path_buf()))
+    .max_data_size(100)
+    .base_size(500)
+    .auto_tune(2_000, AutoTune::Confirm);
+  let mut db: DB<_,_,P,V> = setup().build()?;
+  let mut r = rand().seed([13,12]);
+  // values of about 200 bytes make for blocks of about 300 rows
+  let mut rows: Vec<(P,V)> = (0..6_000).map(|i: u32| {
+    let p = (r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0);
+    let mut v = vec![0u8;196];
+    v.extend(&i.to_be_bytes());
+    (p,v)
+  }).collect();
+  let batch = |rows: &[(P,V)]| -> Vec<Row<P,V>> {
+    rows.iter().map(|(p,v)| Row::Insert(*p,v.clone())).collect()
+  };
+  db.batch(&batch(&rows[..1_000]))?;
+  assert![db.tuning().is_none()];
+  db.batch(&batch(&rows[1_000..2_000]))?;
+  let tuning = db.tuning().unwrap().clone();
+  assert_eq![tuning.rows, 2_000];
+  assert_eq![tuning.state, TuningState::Pending];
+  assert_eq![tuning.previous_max_data_size, 100];
+  assert![tuning.row_bytes > 200.0 && tuning.row_bytes < 230.0, "{:?}", tuning];
+  assert![tuning.max_data_size > 250 && tuning.max_data_size < 330, "{:?}", tuning];
+  assert_eq![tuning.row_extent, Some(0.0)];
+
+  // pending settings survive a reopen and don't change the block size
+  drop(db);
+  let mut db: DB<_,_,P,V> = setup().build()?;
+  assert_eq![db.tuning(), Some(&tuning)];
+  db.apply_tuning()?;
+  assert![db.apply_tuning().is_err()];
+  assert![db.reject_tuning().is_err()];
+
+  // new trees are written with the tuned block size, also after a reopen
+  drop(db);
+  let mut db: DB<_,_,P,V> = setup().build()?;
+  assert_eq![db.tuning().unwrap().state, TuningState::Applied];
+  db.batch(&batch(&rows[2_000..6_000]))?;
+  let stats = db.tree_stats()?;
+  let largest = stats.iter().max_by_key(|t| t.rows).unwrap();
+  assert_eq![largest.rows, 4_000];
+  // blocks of at most 100 rows would take 40 or more
+  assert![largest.data_blocks < 4_000/100, "{:?}", largest];
+
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let mut values: Vec<V> = db.query(&bbox)?
+    .map(|r| r.map(|(_,v,_)| v))
+    .collect::<Result<_,Error>>()?;
+  values.sort_unstable();
+  rows.sort_unstable_by(|a,b| a.1.cmp(&b.1));
+  assert_eq![values, rows.into_iter().map(|(_,v)| v).collect::<Vec<V>>()];
+  Ok(())
+}
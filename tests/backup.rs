@@ -0,0 +1,128 @@
+use eyros::{Setup,DB,Row};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::path::PathBuf;
+
+type P = (f32,f32);
+type V = u32;
+
+fn storage (dir: PathBuf) -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> {
+  move |name: &str| {
+    RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build()
+  }
+}
+
+fn values<S,U> (db: &mut DB<S,U,P,V>) -> Result<Vec<V>,Error> where
+S: random_access_storage::RandomAccess<Error=failure::Error>,
+U: Fn(&str) -> Result<S,failure::Error> {
+  let mut values = vec![];
+  for result in db.query(&((-1.0,-1.0),(1.0,1.0)))? {
+    values.push(result?.1);
+  }
+  values.sort_unstable();
+  Ok(values)
+}
+
+#[test]
+fn backup() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let backup_dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let setup = |dir: PathBuf| Setup::new(storage(dir))
+    .max_data_size(100)
+    .base_size(500)
+    .changes(true);
+  let mut db: DB<_,_,P,V> = setup(dir.path().to_path_buf()).build()?;
+  let mut r = rand().seed([13,12]);
+  let mut next_value = 0;
+  let mut batch = |n: usize| -> Vec<Row<P,V>> {
+    (0..n).map(|_| {
+      next_value += 1;
+      Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), next_value)
+    }).collect()
+  };
+  // trees and staged rows that haven't been built into a tree yet
+  db.batch(&batch(2_000))?;
+  db.batch(&batch(120))?;
+  db.delete_query(&((-0.5,-0.5),(-0.3,-0.3)))?;
+  let expected = values(&mut db)?;
+  let report = db.backup(storage(backup_dir.path().to_path_buf()))?;
+  assert_eq![report.last_change, db.last_change()?];
+  assert![report.stores.iter().any(|s| s == "tree0"), "stores: {:?}", report.stores];
+  assert![report.bytes > 0];
+
+  // the handle stays open for writes
+  db.batch(&batch(300))?;
+  let later = values(&mut db)?;
+  assert_eq![later.len(), expected.len() + 300];
+
+  {
+    let mut copy: DB<_,_,P,V> = setup(backup_dir.path().to_path_buf()).build()?;
+    assert_eq![values(&mut copy)?, expected, "copy holds the rows at the backup"];
+    assert_eq![copy.last_change()?, report.last_change];
+    copy.close()?;
+  }
+
+  // backing up over an earlier copy replaces it
+  let report = db.backup(storage(backup_dir.path().to_path_buf()))?;
+  let mut copy: DB<_,_,P,V> = setup(backup_dir.path().to_path_buf()).build()?;
+  assert_eq![values(&mut copy)?, later];
+  assert_eq![copy.last_change()?, report.last_change];
+  Ok(())
+}
+
+#[test]
+fn backup_closed() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let backup_dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut db: DB<_,_,P,V> = DB::open(storage(dir.path().to_path_buf()))?;
+  db.close()?;
+  assert![db.backup(storage(backup_dir.path().to_path_buf())).is_err()];
+  Ok(())
+}
+
+#[test]
+fn backup_during_writes() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let backup_dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let setup = |dir: PathBuf| Setup::new(storage(dir))
+    .max_data_size(100)
+    .base_size(500)
+    .changes(true);
+  let mut db: DB<_,_,P,V> = setup(dir.path().to_path_buf()).build()?;
+  let mut r = rand().seed([13,12]);
+  let rows: Vec<Row<P,V>> = (0..2_000).map(|i| {
+    Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), i)
+  }).collect();
+  db.batch(&rows)?;
+  let expected = values(&mut db)?;
+  let last_change = db.last_change()?;
+
+  let backup = db.begin_backup(storage(backup_dir.path().to_path_buf()))?;
+  // updates and deletes rewrite rows of blocks that the backup hasn't copied
+  let updates: Vec<Row<P,V>> = db.query(&((-1.0,-1.0),(1.0,1.0)))?
+    .filter_map(|r| r.ok())
+    .filter(|(_,v,_)| v % 10 == 0)
+    .map(|(p,v,loc)| Row::Update(loc, p, v + 100_000))
+    .collect();
+  db.batch(&updates)?;
+  db.delete_query(&((-0.5,-0.5),(0.0,0.0)))?;
+  db.batch(&rows[..300].iter().map(|row| match row {
+    Row::Insert(p,v) => Row::Insert(*p, v + 10_000),
+    _ => panic!["insert"]
+  }).collect::<Vec<_>>())?;
+  assert![db.compact().is_err(), "compaction waits for the backup"];
+  assert![db.begin_backup(storage(backup_dir.path().to_path_buf())).is_err(),
+    "one backup at a time"];
+  let report = backup.run()?;
+  assert_eq![report.last_change, last_change];
+  assert_ne![values(&mut db)?, expected];
+  db.compact()?;
+
+  let mut copy: DB<_,_,P,V> = setup(backup_dir.path().to_path_buf()).build()?;
+  assert_eq![values(&mut copy)?, expected, "copy holds the rows at the backup"];
+  assert_eq![copy.last_change()?, last_change];
+  assert_eq![copy.check()?.problems.len(), 0];
+  Ok(())
+}
@@ -0,0 +1,48 @@
+use eyros::{Setup,DB,Row};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use tempfile::Builder as Tmpfile;
+use std::time::Duration;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn block_heat() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let (setup,clock) = Setup::new(|name: &str| {
+      let p = dir.path().join(name);
+      Ok(RandomAccessDisk::builder(p)
+        .auto_sync(false)
+        .build()?)
+    })
+    .max_data_size(100)
+    .base_size(500)
+    .track_heat(Duration::from_secs(60))
+    .deterministic(3);
+  let mut db: DB<_,_,P,V> = setup.build()?;
+  let batch: Vec<Row<P,V>> = (0..1_000).map(|i| {
+    Row::Insert(((i as f32)/1000.0,0.0), i)
+  }).collect();
+  db.batch(&batch)?;
+  assert_eq![db.block_heat()?, vec![], "nothing read yet"];
+
+  let all = ((-1.0,-1.0),(1.0,1.0));
+  let hot = ((0.0,-1.0),(0.05,1.0));
+  db.query(&all)?.collect::<Result<Vec<_>,Error>>()?;
+  for _ in 0..3 {
+    db.query(&hot)?.collect::<Result<Vec<_>,Error>>()?;
+  }
+  let heat = db.block_heat()?;
+  assert![heat.len() > 1, "every block was read"];
+  assert![heat[0].1 > heat[heat.len()-1].1, "hot blocks score higher"];
+  let (hot_offset,hot_score) = heat[0];
+  let cold_score = heat[heat.len()-1].1;
+  assert_eq![cold_score, 1.0];
+
+  clock.advance(Duration::from_secs(60));
+  let heat = db.block_heat()?;
+  let score = heat.iter().find(|(o,_)| *o == hot_offset).unwrap().1;
+  assert_eq![score, hot_score/2.0, "scores halve after the half-life"];
+  Ok(())
+}
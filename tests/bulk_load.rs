@@ -0,0 +1,97 @@
+use eyros::{Setup,DB,Row,BulkLoader};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::path::PathBuf;
+
+type P = (f32,f32);
+type V = u32;
+
+fn storage (dir: PathBuf) -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> {
+  move |name: &str| {
+    RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build()
+  }
+}
+
+fn values<S,U> (db: &mut DB<S,U,P,V>, bbox: &((f32,f32),(f32,f32)))
+-> Result<Vec<V>,Error> where
+S: random_access_storage::RandomAccess<Error=failure::Error>,
+U: Fn(&str) -> Result<S,failure::Error> {
+  let mut values = vec![];
+  for result in db.query(bbox)? {
+    values.push(result?.1);
+  }
+  values.sort_unstable();
+  Ok(values)
+}
+
+#[test]
+fn bulk_load() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let setup = |dir: PathBuf| Setup::new(storage(dir))
+    .max_data_size(100)
+    .base_size(500);
+  let mut db: DB<_,_,P,V> = setup(dir.path().to_path_buf()).build()?;
+  let mut r = rand().seed([13,12]);
+  let rows: Vec<(P,V)> = (0..20_000).map(|i| {
+    ((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), i)
+  }).collect();
+  let mut loader = BulkLoader::new(&mut db)?;
+  loader.extend(rows.iter().cloned());
+  assert_eq![loader.len(), 20_000];
+  let report = loader.finish()?;
+  assert_eq![report.rows, 20_000];
+  assert_eq![report.blocks, 200];
+  // 500 << 6 is the smallest size class that holds 20k rows
+  assert_eq![report.tree, Some(6)];
+
+  let bboxes = [
+    ((-1.0,-1.0),(1.0,1.0)),
+    ((-0.5,-0.8),(0.3,-0.5)),
+    ((0.2,0.2),(0.25,0.9))
+  ];
+  for bbox in bboxes.iter() {
+    let mut expected: Vec<V> = rows.iter()
+      .filter(|((x,y),_)| {
+        (bbox.0).0 <= *x && *x <= (bbox.1).0 && (bbox.0).1 <= *y && *y <= (bbox.1).1
+      })
+      .map(|(_,v)| *v)
+      .collect();
+    expected.sort_unstable();
+    assert_eq![values(&mut db, bbox)?, expected];
+  }
+
+  // a small query reads fewer blocks than it does after loading the same
+  // rows with a batch() at a time
+  let batch_dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut batch_db: DB<_,_,P,V> = setup(batch_dir.path().to_path_buf()).build()?;
+  for chunk in rows.chunks(1_000) {
+    batch_db.batch(&chunk.iter().map(|(p,v)| Row::Insert(*p,*v)).collect::<Vec<_>>())?;
+  }
+  let bulk_cost = db.estimate_cost(&bboxes[2])?;
+  let batch_cost = batch_db.estimate_cost(&bboxes[2])?;
+  assert![bulk_cost.blocks < batch_cost.blocks, "{} >= {} blocks",
+    bulk_cost.blocks, batch_cost.blocks];
+
+  // the loaded tree takes part in later batches and survives a reopen
+  let more: Vec<Row<P,V>> = (0..1_000).map(|i| {
+    Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), 100_000+i)
+  }).collect();
+  db.batch(&more)?;
+  assert_eq![values(&mut db, &bboxes[0])?.len(), 21_000];
+  db.close()?;
+  let mut db: DB<_,_,P,V> = setup(dir.path().to_path_buf()).build()?;
+  assert_eq![values(&mut db, &bboxes[0])?.len(), 21_000];
+  Ok(())
+}
+
+#[test]
+fn bulk_load_changes() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut db: DB<_,_,P,V> = Setup::new(storage(dir.path().to_path_buf()))
+    .changes(true)
+    .build()?;
+  assert![BulkLoader::new(&mut db).is_err()];
+  Ok(())
+}
@@ -0,0 +1,72 @@
+use eyros::{Setup,DB,Row};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = Vec<u8>;
+
+#[test]
+fn cache_bytes() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  };
+  let budget = 200_000;
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .data_list_cache_size(100_000)
+    .cache_bytes(budget)
+    .build()?;
+  let mut r = rand().seed([5,9]);
+  // a few big values among many small ones
+  let batch: Vec<Row<P,V>> = (0..3_000).map(|i| {
+    let len = if i % 10 == 0 { 1_000 } else { 8 };
+    Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), vec![7;len])
+  }).collect();
+  db.batch(&batch)?;
+
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let rows = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?;
+  assert_eq![rows.len(), 3_000];
+  let (blocks,bytes) = {
+    let dstore = db.data_store.read().unwrap();
+    (dstore.cached_blocks(), dstore.cached_bytes())
+  };
+  assert![blocks > 0];
+  assert![bytes as u64 <= budget, "{} bytes cached, budget {}", bytes, budget];
+  // 300 values of 1000 bytes don't fit, so some blocks were evicted
+  assert![bytes as u64 > budget / 4, "{} bytes cached", bytes];
+  let rows = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?;
+  assert_eq![rows.len(), 3_000];
+  assert![db.data_store.read().unwrap().cached_bytes() as u64 <= budget];
+
+  // deletes shrink the estimate of cached blocks
+  let deletes: Vec<Row<P,V>> = rows.iter()
+    .filter(|row| row.1.len() > 8)
+    .map(|row| Row::Delete(row.2))
+    .collect();
+  db.batch(&deletes)?;
+  let rows = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?;
+  assert_eq![rows.len(), 2_700];
+  assert![db.data_store.read().unwrap().cached_bytes() as u64 <= budget];
+
+  let unbounded_dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut db: DB<_,_,P,V> = Setup::new(|name: &str| {
+    let p = unbounded_dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  })
+    .max_data_size(100)
+    .base_size(500)
+    .data_list_cache_size(100_000)
+    .build()?;
+  db.batch(&batch)?;
+  assert_eq![db.query(&bbox)?.count(), 3_000];
+  let dstore = db.data_store.read().unwrap();
+  assert![dstore.cached_blocks() > blocks, "{} <= {}", dstore.cached_blocks(), blocks];
+  assert![dstore.cached_bytes() as u64 > budget];
+  Ok(())
+}
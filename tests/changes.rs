@@ -0,0 +1,85 @@
+use eyros::{Setup,DB,Row,Change};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::path::PathBuf;
+
+type P = (f32,f32);
+type V = u32;
+
+fn storage (dir: PathBuf) -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> {
+  move |name: &str| {
+    Ok(RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build()?)
+  }
+}
+
+fn values<S,U> (db: &mut DB<S,U,P,V>) -> Result<Vec<V>,Error> where
+S: random_access_storage::RandomAccess<Error=failure::Error>,
+U: Fn(&str) -> Result<S,failure::Error> {
+  let mut values = vec![];
+  for result in db.query(&((-1.0,-1.0),(1.0,1.0)))? {
+    values.push(result?.1);
+  }
+  values.sort();
+  Ok(values)
+}
+
+#[test]
+fn changes() -> Result<(),Error> {
+  let adir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let bdir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut primary: DB<_,_,P,V> = Setup::new(storage(adir.path().to_path_buf()))
+    .max_data_size(100)
+    .base_size(300)
+    .changes(true)
+    .build()?;
+  let mut replica: DB<_,_,P,V> = Setup::new(storage(bdir.path().to_path_buf()))
+    .max_data_size(100)
+    .base_size(300)
+    .build()?;
+  assert![replica.changes(0).is_err(), "feed is off by default"];
+
+  let mut r = rand().seed([13,12]);
+  let mut next_value = 0;
+  let mut since = 0;
+  for round in 0..4 {
+    let inserts: Vec<Row<P,V>> = (0..250).map(|_| {
+      next_value += 1;
+      let x: f32 = r.read::<f32>()*2.0-1.0;
+      Row::Insert((x, r.read::<f32>()*2.0-1.0), next_value)
+    }).collect();
+    primary.batch(&inserts)?;
+    // delete some rows by location and some by region
+    let deletes: Vec<Row<P,V>> = primary.query(&((-0.2,-0.2),(0.0,0.0)))?
+      .take(if round == 0 { 10 } else { 0 })
+      .map(|row| row.map(|(_,_,loc)| Row::Delete(loc)))
+      .collect::<Result<_,Error>>()?;
+    primary.batch(&deletes)?;
+    let removed = primary.delete_query(&((0.5,0.5),(0.6,0.6)))?;
+
+    let changes = primary.changes(since)?.collect::<Result<Vec<_>,Error>>()?;
+    assert_eq![changes.len(), 250 + deletes.len() + removed, "round {}", round];
+    assert_eq![changes.first().map(|c| c.seq), Some(since+1)];
+    assert_eq![changes.last().map(|c| c.seq), Some(primary.last_change()?)];
+    let deleted = changes.iter().filter(|c| match c.change {
+      Change::Delete(..) => true,
+      _ => false
+    }).count();
+    assert_eq![deleted, deletes.len() + removed];
+    replica.apply_changes(&changes)?;
+    since = changes.last().unwrap().seq;
+    assert_eq![values(&mut replica)?, values(&mut primary)?, "round {}", round];
+  }
+  assert_eq![primary.changes(since)?.count(), 0, "caught up"];
+
+  drop(primary);
+  let primary: DB<_,_,P,V> = Setup::new(storage(adir.path().to_path_buf()))
+    .max_data_size(100)
+    .base_size(300)
+    .changes(true)
+    .build()?;
+  assert_eq![primary.last_change()?, since, "feed persists"];
+  assert_eq![primary.changes(0)?.count() as u64, since];
+  Ok(())
+}
@@ -0,0 +1,78 @@
+use eyros::{Setup,DB,Row,CheckProblem};
+use eyros::Error;
+use random::{Source,default as rand};
+use random_access_disk::RandomAccessDisk;
+use tempfile::Builder as Tmpfile;
+use std::path::Path;
+
+type P = (f32,f32);
+type V = u32;
+type Storage = Box<dyn Fn(&str) -> Result<RandomAccessDisk,failure::Error>>;
+
+fn open (dir: &Path) -> Result<DB<RandomAccessDisk,Storage,P,V>,Error> {
+  let dir = dir.to_path_buf();
+  let storage: Storage = Box::new(move |name| {
+    RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build()
+  });
+  Setup::new(storage).max_data_size(100).base_size(500).build()
+}
+
+#[test]
+fn check() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let offset = {
+    let mut db = open(dir.path())?;
+    let mut r = rand().seed([8,88]);
+    let batch: Vec<Row<P,V>> = (0..1_500).map(|i| {
+      Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), i)
+    }).collect();
+    db.batch(&batch)?;
+    let report = db.check()?;
+    assert![report.is_ok(), "{:?}", report.problems];
+    assert![report.trees >= 1 && report.blocks >= 15];
+    let row = db.query(&bbox)?.find(|r| r.as_ref().map(|r| (r.2).0 > 0).unwrap_or(true))
+      .unwrap()?;
+    db.close()?;
+    (row.2).0 - 1
+  };
+
+  // flip a byte in the rows of a block, past its header and checksum
+  let data = dir.path().join("data");
+  let mut buf = std::fs::read(&data)?;
+  buf[offset as usize + 40] ^= 0xff;
+  std::fs::write(&data, buf)?;
+  let mut db = open(dir.path())?;
+  let report = db.check()?;
+  assert_eq![report.problems.len(), 1];
+  match &report.problems[0] {
+    CheckProblem::Block { offset: o, .. } => assert_eq![*o, offset],
+    p => panic!["unexpected problem: {}", p]
+  }
+  assert_eq![db.repair()?, report];
+  assert_eq![db.quarantined()?, vec![offset]];
+  let report = db.check()?;
+  assert![report.is_ok()];
+  assert_eq![report.quarantined, vec![offset]];
+  let rows = db.query(&bbox)?.count() as u64;
+  db.close()?;
+
+  // lose the range store
+  std::fs::write(dir.path().join("range"), [])?;
+  let mut db = open(dir.path())?;
+  let report = db.check()?;
+  assert_eq![report.blocks, report.problems.len() as u64];
+  assert![report.problems.iter().all(|p| matches![p, CheckProblem::MissingRange { .. }])];
+  db.repair()?;
+  assert![db.check()?.is_ok()];
+  assert_eq![db.count(&bbox)?, rows];
+  assert_eq![db.query(&bbox)?.count() as u64, rows];
+
+  // repair() writes, so read-only handles can only check
+  let mut ro: DB<_,_,P,V> = Setup::new(|name: &str| {
+    RandomAccessDisk::builder(dir.path().join(name)).auto_sync(false).build()
+  }).read_only(true).build()?;
+  assert![ro.check()?.is_ok()];
+  assert![ro.repair().is_err()];
+  Ok(())
+}
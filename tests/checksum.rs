@@ -0,0 +1,129 @@
+use eyros::{Setup,DB,Row,Location};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random_access_storage::RandomAccess;
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = u32;
+
+fn rows () -> Vec<Row<P,V>> {
+  (0..1_000).map(|i| Row::Insert(((i as f32)/1000.0,0.5), i)).collect()
+}
+
+// flip the bits of the last byte of the block at `block`
+fn corrupt (store: &mut RandomAccessDisk, block: u64) -> Result<(),Error> {
+  let len = store.read(block, 4)?;
+  let len = u32::from_be_bytes([len[0],len[1],len[2],len[3]]) as u64;
+  let b = store.read(block+len-1, 1)?[0];
+  store.write(block+len-1, &[!b])?;
+  store.sync_all()?;
+  Ok(())
+}
+
+#[test]
+fn checksum_mismatch() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  };
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let full: Vec<(P,V,Location)> = {
+    let mut db: DB<_,_,P,V> = Setup::new(&storage)
+      .max_data_size(100)
+      .base_size(500)
+      .build()?;
+    db.batch(&rows())?;
+    db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?
+  };
+  assert_eq![full.len(), 1_000];
+  let block = (full[0].2).0 - 1;
+  corrupt(&mut storage("data")?, block)?;
+  let expected = full.iter().filter(|row| (row.2).0 != block+1).count();
+  {
+    let mut db: DB<_,_,P,V> = Setup::new(&storage)
+      .max_data_size(100)
+      .base_size(500)
+      .block_checksums(false)
+      .build()?;
+    let results = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?;
+    assert_eq![results.len(), 1_000, "checksums not verified"];
+    assert_eq![db.quarantined()?, Vec::<u64>::new()];
+  }
+  {
+    let mut db: DB<_,_,P,V> = Setup::new(&storage)
+      .max_data_size(100)
+      .base_size(500)
+      .build()?;
+    let results = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?;
+    assert_eq![results.len(), expected, "corrupt block skipped"];
+    assert_eq![db.quarantined()?, vec![block], "corrupt block quarantined"];
+  }
+  Ok(())
+}
+
+#[test]
+fn checksum_after_update() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  };
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  {
+    let mut db: DB<_,_,P,V> = Setup::new(&storage)
+      .max_data_size(100)
+      .base_size(500)
+      .build()?;
+    db.batch(&rows())?;
+    let updates: Vec<Row<P,V>> = db.query(&bbox)?
+      .filter_map(|r| r.ok())
+      .filter(|(_,v,_)| v % 10 == 0)
+      .map(|(p,v,loc)| Row::Update(loc, p, v + 1_000))
+      .collect();
+    assert_eq![updates.len(), 100];
+    db.batch(&updates)?;
+  }
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let results = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?;
+  assert_eq![db.quarantined()?, Vec::<u64>::new(), "updated blocks pass the checksum"];
+  assert_eq![results.len(), 1_000];
+  assert_eq![results.iter().filter(|(_,v,_)| *v >= 1_000).count(), 100];
+  Ok(())
+}
+
+#[test]
+fn checksum_legacy_blocks() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  };
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  {
+    let mut db: DB<_,_,P,V> = Setup::new(&storage)
+      .max_data_size(100)
+      .base_size(500)
+      .block_checksums(false)
+      .build()?;
+    db.batch(&rows())?;
+  }
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let results = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?;
+  assert_eq![results.len(), 1_000, "blocks without checksums are readable"];
+  assert_eq![db.quarantined()?, Vec::<u64>::new()];
+  let more: Vec<Row<P,V>> = (0..500).map(|i| {
+    Row::Insert(((i as f32)/500.0,0.25), 1_000+i)
+  }).collect();
+  db.batch(&more)?;
+  let results = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?;
+  assert_eq![results.len(), 1_500, "old and new blocks read side by side"];
+  Ok(())
+}
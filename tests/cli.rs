@@ -0,0 +1,70 @@
+#![cfg(feature="cli")]
+use eyros::Error;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::process::{Command,Output};
+
+fn eyros (args: &[&str]) -> Result<Output,Error> {
+  Ok(Command::new(env!("CARGO_BIN_EXE_eyros")).args(args).output()?)
+}
+
+fn stdout (output: &Output) -> String {
+  assert![output.status.success(), "{}", String::from_utf8_lossy(&output.stderr)];
+  String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn cli() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let db = dir.path().join("db");
+  let db = db.to_str().unwrap();
+  let csv = dir.path().join("rows.csv");
+
+  // more rows than the default base size, so that a tree is built
+  let mut r = rand().seed([9,99]);
+  let rows: Vec<(f32,f32,String)> = (0..10_000).map(|i| {
+    (r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0, format!["row {}, #{}", i, i])
+  }).collect();
+  let mut body = "x,y,name\n".to_string();
+  for (x,y,name) in rows.iter() {
+    body.push_str(&format!["{},{},\"{}\"\n", x, y, name]);
+  }
+  std::fs::write(&csv, body)?;
+
+  // reading a missing database fails without creating it
+  assert![!eyros(&["info", db])?.status.success()];
+  assert![!dir.path().join("db").exists()];
+
+  let output = eyros(&["import-csv", db, csv.to_str().unwrap(),
+    "--value", "text", "--skip-header", "--batch-size", "1000"])?;
+  stdout(&output);
+  assert_eq![String::from_utf8_lossy(&output.stderr).trim(), "imported 10000 rows"];
+
+  // dump prints rows back as csv that import-csv reads
+  let dump = stdout(&eyros(&["dump", db, "--value", "text"])?);
+  let mut dumped: Vec<&str> = dump.lines().collect();
+  dumped.sort();
+  let mut expected: Vec<String> = rows.iter()
+    .map(|(x,y,name)| format!["{},{},\"{}\"", x, y, name]).collect();
+  expected.sort();
+  assert_eq![dumped, expected];
+
+  let inside = |(x,y,_): &&(f32,f32,String)| {
+    -0.5 <= *x && *x <= 0.3 && -0.2 <= *y && *y <= 0.6
+  };
+  let query = stdout(&eyros(&["query", db, "-0.5,-0.2,0.3,0.6", "--value", "text"])?);
+  assert_eq![query.lines().count(), rows.iter().filter(inside).count()];
+  assert![!eyros(&["query", db, "-0.5,-0.2,0.3", "--value", "text"])?.status.success()];
+
+  let info = stdout(&eyros(&["info", db, "--value", "text"])?);
+  assert![info.lines().any(|l| l.starts_with("tree "))];
+  let verify = stdout(&eyros(&["verify", db, "--value", "text"])?);
+  assert![verify.contains("10000 rows readable")];
+  assert_eq![verify.lines().last(), Some("ok")];
+  let repair = stdout(&eyros(&["repair", db, "--value", "text"])?);
+  assert_eq![repair.trim(), "0 problems found, 0 quarantined data blocks"];
+
+  assert![!eyros(&["dump", db, "--point", "f16x2"])?.status.success()];
+  assert![!eyros(&["frobnicate", db])?.status.success()];
+  Ok(())
+}
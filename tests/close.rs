@@ -0,0 +1,136 @@
+use eyros::{Setup,DB,Row,Closed,Trigger};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random_access_storage::RandomAccess;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::collections::BTreeSet;
+use std::io;
+use std::sync::{Arc,Mutex};
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn close() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let mut r = rand().seed([13,12]);
+  let batch: Vec<Row<P,V>> = (0..1_200).map(|i| {
+    let x: f32 = r.read::<f32>()*2.0-1.0;
+    let y: f32 = r.read::<f32>()*2.0-1.0;
+    Row::Insert((x,y), i)
+  }).collect();
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  {
+    let mut db: DB<_,_,P,V> = Setup::new(&storage)
+      .max_data_size(100)
+      .base_size(500)
+      .build()?;
+    db.batch(&batch)?;
+    assert![!db.is_closed()];
+    db.close()?;
+    assert![db.is_closed()];
+    db.close()?;
+
+    let err = db.query(&bbox).err().expect("query after close fails");
+    assert![err.downcast_ref::<Closed>().is_some(), "query: {}", err];
+    let err = db.batch(&batch).err().expect("batch after close fails");
+    assert![err.downcast_ref::<Closed>().is_some(), "batch: {}", err];
+  }
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let results = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?;
+  assert_eq![results.len(), 1_200, "reopened after close"];
+  Ok(())
+}
+
+// records the names of stores written since they were last synced
+struct Tracked {
+  name: String,
+  store: RandomAccessDisk,
+  unsynced: Arc<Mutex<BTreeSet<String>>>
+}
+
+impl Tracked {
+  fn touch (&self) {
+    self.unsynced.lock().unwrap().insert(self.name.clone());
+  }
+}
+
+impl RandomAccess for Tracked {
+  type Error = failure::Error;
+  fn write (&mut self, offset: u64, data: &[u8]) -> Result<(),failure::Error> {
+    self.touch();
+    self.store.write(offset, data)
+  }
+  fn read (&mut self, offset: u64, length: u64) -> Result<Vec<u8>,failure::Error> {
+    self.store.read(offset, length)
+  }
+  fn read_to_writer (&mut self, offset: u64, length: u64,
+  buf: &mut impl io::Write) -> Result<(),failure::Error> {
+    self.store.read_to_writer(offset, length, buf)
+  }
+  fn del (&mut self, offset: u64, length: u64) -> Result<(),failure::Error> {
+    self.touch();
+    self.store.del(offset, length)
+  }
+  fn truncate (&mut self, length: u64) -> Result<(),failure::Error> {
+    self.touch();
+    self.store.truncate(length)
+  }
+  fn len (&self) -> Result<u64,failure::Error> {
+    self.store.len()
+  }
+  fn is_empty (&mut self) -> Result<bool,failure::Error> {
+    self.store.is_empty()
+  }
+  fn sync_all (&mut self) -> Result<(),failure::Error> {
+    self.unsynced.lock().unwrap().remove(&self.name);
+    self.store.sync_all()
+  }
+}
+
+#[test]
+fn close_syncs() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let root = dir.path().to_path_buf();
+  let unsynced = Arc::new(Mutex::new(BTreeSet::new()));
+  let tracked = Arc::clone(&unsynced);
+  let mut db: DB<_,_,P,V> = Setup::new(move |name: &str| {
+      Ok(Tracked {
+        name: name.to_string(),
+        store: RandomAccessDisk::builder(root.join(name))
+          .auto_sync(false)
+          .build()?,
+        unsynced: Arc::clone(&tracked)
+      })
+    })
+    .max_data_size(100)
+    .base_size(500)
+    .wal(true)
+    .changes(true)
+    .build()?;
+  db.add_trigger(Trigger::new("all").outbox())?;
+  db.create_view("west", ((-1.0,-1.0),(0.0,1.0)))?;
+  let mut r = rand().seed([13,12]);
+  for n in [300,1_200] {
+    let batch: Vec<Row<P,V>> = (0..n).map(|i| {
+      let x: f32 = r.read::<f32>()*2.0-1.0;
+      let y: f32 = r.read::<f32>()*2.0-1.0;
+      Row::Insert((x,y), i)
+    }).collect();
+    db.batch(&batch)?;
+  }
+  db.close()?;
+  let unsynced = unsynced.lock().unwrap();
+  assert![unsynced.is_empty(), "written but not synced: {:?}", *unsynced];
+  Ok(())
+}
@@ -0,0 +1,61 @@
+use eyros::{Setup,DB,Row};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::path::PathBuf;
+use std::ops::ControlFlow;
+
+type P = (f32,f32);
+type V = u32;
+type Open = Box<dyn Fn(&str) -> Result<RandomAccessDisk,failure::Error>>;
+
+fn open (path: PathBuf, span: u64) -> Result<DB<RandomAccessDisk,Open,P,V>,Error> {
+  let storage: Open = Box::new(move |name: &str| {
+    Ok(RandomAccessDisk::builder(path.join(name)).auto_sync(false).build()?)
+  });
+  Setup::new(storage)
+    .max_data_size(20)
+    .base_size(500)
+    .coalesce_branches(span)
+    .build()
+}
+
+#[test]
+fn coalesce() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut r = rand().seed([5,31]);
+  let rows: Vec<(P,V)> = (0..5_000).map(|i| {
+    ((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), i)
+  }).collect();
+  {
+    let mut db = open(dir.path().to_path_buf(), 0)?;
+    let batch: Vec<Row<P,V>> = rows.iter().map(|(p,v)| Row::Insert(*p,*v)).collect();
+    db.batch(&batch)?;
+  }
+  let bbox = ((-0.6,-0.6),(0.6,0.4));
+  let inside = |p: &P| -0.6 <= p.0 && p.0 <= 0.6 && -0.6 <= p.1 && p.1 <= 0.4;
+  let mut expected: Vec<V> = rows.iter().filter(|(p,_)| inside(p)).map(|r| r.1).collect();
+  expected.sort();
+
+  let mut reads = vec![];
+  for span in [0,16_384,65_536].iter() {
+    // a fresh handle for each span so that no blocks are cached
+    let mut db = open(dir.path().to_path_buf(), *span)?;
+    db.reset_stats()?;
+    let mut values: Vec<V> = db.query(&bbox)?.map(|r| r.map(|r| r.1))
+      .collect::<Result<Vec<V>,Error>>()?;
+    values.sort();
+    assert_eq![values, expected, "span {}", span];
+    let mut count = 0;
+    db.query_for_each(&bbox, |_,_| {
+      count += 1;
+      ControlFlow::Continue(())
+    })?;
+    assert_eq![count, expected.len()];
+    reads.push(db.stats()?.reads);
+  }
+  assert![reads[1] < reads[0], "coalesced {} reads, plain {}", reads[1], reads[0]];
+  assert![reads[2] <= reads[1], "64K span {} reads, 16K span {}", reads[2], reads[1]];
+  Ok(())
+}
@@ -0,0 +1,89 @@
+use eyros::{Setup,DB,Row,Codec,Location};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = u32;
+
+// little-endian x, y, value
+struct FixedWidth;
+impl Codec<P,V> for FixedWidth {
+  fn serialize (&self, row: &(P,V)) -> Result<Vec<u8>,Error> {
+    let ((x,y),v) = row;
+    let mut buf = Vec::with_capacity(12);
+    buf.extend(&x.to_le_bytes());
+    buf.extend(&y.to_le_bytes());
+    buf.extend(&v.to_le_bytes());
+    Ok(buf)
+  }
+  fn deserialize (&self, buf: &[u8]) -> Result<(usize,(P,V)),Error> {
+    if buf.len() < 12 {
+      return Err(Error::Corrupt("row is too short".into()));
+    }
+    let f = |i: usize| [buf[i],buf[i+1],buf[i+2],buf[i+3]];
+    let (x,y) = (f32::from_le_bytes(f(0)), f32::from_le_bytes(f(4)));
+    Ok((12,((x,y),u32::from_le_bytes(f(8)))))
+  }
+  fn take_bytes (&self, _buf: &[u8]) -> Result<usize,Error> {
+    Ok(12)
+  }
+}
+
+fn sorted (rows: Vec<(P,V,Location)>) -> Vec<V> {
+  let mut values: Vec<V> = rows.into_iter().map(|r| r.1).collect();
+  values.sort();
+  values
+}
+
+#[test]
+fn codec() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  };
+  let setup = || Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500);
+  let mut r = rand().seed([3,4]);
+  let points: Vec<P> = (0..2_000).map(|_| {
+    (r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0)
+  }).collect();
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let mut expected: Vec<V> = (0..2_000).collect();
+  {
+    let mut db: DB<_,_,P,V> = setup().build()?;
+    db.set_codec(FixedWidth)?;
+    let batch: Vec<Row<P,V>> = points.iter().enumerate()
+      .map(|(i,p)| Row::Insert(*p,i as u32))
+      .collect();
+    db.batch(&batch)?;
+    let mut batch = vec![];
+    for result in db.query(&bbox)? {
+      let (p,v,loc) = result?;
+      if v % 10 == 0 && loc.0 != 0 {
+        batch.push(Row::Update(loc,p,v+10_000));
+      }
+    }
+    assert![!batch.is_empty(), "some rows are in blocks"];
+    for row in batch.iter() {
+      if let Row::Update(_,_,v) = row {
+        expected[(v-10_000) as usize] = *v;
+      }
+    }
+    db.batch(&batch)?;
+    expected.sort();
+    assert_eq![sorted(db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?), expected];
+  }
+  let data = std::fs::read(dir.path().join("data"))?;
+  let row = FixedWidth.serialize(&(points[1],1))?;
+  assert![data.windows(12).any(|w| w == row.as_slice()), "rows are fixed width"];
+  {
+    let mut db: DB<_,_,P,V> = setup().build()?;
+    db.set_codec(FixedWidth)?;
+    assert_eq![sorted(db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?), expected];
+  }
+  Ok(())
+}
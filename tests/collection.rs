@@ -0,0 +1,104 @@
+use eyros::{Setup,DB,Row};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::path::PathBuf;
+
+type P = (f32,f32);
+type V = u32;
+type R = ((f32,f32),(f32,f32));
+type T = (f32,f32,f32);
+
+fn storage (dir: PathBuf) -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> + Clone {
+  move |name: &str| {
+    Ok(RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build()?)
+  }
+}
+
+#[test]
+fn collections() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut r = rand().seed([13,14]);
+  let roads: Vec<Row<R,u64>> = (0..2_000).map(|i| {
+    let x = r.read::<f32>()*2.0-1.0;
+    let y = r.read::<f32>()*2.0-1.0;
+    Row::Insert(((x,x+0.02),(y,y+0.02)), i)
+  }).collect();
+  let sensors: Vec<Row<T,Vec<u8>>> = (0..2_000).map(|i| {
+    let p = (r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0, r.read::<f32>());
+    Row::Insert(p, vec![(i%256) as u8;3])
+  }).collect();
+  let everywhere = ((-1.0,-1.0),(1.0,1.0));
+  let everywhen = ((-1.0,-1.0,0.0),(1.0,1.0,1.0));
+  {
+    let mut db: DB<_,_,P,V> = Setup::new(storage(dir.path().to_path_buf()))
+      .max_data_size(100)
+      .base_size(500)
+      .data_list_cache_size(4)
+      .build()?;
+    db.batch(&[Row::Insert((0.5,0.5), 7)])?;
+    let mut c_roads = db.collection::<R,u64>("roads")?;
+    let mut c_sensors = db.collection::<T,Vec<u8>>("sensors")?;
+    c_roads.batch(&roads)?;
+    c_sensors.batch(&sensors)?;
+    assert_eq![c_roads.query(&everywhere)?.count(), roads.len()];
+    assert_eq![c_sensors.query(&everywhen)?.count(), sensors.len()];
+    assert_eq![db.query(&everywhere)?.count(), 1];
+
+    // all of the collections keep their blocks in one cache of 4 blocks
+    let stores = (db.data_store.clone(), c_roads.data_store.clone(),
+      c_sensors.data_store.clone());
+    let cached = |db_blocks: usize, roads_blocks: usize, sensors_blocks: usize| {
+      assert_eq![
+        (stores.0.read().unwrap().cached_blocks(),
+          stores.1.read().unwrap().cached_blocks(),
+          stores.2.read().unwrap().cached_blocks()),
+        (db_blocks,roads_blocks,sensors_blocks)
+      ];
+    };
+    cached(0,0,4);
+    c_roads.query(&everywhere)?.count();
+    cached(0,4,0);
+    c_sensors.query(&((-0.1,-0.1,0.0),(0.1,0.1,0.1)))?.count();
+    let n = stores.2.read().unwrap().cached_blocks();
+    assert![n > 0];
+    cached(0,4-n,n);
+
+    // a reloaded collection drops its blocks and stays in the shared cache
+    c_roads.refresh()?;
+    let roads_cached = || c_roads.data_store.read().unwrap().cached_blocks();
+    assert_eq![roads_cached(), 0];
+    assert_eq![stores.2.read().unwrap().cached_blocks(), n];
+    c_roads.query(&everywhere)?.count();
+    assert_eq![c_roads.data_store.read().unwrap().cached_blocks(), 4];
+    assert_eq![stores.2.read().unwrap().cached_blocks(), 0];
+  }
+  let names: Vec<String> = std::fs::read_dir(dir.path())?
+    .map(|e| e.unwrap().file_name().into_string().unwrap())
+    .collect();
+  assert![names.iter().any(|n| n == "roads.meta")];
+  assert![names.iter().any(|n| n == "sensors.data")];
+
+  // collections keep their rows and settings when they are opened again
+  let db: DB<_,_,P,V> = DB::open(storage(dir.path().to_path_buf()))?;
+  let mut c_roads = db.collection::<R,u64>("roads")?;
+  let mut values: Vec<u64> = c_roads.query(&everywhere)?
+    .map(|r| r.map(|(_,v,_)| v))
+    .collect::<Result<_,_>>()?;
+  values.sort();
+  assert_eq![values, (0..roads.len() as u64).collect::<Vec<_>>()];
+  let mut c_sensors = db.collection::<T,Vec<u8>>("sensors")?;
+  assert_eq![c_sensors.query(&everywhen)?.count(), sensors.len()];
+  let mut empty = db.collection::<P,V>("buildings")?;
+  assert_eq![empty.query(&everywhere)?.count(), 0];
+
+  for name in ["", "roads.meta", "../roads", "a b"].iter() {
+    match db.collection::<P,V>(name) {
+      Err(Error::Invalid(_)) => {},
+      Err(e) => panic!["unexpected error for {:?}: {}", name, e],
+      Ok(_) => panic!["opened collection {:?}", name]
+    }
+  }
+  Ok(())
+}
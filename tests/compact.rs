@@ -0,0 +1,200 @@
+use eyros::{Setup,DB,Row};
+use eyros::Error;
+use failure::bail;
+use random_access_disk::RandomAccessDisk;
+use random_access_storage::RandomAccess;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::cell::Cell;
+use std::io;
+use std::rc::Rc;
+
+type P = (f32,f32);
+type V = u32;
+
+fn rows<S,U> (db: &mut DB<S,U,P,V>) -> Result<Vec<(P,V)>,Error> where
+S: random_access_storage::RandomAccess<Error=failure::Error>,
+U: Fn(&str) -> Result<S,failure::Error> {
+  let mut rows: Vec<(P,V)> = db.query(&((0.0,0.0),(1.0,1.0)))?
+    .map(|r| r.map(|(p,v,_)| (p,v)))
+    .collect::<Result<Vec<_>,Error>>()?;
+  rows.sort_unstable_by_key(|(_,v)| *v);
+  Ok(rows)
+}
+
+#[test]
+fn compact() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  };
+  let mut r = rand().seed([13,12]);
+  let mut count: u32 = 0;
+  let mut inserts = |n| -> Vec<Row<P,V>> {
+    (0..n).map(|_| {
+      count += 1;
+      Row::Insert((r.read::<f32>(), r.read::<f32>()), count)
+    }).collect()
+  };
+  let expected = {
+    let mut db: DB<_,_,P,V> = Setup::new(&storage)
+      .max_data_size(100)
+      .base_size(500)
+      .build()?;
+    db.batch(&inserts(1_500))?;
+    db.batch(&inserts(600))?; // merges leave dead blocks behind
+    // every row of the blocks in this range
+    db.delete_query(&((0.0,0.0),(0.1,1.0)))?;
+    let mut batch = inserts(100);
+    for result in db.query(&((0.0,0.0),(1.0,1.0)))? {
+      let (_,v,loc) = result?;
+      if v % 7 == 0 && loc.0 > 0 { batch.push(Row::Delete(loc)) }
+    }
+    db.batch(&batch)?; // staged deletes
+    assert![db.freeze_tree(2)?];
+    let expected = rows(&mut db)?;
+    let before = db.disk_usage()?;
+    assert![before.data_dead > 0];
+
+    let report = db.compact()?;
+    assert![report.removed > 0, "blocks without live rows dropped"];
+    assert![report.rewritten > 0, "blocks with deleted rows rewritten"];
+    assert_eq![report.bytes_before, before.data_live + before.data_dead];
+    let after = db.disk_usage()?;
+    assert_eq![after.data_dead, 0];
+    assert_eq![after.data_live, report.bytes_after];
+    assert![report.bytes_after < before.data_live];
+    assert_eq![db.frozen_trees()?, vec![2], "frozen trees stay frozen"];
+    assert_eq![rows(&mut db)?, expected];
+
+    // locations from a query after compacting point at the moved rows
+    let mut batch = inserts(100);
+    let mut expected: Vec<(P,V)> = batch.iter().map(|row| match row {
+      Row::Insert(p,v) => (*p,*v),
+      _ => panic!["unexpected row type"]
+    }).collect();
+    for result in db.query(&((0.0,0.0),(1.0,1.0)))? {
+      let (p,v,loc) = result?;
+      if v % 11 == 0 && loc.0 > 0 { batch.push(Row::Delete(loc)) }
+      else { expected.push((p,v)) }
+    }
+    db.batch(&batch)?;
+    expected.sort_unstable_by_key(|(_,v)| *v);
+    assert_eq![rows(&mut db)?, expected];
+    expected
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  assert_eq![rows(&mut db)?, expected, "compacted stores reopen"];
+  db.compact()?;
+  assert_eq![db.disk_usage()?.data_dead, 0];
+  assert_eq![rows(&mut db)?, expected];
+  Ok(())
+}
+
+// fails every write after the first `budget` writes, like a crashed process
+struct CrashStore {
+  store: RandomAccessDisk,
+  budget: Rc<Cell<Option<usize>>>
+}
+
+impl CrashStore {
+  fn spend (&mut self) -> Result<(),failure::Error> {
+    match self.budget.get() {
+      Some(0) => bail!["crashed"],
+      Some(n) => self.budget.set(Some(n-1)),
+      None => {}
+    }
+    Ok(())
+  }
+}
+
+impl RandomAccess for CrashStore {
+  type Error = failure::Error;
+  fn write (&mut self, offset: u64, data: &[u8]) -> Result<(),failure::Error> {
+    self.spend()?;
+    self.store.write(offset, data)
+  }
+  fn read (&mut self, offset: u64, length: u64) -> Result<Vec<u8>,failure::Error> {
+    self.store.read(offset, length)
+  }
+  fn read_to_writer (&mut self, offset: u64, length: u64,
+  buf: &mut impl io::Write) -> Result<(),failure::Error> {
+    self.store.read_to_writer(offset, length, buf)
+  }
+  fn del (&mut self, offset: u64, length: u64) -> Result<(),failure::Error> {
+    self.spend()?;
+    self.store.del(offset, length)
+  }
+  fn truncate (&mut self, length: u64) -> Result<(),failure::Error> {
+    self.spend()?;
+    self.store.truncate(length)
+  }
+  fn len (&self) -> Result<u64,failure::Error> {
+    self.store.len()
+  }
+  fn is_empty (&mut self) -> Result<bool,failure::Error> {
+    self.store.is_empty()
+  }
+  fn sync_all (&mut self) -> Result<(),failure::Error> {
+    self.spend()?;
+    self.store.sync_all()
+  }
+}
+
+#[test]
+fn compact_crash() -> Result<(),Error> {
+  let mut r = rand().seed([15,16]);
+  let batches: Vec<Vec<Row<P,V>>> = (0..2).map(|b| (0..300).map(|i| {
+    let v = b*300+i;
+    Row::Insert(((v as f32)/1_000.0, r.read::<f32>()), v)
+  }).collect()).collect();
+  let mut writes = 0;
+  let mut crash_points = vec![None];
+  // run once without crashing to count writes, then crash after each write
+  while let Some(crash_after) = crash_points.pop() {
+    let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+    let budget = Rc::new(Cell::new(None));
+    let storage = |name: &str| -> Result<CrashStore,failure::Error> {
+      Ok(CrashStore {
+        store: RandomAccessDisk::builder(dir.path().join(name)).auto_sync(false).build()?,
+        budget: Rc::clone(&budget)
+      })
+    };
+    let setup = || Setup::new(&storage).max_data_size(50).base_size(200);
+    let expected = {
+      let mut db: DB<_,_,P,V> = setup().build()?;
+      for batch in batches.iter() {
+        db.batch(batch)?;
+      }
+      db.delete_query(&((0.0,0.0),(0.1,1.0)))?;
+      let mut deletes = vec![];
+      for result in db.query(&((0.0,0.0),(1.0,1.0)))? {
+        let (_,v,loc) = result?;
+        if v % 3 == 0 && loc.0 > 0 { deletes.push(Row::Delete(loc)) }
+      }
+      db.batch(&deletes)?;
+      let expected = rows(&mut db)?;
+      budget.set(Some(crash_after.unwrap_or(usize::MAX)));
+      let res = db.compact();
+      assert_eq![res.is_err(), crash_after.is_some(), "crash after {:?}", crash_after];
+      if crash_after.is_none() {
+        writes = usize::MAX - budget.get().unwrap();
+        crash_points = (0..writes).map(Some).collect();
+        assert_eq![db.disk_usage()?.data_dead, 0];
+      }
+      budget.set(None);
+      expected
+    };
+    let mut db: DB<_,_,P,V> = setup().build()?;
+    assert_eq![rows(&mut db)?, expected, "crash after {:?} of {}", crash_after, writes];
+    db.compact()?;
+    assert_eq![db.disk_usage()?.data_dead, 0];
+    assert_eq![rows(&mut db)?, expected, "compact after crash {:?}", crash_after];
+  }
+  assert![writes > 0];
+  Ok(())
+}
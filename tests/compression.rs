@@ -0,0 +1,97 @@
+use eyros::{Setup,DB,Row,Compression};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = Vec<u8>;
+
+fn rows (start: u32, n: u32) -> Vec<Row<P,V>> {
+  (start..start+n).map(|i| {
+    let value = format!["vessel-{:08} status=underway", i].into_bytes();
+    Row::Insert(((i as f32)/10_000.0, 0.5), value)
+  }).collect()
+}
+
+// write, update, delete, and reopen with `compression`, returning the size
+// of the data store
+fn check (compression: Compression) -> Result<u64,Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  };
+  let bbox = ((0.0,0.0),(1.0,1.0));
+  let size = {
+    let mut db: DB<_,_,P,V> = Setup::new(&storage)
+      .max_data_size(100)
+      .base_size(500)
+      .compression(compression)
+      .build()?;
+    db.batch(&rows(0, 2_000))?;
+    let mut batch = vec![];
+    for result in db.query(&bbox)? {
+      let (p,v,loc) = result?;
+      if v.ends_with(b"0 status=underway") {
+        batch.push(Row::Delete(loc));
+      } else if v.ends_with(b"5 status=underway") {
+        let mut v = v.clone();
+        v.extend(b" (moored)");
+        batch.push(Row::Update(loc, p, v));
+      }
+    }
+    db.batch(&batch)?;
+    let usage = db.disk_usage()?;
+    usage.data_live + usage.data_dead
+  };
+  // blocks are readable whatever the current setting is
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  db.batch(&rows(2_000, 500))?;
+  let results = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?;
+  assert_eq![results.len(), 2_300, "{:?}", compression];
+  let moored = results.iter().filter(|(_,v,_)| v.ends_with(b"(moored)")).count();
+  assert_eq![moored, 200, "{:?}", compression];
+  Ok(size)
+}
+
+#[test]
+fn compression_none () -> Result<(),Error> {
+  check(Compression::None)?;
+  Ok(())
+}
+
+#[cfg(feature="lz4")]
+#[test]
+fn compression_lz4 () -> Result<(),Error> {
+  let plain = check(Compression::None)?;
+  let compressed = check(Compression::Lz4)?;
+  assert![compressed < plain, "{} < {}", compressed, plain];
+  Ok(())
+}
+
+#[cfg(feature="zstd")]
+#[test]
+fn compression_zstd () -> Result<(),Error> {
+  let plain = check(Compression::None)?;
+  let compressed = check(Compression::Zstd(3))?;
+  assert![compressed < plain, "{} < {}", compressed, plain];
+  Ok(())
+}
+
+#[cfg(not(feature="lz4"))]
+#[test]
+fn compression_missing_feature () -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  };
+  let db: Result<DB<_,_,P,V>,Error> = Setup::new(&storage)
+    .compression(Compression::Lz4)
+    .build();
+  assert![db.is_err()];
+  Ok(())
+}
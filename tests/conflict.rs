@@ -0,0 +1,88 @@
+use eyros::{Setup,DB,Row,Conflict,BulkLoader};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn conflict() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let mut r = rand().seed([13,12]);
+  let rows: Vec<Row<P,V>> = (0..1_200).map(|i| {
+    let x: f32 = r.read::<f32>()*2.0-1.0;
+    let y: f32 = r.read::<f32>()*2.0-1.0;
+    Row::Insert((x,y), i)
+  }).collect();
+  let open = || -> Result<DB<_,_,P,V>,Error> {
+    Setup::new(&storage)
+      .max_data_size(100)
+      .base_size(500)
+      .check_conflicts(true)
+      .build()
+  };
+  let mut a = open()?;
+  let mut b = open()?;
+  a.batch(&rows[0..600])?;
+
+  let err = b.batch(&rows[600..700]).err().expect("stale writer conflicts");
+  let conflict = err.downcast_ref::<Conflict>().expect("conflict error");
+  assert_eq![conflict.expected, 0];
+  assert_eq![conflict.found, 1];
+  assert![!b.is_poisoned(), "nothing was written"];
+
+  b.try_recover()?;
+  b.batch(&rows[600..700])?;
+  let err = a.batch(&rows[700..]).err().expect("a is now stale");
+  assert![err.downcast_ref::<Conflict>().is_some(), "{}", err];
+
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let results = b.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?;
+  assert_eq![results.len(), 700, "both writers' commits are kept"];
+  Ok(())
+}
+
+#[test]
+fn conflict_bulk_load() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let mut r = rand().seed([13,12]);
+  let rows: Vec<(P,V)> = (0..2_000).map(|i| {
+    ((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), i)
+  }).collect();
+  let open = || -> Result<DB<_,_,P,V>,Error> {
+    Setup::new(&storage)
+      .max_data_size(100)
+      .base_size(500)
+      .check_conflicts(true)
+      .build()
+  };
+  let mut a = open()?;
+  let mut b = open()?;
+  let inserts: Vec<Row<P,V>> = rows[0..600].iter()
+    .map(|(p,v)| Row::Insert(*p,*v)).collect();
+  a.batch(&inserts)?;
+
+  let mut loader = BulkLoader::new(&mut b)?;
+  loader.extend(rows[600..].iter().cloned());
+  let err = loader.finish().err().expect("stale bulk load conflicts");
+  assert![err.downcast_ref::<Conflict>().is_some(), "{}", err];
+  assert![!b.is_poisoned(), "nothing was written"];
+
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  assert_eq![a.query(&bbox)?.count(), 600];
+  Ok(())
+}
@@ -0,0 +1,89 @@
+use eyros::{Setup,DB,Row,Consistency,Stale};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn consistency() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let open = || -> Result<DB<_,_,P,V>,Error> {
+    Setup::new(&storage)
+      .max_data_size(100)
+      .base_size(500)
+      .check_conflicts(true)
+      .build()
+  };
+  let mut r = rand().seed([13,12]);
+  let rows: Vec<Row<P,V>> = (0..1_200).map(|i| {
+    let x: f32 = r.read::<f32>()*2.0-1.0;
+    let y: f32 = r.read::<f32>()*2.0-1.0;
+    Row::Insert((x,y), i)
+  }).collect();
+  let count = |db: &mut DB<_,_,P,V>, level| -> Result<usize,Error> {
+    let bbox = ((-1.0,-1.0),(1.0,1.0));
+    Ok(db.query_consistent(&bbox, level)?.collect::<Result<Vec<_>,Error>>()?.len())
+  };
+  let mut writer = open()?;
+  let mut replica = open()?;
+  writer.batch(&rows[0..600])?;
+  writer.batch(&rows[600..700])?;
+  let token = writer.sequence();
+  assert_eq![token, 2];
+
+  assert_eq![count(&mut replica, Consistency::Local)?, 0, "local read is stale"];
+  let lag = Consistency::BoundedStaleness { max_lag: 2 };
+  assert_eq![count(&mut replica, lag)?, 0, "within the allowed lag"];
+  let lag = Consistency::BoundedStaleness { max_lag: 1 };
+  assert_eq![count(&mut replica, lag)?, 700, "reloaded when lagging"];
+
+  writer.batch(&rows[700..])?;
+  let token = writer.sequence();
+  assert_eq![count(&mut replica, Consistency::ReadYourWrites(token))?, 1_200];
+
+  let err = replica.query_consistent(&((-1.0,-1.0),(1.0,1.0)),
+    Consistency::ReadYourWrites(token+1)).err().expect("future token");
+  let stale = err.downcast_ref::<Stale>().expect("stale error");
+  assert_eq![(stale.sequence,stale.required), (token,token+1)];
+  Ok(())
+}
+
+#[test]
+fn read_your_writes_default_setup() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let mut writer: DB<_,_,P,V> = DB::open(&storage)?;
+  let mut replica: DB<_,_,P,V> = DB::open(&storage)?;
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  // small batches only touch staging
+  for i in 0..3 {
+    writer.batch(&[Row::Insert((0.1*i as f32,0.2), i)])?;
+  }
+  let token = writer.sequence();
+  assert_eq![token, 3, "staged batches advance the sequence"];
+  let results = replica.query_consistent(&bbox, Consistency::ReadYourWrites(token))?
+    .collect::<Result<Vec<_>,Error>>()?;
+  assert_eq![results.len(), 3];
+
+  assert_eq![writer.delete_query(&((0.0,0.0),(0.05,1.0)))?, 1];
+  let token = writer.sequence();
+  assert_eq![token, 4, "deletes advance the sequence"];
+  let results = replica.query_consistent(&bbox, Consistency::ReadYourWrites(token))?
+    .collect::<Result<Vec<_>,Error>>()?;
+  assert_eq![results.len(), 2];
+  Ok(())
+}
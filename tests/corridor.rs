@@ -0,0 +1,73 @@
+use eyros::{Setup,DB,Row,segment_distance,haversine};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+
+type P = (f64,f64);
+type V = u32;
+
+#[test]
+fn segment_distances() -> Result<(),Error> {
+  let degree = haversine((0.0,0.0),(1.0,0.0));
+  assert![(degree-111_195.0).abs() < 1.0, "one degree along the equator"];
+  let d = segment_distance((0.5,0.01), (0.0,0.0), (1.0,0.0));
+  assert![(d-degree*0.01).abs() < 0.01, "cross-track distance {}", d];
+  let d = segment_distance((2.0,0.0), (0.0,0.0), (1.0,0.0));
+  assert![(d-degree).abs() < 0.01, "distance past the end {}", d];
+  let d = segment_distance((-0.5,0.0), (0.0,0.0), (1.0,0.0));
+  assert![(d-degree*0.5).abs() < 0.01, "distance before the start {}", d];
+  Ok(())
+}
+
+#[test]
+fn query_corridor() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([3,4]);
+  // points around Berlin
+  let mut points: Vec<P> = (0..2_000).map(|_| {
+    (13.0 + r.read::<f64>(), 52.0 + r.read::<f64>())
+  }).collect();
+  // and some along the equator across the antimeridian
+  points.extend((0..400).map(|i| {
+    let lon = 179.0 + (i as f64)/200.0;
+    (if lon > 180.0 { lon - 360.0 } else { lon }, r.read::<f64>()*0.1-0.05)
+  }));
+  let batch: Vec<Row<P,V>> = points.iter().enumerate()
+    .map(|(i,p)| Row::Insert(*p,i as u32))
+    .collect();
+  db.batch(&batch[0..1_500])?;
+  db.batch(&batch[1_500..])?;
+
+  let routes: Vec<(Vec<P>,f64)> = vec![
+    (vec![(13.1,52.1),(13.4,52.6),(13.9,52.5)], 2_000.0),
+    (vec![(13.5,52.5)], 10_000.0),
+    (vec![(179.6,0.0),(-179.6,0.01)], 3_000.0),
+  ];
+  for (route,width) in routes.iter() {
+    let mut expected: Vec<V> = points.iter().enumerate().filter(|(_,p)| {
+      let p = **p;
+      if route.len() == 1 { return haversine(p, route[0]) <= *width }
+      route.windows(2).any(|w| segment_distance(p, w[0], w[1]) <= *width)
+    }).map(|(i,_)| i as u32).collect();
+    let mut results = vec![];
+    for result in db.query_corridor(route, *width)? {
+      results.push(result?.1);
+    }
+    expected.sort();
+    results.sort();
+    assert![expected.len() > 0, "route {:?} passes near some points", route];
+    assert_eq![results, expected, "points near {:?}", route];
+  }
+  Ok(())
+}
@@ -0,0 +1,74 @@
+use eyros::{Setup,DB,Row,Location};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn count() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let mut batch = |n: u32| -> Vec<Row<P,V>> {
+    (0..n).map(|i| {
+      let x: f32 = r.read::<f32>()*2.0-1.0;
+      let y: f32 = r.read::<f32>()*2.0-1.0;
+      Row::Insert((x,y), i)
+    }).collect()
+  };
+  db.batch(&batch(3_000))?; // written to a tree
+  db.batch(&batch(80))?; // stays in staging
+
+  let bboxes = vec![
+    ((-1.0,-1.0),(1.0,1.0)),
+    ((-0.5,-0.8),(0.3,-0.5)),
+    ((0.1,0.1),(0.9,0.95)),
+    ((2.0,2.0),(3.0,3.0)),
+  ];
+  let check = |db: &mut DB<_,_,P,V>| -> Result<Vec<Location>,Error> {
+    let mut locations = vec![];
+    for bbox in bboxes.iter() {
+      let mut points = vec![];
+      for result in db.query(bbox)? {
+        let (point,_,location) = result?;
+        points.push(point);
+        locations.push(location);
+      }
+      assert_eq![db.count(bbox)?, points.len() as u64, "count for {:?}", bbox];
+      let agg = db.aggregate(bbox)?;
+      assert_eq![agg.count, points.len() as u64, "aggregate count"];
+      match agg.bounds {
+        None => assert![points.is_empty(), "bounds when there are points"],
+        Some(((x0,y0),(x1,y1))) => {
+          let min_x = points.iter().map(|p| p.0).fold(f32::INFINITY, f32::min);
+          let min_y = points.iter().map(|p| p.1).fold(f32::INFINITY, f32::min);
+          let max_x = points.iter().map(|p| p.0).fold(f32::NEG_INFINITY, f32::max);
+          let max_y = points.iter().map(|p| p.1).fold(f32::NEG_INFINITY, f32::max);
+          assert_eq![(x0,y0,x1,y1), (min_x,min_y,max_x,max_y), "bounds"];
+        }
+      }
+    }
+    Ok(locations)
+  };
+  let locations = check(&mut db)?;
+
+  // staged deletes of block and staging rows are excluded from counts
+  let deletes: Vec<Row<P,V>> = locations.iter().step_by(7)
+    .map(|loc| Row::Delete(*loc))
+    .collect();
+  db.batch(&deletes[0..deletes.len().min(60)])?;
+  check(&mut db)?;
+  Ok(())
+}
@@ -1,6 +1,7 @@
 use eyros::{DB,Row,Point,Cursor,Block,order,order_len};
 use random::{Source,default as rand};
-use failure::{Error,bail};
+use eyros::Error;
+use failure::bail;
 use random_access_disk::RandomAccessDisk;
 use std::mem::size_of;
 use tempfile::Builder as Tmpfile;
@@ -28,14 +29,14 @@ impl PartialEq for P {
 }
 
 impl ToBytes for P {
-  fn to_bytes(&self) -> Result<Vec<u8>,Error> {
+  fn to_bytes(&self) -> Result<Vec<u8>,failure::Error> {
     let count = self.count_bytes();
     let mut bytes = vec![0u8;count];
     let size = self.write_bytes(&mut bytes)?;
     if size != count { bail!["unexpected size while writing into buffer"] }
     Ok(bytes)
   }
-  fn write_bytes(&self, dst: &mut [u8]) -> Result<usize,Error> {
+  fn write_bytes(&self, dst: &mut [u8]) -> Result<usize,failure::Error> {
     if dst.len() < 1+4+4 { bail!["dst buffer too small"] }
     match self {
       P::Point(x,y) => {
@@ -53,7 +54,7 @@ impl ToBytes for P {
 }
 
 impl FromBytes for P {
-  fn from_bytes(src: &[u8]) -> Result<(usize,Self),Error> {
+  fn from_bytes(src: &[u8]) -> Result<(usize,Self),failure::Error> {
     if src.len() < 1+4+4 {
       bail!["buffer too small while loading from bytes"]
     }
@@ -78,7 +79,7 @@ impl CountBytes for P {
       P::Interval(_,_) => 1+4*4,
     }
   }
-  fn count_from_bytes(buf: &[u8]) -> Result<usize,Error> {
+  fn count_from_bytes(buf: &[u8]) -> Result<usize,failure::Error> {
     if buf.len() < 1+4+4 { bail!["buffer too small for type in count"] }
     Ok(match buf[0] {
       0 => 1+4*2,
@@ -145,7 +146,19 @@ impl Point for P {
       },
       _ => panic!["match case beyond dimension"]
     };
-    match order { Some(x) => x, None => Ordering::Less }
+    order.unwrap_or(Ordering::Equal)
+  }
+
+  fn cmp_upper_at (&self, other: &Self, level: usize) -> Ordering where Self: Sized {
+    // upper bounds, the same values that serialize_at() writes for pivots
+    let upper = |p: &P| match (level % Self::dim(), p) {
+      (0,P::Point(x,_)) => *x,
+      (0,P::Interval((_,x),_)) => *x,
+      (1,P::Point(_,y)) => *y,
+      (1,P::Interval(_,(_,y))) => *y,
+      _ => panic!["match case beyond dimension"]
+    };
+    upper(self).total_cmp(&upper(other))
   }
 
   fn midpoint_upper (&self, other: &Self) -> Self where Self: Sized {
@@ -168,10 +181,10 @@ impl Point for P {
   fn serialize_at (&self, level: usize, dst: &mut [u8])
   -> Result<usize,Error> {
     match (level % Self::dim(), self) {
-      (0,P::Point(x,_)) => x.write_bytes(dst),
-      (0,P::Interval((_,x),_)) => x.write_bytes(dst),
-      (1,P::Point(_,y)) => y.write_bytes(dst),
-      (1,P::Interval(_,(_,y))) => y.write_bytes(dst),
+      (0,P::Point(x,_)) => Ok(x.write_bytes(dst)?),
+      (0,P::Interval((_,x),_)) => Ok(x.write_bytes(dst)?),
+      (1,P::Point(_,y)) => Ok(y.write_bytes(dst)?),
+      (1,P::Interval(_,(_,y))) => Ok(y.write_bytes(dst)?),
       _ => panic!["match case beyond dimension"]
     }
   }
@@ -301,7 +314,7 @@ impl Point for P {
 fn mix() -> Result<(),Error> {
   let dir = Tmpfile::new().prefix("eyros").tempdir()?;
   let mut db: DB<_,_,P,V> = DB::open(
-    |name: &str| -> Result<RandomAccessDisk,Error> {
+    |name: &str| -> Result<RandomAccessDisk,failure::Error> {
       let p = dir.path().join(name);
       Ok(RandomAccessDisk::builder(p)
         .auto_sync(false)
@@ -5,7 +5,7 @@ extern crate random_access_disk;
 extern crate tempfile;
 
 use eyros::{DB,Row,Location};
-use failure::Error;
+use eyros::Error;
 use random_access_disk::RandomAccessDisk;
 use random::{Source,default as rand};
 use tempfile::Builder as Tmpfile;
@@ -21,7 +21,7 @@ type V = u32;
 fn delete() -> Result<(),Error> {
   let dir = Tmpfile::new().prefix("eyros").tempdir()?;
   let mut db: DB<_,_,P,V> = DB::open(
-    |name: &str| -> Result<RandomAccessDisk,Error> {
+    |name: &str| -> Result<RandomAccessDisk,failure::Error> {
       let p = dir.path().join(name);
       Ok(RandomAccessDisk::builder(p)
         .auto_sync(false)
@@ -0,0 +1,74 @@
+use eyros::{Setup,DB,Row};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn delete_query() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let batch: Vec<Row<P,V>> = (0..2_300).map(|i| {
+    let x: f32 = r.read::<f32>()*2.0-1.0;
+    let y: f32 = r.read::<f32>()*2.0-1.0;
+    Row::Insert((x,y), i)
+  }).collect();
+  db.batch(&batch)?;
+
+  let all_bbox = ((-1.0,-1.0),(1.0,1.0));
+  let bbox = ((-0.5,-0.2),(0.3,0.6));
+  let mut expected: Vec<V> = db.query(&all_bbox)?
+    .collect::<Result<Vec<_>,Error>>()?
+    .into_iter()
+    .filter(|row| !((row.0).0 >= -0.5 && (row.0).0 <= 0.3
+      && (row.0).1 >= -0.2 && (row.0).1 <= 0.6))
+    .map(|row| row.1)
+    .collect();
+  expected.sort();
+  let removed = db.delete_query(&bbox)?;
+  assert_eq![removed, 2_300 - expected.len()];
+  assert![removed > 0];
+  assert_eq![db.query(&bbox)?.count(), 0, "no rows left in bbox"];
+  assert_eq![db.delete_query(&bbox)?, 0, "nothing left to delete"];
+
+  let values = |db: &mut DB<_,_,P,V>| -> Result<Vec<V>,Error> {
+    let mut values = db.query(&all_bbox)?
+      .map(|r| r.map(|row| row.1))
+      .collect::<Result<Vec<_>,Error>>()?;
+    values.sort();
+    Ok(values)
+  };
+  assert_eq![values(&mut db)?, expected, "rows outside bbox are kept"];
+  db.close()?;
+
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  assert_eq![values(&mut db)?, expected, "deletes persist"];
+
+  // rows still in staging are deleted along with rows in blocks
+  let staged: Vec<Row<P,V>> = (0..40).map(|i| {
+    Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), 2_300+i)
+  }).collect();
+  db.batch(&staged)?;
+  expected.extend(2_300..2_340);
+  let removed = db.delete_query_filter(&all_bbox, |_,v| v % 2 == 0)?;
+  let odd: Vec<V> = expected.iter().filter(|v| *v % 2 == 1).cloned().collect();
+  assert_eq![removed, expected.len() - odd.len()];
+  assert_eq![values(&mut db)?, odd, "filtered rows removed"];
+  Ok(())
+}
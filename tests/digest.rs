@@ -0,0 +1,58 @@
+use eyros::{Setup,DB,Row};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn query_digest() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let open = |name: &'static str| {
+    let dir = dir.path().join(name);
+    Setup::new(move |store: &str| -> Result<RandomAccessDisk,failure::Error> {
+      Ok(RandomAccessDisk::builder(dir.join(store)).auto_sync(false).build()?)
+    })
+    .max_data_size(100)
+    .base_size(500)
+  };
+  let mut r = rand().seed([13,12]);
+  let rows: Vec<Row<P,V>> = (0..1_200).map(|i| {
+    Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), i)
+  }).collect();
+  let bboxes = vec![
+    ((-1.0,-1.0),(1.0,1.0)),
+    ((-0.5,-0.8),(0.3,-0.5)),
+    ((0.2,0.2),(0.9,0.4))
+  ];
+  let mut primary: DB<_,_,P,V> = open("primary").build()?;
+  primary.batch(&rows)?;
+  // same rows in reverse order and in smaller batches, so the rows end up in
+  // different trees, blocks, and staging positions
+  let mut replica: DB<_,_,P,V> = open("replica").build()?;
+  let mut reversed = rows.clone();
+  reversed.reverse();
+  for chunk in reversed.chunks(300) {
+    replica.batch(chunk)?;
+  }
+  for bbox in bboxes.iter() {
+    let a = primary.query_digest(bbox)?;
+    let b = replica.query_digest(bbox)?;
+    assert_eq![a.sequence, primary.sequence()];
+    assert_eq![a.rows, primary.query(bbox)?.count() as u64];
+    assert_eq![(a.rows,a.hash), (b.rows,b.hash), "same rows, same digest"];
+  }
+  let empty = primary.query_digest(&((2.0,2.0),(3.0,3.0)))?;
+  assert_eq![(empty.rows,empty.hash), (0,0)];
+
+  // a replica with one changed value
+  let (p,v,loc) = replica.query(&bboxes[0])?.next().unwrap()?;
+  replica.batch(&[Row::Update(loc, p, v + 1)])?;
+  let a = primary.query_digest(&bboxes[0])?;
+  let b = replica.query_digest(&bboxes[0])?;
+  assert_eq![a.rows, b.rows];
+  assert![a.hash != b.hash, "changed value changes the digest"];
+  Ok(())
+}
@@ -0,0 +1,62 @@
+use eyros::{Setup,DB,Row};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::fs;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn disk_usage() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut db: DB<_,_,P,V> = Setup::new(|name: &str| {
+      let p = dir.path().join(name);
+      Ok(RandomAccessDisk::builder(p)
+        .auto_sync(false)
+        .build()?)
+    })
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let mut batch = |n| -> Vec<Row<P,V>> {
+    (0..n).map(|i| {
+      let x: f32 = r.read::<f32>()*2.0-1.0;
+      let y: f32 = r.read::<f32>()*2.0-1.0;
+      Row::Insert((x,y), i)
+    }).collect()
+  };
+  db.batch(&batch(700))?;
+  let usage = db.disk_usage()?;
+  assert![usage.staging_inserts > 0, "200 rows in staging"];
+  assert![usage.data_live > 0];
+  assert_eq![usage.data_dead, 0, "nothing merged yet"];
+  assert_eq![usage.trees.len(), 1];
+
+  db.batch(&batch(400))?; // merges tree 0 into tree 1
+  db.close()?;
+  let usage = db.disk_usage();
+  assert![usage.is_err(), "closed"];
+
+  let mut db: DB<_,_,P,V> = Setup::new(|name: &str| {
+      let p = dir.path().join(name);
+      Ok(RandomAccessDisk::builder(p)
+        .auto_sync(false)
+        .build()?)
+    })
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let usage = db.disk_usage()?;
+  assert![usage.data_dead > 0, "blocks from the merged tree are dead"];
+  assert_eq![usage.trees[0], 0, "tree 0 was merged away"];
+  assert![usage.trees[1] > 0];
+  let mut total = 0;
+  for entry in fs::read_dir(dir.path())? {
+    total += entry?.metadata()?.len();
+  }
+  assert_eq![usage.total(), total, "matches the files on disk"];
+  Ok(())
+}
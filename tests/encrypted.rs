@@ -0,0 +1,243 @@
+#![cfg(feature="aes")]
+use eyros::{Setup,DB,Row,Encrypted,StorageKey,DecryptFailed,ENCRYPTED_BLOCK_SIZE};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random_access_storage::RandomAccess;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::path::{Path,PathBuf};
+use std::{cell::Cell,io,rc::Rc};
+use failure::bail;
+
+type P = (f32,f32);
+type V = u32;
+
+fn disk (dir: PathBuf) -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> + Send + Sync {
+  move |name: &str| {
+    Ok(RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build()?)
+  }
+}
+
+fn open (dir: &Path, key: &StorageKey) -> Result<DB<Encrypted<RandomAccessDisk>,
+eyros::EncryptedStorage<RandomAccessDisk>,P,V>,Error> {
+  Setup::encrypted(disk(dir.to_path_buf()), key.clone())
+    .max_data_size(100)
+    .base_size(500)
+    .build()
+}
+
+#[test]
+fn encrypted_store() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let key = StorageKey::new([7;32]);
+  let inner = RandomAccessDisk::open(dir.path().join("store"))?;
+  let mut store = Encrypted::open(inner, &key, "store")?;
+  let mut model: Vec<u8> = vec![];
+  let mut r = rand().seed([1,2]);
+  let bs = ENCRYPTED_BLOCK_SIZE as u64;
+  for i in 0..300 {
+    let len = model.len() as u64;
+    match r.read::<u32>() % 10 {
+      // appends, writes that straddle blocks, and writes past the end
+      0..=6 => {
+        let offset = match i % 3 {
+          0 => len,
+          1 => r.read::<u64>() % (len+1),
+          _ => len + r.read::<u64>() % bs
+        };
+        let data: Vec<u8> = (0..r.read::<u64>() % (bs*2)).map(|_| r.read::<u8>()).collect();
+        store.write(offset, &data)?;
+        let end = offset as usize + data.len();
+        if model.len() < end { model.resize(end, 0) }
+        model[offset as usize..end].copy_from_slice(&data);
+      },
+      7 => {
+        let n = r.read::<u64>() % (len+bs);
+        store.truncate(n)?;
+        model.resize(n as usize, 0);
+      },
+      _ => {
+        let offset = r.read::<u64>() % (len+1);
+        let n = r.read::<u64>() % (bs*2);
+        store.del(offset, n)?;
+        let end = (offset+n).min(len) as usize;
+        for b in model[offset as usize..end].iter_mut() { *b = 0 }
+      }
+    }
+    assert_eq![store.len()?, model.len() as u64];
+    let offset = r.read::<u64>() % (model.len() as u64 + 1);
+    let n = r.read::<u64>() % (model.len() as u64 - offset + 1);
+    assert_eq![store.read(offset, n)?, &model[offset as usize..(offset+n) as usize]];
+  }
+  assert_eq![store.read(0, model.len() as u64)?, model];
+  assert![store.read(0, model.len() as u64 + 1).is_err()];
+
+  // the same bytes under another name don't decrypt
+  let copy = RandomAccessDisk::open(dir.path().join("store"))?;
+  assert![Encrypted::open(copy, &key, "other").is_err()];
+  Ok(())
+}
+
+// store that writes only the first half of the write after `tear` is set,
+// as if the process crashed partway through it
+struct TornStore {
+  store: RandomAccessDisk,
+  tear: Rc<Cell<bool>>
+}
+
+impl RandomAccess for TornStore {
+  type Error = failure::Error;
+  fn write (&mut self, offset: u64, data: &[u8]) -> Result<(),failure::Error> {
+    if self.tear.get() {
+      self.store.write(offset, &data[0..data.len()/2])?;
+      bail!["crashed"];
+    }
+    self.store.write(offset, data)
+  }
+  fn read (&mut self, offset: u64, length: u64) -> Result<Vec<u8>,failure::Error> {
+    self.store.read(offset, length)
+  }
+  fn read_to_writer (&mut self, offset: u64, length: u64,
+  buf: &mut impl io::Write) -> Result<(),failure::Error> {
+    self.store.read_to_writer(offset, length, buf)
+  }
+  fn del (&mut self, offset: u64, length: u64) -> Result<(),failure::Error> {
+    self.store.del(offset, length)
+  }
+  fn truncate (&mut self, length: u64) -> Result<(),failure::Error> {
+    self.store.truncate(length)
+  }
+  fn len (&self) -> Result<u64,failure::Error> {
+    self.store.len()
+  }
+  fn is_empty (&mut self) -> Result<bool,failure::Error> {
+    self.store.is_empty()
+  }
+  fn sync_all (&mut self) -> Result<(),failure::Error> {
+    self.store.sync_all()
+  }
+}
+
+#[test]
+fn encrypted_torn_write() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let key = StorageKey::new([9;32]);
+  let path = dir.path().join("store");
+  let tear = Rc::new(Cell::new(false));
+  let torn = TornStore { store: RandomAccessDisk::open(path.clone())?, tear: Rc::clone(&tear) };
+  let mut store = Encrypted::open(torn, &key, "store")?;
+  let model: Vec<u8> = (0..ENCRYPTED_BLOCK_SIZE*3).map(|i| (i % 251) as u8).collect();
+  store.write(0, &model)?;
+  store.write(10, b"first")?;
+  let mut expected = model.clone();
+  expected[10..15].copy_from_slice(b"first");
+
+  // a torn write of a few bytes leaves the rest of its block, and the block
+  // itself, as they were before the write
+  tear.set(true);
+  assert![store.write(ENCRYPTED_BLOCK_SIZE as u64 + 20, b"second").is_err()];
+  assert![store.write(10, b"third").is_err()];
+  let mut store = Encrypted::open(RandomAccessDisk::open(path)?, &key, "store")?;
+  assert_eq![store.len()?, expected.len() as u64];
+  assert_eq![store.read(0, expected.len() as u64)?, expected];
+  Ok(())
+}
+
+#[test]
+fn encrypted_db() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let key = StorageKey::new([42;32]);
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let mut r = rand().seed([3,4]);
+  let batch: Vec<Row<P,V>> = (0..2_000).map(|i| {
+    Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), i)
+  }).collect();
+  {
+    let mut db = open(dir.path(), &key)?;
+    db.batch(&batch)?;
+    assert_eq![db.query(&bbox)?.count(), batch.len()];
+  }
+  {
+    let mut db = open(dir.path(), &key)?;
+    assert_eq![db.query(&bbox)?.count(), batch.len()];
+  }
+  // no store holds the meta header or the rows in the clear
+  let meta = std::fs::read(dir.path().join("meta"))?;
+  assert![!meta.windows(4).any(|w| w == b"EYRS")];
+
+  // another key can't open the database
+  let err = open(dir.path(), &StorageKey::new([43;32])).err()
+    .expect("wrong key fails to open");
+  match err {
+    Error::DecryptFailed(DecryptFailed { name, offset: 0 }) => assert_eq![name, "meta"],
+    e => panic!["unexpected error: {}", e]
+  }
+
+  // data blocks in an altered encrypted block fail to decrypt and are
+  // quarantined instead of returning garbage. every block has two slots, so
+  // alter enough bytes to cover both slots of one block.
+  let path = dir.path().join("data");
+  let mut data = std::fs::read(&path)?;
+  let i = data.len()/2;
+  for b in data[i..i+4*(ENCRYPTED_BLOCK_SIZE+64)].iter_mut() { *b ^= 1 }
+  std::fs::write(&path, &data)?;
+  let mut db = open(dir.path(), &key)?;
+  let n = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?.len();
+  assert![n < batch.len()];
+  assert![!db.quarantined()?.is_empty()];
+  Ok(())
+}
+
+#[test]
+fn encrypted_key_rotation() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let (a,b) = (StorageKey::new([1;32]), StorageKey::new([2;32]));
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let mut r = rand().seed([5,6]);
+  let batch: Vec<Row<P,V>> = (0..2_000).map(|i| {
+    Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), i)
+  }).collect();
+  {
+    let mut db = open(dir.path(), &a)?;
+    db.batch(&batch[0..1_000])?;
+    assert_eq![db.rotate_key(b.clone().into())?, 1];
+    // blocks sealed with the old key are still read by the open handle
+    db.batch(&batch[1_000..])?;
+    assert_eq![db.query(&bbox)?.count(), batch.len()];
+  }
+  // the old key opens nothing written since the rotation
+  assert![open(dir.path(), &a).is_err()];
+  {
+    let mut db: DB<_,_,P,V> = Setup::encrypted(disk(dir.path().to_path_buf()), b.clone())
+      .old_key(0, a.clone().into())
+      .max_data_size(100)
+      .base_size(500)
+      .build()?;
+    assert_eq![db.query(&bbox)?.count(), batch.len()];
+    db.compact()?;
+    assert_eq![db.query(&bbox)?.count(), batch.len()];
+  }
+  // once compacted, nothing needs the old key
+  let mut db = open(dir.path(), &b)?;
+  assert_eq![db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?.len(), batch.len()];
+  assert![db.quarantined()?.is_empty()];
+  Ok(())
+}
+
+#[test]
+fn passphrase() {
+  let a = StorageKey::from_passphrase(b"correct horse", b"salt");
+  let b = StorageKey::from_passphrase(b"correct horse", b"salt");
+  let c = StorageKey::from_passphrase(b"correct horse", b"pepper");
+  let name = "store";
+  let dir = Tmpfile::new().prefix("eyros").tempdir().unwrap();
+  let mut store = Encrypted::open(RandomAccessDisk::open(dir.path().join(name)).unwrap(), &a, name)
+    .unwrap();
+  store.write(0, b"hello").unwrap();
+  let mut same = Encrypted::open(RandomAccessDisk::open(dir.path().join(name)).unwrap(), &b, name)
+    .unwrap();
+  assert_eq![same.read(0, 5).unwrap(), b"hello"];
+  assert![Encrypted::open(RandomAccessDisk::open(dir.path().join(name)).unwrap(), &c, name)
+    .is_err()];
+  assert_eq![format!["{:?}", a], "StorageKey(..)"];
+}
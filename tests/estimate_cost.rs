@@ -0,0 +1,57 @@
+use eyros::{Setup,DB,Row,QueryCost};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn estimate_cost() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    Ok(RandomAccessDisk::builder(dir.path().join(name)).auto_sync(false).build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let all = ((-1.0,-1.0),(1.0,1.0));
+  assert_eq![db.estimate_cost(&all)?, QueryCost::default()];
+
+  let mut r = rand().seed([5,55]);
+  let mut n = 0;
+  for size in [2_000,700,100].iter() {
+    let batch: Vec<Row<P,V>> = (0..*size).map(|_| {
+      n += 1;
+      Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), n)
+    }).collect();
+    db.batch(&batch)?;
+  }
+
+  // every block lies inside of the whole space
+  let cost = db.estimate_cost(&all)?;
+  let stats = db.tree_stats()?;
+  assert_eq![cost.rows, n as u64];
+  assert_eq![cost.rows_inside, n as u64];
+  assert_eq![cost.blocks, stats.iter().map(|t| t.data_blocks).sum::<u64>()];
+  assert_eq![cost.bytes, stats.iter().map(|t| t.data_bytes).sum::<u64>()];
+
+  // a small box reads fewer blocks and bounds the number of results
+  let bbox = ((-0.3,0.1),(0.2,0.4));
+  db.reset_stats()?;
+  let small = db.estimate_cost(&bbox)?;
+  let s = db.stats()?;
+  assert_eq![s.block_cache_hits + s.block_cache_misses, 0];
+  let found = db.query(&bbox)?.count() as u64;
+  assert![small.blocks > 0 && small.blocks < cost.blocks];
+  assert![small.bytes > 0 && small.bytes < cost.bytes];
+  assert![small.rows_inside <= found && found <= small.rows];
+
+  // deleted rows still count until compaction
+  db.delete_query(&bbox)?;
+  assert_eq![db.query(&bbox)?.count(), 0];
+  assert_eq![db.estimate_cost(&bbox)?.blocks, small.blocks];
+  Ok(())
+}
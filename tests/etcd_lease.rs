@@ -0,0 +1,123 @@
+#![cfg(feature="etcd-lease")]
+use eyros::{Leadership,EtcdLease};
+use eyros::Error;
+use serde_json::{json,Value};
+use std::collections::{HashMap,HashSet};
+use std::io::{BufRead,BufReader,Read,Write};
+use std::net::TcpListener;
+use std::sync::{Arc,Mutex};
+use std::thread;
+use std::time::Duration;
+
+// the parts of etcd's JSON gateway that leases use, with leases that only
+// expire when a test says so
+#[derive(Default)]
+struct Etcd {
+  next: i64,
+  leases: HashSet<i64>,
+  // key -> (value, lease)
+  keys: HashMap<String,(String,i64)>
+}
+
+impl Etcd {
+  fn handle (&mut self, path: &str, req: &Value) -> Value {
+    let id = |v: &Value| v.as_str().and_then(|s| s.parse::<i64>().ok()).unwrap();
+    match path {
+      "/v3/lease/grant" => {
+        self.next += 1;
+        self.leases.insert(self.next);
+        json!({ "ID": self.next.to_string(), "TTL": req["TTL"] })
+      },
+      "/v3/lease/keepalive" => {
+        if self.leases.contains(&id(&req["ID"])) {
+          json!({ "result": { "ID": req["ID"], "TTL": "10" } })
+        } else {
+          json!({ "result": { "ID": req["ID"] } })
+        }
+      },
+      "/v3/lease/revoke" => {
+        self.expire(id(&req["ID"]));
+        json!({})
+      },
+      "/v3/kv/txn" => {
+        let key = req["compare"][0]["key"].as_str().unwrap().to_string();
+        assert_eq![key, "ZXlyb3MvbGVhZGVy", "base64 of eyros/leader"];
+        match self.keys.get(&key) {
+          None => {
+            let put = &req["success"][0]["request_put"];
+            let lease = id(&put["lease"]);
+            assert![self.leases.contains(&lease), "put with a live lease"];
+            self.keys.insert(key, (put["value"].as_str().unwrap().to_string(), lease));
+            json!({ "succeeded": true })
+          },
+          Some((value,lease)) => json!({ "responses": [{ "response_range": {
+            "kvs": [{ "key": key, "value": value, "lease": lease.to_string() }]
+          }}]})
+        }
+      },
+      _ => panic!["unexpected path {}", path]
+    }
+  }
+  fn expire (&mut self, lease: i64) {
+    self.leases.remove(&lease);
+    self.keys.retain(|_,(_,l)| *l != lease);
+  }
+}
+
+fn serve (etcd: Arc<Mutex<Etcd>>) -> Result<String,Error> {
+  let listener = TcpListener::bind("127.0.0.1:0")?;
+  let addr = listener.local_addr()?;
+  thread::spawn(move || {
+    for stream in listener.incoming() {
+      let mut stream = stream.unwrap();
+      let mut reader = BufReader::new(stream.try_clone().unwrap());
+      let mut line = String::new();
+      reader.read_line(&mut line).unwrap();
+      let path = line.split(' ').nth(1).unwrap().to_string();
+      let mut len = 0;
+      loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).unwrap();
+        if header.trim().is_empty() { break }
+        let lower = header.to_lowercase();
+        if let Some(n) = lower.strip_prefix("content-length:") {
+          len = n.trim().parse().unwrap();
+        }
+      }
+      let mut body = vec![0;len];
+      reader.read_exact(&mut body).unwrap();
+      let req: Value = serde_json::from_slice(&body).unwrap();
+      let res = etcd.lock().unwrap().handle(&path, &req).to_string();
+      write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+        Content-Length: {}\r\nConnection: close\r\n\r\n{}", res.len(), res).unwrap();
+    }
+  });
+  Ok(format!["http://{}/", addr])
+}
+
+#[test]
+fn etcd_lease() -> Result<(),Error> {
+  let etcd = Arc::new(Mutex::new(Etcd::default()));
+  let endpoint = serve(Arc::clone(&etcd))?;
+  let ttl = Duration::from_secs(10);
+  let mut a = EtcdLease::new(endpoint.as_str(), "eyros/leader", 1, ttl);
+  let mut b = EtcdLease::new(endpoint.as_str(), "eyros/leader", 2, ttl);
+
+  assert![a.acquire()?, "a takes the free lease"];
+  assert_eq![etcd.lock().unwrap().keys["ZXlyb3MvbGVhZGVy"].0, "AAAAAAAAAAE=", "a's id"];
+  assert![!b.acquire()?, "b waits while the lease is held"];
+  assert![a.renew()?, "a renews"];
+  assert![a.acquire()?, "a already holds the lease"];
+  assert![!b.renew()?, "b isn't the leader"];
+  assert![a.is_leader() && !b.is_leader()];
+
+  etcd.lock().unwrap().expire(1);
+  assert![b.acquire()?, "b takes over the expired lease"];
+  assert![!a.renew()?, "a lost the lease"];
+  assert![!a.is_leader()];
+
+  b.release()?;
+  assert![!b.is_leader()];
+  assert![a.acquire()?, "a takes the released lease"];
+  Ok(())
+}
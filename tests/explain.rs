@@ -0,0 +1,69 @@
+use eyros::{Setup,DB,Row,QueryPlan};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn explain() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    Ok(RandomAccessDisk::builder(dir.path().join(name)).auto_sync(false).build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .selectivity_order(true)
+    .build()?;
+  let all = ((-1.0,-1.0),(1.0,1.0));
+  assert_eq![db.explain(&all)?, QueryPlan::default()];
+
+  let mut r = rand().seed([6,66]);
+  let mut n = 0;
+  for size in [2_000,700,100].iter() {
+    let batch: Vec<Row<P,V>> = (0..*size).map(|_| {
+      n += 1;
+      Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), n)
+    }).collect();
+    db.batch(&batch)?;
+  }
+
+  // rows past the last multiple of base_size stay in the staging area
+  let plan = db.explain(&all)?;
+  assert_eq![plan.staged, 300];
+  assert_eq![plan.staged_matches, 300];
+  assert_eq![plan.cost, db.estimate_cost(&all)?];
+  assert_eq![plan.cost.rows, n as u64];
+  let stats = db.tree_stats()?;
+  assert_eq![plan.trees.len(), stats.len()];
+  for t in plan.trees.iter() {
+    let s = stats.iter().find(|s| s.index == t.index).unwrap();
+    assert_eq![t.cost.blocks, s.data_blocks];
+    assert_eq![t.cost.rows, s.rows];
+    assert![t.estimate.is_some()];
+  }
+  // trees are listed in the order that queries visit them
+  for pair in plan.trees.windows(2) {
+    assert![pair[0].estimate >= pair[1].estimate];
+  }
+
+  // a small box reads fewer blocks, and explaining it doesn't read any
+  let bbox = ((-0.3,0.1),(0.2,0.4));
+  db.reset_stats()?;
+  let small = db.explain(&bbox)?;
+  let s = db.stats()?;
+  assert_eq![s.block_cache_hits + s.block_cache_misses, 0];
+  assert_eq![small.cost, db.estimate_cost(&bbox)?];
+  assert![small.cost.blocks > 0 && small.cost.blocks < plan.cost.blocks];
+  assert![small.staged_matches < small.staged];
+  let found = db.query(&bbox)?.count() as u64;
+  assert![small.cost.rows_inside <= found && found <= small.cost.rows];
+
+  let text = small.to_string();
+  assert![text.starts_with("staging: 300 rows, 0 deletes")];
+  assert_eq![text.lines().count(), small.trees.len()+2];
+  Ok(())
+}
@@ -0,0 +1,146 @@
+use eyros::{Setup,DB,Row,MultiDB};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::collections::BTreeMap;
+use std::path::{Path,PathBuf};
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn export_sharded() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut db: DB<_,_,P,V> = Setup::new(|name: &str| {
+      let p = dir.path().join("db").join(name);
+      Ok(RandomAccessDisk::builder(p)
+        .auto_sync(false)
+        .build()?)
+    })
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let batch: Vec<Row<P,V>> = (0..1_800).map(|i| {
+    let x: f32 = r.read::<f32>()*2.0-1.0;
+    let y: f32 = r.read::<f32>()*2.0-1.0;
+    Row::Insert((x,y), i)
+  }).collect();
+  db.batch(&batch)?;
+
+  let grid = vec![
+    ("sw".to_string(), ((-1.0,-1.0),(0.0,0.0))),
+    ("se".to_string(), ((0.0,-1.0),(1.0,0.0))),
+    ("n".to_string(), ((-1.0,0.0),(1.0,1.0))),
+  ];
+  let open_shard = |cell: &str, name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join("shards").join(cell).join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let shards = db.export_sharded(&grid, &open_shard)?;
+  assert_eq![shards.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(),
+    vec!["sw","se","n"]];
+
+  let mut total = 0;
+  for ((cell,bbox),info) in grid.iter().zip(shards.iter()) {
+    let mut expected: Vec<V> = db.query(bbox)?
+      .map(|r| r.map(|row| row.1)).collect::<Result<_,Error>>()?;
+    expected.sort();
+    let mut shard: DB<_,_,P,V> = Setup::new(|name: &str| open_shard(cell, name))
+      .max_data_size(100)
+      .base_size(500)
+      .build()?;
+    let mut values: Vec<V> = shard.query(&((-1.0,-1.0),(1.0,1.0)))?
+      .map(|r| r.map(|row| row.1)).collect::<Result<_,Error>>()?;
+    values.sort();
+    assert_eq![values, expected, "shard {} holds its cell", cell];
+    assert_eq![info.rows, values.len()];
+    total += values.len();
+  }
+  assert![total >= 1_800, "every row is in at least one shard"];
+  Ok(())
+}
+
+#[test]
+fn attach_bundles() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let root = dir.path().to_path_buf();
+  let mut db: DB<_,_,P,V> = Setup::new(|name: &str| {
+      let p = root.join("db").join(name);
+      Ok(RandomAccessDisk::builder(p)
+        .auto_sync(false)
+        .build()?)
+    })
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let batch: Vec<Row<P,V>> = (0..1_800).map(|i| {
+    let x: f32 = r.read::<f32>()*2.0-1.0;
+    let y: f32 = r.read::<f32>()*2.0-1.0;
+    Row::Insert((x,y), i)
+  }).collect();
+  db.batch(&batch)?;
+  let grid = vec![
+    ("w".to_string(), ((-1.0,-1.0),(-0.0001,1.0))),
+    ("e".to_string(), ((0.0,-1.0),(1.0,1.0))),
+  ];
+  let shard_dir = root.join("shards");
+  db.export_sharded(&grid, |cell,name| {
+    Ok(RandomAccessDisk::builder(shard_dir.join(cell).join(name))
+      .auto_sync(false)
+      .build()?)
+  })?;
+
+  let shard_dir = root.join("shards");
+  let before = read_dir(&shard_dir)?;
+  let mut multi: MultiDB<_,P,V> = MultiDB::new(move |bundle,name| {
+      Ok(RandomAccessDisk::builder(shard_dir.join(bundle).join(name))
+        .auto_sync(false)
+        .build()?)
+    })
+    .max_data_size(100)
+    .base_size(500);
+  let bbox = ((-0.5,-0.5),(0.5,0.5));
+  let query = |multi: &mut MultiDB<_,P,V>| -> Result<Vec<V>,Error> {
+    let mut values: Vec<V> = multi.query(&bbox)?
+      .map(|r| r.map(|row| row.1)).collect::<Result<_,Error>>()?;
+    values.sort();
+    Ok(values)
+  };
+  let mut expected = |bbox| -> Result<Vec<V>,Error> {
+    let mut values: Vec<V> = db.query(&bbox)?
+      .map(|r| r.map(|row| row.1)).collect::<Result<_,Error>>()?;
+    values.sort();
+    Ok(values)
+  };
+  assert_eq![query(&mut multi)?, Vec::<V>::new(), "nothing attached"];
+  assert![multi.attach_bundle("w")?];
+  assert![!multi.attach_bundle("w")?, "already attached"];
+  assert_eq![query(&mut multi)?, expected(((-0.5,-0.5),(-0.0001,0.5)))?];
+  assert![multi.attach_bundle("e")?];
+  assert_eq![multi.bundles(), vec!["w","e"]];
+  assert_eq![query(&mut multi)?, expected(bbox)?, "spans both shards"];
+  assert![multi.detach_bundle("w")?];
+  assert_eq![query(&mut multi)?, expected(((0.0,-0.5),(0.5,0.5)))?];
+  assert![multi.detach_bundle("e")?];
+  assert![read_dir(&root.join("shards"))? == before, "bundles aren't written"];
+  Ok(())
+}
+
+// contents of every file under `dir`, by path
+fn read_dir (dir: &Path) -> Result<BTreeMap<PathBuf,Vec<u8>>,Error> {
+  let mut files = BTreeMap::new();
+  for entry in std::fs::read_dir(dir)? {
+    let path = entry?.path();
+    if path.is_dir() {
+      files.extend(read_dir(&path)?);
+    } else {
+      files.insert(path.clone(), std::fs::read(&path)?);
+    }
+  }
+  Ok(files)
+}
@@ -0,0 +1,56 @@
+use eyros::{Setup,DB,Row,Locked,ReadOnly};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn file_lock() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let batch: Vec<Row<P,V>> = (0..200).map(|i| {
+    let x = (i as f32)/100.0-1.0;
+    Row::Insert((x,-x), i)
+  }).collect();
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+
+  let mut writer: DB<_,_,P,V> = DB::open_dir(dir.path(), |s| s)?;
+  writer.batch(&batch)?;
+
+  let err = DB::<_,_,P,V>::open_dir(dir.path(), |s| s)
+    .err().expect("second writer fails to open");
+  assert![err.downcast_ref::<Locked>().is_some(), "second writer: {}", err];
+
+  let mut reader: DB<_,_,P,V> = DB::open_dir(dir.path(), |s| s.read_only(true))?;
+  assert_eq![reader.query(&bbox)?.count(), batch.len()];
+  let err = reader.batch(&batch).err().expect("reader batch fails");
+  assert![err.downcast_ref::<ReadOnly>().is_some(), "reader batch: {}", err];
+
+  let unlocked: DB<_,_,P,V> = DB::open_dir(dir.path(), |s| s.lock(false))?;
+  drop(unlocked);
+
+  writer.close()?;
+  let mut writer: DB<_,_,P,V> = DB::open_dir(dir.path(), |s| s)?;
+  assert_eq![writer.query(&bbox)?.count(), batch.len()];
+  drop(writer);
+  DB::<_,_,P,V>::open_dir(dir.path(), |s| s)?;
+  Ok(())
+}
+
+#[test]
+fn lock_file_setup() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    RandomAccessDisk::open(dir.path().join(name))
+  };
+  let lock = dir.path().join("lock");
+  let mut db: DB<_,_,P,V> = Setup::new(&storage).lock_file(&lock).build()?;
+  db.batch(&[Row::Insert((0.5,0.5),1)])?;
+  let err = Setup::new(&storage).lock_file(&lock).build::<P,V>()
+    .err().expect("second writer fails to open");
+  assert![err.downcast_ref::<Locked>().is_some(), "second writer: {}", err];
+  let mut reader: DB<_,_,P,V> = DB::open_read_only(&storage)?;
+  assert_eq![reader.query(&((0.0,0.0),(1.0,1.0)))?.count(), 1];
+  Ok(())
+}
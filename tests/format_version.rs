@@ -0,0 +1,79 @@
+use eyros::{Setup,DB,Row,FormatVersion,FORMAT_VERSION};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn format_version() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    RandomAccessDisk::open(dir.path().join(name))
+  };
+  let batch: Vec<Row<P,V>> = (0..300).map(|i| {
+    let x = (i as f32)/150.0-1.0;
+    Row::Insert((x,x), i)
+  }).collect();
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  {
+    let mut db: DB<_,_,P,V> = Setup::new(&storage).base_size(100).build()?;
+    db.batch(&batch)?;
+  }
+  let path = dir.path().join("meta");
+  let meta = std::fs::read(&path)?;
+  assert_eq![&meta[0..4], b"EYRS"];
+  assert_eq![u16::from_be_bytes([meta[4],meta[5]]), FORMAT_VERSION];
+  let body = record(&meta)[8..].to_vec();
+
+  // databases from before the header or the record slots are upgraded when
+  // opened for writing
+  let mut v1 = b"EYRS".to_vec();
+  v1.extend(&[0,1,0,0]);
+  v1.extend(&body);
+  for old in [body.clone(), v1].iter() {
+    std::fs::write(&path, old)?;
+    {
+      let mut db: DB<_,_,P,V> = Setup::new(&storage).read_only(true).build()?;
+      assert_eq![db.query(&bbox)?.count(), batch.len()];
+    }
+    assert_eq![&std::fs::read(&path)?, old];
+    {
+      let mut db: DB<_,_,P,V> = Setup::new(&storage).build()?;
+      assert_eq![db.query(&bbox)?.count(), batch.len()];
+    }
+    let upgraded = std::fs::read(&path)?;
+    assert_eq![&upgraded[0..4], b"EYRS"];
+    assert_eq![u16::from_be_bytes([upgraded[4],upgraded[5]]), FORMAT_VERSION];
+    assert_eq![&record(&upgraded)[8..], &body[..]];
+  }
+  let meta = std::fs::read(&path)?;
+
+  // newer versions are rejected instead of misread
+  let mut newer = meta.clone();
+  newer[4..6].copy_from_slice(&(FORMAT_VERSION+1).to_be_bytes());
+  std::fs::write(&path, &newer)?;
+  let err = Setup::new(&storage).build::<P,V>()
+    .err().expect("newer format version fails to open");
+  assert_eq![
+    err.downcast_ref::<FormatVersion>(),
+    Some(&FormatVersion { found: FORMAT_VERSION+1, supported: FORMAT_VERSION })
+  ];
+  assert_eq![std::fs::read(&path)?, newer];
+  Ok(())
+}
+
+// The newest record that the slots after the header point at.
+fn record (meta: &[u8]) -> &[u8] {
+  let u64_at = |i: usize| {
+    let mut b = [0u8;8];
+    b.copy_from_slice(&meta[i..i+8]);
+    u64::from_be_bytes(b)
+  };
+  let (_,offset,len) = (0..2).map(|slot| 8+slot*32)
+    .filter(|i| &meta[*i..*i+4] == b"EYSB")
+    .map(|i| (u64_at(i+4), u64_at(i+12) as usize, (u64_at(i+16) & 0xffff_ffff) as usize))
+    .max().expect("a record slot");
+  &meta[offset..offset+len]
+}
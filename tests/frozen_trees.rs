@@ -0,0 +1,112 @@
+use eyros::{Setup,DB,Row};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random_access_storage::RandomAccess;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::cell::Cell;
+use std::io;
+use std::rc::Rc;
+
+type P = (f32,f32);
+type V = u32;
+
+// counts reads of tree files
+struct CountingStore {
+  store: RandomAccessDisk,
+  reads: Rc<Cell<usize>>
+}
+
+impl RandomAccess for CountingStore {
+  type Error = failure::Error;
+  fn write (&mut self, offset: u64, data: &[u8]) -> Result<(),failure::Error> {
+    self.store.write(offset, data)
+  }
+  fn read (&mut self, offset: u64, length: u64) -> Result<Vec<u8>,failure::Error> {
+    self.reads.set(self.reads.get()+1);
+    self.store.read(offset, length)
+  }
+  fn read_to_writer (&mut self, offset: u64, length: u64,
+  buf: &mut impl io::Write) -> Result<(),failure::Error> {
+    self.store.read_to_writer(offset, length, buf)
+  }
+  fn del (&mut self, offset: u64, length: u64) -> Result<(),failure::Error> {
+    self.store.del(offset, length)
+  }
+  fn truncate (&mut self, length: u64) -> Result<(),failure::Error> {
+    self.store.truncate(length)
+  }
+  fn len (&self) -> Result<u64,failure::Error> {
+    self.store.len()
+  }
+  fn is_empty (&mut self) -> Result<bool,failure::Error> {
+    self.store.is_empty()
+  }
+  fn sync_all (&mut self) -> Result<(),failure::Error> {
+    self.store.sync_all()
+  }
+}
+
+#[test]
+fn frozen_trees() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let reads = Rc::new(Cell::new(0));
+  let storage = |name: &str| -> Result<CountingStore,failure::Error> {
+    let p = dir.path().join(name);
+    let store = RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?;
+    Ok(CountingStore {
+      store,
+      reads: if name.starts_with("tree") { Rc::clone(&reads) }
+        else { Rc::new(Cell::new(0)) }
+    })
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let mut count = 0;
+  let mut batch = |n| -> Vec<Row<P,V>> {
+    (0..n).map(|_| {
+      count += 1;
+      let x: f32 = r.read::<f32>();
+      let y: f32 = r.read::<f32>()*0.01;
+      Row::Insert((x,y), count)
+    }).collect()
+  };
+  db.batch(&batch(2_000))?; // one tree at index 2
+  assert_eq![db.frozen_trees()?, Vec::<usize>::new()];
+  assert![!db.freeze_tree(0)?, "empty trees aren't frozen"];
+  assert![db.freeze_tree(2)?];
+  assert_eq![db.frozen_trees()?, vec![2]];
+
+  let bbox = ((0.1,0.0),(0.3,0.005));
+  let collect = |db: &mut DB<_,_,P,V>| -> Result<Vec<V>,Error> {
+    let mut values = db.query(&bbox)?
+      .map(|r| r.map(|row| row.1))
+      .collect::<Result<Vec<_>,Error>>()?;
+    values.sort();
+    Ok(values)
+  };
+  reads.set(0);
+  let frozen = collect(&mut db)?;
+  assert_eq![reads.get(), 0, "no tree reads while frozen"];
+  db.thaw_tree(2)?;
+  assert_eq![db.frozen_trees()?, Vec::<usize>::new()];
+  let thawed = collect(&mut db)?;
+  assert![reads.get() > 0, "thawed trees read from the store"];
+  assert_eq![frozen, thawed];
+  assert![!frozen.is_empty()];
+
+  assert![db.freeze_tree(2)?];
+  db.batch(&batch(600))?; // tree 0 added, tree 2 unchanged
+  assert_eq![db.frozen_trees()?, vec![2]];
+  db.batch(&batch(1_500))?; // tree 2 merged into tree 3
+  assert_eq![db.frozen_trees()?, Vec::<usize>::new(), "rebuilding drops the image"];
+  assert![db.freeze_tree(3)?];
+  let all = ((0.0,0.0),(1.0,1.0));
+  assert_eq![db.query(&all)?.count(), 4_100];
+  Ok(())
+}
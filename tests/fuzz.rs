@@ -0,0 +1,80 @@
+use eyros::{Setup,DB,Row,Fuzz,Fuzzable};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::collections::HashMap;
+
+type P = (f64,f64);
+type V = u32;
+
+#[test]
+fn fuzz() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join("db").join(name);
+    Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([7,9]);
+  let points: Vec<P> = (0..1_500).map(|_| {
+    (r.read::<f64>()*2.0-1.0, r.read::<f64>()*2.0-1.0)
+  }).collect();
+  let batch: Vec<Row<P,V>> = points.iter().enumerate()
+    .map(|(i,p)| Row::Insert(*p,i as u32))
+    .collect();
+  db.batch(&batch)?;
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+
+  let jitter = Fuzz::Jitter { radius: 0.05, seed: 1234 };
+  let mut first: HashMap<V,P> = HashMap::new();
+  let mut moved = 0;
+  for result in db.query_fuzzed(&bbox, jitter)? {
+    let (p,v,_) = result?;
+    let exact = points[v as usize];
+    assert![(p.0-exact.0).abs() <= 0.05 && (p.1-exact.1).abs() <= 0.05];
+    if p != exact { moved += 1 }
+    first.insert(v, p);
+  }
+  assert_eq![first.len(), points.len()];
+  assert![moved > points.len()*9/10, "most points are moved"];
+  for result in db.query_fuzzed(&bbox, jitter)? {
+    let (p,v,_) = result?;
+    assert_eq![first[&v], p, "jitter is stable across queries"];
+  }
+  let other = Fuzz::Jitter { radius: 0.05, seed: 4321 };
+  assert_ne![points[0].fuzz(&jitter), points[0].fuzz(&other)];
+
+  let snap = Fuzz::Snap { cell: 0.25 };
+  for result in db.query_fuzzed(&bbox, snap)? {
+    let (p,v,_) = result?;
+    let exact = points[v as usize];
+    let center = |x: f64| (x/0.25).floor()*0.25 + 0.125;
+    assert_eq![p, (center(exact.0),center(exact.1))];
+  }
+  let iv = ((0.3f32,0.6f32),(-0.1f32,0.1f32)).fuzz(&snap);
+  assert_eq![iv, ((0.25,0.75),(-0.25,0.25))];
+  assert![db.query_fuzzed(&bbox, Fuzz::Snap { cell: 0.0 }).is_err()];
+
+  let grid = vec![("all".to_string(), bbox)];
+  let shards = db.export_sharded_fuzzed(&grid, snap, |cell,name| {
+    let p = dir.path().join(cell).join(name);
+    Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  })?;
+  assert_eq![shards[0].rows, points.len()];
+  let mut shard: DB<_,_,P,V> = Setup::new(|name: &str| {
+    let p = dir.path().join("all").join(name);
+    Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  }).build()?;
+  let mut n = 0;
+  for result in shard.query(&bbox)? {
+    let (p,v,_) = result?;
+    assert_eq![p, points[v as usize].fuzz(&snap), "shards hold fuzzed points"];
+    n += 1;
+  }
+  assert_eq![n, points.len()];
+  Ok(())
+}
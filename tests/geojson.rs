@@ -0,0 +1,89 @@
+#![cfg(feature="geojson")]
+use eyros::{Setup,DB,Row,Mix,Mix2,geojson};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use tempfile::Builder as Tmpfile;
+use serde_json::{Value as Json,json};
+
+type P = Mix2<f64,f64>;
+type V = Vec<u8>;
+
+const FEATURES: &str = r#"{
+  "type": "FeatureCollection",
+  "features": [
+    { "type": "Feature", "properties": { "name": "gate" },
+      "geometry": { "type": "Point", "coordinates": [13.3777,52.5163] } },
+    { "type": "Feature", "properties": { "name": "park" },
+      "geometry": { "type": "Polygon", "coordinates": [[
+        [13.33,52.51],[13.37,52.51],[13.37,52.52],[13.33,52.52],[13.33,52.51]
+      ]] } },
+    { "type": "Feature", "properties": { "name": "meridian" },
+      "geometry": { "type": "LineString", "coordinates": [[13.4,52.4],[13.4,52.6]] } }
+  ]
+}
+{ "type": "Feature", "properties": { "name": "far" },
+  "geometry": { "type": "MultiPoint", "coordinates": [[-70.0,10.0],[-71.0,11.0]] } }
+"#;
+
+#[test]
+fn geojson() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage).build()?;
+  let rows: Vec<Row<P,V>> = geojson::read(FEATURES.as_bytes(), |feature| {
+    Ok(serde_json::to_vec(&feature["properties"])?)
+  })?;
+  assert_eq![rows.len(), 4];
+  match &rows[1] {
+    Row::Insert(p,_) => assert_eq![*p, Mix2::new(
+      Mix::Interval(13.33,13.37), Mix::Interval(52.51,52.52))],
+    _ => panic!["expected an insert"]
+  }
+  match &rows[2] {
+    Row::Insert(p,_) => assert_eq![*p, Mix2::new(
+      Mix::Scalar(13.4), Mix::Interval(52.4,52.6))],
+    _ => panic!["expected an insert"]
+  }
+  db.batch(&rows)?;
+
+  let mut buf = vec![];
+  let bbox = ((13.0,52.0),(14.0,53.0));
+  let n = geojson::write(&mut buf, db.query(&bbox)?, |value| {
+    Ok(serde_json::from_slice(value)?)
+  })?;
+  assert_eq![n, 3];
+  let doc: Json = serde_json::from_slice(&buf)?;
+  assert_eq![doc["type"], "FeatureCollection"];
+  let mut features = doc["features"].as_array().unwrap().clone();
+  features.sort_by_key(|f| f["properties"]["name"].as_str().unwrap().to_string());
+  assert_eq![features[0], json!({
+    "type": "Feature",
+    "properties": { "name": "gate" },
+    "geometry": { "type": "Point", "coordinates": [13.3777,52.5163] }
+  })];
+  assert_eq![features[1]["geometry"]["type"], "LineString"];
+  assert_eq![features[2]["geometry"]["type"], "Polygon"];
+
+  // the written features read back into the same rows
+  let again: Vec<Row<P,V>> = geojson::read(buf.as_slice(), |_| Ok(vec![]))?;
+  let mut again: Vec<P> = again.into_iter().map(|row| match row {
+    Row::Insert(p,_) => p,
+    _ => panic!["expected an insert"]
+  }).collect();
+  let mut expected: Vec<P> = rows[0..3].iter().map(|row| match row {
+    Row::Insert(p,_) => *p,
+    _ => panic!["expected an insert"]
+  }).collect();
+  let key = |p: &P| format!["{:?}", p];
+  again.sort_by_key(key);
+  expected.sort_by_key(key);
+  assert_eq![again, expected];
+
+  let points: Result<Vec<Row<(f64,f64),u32>>,Error> =
+    geojson::read(FEATURES.as_bytes(), |_| Ok(0));
+  assert![points.is_err(), "polygons don't fit in points"];
+  Ok(())
+}
@@ -0,0 +1,119 @@
+use eyros::{Setup,Row,GroupCommit};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use tempfile::Builder as Tmpfile;
+use std::{sync::Arc,thread,time::Duration};
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn group_commit() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let path = dir.path().to_path_buf();
+  let db = Setup::new(move |name: &str| {
+      Ok(RandomAccessDisk::builder(path.join(name)).auto_sync(false).build()?)
+    })
+    .max_data_size(500)
+    .base_size(500)
+    .check_conflicts(true) // every commit bumps the sequence
+    .build::<P,V>()?;
+  let group = Arc::new(GroupCommit::new(db));
+  let writers = 8;
+  let rounds = 40;
+
+  // writers queue up behind a held lock and commit as one group
+  let sequence = {
+    let db = group.lock()?;
+    let seq = db.sequence();
+    let handles: Vec<_> = (0..writers).map(|w| {
+      let group = Arc::clone(&group);
+      thread::spawn(move || group.batch(&[Row::Insert((0.0,0.0),w)]))
+    }).collect();
+    while group.pending()? < writers as usize {
+      thread::sleep(Duration::from_millis(1));
+    }
+    drop(db);
+    for h in handles {
+      h.join().unwrap()?;
+    }
+    seq
+  };
+  assert_eq![group.lock()?.sequence(), sequence+1];
+
+  let handles: Vec<_> = (0..writers).map(|w| {
+    let group = Arc::clone(&group);
+    thread::spawn(move || -> Result<(),Error> {
+      for i in 0..rounds {
+        let v = 1_000 + w*rounds + i;
+        let x = (v % 97) as f32 / 100.0;
+        group.batch(&[Row::Insert((x,x),v)])?;
+      }
+      Ok(())
+    })
+  }).collect();
+  for h in handles {
+    h.join().unwrap()?;
+  }
+  let mut db = Arc::try_unwrap(group).ok().unwrap().into_inner()?;
+  assert![db.sequence() <= sequence + 1 + (writers*rounds) as u64];
+  let mut values: Vec<V> = db.query(&((-1.0,-1.0),(1.0,1.0)))?
+    .map(|r| r.map(|row| row.1)).collect::<Result<_,Error>>()?;
+  values.sort();
+  let mut expected: Vec<V> = (0..writers).collect();
+  expected.extend(1_000..1_000+writers*rounds);
+  assert_eq![values, expected];
+  Ok(())
+}
+
+#[test]
+fn group_commit_errors() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let path = dir.path().to_path_buf();
+  let db = Setup::new(move |name: &str| {
+      Ok(RandomAccessDisk::builder(path.join(name)).auto_sync(false).build()?)
+    })
+    .build::<P,V>()?;
+  let group = Arc::new(GroupCommit::new(db));
+  let batches = vec![
+    vec![Row::Insert((0.1,0.1),1)],
+    vec![Row::Insert((f32::NAN,0.2),2)],
+    vec![Row::Delete((1_000_000,0))],
+    vec![Row::Insert((0.3,0.3),3)],
+  ];
+  let run = |batches: Vec<Vec<Row<P,V>>>| -> Result<Vec<Result<(),Error>>,Error> {
+    let db = group.lock()?;
+    let n = batches.len();
+    let handles: Vec<_> = batches.into_iter().map(|rows| {
+      let group = Arc::clone(&group);
+      thread::spawn(move || group.batch(&rows))
+    }).collect();
+    while group.pending()? < n {
+      thread::sleep(Duration::from_millis(1));
+    }
+    drop(db);
+    Ok(handles.into_iter().map(|h| h.join().unwrap()).collect())
+  };
+
+  // writers with bad rows fail alone
+  let results = run(batches)?;
+  assert![results[0].is_ok() && results[3].is_ok()];
+  assert![matches![results[1], Err(Error::Invalid(_))], "NaN point"];
+  assert![matches![results[2], Err(Error::Invalid(_))], "location past the end"];
+  let mut values: Vec<V> = group.lock()?.query(&((-1.0,-1.0),(1.0,1.0)))?
+    .map(|r| r.map(|row| row.1)).collect::<Result<_,Error>>()?;
+  values.sort();
+  assert_eq![values, vec![1,3]];
+
+  // a failed commit gives every writer in the group the typed error
+  group.lock()?.close()?;
+  let results = run(vec![
+    vec![Row::Insert((0.4,0.4),4)],
+    vec![Row::Insert((0.5,0.5),5)],
+    vec![Row::Insert((0.6,0.6),6)],
+  ])?;
+  for r in results {
+    assert![matches![r, Err(Error::Closed(_))], "{:?}", r];
+  }
+  Ok(())
+}
@@ -0,0 +1,161 @@
+#![cfg(feature="hypercore")]
+use eyros::{DB,Row,Feed,HypercoreStore,hypercore_storage};
+use eyros::Error;
+use random_access_storage::RandomAccess;
+use random::{Source,default as rand};
+use std::collections::HashMap;
+use std::sync::{Arc,Mutex};
+
+type P = ((f32,f32),(f32,f32));
+type V = u32;
+type Entries = Arc<Mutex<Vec<Vec<u8>>>>;
+
+// in-memory log, with an optional remote log that missing entries are
+// downloaded from and a count of the downloads
+#[derive(Clone,Default)]
+struct MemoryFeed {
+  local: Arc<Mutex<HashMap<u64,Vec<u8>>>>,
+  len: Arc<Mutex<u64>>,
+  remote: Option<Entries>,
+  downloads: Arc<Mutex<u64>>
+}
+
+impl MemoryFeed {
+  fn replica (remote: Entries) -> Self {
+    let len = remote.lock().unwrap().len() as u64;
+    Self { len: Arc::new(Mutex::new(len)), remote: Some(remote), ..Default::default() }
+  }
+  fn entries (&self) -> Entries {
+    let local = self.local.lock().unwrap();
+    let len = *self.len.lock().unwrap();
+    Arc::new(Mutex::new((0..len).map(|i| local[&i].clone()).collect()))
+  }
+}
+
+impl Feed for MemoryFeed {
+  fn len (&self) -> Result<u64,failure::Error> {
+    Ok(*self.len.lock().unwrap())
+  }
+  fn get (&mut self, index: u64) -> Result<Vec<u8>,failure::Error> {
+    if let Some(buf) = self.local.lock().unwrap().get(&index) {
+      return Ok(buf.clone());
+    }
+    let buf = match &self.remote {
+      Some(remote) => remote.lock().unwrap()[index as usize].clone(),
+      None => failure::bail!["missing entry {}", index]
+    };
+    *self.downloads.lock().unwrap() += 1;
+    self.local.lock().unwrap().insert(index, buf.clone());
+    Ok(buf)
+  }
+  fn append (&mut self, data: &[u8]) -> Result<(),failure::Error> {
+    let mut len = self.len.lock().unwrap();
+    self.local.lock().unwrap().insert(*len, data.to_vec());
+    *len += 1;
+    Ok(())
+  }
+}
+
+#[test]
+fn hypercore_store() -> Result<(),Error> {
+  let (index,content) = (MemoryFeed::default(), MemoryFeed::default());
+  let mut store = HypercoreStore::open(index.clone(), content.clone())?;
+  let mut model: Vec<u8> = vec![];
+  let mut r = rand().seed([5,6]);
+  for i in 0..500 {
+    let len = model.len() as u64;
+    match r.read::<u32>() % 10 {
+      0..=6 => {
+        let offset = match i % 3 {
+          0 => len,
+          1 => r.read::<u64>() % (len+1),
+          _ => len + r.read::<u64>() % 100
+        };
+        let data: Vec<u8> = (0..1+r.read::<u64>() % 300).map(|_| r.read::<u8>()).collect();
+        store.write(offset, &data)?;
+        let end = offset as usize + data.len();
+        if model.len() < end { model.resize(end, 0) }
+        model[offset as usize..end].copy_from_slice(&data);
+      },
+      7 => {
+        let n = r.read::<u64>() % (len+100);
+        store.truncate(n)?;
+        model.resize(n as usize, 0);
+      },
+      _ => {
+        let offset = r.read::<u64>() % (len+1);
+        let n = r.read::<u64>() % 300;
+        store.del(offset, n)?;
+        let end = (offset+n).min(len) as usize;
+        for b in model[offset as usize..end].iter_mut() { *b = 0 }
+      }
+    }
+    assert_eq![store.len()?, model.len() as u64];
+    let offset = r.read::<u64>() % (model.len() as u64 + 1);
+    let n = r.read::<u64>() % (model.len() as u64 - offset + 1);
+    assert_eq![store.read(offset, n)?, &model[offset as usize..(offset+n) as usize]];
+  }
+  // replaying the feeds gives the same store
+  let mut reopened = HypercoreStore::open(index, content)?;
+  assert_eq![reopened.read(0, model.len() as u64)?, model];
+  Ok(())
+}
+
+#[test]
+fn hypercore_replica() -> Result<(),Error> {
+  let feeds: Arc<Mutex<HashMap<String,MemoryFeed>>> = Arc::default();
+  let origin = {
+    let feeds = Arc::clone(&feeds);
+    hypercore_storage(move |name: &str| {
+      Ok(feeds.lock().unwrap().entry(name.to_string()).or_default().clone())
+    })
+  };
+  let mut r = rand().seed([7,8]);
+  let batch: Vec<Row<P,V>> = (0..5_000).map(|i| {
+    let x = r.read::<f32>()*2.0-1.0;
+    let y = r.read::<f32>()*2.0-1.0;
+    Row::Insert(((x,x+0.01),(y,y+0.01)), i)
+  }).collect();
+  {
+    let mut db: DB<_,_,P,V> = eyros::Setup::new(origin)
+      .max_data_size(100)
+      .base_size(1_000)
+      .build()?;
+    db.batch(&batch)?;
+  }
+
+  // a replica gets the index feeds in full and content entries on demand
+  let remotes: HashMap<String,Entries> = feeds.lock().unwrap().iter()
+    .map(|(name,feed)| (name.clone(),feed.entries()))
+    .collect();
+  let replicas: Arc<Mutex<HashMap<String,MemoryFeed>>> = Arc::default();
+  let replica = {
+    let replicas = Arc::clone(&replicas);
+    hypercore_storage(move |name: &str| {
+      let remote = remotes.get(name).cloned().unwrap_or_default();
+      Ok(replicas.lock().unwrap().entry(name.to_string())
+        .or_insert_with(|| MemoryFeed::replica(remote)).clone())
+    })
+  };
+  let mut db: DB<_,_,P,V> = DB::open(replica)?;
+  let bbox = ((-0.1,-0.1),(0.05,0.05));
+  let mut values: Vec<V> = db.query(&bbox)?
+    .map(|row| row.map(|(_,v,_)| v))
+    .collect::<Result<Vec<_>,Error>>()?;
+  values.sort();
+  let mut expected: Vec<V> = batch.iter().filter_map(|row| match row {
+    Row::Insert(((x0,x1),(y0,y1)),v) => {
+      if *x0 <= 0.05 && -0.1 <= *x1 && *y0 <= 0.05 && -0.1 <= *y1 { Some(*v) } else { None }
+    },
+    _ => None
+  }).collect();
+  expected.sort();
+  assert_eq![values, expected];
+
+  let replicas = replicas.lock().unwrap();
+  let data = &replicas["data"];
+  let fetched = *data.downloads.lock().unwrap();
+  assert![fetched > 0];
+  assert![fetched < data.len()?/2, "fetched {} of {} data entries", fetched, data.len()?];
+  Ok(())
+}
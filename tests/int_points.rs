@@ -0,0 +1,112 @@
+use eyros::{Setup,DB,Row,Point};
+use eyros::Error;
+use random::{Source,default as rand};
+use random_access_disk::RandomAccessDisk;
+use tempfile::Builder as Tmpfile;
+use std::path::PathBuf;
+
+fn storage (dir: PathBuf) -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> {
+  move |name: &str| {
+    Ok(RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build()?)
+  }
+}
+
+// runs the query and a linear scan over the inserted rows and compares them
+fn check<P> (db: &mut DB<RandomAccessDisk,impl Fn(&str)
+-> Result<RandomAccessDisk,failure::Error>,P,u32>, inserted: &[(P,u32)],
+bbox: &P::Bounds) -> Result<usize,Error> where P: Point+'static {
+  let mut expected: Vec<u32> = inserted.iter()
+    .filter(|(p,_)| p.overlaps(bbox))
+    .map(|(_,v)| *v)
+    .collect();
+  let mut results = db.query(bbox)?
+    .map(|r| r.map(|(_,v,_)| v))
+    .collect::<Result<Vec<u32>,Error>>()?;
+  expected.sort();
+  results.sort();
+  assert_eq![results, expected];
+  Ok(results.len())
+}
+
+#[test]
+fn grid_u64() -> Result<(),Error> {
+  type P = (u64,u64);
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut db: DB<_,_,P,u32> = Setup::new(storage(dir.path().to_path_buf()))
+    .base_size(200)
+    .max_data_size(50)
+    .build()?;
+  let mut r = rand().seed([7,11]);
+  // coordinates cluster at the top of the range, where the sum of two
+  // coordinates overflows
+  let inserted: Vec<(P,u32)> = (0..5_000).map(|i| {
+    let x = u64::MAX - r.read::<u64>() % 1_000_000;
+    let y = r.read::<u64>();
+    ((x,y),i)
+  }).collect();
+  for chunk in inserted.chunks(1_000) {
+    db.batch(&chunk.iter().map(|(p,v)| Row::Insert(*p,*v)).collect::<Vec<_>>())?;
+  }
+  assert![check(&mut db, &inserted, &((u64::MAX-500_000,0),(u64::MAX,u64::MAX/2)))? > 0];
+  assert_eq![check(&mut db, &inserted, &((0,0),(u64::MAX,u64::MAX)))?, inserted.len()];
+  assert_eq![check(&mut db, &inserted, &((0,0),(u64::MAX-1_000_000,u64::MAX)))?, 0];
+  Ok(())
+}
+
+#[test]
+fn telemetry_4d() -> Result<(),Error> {
+  // (x,y,z,t) where t is an interval of signed timestamps
+  type P = (f32,f32,f32,(i64,i64));
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut db: DB<_,_,P,u32> = Setup::new(storage(dir.path().to_path_buf()))
+    .base_size(200)
+    .max_data_size(50)
+    .build()?;
+  let mut r = rand().seed([3,5]);
+  let inserted: Vec<(P,u32)> = (0..5_000).map(|i| {
+    let t = (r.read::<u32>() % 100_000) as i64 - 50_000;
+    let p = (
+      r.read::<f32>()*2.0-1.0,
+      r.read::<f32>()*2.0-1.0,
+      r.read::<f32>()*100.0,
+      (t, t + (r.read::<u32>() % 600) as i64)
+    );
+    (p,i)
+  }).collect();
+  db.batch(&inserted.iter().map(|(p,v)| Row::Insert(*p,*v)).collect::<Vec<_>>())?;
+  let bbox = ((-0.5,-0.5,10.0,-10_000),(0.5,0.5,60.0,10_000));
+  assert![check(&mut db, &inserted, &bbox)? > 0];
+  Ok(())
+}
+
+#[test]
+fn small_ints() -> Result<(),Error> {
+  // 6 dimensions with every signed integer width and an i8 interval
+  type P = (i8,i16,i32,i64,(i8,i8),u8);
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut db: DB<_,_,P,u32> = Setup::new(storage(dir.path().to_path_buf()))
+    .base_size(200)
+    .max_data_size(50)
+    .build()?;
+  let mut r = rand().seed([13,17]);
+  let inserted: Vec<(P,u32)> = (0..3_000).map(|i| {
+    let a = r.read::<u8>() as i8;
+    let b = r.read::<u8>() as i8;
+    let p = (
+      r.read::<u8>() as i8,
+      r.read::<u16>() as i16,
+      r.read::<u32>() as i32,
+      r.read::<u64>() as i64,
+      (a.min(b),a.max(b)),
+      r.read::<u8>()
+    );
+    (p,i)
+  }).collect();
+  db.batch(&inserted.iter().map(|(p,v)| Row::Insert(*p,*v)).collect::<Vec<_>>())?;
+  let bbox = (
+    (-100,i16::MIN,0,i64::MIN,-10,0),
+    (100,0,i32::MAX,i64::MAX,10,200)
+  );
+  assert![check(&mut db, &inserted, &bbox)? > 0];
+  Ok(())
+}
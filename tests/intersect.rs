@@ -0,0 +1,77 @@
+use eyros::{Setup,DB,Row,Point,Intersect};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::path::PathBuf;
+
+// time ranges along x, value ranges along y
+type P = ((f32,f32),(f32,f32));
+type V = u32;
+
+fn storage (dir: PathBuf) -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> {
+  move |name: &str| {
+    RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build()
+  }
+}
+
+#[test]
+fn intersect() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut db: DB<_,_,P,V> = Setup::new(storage(dir.path().to_path_buf()))
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let mut rows = vec![];
+  // 1000 rows go into a tree, the last 100 stay in staging
+  for n in [1_000,100].iter() {
+    let start = rows.len();
+    let batch: Vec<(P,V)> = (0..*n).map(|i| {
+      let t0 = r.read::<f32>()*100.0;
+      let t1 = t0 + r.read::<f32>().powi(2)*30.0;
+      let y0 = r.read::<f32>();
+      let y1 = y0 + r.read::<f32>().powi(2)*0.5;
+      (((t0,t1),(y0,y1)), (start+i) as V)
+    }).collect();
+    rows.extend(batch.iter().cloned());
+    db.batch(&batch.iter().map(|(p,v)| Row::Insert(*p,*v)).collect::<Vec<_>>())?;
+  }
+  let queries = [
+    (((20.0,0.0),(40.0,1.0)),Intersect::Overlaps),
+    (((20.0,0.0),(40.0,1.0)),Intersect::Within),
+    (((10.0,0.2),(30.0,0.9)),Intersect::Within),
+    (((50.0,0.5),(50.0,0.5)),Intersect::Overlaps),
+    (((50.0,0.5),(50.0,0.5)),Intersect::Contains),
+    (((30.0,0.4),(32.0,0.45)),Intersect::Contains)
+  ];
+  for (bbox,mode) in queries.iter() {
+    let mut expected: Vec<V> = rows.iter()
+      .filter(|(p,_)| mode.matches(p, bbox))
+      .map(|(_,v)| *v)
+      .collect();
+    expected.sort_unstable();
+    let mut values: Vec<V> = db.query(bbox)?.intersect(*mode)
+      .map(|r| r.map(|(_,v,_)| v))
+      .collect::<Result<_,Error>>()?;
+    values.sort_unstable();
+    assert_eq![values, expected, "{:?} {:?}", bbox, mode];
+    // rows that don't match don't use up the limit
+    let n = db.query(bbox)?.intersect(*mode).limit(5).count();
+    assert_eq![n, expected.len().min(5)];
+    assert![!values.is_empty(), "{:?} {:?}", bbox, mode];
+    // the other modes are stricter than overlapping, except for a point
+    if *mode != Intersect::Overlaps && bbox.0 != bbox.1 {
+      assert![values.len() < db.query(bbox)?.count(), "{:?} {:?}", bbox, mode];
+    }
+  }
+  // scalar coordinates are within any box they overlap, but only contain a
+  // box of zero width
+  let bbox = ((20.0,0.0),(40.0,1.0));
+  let p: ((f32,f32),f32) = ((25.0,30.0),0.5);
+  assert![p.within(&bbox)];
+  assert![!p.contains(&bbox)];
+  assert![p.contains(&((26.0,0.5),(29.0,0.5)))];
+  assert![!((15.0,30.0),0.5).within(&bbox)];
+  Ok(())
+}
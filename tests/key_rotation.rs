@@ -0,0 +1,93 @@
+use eyros::{Setup,DB,Row,Cipher};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use tempfile::Builder as Tmpfile;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+type P = (f32,f32);
+type V = Vec<u8>;
+
+// toy cipher for tests: xor with the key behind a tag that identifies the key
+struct Xor;
+impl Cipher for Xor {
+  fn encrypt (&self, key: &[u8], buf: &[u8]) -> Result<Vec<u8>,Error> {
+    let mut out = crc32fast::hash(key).to_be_bytes().to_vec();
+    out.extend(buf.iter().enumerate().map(|(i,b)| b ^ key[i % key.len()]));
+    Ok(out)
+  }
+  fn decrypt (&self, key: &[u8], buf: &[u8]) -> Result<Vec<u8>,Error> {
+    if buf.len() < 4 || buf[0..4] != crc32fast::hash(key).to_be_bytes() {
+      return Err(Error::Invalid("wrong key".into()));
+    }
+    Ok(buf[4..].iter().enumerate().map(|(i,b)| b ^ key[i % key.len()]).collect())
+  }
+}
+
+fn storage (dir: PathBuf) -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> {
+  move |name: &str| {
+    Ok(RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build()?)
+  }
+}
+
+fn rows (start: u32, n: u32) -> Vec<Row<P,V>> {
+  (start..start+n).map(|i| {
+    let value = format!["vessel-{:08} status=underway", i].into_bytes();
+    Row::Insert(((i as f32)/10_000.0, 0.5), value)
+  }).collect()
+}
+
+fn count<S,U> (db: &mut DB<S,U,P,V>) -> Result<usize,Error> where
+S: random_access_storage::RandomAccess<Error=failure::Error>,
+U: Fn(&str) -> Result<S,failure::Error> {
+  let mut n = 0;
+  for result in db.query(&((0.0,0.0),(1.0,1.0)))? {
+    result?;
+    n += 1;
+  }
+  Ok(n)
+}
+
+#[test]
+fn key_rotation() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let (a,b) = (b"first key".to_vec(), b"second key".to_vec());
+  let setup = || Setup::new(storage(dir.path().to_path_buf()))
+    .max_data_size(100)
+    .base_size(500);
+  {
+    let mut db: DB<_,_,P,V> = setup().encryption(Arc::new(Xor), a.clone()).build()?;
+    db.batch(&rows(0, 2_000))?;
+    let epochs = db.key_epochs()?;
+    assert_eq![epochs.keys().cloned().collect::<Vec<_>>(), vec![Some(0)]];
+    let data = std::fs::read(dir.path().join("data"))?;
+    assert![!data.windows(8).any(|w| w == b"underway"), "rows are encrypted"];
+
+    assert_eq![db.rotate_key(b.clone())?, 1];
+    db.batch(&rows(2_000, 1_000))?;
+    assert_eq![count(&mut db)?, 3_000];
+    let epochs = db.key_epochs()?;
+    assert![epochs.contains_key(&Some(0)) && epochs.contains_key(&Some(1)),
+      "new blocks use the new key: {:?}", epochs];
+  }
+  {
+    let mut db: DB<_,_,P,V> = setup().encryption(Arc::new(Xor), b.clone())
+      .old_key(0, a.clone())
+      .build()?;
+    assert_eq![count(&mut db)?, 3_000, "old blocks read with the old key"];
+    db.compact()?;
+    let epochs = db.key_epochs()?;
+    assert_eq![epochs.keys().cloned().collect::<Vec<_>>(), vec![Some(1)]];
+  }
+  {
+    let mut db: DB<_,_,P,V> = setup().encryption(Arc::new(Xor), b.clone())
+      .build()?;
+    assert_eq![count(&mut db)?, 3_000, "old key no longer needed"];
+    assert![db.quarantined()?.is_empty()];
+  }
+  {
+    let mut db: DB<_,_,P,V> = setup().build()?;
+    assert![db.rotate_key(a.clone()).is_err(), "encryption is not set up"];
+  }
+  Ok(())
+}
@@ -0,0 +1,63 @@
+use eyros::{Setup,DB,Row,ManualClock,Clock};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use tempfile::Builder as Tmpfile;
+use std::sync::Arc;
+use std::time::Duration;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn latency() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  };
+  let clock = Arc::new(ManualClock::new(Duration::from_secs(1_000)));
+  let c: Arc<dyn Clock> = clock.clone();
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .clock(c)
+    .build()?;
+  let batch: Vec<Row<P,V>> = (0..100).map(|i| {
+    Row::Insert((i as f32 / 100.0, 0.5), i)
+  }).collect();
+  db.batch(&batch)?;
+  let latency = db.latency_percentiles()?;
+  assert_eq![latency.count, 0];
+  assert_eq![latency.p99, Duration::from_nanos(0)];
+
+  let bbox = ((0.0,0.0),(1.0,1.0));
+  // 90 queries of 1ms, 9 of 50ms, and one of 2s
+  for i in 0..100 {
+    let mut rows = db.query(&bbox)?;
+    assert![rows.next().is_some()];
+    clock.advance(match i {
+      0..=89 => Duration::from_millis(1),
+      90..=98 => Duration::from_millis(50),
+      _ => Duration::from_secs(2)
+    });
+    assert_eq![rows.count(), 99]; // recorded once the iterator runs out
+  }
+  // dropped before running out
+  let rows = db.query(&bbox)?;
+  clock.advance(Duration::from_millis(1));
+  drop(rows);
+
+  let within = |d: Duration, expected: Duration| {
+    d >= expected && d.as_nanos() <= expected.as_nanos() * 9 / 8
+  };
+  let latency = db.latency_percentiles()?;
+  assert_eq![latency.count, 101];
+  assert![within(latency.p50, Duration::from_millis(1)), "{:?}", latency];
+  assert![within(latency.p95, Duration::from_millis(50)), "{:?}", latency];
+  assert![within(latency.p99, Duration::from_millis(50)), "{:?}", latency];
+  assert_eq![latency.max, Duration::from_secs(2)];
+
+  db.reset_stats()?;
+  assert_eq![db.latency_percentiles()?.count, 101];
+  db.reset_latency()?;
+  assert_eq![db.latency_percentiles()?, Default::default()];
+  Ok(())
+}
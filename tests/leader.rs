@@ -0,0 +1,34 @@
+#![cfg(feature="file-lease")]
+use eyros::{Leadership,FileLease,ManualClock,Clock};
+use eyros::Error;
+use tempfile::Builder as Tmpfile;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[test]
+fn file_lease() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let path = dir.path().join("leader");
+  let clock = Arc::new(ManualClock::new(Duration::from_secs(1_000)));
+  let c: Arc<dyn Clock> = clock.clone();
+  let ttl = Duration::from_secs(10);
+  let mut a = FileLease::new(&path, 1, ttl).clock(Arc::clone(&c));
+  let mut b = FileLease::new(&path, 2, ttl).clock(Arc::clone(&c));
+
+  assert![a.acquire()?, "a takes the free lease"];
+  assert![!b.acquire()?, "b waits while the lease is held"];
+  clock.advance(Duration::from_secs(8));
+  assert![a.renew()?, "a renews"];
+  clock.advance(Duration::from_secs(8));
+  assert![!b.acquire()?, "renewed lease hasn't expired"];
+  assert![a.is_leader() && !b.is_leader()];
+
+  clock.advance(Duration::from_secs(3));
+  assert![b.acquire()?, "b takes over the expired lease"];
+  assert![!a.renew()?, "a lost the lease"];
+  assert![!a.is_leader()];
+
+  b.release()?;
+  assert![a.acquire()?, "a takes the released lease"];
+  Ok(())
+}
@@ -6,7 +6,7 @@ extern crate random_access_storage;
 extern crate tempfile;
 
 use eyros::{DB,Row};
-use failure::Error;
+use eyros::Error;
 use random_access_disk::RandomAccessDisk;
 use random_access_storage::RandomAccess;
 use random::{Source,default as rand};
@@ -35,7 +35,7 @@ fn load() -> Result<(),Error> {
   {
     // seed the db
     let mut db: DB<_,_,P,V> = DB::open(
-      |name: &str| -> Result<RandomAccessDisk,Error> {
+      |name: &str| -> Result<RandomAccessDisk,failure::Error> {
         let p = dir.path().join(name);
         Ok(RandomAccessDisk::builder(p)
           .auto_sync(false)
@@ -54,7 +54,7 @@ fn load() -> Result<(),Error> {
     // let the previous db fall out of scope and create a new one
     // so it loads records from zero
     let mut db: DB<_,_,P,V> = DB::open(
-      |name: &str| -> Result<RandomAccessDisk,Error> {
+      |name: &str| -> Result<RandomAccessDisk,failure::Error> {
         let p = dir.path().join(name);
         Ok(RandomAccessDisk::builder(p)
           .auto_sync(false)
@@ -78,7 +78,7 @@ fn load() -> Result<(),Error> {
   {
     // batch insert the records on a new db
     let mut db: DB<_,_,P,V> = DB::open(
-      |name: &str| -> Result<RandomAccessDisk,Error> {
+      |name: &str| -> Result<RandomAccessDisk,failure::Error> {
         let p = dir.path().join(name);
         Ok(RandomAccessDisk::builder(p)
           .auto_sync(false)
@@ -113,8 +113,8 @@ fn contains_pt<T> (min: T, max: T, pt: T) -> bool where T: PartialOrd {
 
 fn check<S,U> (db: &mut DB<S,U,P,V>, inserts: &Vec<Row<P,V>>, size: usize)
 -> Result<(),Error> where
-S: RandomAccess<Error=Error>,
-U: (Fn(&str) -> Result<S,Error>) {
+S: RandomAccess<Error=failure::Error>,
+U: (Fn(&str) -> Result<S,failure::Error>) {
   {
     let bbox = ((-1.0,-1.0,0.0),(1.0,1.0,1000.0));
     let mut results = vec![];
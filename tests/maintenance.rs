@@ -0,0 +1,166 @@
+use eyros::{Setup,DB,Row,Job,Retention,Executor};
+use eyros::async_db::{AsyncDB,AsyncRandomAccess,StoreFuture};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random_access_storage::RandomAccess;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc,Mutex};
+use std::task::{Context,Poll,Wake,Waker};
+use std::thread;
+use std::time::{Duration,Instant};
+
+type P = (f32,f32);
+type V = u32;
+
+fn storage (dir: PathBuf) -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> {
+  move |name: &str| {
+    RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build()
+  }
+}
+
+// disk store whose calls complete as soon as they are polled
+struct Disk(RandomAccessDisk);
+
+impl AsyncRandomAccess for Disk {
+  fn write<'a> (&'a mut self, offset: u64, data: &'a [u8]) -> StoreFuture<'a,()> {
+    Box::pin(async move { self.0.write(offset, data) })
+  }
+  fn read (&mut self, offset: u64, length: u64) -> StoreFuture<'_,Vec<u8>> {
+    Box::pin(async move { self.0.read(offset, length) })
+  }
+  fn truncate (&mut self, length: u64) -> StoreFuture<'_,()> {
+    Box::pin(async move { self.0.truncate(length) })
+  }
+  fn len (&mut self) -> StoreFuture<'_,u64> {
+    Box::pin(async move { self.0.len() })
+  }
+  fn sync_all (&mut self) -> StoreFuture<'_,()> {
+    Box::pin(async move { self.0.sync_all() })
+  }
+}
+
+fn async_storage (dir: PathBuf) -> impl Fn(&str) -> StoreFuture<'static,Disk> {
+  let storage = storage(dir);
+  move |name: &str| {
+    let store = storage(name);
+    Box::pin(async move { Ok(Disk(store?)) })
+  }
+}
+
+fn batches () -> Vec<Vec<Row<P,V>>> {
+  let mut r = rand().seed([13,12]);
+  // the second batch merges trees, which leaves dead blocks behind
+  [1_500,600].iter().scan(0, |count,n| {
+    Some((0..*n).map(|_| {
+      *count += 1;
+      Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), *count)
+    }).collect())
+  }).collect()
+}
+
+fn setup (dir: PathBuf)
+-> Setup<RandomAccessDisk,impl Fn(&str) -> Result<RandomAccessDisk,failure::Error>> {
+  configure(Setup::new(storage(dir)))
+}
+
+fn configure<S,U> (setup: Setup<S,U>) -> Setup<S,U> where
+S: RandomAccess<Error=failure::Error>, U: Fn(&str) -> Result<S,failure::Error> {
+  setup
+    .max_data_size(100)
+    .base_size(500)
+    .changes(true)
+    .retention(Retention::default().max_changes(100))
+    .compact_ratio(0.0)
+}
+
+#[test]
+fn maintenance_jobs() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut db: DB<_,_,P,V> = setup(dir.path().to_path_buf()).build()?;
+  for batch in batches().iter() {
+    db.batch(batch)?;
+  }
+  assert![db.disk_usage()?.data_dead > 0];
+  assert_eq![db.pending_maintenance()?, vec![Job::PruneHistory,Job::Compact]];
+  let report = db.run_maintenance(Duration::from_secs(60))?;
+  assert_eq![report.completed, vec![Job::PruneHistory,Job::Compact]];
+  assert_eq![report.remaining, 0];
+  assert_eq![db.disk_usage()?.data_dead, 0];
+  assert_eq![db.changes(2_000)?.count(), 100];
+  assert![db.changes(0).is_err(), "pruned"];
+  assert_eq![db.pending_maintenance()?, vec![]];
+  Ok(())
+}
+
+// runs each task on a thread of its own
+struct Threads;
+
+struct Unpark(thread::Thread);
+
+impl Wake for Unpark {
+  fn wake (self: Arc<Self>) { self.0.unpark() }
+}
+
+fn block_on<F: Future> (f: F) -> F::Output {
+  let waker = Waker::from(Arc::new(Unpark(thread::current())));
+  let mut cx = Context::from_waker(&waker);
+  let mut f = Box::pin(f);
+  loop {
+    match f.as_mut().poll(&mut cx) {
+      Poll::Ready(x) => return x,
+      Poll::Pending => thread::park()
+    }
+  }
+}
+
+impl Executor for Threads {
+  fn spawn (&self, task: Pin<Box<dyn Future<Output=()>+Send>>) {
+    thread::spawn(move || block_on(task));
+  }
+  fn sleep (&self, duration: Duration) -> Pin<Box<dyn Future<Output=()>+Send>> {
+    let state: Arc<Mutex<(bool,Option<Waker>)>> = Arc::new(Mutex::new((false,None)));
+    let timer = Arc::clone(&state);
+    thread::spawn(move || {
+      thread::sleep(duration);
+      let mut t = timer.lock().unwrap();
+      t.0 = true;
+      if let Some(waker) = t.1.take() { waker.wake() }
+    });
+    Box::pin(std::future::poll_fn(move |cx| {
+      let mut s = state.lock().unwrap();
+      if s.0 { return Poll::Ready(()) }
+      s.1 = Some(cx.waker().clone());
+      Poll::Pending
+    }))
+  }
+}
+
+#[test]
+fn start_maintenance() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut db: AsyncDB<_,_,P,V> = block_on(AsyncDB::open_from_setup(
+    async_storage(dir.path().to_path_buf()), configure))?;
+  for batch in batches().iter() {
+    block_on(db.batch(batch))?;
+  }
+  let (interval,budget) = (Duration::from_millis(1),Duration::from_secs(1));
+  db.start_maintenance(Threads, interval, budget)?;
+  assert![db.start_maintenance(Threads, interval, budget).is_err(), "already running"];
+  let start = Instant::now();
+  loop {
+    let pending = block_on(db.run(|db| db.pending_maintenance()))?;
+    if pending.is_empty() { break }
+    assert![start.elapsed() < Duration::from_secs(30), "maintenance ran"];
+    thread::sleep(Duration::from_millis(1));
+  }
+  assert_eq![block_on(db.run(|db| Ok(db.disk_usage()?.data_dead)))?, 0];
+  assert![block_on(db.run(|db| Ok(db.changes(0).is_err())))?, "pruned"];
+  block_on(db.close())?;
+  assert![block_on(db.run(|db| db.run_maintenance(Duration::from_secs(1)))).is_err(),
+    "closed after maintenance stopped"];
+  Ok(())
+}
@@ -0,0 +1,71 @@
+use eyros::{Setup,DB,Row};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn max_trees() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  };
+  let setup = || Setup::new(&storage)
+    .max_data_size(500)
+    .base_size(500)
+    .max_trees(2);
+  let live = |db: &mut DB<_,_,P,V>| -> Result<usize,Error> {
+    let mut n = 0;
+    for tree in db.trees.iter() {
+      if !tree.write().unwrap().is_empty()? { n += 1 }
+    }
+    Ok(n)
+  };
+  let mut db: DB<_,_,P,V> = setup().build()?;
+  let mut r = rand().seed([3,14]);
+  let mut n = 0;
+  // 15 batches of one base size end with 3 trees on the regular schedule
+  for _ in 0..15 {
+    let batch: Vec<Row<P,V>> = (0..500).map(|_| {
+      n += 1;
+      Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), n)
+    }).collect();
+    db.batch(&batch)?;
+    assert![live(&mut db)? <= 2, "at most 2 trees"];
+  }
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let check = |db: &mut DB<_,_,P,V>, n: V| -> Result<(),Error> {
+    let mut values: Vec<V> = db.query(&bbox)?
+      .map(|r| r.map(|row| row.1)).collect::<Result<_,Error>>()?;
+    values.sort();
+    assert_eq![values, (1..=n).collect::<Vec<V>>()];
+    Ok(())
+  };
+  check(&mut db, n)?;
+  drop(db);
+
+  // the merged trees are gone after reopening
+  let mut db: DB<_,_,P,V> = setup().build()?;
+  assert![live(&mut db)? <= 2];
+  check(&mut db, n)?;
+  drop(db);
+
+  // lowering the ceiling merges every tree on the next batch
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(500)
+    .base_size(500)
+    .max_trees(1)
+    .build()?;
+  let batch: Vec<Row<P,V>> = (0..500).map(|_| {
+    n += 1;
+    Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), n)
+  }).collect();
+  db.batch(&batch)?;
+  assert_eq![live(&mut db)?, 1];
+  check(&mut db, n)?;
+  Ok(())
+}
@@ -0,0 +1,55 @@
+#![cfg(feature="memory")]
+use eyros::{DB,Row,Setup,memory_storage};
+use eyros::Error;
+use random::{Source,default as rand};
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn memory() -> Result<(),Error> {
+  let mut db: DB<_,_,P,V> = DB::open_memory(|setup| {
+    setup.max_data_size(100).base_size(500)
+  })?;
+  let mut r = rand().seed([13,12]);
+  let mut rows: Vec<(P,V)> = vec![];
+  for size in [1_200,300,50].iter() {
+    let batch: Vec<Row<P,V>> = (0..*size).map(|_| {
+      let p = (r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0);
+      rows.push((p,rows.len() as u32));
+      Row::Insert(p, rows.len() as u32 - 1)
+    }).collect();
+    db.batch(&batch)?;
+  }
+  let bbox = ((-0.5,-0.5),(0.5,0.5));
+  let inside = |p: &P| -0.5 <= p.0 && p.0 <= 0.5 && -0.5 <= p.1 && p.1 <= 0.5;
+  let mut expected: Vec<V> = rows.iter().filter(|(p,_)| inside(p)).map(|r| r.1).collect();
+  expected.sort();
+  let results = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?;
+  let mut values: Vec<V> = results.iter().map(|r| r.1).collect();
+  values.sort();
+  assert_eq![values, expected];
+
+  // deletes and compaction rewrite and truncate stores
+  let deletes: Vec<Row<P,V>> = results.iter().filter(|r| r.1 % 2 == 0)
+    .map(|r| Row::Delete(r.2)).collect();
+  db.batch(&deletes)?;
+  db.compact()?;
+  let mut values: Vec<V> = db.query(&bbox)?.map(|r| r.map(|r| r.1))
+    .collect::<Result<Vec<_>,Error>>()?;
+  values.sort();
+  expected.retain(|v| v % 2 == 1);
+  assert_eq![values, expected];
+
+  // handles opened with one storage function share their stores
+  let storage = memory_storage();
+  let mut a: DB<_,_,P,V> = Setup::new(&storage).build()?;
+  a.batch(&[Row::Insert((0.1,0.2),5)])?;
+  drop(a);
+  let mut b: DB<_,_,P,V> = Setup::new(&storage).build()?;
+  assert_eq![b.query(&bbox)?.map(|r| r.map(|r| r.1)).collect::<Result<Vec<_>,Error>>()?,
+    vec![5]];
+  let mut other: DB<_,_,P,V> = DB::open_memory(|setup| setup)?;
+  assert_eq![other.query(&bbox)?.count(), 0];
+  Ok(())
+}
@@ -0,0 +1,65 @@
+use eyros::{Setup,DB,Row,merge};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = (u32,u32); // (id,revision)
+
+#[test]
+fn merge_dbs() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |prefix: &'static str| {
+    let dir = dir.path().to_path_buf();
+    move |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+      let p = dir.join(format!("{}_{}", prefix, name));
+      Ok(RandomAccessDisk::builder(p)
+        .auto_sync(false)
+        .build()?)
+    }
+  };
+  let mut a: DB<_,_,P,V> = Setup::new(storage("a"))
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut b: DB<_,_,P,V> = Setup::new(storage("b"))
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut out: DB<_,_,P,V> = Setup::new(storage("out"))
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let points: Vec<P> = (0..1_500).map(|_| {
+    (r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0)
+  }).collect();
+  // ids 0..1000 in a, ids 500..1500 in b with newer revisions for 500..700
+  a.batch(&(0..1_000).map(|i| {
+    Row::Insert(points[i], (i as u32,1))
+  }).collect::<Vec<_>>())?;
+  b.batch(&(500..1_500).map(|i| {
+    Row::Insert(points[i], (i as u32, if i < 700 { 2 } else { 1 }))
+  }).collect::<Vec<_>>())?;
+
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let report = merge(&mut a, &mut b, &mut out, &bbox,
+    |_p,v| v.0,
+    |_id,x,y| if (y.1).1 > (x.1).1 { y } else { x }
+  )?;
+  assert_eq![report.rows_a, 1_000];
+  assert_eq![report.rows_b, 1_000];
+  assert_eq![report.conflicts, 500];
+  assert_eq![report.written, 1_500];
+
+  let mut values: Vec<V> = out.query(&bbox)?
+    .map(|r| r.map(|row| row.1))
+    .collect::<Result<_,Error>>()?;
+  values.sort();
+  let expected: Vec<V> = (0..1_500).map(|i| {
+    (i, if i >= 500 && i < 700 { 2 } else { 1 })
+  }).collect();
+  assert_eq![values, expected, "union with resolved revisions"];
+  Ok(())
+}
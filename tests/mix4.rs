@@ -1,6 +1,6 @@
 use eyros::{DB,Row,Point,Mix,Mix4};
 use random::{Source,default as rand};
-use failure::Error;
+use eyros::Error;
 use random_access_disk::RandomAccessDisk;
 use tempfile::Builder as Tmpfile;
 use std::cmp::Ordering;
@@ -12,7 +12,7 @@ type V = u32;
 fn mix4() -> Result<(),Error> {
   let dir = Tmpfile::new().prefix("eyros").tempdir()?;
   let mut db: DB<_,_,P,V> = DB::open(
-    |name: &str| -> Result<RandomAccessDisk,Error> {
+    |name: &str| -> Result<RandomAccessDisk,failure::Error> {
       let p = dir.path().join(name);
       Ok(RandomAccessDisk::builder(p)
         .auto_sync(false)
@@ -1,5 +1,5 @@
 use eyros::{Mix,Mix2,Mix3};
-use failure::Error;
+use eyros::Error;
 
 #[test]
 fn mix_into() -> Result<(),Error> {
@@ -0,0 +1,52 @@
+use eyros::{Setup,DB,Row,Mix,Mix2};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use tempfile::Builder as Tmpfile;
+
+type P = Mix2<f32,f32>;
+type V = u32;
+
+#[test]
+fn mix_points_intervals() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut db: DB<_,_,P,V> = Setup::new(
+    |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+      let p = dir.path().join(name);
+      Ok(RandomAccessDisk::builder(p)
+        .auto_sync(false)
+        .build()?)
+    })
+    .branch_factor(5)
+    .max_data_size(50)
+    .base_size(100)
+    .build()?;
+  // points of interest on even slots, road segment bboxes on odd slots
+  let batch: Vec<Row<P,V>> = (0..450).map(|i| {
+    let x = (i as f32)*0.01;
+    if i % 2 == 0 {
+      Row::Insert(Mix2::new(x.into(), x.into()), i)
+    } else {
+      Row::Insert(Mix2::new((x,x+0.002).into(), (x,x+0.002).into()), i)
+    }
+  }).collect();
+  db.batch(&batch)?;
+  assert![db.trees.iter().any(|t| t.read().unwrap().bytes > 0), "trees were built"];
+  assert_eq![db.staging.inserts.read().unwrap().len(), 50, "remainder in staging"];
+
+  let bbox = ((0.995,0.995),(2.005,2.005));
+  let mut results: Vec<(P,V)> = vec![];
+  for result in db.query(&bbox)? {
+    let r = result?;
+    results.push((r.0,r.1));
+  }
+  results.sort_unstable_by_key(|(_,v)| *v);
+  let values: Vec<V> = results.iter().map(|(_,v)| *v).collect();
+  assert_eq![values, (100..=200).collect::<Vec<V>>(), "points and intervals"];
+  for (p,v) in results.iter() {
+    assert_eq![p.v0.is_scalar(), v % 2 == 0, "geometry kind preserved"];
+    if let Mix::Interval(x0,x1) = p.v1 {
+      assert![x0 < x1, "interval bounds preserved"];
+    }
+  }
+  Ok(())
+}
@@ -0,0 +1,86 @@
+use eyros::{Setup,DB,Row};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use tempfile::Builder as Tmpfile;
+use std::path::PathBuf;
+
+type P = (f32,f32);
+type V = u32;
+
+fn storage (dir: PathBuf) -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> {
+  move |name: &str| {
+    Ok(RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build()?)
+  }
+}
+
+fn open (dir: PathBuf) -> Result<DB<RandomAccessDisk,
+impl Fn(&str) -> Result<RandomAccessDisk,failure::Error>,P,V>,Error> {
+  Setup::new(storage(dir))
+    .max_data_size(50)
+    .base_size(100)
+    .build()
+}
+
+fn rows<S,U> (db: &mut DB<S,U,P,V>) -> Result<Vec<(P,V)>,Error> where
+S: random_access_storage::RandomAccess<Error=failure::Error>,
+U: Fn(&str) -> Result<S,failure::Error> {
+  let mut rows = vec![];
+  for result in db.query(&((-1.0,-1.0),(1.0,1.0)))? {
+    let (p,v,_) = result?;
+    rows.push((p,v));
+  }
+  rows.sort_by_key(|(_,v)| *v);
+  Ok(rows)
+}
+
+fn point (i: u32) -> P {
+  ((i as f32)/1_000.0*2.0-1.0, -0.5)
+}
+
+#[test]
+fn mixed_batch() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut db = open(dir.path().to_path_buf())?;
+  // 250 rows: 200 in trees and 50 staged
+  db.batch(&(0..250).map(|i| Row::Insert(point(i),i)).collect::<Vec<_>>())?;
+  let mut expected: Vec<(P,V)> = (0..250).map(|i| (point(i),i)).collect();
+
+  // move a few rows from trees and from staging with enough inserts to merge
+  let mut batch = vec![];
+  for result in db.query(&((-1.0,-1.0),(1.0,1.0)))? {
+    let (p,v,loc) = result?;
+    if v % 25 != 0 { continue }
+    batch.push(Row::Delete(loc));
+    batch.push(Row::Insert((p.0,0.5),v));
+    expected[v as usize].1 = v;
+    expected[v as usize].0 = (p.0,0.5);
+  }
+  assert![batch.iter().any(|r| match r {
+    Row::Delete(loc) => loc.0 == 0,
+    _ => false
+  }), "deletes staged rows"];
+  for i in 250..350 {
+    batch.push(Row::Insert(point(i),i));
+    expected.push((point(i),i));
+  }
+  db.batch(&batch)?;
+  assert_eq![rows(&mut db)?, expected, "move with merge"];
+
+  // delete enough rows in trees to flush deletes along with a staged delete
+  let mut batch = vec![];
+  for result in db.query(&((-1.0,-1.0),(1.0,1.0)))? {
+    let (_,v,loc) = result?;
+    if v % 3 == 0 { batch.push(Row::Delete(loc)) }
+  }
+  assert![batch.len() >= 100];
+  batch.push(Row::Insert(point(400),400));
+  db.batch(&batch)?;
+  expected.retain(|(_,v)| v % 3 != 0);
+  expected.push((point(400),400));
+  assert_eq![rows(&mut db)?, expected, "delete flush"];
+
+  drop(db);
+  let mut db = open(dir.path().to_path_buf())?;
+  assert_eq![rows(&mut db)?, expected, "after reopen"];
+  Ok(())
+}
@@ -5,7 +5,7 @@ extern crate random_access_disk;
 extern crate tempfile;
 
 use eyros::{DB,Row,Setup};
-use failure::Error;
+use eyros::Error;
 use random_access_disk::RandomAccessDisk;
 use random::{Source,default as rand};
 use tempfile::Builder as Tmpfile;
@@ -19,7 +19,7 @@ type V = u32;
 #[test]
 fn multi_batch() -> Result<(),Error> {
   let dir = Tmpfile::new().prefix("eyros").tempdir()?;
-  let storage = |name: &str| -> Result<RandomAccessDisk,Error> {
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
     let p = dir.path().join(name);
     Ok(RandomAccessDisk::builder(p)
       .auto_sync(false)
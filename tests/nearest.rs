@@ -0,0 +1,187 @@
+use eyros::{Setup,DB,Row,Distance,Mix,Mix2};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random_access_storage::RandomAccess;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::cell::Cell;
+use std::io;
+use std::rc::Rc;
+use std::time::Duration;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn nearest() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let mut batch = |n| -> Vec<Row<P,V>> {
+    (0..n).map(|i| {
+      let x: f32 = r.read::<f32>()*2.0-1.0;
+      let y: f32 = r.read::<f32>()*2.0-1.0;
+      Row::Insert((x,y), i)
+    }).collect()
+  };
+  db.batch(&batch(2_000))?;
+  db.batch(&batch(300))?; // leave some rows in staging
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let deletes: Vec<Row<P,V>> = db.query(&bbox)?.step_by(7)
+    .map(|r| r.map(|row| Row::Delete(row.2)))
+    .collect::<Result<Vec<_>,Error>>()?;
+  db.batch(&deletes)?;
+  db.close()?;
+
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .track_heat(Duration::from_secs(60))
+    .build()?;
+  let target = (0.25,-0.4);
+  let results = db.nearest(&target, 10)?;
+  let blocks_read = db.block_heat()?.len();
+  let blocks_total = db.data_store.write().unwrap().block_ranges()?.len();
+  assert![blocks_read < blocks_total,
+    "read {} of {} blocks", blocks_read, blocks_total];
+
+  let mut all = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?;
+  all.sort_by(|a,b| {
+    a.0.distance_sq(&target).partial_cmp(&b.0.distance_sq(&target)).unwrap()
+  });
+  assert_eq![results.len(), 10];
+  let dist = |rows: &Vec<(P,V,_)>| -> Vec<f64> {
+    rows.iter().map(|row| row.0.distance_sq(&target)).collect()
+  };
+  assert_eq![dist(&results), dist(&all[0..10].to_vec()), "k nearest rows"];
+  for row in results.iter() {
+    assert![all.iter().any(|x| x.2 == row.2), "result is not deleted"];
+  }
+
+  let streamed = db.nearest_iter(&target)?
+    .collect::<Result<Vec<_>,Error>>()?;
+  assert_eq![streamed.len(), all.len(), "every live row streamed once"];
+  assert_eq![dist(&streamed), dist(&all), "rows in distance order"];
+  Ok(())
+}
+
+// counts reads of a group of stores
+struct CountingStore {
+  store: RandomAccessDisk,
+  reads: Rc<Cell<usize>>
+}
+
+impl RandomAccess for CountingStore {
+  type Error = failure::Error;
+  fn write (&mut self, offset: u64, data: &[u8]) -> Result<(),failure::Error> {
+    self.store.write(offset, data)
+  }
+  fn read (&mut self, offset: u64, length: u64) -> Result<Vec<u8>,failure::Error> {
+    self.reads.set(self.reads.get()+1);
+    self.store.read(offset, length)
+  }
+  fn read_to_writer (&mut self, offset: u64, length: u64,
+  buf: &mut impl io::Write) -> Result<(),failure::Error> {
+    self.store.read_to_writer(offset, length, buf)
+  }
+  fn del (&mut self, offset: u64, length: u64) -> Result<(),failure::Error> {
+    self.store.del(offset, length)
+  }
+  fn truncate (&mut self, length: u64) -> Result<(),failure::Error> {
+    self.store.truncate(length)
+  }
+  fn len (&self) -> Result<u64,failure::Error> {
+    self.store.len()
+  }
+  fn is_empty (&mut self) -> Result<bool,failure::Error> {
+    self.store.is_empty()
+  }
+  fn sync_all (&mut self) -> Result<(),failure::Error> {
+    self.store.sync_all()
+  }
+}
+
+#[test]
+fn nearest_branches() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let (tree_reads,range_reads) = (Rc::new(Cell::new(0)),Rc::new(Cell::new(0)));
+  let storage = |name: &str| -> Result<CountingStore,failure::Error> {
+    let p = dir.path().join(name);
+    let store = RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?;
+    let reads = if name.starts_with("tree") { Rc::clone(&tree_reads) }
+      else if name == "range" { Rc::clone(&range_reads) }
+      else { Rc::new(Cell::new(0)) };
+    Ok(CountingStore { store, reads })
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .branch_factor(5)
+    .max_data_size(50)
+    .base_size(1_000)
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let rows: Vec<Row<P,V>> = (0..20_000).map(|i| {
+    Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), i)
+  }).collect();
+  db.batch(&rows)?;
+  let mut branches = 0;
+  for tree in db.trees.iter() {
+    branches += tree.write().unwrap().shape()?.1;
+  }
+  let target = (0.25,-0.4);
+  let (trees_before,ranges_before) = (tree_reads.get(),range_reads.get());
+  let results = db.nearest(&target, 5)?;
+  let read = tree_reads.get() - trees_before;
+  assert![read > 0 && (read as u64) < branches/4,
+    "read {} of {} branches", read, branches];
+  assert_eq![range_reads.get(), ranges_before, "range store left unread"];
+
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let mut all = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?;
+  all.sort_by(|a,b| a.0.distance_sq(&target).total_cmp(&b.0.distance_sq(&target)));
+  let dist = |rows: &[(P,V,_)]| -> Vec<f64> {
+    rows.iter().map(|row| row.0.distance_sq(&target)).collect()
+  };
+  assert_eq![dist(&results), dist(&all[0..5]), "k nearest rows"];
+  Ok(())
+}
+
+#[test]
+fn nearest_intervals() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut db: DB<_,_,Mix2<f32,f32>,V> = Setup::new(|name: &str| {
+      let p = dir.path().join(name);
+      Ok(RandomAccessDisk::builder(p)
+        .auto_sync(false)
+        .build()?)
+    })
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let rows: Vec<Row<Mix2<f32,f32>,V>> = (0..800).map(|i| {
+    let x = (i as f32)/100.0;
+    let point = if i % 2 == 0 {
+      Mix2::new(Mix::Scalar(x), Mix::Scalar(0.0))
+    } else {
+      Mix2::new(Mix::Scalar(x), Mix::Interval(1.0,2.0))
+    };
+    Row::Insert(point, i)
+  }).collect();
+  db.batch(&rows)?;
+  // the interval [1,2] covers y=1.5, so only the x offset counts
+  let results = db.nearest(&(3.005,1.5), 2)?;
+  let mut values: Vec<V> = results.iter().map(|row| row.1).collect();
+  values.sort();
+  assert_eq![values, vec![299,301]];
+  Ok(())
+}
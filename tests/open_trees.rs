@@ -0,0 +1,129 @@
+use eyros::{Setup,DB,Row,Location};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::collections::HashMap;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn open_trees() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .max_open_trees(2)
+    .build()?;
+  let mut r = rand().seed([17,2]);
+  let mut n = 0;
+  for size in [4_000,2_000,1_000,500].iter() {
+    let batch: Vec<Row<P,V>> = (0..*size).map(|_| {
+      n += 1;
+      Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), n)
+    }).collect();
+    db.batch(&batch)?;
+  }
+  // tree of each data block
+  let mut trees: HashMap<u64,usize> = HashMap::new();
+  for (i,tree) in db.trees.iter().enumerate() {
+    let mut t = tree.write().unwrap();
+    if t.is_empty()? { continue }
+    for offset in t.data_offsets()? {
+      trees.insert(offset+1, i);
+    }
+  }
+  let used: std::collections::HashSet<usize> = trees.values().cloned().collect();
+  assert![used.len() >= 3, "rows span several trees: {:?}", used];
+
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let check = |rows: &Vec<(P,V,Location)>, max: usize| {
+    let mut span: HashMap<usize,(usize,usize)> = HashMap::new();
+    for (i,(_,_,loc)) in rows.iter().enumerate() {
+      if loc.0 == 0 { continue }
+      let e = span.entry(trees[&loc.0]).or_insert((i,i));
+      e.1 = i;
+    }
+    let most = (0..rows.len()).map(|i| {
+      span.values().filter(|(a,b)| *a <= i && i <= *b).count()
+    }).max().unwrap();
+    assert![most <= max, "{} trees read at once, limit {}", most, max];
+    most
+  };
+  let mut expected: Vec<V> = (1..=n).collect();
+  expected.sort();
+  let rows = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?;
+  check(&rows, 2);
+  let rows = db.query(&bbox)?.max_open_trees(1)
+    .collect::<Result<Vec<_>,Error>>()?;
+  assert_eq![check(&rows, 1), 1];
+  let mut values: Vec<V> = rows.iter().map(|r| r.1).collect();
+  values.sort();
+  assert_eq![values, expected];
+  let rows = db.query(&bbox)?.max_open_trees(100)
+    .collect::<Result<Vec<_>,Error>>()?;
+  assert![check(&rows, 100) > 2, "unbounded queries interleave every tree"];
+  let mut values: Vec<V> = rows.iter().map(|r| r.1).collect();
+  values.sort();
+  assert_eq![values, expected];
+  Ok(())
+}
+
+#[test]
+fn buffered_blocks() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([17,2]);
+  let mut n = 0;
+  for size in [4_000,2_000,1_000,500].iter() {
+    let batch: Vec<Row<P,V>> = (0..*size).map(|_| {
+      n += 1;
+      Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), n)
+    }).collect();
+    db.batch(&batch)?;
+  }
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let expected: Vec<V> = (1..=n).collect();
+
+  let mut iter = db.query(&bbox)?;
+  let mut most = 0;
+  while let Some(result) = iter.next() {
+    result?;
+    most = most.max(iter.buffered_blocks());
+  }
+  assert![most > 1, "every open tree holds a block"];
+
+  let mut iter = db.query(&bbox)?.max_buffered_blocks(1);
+  let mut values = vec![];
+  while let Some(result) = iter.next() {
+    values.push(result?.1);
+    assert![iter.buffered_blocks() <= 1];
+  }
+  values.sort();
+  assert_eq![values, expected, "spilled blocks are read again"];
+
+  // cursors taken while blocks are spilled resume at the spilled row
+  let mut iter = db.query(&bbox)?.max_buffered_blocks(1);
+  let mut values: Vec<V> = iter.by_ref().take(2_000)
+    .map(|r| r.map(|r| r.1)).collect::<Result<_,Error>>()?;
+  let cursor = iter.cursor().expect("cursor");
+  drop(iter);
+  for result in db.query_resume(&cursor)? {
+    values.push(result?.1);
+  }
+  values.sort();
+  assert_eq![values, expected];
+  Ok(())
+}
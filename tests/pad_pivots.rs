@@ -1,8 +1,10 @@
 #![recursion_limit="1024"]
 
 extern crate failure;
+use eyros::Error;
 
 #[path="../src/ensure.rs"]
+#[allow(unused_macros)]
 #[macro_use] mod ensure;
 
 #[path="../src/order.rs"]
@@ -0,0 +1,71 @@
+use eyros::{Setup,DB,Row,point};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+
+point! {
+  #[derive(PartialEq)]
+  struct Sighting {
+    lon: f32,
+    lat: f32,
+    time: u64
+  }
+}
+
+type V = u32;
+
+#[test]
+fn point_macro() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  };
+  let setup = || Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500);
+  let mut r = rand().seed([11,3]);
+  let sightings: Vec<Sighting> = (0..2_000).map(|_| {
+    Sighting {
+      lon: r.read::<f32>()*2.0-1.0,
+      lat: r.read::<f32>()*2.0-1.0,
+      time: r.read::<u64>() % 10_000
+    }
+  }).collect();
+  let bbox = ((-0.5,-0.5,2_000),(0.5,0.5,3_000));
+  let mut expected: Vec<V> = sightings.iter().enumerate().filter(|(_,s)| {
+    -0.5 <= s.lon && s.lon <= 0.5 && -0.5 <= s.lat && s.lat <= 0.5
+      && 2_000 <= s.time && s.time <= 3_000
+  }).map(|(i,_)| i as u32).collect();
+  expected.sort();
+  {
+    let mut db: DB<_,_,Sighting,V> = setup().build()?;
+    let batch: Vec<Row<Sighting,V>> = sightings.iter().enumerate()
+      .map(|(i,s)| Row::Insert(*s,i as u32))
+      .collect();
+    db.batch(&batch)?;
+    let mut results = vec![];
+    for result in db.query(&bbox)? {
+      let (s,v,_) = result?;
+      assert_eq![s, sightings[v as usize]];
+      results.push(v);
+    }
+    results.sort();
+    assert![!expected.is_empty()];
+    assert_eq![results, expected];
+  }
+  {
+    // stored exactly like the tuple of the fields
+    let mut db: DB<_,_,(f32,f32,u64),V> = setup().build()?;
+    let mut results = vec![];
+    for result in db.query(&bbox)? {
+      let (t,v,_) = result?;
+      assert_eq![Sighting::from(t), sightings[v as usize]];
+      results.push(v);
+    }
+    results.sort();
+    assert_eq![results, expected];
+  }
+  Ok(())
+}
@@ -0,0 +1,107 @@
+use eyros::{Setup,DB,Row,Poisoned};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random_access_storage::RandomAccess;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::cell::Cell;
+use std::io;
+use std::rc::Rc;
+
+type P = (f32,f32);
+type V = u32;
+
+// fails every write and sync while `broken` is set
+struct BrokenStore {
+  store: RandomAccessDisk,
+  broken: Rc<Cell<bool>>
+}
+
+impl BrokenStore {
+  fn check (&self) -> Result<(),Error> {
+    if self.broken.get() {
+      return Err(io::Error::new(io::ErrorKind::Other, "disk failure").into());
+    }
+    Ok(())
+  }
+}
+
+impl RandomAccess for BrokenStore {
+  type Error = failure::Error;
+  fn write (&mut self, offset: u64, data: &[u8]) -> Result<(),failure::Error> {
+    self.check()?;
+    self.store.write(offset, data)
+  }
+  fn read (&mut self, offset: u64, length: u64) -> Result<Vec<u8>,failure::Error> {
+    self.store.read(offset, length)
+  }
+  fn read_to_writer (&mut self, offset: u64, length: u64,
+  buf: &mut impl io::Write) -> Result<(),failure::Error> {
+    self.store.read_to_writer(offset, length, buf)
+  }
+  fn del (&mut self, offset: u64, length: u64) -> Result<(),failure::Error> {
+    self.check()?;
+    self.store.del(offset, length)
+  }
+  fn truncate (&mut self, length: u64) -> Result<(),failure::Error> {
+    self.check()?;
+    self.store.truncate(length)
+  }
+  fn len (&self) -> Result<u64,failure::Error> {
+    self.store.len()
+  }
+  fn is_empty (&mut self) -> Result<bool,failure::Error> {
+    self.store.is_empty()
+  }
+  fn sync_all (&mut self) -> Result<(),failure::Error> {
+    self.check()?;
+    self.store.sync_all()
+  }
+}
+
+#[test]
+fn poison_and_recover() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let broken = Rc::new(Cell::new(false));
+  let storage = |name: &str| -> Result<BrokenStore,failure::Error> {
+    let p = dir.path().join(name);
+    let store = RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?;
+    let is_data = name == "data";
+    Ok(BrokenStore {
+      store,
+      broken: if is_data { Rc::clone(&broken) } else { Rc::new(Cell::new(false)) }
+    })
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let rows: Vec<Row<P,V>> = (0..700).map(|i| {
+    let x: f32 = r.read::<f32>()*2.0-1.0;
+    let y: f32 = r.read::<f32>()*2.0-1.0;
+    Row::Insert((x,y), i)
+  }).collect();
+  db.batch(&rows[0..300])?;
+
+  broken.set(true);
+  assert![db.batch(&rows[300..]).is_err(), "building a tree fails"];
+  assert![db.is_poisoned()];
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let err = db.query(&bbox).err().expect("query on a poisoned handle fails");
+  let poisoned = err.downcast_ref::<Poisoned>().expect("poisoned error");
+  assert![poisoned.reason.contains("disk failure"), "{}", poisoned.reason];
+
+  broken.set(false);
+  db.try_recover()?;
+  assert![!db.is_poisoned()];
+  let results = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?;
+  assert_eq![results.len(), 300, "rows from before the failed batch"];
+
+  db.batch(&rows[300..])?;
+  let results = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?;
+  assert_eq![results.len(), 700, "failed batch written again"];
+  Ok(())
+}
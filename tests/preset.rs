@@ -0,0 +1,58 @@
+use eyros::{Setup,DB,Row,Profile};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn preset() -> Result<(),Error> {
+  let profiles = [Profile::LocalSSD, Profile::Browser, Profile::ObjectStore,
+    Profile::Embedded];
+  let mut r = rand().seed([3,14]);
+  let rows: Vec<(P,V)> = (0..2_500).map(|i| {
+    ((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), i)
+  }).collect();
+  let bbox = ((-0.5,-0.5),(0.5,0.5));
+  let inside = |p: &P| -0.5 <= p.0 && p.0 <= 0.5 && -0.5 <= p.1 && p.1 <= 0.5;
+  let mut expected: Vec<V> = rows.iter().filter(|(p,_)| inside(p)).map(|r| r.1).collect();
+  expected.sort();
+  for profile in profiles.iter() {
+    let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+    let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+      let p = dir.path().join(name);
+      Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+    };
+    let setup = Setup::new(&storage).preset(*profile);
+    assert![setup.fields.cache_bytes.is_some(), "{:?}", profile];
+    assert![setup.fields.base_size >= setup.fields.max_data_size, "{:?}", profile];
+    let mut db: DB<_,_,P,V> = setup.build()?;
+    // drip-fed and bulk writes
+    for chunk in [&rows[0..20], &rows[20..100], &rows[100..]].iter() {
+      let batch: Vec<Row<P,V>> = chunk.iter().map(|(p,v)| Row::Insert(*p,*v)).collect();
+      db.batch(&batch)?;
+    }
+    let mut values: Vec<V> = db.query(&bbox)?
+      .map(|r| r.map(|(_,v,_)| v))
+      .collect::<Result<Vec<_>,Error>>()?;
+    values.sort();
+    assert_eq![values, expected, "{:?}", profile];
+  }
+
+  // later calls override the preset
+  let setup = Setup::new(|_: &str| -> Result<RandomAccessDisk,failure::Error> { unreachable![] })
+    .preset(Profile::Embedded)
+    .max_data_size(800)
+    .wal(false);
+  assert_eq![setup.fields.max_data_size, 800];
+  assert_eq![setup.fields.base_size, 2_000];
+  assert![!setup.fields.wal];
+  let setup = Setup::new(|_: &str| -> Result<RandomAccessDisk,failure::Error> { unreachable![] })
+    .preset(Profile::ObjectStore);
+  assert![setup.fields.check_conflicts];
+  assert![setup.fields.retry.attempts > 1];
+  assert![setup.fields.coalesce_span > 0];
+  Ok(())
+}
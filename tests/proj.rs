@@ -0,0 +1,63 @@
+#![cfg(feature="proj")]
+use eyros::{Setup,DB,Row,Projection,Projectable,WebMercator};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+
+type P = (f64,f64);
+type V = u32;
+
+#[test]
+fn web_mercator() -> Result<(),Error> {
+  let (x,y) = WebMercator.forward(13.4,52.5);
+  assert![(x-1_491_681.0).abs() < 1.0, "x={}", x];
+  assert![(y-6_891_041.0).abs() < 1.0, "y={}", y];
+  let (lon,lat) = WebMercator.inverse(x,y);
+  assert![(lon-13.4).abs() < 1e-9 && (lat-52.5).abs() < 1e-9];
+  Ok(())
+}
+
+#[test]
+fn query_projected() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(50)
+    .base_size(200)
+    .build()?;
+  let mut r = rand().seed([7,5]);
+  let points: Vec<P> = (0..1_000).map(|i| {
+    let lon = -180.0 + 360.0 * (i as f64) / 1_000.0;
+    let lat = r.read::<f64>()*160.0-80.0;
+    (lon,lat)
+  }).collect();
+  let batch: Vec<Row<P,V>> = points.iter().enumerate()
+    .map(|(i,p)| Row::Insert(*p,i as u32))
+    .collect();
+  db.batch(&batch[0..600])?;
+  db.batch(&batch[600..])?;
+
+  let bbox = ((-2_000_000.0,-1_000_000.0),(3_000_000.0,4_000_000.0));
+  let mut expected: Vec<(V,P)> = points.iter().enumerate()
+    .map(|(i,p)| (i as u32, p.project(&WebMercator)))
+    .filter(|(_,(x,y))| {
+      *x >= (bbox.0).0 && *x <= (bbox.1).0 && *y >= (bbox.0).1 && *y <= (bbox.1).1
+    })
+    .collect();
+  let mut results = vec![];
+  for result in db.query_projected(&bbox, &WebMercator)? {
+    let (point,value,_) = result?;
+    results.push((value,point));
+  }
+  expected.sort_by_key(|(v,_)| *v);
+  results.sort_by_key(|(v,_)| *v);
+  assert![expected.len() > 0, "test bbox covers some points"];
+  assert_eq![results, expected, "projected results"];
+  Ok(())
+}
@@ -0,0 +1,94 @@
+use eyros::{Setup,DB,Row,Summarize,Pruner,BlockInfo,Verdict,Location};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::collections::HashMap;
+
+type P = (f32,f32);
+type V = u32;
+type B = ((f32,f32),(f32,f32));
+
+struct Max;
+impl Summarize<P,V> for Max {
+  type Summary = u32;
+  fn name (&self) -> &str { "max" }
+  fn summarize (&self, rows: &[(P,V)]) -> u32 {
+    rows.iter().map(|(_,v)| *v).max().unwrap_or(0)
+  }
+  fn combine (&self, a: &u32, b: &u32) -> u32 { *a.max(b) }
+}
+
+struct AtLeast(u32);
+impl Pruner<P,V> for AtLeast {
+  fn summaries (&self) -> Vec<String> { vec!["max".into()] }
+  fn block (&self, _bbox: &B, block: &BlockInfo<P,V>) -> Verdict {
+    match block.summary(&Max) {
+      Ok(Some(max)) if max < self.0 => Verdict::Skip,
+      _ => Verdict::default()
+    }
+  }
+}
+
+struct Largest;
+impl Pruner<P,V> for Largest {
+  fn summaries (&self) -> Vec<String> { vec!["max".into()] }
+  fn block (&self, _bbox: &B, block: &BlockInfo<P,V>) -> Verdict {
+    assert![block.range.is_some() && block.rows.is_some()];
+    Verdict::Read(block.summary(&Max).unwrap().unwrap() as i64)
+  }
+}
+
+fn sorted (rows: Vec<(P,V,Location)>) -> Vec<V> {
+  let mut values: Vec<V> = rows.into_iter().map(|r| r.1).collect();
+  values.sort();
+  values
+}
+
+#[test]
+fn prune() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut db: DB<_,_,P,V> = Setup::new(|name: &str| {
+      Ok(RandomAccessDisk::builder(dir.path().join(name)).auto_sync(false).build()?)
+    })
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  db.add_summary(Max)?;
+  let mut r = rand().seed([13,12]);
+  // values grow with x, so blocks that split on x have small maximums
+  let rows: Vec<Row<P,V>> = (0..2_000).map(|_| {
+    let x: f32 = r.read::<f32>()*2.0-1.0;
+    let y: f32 = r.read::<f32>()*2.0-1.0;
+    Row::Insert((x,y), ((x+1.0)*1_000.0) as u32)
+  }).collect();
+  db.batch(&rows)?;
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let all: Vec<(P,V,Location)> = db.query(&bbox)?.collect::<Result<_,_>>()?;
+  assert_eq![all.len(), 2_000];
+  let mut block_max: HashMap<u64,V> = HashMap::new();
+  for (_,v,loc) in all.iter() {
+    assert![loc.0 > 0, "every row is in a block"];
+    let max = block_max.entry(loc.0).or_insert(0);
+    *max = (*max).max(*v);
+  }
+
+  db.set_pruner(AtLeast(1_500));
+  let pruned = db.query(&bbox)?.collect::<Result<Vec<_>,_>>()?;
+  let expected: Vec<_> = all.iter().cloned()
+    .filter(|(_,_,loc)| block_max[&loc.0] >= 1_500).collect();
+  assert![expected.len() < all.len(), "some blocks are skipped"];
+  assert_eq![sorted(pruned), sorted(expected)];
+  assert_eq![db.scan(&bbox)?.count(), 2_000, "scans ignore the pruner"];
+
+  db.set_pruner(Largest);
+  let first = db.query(&bbox)?.limit(1).next().unwrap()?;
+  let largest = block_max.values().max().unwrap();
+  assert_eq![block_max[&(first.2).0], *largest, "highest priority block is read first"];
+  let ordered = db.query(&bbox)?.collect::<Result<Vec<_>,_>>()?;
+  assert_eq![sorted(ordered), sorted(all.clone())];
+
+  db.clear_pruner();
+  assert_eq![db.query(&bbox)?.count(), 2_000];
+  Ok(())
+}
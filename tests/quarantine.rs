@@ -0,0 +1,134 @@
+use eyros::{Setup,DB,Row,Location,Job};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random_access_storage::RandomAccess;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::sync::{Arc,Mutex};
+use std::time::Duration;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn quarantine() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let full: Vec<(P,V,Location)> = {
+    let mut db: DB<_,_,P,V> = Setup::new(&storage)
+      .max_data_size(100)
+      .base_size(500)
+      .build()?;
+    let mut r = rand().seed([13,12]);
+    let batch: Vec<Row<P,V>> = (0..1_000).map(|i| {
+      let x: f32 = r.read::<f32>()*2.0-1.0;
+      let y: f32 = r.read::<f32>()*2.0-1.0;
+      Row::Insert((x,y), i)
+    }).collect();
+    db.batch(&batch)?;
+    db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?
+  };
+  assert_eq![full.len(), 1_000];
+
+  // corrupt the length header of one data block
+  let block = (full[0].2).0 - 1;
+  {
+    let mut data = storage("data")?;
+    data.write(block, &[0,0,0,1])?;
+    data.sync_all()?;
+  }
+  let expected = full.iter().filter(|row| (row.2).0 != block+1).count();
+  {
+    let mut db: DB<_,_,P,V> = Setup::new(&storage)
+      .max_data_size(100)
+      .base_size(500)
+      .build()?;
+    let reported = Arc::new(Mutex::new(vec![]));
+    {
+      let reported = Arc::clone(&reported);
+      db.set_quarantine_callback(move |offset, err| {
+        reported.lock().unwrap().push((offset, err.to_string()));
+      })?;
+    }
+    let results = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?;
+    assert_eq![results.len(), expected, "corrupt block skipped"];
+    assert_eq![db.quarantined()?, vec![block], "block quarantined"];
+    assert_eq![db.stats()?.quarantined_blocks, 1];
+    db.query(&bbox)?.count();
+    assert_eq![db.stats()?.quarantined_blocks, 1, "blocks are counted once"];
+    {
+      let reported = reported.lock().unwrap();
+      assert_eq![reported.len(), 1];
+      assert_eq![reported[0].0, block];
+      assert![!reported[0].1.is_empty()];
+    }
+    db.save_quarantine()?;
+  }
+  {
+    let mut db: DB<_,_,P,V> = Setup::new(&storage)
+      .max_data_size(100)
+      .base_size(500)
+      .build()?;
+    assert_eq![db.quarantined()?, vec![block], "quarantine persisted"];
+    let results = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?;
+    assert_eq![results.len(), expected, "quarantined block skipped"];
+    db.release_quarantine(block)?;
+    assert_eq![db.quarantined()?, Vec::<u64>::new(), "quarantine released"];
+  }
+  Ok(())
+}
+
+#[test]
+fn quarantine_maintenance() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let block = {
+    let mut db: DB<_,_,P,V> = Setup::new(&storage)
+      .max_data_size(100)
+      .base_size(500)
+      .build()?;
+    let batch: Vec<Row<P,V>> = (0..1_000).map(|i| {
+      Row::Insert(((i as f32)/1000.0,0.0), i)
+    }).collect();
+    db.batch(&batch)?;
+    let row = db.query(&bbox)?.next().unwrap()?;
+    (row.2).0 - 1
+  };
+  {
+    let mut data = storage("data")?;
+    data.write(block, &[0,0,0,1])?;
+    data.sync_all()?;
+  }
+  {
+    let (setup,_clock) = Setup::new(&storage)
+      .max_data_size(100)
+      .base_size(500)
+      .deterministic(1);
+    let mut db: DB<_,_,P,V> = setup.build()?;
+    assert_eq![db.pending_maintenance()?, vec![]];
+    db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?;
+    assert_eq![db.pending_maintenance()?, vec![Job::SaveQuarantine]];
+    let report = db.run_maintenance(Duration::from_millis(10))?;
+    assert_eq![report.completed, vec![Job::SaveQuarantine]];
+    assert_eq![report.remaining, 0];
+    assert_eq![db.pending_maintenance()?, vec![]];
+  }
+  let db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  assert_eq![db.quarantined()?, vec![block], "saved by maintenance"];
+  Ok(())
+}
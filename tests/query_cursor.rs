@@ -0,0 +1,78 @@
+use eyros::{Setup,DB,Row,QueryCursor,StaleCursor};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+type P = (f32,f32);
+type V = u32;
+
+fn storage (dir: PathBuf) -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> {
+  move |name: &str| {
+    RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build()
+  }
+}
+
+#[test]
+fn query_cursor() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut db: DB<_,_,P,V> = Setup::new(storage(dir.path().to_path_buf()))
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let rows: Vec<(P,V)> = (0..2_100).map(|i| {
+    ((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), i)
+  }).collect();
+  // the last 100 rows stay in staging
+  for chunk in rows.chunks(1_000) {
+    db.batch(&chunk.iter().map(|(p,v)| Row::Insert(*p,*v)).collect::<Vec<_>>())?;
+  }
+  let bbox = ((-0.6,-0.9),(0.7,0.4));
+  let mut expected: Vec<V> = db.query(&bbox)?
+    .map(|r| r.map(|(_,v,_)| v))
+    .collect::<Result<_,Error>>()?;
+  expected.sort_unstable();
+
+  // each page starts from a cursor that went through a string
+  let mut values = vec![];
+  let mut pages = 0;
+  let mut token = {
+    let mut page = db.query(&bbox)?.limit(97);
+    values.extend(page.by_ref().map(|r| r.map(|(_,v,_)| v))
+      .collect::<Result<Vec<_>,Error>>()?);
+    page.cursor().unwrap().to_string()
+  };
+  loop {
+    pages += 1;
+    let cursor: QueryCursor<P> = token.parse()?;
+    assert_eq![cursor.to_string(), token];
+    if cursor.is_done() { break }
+    let mut page = db.query_resume(&cursor)?.limit(97);
+    let rows = page.by_ref().map(|r| r.map(|(_,v,_)| v))
+      .collect::<Result<Vec<_>,Error>>()?;
+    assert![rows.len() <= 97];
+    values.extend(rows);
+    token = page.cursor().unwrap().to_string();
+  }
+  assert![pages > 5];
+  let unique: HashSet<V> = values.iter().cloned().collect();
+  assert_eq![unique.len(), values.len(), "no row is returned twice"];
+  values.sort_unstable();
+  assert_eq![values, expected];
+
+  // cursors go stale once a batch rebuilds the trees
+  let cursor = db.query(&bbox)?.limit(10).cursor().unwrap();
+  let next = db.query_resume(&cursor)?.count();
+  assert_eq![next, expected.len()];
+  db.batch(&rows[..600].iter().map(|(p,v)| Row::Insert(*p,*v)).collect::<Vec<_>>())?;
+  match db.query_resume(&cursor) {
+    Err(e) => assert![e.downcast_ref::<StaleCursor>().is_some()],
+    Ok(_) => panic!["expected a stale cursor"]
+  }
+  assert![QueryCursor::<P>::decode(&[9]).is_err()];
+  assert!["zz".parse::<QueryCursor<P>>().is_err()];
+  Ok(())
+}
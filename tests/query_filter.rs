@@ -0,0 +1,56 @@
+use eyros::{Setup,DB,Row,Location};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = u32;
+
+fn sorted (rows: Vec<(P,V,Location)>) -> Vec<V> {
+  let mut values: Vec<V> = rows.into_iter().map(|r| r.1).collect();
+  values.sort();
+  values
+}
+
+#[test]
+fn query_filter() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([5,13]);
+  let rows: Vec<(P,V)> = (0..2_400).map(|i| {
+    ((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), i)
+  }).collect();
+  let batch: Vec<Row<P,V>> = rows.iter().map(|(p,v)| Row::Insert(*p,*v)).collect();
+  db.batch(&batch[0..2_000])?;
+  db.batch(&batch[2_000..])?; // some rows stay in staging
+
+  let bbox = ((-0.5,-0.5),(0.5,0.5));
+  let inside = |p: &P| -0.5 <= p.0 && p.0 <= 0.5 && -0.5 <= p.1 && p.1 <= 0.5;
+  let expected: Vec<V> = rows.iter()
+    .filter(|(p,v)| v % 10 == 0 && inside(p))
+    .map(|(_,v)| *v).collect();
+  assert![!expected.is_empty()];
+  assert![expected.iter().any(|v| *v >= 2_000)];
+
+  let found = db.query_filter(&bbox, |v| v % 10 == 0)?
+    .collect::<Result<Vec<_>,Error>>()?;
+  assert_eq![sorted(found), expected];
+  assert_eq![db.query_filter(&bbox, |v| v % 10 == 0)?.limit(5).count(), 5];
+  assert_eq![db.query_filter(&bbox, |_| false)?.count(), 0];
+
+  // combines with the visibility predicate
+  db.set_visibility(|_,v| v % 4 == 0);
+  let found = db.query_filter(&bbox, |v| v % 10 == 0)?
+    .collect::<Result<Vec<_>,Error>>()?;
+  let both: Vec<V> = expected.iter().cloned().filter(|v| v % 4 == 0).collect();
+  assert_eq![sorted(found), both];
+  Ok(())
+}
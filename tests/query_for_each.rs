@@ -0,0 +1,56 @@
+use eyros::{Setup,DB,Row};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::ops::ControlFlow;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn query_for_each() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut db: DB<_,_,P,V> = Setup::new(
+    |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+      let p = dir.path().join(name);
+      Ok(RandomAccessDisk::builder(p)
+        .auto_sync(false)
+        .build()?)
+    })
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let batch: Vec<Row<P,V>> = (0..2_300).map(|i| {
+    let x: f32 = r.read::<f32>()*2.0-1.0;
+    let y: f32 = r.read::<f32>()*2.0-1.0;
+    Row::Insert((x,y), i)
+  }).collect();
+  db.batch(&batch)?;
+
+  let bbox = ((-0.5,-0.8),(0.3,0.5));
+  let mut expected: Vec<V> = vec![];
+  for result in db.query(&bbox)? {
+    expected.push(result?.1);
+  }
+  expected.sort();
+
+  let mut values: Vec<V> = vec![];
+  db.query_for_each(&bbox, |_p,v| {
+    values.push(*v);
+    ControlFlow::Continue(())
+  })?;
+  values.sort();
+  assert_eq![values, expected, "same results as query()"];
+
+  let mut count = 0;
+  db.query_for_each(&bbox, |p,_v| {
+    assert![-0.5 <= p.0 && p.0 <= 0.3, "x in bbox"];
+    count += 1;
+    if count < 25 { ControlFlow::Continue(()) }
+    else { ControlFlow::Break(()) }
+  })?;
+  assert_eq![count, 25, "stopped after break"];
+  Ok(())
+}
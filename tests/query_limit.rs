@@ -0,0 +1,165 @@
+use eyros::{Setup,DB,Row,CancelToken,Cancelled,TimedOut,Clock};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::time::Duration;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn query_limit() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let mut batch = |n: u32| -> Vec<Row<P,V>> {
+    (0..n).map(|i| {
+      let x: f32 = r.read::<f32>()*2.0-1.0;
+      let y: f32 = r.read::<f32>()*2.0-1.0;
+      Row::Insert((x,y), i)
+    }).collect()
+  };
+  db.batch(&batch(2_000))?; // written to a tree
+  db.batch(&batch(50))?; // stays in staging
+  let bbox = ((-0.5,-0.5),(0.5,0.5));
+
+  let mut limited = vec![];
+  for result in db.query(&bbox)?.limit(80) {
+    let (point,_,_) = result?;
+    assert![point.0 >= -0.5 && point.0 <= 0.5 && point.1 >= -0.5 && point.1 <= 0.5];
+    limited.push(point);
+  }
+  assert_eq![limited.len(), 80, "limit caps the number of results"];
+  let limited_blocks = db.data_store.read().unwrap().cached_blocks();
+
+  let mut all = 0;
+  for result in db.query(&bbox)? {
+    result?;
+    all += 1;
+  }
+  assert![all > 80, "enough matches to exceed the limit"];
+  assert![limited_blocks < db.data_store.read().unwrap().cached_blocks(),
+    "a limited query stops reading blocks early"];
+
+  let mut count = 0;
+  for result in db.query(&bbox)?.limit(all+10) {
+    result?;
+    count += 1;
+  }
+  assert_eq![count, all, "a limit above the number of matches returns all"];
+  assert_eq![db.query(&bbox)?.limit(0).count(), 0, "zero limit"];
+  Ok(())
+}
+
+#[test]
+fn query_cancel() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([5,6]);
+  let batch: Vec<Row<P,V>> = (0..1_000).map(|i| {
+    let x: f32 = r.read::<f32>()*2.0-1.0;
+    let y: f32 = r.read::<f32>()*2.0-1.0;
+    Row::Insert((x,y), i)
+  }).collect();
+  db.batch(&batch)?;
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+
+  let token = CancelToken::new();
+  let mut count = 0;
+  let mut iter = db.query(&bbox)?.cancel_on(token.clone());
+  while let Some(result) = iter.next() {
+    match result {
+      Ok(_) => count += 1,
+      Err(Error::Cancelled(_)) => break,
+      Err(e) => return Err(e)
+    }
+    if count == 25 { token.cancel() }
+  }
+  assert_eq![count, 25, "no results after cancellation"];
+  assert![iter.next().is_none(), "done after the cancelled error"];
+  // the rest of the query is still reachable from its cursor
+  let cursor = iter.cursor().unwrap();
+  drop(iter);
+  assert_eq![db.query_resume(&cursor)?.count(), batch.len() - 25];
+
+  let token = CancelToken::new();
+  token.cancel();
+  let results: Vec<_> = db.query(&bbox)?.cancel_on(token).collect();
+  assert_eq![results.len(), 1, "cancelled before the first result"];
+  assert![matches![results[0], Err(Error::Cancelled(Cancelled))]];
+  Ok(())
+}
+
+#[test]
+fn query_timeout() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let (setup,clock) = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .deterministic(7);
+  let mut db: DB<_,_,P,V> = setup.build()?;
+  let mut r = rand().seed([7,8]);
+  let batch: Vec<Row<P,V>> = (0..1_000).map(|i| {
+    let x: f32 = r.read::<f32>()*2.0-1.0;
+    let y: f32 = r.read::<f32>()*2.0-1.0;
+    Row::Insert((x,y), i)
+  }).collect();
+  db.batch(&batch)?;
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+
+  let mut iter = db.query(&bbox)?.timeout(Duration::from_millis(50));
+  let mut count = 0;
+  for _ in 0..40 {
+    iter.next().unwrap()?;
+    count += 1;
+  }
+  clock.advance(Duration::from_millis(49));
+  iter.next().unwrap()?;
+  count += 1;
+  clock.advance(Duration::from_millis(1));
+  match iter.next() {
+    Some(Err(Error::TimedOut(TimedOut { deadline }))) => {
+      assert_eq![deadline, Duration::from_millis(50)];
+    },
+    r => panic!["expected a timeout, got {:?}", r.map(|r| r.map(|_| ()))]
+  }
+  assert![iter.next().is_none(), "done after the timeout"];
+  let cursor = iter.cursor().unwrap();
+  drop(iter);
+  assert_eq![db.query_resume(&cursor)?.count(), batch.len() - count];
+
+  // the earlier of two deadlines applies
+  let now = clock.now();
+  let results: Vec<_> = db.query(&bbox)?
+    .deadline(now + Duration::from_secs(60))
+    .deadline(now)
+    .collect();
+  assert_eq![results.len(), 1];
+  assert![matches![results[0], Err(Error::TimedOut(_))]];
+  Ok(())
+}
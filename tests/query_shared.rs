@@ -0,0 +1,60 @@
+use eyros::{Setup,DB,Row,SharedRow};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::sync::Arc;
+
+type P = (f32,f32);
+type V = Vec<u8>;
+
+#[test]
+fn query_shared() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut db: DB<_,_,P,V> = Setup::new(
+    |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+      let p = dir.path().join(name);
+      Ok(RandomAccessDisk::builder(p)
+        .auto_sync(false)
+        .build()?)
+    })
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let batch: Vec<Row<P,V>> = (0..1_200).map(|i| {
+    let x: f32 = r.read::<f32>()*2.0-1.0;
+    let y: f32 = r.read::<f32>()*2.0-1.0;
+    Row::Insert((x,y), vec![(i%256) as u8;64])
+  }).collect();
+  db.batch(&batch)?;
+
+  let bbox = ((-0.5,-0.8),(0.3,0.5));
+  let mut expected = vec![];
+  for result in db.query(&bbox)? {
+    expected.push(result?);
+  }
+  let first: Vec<SharedRow<P,V>> = db.query_shared(&bbox)?
+    .collect::<Result<_,Error>>()?;
+  let second: Vec<SharedRow<P,V>> = db.query_shared(&bbox)?
+    .collect::<Result<_,Error>>()?;
+  assert_eq![first.len(), expected.len(), "same number of results"];
+
+  let mut owned: Vec<(P,V,(u64,u32))> = first.iter()
+    .map(|row| row.clone().into_owned()).collect();
+  owned.sort_by(|a,b| a.2.cmp(&b.2));
+  expected.sort_by(|a,b| a.2.cmp(&b.2));
+  assert_eq![owned, expected, "same results as query()"];
+
+  let mut shared = 0;
+  for (a,b) in first.iter().zip(second.iter()) {
+    if let (SharedRow::Block(x,i),SharedRow::Block(y,j)) = (a,b) {
+      assert![Arc::ptr_eq(x,y), "repeated query shares the cached block"];
+      assert_eq![i, j];
+      shared += 1;
+    }
+    assert![a.point().0 >= -0.5 && a.point().0 <= 0.3, "x in bbox"];
+  }
+  assert![shared > 0, "tree results are shared"];
+  Ok(())
+}
@@ -0,0 +1,79 @@
+use eyros::{Setup,DB,Row,Location};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = u32;
+
+fn sorted (rows: Vec<(P,V,Location)>) -> Vec<V> {
+  let mut values: Vec<V> = rows.into_iter().map(|r| r.1).collect();
+  values.sort();
+  values
+}
+
+#[test]
+fn query_threads() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .query_threads(3)
+    .build()?;
+  let mut r = rand().seed([8,30]);
+  let mut n = 0;
+  for size in [4_000,2_000,1_000,500,200].iter() {
+    let batch: Vec<Row<P,V>> = (0..*size).map(|_| {
+      n += 1;
+      Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), n)
+    }).collect();
+    db.batch(&batch)?;
+  }
+  let mut trees = 0;
+  for tree in db.trees.iter() {
+    if !tree.write().unwrap().is_empty()? { trees += 1 }
+  }
+  assert![trees >= 3, "rows span several trees"];
+
+  let bbox = ((-0.5,-0.5),(0.5,0.5));
+  let expected = sorted(db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?);
+  assert![expected.len() > 1_000];
+  let rows = db.query_parallel(&bbox)?.collect::<Result<Vec<_>,Error>>()?;
+  assert_eq![sorted(rows.clone()), expected];
+
+  // deletes through the locations of a parallel query
+  let deletes: Vec<Row<P,V>> = rows.iter().filter(|r| r.1 % 2 == 0)
+    .map(|r| Row::Delete(r.2)).collect();
+  db.batch(&deletes)?;
+  let odd: Vec<V> = expected.iter().cloned().filter(|v| v % 2 == 1).collect();
+  assert_eq![sorted(db.query_parallel(&bbox)?.collect::<Result<Vec<_>,Error>>()?), odd];
+  assert_eq![sorted(db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?), odd];
+
+  assert_eq![db.query_parallel(&bbox)?.limit(7).count(), 7];
+  // dropping a partly read query stops the workers
+  for _ in 0..20 {
+    let mut iter = db.query_parallel(&bbox)?;
+    assert![iter.next().is_some()];
+  }
+  let all = ((-1.0,-1.0),(1.0,1.0));
+  assert_eq![
+    db.query_parallel(&all)?.count(),
+    db.query(&all)?.count()
+  ];
+  drop(db);
+
+  // a block budget sends owned rows from the workers
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .query_threads(3)
+    .max_buffered_blocks(1)
+    .build()?;
+  assert_eq![sorted(db.query_parallel(&bbox)?.collect::<Result<Vec<_>,Error>>()?), odd];
+  Ok(())
+}
@@ -0,0 +1,71 @@
+use eyros::{Setup,DB,Row,DataRange};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random_access_storage::RandomAccess;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::path::PathBuf;
+
+type P = ((f32,f32),(f32,f32));
+type V = u32;
+
+fn storage (dir: PathBuf) -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> {
+  move |name: &str| {
+    Ok(RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build()?)
+  }
+}
+
+#[test]
+fn range_chunks() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut r = rand().seed([9,19]);
+  let batch: Vec<Row<P,V>> = (0..5_000).map(|i| {
+    let x = r.read::<f32>()*2.0-1.0;
+    let y = r.read::<f32>()*2.0-1.0;
+    Row::Insert(((x,x+0.01),(y,y+0.01)), i)
+  }).collect();
+  let bbox = ((-0.4,-0.2),(0.3,0.5));
+  let (stats,cost) = {
+    let mut db: DB<_,_,P,V> = Setup::new(storage(dir.path().to_path_buf()))
+      .max_data_size(50)
+      .base_size(500)
+      .build()?;
+    db.batch(&batch)?;
+    (db.tree_stats()?, db.estimate_cost(&bbox)?)
+  };
+
+  // 7 bytes is smaller than one record and splits most of them across chunks
+  for chunk_size in [7,100,4096].iter() {
+    let mut db: DB<_,_,P,V> = Setup::new(storage(dir.path().to_path_buf()))
+      .range_chunk_size(*chunk_size)
+      .build()?;
+    assert_eq![db.tree_stats()?, stats];
+    assert_eq![db.estimate_cost(&bbox)?, cost];
+  }
+
+  let blocks: u64 = stats.iter().map(|t| t.data_blocks).sum();
+  let open = || -> Result<DataRange<RandomAccessDisk,P>,Error> {
+    Ok(DataRange::new(RandomAccessDisk::builder(dir.path().join("range")).build()?, 0))
+  };
+  let mut ranges = open()?;
+  ranges.chunk_size = 13;
+  let mut rows = 0;
+  let mut n = 0;
+  for entry in ranges.list()? {
+    rows += entry?.2;
+    n += 1;
+  }
+  assert_eq![n, blocks];
+  assert_eq![rows, batch.len() as u64];
+
+  // a record cut short at the end of the store fails instead of being skipped
+  let len = ranges.store.len()?;
+  ranges.store.truncate(len-3)?;
+  let mut ranges = open()?;
+  ranges.chunk_size = 13;
+  let results: Vec<_> = ranges.list()?.collect();
+  assert_eq![results.len() as u64, blocks];
+  assert![results[..results.len()-1].iter().all(|r| r.is_ok())];
+  assert![results.last().unwrap().is_err()];
+  Ok(())
+}
@@ -0,0 +1,64 @@
+use eyros::{Setup,DB,Row,ChecksumMismatch,raw};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random_access_storage::RandomAccess;
+use tempfile::Builder as Tmpfile;
+use std::path::PathBuf;
+
+type P = (f32,f32);
+type V = u32;
+
+fn storage (dir: PathBuf) -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> {
+  move |name: &str| {
+    RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build()
+  }
+}
+
+#[test]
+fn raw_blocks() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let open = storage(dir.path().to_path_buf());
+  {
+    let mut db: DB<_,_,P,V> = Setup::new(&open)
+      .max_data_size(100)
+      .base_size(500)
+      .build()?;
+    let rows: Vec<Row<P,V>> = (0..1_000).map(|i| {
+      Row::Insert(((i as f32)/1000.0,0.5), i)
+    }).collect();
+    db.batch(&rows)?;
+    db.close()?;
+  }
+  let mut data = open("data")?;
+  let mut range = open("range")?;
+  let mut values = vec![];
+  let mut blocks = 0;
+  for entry in raw::range_entries::<_,P>(&mut range)? {
+    let (offset,((x0,x1),(y0,y1)),count) = entry?;
+    let block = raw::read_block(&mut data, offset)?;
+    let header = raw::parse_header(&block)?;
+    assert![header.checksum.is_some()];
+    assert_eq![header.bitfield.len() as u64, count.div_ceil(8)];
+    let rows = raw::parse_rows::<P,V>(&block)?;
+    assert_eq![rows.len(), header.live_rows()];
+    for (point,value,index) in rows {
+      assert![header.is_live(index as usize)];
+      assert![x0 <= point.0 && point.0 <= x1 && y0 <= point.1 && point.1 <= y1];
+      values.push(value);
+    }
+    blocks += 1;
+  }
+  assert![blocks >= 10, "{} blocks", blocks];
+  values.sort_unstable();
+  assert_eq![values, (0..1_000).collect::<Vec<V>>()];
+
+  // flipping a byte of the rows fails the checksum
+  let (offset,_,_) = raw::range_entries::<_,P>(&mut range)?.next().unwrap()?;
+  let len = data.read(offset, 4)?;
+  let len = u32::from_be_bytes([len[0],len[1],len[2],len[3]]) as u64;
+  let b = data.read(offset+len-1, 1)?[0];
+  data.write(offset+len-1, &[!b])?;
+  let err = raw::parse_rows::<P,V>(&raw::read_block(&mut data, offset)?).unwrap_err();
+  assert![err.downcast_ref::<ChecksumMismatch>().is_some()];
+  Ok(())
+}
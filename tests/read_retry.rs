@@ -0,0 +1,166 @@
+use eyros::{Setup,DB,Row,RetryPolicy,Clock};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random_access_storage::RandomAccess;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::cell::Cell;
+use std::io;
+use std::rc::Rc;
+use std::time::Duration;
+
+type P = (f32,f32);
+type V = u32;
+
+// fails every other read while `flaky` is set
+struct FlakyStore {
+  store: RandomAccessDisk,
+  flaky: Rc<Cell<bool>>,
+  fail_next: bool
+}
+
+impl RandomAccess for FlakyStore {
+  type Error = failure::Error;
+  fn write (&mut self, offset: u64, data: &[u8]) -> Result<(),failure::Error> {
+    self.store.write(offset, data)
+  }
+  fn read (&mut self, offset: u64, length: u64) -> Result<Vec<u8>,failure::Error> {
+    if self.flaky.get() {
+      self.fail_next = !self.fail_next;
+      if self.fail_next {
+        return Err(io::Error::new(io::ErrorKind::TimedOut, "timed out").into());
+      }
+    }
+    self.store.read(offset, length)
+  }
+  fn read_to_writer (&mut self, offset: u64, length: u64,
+  buf: &mut impl io::Write) -> Result<(),failure::Error> {
+    self.store.read_to_writer(offset, length, buf)
+  }
+  fn del (&mut self, offset: u64, length: u64) -> Result<(),failure::Error> {
+    self.store.del(offset, length)
+  }
+  fn truncate (&mut self, length: u64) -> Result<(),failure::Error> {
+    self.store.truncate(length)
+  }
+  fn len (&self) -> Result<u64,failure::Error> {
+    self.store.len()
+  }
+  fn is_empty (&mut self) -> Result<bool,failure::Error> {
+    self.store.is_empty()
+  }
+  fn sync_all (&mut self) -> Result<(),failure::Error> {
+    self.store.sync_all()
+  }
+}
+
+#[test]
+fn read_retry() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let flaky = Rc::new(Cell::new(false));
+  let storage = |name: &str| -> Result<FlakyStore,failure::Error> {
+    let p = dir.path().join(name);
+    let store = RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?;
+    let is_flaky = name == "data" || name.starts_with("tree");
+    Ok(FlakyStore {
+      store,
+      flaky: if is_flaky { Rc::clone(&flaky) } else { Rc::new(Cell::new(false)) },
+      fail_next: false
+    })
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .retry(RetryPolicy::new(3).backoff(Duration::from_millis(1)))
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let batch: Vec<Row<P,V>> = (0..1_000).map(|i| {
+    let x: f32 = r.read::<f32>()*2.0-1.0;
+    let y: f32 = r.read::<f32>()*2.0-1.0;
+    Row::Insert((x,y), i)
+  }).collect();
+  db.batch(&batch)?;
+
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  flaky.set(true);
+  let results = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?;
+  assert_eq![results.len(), 1_000, "transient failures are retried"];
+
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let results = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>();
+  assert![results.is_err(), "without retries the query fails"];
+  Ok(())
+}
+
+#[test]
+fn transient_errors_not_quarantined() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let flaky = Rc::new(Cell::new(false));
+  let storage = |name: &str| -> Result<FlakyStore,failure::Error> {
+    let p = dir.path().join(name);
+    let store = RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?;
+    Ok(FlakyStore {
+      store,
+      flaky: if name == "data" { Rc::clone(&flaky) } else { Rc::new(Cell::new(false)) },
+      fail_next: false
+    })
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let batch: Vec<Row<P,V>> = (0..1_000).map(|i| {
+    Row::Insert(((i as f32)/1000.0,0.0), i)
+  }).collect();
+  db.batch(&batch)?;
+  flaky.set(true);
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let results = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>();
+  assert![results.is_err(), "transient error is returned"];
+  assert_eq![db.quarantined()?, Vec::<u64>::new(), "no blocks quarantined"];
+  Ok(())
+}
+
+#[test]
+fn retry_backoff_with_manual_clock() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let flaky = Rc::new(Cell::new(false));
+  let storage = |name: &str| -> Result<FlakyStore,failure::Error> {
+    let p = dir.path().join(name);
+    let store = RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?;
+    Ok(FlakyStore {
+      store,
+      flaky: if name == "data" { Rc::clone(&flaky) } else { Rc::new(Cell::new(false)) },
+      fail_next: false
+    })
+  };
+  let (setup,clock) = Setup::new(&storage)
+    .max_data_size(50)
+    .base_size(300)
+    .retry(RetryPolicy::new(2)
+      .backoff(Duration::from_secs(60))
+      .max_backoff(Duration::from_secs(600)))
+    .deterministic(7);
+  let mut db: DB<_,_,P,V> = setup.build()?;
+  let batch: Vec<Row<P,V>> = (0..600).map(|i| {
+    Row::Insert(((i as f32)/1000.0,0.0), i)
+  }).collect();
+  db.batch(&batch)?;
+  flaky.set(true);
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let results = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?;
+  assert_eq![results.len(), 600];
+  // every data block fails once and is read again after a simulated backoff
+  let waited = clock.now().as_secs();
+  assert![waited >= 60 && waited % 60 == 0, "simulated backoff, got {}s", waited];
+  Ok(())
+}
@@ -0,0 +1,68 @@
+use eyros::{Setup,DB,Row,ManualClock,Clock,Job,Backpressure};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::sync::Arc;
+use std::time::Duration;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn realtime() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    RandomAccessDisk::builder(p).auto_sync(false).build()
+  };
+  let clock = Arc::new(ManualClock::new(Duration::from_secs(1_000)));
+  let c: Arc<dyn Clock> = clock.clone();
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .clock(c)
+    .max_data_size(100)
+    .base_size(500)
+    .realtime(Duration::from_millis(5), 1_500)
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let rows: Vec<(P,V)> = (0..3_000).map(|i| {
+    ((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), i)
+  }).collect();
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let batch = |rows: &[(P,V)]| -> Vec<Row<P,V>> {
+    rows.iter().map(|(p,v)| Row::Insert(*p,*v)).collect()
+  };
+
+  // without a measured build, every row stays in staging
+  db.batch(&batch(&rows[..1_000]))?;
+  db.batch(&batch(&rows[1_000..2_100]))?;
+  assert_eq![db.debt()?, 1_600];
+  assert_eq![db.pending_maintenance()?, vec![Job::BuildTrees]];
+  assert_eq![db.query(&bbox)?.count(), 2_100];
+
+  // past the debt limit, batches fail without writing anything
+  match db.batch(&batch(&rows[2_100..2_200])) {
+    Err(e) => assert_eq![
+      e.downcast_ref::<Backpressure>(),
+      Some(&Backpressure { debt: 1_600, limit: 1_500 })
+    ],
+    Ok(()) => panic!["expected backpressure"]
+  }
+  assert_eq![db.query(&bbox)?.count(), 2_100];
+
+  let report = db.run_maintenance(Duration::from_millis(5))?;
+  assert_eq![report.completed, vec![Job::BuildTrees]];
+  assert_eq![db.debt()?, 0];
+  assert![db.pending_maintenance()?.is_empty()];
+  assert_eq![db.query(&bbox)?.count(), 2_100];
+
+  // the manual clock makes the measured build free, so it fits the deadline
+  db.batch(&batch(&rows[2_100..]))?;
+  assert_eq![db.debt()?, 0];
+  let mut values: Vec<V> = db.query(&bbox)?
+    .map(|r| r.map(|(_,v,_)| v))
+    .collect::<Result<_,Error>>()?;
+  values.sort_unstable();
+  assert_eq![values, (0..3_000).collect::<Vec<V>>()];
+  Ok(())
+}
@@ -0,0 +1,81 @@
+use eyros::{Setup,DB,Row,Polygon,Circle,QueryRegion};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::path::PathBuf;
+
+type P = (f32,f32);
+type V = u32;
+
+fn storage (dir: PathBuf) -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> {
+  move |name: &str| {
+    Ok(RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build()?)
+  }
+}
+
+fn check<S,U,R> (db: &mut DB<S,U,P,V>, inserts: &[(P,V)], region: R)
+-> Result<usize,Error> where
+S: random_access_storage::RandomAccess<Error=failure::Error>,
+U: Fn(&str) -> Result<S,failure::Error>,
+R: QueryRegion<P>+Clone+'static {
+  let mut expected: Vec<V> = inserts.iter()
+    .filter(|(p,_)| region.contains_point(p))
+    .map(|(_,v)| *v)
+    .collect();
+  expected.sort();
+  let mut results = vec![];
+  for result in db.query_region(region)? {
+    results.push(result?.1);
+  }
+  results.sort();
+  assert_eq![results, expected];
+  Ok(results.len())
+}
+
+fn contains<R> (region: &R, point: P) -> bool where R: QueryRegion<P> {
+  region.contains_point(&point)
+}
+
+fn overlaps<R> (region: &R, bbox: ((f32,f32),(f32,f32))) -> bool
+where R: QueryRegion<P> {
+  region.overlaps_bbox(&bbox)
+}
+
+#[test]
+fn region() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut db: DB<_,_,P,V> = Setup::new(storage(dir.path().to_path_buf()))
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let inserts: Vec<(P,V)> = (0..5_000).map(|i| {
+    ((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), i)
+  }).collect();
+  let rows: Vec<Row<P,V>> = inserts.iter()
+    .map(|(p,v)| Row::Insert(*p,*v))
+    .collect();
+  db.batch(&rows[0..4_800])?;
+  db.batch(&rows[4_800..])?;
+
+  // L shape with a square hole
+  let polygon = Polygon::new(vec![
+    vec![(-0.8,-0.8),(0.6,-0.8),(0.6,-0.2),(-0.2,-0.2),(-0.2,0.7),(-0.8,0.7)],
+    vec![(-0.6,-0.6),(-0.4,-0.6),(-0.4,-0.4),(-0.6,-0.4)]
+  ]);
+  assert![!contains(&polygon, (0.3,0.3)), "outside the L"];
+  assert![!contains(&polygon, (-0.5,-0.5)), "inside the hole"];
+  assert![contains(&polygon, (-0.7,0.5))];
+  assert![!overlaps(&polygon, ((0.0,0.0),(0.5,0.5)))];
+  assert![overlaps(&polygon, ((-0.3,0.0),(0.5,0.5)))];
+  assert![check(&mut db, &inserts, polygon)? > 0];
+
+  let circle = Circle::new((0.25,0.25), 0.3);
+  assert![!overlaps(&circle, ((0.5,0.5),(0.6,0.6)))];
+  assert![check(&mut db, &inserts, circle)? > 0];
+
+  let empty = Polygon::new(vec![]);
+  assert_eq![check(&mut db, &inserts, empty)?, 0];
+  Ok(())
+}
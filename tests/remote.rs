@@ -0,0 +1,130 @@
+use eyros::{DB,Row,Setup,Fetch,RemoteStore,ReadOnly};
+use eyros::Error;
+use random::{Source,default as rand};
+use random_access_disk::RandomAccessDisk;
+use random_access_storage::RandomAccess;
+use tempfile::Builder as Tmpfile;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize,Ordering};
+
+type P = (f32,f32);
+type V = u32;
+
+// serves files under a directory as mock://db/{name}
+struct FileFetch {
+  dir: PathBuf,
+  requests: Arc<AtomicUsize>
+}
+
+impl FileFetch {
+  fn path (&self, url: &str) -> PathBuf {
+    self.dir.join(url.trim_start_matches("mock://db/"))
+  }
+}
+
+impl Fetch for FileFetch {
+  fn fetch (&self, url: &str, start: u64, end: u64) -> Result<Vec<u8>,failure::Error> {
+    self.requests.fetch_add(1, Ordering::SeqCst);
+    let buf = std::fs::read(self.path(url))?;
+    let end = (end as usize).min(buf.len());
+    Ok(buf[(start as usize).min(end)..end].to_vec())
+  }
+  fn size (&self, url: &str) -> Result<Option<u64>,failure::Error> {
+    match std::fs::metadata(self.path(url)) {
+      Ok(m) => Ok(Some(m.len())),
+      Err(_) => Ok(None)
+    }
+  }
+}
+
+#[test]
+fn remote() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let path = dir.path().to_path_buf();
+  let mut rows: Vec<(P,V)> = vec![];
+  {
+    let mut db: DB<_,_,P,V> = Setup::new(move |name: &str| {
+      Ok(RandomAccessDisk::builder(path.join(name)).auto_sync(false).build()?)
+    }).max_data_size(100).base_size(500).build()?;
+    let mut r = rand().seed([7,77]);
+    for size in [1_500,400,30].iter() {
+      let batch: Vec<Row<P,V>> = (0..*size).map(|_| {
+        let p = (r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0);
+        rows.push((p,rows.len() as u32));
+        Row::Insert(p, rows.len() as u32 - 1)
+      }).collect();
+      db.batch(&batch)?;
+    }
+  }
+
+  let requests = Arc::new(AtomicUsize::new(0));
+  let fetch = FileFetch { dir: dir.path().to_path_buf(), requests: Arc::clone(&requests) };
+  let mut db: DB<_,_,P,V> = DB::open_remote_with(fetch, "mock://db/", |setup| {
+    setup.max_data_size(100).base_size(500)
+  })?;
+  let bbox = ((-0.5,-0.5),(0.3,0.6));
+  let inside = |p: &P| -0.5 <= p.0 && p.0 <= 0.3 && -0.5 <= p.1 && p.1 <= 0.6;
+  let mut expected: Vec<V> = rows.iter().filter(|(p,_)| inside(p)).map(|r| r.1).collect();
+  expected.sort();
+  let query = |db: &mut DB<_,_,P,V>| -> Result<Vec<V>,Error> {
+    let mut values = db.query(&bbox)?.map(|r| r.map(|r| r.1))
+      .collect::<Result<Vec<V>,Error>>()?;
+    values.sort();
+    Ok(values)
+  };
+  let before = requests.load(Ordering::SeqCst);
+  assert_eq![query(&mut db)?, expected];
+  let first = requests.load(Ordering::SeqCst) - before;
+  assert![first > 0];
+  // the second query is served from the page and block caches
+  assert_eq![query(&mut db)?, expected];
+  assert_eq![requests.load(Ordering::SeqCst) - before, first];
+
+  // writes fail without poisoning the handle
+  let err = db.batch(&[Row::Insert((0.0,0.0),9_999)]).unwrap_err();
+  assert_eq![err.downcast_ref::<ReadOnly>(), Some(&ReadOnly)];
+  let err = db.delete_query(&bbox).unwrap_err();
+  assert_eq![err.downcast_ref::<ReadOnly>(), Some(&ReadOnly)];
+  assert![db.compact().is_err()];
+  assert_eq![query(&mut db)?, expected];
+  Ok(())
+}
+
+#[test]
+fn remote_pages() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let data: Vec<u8> = (0..20_000).map(|i| (i % 251) as u8).collect();
+  std::fs::write(dir.path().join("blob"), &data)?;
+  let requests = Arc::new(AtomicUsize::new(0));
+  let fetch = Arc::new(FileFetch { dir: dir.path().to_path_buf(), requests: Arc::clone(&requests) });
+  let mut store = RemoteStore::open(fetch, "mock://db/blob")?.page_size(1_024);
+  assert_eq![store.len()?, 20_000];
+  let count = || requests.load(Ordering::SeqCst);
+
+  // a read over 3 missing pages takes one request
+  assert_eq![store.read(100, 2_500)?, data[100..2_600].to_vec()];
+  assert_eq![count(), 1];
+  assert_eq![store.read(1_500, 100)?, data[1_500..1_600].to_vec()];
+  assert_eq![count(), 1];
+  // pages 2 and 3: one cached, one fetched
+  assert_eq![store.read(2_900, 500)?, data[2_900..3_400].to_vec()];
+  assert_eq![count(), 2];
+  // the last page is short
+  assert_eq![store.read(19_990, 10)?, data[19_990..].to_vec()];
+  assert_eq![count(), 3];
+  // large reads bypass the page cache
+  assert_eq![store.read(0, 8_000)?, data[..8_000].to_vec()];
+  assert_eq![store.read(0, 8_000)?, data[..8_000].to_vec()];
+  assert_eq![count(), 5];
+
+  assert![store.read(19_000, 2_000).is_err()];
+  assert![store.write(0, &[1,2,3]).is_err()];
+  assert![store.truncate(0).is_err()];
+
+  // missing objects are empty
+  let fetch = Arc::new(FileFetch { dir: dir.path().to_path_buf(), requests });
+  let store = RemoteStore::open(fetch, "mock://db/missing")?;
+  assert_eq![store.len()?, 0];
+  Ok(())
+}
@@ -0,0 +1,83 @@
+use eyros::{Setup,DB,Row};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::path::{Path,PathBuf};
+
+type P = (f32,f32);
+type V = u32;
+
+fn storage (dir: PathBuf) -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> {
+  move |name: &str| {
+    Ok(RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build()?)
+  }
+}
+
+fn values<S,U> (db: &mut DB<S,U,P,V>) -> Result<Vec<V>,Error> where
+S: random_access_storage::RandomAccess<Error=failure::Error>,
+U: Fn(&str) -> Result<S,failure::Error> {
+  let mut values = vec![];
+  for result in db.query(&((-1.0,-1.0),(1.0,1.0)))? {
+    values.push(result?.1);
+  }
+  values.sort();
+  Ok(values)
+}
+
+fn copy_dir (src: &Path, dst: &Path) -> Result<(),Error> {
+  for entry in std::fs::read_dir(src)? {
+    let entry = entry?;
+    std::fs::copy(entry.path(), dst.join(entry.file_name()))?;
+  }
+  Ok(())
+}
+
+#[test]
+fn restore() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let backup_dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut db: DB<_,_,P,V> = Setup::new(storage(dir.path().to_path_buf()))
+    .max_data_size(100)
+    .base_size(300)
+    .changes(true)
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let mut next_value = 0;
+  let mut snapshots = vec![];
+  let mut backup = 0;
+  for round in 0..5 {
+    let inserts: Vec<Row<P,V>> = (0..200).map(|_| {
+      next_value += 1;
+      let x = (next_value as f32)/1_000.0*2.0-1.0;
+      Row::Insert((x, r.read::<f32>()*2.0-1.0), next_value)
+    }).collect();
+    db.batch(&inserts)?;
+    db.delete_query(&((-0.5,-0.5),(-0.4,-0.4)))?;
+    snapshots.push((db.last_change()?, values(&mut db)?));
+    if round == 1 {
+      copy_dir(dir.path(), backup_dir.path())?;
+      backup = db.last_change()?;
+    }
+  }
+
+  let (seq,expected) = &snapshots[2];
+  let edir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut dest: DB<_,_,P,V> = DB::open(storage(edir.path().to_path_buf()))?;
+  let report = db.restore(&mut dest, 0, *seq, 150)?;
+  assert_eq![report.changes, *seq];
+  assert_eq![report.seq, *seq];
+  assert_eq![&values(&mut dest)?, expected, "from an empty database"];
+
+  let (seq,expected) = &snapshots[3];
+  let mut dest: DB<_,_,P,V> = DB::open(
+    storage(backup_dir.path().to_path_buf())
+  )?;
+  let report = db.restore(&mut dest, backup, *seq, 1000)?;
+  assert_eq![report.changes, seq - backup];
+  assert_eq![&values(&mut dest)?, expected, "from a backup"];
+
+  assert![db.restore(&mut dest, 10, 5, 100).is_err()];
+  assert![db.restore(&mut dest, 0, db.last_change()?+1, 100).is_err()];
+  Ok(())
+}
@@ -0,0 +1,176 @@
+use eyros::{Setup,DB,Row,Retention,HistoryPruned};
+use eyros::Error;
+use failure::bail;
+use random_access_disk::RandomAccessDisk;
+use random_access_storage::RandomAccess;
+use tempfile::Builder as Tmpfile;
+use std::cell::Cell;
+use std::io;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Duration;
+
+type P = (f32,f32);
+type V = u32;
+
+fn storage (dir: PathBuf) -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> {
+  move |name: &str| {
+    Ok(RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build()?)
+  }
+}
+
+fn inserts (start: u32, n: u32) -> Vec<Row<P,V>> {
+  (start..start+n).map(|i| {
+    let x = (i as f32)/1_000.0*2.0-1.0;
+    Row::Insert((x,0.0), i)
+  }).collect()
+}
+
+#[test]
+fn retention() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let (setup,clock) = Setup::new(storage(dir.path().to_path_buf()))
+    .max_data_size(100)
+    .base_size(300)
+    .changes(true)
+    .retention(Retention::default()
+      .max_changes(150)
+      .max_age(Duration::from_secs(60)))
+    .deterministic(5);
+  let mut db: DB<_,_,P,V> = setup.build()?;
+
+  db.batch(&inserts(0, 100))?;
+  clock.advance(Duration::from_secs(30));
+  db.batch(&inserts(100, 100))?;
+  assert_eq![db.prune_history()?, 50, "max_changes"];
+  let changes = db.changes(50)?.collect::<Result<Vec<_>,Error>>()?;
+  assert_eq![changes.len(), 150];
+  assert_eq![changes[0].seq, 51];
+  assert_eq![changes[0].time, Duration::from_secs(0)];
+  assert_eq![changes[149].time, Duration::from_secs(30)];
+
+  let err = db.changes(10).err().expect("pruned changes");
+  let pruned = err.downcast_ref::<HistoryPruned>().expect("pruned error");
+  assert_eq![pruned.first, 51];
+
+  clock.advance(Duration::from_secs(45));
+  db.batch(&inserts(200, 20))?;
+  let report = db.compact()?;
+  assert_eq![report.changes_pruned, 50, "max_age"];
+  let changes = db.changes(100)?.collect::<Result<Vec<_>,Error>>()?;
+  assert_eq![changes.len(), 120];
+  assert_eq![changes.first().map(|c| c.seq), Some(101)];
+  assert_eq![changes.last().map(|c| c.seq), Some(220)];
+  assert_eq![db.prune_history()?, 0, "nothing left to prune"];
+
+  drop(db);
+  let db: DB<_,_,P,V> = Setup::new(storage(dir.path().to_path_buf()))
+    .max_data_size(100)
+    .base_size(300)
+    .changes(true)
+    .build()?;
+  assert_eq![db.last_change()?, 220, "feed persists"];
+  assert_eq![db.changes(100)?.count(), 120];
+  assert![db.changes(99).is_err()];
+  Ok(())
+}
+
+struct CrashStore {
+  store: RandomAccessDisk,
+  budget: Rc<Cell<Option<usize>>>
+}
+
+impl CrashStore {
+  fn spend (&mut self) -> Result<(),failure::Error> {
+    match self.budget.get() {
+      Some(0) => bail!["crashed"],
+      Some(n) => self.budget.set(Some(n-1)),
+      None => {}
+    }
+    Ok(())
+  }
+}
+
+impl RandomAccess for CrashStore {
+  type Error = failure::Error;
+  fn write (&mut self, offset: u64, data: &[u8]) -> Result<(),failure::Error> {
+    self.spend()?;
+    self.store.write(offset, data)
+  }
+  fn read (&mut self, offset: u64, length: u64) -> Result<Vec<u8>,failure::Error> {
+    self.store.read(offset, length)
+  }
+  fn read_to_writer (&mut self, offset: u64, length: u64,
+  buf: &mut impl io::Write) -> Result<(),failure::Error> {
+    self.store.read_to_writer(offset, length, buf)
+  }
+  fn del (&mut self, offset: u64, length: u64) -> Result<(),failure::Error> {
+    self.spend()?;
+    self.store.del(offset, length)
+  }
+  fn truncate (&mut self, length: u64) -> Result<(),failure::Error> {
+    self.spend()?;
+    self.store.truncate(length)
+  }
+  fn len (&self) -> Result<u64,failure::Error> {
+    self.store.len()
+  }
+  fn is_empty (&mut self) -> Result<bool,failure::Error> {
+    self.store.is_empty()
+  }
+  fn sync_all (&mut self) -> Result<(),failure::Error> {
+    self.spend()?;
+    self.store.sync_all()
+  }
+}
+
+#[test]
+fn retention_crash() -> Result<(),Error> {
+  let mut writes = 0;
+  let mut crash_points = vec![None];
+  // run once without crashing to count writes, then crash after each write
+  while let Some(crash_after) = crash_points.pop() {
+    let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+    let budget = Rc::new(Cell::new(None));
+    let storage = |name: &str| -> Result<CrashStore,failure::Error> {
+      Ok(CrashStore {
+        store: RandomAccessDisk::builder(dir.path().join(name)).auto_sync(false).build()?,
+        budget: Rc::clone(&budget)
+      })
+    };
+    let setup = || Setup::new(&storage)
+      .max_data_size(100)
+      .base_size(300)
+      .changes(true);
+    let expected = {
+      let mut db: DB<_,_,P,V> = setup()
+        .retention(Retention::default().max_changes(150))
+        .build()?;
+      db.batch(&inserts(0, 100))?;
+      db.batch(&inserts(100, 100))?;
+      let expected = db.changes(50)?.collect::<Result<Vec<_>,Error>>()?;
+      budget.set(Some(crash_after.unwrap_or(usize::MAX)));
+      let res = db.prune_history();
+      assert_eq![res.is_err(), crash_after.is_some(), "crash after {:?}", crash_after];
+      if crash_after.is_none() {
+        writes = usize::MAX - budget.get().unwrap();
+        crash_points = (0..writes).map(Some).collect();
+      }
+      budget.set(None);
+      expected
+    };
+    let db: DB<_,_,P,V> = setup().build()?;
+    assert_eq![db.last_change()?, 200, "crash after {:?} of {}", crash_after, writes];
+    let changes = db.changes(50)?.collect::<Result<Vec<_>,Error>>()?;
+    assert_eq![changes, expected, "crash after {:?} of {}", crash_after, writes];
+    match db.changes(0) {
+      Ok(all) => assert_eq![all.count(), 200, "crash after {:?}", crash_after],
+      Err(err) => {
+        let pruned = err.downcast_ref::<HistoryPruned>().expect("pruned error");
+        assert_eq![pruned.first, 51, "crash after {:?}", crash_after];
+      }
+    }
+  }
+  assert![writes > 0];
+  Ok(())
+}
@@ -0,0 +1,56 @@
+use eyros::{Setup,DB,Row,CacheMode};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn scan_cache_bypass() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  {
+    let mut db: DB<_,_,P,V> = Setup::new(&storage)
+      .max_data_size(100)
+      .base_size(500)
+      .build()?;
+    let mut r = rand().seed([13,12]);
+    let batch: Vec<Row<P,V>> = (0..1_500).map(|i| {
+      let x: f32 = r.read::<f32>()*2.0-1.0;
+      let y: f32 = r.read::<f32>()*2.0-1.0;
+      Row::Insert((x,y), i)
+    }).collect();
+    db.batch(&batch)?;
+  }
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .maintenance_cache(CacheMode::Bypass)
+    .build()?;
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let mut scanned = vec![];
+  for result in db.scan(&bbox)? {
+    scanned.push(result?.1);
+  }
+  assert_eq![scanned.len(), 1_500, "scan returns every row"];
+  assert_eq![db.data_store.read().unwrap().cached_blocks(), 0,
+    "scan does not populate the block cache"];
+
+  let mut queried = vec![];
+  for result in db.query(&bbox)? {
+    queried.push(result?.1);
+  }
+  assert![db.data_store.read().unwrap().cached_blocks() > 0,
+    "query populates the block cache"];
+  scanned.sort();
+  queried.sort();
+  assert_eq![scanned, queried, "scan and query agree"];
+  Ok(())
+}
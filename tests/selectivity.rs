@@ -0,0 +1,74 @@
+use eyros::{Setup,DB,Row};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::path::PathBuf;
+
+type P = (f32,f32);
+type V = u32;
+
+fn storage (dir: PathBuf) -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> {
+  move |name: &str| {
+    RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build()
+  }
+}
+
+const DENSE: V = 1_000_000;
+
+fn fill<S,U> (db: &mut DB<S,U,P,V>) -> Result<(),Error> where
+S: random_access_storage::RandomAccess<Error=failure::Error>,
+U: Fn(&str) -> Result<S,failure::Error> {
+  let mut r = rand().seed([13,12]);
+  // a large tree packed into the top right corner
+  let dense: Vec<Row<P,V>> = (0..2_000).map(|i| {
+    Row::Insert((0.5+r.read::<f32>()*0.5, 0.5+r.read::<f32>()*0.5), DENSE+i)
+  }).collect();
+  db.batch(&dense)?;
+  // a small tree spread over the whole space, which plain queries visit first
+  let sparse: Vec<Row<P,V>> = (0..1_000).map(|i| {
+    Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), i)
+  }).collect();
+  db.batch(&sparse)?;
+  Ok(())
+}
+
+#[test]
+fn selectivity_order() -> Result<(),Error> {
+  let bbox = ((0.6,0.6),(0.9,0.9));
+  let mut results = vec![];
+  for enabled in [false,true].iter() {
+    let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+    let mut db: DB<_,_,P,V> = Setup::new(storage(dir.path().to_path_buf()))
+      .max_data_size(100)
+      .base_size(500)
+      .selectivity_order(*enabled)
+      .build()?;
+    fill(&mut db)?;
+    let counts: Vec<u64> = db.tree_stats()?.iter().map(|t| t.rows).collect();
+    assert_eq![counts.iter().filter(|n| **n > 0).count(), 2, "trees: {:?}", counts];
+    let first = db.query(&bbox)?.limit(20).collect::<Result<Vec<_>,Error>>()?;
+    assert_eq![first.len(), 20];
+    let from_dense = first.iter().filter(|(_,v,_)| *v >= DENSE).count();
+    if *enabled {
+      assert_eq![from_dense, 20, "the dense tree is read first"];
+    } else {
+      assert![from_dense < 20, "the sparse tree is read first"];
+    }
+    // ordering changes only when rows arrive, not which rows match
+    let mut all: Vec<V> = db.query(&bbox)?
+      .map(|r| r.map(|(_,v,_)| v))
+      .collect::<Result<_,Error>>()?;
+    all.sort_unstable();
+    results.push(all);
+    // statistics are rebuilt for trees written after the first query
+    let mut r = rand().seed([5,6]);
+    db.batch(&(0..500).map(|i| {
+      Row::Insert((0.7+r.read::<f32>()*0.1, 0.7+r.read::<f32>()*0.1), 2*DENSE+i)
+    }).collect::<Vec<_>>())?;
+    let n = db.query(&bbox)?.count();
+    assert_eq![n, results.last().unwrap().len() + 500];
+  }
+  assert_eq![results[0], results[1]];
+  Ok(())
+}
@@ -0,0 +1,73 @@
+use eyros::{Setup,DB,Row,QueryIterator};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::thread;
+
+type P = (f32,f32);
+type V = u32;
+
+fn assert_send_sync<T: Send+Sync> () {}
+fn assert_send<T: Send> () {}
+
+#[test]
+fn send_sync() -> Result<(),Error> {
+  type Open = fn (&str) -> Result<RandomAccessDisk,failure::Error>;
+  assert_send_sync::<DB<RandomAccessDisk,Open,P,V>>();
+  assert_send::<QueryIterator<'static,RandomAccessDisk,P,V>>();
+
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let path = dir.path().to_path_buf();
+  let mut db: DB<_,_,P,V> = Setup::new(move |name: &str| {
+      Ok(RandomAccessDisk::builder(path.join(name)).auto_sync(false).build()?)
+    })
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let rows: Vec<Row<P,V>> = (0..2_100).map(|i| {
+    let x: f32 = r.read::<f32>()*2.0-1.0;
+    let y: f32 = r.read::<f32>()*2.0-1.0;
+    Row::Insert((x,y), i)
+  }).collect();
+  // write from another thread, then move the handle back
+  let mut db = thread::spawn(move || -> Result<_,Error> {
+    db.batch(&rows)?;
+    Ok(db)
+  }).join().unwrap()?;
+
+  let bboxes = vec![
+    ((-1.0,-1.0),(1.0,1.0)),
+    ((-0.5,-0.8),(0.3,-0.5)),
+    ((0.2,0.2),(0.9,0.4)),
+    ((-0.9,0.1),(-0.1,0.9))
+  ];
+  let mut expected = vec![];
+  for bbox in bboxes.iter() {
+    let mut values: Vec<V> = db.query(bbox)?.map(|r| r.map(|row| row.1))
+      .collect::<Result<_,Error>>()?;
+    values.sort();
+    expected.push(values);
+  }
+  assert_eq![expected[0].len(), 2_100];
+
+  // read several queries at once on separate threads
+  let mut queries = vec![];
+  for bbox in bboxes.iter() {
+    queries.push(db.query(bbox)?);
+  }
+  let results: Vec<Vec<V>> = thread::scope(|scope| {
+    let handles: Vec<_> = queries.into_iter().map(|q| {
+      scope.spawn(move || -> Result<Vec<V>,Error> {
+        let mut values: Vec<V> = q.map(|r| r.map(|row| row.1))
+          .collect::<Result<_,Error>>()?;
+        values.sort();
+        Ok(values)
+      })
+    }).collect();
+    handles.into_iter().map(|h| h.join().unwrap()).collect::<Result<_,Error>>()
+  })?;
+  assert_eq![results, expected];
+  Ok(())
+}
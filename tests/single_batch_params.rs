@@ -5,7 +5,7 @@ extern crate random_access_disk;
 extern crate tempfile;
 
 use eyros::{Setup,Row};
-use failure::Error;
+use eyros::Error;
 use random_access_disk::RandomAccessDisk;
 use random::{Source,default as rand};
 use tempfile::Builder as Tmpfile;
@@ -49,7 +49,7 @@ fn single_200k_batch_xxyyz_9_bf_400_data_1k_base() -> Result<(),Error> {
 fn from_params (size: usize, bf: usize, max_data_size: usize,
 base_size: usize) -> Result<(),Error> {
   let dir = Tmpfile::new().prefix("eyros").tempdir()?;
-  let open = |name: &str| -> Result<RandomAccessDisk,Error> {
+  let open = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
     let p = dir.path().join(name);
     Ok(RandomAccessDisk::builder(p)
       .auto_sync(false)
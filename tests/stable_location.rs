@@ -0,0 +1,81 @@
+use eyros::{Setup,DB,Row,StableLocation,StaleLocation};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn stable_location_encoding() -> Result<(),Error> {
+  let loc = StableLocation { generation: 3, offset: 0x0102, index: 7 };
+  assert_eq![loc.encode().to_vec(), vec![
+    1, 0,0,0,0,0,0,0,3, 0,0,0,0,0,0,1,2, 0,0,0,7
+  ]];
+  assert_eq![StableLocation::decode(&loc.encode())?, loc];
+  let s = loc.to_string();
+  assert_eq![s, "010000000000000003000000000000010200000007"];
+  assert_eq![s.parse::<StableLocation>()?, loc];
+  assert![StableLocation::decode(&loc.encode()[..20]).is_err()];
+  let mut v2 = loc.encode();
+  v2[0] = 2;
+  assert![StableLocation::decode(&v2).is_err()];
+  assert!["01zz".parse::<StableLocation>().is_err()];
+
+  // ids are fixed for a seed
+  assert_eq![loc.id(0), loc.id(0)];
+  assert_eq![loc.id(0), 0xa2b1_79bd_7493_a0f0];
+  assert_ne![loc.id(0), loc.id(1)];
+  assert_ne![loc.id(0), StableLocation { index: 8, ..loc }.id(0)];
+  Ok(())
+}
+
+#[test]
+fn stable_location() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    Ok(RandomAccessDisk::builder(dir.path().join(name)).auto_sync(false).build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let batch: Vec<Row<P,V>> = (0..1_000).map(|i| {
+    Row::Insert((i as f32 / 1_000.0, 0.5), i)
+  }).collect();
+  db.batch(&batch)?;
+  db.batch(&[Row::Insert((0.25,0.25),5_000)])?;
+
+  // one row in a data block and one in staging
+  let bbox = ((0.2495,0.2),(0.2505,0.6));
+  let rows = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?;
+  assert_eq![rows.len(), 2];
+  let stable: Vec<StableLocation> = rows.iter().map(|r| db.stable_location(&r.2)).collect();
+  assert![stable.iter().any(|s| s.offset == 0) && stable.iter().any(|s| s.offset > 0)];
+  // round trip through a queue as strings and through another database
+  let queued: Vec<String> = stable.iter().map(|s| s.to_string()).collect();
+  let mut other: DB<_,_,P,StableLocation> = Setup::new(|name: &str| {
+    storage(&format!["other_{}", name])
+  }).build()?;
+  other.batch(&stable.iter().map(|s| Row::Insert((0.0,0.0),*s)).collect::<Vec<_>>())?;
+  let mut stored: Vec<StableLocation> = other.query(&((0.0,0.0),(0.0,0.0)))?
+    .map(|r| r.map(|r| r.1)).collect::<Result<Vec<_>,Error>>()?;
+  stored.sort_by_key(|s| s.offset);
+  let mut expected = stable.clone();
+  expected.sort_by_key(|s| s.offset);
+  assert_eq![stored, expected];
+
+  let deletes = queued.iter().map(|s| {
+    Ok(Row::Delete(db.resolve_location(&s.parse()?)?))
+  }).collect::<Result<Vec<Row<P,V>>,Error>>()?;
+  db.batch(&deletes)?;
+  assert_eq![db.query(&bbox)?.count(), 0];
+
+  // every batch advances the sequence, even one that only stages rows
+  let err = db.resolve_location(&stable[0]).unwrap_err();
+  assert_eq![err.downcast_ref::<StaleLocation>(), Some(&StaleLocation {
+    generation: stable[0].generation,
+    sequence: db.sequence()
+  })];
+  Ok(())
+}
@@ -0,0 +1,56 @@
+use eyros::{Setup,DB,Row,Stats};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn stats() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  assert_eq![db.stats()?, Stats::default()];
+  let mut r = rand().seed([9,4]);
+  let batch: Vec<Row<P,V>> = (0..2_000).map(|i| {
+    Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), i)
+  }).collect();
+  db.batch(&batch)?;
+  let stats = db.stats()?;
+  assert![stats.batch_bytes_written > 2_000*12, "rows went to storage"];
+  assert_eq![stats.batch_bytes_written, stats.bytes_written];
+
+  // a small batch stays in staging
+  db.batch(&[Row::Insert((0.0,0.0),5_000)])?;
+  let stats = db.stats()?;
+  assert![stats.batch_bytes_written < 100];
+  assert![stats.bytes_written > stats.batch_bytes_written];
+
+  db.reset_stats()?;
+  let bbox = ((-0.5,-0.5),(0.5,0.5));
+  let n = db.query(&bbox)?.count();
+  let first = db.stats()?;
+  assert![first.block_cache_misses > 0];
+  assert![first.bytes_read > 0];
+  assert_eq![first.bytes_written, 0];
+  assert_eq![db.query(&bbox)?.count(), n];
+  let second = db.stats()?;
+  assert_eq![second.block_cache_misses, first.block_cache_misses,
+    "blocks are cached"];
+  assert_eq![second.block_cache_hits - first.block_cache_hits,
+    first.block_cache_misses];
+  // only branches are read again
+  assert![second.bytes_read - first.bytes_read < first.bytes_read];
+
+  db.reset_stats()?;
+  assert_eq![db.stats()?, Stats::default()];
+  Ok(())
+}
@@ -0,0 +1,89 @@
+use eyros::{Setup,DB,Row,Change};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use tempfile::Builder as Tmpfile;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize,Ordering};
+use std::task::{Context,Poll,Wake,Waker};
+
+type P = (f32,f32);
+type V = u32;
+
+fn storage (dir: PathBuf) -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> {
+  move |name: &str| {
+    RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build()
+  }
+}
+
+struct Wakes(AtomicUsize);
+
+impl Wake for Wakes {
+  fn wake (self: Arc<Self>) {
+    self.0.fetch_add(1, Ordering::SeqCst);
+  }
+}
+
+#[test]
+fn subscribe() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut db: DB<_,_,P,V> = Setup::new(storage(dir.path().to_path_buf()))
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  db.batch(&[Row::Insert((0.5,0.5), 0)])?;
+  let mut sub = db.subscribe(&((0.0,0.0),(1.0,1.0)))?;
+  let mut other = db.subscribe(&((-1.0,-1.0),(-0.5,-0.5)))?;
+  assert_eq![sub.try_next(), None];
+
+  // a batch of 600 rows goes into a tree, half of them inside the box
+  let rows: Vec<Row<P,V>> = (1..=600).map(|i| {
+    let x = if i % 2 == 0 { 0.25 } else { -0.25 };
+    Row::Insert((x, i as f32 / 1000.0), i)
+  }).collect();
+  db.batch(&rows)?;
+  assert_eq![sub.pending(), 300];
+  assert_eq![other.pending(), 0];
+  let values: Vec<V> = std::iter::from_fn(|| sub.try_next()).map(|c| match c {
+    Change::Insert(_,v) => v,
+    c => panic!["unexpected {:?}", c]
+  }).collect();
+  assert_eq![values, (1..=300).map(|i| i*2).collect::<Vec<V>>()];
+
+  // deletes and updates report the old row
+  let (p,v,loc) = db.query(&((0.49,0.49),(0.51,0.51)))?.next().unwrap()?;
+  db.batch(&[Row::Update(loc, (-0.75,-0.75), v)])?;
+  assert_eq![sub.try_next(), Some(Change::Delete(p,v,loc))];
+  assert_eq![sub.try_next(), None];
+  assert_eq![other.try_next(), Some(Change::Insert((-0.75,-0.75),v))];
+  assert_eq![db.delete_query(&((-1.0,-1.0),(-0.7,-0.7)))?, 1];
+  match other.try_next() {
+    Some(Change::Delete(p,0,_)) => assert_eq![p, (-0.75,-0.75)],
+    c => panic!["unexpected {:?}", c]
+  }
+
+  // an async reader is woken by the next commit
+  let wakes = Arc::new(Wakes(AtomicUsize::new(0)));
+  let waker = Waker::from(Arc::clone(&wakes));
+  let mut cx = Context::from_waker(&waker);
+  {
+    let mut next = Box::pin(sub.next());
+    assert![Pin::new(&mut next).poll(&mut cx).is_pending()];
+    db.batch(&[Row::Insert((0.1,0.1), 1_000)])?;
+    assert_eq![wakes.0.load(Ordering::SeqCst), 1];
+    match Pin::new(&mut next).poll(&mut cx) {
+      Poll::Ready(Some(Change::Insert(_,1_000))) => {},
+      r => panic!["unexpected {:?}", r]
+    }
+  }
+
+  // closing the database ends the subscriptions
+  drop(other);
+  db.close()?;
+  assert![sub.is_closed()];
+  let mut next = Box::pin(sub.next());
+  assert![matches![Pin::new(&mut next).poll(&mut cx), Poll::Ready(None)]];
+  Ok(())
+}
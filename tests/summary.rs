@@ -0,0 +1,170 @@
+use eyros::{Setup,DB,Row,Summarize};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize,Ordering};
+
+type P = (f32,f32);
+type V = u32;
+
+// count, sum, and maximum of the values
+struct Stats {
+  calls: Arc<AtomicUsize>
+}
+
+impl Summarize<P,V> for Stats {
+  type Summary = (u64,u64,u32);
+  fn name (&self) -> &str { "stats" }
+  fn summarize (&self, rows: &[(P,V)]) -> Self::Summary {
+    self.calls.fetch_add(1, Ordering::SeqCst);
+    rows.iter().fold((0,0,0), |(n,sum,max),(_,v)| {
+      (n+1, sum + (*v as u64), max.max(*v))
+    })
+  }
+  fn combine (&self, a: &Self::Summary, b: &Self::Summary) -> Self::Summary {
+    (a.0 + b.0, a.1 + b.1, a.2.max(b.2))
+  }
+}
+
+fn expected<S,U> (db: &mut DB<S,U,P,V>, bbox: &((f32,f32),(f32,f32)))
+-> Result<Option<(u64,u64,u32)>,Error> where
+S: random_access_storage::RandomAccess<Error=failure::Error>,
+U: Fn(&str) -> Result<S,failure::Error> {
+  let mut result = None;
+  for row in db.query(bbox)? {
+    let v = row?.1;
+    let (n,sum,max) = result.unwrap_or((0,0,0));
+    result = Some((n+1, sum + (v as u64), max.max(v)));
+  }
+  Ok(result)
+}
+
+#[test]
+fn summary() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  };
+  let mut r = rand().seed([13,12]);
+  let mut count: u32 = 0;
+  let mut rows = |n| -> Vec<Row<P,V>> {
+    (0..n).map(|_| {
+      count += 1;
+      Row::Insert((r.read::<f32>(), r.read::<f32>()), count)
+    }).collect()
+  };
+  let bboxes = vec![
+    ((0.0,0.0),(1.0,1.0)),
+    ((0.01,0.0),(0.05,1.0)),
+    ((0.0,0.2),(0.2,0.4)),
+    ((0.5,0.5),(0.6,0.6))
+  ];
+  let calls = Arc::new(AtomicUsize::new(0));
+  let stats = Stats { calls: Arc::clone(&calls) };
+  {
+    let mut db: DB<_,_,P,V> = Setup::new(&storage)
+      .max_data_size(100)
+      .base_size(500)
+      .build()?;
+    db.batch(&rows(500))?; // written before the summary is registered
+    db.add_summary(Stats { calls: Arc::clone(&calls) })?;
+    assert![db.add_summary(Stats { calls: Arc::clone(&calls) }).is_err()];
+    db.batch(&rows(1_600))?;
+    for bbox in bboxes.iter() {
+      assert_eq![db.summarize(bbox, &stats)?, expected(&mut db, bbox)?];
+    }
+    // whole blocks use their stored summaries
+    calls.store(0, Ordering::SeqCst);
+    let all = db.summarize(&((0.0,0.0),(1.0,1.0)), &stats)?;
+    assert_eq![all.map(|s| s.0), Some(2_100)];
+    let n = calls.load(Ordering::SeqCst);
+    assert![n < 10, "summarized {} times", n];
+  }
+  {
+    let mut db: DB<_,_,P,V> = Setup::new(&storage)
+      .max_data_size(100)
+      .base_size(500)
+      .build()?;
+    db.add_summary(Stats { calls: Arc::clone(&calls) })?;
+    let mut batch = rows(300);
+    for result in db.query(&((0.0,0.0),(1.0,1.0)))? {
+      let (p,v,loc) = result?;
+      if v % 97 == 0 { batch.push(Row::Delete(loc)) }
+      else if v % 89 == 0 { batch.push(Row::Update(loc, p, v + 100_000)) }
+    }
+    db.batch(&batch)?;
+    for bbox in bboxes.iter() {
+      assert_eq![db.summarize(bbox, &stats)?, expected(&mut db, bbox)?];
+    }
+    assert_eq![db.summarize(&((2.0,2.0),(3.0,3.0)), &stats)?, None];
+    assert![db.remove_summary("stats")?];
+    assert![!db.remove_summary("stats")?];
+    for bbox in bboxes.iter() {
+      assert_eq![db.summarize(bbox, &stats)?, expected(&mut db, bbox)?,
+        "summarized from rows without a registered summary"];
+    }
+  }
+  Ok(())
+}
+
+// delete and update rows in data blocks, then write enough rows to merge the
+// trees, which applies the staged deletes to the blocks
+fn churn<S,U> (db: &mut DB<S,U,P,V>, mut batch: Vec<Row<P,V>>,
+flush: Vec<Row<P,V>>, k: u32) -> Result<(),Error> where
+S: random_access_storage::RandomAccess<Error=failure::Error>,
+U: Fn(&str) -> Result<S,failure::Error> {
+  for result in db.query(&((0.0,0.0),(1.0,1.0)))? {
+    let (p,v,loc) = result?;
+    if loc.0 == 0 { continue }
+    if v % 37 == k { batch.push(Row::Delete(loc)) }
+    else if v % 41 == k { batch.push(Row::Update(loc, p, v + 1)) }
+  }
+  db.batch(&batch)?;
+  db.batch(&flush)?;
+  Ok(())
+}
+
+#[test]
+fn summary_rollup() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  };
+  let mut r = rand().seed([13,12]);
+  let mut count: u32 = 0;
+  let mut rows = |n| -> Vec<Row<P,V>> {
+    (0..n).map(|_| {
+      count += 1;
+      Row::Insert(((count as f32)/10_000.0, r.read::<f32>()), count)
+    }).collect()
+  };
+  let all = ((0.0,0.0),(1.0,1.0));
+  let calls = Arc::new(AtomicUsize::new(0));
+  let stats = Stats { calls: Arc::clone(&calls) };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  db.add_summary(Stats { calls: Arc::clone(&calls) })?;
+  db.batch(&rows(2_000))?;
+  churn(&mut db, rows(250), rows(250), 0)?;
+  assert_eq![db.verify_summaries(&stats)?, Vec::<u64>::new()];
+  assert_eq![db.summarize(&all, &stats)?, expected(&mut db, &all)?];
+  // applied deletes stay in the staging area until the next tree is written
+  db.batch(&rows(500))?;
+  calls.store(0, Ordering::SeqCst);
+  assert_eq![db.summarize(&all, &stats)?, expected(&mut db, &all)?];
+  let n = calls.load(Ordering::SeqCst);
+  assert![n < 10, "summarized {} times", n];
+  // blocks changed while the summary isn't registered
+  assert![db.remove_summary("stats")?];
+  churn(&mut db, rows(250), rows(250), 1)?;
+  db.add_summary(Stats { calls: Arc::clone(&calls) })?;
+  assert_eq![db.verify_summaries(&stats)?, Vec::<u64>::new()];
+  assert_eq![db.summarize(&all, &stats)?, expected(&mut db, &all)?];
+  Ok(())
+}
@@ -0,0 +1,90 @@
+use eyros::{Setup,DB,Row};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn top_k() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let mut batch = |n: u32| -> Vec<Row<P,V>> {
+    (0..n).map(|_| {
+      let x: f32 = r.read::<f32>()*2.0-1.0;
+      let y: f32 = r.read::<f32>()*2.0-1.0;
+      Row::Insert((x,y), r.read::<u32>() % 1_000_000)
+    }).collect()
+  };
+  db.batch(&batch(3_000))?; // written to a tree
+  db.batch(&batch(80))?; // stays in staging
+  let bbox = ((-0.6,-0.7),(0.8,0.5));
+
+  let mut all = vec![];
+  for result in db.query(&bbox)? {
+    all.push(result?);
+  }
+  let score = |_: &P, v: &V| *v as f64;
+  all.sort_by(|a,b| b.1.cmp(&a.1));
+  let top = db.top_k(&bbox, 25, score)?;
+  assert_eq![
+    top.iter().map(|r| r.1).collect::<Vec<_>>(),
+    all.iter().take(25).map(|r| r.1).collect::<Vec<_>>(),
+    "highest values first"
+  ];
+  assert![top.iter().all(|(p,_,_)| {
+    p.0 >= -0.6 && p.0 <= 0.8 && p.1 >= -0.7 && p.1 <= 0.5
+  }), "results are inside the bbox"];
+  assert_eq![db.top_k(&bbox, 0, score)?.len(), 0, "k = 0"];
+  assert_eq![db.top_k(&bbox, all.len()+5, score)?.len(), all.len(), "k > matches"];
+
+  // score by closeness to a corner, with a matching bound per block range
+  let closeness = |p: &P| -> f64 {
+    -(((p.0-0.8) as f64).powi(2) + ((p.1-0.5) as f64).powi(2))
+  };
+  let mut by_closeness = all.clone();
+  by_closeness.sort_by(|a,b| closeness(&b.0).total_cmp(&closeness(&a.0)));
+  let dir2 = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage2 = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir2.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  // copy into a fresh database so the block cache starts out empty
+  let mut fresh: DB<_,_,P,V> = Setup::new(&storage2)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut copy = vec![];
+  for result in db.query(&((-1.0,-1.0),(1.0,1.0)))? {
+    let (p,v,_) = result?;
+    copy.push(Row::Insert(p,v));
+  }
+  fresh.batch(&copy)?;
+  let near = fresh.top_k_bounded(&bbox, 10, |p,_| closeness(p), |range| {
+    let dx = (0.8f32 - (range.0).1).max(0.0) as f64;
+    let dy = (0.5f32 - (range.1).1).max(0.0) as f64;
+    -(dx*dx + dy*dy)
+  })?;
+  assert_eq![
+    near.iter().map(|r| r.0).collect::<Vec<_>>(),
+    by_closeness.iter().take(10).map(|r| r.0).collect::<Vec<_>>(),
+    "closest points first"
+  ];
+  let read = fresh.data_store.read().unwrap().cached_blocks();
+  assert![read > 0 && read < 10, "bounded top-k reads few blocks ({})", read];
+  Ok(())
+}
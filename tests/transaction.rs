@@ -0,0 +1,91 @@
+use eyros::{Setup,DB,Row,Conflict};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use tempfile::Builder as Tmpfile;
+use std::path::PathBuf;
+
+type P = (f32,f32);
+type V = u32;
+
+fn storage (dir: PathBuf) -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> {
+  move |name: &str| {
+    RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build()
+  }
+}
+
+// moves every row in the left half to the right half, failing halfway when
+// `fail` is set
+fn move_right<S,U> (db: &mut DB<S,U,P,V>, fail: bool) -> Result<(),Error>
+where S: random_access_storage::RandomAccess<Error=failure::Error>,
+U: Fn(&str) -> Result<S,failure::Error> {
+  let left = ((0.0,0.0),(0.49,1.0));
+  let mut tx = db.transaction();
+  let rows = tx.db().query(&left)?.collect::<Result<Vec<_>,Error>>()?;
+  for (i,(p,v,loc)) in rows.into_iter().enumerate() {
+    if fail && i == 50 {
+      tx.rollback();
+      return Err(Error::Other("failed halfway".into()));
+    }
+    tx.update(loc, (p.0+0.5,p.1), v);
+  }
+  tx.commit()
+}
+
+#[test]
+fn transaction() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut db: DB<_,_,P,V> = Setup::new(storage(dir.path().to_path_buf()))
+    .max_data_size(100)
+    .base_size(500)
+    .wal(true)
+    .build()?;
+  let left = ((0.0,0.0),(0.49,1.0));
+  let right = ((0.5,0.0),(1.0,1.0));
+  {
+    let mut tx = db.transaction();
+    for i in 0..400 {
+      tx.insert((0.25, i as f32 / 400.0), i);
+    }
+    tx.batch(&[Row::Insert((0.75,0.5), 400)]);
+    assert_eq![tx.len(), 401];
+    // nothing is written until the commit
+    assert_eq![tx.db().query(&((0.0,0.0),(1.0,1.0)))?.count(), 0];
+    tx.commit()?;
+  }
+  assert_eq![db.query(&left)?.count(), 400];
+  let sequence = db.sequence();
+
+  assert![move_right(&mut db, true).is_err()];
+  assert_eq![db.query(&left)?.count(), 400];
+  assert_eq![db.query(&right)?.count(), 1];
+  assert_eq![db.sequence(), sequence];
+
+  // dropping an uncommitted transaction discards its rows too
+  {
+    let mut tx = db.transaction();
+    tx.insert((0.9,0.9), 1_000);
+  }
+  assert_eq![db.query(&right)?.count(), 1];
+
+  move_right(&mut db, false)?;
+  assert_eq![db.query(&left)?.count(), 0];
+  assert_eq![db.query(&right)?.count(), 401];
+  // 801 rows, with 400 of them deleted, went into a tree in one commit
+  assert![db.sequence() > sequence];
+  assert![db.transaction().commit().is_ok()];
+
+  // a batch in the middle of a transaction moves rows, so the transaction's
+  // locations can't be trusted anymore
+  let sequence = db.sequence();
+  let mut tx = db.transaction();
+  let rows = tx.db().query(&right)?.collect::<Result<Vec<_>,Error>>()?;
+  tx.delete(rows[0].2);
+  tx.db().batch(&[Row::Insert((0.1,0.1), 2_000)])?;
+  let err = tx.commit().unwrap_err();
+  assert_eq![err.downcast_ref::<Conflict>(), Some(&Conflict {
+    expected: sequence,
+    found: sequence + 1
+  })];
+  assert_eq![db.query(&right)?.count(), 401, "nothing deleted"];
+  Ok(())
+}
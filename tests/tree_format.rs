@@ -0,0 +1,64 @@
+use eyros::{Setup,DB,Row,TreeFormat};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random_access_storage::RandomAccess;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn tree_format() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let batch: Vec<Row<P,V>> = (0..1_200).map(|i| {
+    let x: f32 = r.read::<f32>()*2.0-1.0;
+    let y: f32 = r.read::<f32>()*2.0-1.0;
+    Row::Insert((x,y), i)
+  }).collect();
+  db.batch(&batch)?;
+  let format = TreeFormat::default();
+  assert_eq![format.version, TreeFormat::VERSION];
+  assert_eq![db.tree_formats()?, vec![None,Some(format)]];
+  db.close()?;
+
+  let mut store = storage("tree1")?;
+  let header = store.read(0, TreeFormat::HEADER_LEN)?;
+  assert_eq![TreeFormat::from_header(&header)?, Some(format)];
+
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  assert_eq![db.query(&bbox)?.count(), 1_200, "reopened tree is readable"];
+  drop(db);
+
+  let unsupported: Result<DB<_,_,P,V>,Error> = Setup::new(&storage)
+    .tree_format(TreeFormat::default().compression(9))
+    .build();
+  assert![unsupported.is_err(), "unknown compression is rejected"];
+
+  let mut newer = format;
+  newer.version = TreeFormat::VERSION + 1;
+  store.write(0, &newer.to_header())?;
+  store.sync_all()?;
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let results = db.query(&bbox)?.collect::<Result<Vec<_>,Error>>();
+  assert![results.is_err(), "trees from a newer version are rejected"];
+  Ok(())
+}
@@ -0,0 +1,66 @@
+use eyros::{Setup,DB,Row};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn tree_stats() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    Ok(RandomAccessDisk::builder(dir.path().join(name)).auto_sync(false).build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  assert_eq![db.tree_stats()?, vec![]];
+
+  let mut r = rand().seed([3,14]);
+  let mut n = 0;
+  for size in [2_000,700,100].iter() {
+    let batch: Vec<Row<P,V>> = (0..*size).map(|_| {
+      n += 1;
+      Row::Insert((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), n)
+    }).collect();
+    db.batch(&batch)?;
+  }
+  let stats = db.tree_stats()?;
+  assert![stats.len() >= 2];
+  assert![stats.windows(2).all(|w| w[0].index < w[1].index)];
+  // staged rows aren't in a tree yet
+  let in_trees = db.query(&((-1.0,-1.0),(1.0,1.0)))?
+    .filter(|r| r.as_ref().map(|r| (r.2).0 != 0).unwrap_or(true))
+    .count() as u64;
+  assert![in_trees >= 2_000 && in_trees < n as u64];
+  assert_eq![stats.iter().map(|t| t.rows).sum::<u64>(), in_trees];
+  for t in stats.iter() {
+    assert![t.depth >= 1 && t.branches >= 1, "{:?}", t];
+    assert![t.data_blocks * 100 >= t.rows, "{:?}", t];
+    assert_eq![t.live_rows, t.rows];
+    assert_eq![t.deleted_rows, 0];
+    assert![!t.frozen];
+  }
+  let usage = db.disk_usage()?;
+  assert_eq![stats.iter().map(|t| t.data_bytes).sum::<u64>(), usage.data_live];
+  for t in stats.iter() {
+    assert_eq![t.branch_bytes, usage.trees[t.index]];
+  }
+
+  // deleted rows are counted from the block bitfields
+  let deleted = db.delete_query(&((-1.0,-1.0),(0.0,0.0)))?;
+  assert![deleted > 0];
+  let after = db.tree_stats()?;
+  let staged_deleted = deleted as u64 - after.iter().map(|t| t.deleted_rows).sum::<u64>();
+  assert![staged_deleted < n as u64 - in_trees];
+  for (a,b) in stats.iter().zip(after.iter()) {
+    assert_eq![(a.index,a.rows,a.data_bytes), (b.index,b.rows,b.data_bytes)];
+    assert_eq![b.live_rows + b.deleted_rows, b.rows];
+  }
+  db.compact()?;
+  assert![db.tree_stats()?.iter().all(|t| t.deleted_rows == 0)];
+  Ok(())
+}
@@ -0,0 +1,166 @@
+use eyros::{Setup,DB,Row,Trigger};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use random_access_storage::RandomAccess;
+use std::sync::{Arc,Mutex};
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn triggers() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let zone = ((-0.5,-0.5),(0.0,0.0));
+  let seen = Arc::new(Mutex::new(vec![]));
+  let s = Arc::clone(&seen);
+  db.add_trigger(Trigger::new("zone")
+    .region(zone)
+    .callback(move |name,_p,v| s.lock().unwrap().push((name.to_string(),*v))))?;
+  db.add_trigger(Trigger::new("even")
+    .region(zone)
+    .filter(|_p,v| v % 2 == 0)
+    .outbox())?;
+  assert![db.add_trigger(Trigger::new("zone")).is_err(), "duplicate name"];
+  assert_eq![db.triggers(), vec!["zone","even"]];
+
+  let mut r = rand().seed([13,12]);
+  let rows: Vec<Row<P,V>> = (0..1_200).map(|i| {
+    let x: f32 = r.read::<f32>()*2.0-1.0;
+    let y: f32 = r.read::<f32>()*2.0-1.0;
+    Row::Insert((x,y), i)
+  }).collect();
+  db.batch(&rows[0..700])?;
+  db.batch(&rows[700..])?;
+
+  let expected: Vec<V> = rows.iter().filter_map(|row| match row {
+    Row::Insert(p,v) if p.0 >= -0.5 && p.0 <= 0.0 && p.1 >= -0.5 && p.1 <= 0.0 => Some(*v),
+    _ => None
+  }).collect();
+  assert![!expected.is_empty()];
+  let called: Vec<V> = seen.lock().unwrap().iter().map(|(name,v)| {
+    assert_eq![name, "zone"];
+    *v
+  }).collect();
+  assert_eq![called, expected, "callback for every row entering the zone"];
+
+  let mut outboxed = vec![];
+  let mut offset = 0;
+  loop {
+    let events = db.read_outbox(offset, 7)?;
+    if events.is_empty() { break }
+    for event in events.iter() {
+      assert_eq![event.trigger, "even"];
+      assert![event.point.0 >= -0.5 && event.point.0 <= 0.0];
+      outboxed.push(event.value);
+    }
+    offset = events.last().unwrap().next;
+  }
+  let even: Vec<V> = expected.iter().cloned().filter(|v| v % 2 == 0).collect();
+  assert_eq![outboxed, even, "outbox holds the filtered rows"];
+
+  assert![db.remove_trigger("zone")];
+  seen.lock().unwrap().clear();
+  db.batch(&rows[0..10])?;
+  assert_eq![seen.lock().unwrap().len(), 0, "removed trigger doesn't fire"];
+  Ok(())
+}
+
+#[test]
+fn outbox_consumers() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let open = || -> Result<DB<_,_,P,V>,Error> {
+    let mut db: DB<_,_,P,V> = Setup::new(&storage)
+      .max_data_size(100)
+      .base_size(500)
+      .build()?;
+    db.add_trigger(Trigger::new("all"))?;
+    Ok(db)
+  };
+  let rows: Vec<Row<P,V>> = (0..700).map(|i| {
+    Row::Insert(((i as f32)/1000.0,0.0), i)
+  }).collect();
+  {
+    let mut db = open()?;
+    db.batch(&rows[0..300])?;
+    let events = db.poll_outbox("mailer", 100)?;
+    assert_eq![events.len(), 100];
+    assert_eq![db.poll_outbox("mailer", 100)?, events, "poll doesn't advance"];
+    db.ack_outbox("mailer", events.last().unwrap().next)?;
+    // crash before acknowledging the next page
+    let events = db.poll_outbox("mailer", 100)?;
+    assert_eq![events[0].value, 100];
+  }
+  let mut db = open()?;
+  let events = db.poll_outbox("mailer", 1_000)?;
+  assert_eq![events.iter().map(|e| e.value).collect::<Vec<_>>(),
+    (100..300).collect::<Vec<_>>(), "resumes after the acknowledged offset"];
+  assert_eq![db.poll_outbox("audit", 1_000)?.len(), 300, "consumers are independent"];
+  db.ack_outbox("mailer", events.last().unwrap().next)?;
+  db.batch(&rows[300..])?;
+  let events = db.poll_outbox("mailer", 1_000)?;
+  assert_eq![events.len(), 400];
+  assert_eq![events[0].value, 300];
+  Ok(())
+}
+
+#[test]
+fn outbox_torn_append() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let open = || -> Result<DB<_,_,P,V>,Error> {
+    let mut db: DB<_,_,P,V> = Setup::new(&storage)
+      .max_data_size(100)
+      .base_size(500)
+      .build()?;
+    db.add_trigger(Trigger::new("all").outbox())?;
+    Ok(db)
+  };
+  let rows: Vec<Row<P,V>> = (0..200).map(|i| {
+    Row::Insert(((i as f32)/1000.0,0.0), i)
+  }).collect();
+  let len = {
+    let mut db = open()?;
+    db.batch(&rows[0..100])?;
+    let len = storage("outbox")?.len()?;
+    db.batch(&rows[100..150])?;
+    len
+  };
+  // crash partway through appending the second batch's events
+  for cut in [len+3, len+20].iter() {
+    storage("outbox")?.truncate(*cut)?;
+    let mut db = open()?;
+    let events = db.poll_outbox("mailer", 1_000)?;
+    assert_eq![events.len(), 100, "torn events are dropped"];
+    assert_eq![storage("outbox")?.len()?, len, "torn tail is truncated"];
+    db.batch(&rows[150..])?;
+    let events = db.poll_outbox("mailer", 1_000)?;
+    assert_eq![events.iter().map(|e| e.value).collect::<Vec<_>>(),
+      (0..100).chain(150..200).collect::<Vec<_>>(),
+      "events appended after the torn tail are read back"];
+    storage("outbox")?.truncate(len)?;
+  }
+  Ok(())
+}
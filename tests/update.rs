@@ -0,0 +1,143 @@
+use eyros::{Setup,DB,Row,Location};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::collections::HashMap;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn update() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let batch: Vec<Row<P,V>> = (0..1_200).map(|i| {
+    let x: f32 = r.read::<f32>()*2.0-1.0;
+    let y: f32 = r.read::<f32>()*2.0-1.0;
+    Row::Insert((x,y), i)
+  }).collect();
+  db.batch(&batch)?;
+
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let rows = |db: &mut DB<_,_,P,V>| -> Result<HashMap<V,(P,Location)>,Error> {
+    let mut rows = HashMap::new();
+    for result in db.query(&bbox)? {
+      let (p,v,loc) = result?;
+      assert![rows.insert(v,(p,loc)).is_none(), "value {} is unique", v];
+    }
+    Ok(rows)
+  };
+  let before = rows(&mut db)?;
+  assert_eq![before.len(), 1_200];
+
+  // same point, new value: overwritten in place
+  let in_place: Vec<Row<P,V>> = before.iter()
+    .filter(|(_,(_,loc))| loc.0 > 0)
+    .take(50)
+    .map(|(v,(p,loc))| Row::Update(*loc, *p, v + 10_000))
+    .collect();
+  let data_bytes = db.data_store.write().unwrap().bytes()?;
+  let staged = db.staging.inserts.read().unwrap().len();
+  db.batch(&in_place)?;
+  assert_eq![db.data_store.write().unwrap().bytes()?, data_bytes, "no new blocks"];
+  assert_eq![db.staging.inserts.read().unwrap().len(), staged, "nothing staged"];
+  let after = rows(&mut db)?;
+  assert_eq![after.len(), 1_200];
+  for row in in_place.iter() {
+    if let Row::Update(loc,p,v) = row {
+      assert_eq![after.get(v), Some(&(*p,*loc)), "updated in place"];
+    }
+  }
+
+  // moved out of their blocks: deleted and inserted in the same batch
+  let moved: Vec<Row<P,V>> = after.iter()
+    .filter(|(v,_)| *v % 3 == 0 && **v < 10_000)
+    .map(|(v,(_,loc))| Row::Update(*loc, (5.0,5.0+(*v as f32)), v + 20_000))
+    .collect();
+  assert![moved.iter().any(|r| match r {
+    Row::Update(loc,_,_) => loc.0 == 0,
+    _ => false
+  }), "some updates target staging rows"];
+  db.batch(&moved)?;
+  let after = rows(&mut db)?;
+  assert_eq![after.len(), 1_200 - moved.len(), "moved rows left the bbox"];
+  let wide = ((-1.0,-1.0),(10.0,2_000.0));
+  let mut values = db.query(&wide)?
+    .map(|r| r.map(|row| row.1))
+    .collect::<Result<Vec<_>,Error>>()?;
+  values.sort();
+  let mut expected: Vec<V> = (0..1_200).map(|v| {
+    if v % 3 == 0 { v + 20_000 }
+    else if in_place.iter().any(|r| match r {
+      Row::Update(_,_,u) => *u == v + 10_000,
+      _ => false
+    }) { v + 10_000 }
+    else { v }
+  }).collect();
+  expected.sort();
+  db.close()?;
+
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut reopened = db.query(&wide)?
+    .map(|r| r.map(|row| row.1))
+    .collect::<Result<Vec<_>,Error>>()?;
+  reopened.sort();
+  assert_eq![values, reopened, "updates persist"];
+  assert_eq![values.len(), 1_200];
+  Ok(())
+}
+
+#[test]
+fn update_interval() -> Result<(),Error> {
+  type P = ((f32,f32),(f32,f32));
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([14,15]);
+  let mut batch: Vec<Row<P,V>> = (1..1_000).map(|i| {
+    let x: f32 = r.read::<f32>();
+    let y: f32 = r.read::<f32>();
+    Row::Insert(((x,x+0.001),(y,y+0.001)), i)
+  }).collect();
+  batch.push(Row::Insert(((0.5,0.501),(0.5,0.501)), 0));
+  db.batch(&batch)?;
+  let loc = db.query(&((0.5,0.5),(0.501,0.501)))?
+    .map(|r| r.unwrap())
+    .find(|(_,v,_)| *v == 0)
+    .map(|(_,_,loc)| loc)
+    .unwrap();
+  assert![loc.0 > 0, "row is in a tree"];
+
+  // the new interval overlaps the block's bbox but reaches past it, so it
+  // can't be written in place
+  let point = ((0.5,5.0),(0.5,0.501));
+  db.batch(&[Row::Update(loc, point, 0)])?;
+  let found: Vec<(P,V)> = db.query(&((4.0,0.4),(6.0,0.6)))?
+    .map(|r| r.map(|(p,v,_)| (p,v)))
+    .collect::<Result<_,Error>>()?;
+  assert_eq![found, vec![(point,0)]];
+  assert_eq![db.query(&((0.0,0.0),(1.0,1.0)))?.count(), 1_000];
+  Ok(())
+}
@@ -0,0 +1,261 @@
+use eyros::{Setup,DB,Row};
+use eyros::Error;
+use failure::bail;
+use random_access_disk::RandomAccessDisk;
+use random_access_storage::RandomAccess;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::cell::Cell;
+use std::io;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+type P = (f32,f32);
+type V = u32;
+
+fn storage (dir: PathBuf) -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> {
+  move |name: &str| {
+    Ok(RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build()?)
+  }
+}
+
+#[test]
+fn user_meta() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let open = || -> Result<DB<_,_,P,V>,Error> {
+    Setup::new(storage(dir.path().to_path_buf()))
+      .max_data_size(100)
+      .base_size(500)
+      .build()
+  };
+  let mut r = rand().seed([21,22]);
+  let mut rows = |n: u32| -> Vec<Row<P,V>> {
+    (0..n).map(|i| Row::Insert((r.read::<f32>(), r.read::<f32>()), i)).collect()
+  };
+  let blob: Vec<u8> = (0..70_000).map(|i| (i%251) as u8).collect();
+  {
+    let mut db = open()?;
+    db.put_meta("schema", b"3")?;
+    db.put_meta("cursor", b"1000")?;
+    assert_eq![db.get_meta("schema"), Some(&b"3"[..]), "uncommitted entries are visible"];
+  }
+  {
+    let mut db = open()?;
+    assert_eq![db.get_meta("schema"), None, "entries are written with a batch"];
+    db.put_meta("schema", b"3")?;
+    db.put_meta("cursor", b"1000")?;
+    db.batch(&rows(100))?; // staged only
+  }
+  {
+    let mut db = open()?;
+    assert_eq![db.get_meta("schema"), Some(&b"3"[..])];
+    assert_eq![db.get_meta("cursor"), Some(&b"1000"[..])];
+    db.put_meta("cursor", b"2000")?;
+    db.put_meta("blob", &blob)?;
+    db.put_meta("", b"empty key")?;
+    db.batch(&rows(1_000))?; // builds a tree
+  }
+  {
+    let mut db = open()?;
+    assert_eq![db.meta_keys(), vec!["", "blob", "cursor", "schema"]];
+    assert_eq![db.get_meta("cursor"), Some(&b"2000"[..])];
+    assert_eq![db.get_meta("blob"), Some(&blob[..])];
+    assert_eq![db.get_meta(""), Some(&b"empty key"[..])];
+    assert![db.delete_meta("blob")?];
+    assert![!db.delete_meta("missing")?];
+    db.batch(&[])?; // commits the entries alone
+    assert_eq![db.query(&((0.0,0.0),(1.0,1.0)))?.count(), 1_100];
+  }
+  let mut db = open()?;
+  assert_eq![db.meta_keys(), vec!["", "cursor", "schema"]];
+  assert_eq![db.get_meta("blob"), None];
+
+  let long = "k".repeat(70_000);
+  match db.put_meta(&long, b"") {
+    Err(Error::Invalid(_)) => {},
+    r => panic!["unexpected result for a long key: {:?}", r]
+  }
+  Ok(())
+}
+
+#[test]
+fn user_meta_other_commits() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let open = || -> Result<DB<_,_,P,V>,Error> {
+    Setup::new(storage(dir.path().to_path_buf()))
+      .max_data_size(100)
+      .base_size(500)
+      .build()
+  };
+  {
+    let mut db = open()?;
+    db.put_meta("schema", b"3")?;
+    db.batch(&[])?;
+  }
+  {
+    let mut db = open()?;
+    db.put_meta("cursor", b"1000")?;
+    assert![db.delete_meta("schema")?];
+    assert_eq![db.meta_keys(), vec!["cursor"]];
+    db.ack_outbox("mailer", 0)?;
+    db.save_quarantine()?;
+  }
+  let db = open()?;
+  assert_eq![db.get_meta("cursor"), None, "only a batch commits entries"];
+  assert_eq![db.get_meta("schema"), Some(&b"3"[..])];
+  assert_eq![db.meta_keys(), vec!["schema"]];
+  Ok(())
+}
+
+// fails every write after the first `budget` writes, like a crashed process
+struct CrashStore {
+  store: RandomAccessDisk,
+  budget: Rc<Cell<Option<usize>>>
+}
+
+impl CrashStore {
+  fn spend (&mut self) -> Result<(),failure::Error> {
+    match self.budget.get() {
+      Some(0) => bail!["crashed"],
+      Some(n) => self.budget.set(Some(n-1)),
+      None => {}
+    }
+    Ok(())
+  }
+}
+
+impl RandomAccess for CrashStore {
+  type Error = failure::Error;
+  fn write (&mut self, offset: u64, data: &[u8]) -> Result<(),failure::Error> {
+    self.spend()?;
+    self.store.write(offset, data)
+  }
+  fn read (&mut self, offset: u64, length: u64) -> Result<Vec<u8>,failure::Error> {
+    self.store.read(offset, length)
+  }
+  fn read_to_writer (&mut self, offset: u64, length: u64,
+  buf: &mut impl io::Write) -> Result<(),failure::Error> {
+    self.store.read_to_writer(offset, length, buf)
+  }
+  fn del (&mut self, offset: u64, length: u64) -> Result<(),failure::Error> {
+    self.spend()?;
+    self.store.del(offset, length)
+  }
+  fn truncate (&mut self, length: u64) -> Result<(),failure::Error> {
+    self.spend()?;
+    self.store.truncate(length)
+  }
+  fn len (&self) -> Result<u64,failure::Error> {
+    self.store.len()
+  }
+  fn is_empty (&mut self) -> Result<bool,failure::Error> {
+    self.store.is_empty()
+  }
+  fn sync_all (&mut self) -> Result<(),failure::Error> {
+    self.spend()?;
+    self.store.sync_all()
+  }
+}
+
+#[test]
+fn user_meta_crash() -> Result<(),Error> {
+  let mut r = rand().seed([23,24]);
+  let first: Vec<Row<P,V>> = (0..300).map(|i| {
+    Row::Insert((r.read::<f32>(), r.read::<f32>()), i)
+  }).collect();
+  let second: Vec<Row<P,V>> = (300..600).map(|i| {
+    Row::Insert((r.read::<f32>(), r.read::<f32>()), i)
+  }).collect();
+  let bbox = ((0.0,0.0),(1.0,1.0));
+  let mut writes = 0;
+  let mut crash_points = vec![None];
+  // run once without crashing to count writes, then crash after each write
+  while let Some(crash_after) = crash_points.pop() {
+    let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+    let budget = Rc::new(Cell::new(None));
+    let storage = |name: &str| -> Result<CrashStore,failure::Error> {
+      Ok(CrashStore {
+        store: RandomAccessDisk::builder(dir.path().join(name)).auto_sync(false).build()?,
+        budget: Rc::clone(&budget)
+      })
+    };
+    let setup = || Setup::new(&storage)
+      .max_data_size(50)
+      .base_size(400)
+      .wal(true);
+    {
+      let mut db: DB<_,_,P,V> = setup().build()?;
+      db.put_meta("cursor", b"300")?;
+      db.batch(&first)?;
+      budget.set(Some(crash_after.unwrap_or(usize::MAX)));
+      db.put_meta("cursor", b"600")?;
+      let res = db.batch(&second);
+      assert_eq![res.is_err(), crash_after.is_some(), "crash after {:?}", crash_after];
+      if crash_after.is_none() {
+        writes = usize::MAX - budget.get().unwrap();
+        crash_points = (0..writes).map(Some).collect();
+      }
+      budget.set(None);
+    }
+    // the cursor always matches the rows
+    let mut db: DB<_,_,P,V> = setup().build()?;
+    let n = db.query(&bbox)?.count();
+    match db.get_meta("cursor") {
+      Some(b"600") => assert_eq![n, 600, "crash after {:?} of {}", crash_after, writes],
+      Some(b"300") => assert_eq![n, 300, "crash after {:?} of {}", crash_after, writes],
+      c => panic!["unexpected cursor {:?}", c]
+    }
+  }
+  assert![writes > 0];
+  Ok(())
+}
+
+#[test]
+fn meta_save_crash() -> Result<(),Error> {
+  let mut r = rand().seed([25,26]);
+  let rows: Vec<Row<P,V>> = (0..300).map(|i| {
+    Row::Insert((r.read::<f32>(), r.read::<f32>()), i)
+  }).collect();
+  let blob: Vec<u8> = (0..5_000).map(|i| (i%251) as u8).collect();
+  let bbox = ((0.0,0.0),(1.0,1.0));
+  let mut writes = 0;
+  let mut crash_points = vec![None];
+  // without a wal, a crash while committing entries leaves the old ones
+  while let Some(crash_after) = crash_points.pop() {
+    let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+    let budget = Rc::new(Cell::new(None));
+    let storage = |name: &str| -> Result<CrashStore,failure::Error> {
+      Ok(CrashStore {
+        store: RandomAccessDisk::builder(dir.path().join(name)).auto_sync(false).build()?,
+        budget: Rc::clone(&budget)
+      })
+    };
+    let setup = || Setup::new(&storage).max_data_size(50).base_size(100);
+    {
+      let mut db: DB<_,_,P,V> = setup().build()?;
+      db.put_meta("cursor", b"300")?;
+      db.put_meta("blob", &blob)?;
+      db.batch(&rows)?;
+      budget.set(Some(crash_after.unwrap_or(usize::MAX)));
+      // the new record is shorter than the one it replaces
+      db.put_meta("cursor", b"301")?;
+      db.delete_meta("blob")?;
+      let res = db.batch(&[]);
+      assert_eq![res.is_err(), crash_after.is_some(), "crash after {:?}", crash_after];
+      if crash_after.is_none() {
+        writes = usize::MAX - budget.get().unwrap();
+        crash_points = (0..writes).map(Some).collect();
+      }
+      budget.set(None);
+    }
+    let mut db: DB<_,_,P,V> = setup().build()?;
+    assert_eq![db.query(&bbox)?.count(), 300, "crash after {:?} of {}", crash_after, writes];
+    match (db.get_meta("cursor"), db.get_meta("blob")) {
+      (Some(b"300"), Some(b)) => assert_eq![b, &blob[..]],
+      (Some(b"301"), None) => {},
+      c => panic!["unexpected entries {:?} after crash {:?}", c, crash_after]
+    }
+  }
+  assert![writes > 0];
+  Ok(())
+}
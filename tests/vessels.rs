@@ -0,0 +1,96 @@
+#[path="../examples/vessels/tracker.rs"]
+#[allow(dead_code)]
+mod tracker;
+
+use tracker::{Tracker,Report,Crossing,FenceEvent,tile_coords,tile_corner};
+use eyros::Polygon;
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use tempfile::Builder as Tmpfile;
+use std::path::PathBuf;
+
+fn report (mmsi: u32, lon: f32, lat: f32, time: f32) -> Report {
+  Report { mmsi, lon, lat, time, speed: 10.0, course: 90.0 }
+}
+
+fn open (path: PathBuf) -> Result<Tracker<RandomAccessDisk>,Error> {
+  Tracker::open(move |name: &str| {
+    Ok(RandomAccessDisk::builder(path.join(name)).auto_sync(false).build()?)
+  })
+}
+
+#[test]
+fn vessels() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut tracker = open(dir.path().to_path_buf())?;
+  tracker.add_fence("harbor", Polygon::new(vec![vec![
+    (4.0,52.0),(5.0,52.0),(5.0,53.0),(4.0,53.0)
+  ]]))?;
+
+  // 50 vessels sail east across the fence in 30 steps of a minute
+  let mut expected_events = vec![];
+  for step in 0..30 {
+    let time = step as f32 * 60.0;
+    let reports: Vec<Report> = (0..50).map(|i| {
+      report(i, 3.05 + step as f32 * 0.1, 52.1 + i as f32 * 0.01, time)
+    }).collect();
+    let events = tracker.ingest(&reports)?;
+    // past the west edge at step 10 and the east edge at step 20
+    let crossing = match step { 10 => Some(Crossing::Enter), 20 => Some(Crossing::Exit), _ => None };
+    let expected: Vec<FenceEvent> = crossing.iter().flat_map(|c| (0..50).map(move |i| {
+      FenceEvent { fence: "harbor".to_string(), mmsi: i, time, crossing: *c }
+    })).collect();
+    assert_eq![events, expected, "step {}", step];
+    expected_events.extend(expected);
+    if step == 15 {
+      assert_eq![tracker.inside("harbor")?, (0..50).collect::<Vec<u32>>()];
+    }
+  }
+  assert_eq![expected_events.len(), 100];
+  assert_eq![tracker.inside("harbor")?, Vec::<u32>::new()];
+
+  // upserts keep one row per vessel
+  let bbox = ((-180.0,-90.0),(180.0,90.0));
+  assert_eq![tracker.latest.query(&bbox)?.count(), 50];
+  let (p,v) = tracker.position(7)?.unwrap();
+  assert_eq![p, (3.05 + 29.0 * 0.1, 52.1 + 7.0 * 0.01)];
+  assert_eq![v.1, 29.0 * 60.0];
+
+  // late reports only go into the track
+  tracker.ingest(&[report(7, 10.0, 50.0, 100.0)])?;
+  assert_eq![tracker.position(7)?.unwrap().0, p];
+  assert_eq![tracker.latest.query(&bbox)?.count(), 50];
+
+  // time windows
+  let track = tracker.track(7, 600.0, 900.0)?;
+  let times: Vec<f32> = track.iter().map(|s| s.2).collect();
+  assert_eq![times, vec![600.0,660.0,720.0,780.0,840.0,900.0]];
+  assert_eq![tracker.track(7, 90.0, 110.0)?, vec![(10.0,50.0,100.0)]];
+  // vessels 0 to 9 pass through at 540s
+  assert_eq![tracker.seen(((3.9,52.0),(4.0,52.195)), 0.0, 3_600.0)?,
+    (0..10).collect::<Vec<u32>>()];
+  assert_eq![tracker.seen(((3.9,52.0),(4.0,52.195)), 0.0, 500.0)?, Vec::<u32>::new()];
+
+  // a tile over the fleet counts every vessel once
+  let z = 5;
+  let (x,y) = tile_coords(5.95, 52.35, z);
+  let (x,y) = (x as u32, y as u32);
+  let (west,north) = tile_corner(z, x, y);
+  let (east,south) = tile_corner(z, x+1, y+1);
+  assert![west <= 5.95 && 5.95 <= east && south <= 52.1 && 52.6 <= north];
+  let grid = tracker.tile(z, x, y, 16)?;
+  let total: u32 = grid.iter().flatten().sum();
+  assert_eq![total, 50];
+  // vessels are spread over latitudes in one column of the grid
+  let cols: Vec<usize> = (0..16).filter(|c| grid.iter().any(|row| row[*c] > 0)).collect();
+  assert_eq![cols.len(), 1];
+
+  // reopening restores the latest positions
+  drop(tracker);
+  let mut tracker = open(dir.path().to_path_buf())?;
+  assert_eq![tracker.position(7)?.unwrap().0, p];
+  tracker.ingest(&[report(7, 6.0, 52.0, 2_000.0)])?;
+  assert_eq![tracker.position(7)?.unwrap().0, (6.0,52.0)];
+  assert_eq![tracker.latest.query(&bbox)?.count(), 50];
+  Ok(())
+}
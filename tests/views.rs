@@ -0,0 +1,74 @@
+use eyros::{Setup,DB,Row};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn views() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let open = || -> Result<DB<_,_,P,V>,Error> {
+    Setup::new(&storage)
+      .max_data_size(100)
+      .base_size(500)
+      .build()
+  };
+  let mut r = rand().seed([13,12]);
+  let rows: Vec<Row<P,V>> = (0..1_500).map(|i| {
+    let x: f32 = r.read::<f32>()*2.0-1.0;
+    let y: f32 = r.read::<f32>()*2.0-1.0;
+    Row::Insert((x,y), i)
+  }).collect();
+  let bbox = ((-0.5,-0.8),(0.3,0.5));
+  let sorted = |db: &DB<_,_,P,V>| -> Vec<V> {
+    let mut values: Vec<V> = db.view("dash").unwrap().rows().iter()
+      .map(|(_,v)| *v).collect();
+    values.sort();
+    values
+  };
+  let expected = |db: &mut DB<_,_,P,V>| -> Result<Vec<V>,Error> {
+    let mut values: Vec<V> = db.query(&bbox)?
+      .map(|r| r.map(|row| row.1)).collect::<Result<_,Error>>()?;
+    values.sort();
+    Ok(values)
+  };
+
+  let mut db = open()?;
+  db.batch(&rows[0..600])?;
+  db.create_view("dash", bbox)?;
+  assert![db.create_view("dash", bbox).is_err(), "duplicate name"];
+  assert![db.create_view("no/slash", bbox).is_err(), "invalid name"];
+  assert_eq![sorted(&db), expected(&mut db)?, "initial rows"];
+
+  db.batch(&rows[600..1_100])?;
+  assert_eq![sorted(&db), expected(&mut db)?, "inserts applied"];
+
+  let deletes: Vec<Row<P,V>> = db.query(&bbox)?.step_by(3)
+    .map(|r| r.map(|row| Row::Delete(row.2)))
+    .collect::<Result<_,Error>>()?;
+  assert![!deletes.is_empty()];
+  db.batch(&deletes)?;
+  assert_eq![sorted(&db), expected(&mut db)?, "deletes applied"];
+  db.close()?;
+
+  let mut db = open()?;
+  assert_eq![db.views(), vec!["dash"]];
+  db.batch(&rows[1_100..])?;
+  assert_eq![sorted(&db), expected(&mut db)?, "reopened view kept up to date"];
+  assert![db.drop_view("dash")?];
+  assert![!db.drop_view("dash")?];
+  db.close()?;
+
+  let db = open()?;
+  assert_eq![db.views(), Vec::<&str>::new(), "dropped view stays dropped"];
+  Ok(())
+}
@@ -0,0 +1,69 @@
+use eyros::{Setup,DB,Row,Location};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::ops::ControlFlow;
+
+type P = (f32,f32);
+type V = (u32,u32); // (tenant,id)
+
+fn sorted (rows: Vec<(P,V,Location)>) -> Vec<u32> {
+  let mut ids: Vec<u32> = rows.into_iter().map(|r| (r.1).1).collect();
+  ids.sort();
+  ids
+}
+
+#[test]
+fn visibility() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p).auto_sync(false).build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([21,8]);
+  let rows: Vec<(P,V)> = (0..2_300).map(|i| {
+    let p = (r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0);
+    (p, (i % 3, i))
+  }).collect();
+  let batch: Vec<Row<P,V>> = rows.iter().map(|(p,v)| Row::Insert(*p,*v)).collect();
+  db.batch(&batch)?;
+
+  let bbox = ((-0.5,-0.5),(0.5,0.5));
+  let inside = |p: &P| -0.5 <= p.0 && p.0 <= 0.5 && -0.5 <= p.1 && p.1 <= 0.5;
+  let expected: Vec<u32> = rows.iter()
+    .filter(|(p,v)| v.0 == 1 && inside(p))
+    .map(|(_,v)| v.1).collect();
+  assert![!expected.is_empty()];
+
+  db.set_visibility(|_,v| v.0 == 1);
+  assert_eq![sorted(db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?), expected];
+  assert_eq![db.query(&bbox)?.limit(10).count(), 10];
+  let mut ids = vec![];
+  db.query_for_each(&bbox, |_,v| { ids.push(v.1); ControlFlow::Continue(()) })?;
+  ids.sort();
+  assert_eq![ids, expected];
+  assert_eq![db.count(&bbox)?, expected.len() as u64];
+  assert_eq![db.count(&((-1.0,-1.0),(1.0,1.0)))?, 2_300/3 + 1];
+  let top = db.top_k(&bbox, 5, |_,v| v.1 as f64)?;
+  assert![top.iter().all(|r| (r.1).0 == 1)];
+  assert_eq![(top[0].1).1, *expected.iter().max().unwrap()];
+  let near = db.nearest(&(0.0,0.0), 20)?;
+  assert_eq![near.len(), 20];
+  assert![near.iter().all(|r| (r.1).0 == 1)];
+
+  // deletes only reach visible rows
+  let removed = db.delete_query(&bbox)?;
+  assert_eq![removed, expected.len()];
+  db.clear_visibility();
+  let left = sorted(db.query(&bbox)?.collect::<Result<Vec<_>,Error>>()?);
+  let others: Vec<u32> = rows.iter()
+    .filter(|(p,v)| v.0 != 1 && inside(p))
+    .map(|(_,v)| v.1).collect();
+  assert_eq![left, others];
+  Ok(())
+}
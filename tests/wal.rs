@@ -0,0 +1,325 @@
+use eyros::{Setup,DB,Row,Change};
+use eyros::Error;
+use failure::bail;
+use random_access_disk::RandomAccessDisk;
+use random_access_storage::RandomAccess;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::cell::{Cell,RefCell};
+use std::collections::HashMap;
+use std::io;
+use std::rc::Rc;
+
+type P = (f32,f32);
+type V = u32;
+
+// fails every write after the first `budget` writes, like a crashed process
+struct CrashStore {
+  store: RandomAccessDisk,
+  budget: Rc<Cell<Option<usize>>>
+}
+
+impl CrashStore {
+  fn spend (&mut self) -> Result<(),failure::Error> {
+    match self.budget.get() {
+      Some(0) => bail!["crashed"],
+      Some(n) => self.budget.set(Some(n-1)),
+      None => {}
+    }
+    Ok(())
+  }
+}
+
+impl RandomAccess for CrashStore {
+  type Error = failure::Error;
+  fn write (&mut self, offset: u64, data: &[u8]) -> Result<(),failure::Error> {
+    self.spend()?;
+    self.store.write(offset, data)
+  }
+  fn read (&mut self, offset: u64, length: u64) -> Result<Vec<u8>,failure::Error> {
+    self.store.read(offset, length)
+  }
+  fn read_to_writer (&mut self, offset: u64, length: u64,
+  buf: &mut impl io::Write) -> Result<(),failure::Error> {
+    self.store.read_to_writer(offset, length, buf)
+  }
+  fn del (&mut self, offset: u64, length: u64) -> Result<(),failure::Error> {
+    self.spend()?;
+    self.store.del(offset, length)
+  }
+  fn truncate (&mut self, length: u64) -> Result<(),failure::Error> {
+    self.spend()?;
+    self.store.truncate(length)
+  }
+  fn len (&self) -> Result<u64,failure::Error> {
+    self.store.len()
+  }
+  fn is_empty (&mut self) -> Result<bool,failure::Error> {
+    self.store.is_empty()
+  }
+  fn sync_all (&mut self) -> Result<(),failure::Error> {
+    self.spend()?;
+    self.store.sync_all()
+  }
+}
+
+fn values<S,U> (db: &mut DB<S,U,P,V>) -> Result<Vec<V>,Error> where
+S: RandomAccess<Error=failure::Error>, U: Fn(&str) -> Result<S,failure::Error> {
+  let mut values = vec![];
+  for result in db.query(&((0.0,0.0),(1.0,1.0)))? {
+    values.push(result?.1);
+  }
+  values.sort();
+  Ok(values)
+}
+
+#[test]
+fn wal_crash_recovery() -> Result<(),Error> {
+  let mut r = rand().seed([13,12]);
+  let mut count: u32 = 0;
+  let mut rows = |n| -> Vec<Row<P,V>> {
+    (0..n).map(|_| {
+      count += 1;
+      Row::Insert((r.read::<f32>(), r.read::<f32>()), count)
+    }).collect()
+  };
+  let first = rows(1_000);
+  let second = rows(700);
+  let third = rows(300);
+  let mut recovered = 0;
+  let mut expected = vec![];
+  let mut writes = 0;
+  let mut crash_points = vec![None];
+  // run once without crashing to count writes, then crash at points between
+  while let Some(crash_after) = crash_points.pop() {
+    let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+    let budget = Rc::new(Cell::new(None));
+    let storage = |name: &str| -> Result<CrashStore,failure::Error> {
+      let p = dir.path().join(name);
+      Ok(CrashStore {
+        store: RandomAccessDisk::builder(p).auto_sync(false).build()?,
+        budget: Rc::clone(&budget)
+      })
+    };
+    let setup = || Setup::new(&storage)
+      .max_data_size(50)
+      .base_size(200)
+      .wal(true);
+    let before = {
+      let mut db: DB<_,_,P,V> = setup().build()?;
+      db.batch(&first)?;
+      let mut batch = second.clone();
+      for result in db.query(&((0.0,0.0),(1.0,1.0)))? {
+        let (_,v,loc) = result?;
+        if v % 50 == 0 { batch.push(Row::Delete(loc)) }
+      }
+      let before = values(&mut db)?;
+      budget.set(Some(crash_after.unwrap_or(usize::MAX)));
+      let res = db.batch(&batch);
+      assert_eq![res.is_err(), crash_after.is_some(), "crash after {:?}", crash_after];
+      if crash_after.is_none() {
+        writes = usize::MAX - budget.get().unwrap();
+        crash_points = (0..writes).step_by((writes/40).max(1)).map(Some).collect();
+      }
+      budget.set(None);
+      before
+    };
+    let mut db: DB<_,_,P,V> = setup().build()?;
+    // merges can drop block deletes even without a crash, so a replayed
+    // batch may apply more of its deletes than the uninterrupted run did
+    let found = values(&mut db)?;
+    let kept = |values: &Vec<V>| -> Vec<V> {
+      values.iter().filter(|v| *v % 50 != 0 || **v > 1_000).cloned().collect()
+    };
+    match crash_after {
+      None => expected = found.clone(),
+      Some(n) => {
+        if kept(&found) == kept(&expected) {
+          recovered += 1;
+        } else {
+          assert![found == before,
+            "crash after {} of {} writes leaves either the old or the new rows",
+            n, writes];
+        }
+      }
+    }
+    // the recovered database keeps working
+    db.batch(&third)?;
+    assert_eq![values(&mut db)?.len(), found.len() + 300];
+  }
+  assert![recovered > 20, "most crashes are redone from the log ({})", recovered];
+  Ok(())
+}
+
+// (deleted, value) of each change in the feed
+fn feed<S,U> (db: &mut DB<S,U,P,V>) -> Result<Vec<(bool,V)>,Error> where
+S: RandomAccess<Error=failure::Error>, U: Fn(&str) -> Result<S,failure::Error> {
+  let mut changes = vec![];
+  for entry in db.changes(0)? {
+    changes.push(match entry?.change {
+      Change::Insert(_,v) => (false,v),
+      Change::Delete(_,v,_) => (true,v)
+    });
+  }
+  Ok(changes)
+}
+
+#[test]
+fn wal_rollback_blocks() -> Result<(),Error> {
+  let mut r = rand().seed([13,12]);
+  let rows: Vec<Row<P,V>> = (1..=1_000).map(|i| {
+    Row::Insert(((i as f32)/5_000.0, r.read::<f32>()*0.01), i)
+  }).collect();
+  let mut expected = None;
+  let mut recovered = 0;
+  let mut crash_points = vec![None];
+  while let Some(crash_after) = crash_points.pop() {
+    let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+    let budget = Rc::new(Cell::new(None));
+    let storage = |name: &str| -> Result<CrashStore,failure::Error> {
+      let p = dir.path().join(name);
+      Ok(CrashStore {
+        store: RandomAccessDisk::builder(p).auto_sync(false).build()?,
+        budget: Rc::clone(&budget)
+      })
+    };
+    let setup = || Setup::new(&storage)
+      .max_data_size(50)
+      .base_size(200)
+      .changes(true)
+      .wal(true);
+    let before = {
+      let mut db: DB<_,_,P,V> = setup().build()?;
+      db.batch(&rows)?;
+      // enough deletes to clear their bits in the blocks right away, and
+      // updates that fit in place
+      let mut batch = vec![];
+      for result in db.query(&((0.0,0.0),(1.0,1.0)))? {
+        let (p,v,loc) = result?;
+        if v % 4 == 0 { batch.push(Row::Delete(loc)) }
+        else if v % 10 == 1 { batch.push(Row::Update(loc,p,v+10_000)) }
+      }
+      let before = (values(&mut db)?,feed(&mut db)?);
+      budget.set(Some(crash_after.unwrap_or(usize::MAX)));
+      let res = db.batch(&batch);
+      assert_eq![res.is_err(), crash_after.is_some(), "crash after {:?}", crash_after];
+      if crash_after.is_none() {
+        let writes = usize::MAX - budget.get().unwrap();
+        crash_points = (0..writes).map(Some).collect();
+      }
+      budget.set(None);
+      before
+    };
+    let mut db: DB<_,_,P,V> = setup().build()?;
+    let found = (values(&mut db)?,feed(&mut db)?);
+    match (crash_after,&expected) {
+      (None,_) => expected = Some(found),
+      (Some(n),Some(expected)) => {
+        assert![found.0 == before.0 || found.0 == expected.0,
+          "crash after {} writes leaves either the old or the new rows", n];
+        // changes are rolled back and redone along with the batch
+        let changes = if found.0 == before.0 { &before.1 } else { &expected.1 };
+        assert![found.1 == *changes,
+          "crash after {} writes leaves the changes of the rows it keeps", n];
+        if found == *expected { recovered += 1 }
+      },
+      _ => unreachable![]
+    }
+  }
+  assert![recovered > 0, "crashes are redone from the log"];
+  Ok(())
+}
+
+// (written, synced) bytes of each store, by name
+type Disk = Rc<RefCell<HashMap<String,(Vec<u8>,Vec<u8>)>>>;
+
+// keeps only the bytes that were synced when the power is cut
+struct PowerStore {
+  name: String,
+  disk: Disk
+}
+
+impl PowerStore {
+  fn with<T> (&self, f: impl FnOnce(&mut (Vec<u8>,Vec<u8>)) -> T) -> T {
+    f(self.disk.borrow_mut().entry(self.name.clone()).or_default())
+  }
+}
+
+fn power_loss (disk: &Disk) {
+  for (written,synced) in disk.borrow_mut().values_mut() {
+    *written = synced.clone();
+  }
+}
+
+impl RandomAccess for PowerStore {
+  type Error = failure::Error;
+  fn write (&mut self, offset: u64, data: &[u8]) -> Result<(),failure::Error> {
+    self.with(|(written,_)| {
+      let end = offset as usize + data.len();
+      if written.len() < end { written.resize(end, 0) }
+      written[offset as usize..end].copy_from_slice(data);
+    });
+    Ok(())
+  }
+  fn read (&mut self, offset: u64, length: u64) -> Result<Vec<u8>,failure::Error> {
+    self.with(|(written,_)| {
+      let (start,end) = (offset as usize, (offset+length) as usize);
+      if end > written.len() { bail!["read past the end of {}", self.name] }
+      Ok(written[start..end].to_vec())
+    })
+  }
+  fn read_to_writer (&mut self, offset: u64, length: u64,
+  buf: &mut impl io::Write) -> Result<(),failure::Error> {
+    buf.write_all(&self.read(offset, length)?)?;
+    Ok(())
+  }
+  fn del (&mut self, _offset: u64, _length: u64) -> Result<(),failure::Error> {
+    Ok(())
+  }
+  fn truncate (&mut self, length: u64) -> Result<(),failure::Error> {
+    self.with(|(written,_)| written.resize(length as usize, 0));
+    Ok(())
+  }
+  fn len (&self) -> Result<u64,failure::Error> {
+    Ok(self.with(|(written,_)| written.len() as u64))
+  }
+  fn is_empty (&mut self) -> Result<bool,failure::Error> {
+    Ok(self.len()? == 0)
+  }
+  fn sync_all (&mut self) -> Result<(),failure::Error> {
+    self.with(|(written,synced)| *synced = written.clone());
+    Ok(())
+  }
+}
+
+#[test]
+fn wal_power_loss() -> Result<(),Error> {
+  let disk: Disk = Rc::new(RefCell::new(HashMap::new()));
+  let storage = |name: &str| -> Result<PowerStore,failure::Error> {
+    Ok(PowerStore { name: name.to_string(), disk: Rc::clone(&disk) })
+  };
+  let setup = || Setup::new(&storage)
+    .max_data_size(50)
+    .base_size(200)
+    .wal(true);
+  let mut r = rand().seed([13,12]);
+  let mut expected = vec![];
+  // small batches stay in staging and larger ones are built into trees
+  for n in [50,120,400,30] {
+    let rows: Vec<Row<P,V>> = (0..n).map(|_| {
+      let v = expected.len() as V;
+      expected.push(v);
+      Row::Insert((r.read::<f32>(),r.read::<f32>()), v)
+    }).collect();
+    {
+      let mut db: DB<_,_,P,V> = setup().build()?;
+      db.batch(&rows)?;
+      // the handle is lost with the power, without being closed
+      std::mem::forget(db);
+    }
+    power_loss(&disk);
+    let mut db: DB<_,_,P,V> = setup().build()?;
+    assert_eq![values(&mut db)?, expected, "committed rows survive a power loss"];
+  }
+  Ok(())
+}
@@ -0,0 +1,104 @@
+#![cfg(feature="wasm")]
+use eyros::{Setup,DB,Row,WasmFilter,WasmLimits};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+type P = (f32,f32);
+type V = u32;
+
+fn storage (dir: PathBuf) -> impl Fn(&str) -> Result<RandomAccessDisk,failure::Error> {
+  move |name: &str| {
+    RandomAccessDisk::builder(dir.join(name)).auto_sync(false).build()
+  }
+}
+
+fn parse (src: &str) -> Result<Vec<u8>,Error> {
+  wat::parse_str(src).map_err(|e| Error::Invalid(e.to_string()))
+}
+
+// keeps rows with x > 0 and an even value, and skips blocks entirely at x <= 0
+const FILTER: &str = r#"(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+  (func (export "filter") (param $axes i32) (param $dim i32)
+    (param $value i32) (param $len i32) (result i32)
+    (i32.and
+      (f64.gt (f64.load (local.get $axes)) (f64.const 0))
+      ;; values are big-endian u32s
+      (i32.eqz (i32.and
+        (i32.load8_u (i32.add (local.get $value) (i32.const 3)))
+        (i32.const 1)))))
+  (func (export "block") (param $axes i32) (param $dim i32) (param $rows i64)
+    (result i32)
+    ;; the upper end of the first axis
+    (f64.gt (f64.load offset=8 (local.get $axes)) (f64.const 0))))"#;
+
+const SPIN: &str = r#"(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) (i32.const 0))
+  (func (export "filter") (param i32 i32 i32 i32) (result i32)
+    (loop $forever (br $forever))
+    (i32.const 1)))"#;
+
+#[test]
+fn wasm_filter() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let mut db: DB<_,_,P,V> = Setup::new(storage(dir.path().to_path_buf()))
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([13,12]);
+  let rows: Vec<(P,V)> = (0..2_100).map(|i| {
+    ((r.read::<f32>()*2.0-1.0, r.read::<f32>()*2.0-1.0), i)
+  }).collect();
+  // the last 100 rows stay in staging
+  for chunk in rows.chunks(1_000) {
+    db.batch(&chunk.iter().map(|(p,v)| Row::Insert(*p,*v)).collect::<Vec<_>>())?;
+  }
+  let bbox = ((-1.0,-1.0),(1.0,1.0));
+  let filter = Arc::new(WasmFilter::new(&parse(FILTER)?)?);
+  let mut values: Vec<V> = db.query_wasm(&bbox, &filter)?
+    .map(|r| r.map(|(_,v,_)| v))
+    .collect::<Result<_,Error>>()?;
+  // blocks that block() skipped are only read by a plain query
+  let misses = db.stats()?.block_cache_misses;
+  assert_eq![db.query(&bbox)?.count(), rows.len()];
+  assert![db.stats()?.block_cache_misses > misses];
+  values.sort_unstable();
+  let mut expected: Vec<V> = rows.iter()
+    .filter(|((x,_),v)| *x > 0.0 && v % 2 == 0)
+    .map(|(_,v)| *v)
+    .collect();
+  expected.sort_unstable();
+  assert_eq![values, expected];
+
+  // a filter that never returns runs out of fuel and fails the query
+  let spin = Arc::new(WasmFilter::with_limits(&parse(SPIN)?,
+    WasmLimits { fuel: 10_000, memory: 1 << 16 })?);
+  let result = db.query_wasm(&bbox, &spin)?.collect::<Result<Vec<_>,Error>>();
+  assert![result.is_err()];
+
+  // modules can't import host functions
+  let import = r#"(module
+    (import "env" "read_file" (func (param i32) (result i32)))
+    (memory (export "memory") 1)
+    (func (export "alloc") (param i32) (result i32) (i32.const 0))
+    (func (export "filter") (param i32 i32 i32 i32) (result i32) (i32.const 1)))"#;
+  assert![WasmFilter::new(&parse(import)?).is_err()];
+  // nor grow their memory past the limit
+  let grow = r#"(module
+    (memory (export "memory") 1)
+    (func (export "alloc") (param i32) (result i32)
+      (drop (memory.grow (i32.const 100)))
+      (if (result i32) (i32.lt_s (memory.size) (i32.const 2))
+        (then (i32.const 0)) (else (unreachable))))
+    (func (export "filter") (param i32 i32 i32 i32) (result i32) (i32.const 1)))"#;
+  let grow = Arc::new(WasmFilter::with_limits(&parse(grow)?,
+    WasmLimits { fuel: 10_000, memory: 1 << 16 })?);
+  assert_eq![db.query_wasm(&bbox, &grow)?.count(), rows.len()];
+  Ok(())
+}
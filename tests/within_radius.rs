@@ -0,0 +1,61 @@
+use eyros::{Setup,DB,Row,haversine};
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random::{Source,default as rand};
+use tempfile::Builder as Tmpfile;
+
+type P = (f32,f32);
+type V = u32;
+
+#[test]
+fn within_radius() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let storage = |name: &str| -> Result<RandomAccessDisk,failure::Error> {
+    let p = dir.path().join(name);
+    Ok(RandomAccessDisk::builder(p)
+      .auto_sync(false)
+      .build()?)
+  };
+  let mut db: DB<_,_,P,V> = Setup::new(&storage)
+    .max_data_size(100)
+    .base_size(500)
+    .build()?;
+  let mut r = rand().seed([5,6]);
+  // around the antimeridian, near the north pole, and anywhere
+  let mut points: Vec<P> = (0..600).map(|_| {
+    let lon = 179.0 + r.read::<f32>()*2.0;
+    (if lon > 180.0 { lon - 360.0 } else { lon }, r.read::<f32>()*2.0-1.0)
+  }).collect();
+  points.extend((0..600).map(|_| {
+    (r.read::<f32>()*360.0-180.0, 89.5 + r.read::<f32>()*0.5)
+  }));
+  points.extend((0..800).map(|_| {
+    (r.read::<f32>()*360.0-180.0, r.read::<f32>()*180.0-90.0)
+  }));
+  let batch: Vec<Row<P,V>> = points.iter().enumerate()
+    .map(|(i,p)| Row::Insert(*p,i as u32))
+    .collect();
+  db.batch(&batch)?;
+
+  let queries = vec![
+    ((180.0,0.0), 50_000.0),
+    ((-179.9,0.5), 30_000.0),
+    ((40.0,89.9), 40_000.0),
+    ((0.0,0.0), 2_000_000.0)
+  ];
+  for (center,meters) in queries {
+    let mut expected: Vec<V> = points.iter().enumerate().filter(|(_,p)| {
+      haversine((p.0 as f64, p.1 as f64), center) <= meters
+    }).map(|(i,_)| i as u32).collect();
+    let mut results = vec![];
+    for result in db.within_radius(center, meters)? {
+      results.push(result?.1);
+    }
+    expected.sort();
+    results.sort();
+    assert![expected.len() > 0, "{:?} is near some points", center];
+    assert_eq![results, expected, "points within {}m of {:?}", meters, center];
+  }
+  assert![db.within_radius((0.0,91.0), 10.0).is_err()];
+  Ok(())
+}
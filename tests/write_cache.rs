@@ -0,0 +1,42 @@
+#[path="../src/write_cache.rs"]
+mod write_cache;
+use write_cache::WriteCache;
+
+use eyros::Error;
+use random_access_disk::RandomAccessDisk;
+use random_access_storage::RandomAccess;
+use tempfile::Builder as Tmpfile;
+
+#[test]
+fn write_cache_budget() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("eyros").tempdir()?;
+  let file = dir.path().join("store");
+  let store = RandomAccessDisk::builder(file.clone()).auto_sync(false).build()?;
+  let mut cache = WriteCache::open(store)?;
+  cache.max_dirty_bytes(Some(250))?;
+  let mut expected = vec![];
+  for i in 0..10u8 {
+    let chunk = vec![i;100];
+    cache.write(expected.len() as u64, &chunk)?;
+    expected.extend(chunk);
+    // the oldest writes went through to the store
+    let stored = std::fs::read(&file).unwrap_or(vec![]);
+    assert![expected.len() - stored.len() <= 250, "{} bytes buffered",
+      expected.len() - stored.len()];
+    assert_eq![&stored[..], &expected[0..stored.len()]];
+    assert_eq![cache.read(0, expected.len() as u64)?, expected];
+  }
+  // rewrite a flushed range and a buffered range
+  cache.write(50, &[200;100])?;
+  cache.write(950, &[201;20])?;
+  expected[50..150].copy_from_slice(&[200;100]);
+  expected[950..970].copy_from_slice(&[201;20]);
+  assert_eq![cache.read(0, expected.len() as u64)?, expected];
+  cache.truncate(900)?;
+  expected.truncate(900);
+  assert_eq![cache.len()?, 900];
+  assert_eq![cache.read(0, 900)?, expected];
+  cache.sync_all()?;
+  assert_eq![std::fs::read(&file)?, expected];
+  Ok(())
+}